@@ -0,0 +1,438 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! A number of files in flight that follows what the endpoint accepts.
+//!
+//! An `AimdController` raises the level by one after as many successful
+//! requests as the level, and halves it when a request is throttled
+//! (additive increase, multiplicative decrease, as TCP does). A throttled
+//! request sent before the last decrease says nothing about the new level, so
+//! it is ignored: one burst of 503s halves the level once.
+//!
+//! The controller belongs to the whole run, not to a file, so each file
+//! starts from the level the previous ones converged to. The level can be
+//! saved to the `[tuning]` section of the configuration file, see
+//! `config::save_tuning`, as the starting point of the next run. Every
+//! adjustment is recorded with its time, so runs can be graphed.
+
+use crate::impl_s3_ops;
+use crate::ops::{OpError, S3Ops};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+/// The level a run starts from when no tuning was saved.
+pub const DEFAULT_INITIAL_CONCURRENCY: usize = 4;
+pub const DEFAULT_MAX_CONCURRENCY: usize = 64;
+
+/// A number of files transferred at the same time, as given on the command
+/// line: a fixed number, or `auto`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Concurrency {
+    Fixed(usize),
+    Auto,
+}
+
+impl FromStr for Concurrency {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("auto") {
+            return Ok(Concurrency::Auto);
+        }
+        match value.parse() {
+            Ok(n) if n > 0 => Ok(Concurrency::Fixed(n)),
+            _ => Err(format!(
+                "Invalid concurrency: {} (expected a number of files, or auto)",
+                value
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdjustmentReason {
+    /// A window of successful requests.
+    Increase,
+    /// A 503 SlowDown or 429 response.
+    Throttled,
+}
+
+/// One change of the level, written as a line of the tuning log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConcurrencyEvent {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub from: usize,
+    pub to: usize,
+    pub reason: AdjustmentReason,
+}
+
+/// Additive increase, multiplicative decrease of a level between `min` and
+/// `max`.
+#[derive(Debug, Clone)]
+pub struct AimdController {
+    limit: usize,
+    min: usize,
+    max: usize,
+    /// Successes since the last adjustment.
+    successes: usize,
+    /// Incremented by every decrease.
+    epoch: u64,
+    events: Vec<ConcurrencyEvent>,
+}
+
+impl AimdController {
+    /// Starts at `initial`, within `min..=max`. `min` is at least 1.
+    pub fn new(initial: usize, min: usize, max: usize) -> Self {
+        let min = min.max(1);
+        let max = max.max(min);
+        Self {
+            limit: initial.max(min).min(max),
+            min,
+            max,
+            successes: 0,
+            epoch: 0,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// To be read when a request is sent and passed to `on_throttle`.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn on_success(&mut self) {
+        self.successes += 1;
+        if self.successes >= self.limit {
+            self.successes = 0;
+            if self.limit < self.max {
+                self.adjust(self.limit + 1, AdjustmentReason::Increase);
+            }
+        }
+    }
+
+    /// A request sent at `epoch` was throttled. Ignored if the level was
+    /// already lowered since.
+    pub fn on_throttle(&mut self, epoch: u64) {
+        if epoch != self.epoch {
+            return;
+        }
+        self.epoch += 1;
+        self.successes = 0;
+        let to = (self.limit / 2).max(self.min);
+        if to != self.limit {
+            self.adjust(to, AdjustmentReason::Throttled);
+        }
+    }
+
+    /// The adjustments made so far, oldest first.
+    pub fn events(&self) -> &[ConcurrencyEvent] {
+        &self.events
+    }
+
+    fn adjust(&mut self, to: usize, reason: AdjustmentReason) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.events.push(ConcurrencyEvent {
+            timestamp_ms,
+            from: self.limit,
+            to,
+            reason,
+        });
+        self.limit = to;
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    controller: AimdController,
+    in_flight: usize,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Notify,
+}
+
+/// An `AimdController` shared by the files of a run, handing out one permit
+/// per file in flight.
+#[derive(Debug, Clone)]
+pub struct AdaptiveConcurrency {
+    shared: Arc<Shared>,
+}
+
+impl AdaptiveConcurrency {
+    pub fn new(controller: AimdController) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    controller,
+                    in_flight: 0,
+                }),
+                changed: Notify::new(),
+            }),
+        }
+    }
+
+    /// The current level.
+    pub fn limit(&self) -> usize {
+        self.shared.state.lock().unwrap().controller.limit()
+    }
+
+    pub fn max(&self) -> usize {
+        self.shared.state.lock().unwrap().controller.max()
+    }
+
+    pub fn events(&self) -> Vec<ConcurrencyEvent> {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .controller
+            .events()
+            .to_vec()
+    }
+
+    /// Waits until fewer files than the level are in flight. A lowered level
+    /// takes effect as the files in flight finish.
+    pub async fn acquire(&self) -> AdaptivePermit {
+        loop {
+            // Created before the check, so a release in between is not missed.
+            let changed = self.shared.changed.notified();
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.in_flight < state.controller.limit() {
+                    state.in_flight += 1;
+                    return AdaptivePermit {
+                        shared: self.shared.clone(),
+                    };
+                }
+            }
+            changed.await;
+        }
+    }
+
+    fn epoch(&self) -> u64 {
+        self.shared.state.lock().unwrap().controller.epoch()
+    }
+
+    /// Feeds the outcome of a request sent at `epoch` to the controller.
+    /// Errors other than throttling do not change the level.
+    fn record(&self, epoch: u64, result: Result<(), &OpError>) {
+        let raised = {
+            let mut state = self.shared.state.lock().unwrap();
+            let before = state.controller.limit();
+            match result {
+                Ok(()) => state.controller.on_success(),
+                Err(err) if err.is_throttling() => state.controller.on_throttle(epoch),
+                Err(_) => {}
+            }
+            state.controller.limit() > before
+        };
+        if raised {
+            self.shared.changed.notify_waiters();
+        }
+    }
+}
+
+/// A file in flight, until dropped.
+pub struct AdaptivePermit {
+    shared: Arc<Shared>,
+}
+
+impl Drop for AdaptivePermit {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().in_flight -= 1;
+        self.shared.changed.notify_waiters();
+    }
+}
+
+/// `S3Ops` that reports the outcome of every request to an
+/// `AdaptiveConcurrency`.
+pub struct AdaptiveOps<'o, O: ?Sized> {
+    inner: &'o O,
+    concurrency: &'o AdaptiveConcurrency,
+}
+
+impl<'o, O: S3Ops + ?Sized> AdaptiveOps<'o, O> {
+    pub fn new(inner: &'o O, concurrency: &'o AdaptiveConcurrency) -> Self {
+        Self { inner, concurrency }
+    }
+
+    async fn observe<T>(
+        &self,
+        request: impl Future<Output = Result<T, OpError>>,
+    ) -> Result<T, OpError> {
+        let epoch = self.concurrency.epoch();
+        let result = request.await;
+        self.concurrency.record(epoch, result.as_ref().map(|_| ()));
+        result
+    }
+}
+
+impl_s3_ops! {
+    impl<'o, O: S3Ops + ?Sized> S3Ops for AdaptiveOps<'o, O> {
+        async fn head_bucket(&self, bucket: &str) -> Result<(), OpError> {
+            self.observe(self.inner.head_bucket(bucket)).await
+        }
+
+        async fn create_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> Result<String, OpError> {
+            self.observe(self.inner.create_multipart_upload(bucket, key))
+                .await
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+        ) -> Result<(), OpError> {
+            self.observe(self.inner.abort_multipart_upload(bucket, key, upload_id))
+                .await
+        }
+
+        async fn upload_part(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            self.observe(
+                self.inner
+                    .upload_part(bucket, key, upload_id, part_number, body),
+            )
+            .await
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String)>,
+        ) -> Result<String, OpError> {
+            self.observe(
+                self.inner
+                    .complete_multipart_upload(bucket, key, upload_id, parts),
+            )
+            .await
+        }
+
+        async fn put_object(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            self.observe(self.inner.put_object(bucket, key, body)).await
+        }
+
+        async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), OpError> {
+            self.observe(self.inner.delete_object(bucket, key)).await
+        }
+
+        async fn get_object_range(
+            &self,
+            bucket: &str,
+            key: &str,
+            offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>, OpError> {
+            self.observe(self.inner.get_object_range(bucket, key, offset, length))
+                .await
+        }
+
+        async fn get_object_range_if_match(
+            &self,
+            bucket: &str,
+            key: &str,
+            offset: u64,
+            length: u64,
+            e_tag: &str,
+        ) -> Result<Vec<u8>, OpError> {
+            self.observe(
+                self.inner
+                    .get_object_range_if_match(bucket, key, offset, length, e_tag),
+            )
+            .await
+        }
+
+        async fn create_multipart_upload_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> Result<String, OpError> {
+            self.observe(self.inner.create_multipart_upload_sha256(bucket, key))
+                .await
+        }
+
+        async fn upload_part_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+            checksum: String,
+        ) -> Result<String, OpError> {
+            self.observe(self.inner.upload_part_sha256(
+                bucket,
+                key,
+                upload_id,
+                part_number,
+                body,
+                checksum,
+            ))
+            .await
+        }
+
+        async fn complete_multipart_upload_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String, String)>,
+        ) -> Result<String, OpError> {
+            self.observe(
+                self.inner
+                    .complete_multipart_upload_sha256(bucket, key, upload_id, parts),
+            )
+            .await
+        }
+
+        async fn put_object_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: Vec<u8>,
+            checksum: String,
+        ) -> Result<String, OpError> {
+            self.observe(self.inner.put_object_sha256(bucket, key, body, checksum))
+                .await
+        }
+    }
+}
@@ -0,0 +1,410 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Uploads a growing, append-only file as a long-lived multipart upload.
+//!
+//! Each run reads the bytes appended since the previous one and uploads
+//! them as the next parts, and a final run completes the object, so that a
+//! log written all day is assembled at midnight without uploading it again.
+//! An `AppendManifest` records the upload, the parts, and how far the file
+//! was read. Parts other than the last must be at least 5 MiB, so appended
+//! bytes too few to make a part are carried in the manifest, as the
+//! remainder, until the next run has enough.
+//!
+//! The manifest is saved after each part, by replacing it with a new file.
+//! A part uploaded just before a crash, and missing from the manifest, is
+//! found by ListParts on the next run and kept if its ETag is the MD5 of the
+//! bytes it should hold; otherwise it is uploaded again.
+
+use crate::failover::EndpointPool;
+use crate::retry::{RetryPolicy, SlowDownCoordinator};
+use crate::staged_upload::{start_staged_upload, StagedPart};
+use crate::upload::{complete_upload, DEFAULT_PART_SIZE, MAX_PARTS, MIN_PART_SIZE};
+use crate::upload_watch::{list_upload_parts, ListedPart};
+use aws_sdk_s3::model::CompletedPart;
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Error};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// The state of an append upload between runs, as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppendManifest {
+    pub bucket: String,
+    pub key: String,
+    pub upload_id: String,
+    /// The bytes of the file uploaded as `parts`.
+    pub uploaded: u64,
+    /// The bytes of the file read so far: `uploaded`, then the remainder.
+    pub captured: u64,
+    /// The bytes of the file from `uploaded` to `captured`, in base64.
+    #[serde(default)]
+    pub remainder: String,
+    pub parts: Vec<StagedPart>,
+    /// The ETag of the object, without quotes, once the upload is completed.
+    #[serde(default)]
+    pub completed_e_tag: Option<String>,
+}
+
+impl AppendManifest {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        serde_json::from_str(&content).map_err(|err| {
+            Error::Unhandled(Box::from(format!(
+                "Invalid append manifest {}: {}",
+                path.display(),
+                err
+            )))
+        })
+    }
+
+    /// Writes the manifest to a temporary file renamed over `path`, so that
+    /// a crash leaves either the previous manifest or this one.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, content)
+            .and_then(|_| std::fs::rename(&temporary, path))
+            .map_err(|err| Error::Unhandled(Box::new(err)))
+    }
+
+    fn remainder_bytes(&self) -> Result<Vec<u8>, Error> {
+        let remainder = base64::decode(&self.remainder).map_err(|err| {
+            Error::Unhandled(Box::from(format!(
+                "Invalid remainder in the append manifest: {}",
+                err
+            )))
+        })?;
+        if self.uploaded + remainder.len() as u64 != self.captured {
+            return Err(Error::Unhandled(Box::from(format!(
+                "The append manifest holds {} bytes of remainder, but {} were read past the parts",
+                remainder.len(),
+                self.captured.saturating_sub(self.uploaded)
+            ))));
+        }
+        Ok(remainder)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AppendOptions {
+    /// The largest part uploaded.
+    pub part_size: u64,
+    /// The smallest part uploaded before the last one; fewer bytes are
+    /// carried to the next run.
+    pub min_part_size: u64,
+}
+
+impl Default for AppendOptions {
+    fn default() -> Self {
+        Self {
+            part_size: DEFAULT_PART_SIZE,
+            min_part_size: MIN_PART_SIZE,
+        }
+    }
+}
+
+/// What a run of `append_upload` did.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AppendRun {
+    pub upload_id: String,
+    /// The part numbers uploaded by this run.
+    pub parts_uploaded: Vec<i32>,
+    /// The part numbers found uploaded by a run that stopped before saving
+    /// them to the manifest, and kept.
+    pub parts_recovered: Vec<i32>,
+    /// The bytes of the file read by this run.
+    pub bytes_read: u64,
+    /// The bytes carried to the next run.
+    pub remainder: u64,
+    /// The ETag of the object, without quotes, after `--finalize`.
+    pub e_tag: Option<String>,
+}
+
+/// The bytes not yet uploaded: the remainder of the manifest, then the file
+/// from `offset` to `end`.
+struct Pending {
+    remainder: Vec<u8>,
+    file: tokio::fs::File,
+    offset: u64,
+    end: u64,
+}
+
+impl Pending {
+    fn available(&self) -> u64 {
+        self.remainder.len() as u64 + (self.end - self.offset)
+    }
+
+    /// The next `size` bytes, without consuming them.
+    async fn peek(&mut self, size: u64) -> Result<Vec<u8>, Error> {
+        let from_remainder = size.min(self.remainder.len() as u64) as usize;
+        let mut bytes = self.remainder[..from_remainder].to_vec();
+        let from_file = size - from_remainder as u64;
+        if from_file > 0 {
+            let start = bytes.len();
+            bytes.resize(start + from_file as usize, 0);
+            self.file
+                .seek(std::io::SeekFrom::Start(self.offset))
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+            self.file
+                .read_exact(&mut bytes[start..])
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        }
+        Ok(bytes)
+    }
+
+    fn consume(&mut self, size: u64) {
+        let from_remainder = size.min(self.remainder.len() as u64) as usize;
+        self.remainder.drain(..from_remainder);
+        self.offset += size - from_remainder as u64;
+    }
+
+    /// Records in `manifest` that the next part, of `size` bytes, is
+    /// uploaded.
+    fn advance(&mut self, manifest: &mut AppendManifest, part_number: i32, size: u64, e_tag: &str) {
+        manifest.parts.push(StagedPart {
+            part_number,
+            offset: manifest.uploaded,
+            size,
+            e_tag: e_tag.to_string(),
+        });
+        self.consume(size);
+        manifest.uploaded += size;
+        manifest.captured = self.offset;
+        manifest.remainder = base64::encode(&self.remainder);
+    }
+}
+
+fn md5_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Md5::digest(bytes))
+}
+
+/// Checks the parts of `manifest` against those `listed` by ListParts, and
+/// keeps the listed parts that follow them while they hold the next bytes
+/// of `pending`. Returns the part numbers kept.
+async fn reconcile(
+    manifest: &mut AppendManifest,
+    listed: Vec<ListedPart>,
+    pending: &mut Pending,
+    path: &Path,
+) -> Result<Vec<i32>, Error> {
+    let listed: BTreeMap<i32, ListedPart> = listed
+        .into_iter()
+        .map(|part| (part.part_number, part))
+        .collect();
+    for part in &manifest.parts {
+        match listed.get(&part.part_number) {
+            Some(found) if found.e_tag == part.e_tag => {}
+            _ => {
+                return Err(Error::Unhandled(Box::from(format!(
+                    "Part {} of the manifest is missing from upload {} or was replaced",
+                    part.part_number, manifest.upload_id
+                ))))
+            }
+        }
+    }
+    let mut recovered = Vec::new();
+    loop {
+        let part_number = manifest.parts.len() as i32 + 1;
+        let found = match listed.get(&part_number) {
+            Some(found) if found.size <= pending.available() => found,
+            _ => break,
+        };
+        let bytes = pending.peek(found.size).await?;
+        if !found.e_tag.eq_ignore_ascii_case(&md5_hex(&bytes)) {
+            break;
+        }
+        pending.advance(manifest, part_number, found.size, &found.e_tag);
+        manifest.save(path)?;
+        recovered.push(part_number);
+    }
+    Ok(recovered)
+}
+
+async fn upload_bytes(
+    endpoints: &EndpointPool,
+    manifest: &AppendManifest,
+    part_number: i32,
+    bytes: Vec<u8>,
+) -> Result<String, Error> {
+    let what = format!("part {} of {}", part_number, manifest.key);
+    let part = endpoints
+        .retry(
+            &RetryPolicy::default(),
+            &SlowDownCoordinator::new(),
+            &what,
+            |client| {
+                client
+                    .upload_part()
+                    .bucket(&manifest.bucket)
+                    .key(&manifest.key)
+                    .upload_id(&manifest.upload_id)
+                    .part_number(part_number)
+                    .content_length(bytes.len() as i64)
+                    .body(ByteStream::from(bytes.clone()))
+                    .send()
+            },
+        )
+        .await?;
+    Ok(part.e_tag().unwrap_or_default().replace("\"", ""))
+}
+
+/// Uploads the bytes appended to `file` since the last run as the next
+/// parts of the upload of `manifest_path`, creating the upload and the
+/// manifest on the first run. Bytes too few to make a part of
+/// `options.min_part_size` are carried to the next run, unless `finalize`
+/// is set, in which case they are uploaded as the last part and the object
+/// is completed.
+///
+/// A failure leaves the manifest as of the last part uploaded, so that the
+/// run can be repeated.
+pub async fn append_upload(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file: &Path,
+    manifest_path: &Path,
+    finalize: bool,
+    options: &AppendOptions,
+) -> Result<AppendRun, Error> {
+    if options.min_part_size == 0 || options.part_size < options.min_part_size {
+        return Err(Error::Unhandled(Box::from(format!(
+            "The part size ({}) cannot be below the minimum part size ({})",
+            options.part_size, options.min_part_size
+        ))));
+    }
+    let mut manifest = if manifest_path.exists() {
+        let manifest = AppendManifest::load(manifest_path)?;
+        if (manifest.bucket.as_str(), manifest.key.as_str()) != (bucket, key) {
+            return Err(Error::Unhandled(Box::from(format!(
+                "{} is the manifest of {}/{}, not of {}/{}",
+                manifest_path.display(),
+                manifest.bucket,
+                manifest.key,
+                bucket,
+                key
+            ))));
+        }
+        manifest
+    } else {
+        let manifest = AppendManifest {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            upload_id: start_staged_upload(client, bucket, key).await?,
+            ..Default::default()
+        };
+        manifest.save(manifest_path)?;
+        manifest
+    };
+    if let Some(e_tag) = &manifest.completed_e_tag {
+        return Err(Error::Unhandled(Box::from(format!(
+            "Upload {} of {} is already completed, with ETag {}",
+            manifest.upload_id, key, e_tag
+        ))));
+    }
+
+    let handle = tokio::fs::File::open(file)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let end = handle
+        .metadata()
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+        .len();
+    if end < manifest.captured {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} has {} bytes, fewer than the {} already read; was it truncated or rotated?",
+            file.display(),
+            end,
+            manifest.captured
+        ))));
+    }
+    let mut pending = Pending {
+        remainder: manifest.remainder_bytes()?,
+        file: handle,
+        offset: manifest.captured,
+        end,
+    };
+    let mut run = AppendRun {
+        upload_id: manifest.upload_id.clone(),
+        bytes_read: end - manifest.captured,
+        ..Default::default()
+    };
+
+    let listed = list_upload_parts(client, bucket, key, &manifest.upload_id)
+        .await?
+        .ok_or_else(|| {
+            Error::Unhandled(Box::from(format!(
+                "Upload {} of {} no longer exists",
+                manifest.upload_id, key
+            )))
+        })?;
+    run.parts_recovered = reconcile(&mut manifest, listed, &mut pending, manifest_path).await?;
+
+    let endpoints = EndpointPool::single(client.clone());
+    loop {
+        let available = pending.available();
+        let last_part = finalize && manifest.parts.is_empty() && available == 0;
+        if !last_part && (available == 0 || (!finalize && available < options.min_part_size)) {
+            break;
+        }
+        let part_number = manifest.parts.len() as i32 + 1;
+        if part_number as u64 > MAX_PARTS {
+            return Err(Error::Unhandled(Box::from(format!(
+                "Upload {} has the {} parts of an upload; use larger parts",
+                manifest.upload_id, MAX_PARTS
+            ))));
+        }
+        let size = available.min(options.part_size);
+        let bytes = pending.peek(size).await?;
+        let e_tag = upload_bytes(&endpoints, &manifest, part_number, bytes).await?;
+        pending.advance(&mut manifest, part_number, size, &e_tag);
+        manifest.save(manifest_path)?;
+        run.parts_uploaded.push(part_number);
+        if last_part {
+            break;
+        }
+    }
+
+    if finalize {
+        let completed_parts = manifest
+            .parts
+            .iter()
+            .map(|part| {
+                CompletedPart::builder()
+                    .e_tag(format!("\"{}\"", part.e_tag))
+                    .part_number(part.part_number)
+                    .build()
+            })
+            .collect();
+        let e_tag = complete_upload(
+            &endpoints,
+            bucket,
+            key,
+            &manifest.upload_id,
+            completed_parts,
+            &RetryPolicy::default(),
+            &SlowDownCoordinator::new(),
+        )
+        .await?;
+        manifest.completed_e_tag = Some(e_tag.clone());
+        run.e_tag = Some(e_tag);
+    } else {
+        pending.remainder = pending.peek(pending.available()).await?;
+        pending.offset = pending.end;
+        manifest.captured = pending.offset;
+        manifest.remainder = base64::encode(&pending.remainder);
+        run.remainder = pending.remainder.len() as u64;
+    }
+    manifest.save(manifest_path)?;
+    Ok(run)
+}
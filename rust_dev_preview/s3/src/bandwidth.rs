@@ -0,0 +1,126 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! A cap on the bytes per second read from S3, shared by concurrent
+//! downloads.
+//!
+//! Where `rate_limit` paces requests, this paces bytes: each response body
+//! is wrapped in a `ThrottledRead`, and all of them take tokens from one
+//! `TokenBucket`, one per byte, so that their combined throughput stays
+//! under the cap however many are running. A read is paid for after it
+//! completes, and the next read of the same body waits until the bucket is
+//! out of debt. Downloading a large dataset this way leaves the rest of a
+//! shared link to the other hosts on it.
+
+use futures::ready;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::time::Sleep;
+
+/// A token bucket refilled at `bytes_per_second`, holding at most a tenth
+/// of a second of tokens so that the downloads do not burst past the cap.
+#[derive(Debug)]
+pub struct TokenBucket {
+    bytes_per_second: u64,
+    capacity: f64,
+    /// Negative while in debt.
+    tokens: f64,
+    refilled: Instant,
+    taken: u64,
+}
+
+impl TokenBucket {
+    /// Fails unless `bytes_per_second` is positive.
+    pub fn new(bytes_per_second: u64) -> Result<Self, String> {
+        if bytes_per_second == 0 {
+            return Err("The bandwidth limit must be positive".to_string());
+        }
+        let capacity = (bytes_per_second as f64 / 10.0).max(1.0);
+        Ok(Self {
+            bytes_per_second,
+            capacity,
+            tokens: capacity,
+            refilled: Instant::now(),
+            taken: 0,
+        })
+    }
+
+    /// The configured limit.
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// The bytes taken so far.
+    pub fn taken(&self) -> u64 {
+        self.taken
+    }
+
+    /// Takes `bytes` tokens, going into debt if there are not enough, and
+    /// returns how long to wait until the debt is paid back.
+    pub fn take(&mut self, bytes: u64) -> Duration {
+        self.take_at(bytes, Instant::now())
+    }
+
+    /// Same as `take`, at `now`.
+    pub fn take_at(&mut self, bytes: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.refilled = self.refilled.max(now);
+        self.tokens = (self.tokens + elapsed * self.bytes_per_second as f64).min(self.capacity)
+            - bytes as f64;
+        self.taken += bytes;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.bytes_per_second as f64)
+        }
+    }
+}
+
+/// A reader whose reads take their bytes from a shared `TokenBucket`.
+pub struct ThrottledRead<R> {
+    inner: R,
+    bucket: Arc<Mutex<TokenBucket>>,
+    /// The wait owed for the last read, before the next one.
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> ThrottledRead<R> {
+    pub fn new(inner: R, bucket: Arc<Mutex<TokenBucket>>) -> Self {
+        Self {
+            inner,
+            bucket,
+            delay: None,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ThrottledRead<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(delay) = this.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            this.delay = None;
+        }
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = (buf.filled().len() - before) as u64;
+        if read > 0 {
+            let wait = this.bucket.lock().unwrap().take(read);
+            if !wait.is_zero() {
+                this.delay = Some(Box::pin(tokio::time::sleep(wait)));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,322 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Packing of small files into archive objects.
+//!
+//! Uploading many tiny files is dominated by per-request latency, so files
+//! below a size threshold can be concatenated into archive objects, one or
+//! more per directory. Each archive `<dir>/.s3-batch-<id>.pack` has an index
+//! object `<dir>/.s3-batch-<id>.index.json` recording where each file is in
+//! the archive. Sync and prefix downloads read the indexes to see the packed
+//! files as if they were ordinary objects.
+
+use crate::memory_budget::{reserve, MemoryBudget};
+use crate::sync::{format_mtime, parse_mtime, LocalFile, RemoteObject};
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Error};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+/// Default upper bound of the size of an archive object.
+pub const DEFAULT_MAX_ARCHIVE_SIZE: u64 = 64 * 1024 * 1024;
+
+const BATCH_FILE_PREFIX: &str = ".s3-batch-";
+const ARCHIVE_SUFFIX: &str = ".pack";
+const INDEX_SUFFIX: &str = ".index.json";
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOptions {
+    /// Files smaller than this are packed.
+    pub threshold: u64,
+    pub max_archive_size: u64,
+    /// Record the SHA-256 of each packed file in the index; see the `stamp`
+    /// module.
+    pub stamp_content_hash: bool,
+}
+
+/// A packed file: `size` bytes at `offset` in the archive.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub key: String,
+    pub offset: u64,
+    pub size: u64,
+    /// The file's modification time, formatted like the `source-mtime`
+    /// metadata.
+    pub mtime: String,
+    /// The lowercase hex SHA-256 of the file, when stamped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Content of an index object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveIndex {
+    pub archive_key: String,
+    pub entries: Vec<ArchiveEntry>,
+}
+
+/// Files packed together into one archive object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveBatch {
+    /// The key prefix shared by the files, ending with `/` unless empty.
+    pub directory: String,
+    pub files: Vec<LocalFile>,
+    pub size: u64,
+    /// Stamp the entries of the index with the SHA-256 of their file.
+    pub stamp_content_hash: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BatchPlan {
+    pub archives: Vec<ArchiveBatch>,
+    /// Files uploaded as their own object.
+    pub individual: Vec<LocalFile>,
+}
+
+impl BatchPlan {
+    pub fn packed_files(&self) -> usize {
+        self.archives.iter().map(|a| a.files.len()).sum()
+    }
+
+    /// Requests saved compared to one PutObject per file; each archive costs
+    /// two (the archive and its index).
+    pub fn requests_saved(&self) -> i64 {
+        self.packed_files() as i64 - 2 * self.archives.len() as i64
+    }
+}
+
+/// The directory part of a key, including the trailing `/`.
+fn directory_of(key: &str) -> &str {
+    match key.rfind('/') {
+        Some(i) => &key[..=i],
+        None => "",
+    }
+}
+
+/// Splits `files` into archives of small files, grouped by directory and
+/// capped at `options.max_archive_size`, and files uploaded individually.
+///
+/// A group that would hold a single file is not worth an archive and its
+/// index, so that file is uploaded individually.
+pub fn plan_batches(files: Vec<LocalFile>, options: &BatchOptions) -> BatchPlan {
+    let mut plan = BatchPlan::default();
+    let mut by_directory: BTreeMap<String, Vec<LocalFile>> = BTreeMap::new();
+    for file in files {
+        if file.size < options.threshold && file.size <= options.max_archive_size {
+            by_directory
+                .entry(directory_of(&file.key).to_string())
+                .or_default()
+                .push(file);
+        } else {
+            plan.individual.push(file);
+        }
+    }
+
+    fn close(batch: ArchiveBatch, plan: &mut BatchPlan) {
+        if batch.files.len() > 1 {
+            plan.archives.push(batch);
+        } else {
+            plan.individual.extend(batch.files);
+        }
+    }
+    for (directory, files) in by_directory {
+        let mut batch = ArchiveBatch {
+            directory: directory.clone(),
+            files: Vec::new(),
+            size: 0,
+            stamp_content_hash: options.stamp_content_hash,
+        };
+        for file in files {
+            if !batch.files.is_empty() && batch.size + file.size > options.max_archive_size {
+                let full = std::mem::replace(
+                    &mut batch,
+                    ArchiveBatch {
+                        directory: directory.clone(),
+                        files: Vec::new(),
+                        size: 0,
+                        stamp_content_hash: options.stamp_content_hash,
+                    },
+                );
+                close(full, &mut plan);
+            }
+            batch.size += file.size;
+            batch.files.push(file);
+        }
+        close(batch, &mut plan);
+    }
+    plan
+}
+
+/// Whether `key` is an archive or index object written by this module.
+pub fn is_batch_object(key: &str) -> bool {
+    let name = &key[directory_of(key).len()..];
+    name.starts_with(BATCH_FILE_PREFIX)
+        && (name.ends_with(ARCHIVE_SUFFIX) || name.ends_with(INDEX_SUFFIX))
+}
+
+fn is_index(key: &str) -> bool {
+    is_batch_object(key) && key.ends_with(INDEX_SUFFIX)
+}
+
+/// Reads the files of `batch`, uploads them as one archive object, then
+/// uploads its index, with the SHA-256 of each file if
+/// `batch.stamp_content_hash`.
+pub async fn upload_archive(
+    client: &Client,
+    bucket: &str,
+    batch: &ArchiveBatch,
+) -> Result<ArchiveIndex, Error> {
+    upload_archive_with_budget(client, bucket, batch, None).await
+}
+
+/// Same as `upload_archive`, reserving the archive from `budget` before it
+/// is read into memory.
+pub async fn upload_archive_with_budget(
+    client: &Client,
+    bucket: &str,
+    batch: &ArchiveBatch,
+    budget: Option<&MemoryBudget>,
+) -> Result<ArchiveIndex, Error> {
+    let _reservation = reserve(budget, batch.size).await;
+    let id = Uuid::new_v4().to_simple().to_string();
+    let archive_key = format!(
+        "{}{}{}{}",
+        batch.directory, BATCH_FILE_PREFIX, id, ARCHIVE_SUFFIX
+    );
+    let index_key = format!(
+        "{}{}{}{}",
+        batch.directory, BATCH_FILE_PREFIX, id, INDEX_SUFFIX
+    );
+
+    let mut body = Vec::with_capacity(batch.size as usize);
+    let mut entries = Vec::with_capacity(batch.files.len());
+    for file in &batch.files {
+        let data = tokio::fs::read(&file.path)
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        entries.push(ArchiveEntry {
+            key: file.key.clone(),
+            offset: body.len() as u64,
+            size: data.len() as u64,
+            mtime: format_mtime(file.mtime),
+            sha256: if batch.stamp_content_hash {
+                Some(format!("{:x}", Sha256::digest(&data)))
+            } else {
+                None
+            },
+        });
+        body.extend_from_slice(&data);
+    }
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(&archive_key)
+        .content_type("application/octet-stream")
+        .body(ByteStream::from(body))
+        .send()
+        .await?;
+
+    // The index is written last, so a reader never finds an index whose
+    // archive is missing.
+    let index = ArchiveIndex {
+        archive_key,
+        entries,
+    };
+    let json = serde_json::to_vec(&index).map_err(|err| Error::Unhandled(Box::new(err)))?;
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(&index_key)
+        .content_type("application/json")
+        .body(ByteStream::from(json))
+        .send()
+        .await?;
+    Ok(index)
+}
+
+/// Downloads the indexes found in `remote`.
+pub async fn read_indexes(
+    client: &Client,
+    bucket: &str,
+    remote: &HashMap<String, RemoteObject>,
+) -> Result<Vec<ArchiveIndex>, Error> {
+    let mut indexes = Vec::new();
+    for key in remote.keys().filter(|k| is_index(k)) {
+        let resp = client.get_object().bucket(bucket).key(key).send().await?;
+        let data = resp
+            .body
+            .collect()
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?
+            .into_bytes();
+        let index: ArchiveIndex =
+            serde_json::from_slice(&data).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        // An index whose archive was deleted is stale.
+        if remote.contains_key(&index.archive_key) {
+            indexes.push(index);
+        }
+    }
+    Ok(indexes)
+}
+
+/// Where the current version of a packed file is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackedLocation {
+    pub archive_key: String,
+    pub offset: u64,
+    pub size: u64,
+    /// The SHA-256 stamped in the index, if any.
+    pub sha256: Option<String>,
+}
+
+/// Replaces the archive and index objects in `remote` with the files they
+/// hold, and returns the location of each packed file.
+///
+/// A file can appear in several archives, or both in an archive and as an
+/// object, after it was uploaded again; the copy with the latest modification
+/// time wins.
+pub fn expand_remote(
+    remote: &mut HashMap<String, RemoteObject>,
+    indexes: &[ArchiveIndex],
+) -> HashMap<String, PackedLocation> {
+    let mut packed = HashMap::new();
+    for index in indexes {
+        let last_modified = match remote.get(&index.archive_key) {
+            Some(archive) => archive.last_modified,
+            None => continue,
+        };
+        for entry in &index.entries {
+            let mtime = parse_mtime(&entry.mtime);
+            let candidate = RemoteObject {
+                key: entry.key.clone(),
+                size: entry.size,
+                last_modified,
+                source_mtime: mtime,
+                // Packed files have no ETag of their own.
+                e_tag: None,
+            };
+            let newer = remote
+                .get(&entry.key)
+                .map(|existing| candidate.mtime() > existing.mtime())
+                .unwrap_or(true);
+            if newer {
+                remote.insert(entry.key.clone(), candidate);
+                packed.insert(
+                    entry.key.clone(),
+                    PackedLocation {
+                        archive_key: index.archive_key.clone(),
+                        offset: entry.offset,
+                        size: entry.size,
+                        sha256: entry.sha256.clone(),
+                    },
+                );
+            }
+        }
+    }
+    remote.retain(|key, _| !is_batch_object(key));
+    packed
+}
@@ -0,0 +1,313 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! S3 Batch Operations jobs over the objects of a prefix.
+//!
+//! The objects are listed into a CSV manifest stored in the bucket, and a
+//! job invoking an AWS Lambda function on each of them, or tagging them, is
+//! created from it. `monitor_batch_job` follows a job until it is over.
+
+use crate::sync::list_remote;
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Error};
+use aws_sdk_s3control::model::{
+    JobDescriptor, JobManifest, JobManifestFieldName, JobManifestFormat, JobManifestLocation,
+    JobManifestSpec, JobOperation, JobReport, JobReportFormat, JobReportScope, JobStatus,
+    LambdaInvokeOperation, S3PutObjectTaggingOperation, S3Tag,
+};
+use futures::{stream, Stream};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Characters escaped in the keys of a manifest, which must be URL-encoded;
+/// `/` is kept so that the key hierarchy stays readable.
+const MANIFEST_KEY: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Prefix of the completion reports in the report bucket.
+pub const REPORT_PREFIX: &str = "batch-operations-reports";
+
+/// The `bucket,key` CSV manifest of `keys`, in the
+/// `S3BatchOperations_CSV_20180820` format.
+pub fn manifest_csv<S: AsRef<str>>(bucket: &str, keys: &[S]) -> String {
+    keys.iter()
+        .map(|key| {
+            format!(
+                "{},{}\n",
+                bucket,
+                utf8_percent_encode(key.as_ref(), MANIFEST_KEY)
+            )
+        })
+        .collect()
+}
+
+/// Lists the objects under `prefix` and writes their manifest to
+/// `output_key` in the same bucket. Returns the output key.
+///
+/// The manifest itself is left out when it is under `prefix`.
+pub async fn create_batch_job_manifest(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    output_key: &str,
+) -> Result<String, Error> {
+    let mut keys = list_remote(client, bucket, prefix)
+        .await?
+        .into_iter()
+        .map(|(key, _)| key)
+        .filter(|key| key != output_key)
+        .collect::<Vec<_>>();
+    keys.sort();
+    if keys.is_empty() {
+        return Err(Error::Unhandled(Box::from(format!(
+            "No objects under s3://{}/{}",
+            bucket, prefix
+        ))));
+    }
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(output_key)
+        .content_type("text/csv")
+        .body(ByteStream::from(manifest_csv(bucket, &keys).into_bytes()))
+        .send()
+        .await?;
+    Ok(output_key.to_string())
+}
+
+/// Creates a Batch Operations job invoking `lambda_arn` on each object of the
+/// manifest, and returns the job ID.
+///
+/// The job needs the ETag of the manifest object, so that it fails if the
+/// manifest is replaced, and a role it can assume to read the manifest,
+/// invoke the function, and write the completion report of all tasks to
+/// `report_bucket`. The job runs without confirmation.
+pub async fn create_batch_operations_job(
+    s3control_client: &aws_sdk_s3control::Client,
+    account_id: &str,
+    manifest_arn: &str,
+    manifest_etag: &str,
+    lambda_arn: &str,
+    role_arn: &str,
+    report_bucket: &str,
+) -> Result<String, aws_sdk_s3control::Error> {
+    let operation = JobOperation::builder()
+        .lambda_invoke(
+            LambdaInvokeOperation::builder()
+                .function_arn(lambda_arn)
+                .build(),
+        )
+        .build();
+    create_job(
+        s3control_client,
+        account_id,
+        csv_manifest(manifest_arn, manifest_etag),
+        operation,
+        role_arn,
+        report_bucket,
+    )
+    .await
+}
+
+/// Creates a Batch Operations job replacing the tags of each object of the
+/// manifest at `manifest_bucket/manifest_key` with `tag_set`, and returns
+/// the job ID.
+///
+/// The ETag of the manifest is read with `s3_client`. As with
+/// `create_batch_operations_job`, the job runs as `role_arn`, which must be
+/// allowed `s3:PutObjectTagging` on the objects, and writes its completion
+/// report to `report_bucket`.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_tagging_batch_job(
+    s3control_client: &aws_sdk_s3control::Client,
+    s3_client: &Client,
+    account_id: &str,
+    manifest_bucket: &str,
+    manifest_key: &str,
+    tag_set: HashMap<String, String>,
+    role_arn: &str,
+    report_bucket: &str,
+) -> Result<String, Error> {
+    let head = s3_client
+        .head_object()
+        .bucket(manifest_bucket)
+        .key(manifest_key)
+        .send()
+        .await?;
+    let mut tag_set: Vec<_> = tag_set.into_iter().collect();
+    tag_set.sort();
+    let tags = tag_set
+        .into_iter()
+        .map(|(key, value)| S3Tag::builder().key(key).value(value).build())
+        .collect();
+    let operation = JobOperation::builder()
+        .s3_put_object_tagging(
+            S3PutObjectTaggingOperation::builder()
+                .set_tag_set(Some(tags))
+                .build(),
+        )
+        .build();
+    create_job(
+        s3control_client,
+        account_id,
+        csv_manifest(
+            &format!("arn:aws:s3:::{}/{}", manifest_bucket, manifest_key),
+            head.e_tag().unwrap_or_default(),
+        ),
+        operation,
+        role_arn,
+        report_bucket,
+    )
+    .await
+    .map_err(|err| Error::Unhandled(Box::new(err)))
+}
+
+/// Where a job is, from its DescribeJob.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobProgress {
+    pub status: JobStatus,
+    pub total_tasks: i64,
+    pub succeeded: i64,
+    pub failed: i64,
+    /// The tasks over, succeeded or failed, out of the total.
+    pub progress_percent: f64,
+}
+
+impl JobProgress {
+    pub fn from_job(job: &JobDescriptor) -> Self {
+        let status = job.status().cloned().unwrap_or(JobStatus::New);
+        let summary = job.progress_summary();
+        let total_tasks = summary.and_then(|s| s.total_number_of_tasks()).unwrap_or(0);
+        let succeeded = summary
+            .and_then(|s| s.number_of_tasks_succeeded())
+            .unwrap_or(0);
+        let failed = summary
+            .and_then(|s| s.number_of_tasks_failed())
+            .unwrap_or(0);
+        let progress_percent = if total_tasks > 0 {
+            (succeeded + failed) as f64 * 100.0 / total_tasks as f64
+        } else if status == JobStatus::Complete {
+            100.0
+        } else {
+            0.0
+        };
+        Self {
+            status,
+            total_tasks,
+            succeeded,
+            failed,
+            progress_percent,
+        }
+    }
+
+    /// Whether the job completed, failed, or was cancelled.
+    pub fn is_over(&self) -> bool {
+        matches!(
+            self.status,
+            JobStatus::Complete | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+/// Describes `job_id` every `poll_interval`, from now until the job is over
+/// or cannot be described. The stream ends after that progress or error.
+pub fn monitor_batch_job(
+    s3control_client: &aws_sdk_s3control::Client,
+    account_id: &str,
+    job_id: &str,
+    poll_interval: Duration,
+) -> impl Stream<Item = Result<JobProgress, Error>> {
+    let client = s3control_client.clone();
+    let account_id = account_id.to_string();
+    let job_id = job_id.to_string();
+    // The state is whether to wait first, or None once the job is over.
+    stream::unfold(Some(false), move |wait| {
+        let client = client.clone();
+        let account_id = account_id.clone();
+        let job_id = job_id.clone();
+        async move {
+            if wait? {
+                tokio::time::sleep(poll_interval).await;
+            }
+            let described = client
+                .describe_job()
+                .account_id(account_id)
+                .job_id(job_id)
+                .send()
+                .await;
+            match described {
+                Ok(resp) => {
+                    let progress = resp
+                        .job()
+                        .map(JobProgress::from_job)
+                        .ok_or_else(|| Error::Unhandled(Box::from("DescribeJob returned no job")));
+                    let next = match &progress {
+                        Ok(progress) if !progress.is_over() => Some(true),
+                        _ => None,
+                    };
+                    Some((progress, next))
+                }
+                Err(err) => Some((Err(Error::Unhandled(Box::new(err))), None)),
+            }
+        }
+    })
+}
+
+/// A CSV manifest of bucket and key columns.
+fn csv_manifest(manifest_arn: &str, manifest_etag: &str) -> JobManifest {
+    JobManifest::builder()
+        .spec(
+            JobManifestSpec::builder()
+                .format(JobManifestFormat::S3BatchOperationsCsv20180820)
+                .fields(JobManifestFieldName::Bucket)
+                .fields(JobManifestFieldName::Key)
+                .build(),
+        )
+        .location(
+            JobManifestLocation::builder()
+                .object_arn(manifest_arn)
+                .e_tag(manifest_etag.trim_matches('"'))
+                .build(),
+        )
+        .build()
+}
+
+/// Creates a job running `operation` on the objects of `manifest`, reporting
+/// all tasks under `REPORT_PREFIX` in `report_bucket`.
+async fn create_job(
+    s3control_client: &aws_sdk_s3control::Client,
+    account_id: &str,
+    manifest: JobManifest,
+    operation: JobOperation,
+    role_arn: &str,
+    report_bucket: &str,
+) -> Result<String, aws_sdk_s3control::Error> {
+    let report = JobReport::builder()
+        .bucket(format!("arn:aws:s3:::{}", report_bucket))
+        .prefix(REPORT_PREFIX)
+        .format(JobReportFormat::ReportCsv20180820)
+        .report_scope(JobReportScope::AllTasks)
+        .enabled(true)
+        .build();
+    let resp = s3control_client
+        .create_job()
+        .account_id(account_id)
+        .confirmation_required(false)
+        .manifest(manifest)
+        .operation(operation)
+        .report(report)
+        .priority(10)
+        .role_arn(role_arn)
+        .client_request_token(Uuid::new_v4().to_string())
+        .send()
+        .await?;
+    Ok(resp.job_id().unwrap_or_default().to_string())
+}
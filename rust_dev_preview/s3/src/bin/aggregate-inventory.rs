@@ -0,0 +1,123 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::inventory::{
+    aggregate_inventory_with_options, assumed_role_clients, INVENTORY_ROLE_NAME,
+};
+use s3_service::units::format_size;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The ID of an account whose buckets are counted. Can be repeated.
+    #[structopt(short, long, number_of_values = 1, required = true)]
+    account_id: Vec<String>,
+
+    /// The bucket the report is written to.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The read-only role assumed in each account.
+    #[structopt(long)]
+    role_name: Option<String>,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Counts the objects and bytes of every bucket of several AWS accounts, by
+/// storage class and by Region, and writes the totals as JSON to a bucket.
+/// # Arguments
+///
+/// * `-a ACCOUNT ...` - The IDs of the accounts whose buckets are counted.
+/// * `-b BUCKET` - The bucket the report is written to.
+/// * `[--role-name ROLE]` - The read-only role assumed in each account.
+///   The default is S3InventoryReadOnly.
+/// * `[-r REGION]` - The Region in which the clients are created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        account_id,
+        bucket,
+        role_name,
+        verbose,
+    } = Opt::from_args();
+    let role_name = role_name.unwrap_or_else(|| INVENTORY_ROLE_NAME.to_string());
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Accounts:          {}", account_id.join(", "));
+        println!("Bucket:            {}", &bucket);
+        println!("Role:              {}", &role_name);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let sts_client = aws_sdk_sts::Client::new(&shared_config);
+    let account_ids: Vec<&str> = account_id.iter().map(String::as_str).collect();
+
+    let report = aggregate_inventory_with_options(
+        &sts_client,
+        &Client::new(&shared_config),
+        &account_ids,
+        &bucket,
+        &role_name,
+        &assumed_role_clients(&shared_config),
+    )
+    .await?;
+
+    println!(
+        "{} objects, {}",
+        report.total_objects,
+        format_size(report.total_bytes)
+    );
+    for (class, totals) in &report.storage_class_breakdown {
+        println!(
+            "  {}: {} objects, {}",
+            class,
+            totals.objects,
+            format_size(totals.bytes)
+        );
+    }
+    for (region, totals) in &report.region_breakdown {
+        println!(
+            "  {}: {} objects, {}",
+            region,
+            totals.objects,
+            format_size(totals.bytes)
+        );
+    }
+    for account in &report.accounts {
+        for denied in &account.access_denied {
+            println!("  skipped in {}: {}", account.account_id, denied);
+        }
+    }
+    println!("Wrote the report to s3://{}/{}", bucket, report.key);
+
+    Ok(())
+}
@@ -0,0 +1,155 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use s3_service::impl_s3_ops;
+use s3_service::ops::{OpError, S3Ops};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The number of calls timed in each round.
+    #[structopt(long, default_value = "10000")]
+    calls: usize,
+
+    /// The number of times the measurement is repeated.
+    #[structopt(long, default_value = "5")]
+    rounds: usize,
+}
+
+/// `S3Ops` answering every call at once. Every call is counted, under a
+/// lock as a test mock records it.
+#[derive(Debug, Default)]
+struct CountingOps {
+    calls: Mutex<usize>,
+}
+
+impl CountingOps {
+    fn call(&self) -> Result<(), OpError> {
+        *self.calls.lock().unwrap() += 1;
+        Ok(())
+    }
+}
+
+impl_s3_ops! {
+    impl S3Ops for CountingOps {
+        async fn head_bucket(&self, _bucket: &str) -> Result<(), OpError> {
+            self.call()
+        }
+
+        async fn create_multipart_upload(
+            &self,
+            _bucket: &str,
+            _key: &str,
+        ) -> Result<String, OpError> {
+            self.call()?;
+            Ok("upload-id".to_string())
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _upload_id: &str,
+        ) -> Result<(), OpError> {
+            self.call()
+        }
+
+        async fn upload_part(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _upload_id: &str,
+            part_number: i32,
+            _body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            self.call()?;
+            Ok(format!("\"etag-{}\"", part_number))
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _upload_id: &str,
+            _parts: Vec<(i32, String)>,
+        ) -> Result<String, OpError> {
+            self.call()?;
+            Ok("etag".to_string())
+        }
+
+        async fn put_object(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            self.call()?;
+            Ok("etag".to_string())
+        }
+
+        async fn delete_object(&self, _bucket: &str, _key: &str) -> Result<(), OpError> {
+            self.call()
+        }
+
+        async fn get_object_range(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>, OpError> {
+            self.call()?;
+            Ok(vec![0; length as usize])
+        }
+    }
+}
+
+/// The futures of `S3Ops`, as built.
+fn futures_built() -> &'static str {
+    if cfg!(feature = "nightly") {
+        "unboxed (nightly feature)"
+    } else if cfg!(feature = "async-trait") {
+        "boxed by async-trait (async-trait feature)"
+    } else {
+        "boxed (default)"
+    }
+}
+
+/// Times `calls` PutObject calls on `ops`, generic as the transfer logic
+/// is, so the calls are only boxed when the trait boxes them.
+async fn time_calls<O: S3Ops + ?Sized>(ops: &O, calls: usize) -> Duration {
+    let start = Instant::now();
+    for _ in 0..calls {
+        let _ = ops.put_object("bucket", "key", Vec::new()).await;
+    }
+    start.elapsed()
+}
+
+/// Times the same mock calls through `S3Ops` in the form it was built
+/// with. Build it with and without `--features nightly` to compare boxed
+/// futures with unboxed ones. No request is sent.
+///
+/// ## Usage
+/// ```
+/// bench-ops [--calls N] [--rounds N]
+/// ```
+#[tokio::main]
+async fn main() {
+    let Opt { calls, rounds } = Opt::from_args();
+    println!("S3Ops futures: {}", futures_built());
+    // The first round warms up the allocator and the caches.
+    time_calls(&CountingOps::default(), calls).await;
+    for round in 1..=rounds {
+        let elapsed = time_calls(&CountingOps::default(), calls).await;
+        println!(
+            "Round {}: {} calls, {} ns per call",
+            round,
+            calls,
+            elapsed.as_nanos() / calls.max(1) as u128
+        );
+    }
+}
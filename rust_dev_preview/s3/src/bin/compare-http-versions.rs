@@ -0,0 +1,144 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_sdk_s3::model::{Delete, ObjectIdentifier};
+use aws_sdk_s3::{Client, Endpoint, Error};
+use http::Uri;
+use s3_service::cli::{parse_endpoint_uri, parse_size};
+use s3_service::http2::{
+    build_s3_client_counted, time_sequential_uploads, ConnectionCounter, HttpVersion,
+    SequentialTimings,
+};
+use s3_service::units::{format_duration, format_latency};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The profile in the .aws/credentials file.
+    profile: String,
+
+    /// The endpoint URL.
+    #[structopt(parse(try_from_str = parse_endpoint_uri))]
+    url: Uri,
+
+    /// The name of the bucket.
+    bucket: String,
+
+    /// The number of objects uploaded with each HTTP version.
+    #[structopt(long, default_value = "100")]
+    count: usize,
+
+    /// The size of each object.
+    #[structopt(long, default_value = "1KiB", parse(try_from_str = parse_size))]
+    size: u64,
+
+    /// The requests in flight at a time over HTTP/2.
+    #[structopt(long, default_value = "100")]
+    max_concurrent_streams: u32,
+
+    /// The prefix of the uploaded objects, which are deleted at the end.
+    #[structopt(long, default_value = "http-version-benchmark/")]
+    prefix: String,
+}
+
+fn report(label: &str, timings: &SequentialTimings, connections: u64) {
+    println!(
+        "{}: {} uploads in {}; first {}, then {} each; \
+         connection setup {}; {} connections opened",
+        label,
+        timings.uploads,
+        format_duration(timings.total),
+        format_latency(timings.first),
+        format_latency(timings.mean_reused()),
+        format_latency(timings.setup_overhead()),
+        connections
+    );
+}
+
+async fn delete_keys(client: &Client, bucket: &str, keys: Vec<String>) -> Result<(), Error> {
+    for chunk in keys.chunks(1000) {
+        let objects = chunk
+            .iter()
+            .map(|key| ObjectIdentifier::builder().key(key).build())
+            .collect();
+        client
+            .delete_objects()
+            .bucket(bucket)
+            .delete(Delete::builder().set_objects(Some(objects)).build())
+            .send()
+            .await?;
+    }
+    Ok(())
+}
+
+/// Compares the connection setup of HTTP/1.1 and HTTP/2 over sequential
+/// small uploads, each version with its own client and connection pool.
+///
+/// ## Usage
+/// ```shell
+/// compare-http-versions <profile> <url> <bucket> [--count N] [--size SIZE] \
+///   [--max-concurrent-streams N] [--prefix PREFIX]
+/// ```
+///
+/// Both clients reuse their connections, so the first upload of each pays
+/// for the TCP and TLS handshakes and the others do not; the difference is
+/// printed as the connection setup. An `https` endpoint that does not offer
+/// HTTP/2 is reached over HTTP/1.1 by both clients, and a plain `http` one
+/// always is. The uploaded objects are deleted at the end.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    const REGION: &str = "us-east-1";
+    let Opt {
+        profile,
+        url,
+        bucket,
+        count,
+        size,
+        max_concurrent_streams,
+        prefix,
+    } = Opt::from_args();
+    // credentials are read from .aws/credentials file
+    let conf = aws_config::from_env()
+        .region(REGION)
+        .credentials_provider(
+            aws_config::profile::ProfileFileCredentialsProvider::builder()
+                .profile_name(profile)
+                .build(),
+        )
+        .load()
+        .await;
+    let s3_conf = || {
+        aws_sdk_s3::config::Builder::from(&conf)
+            .endpoint_resolver(Endpoint::immutable(url.clone()))
+            .build()
+    };
+
+    let versions = [
+        ("HTTP/1.1", "http1/", HttpVersion::Http1),
+        (
+            "HTTP/2",
+            "http2/",
+            HttpVersion::Http2 {
+                max_concurrent_streams,
+            },
+        ),
+    ];
+    let mut keys = Vec::new();
+    let mut cleanup = None;
+    for (label, version_prefix, version) in versions.iter().copied() {
+        let counter = ConnectionCounter::new();
+        let client = build_s3_client_counted(s3_conf(), version, &counter);
+        let prefix = format!("{}{}", prefix, version_prefix);
+        let timings =
+            time_sequential_uploads(&client, &bucket, &prefix, count, size as usize).await?;
+        report(label, &timings, counter.opened());
+        keys.extend((0..count).map(|n| format!("{}{}", prefix, n)));
+        cleanup = Some(client);
+    }
+    if let Some(client) = cleanup {
+        delete_keys(&client, &bucket, keys).await?;
+    }
+    Ok(())
+}
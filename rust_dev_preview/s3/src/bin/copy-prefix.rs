@@ -0,0 +1,162 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::cli::parse_key_value;
+use s3_service::copy_prefix::{copy_prefix, CopyPrefixOptions};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the source bucket.
+    #[structopt(short, long)]
+    source: String,
+
+    /// The name of the destination bucket.
+    #[structopt(short, long)]
+    destination: String,
+
+    /// The prefix to copy from the source bucket.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// The prefix the objects are copied to. Defaults to the source prefix.
+    #[structopt(long)]
+    destination_prefix: Option<String>,
+
+    /// The maximum number of objects copied at the same time.
+    #[structopt(short, long, default_value = "16")]
+    concurrency: usize,
+
+    /// COPY keeps the source metadata; REPLACE uses the --metadata values.
+    #[structopt(long, default_value = "COPY")]
+    metadata_directive: String,
+
+    /// Metadata (key=value) applied with --metadata-directive REPLACE.
+    #[structopt(long, parse(try_from_str = parse_key_value))]
+    metadata: Vec<(String, String)>,
+
+    /// Skip objects whose destination has the same size and ETag, or was
+    /// copied in parts from the same source.
+    #[structopt(long)]
+    skip_existing: bool,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Copies every object under a prefix from one Amazon S3 bucket to another
+/// using server-side copies.
+/// # Arguments
+///
+/// * `-s SOURCE` - The name of the source bucket.
+/// * `-d DESTINATION` - The name of the destination bucket.
+/// * `[-p PREFIX]` - The prefix to copy. If not supplied, copies the whole bucket.
+/// * `[--destination-prefix PREFIX]` - The prefix in the destination bucket.
+///   If not supplied, the keys remain the same.
+/// * `[-c CONCURRENCY]` - The number of objects copied at the same time.
+/// * `[--metadata-directive COPY|REPLACE]` - Whether to keep the source metadata.
+/// * `[--metadata KEY=VALUE]` - Metadata used with `--metadata-directive REPLACE`.
+/// * `[--skip-existing]` - Skip objects already present in the destination,
+///   recognizing multipart copies by the source ETag they record.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        source,
+        destination,
+        prefix,
+        destination_prefix,
+        concurrency,
+        metadata_directive,
+        metadata,
+        skip_existing,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    let destination_prefix = destination_prefix.unwrap_or_else(|| prefix.clone());
+    let replace_metadata = match metadata_directive.to_uppercase().as_str() {
+        "COPY" => false,
+        "REPLACE" => true,
+        other => {
+            return Err(Error::Unhandled(Box::from(format!(
+                "Unknown metadata directive: {} (COPY or REPLACE)",
+                other
+            ))))
+        }
+    };
+
+    println!();
+
+    if verbose {
+        println!("S3 client version:  {}", PKG_VERSION);
+        println!(
+            "Region:             {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Source bucket:      {}", &source);
+        println!("Source prefix:      {}", &prefix);
+        println!("Destination bucket: {}", &destination);
+        println!("Destination prefix: {}", &destination_prefix);
+        println!("Concurrency:        {}", concurrency);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+
+    let options = CopyPrefixOptions {
+        concurrency,
+        replace_metadata,
+        metadata: metadata.into_iter().collect(),
+        skip_existing,
+        ..Default::default()
+    };
+    let summary = copy_prefix(
+        &client,
+        &source,
+        &prefix,
+        &destination,
+        &destination_prefix,
+        &options,
+    )
+    .await?;
+
+    println!();
+    println!(
+        "Copied {} objects ({} bytes)",
+        summary.copied.len(),
+        summary.bytes_copied
+    );
+    for key in &summary.copied {
+        println!("  copied:  {}", key);
+    }
+    println!("Skipped {} objects", summary.skipped.len());
+    for key in &summary.skipped {
+        println!("  skipped: {}", key);
+    }
+    println!("Failed {} objects", summary.failed.len());
+    for (key, err) in &summary.failed {
+        println!("  failed:  {} ({})", key, err);
+    }
+
+    Ok(())
+}
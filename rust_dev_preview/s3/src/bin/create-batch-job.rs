@@ -0,0 +1,142 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::batch_operations::{
+    create_batch_job_manifest, create_batch_operations_job, REPORT_PREFIX,
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The prefix of the objects the job runs on.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// The key the manifest is written to.
+    #[structopt(short, long, default_value = "batch-operations-manifest.csv")]
+    manifest_key: String,
+
+    /// The ID of the AWS account that owns the bucket.
+    #[structopt(long)]
+    account_id: String,
+
+    /// The ARN of the Lambda function invoked on each object.
+    #[structopt(long)]
+    lambda_arn: String,
+
+    /// The ARN of the IAM role the job runs as.
+    #[structopt(long)]
+    role_arn: String,
+
+    /// The bucket the completion report is written to. Defaults to the
+    /// bucket of the objects.
+    #[structopt(long)]
+    report_bucket: Option<String>,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Lists the objects under a prefix into a CSV manifest and creates an
+/// S3 Batch Operations job invoking a Lambda function on each of them.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `[-p PREFIX]` - The prefix of the objects the job runs on.
+/// * `[-m MANIFEST-KEY]` - The key the manifest is written to.
+///   The default is batch-operations-manifest.csv.
+/// * `--account-id ACCOUNT` - The ID of the account that owns the bucket.
+/// * `--lambda-arn ARN` - The Lambda function invoked on each object.
+/// * `--role-arn ARN` - The IAM role the job runs as.
+/// * `[--report-bucket BUCKET]` - Where the completion report is written.
+///   If not supplied, uses BUCKET.
+/// * `[-r REGION]` - The Region in which the clients are created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        prefix,
+        manifest_key,
+        account_id,
+        lambda_arn,
+        role_arn,
+        report_bucket,
+        verbose,
+    } = Opt::from_args();
+    let report_bucket = report_bucket.unwrap_or_else(|| bucket.clone());
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+    let region = region_provider.region().await.unwrap();
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!("Region:            {}", region.as_ref());
+        println!("Bucket:            {}", &bucket);
+        println!("Prefix:            {}", &prefix);
+        println!("Manifest key:      {}", &manifest_key);
+        println!("Lambda function:   {}", &lambda_arn);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region.clone()).load().await;
+    let client = Client::new(&shared_config);
+    let s3control_client = aws_sdk_s3control::Client::new(&shared_config);
+
+    let manifest_key = create_batch_job_manifest(&client, &bucket, &prefix, &manifest_key).await?;
+    let head = client
+        .head_object()
+        .bucket(&bucket)
+        .key(&manifest_key)
+        .send()
+        .await?;
+    println!("Wrote the manifest to s3://{}/{}", bucket, manifest_key);
+
+    let job_id = create_batch_operations_job(
+        &s3control_client,
+        &account_id,
+        &format!("arn:aws:s3:::{}/{}", bucket, manifest_key),
+        head.e_tag().unwrap_or_default(),
+        &lambda_arn,
+        &role_arn,
+        &report_bucket,
+    )
+    .await
+    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+
+    println!("Created job {}", job_id);
+    println!(
+        "The report is written under s3://{}/{}/",
+        report_bucket, REPORT_PREFIX
+    );
+    println!("Check its status with:");
+    println!(
+        "  aws s3control describe-job --account-id {} --job-id {} --region {}",
+        account_id,
+        job_id,
+        region.as_ref()
+    );
+    Ok(())
+}
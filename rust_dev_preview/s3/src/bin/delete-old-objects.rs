@@ -0,0 +1,121 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::expiry::{delete_keys, find_objects_older_than};
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// Only the objects under this prefix are deleted.
+    #[structopt(short, long)]
+    prefix: Option<String>,
+
+    /// The objects last modified more than this many days ago are deleted.
+    #[structopt(long)]
+    max_age_days: u64,
+
+    /// List the objects that would be deleted without deleting them.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Deletes the objects in an Amazon S3 bucket older than a number of days.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `--max-age-days DAYS` - The objects last modified more than _DAYS_ days ago are deleted.
+/// * `[-p PREFIX]` - Only delete objects under this prefix.
+///   If not supplied, the whole bucket is scanned.
+/// * `[--dry-run]` - List the objects that would be deleted without deleting them.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        prefix,
+        max_age_days,
+        dry_run,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Bucket:            {}", &bucket);
+        println!("Prefix:            {}", prefix.as_deref().unwrap_or(""));
+        println!("Max age:           {} days", max_age_days);
+        println!("Dry run:           {}", dry_run);
+        println!();
+    }
+
+    if prefix.is_none() {
+        eprintln!(
+            "Warning: no prefix given, every object in {} is scanned",
+            bucket
+        );
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+
+    let max_age = Duration::from_secs(max_age_days * 24 * 60 * 60);
+    let aged = find_objects_older_than(&client, &bucket, prefix.as_deref(), max_age).await?;
+    if verbose || dry_run {
+        for key in &aged.keys {
+            println!("  {}", key);
+        }
+    }
+
+    if dry_run {
+        println!(
+            "Would delete {} objects ({} bytes) older than {} days",
+            aged.keys.len(),
+            aged.bytes,
+            max_age_days
+        );
+        return Ok(());
+    }
+
+    let deleted = delete_keys(&client, &bucket, &aged.keys).await?;
+    println!(
+        "Deleted {} of {} objects ({} bytes) older than {} days",
+        deleted,
+        aged.keys.len(),
+        aged.bytes,
+        max_age_days
+    );
+
+    Ok(())
+}
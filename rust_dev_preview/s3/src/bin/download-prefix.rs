@@ -0,0 +1,226 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::cli::parse_size;
+use s3_service::download::{download_prefix_rate_limited, download_prefix_with_verbosity};
+use s3_service::durable::{FsyncOptions, FsyncStats};
+use s3_service::preserve::RestoreOptions;
+use s3_service::units::{format_duration, format_rate, format_size, summary_line};
+use s3_service::verbosity::{request_id_client, VerbosityConfig};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The prefix of the objects to download.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// The local directory the objects are written to.
+    #[structopt(short, long, parse(from_os_str))]
+    directory: PathBuf,
+
+    /// Extract the files packed by `sync-directory --batch-small-objects`.
+    #[structopt(long)]
+    batch_small_objects: bool,
+
+    /// Sync each file to disk before exiting, so it survives a power loss.
+    #[structopt(long)]
+    fsync: bool,
+
+    /// Also sync each file every time this many bytes were written.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    fsync_interval: Option<u64>,
+
+    /// Apply the file metadata recorded by `sync-directory --preserve`, and
+    /// recreate symbolic links.
+    #[structopt(long)]
+    preserve: bool,
+
+    /// With --preserve, also restore the owners by their numeric IDs. Only
+    /// applied when running as root.
+    #[structopt(long, requires = "preserve")]
+    numeric_owner: bool,
+
+    /// The combined bytes per second of all the downloads, e.g. 10MiB.
+    #[structopt(
+        long,
+        parse(try_from_str = parse_size),
+        conflicts_with_all = &["batch-small-objects", "preserve"]
+    )]
+    max_bandwidth: Option<u64>,
+
+    /// The number of objects downloaded at the same time with
+    /// --max-bandwidth.
+    #[structopt(short, long, default_value = "8", requires = "max-bandwidth")]
+    concurrency: usize,
+
+    /// Whether to display additional information, and a line with the
+    /// request ID as each object is downloaded.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Downloads the objects under a prefix in an Amazon S3 bucket to a local
+/// directory.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `-d DIRECTORY` - The local directory the objects are written to.
+/// * `[-p PREFIX]` - The prefix of the objects to download.
+/// * `[--batch-small-objects]` - Extract the files packed into archive objects.
+/// * `[--fsync]` - Sync each file and its directory entry before exiting.
+/// * `[--fsync-interval SIZE]` - Also sync each file every SIZE bytes.
+/// * `[--preserve]` - Apply the mode and modification time recorded by
+///   `sync-directory --preserve`, and recreate symbolic links.
+/// * `[--numeric-owner]` - Also restore the owners, when running as root.
+/// * `[--max-bandwidth SIZE]` - The combined bytes per second of all the downloads.
+/// * `[-c CONCURRENCY]` - The number of objects downloaded at the same time
+///   with `--max-bandwidth`. The default is 8.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information, and a line with the
+///   request ID of each object downloaded outside an archive.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        prefix,
+        directory,
+        batch_small_objects,
+        fsync,
+        fsync_interval,
+        preserve,
+        numeric_owner,
+        max_bandwidth,
+        concurrency,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Bucket:            {}", &bucket);
+        println!("Prefix:            {}", &prefix);
+        println!("Directory:         {}", directory.display());
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let verbosity = VerbosityConfig::from_flag(verbose);
+    let client = if verbosity.show_request_ids {
+        request_id_client(aws_sdk_s3::config::Builder::from(&shared_config).build())
+    } else {
+        Client::new(&shared_config)
+    };
+
+    let options = FsyncOptions {
+        enabled: fsync,
+        interval: fsync_interval,
+    };
+    if let Some(max_bps) = max_bandwidth {
+        let summary = download_prefix_rate_limited(
+            &client,
+            &bucket,
+            &prefix,
+            &directory,
+            max_bps,
+            concurrency,
+            &options,
+        )
+        .await?;
+        println!(
+            "{}; {} files, limit {}",
+            summary_line("Downloaded", summary.bytes, summary.elapsed),
+            summary.files,
+            format_rate(max_bps as f64)
+        );
+        if summary.directories > 0 {
+            println!(
+                "Created {} directories for folder markers",
+                summary.directories
+            );
+        }
+        print_fsync(&summary.fsync);
+        print_unstable(&summary.unstable);
+        return Ok(());
+    }
+
+    let summary = download_prefix_with_verbosity(
+        &client,
+        &bucket,
+        &prefix,
+        &directory,
+        batch_small_objects,
+        &options,
+        preserve.then(|| RestoreOptions { numeric_owner }).as_ref(),
+        verbosity,
+    )
+    .await?;
+    println!(
+        "Downloaded {} files ({}), {} of them from archives",
+        summary.files,
+        format_size(summary.bytes),
+        summary.unpacked_files
+    );
+    if summary.directories > 0 {
+        println!(
+            "Created {} directories for folder markers",
+            summary.directories
+        );
+    }
+    if summary.symlinks > 0 {
+        println!("Recreated {} symbolic links", summary.symlinks);
+    }
+    for warning in &summary.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    print_fsync(&summary.fsync);
+    print_unstable(&summary.unstable);
+
+    Ok(())
+}
+
+fn print_fsync(stats: &FsyncStats) {
+    if stats.syncs > 0 {
+        println!(
+            "Spent {} in {} fsync calls",
+            format_duration(stats.time),
+            stats.syncs
+        );
+    }
+}
+
+fn print_unstable(keys: &[String]) {
+    if !keys.is_empty() {
+        println!("Skipped {} objects that kept changing", keys.len());
+        for key in keys {
+            println!("  unstable: {}", key);
+        }
+    }
+}
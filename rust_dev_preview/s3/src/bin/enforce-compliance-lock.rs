@@ -0,0 +1,103 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::object_lock::{enforce_compliance_lock_with_options, parse_retain_until};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The prefix of the objects to lock.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// The date until which the objects are locked, such as 2030-01-01.
+    #[structopt(long, parse(try_from_str = parse_retain_until))]
+    retain_until: chrono::DateTime<chrono::Utc>,
+
+    /// Count the objects that would be locked without locking them.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Locks the objects under a prefix in an Amazon S3 bucket in Object Lock
+/// compliance mode until a date.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket, which must have Object Lock enabled.
+/// * `--retain-until DATE` - The date until which the objects are locked,
+///   as `2030-01-01` or `2030-01-01T00:00:00Z`.
+/// * `[-p PREFIX]` - The prefix of the objects to lock.
+///   If not supplied, locks every object in the bucket.
+/// * `[--dry-run]` - Count the objects that would be locked without locking them.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        prefix,
+        retain_until,
+        dry_run,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Bucket:            {}", &bucket);
+        println!("Prefix:            {}", &prefix);
+        println!("Retain until:      {}", retain_until.to_rfc3339());
+        println!("Dry run:           {}", dry_run);
+        println!();
+    }
+
+    eprintln!(
+        "Warning: compliance mode cannot be shortened or removed by anyone, including the \
+         root user. The objects cannot be deleted or overwritten until {}.",
+        retain_until.to_rfc3339()
+    );
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+
+    let updated =
+        enforce_compliance_lock_with_options(&client, &bucket, &prefix, retain_until, dry_run)
+            .await?;
+    if dry_run {
+        println!("Would lock {} objects in compliance mode", updated);
+    } else {
+        println!("Locked {} objects in compliance mode", updated);
+    }
+
+    Ok(())
+}
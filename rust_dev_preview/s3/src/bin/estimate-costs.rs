@@ -0,0 +1,133 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::cost::{estimate_monthly_cost_with, list_all_objects, CostOptions};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// Only include the objects under this prefix.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// The Region whose prices are used. Defaults to the client's Region.
+    #[structopt(long)]
+    pricing_region: Option<String>,
+
+    /// GET requests per object per month.
+    #[structopt(long, default_value = "1")]
+    gets_per_object: f64,
+
+    /// PUT requests per object per month.
+    #[structopt(long, default_value = "0")]
+    puts_per_object: f64,
+
+    /// Fraction of the stored bytes downloaded to the internet per month.
+    #[structopt(long, default_value = "0")]
+    transfer_out_fraction: f64,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Estimates the monthly cost of the objects in an Amazon S3 bucket.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `[-p PREFIX]` - Only include the objects under this prefix.
+/// * `[--pricing-region REGION]` - The Region whose prices are used.
+/// * `[--gets-per-object N]` - GET requests per object per month. The default is 1.
+/// * `[--puts-per-object N]` - PUT requests per object per month. The default is 0.
+/// * `[--transfer-out-fraction F]` - Fraction of the bytes downloaded per month.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        prefix,
+        pricing_region,
+        gets_per_object,
+        puts_per_object,
+        transfer_out_fraction,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+    let client_region = region_provider.region().await.unwrap();
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!("Region:            {}", client_region.as_ref());
+        println!("Bucket:            {}", &bucket);
+        println!("Prefix:            {}", &prefix);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+
+    let objects = list_all_objects(&client, &bucket, &prefix).await?;
+    let pricing_region = pricing_region.unwrap_or_else(|| client_region.as_ref().to_string());
+    let estimate = estimate_monthly_cost_with(
+        &objects,
+        &pricing_region,
+        &CostOptions {
+            gets_per_object_per_month: gets_per_object,
+            puts_per_object_per_month: puts_per_object,
+            transfer_out_fraction,
+        },
+    );
+
+    if estimate.pricing_region != estimate.region {
+        println!(
+            "No prices for {}, using the prices of {}",
+            estimate.region, estimate.pricing_region
+        );
+    }
+    for class in &estimate.unknown_storage_classes {
+        println!("No prices for storage class {}, using STANDARD", class);
+    }
+    println!(
+        "{:<22} {:>10} {:>16} {:>12} {:>12}",
+        "Storage class", "Objects", "Bytes", "Storage", "Requests"
+    );
+    for (class, cost) in &estimate.by_storage_class {
+        println!(
+            "{:<22} {:>10} {:>16} {:>12.2} {:>12.2}",
+            class, cost.objects, cost.bytes, cost.storage, cost.requests
+        );
+    }
+    println!();
+    println!("Storage:        ${:>12.2}", estimate.storage);
+    println!("Requests:       ${:>12.2}", estimate.requests);
+    println!("Data transfer:  ${:>12.2}", estimate.data_transfer);
+    println!("Total / month:  ${:>12.2}", estimate.total);
+    println!();
+    println!("Prices are embedded in this example and may be outdated.");
+    println!("See https://aws.amazon.com/s3/pricing/ for current prices.");
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::types::DateTime;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use chrono::TimeZone;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The object to describe.
+    #[structopt(short, long)]
+    key: String,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+fn http_date(date: &DateTime) -> String {
+    chrono::Utc
+        .timestamp(date.secs(), 0)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+fn show(name: &str, value: Option<&str>) {
+    println!("{:<20} {}", format!("{}:", name), value.unwrap_or("-"));
+}
+
+// Display the headers stored with an object.
+// snippet-start:[s3.rust.head-object]
+async fn show_object(client: &Client, bucket: &str, key: &str) -> Result<(), Error> {
+    let head = client.head_object().bucket(bucket).key(key).send().await?;
+
+    println!("{:<20} {}", "Content-Length:", head.content_length());
+    show("Content-Type", head.content_type());
+    show("ETag", head.e_tag());
+    show(
+        "Last-Modified",
+        head.last_modified().map(http_date).as_deref(),
+    );
+    show("Cache-Control", head.cache_control());
+    show("Content-Encoding", head.content_encoding());
+    show("Content-Disposition", head.content_disposition());
+    show("Content-Language", head.content_language());
+    show("Expires", head.expires().map(http_date).as_deref());
+    if let Some(metadata) = head.metadata() {
+        let mut metadata = metadata.iter().collect::<Vec<_>>();
+        metadata.sort();
+        for (name, value) in metadata {
+            show(&format!("x-amz-meta-{}", name), Some(value));
+        }
+    }
+
+    Ok(())
+}
+// snippet-end:[s3.rust.head-object]
+
+/// Displays the HTTP headers stored with an object in an Amazon S3 bucket,
+/// such as Cache-Control and Expires.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `-k KEY` - The name of the object.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        key,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Bucket:            {}", &bucket);
+        println!("Key:               {}", &key);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+
+    show_object(&client, &bucket, &key).await
+}
@@ -0,0 +1,89 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::ownership::{objects_by_owner_with_concurrency, owner_totals, render_owner_table};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// How many object ACLs are read at the same time.
+    #[structopt(long, default_value = "16")]
+    concurrency: usize,
+
+    /// Whether to display additional information, and the keys of each owner.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Lists the owners of the objects in an Amazon S3 bucket, with the number
+/// of objects and bytes of each, to audit a bucket written by several
+/// accounts. The owner is the canonical user ID in the ACL of each object.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `[--concurrency N]` - How many object ACLs are read at the same time.
+///   Defaults to 16.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information, and the keys of
+///   each owner.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        concurrency,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Bucket:            {}", &bucket);
+        println!("Concurrency:       {}", concurrency);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+
+    let owners = objects_by_owner_with_concurrency(&client, &bucket, concurrency).await?;
+    let totals = owner_totals(&owners);
+    println!("{}", render_owner_table(&totals));
+
+    if verbose {
+        for row in &totals {
+            println!();
+            println!("{}:", row.owner_id);
+            for object in &owners[&row.owner_id] {
+                println!("  {}", object.key().unwrap_or_default());
+            }
+        }
+    }
+
+    Ok(())
+}
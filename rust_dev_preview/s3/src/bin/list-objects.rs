@@ -5,6 +5,7 @@
 
 use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::listing::{list_objects, KeyEncoding, ListOutput};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -17,6 +18,19 @@ struct Opt {
     #[structopt(short, long)]
     bucket: String,
 
+    /// Only list the keys starting with this prefix.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// How S3 encodes the keys: none or url. With url, the JSON and CSV
+    /// outputs keep the keys URL-encoded, safe for any consumer.
+    #[structopt(long, default_value = "none")]
+    encoding_type: KeyEncoding,
+
+    /// The output format: text, json, or csv.
+    #[structopt(long, default_value = "text")]
+    output: ListOutput,
+
     /// Whether to display additional information.
     #[structopt(short, long)]
     verbose: bool,
@@ -35,12 +49,39 @@ async fn show_objects(client: &Client, bucket: &str) -> Result<(), Error> {
 }
 // snippet-end:[s3.rust.list-objects]
 
+// Lists the objects under a prefix, with every page, in the format of
+// `output`. Keys can hold newlines; the text output escapes them to keep
+// one key per line.
+async fn show_listing(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    encoding_type: KeyEncoding,
+    output: ListOutput,
+) -> Result<(), Error> {
+    let listing = list_objects(client, bucket, prefix, encoding_type).await?;
+    match output {
+        ListOutput::Text => print!("{}", listing.to_text()),
+        ListOutput::Json => println!("{}", listing.to_json()),
+        ListOutput::Csv => print!("{}", listing.to_csv().await?),
+    }
+    Ok(())
+}
+
 /// Lists the objects in an Amazon S3 bucket.
+///
+/// Without options, prints the keys of the first page as S3 returns them.
+/// Keys can hold newlines and other control characters: with a prefix, an
+/// encoding type, or an output format, the text output escapes them, one key
+/// per line, and the JSON and CSV outputs, meant for other systems, quote
+/// them properly, and with `--encoding-type url` carry them URL-encoded as S3
+/// lists them.
 /// # Arguments
 ///
 /// * `-b BUCKET` - The name of the bucket.
-/// * `-o OBJECT` - The name of the object in the bucket.
-/// * `-n NAME` - The name of person.
+/// * `[-p PREFIX]` - Only list the keys starting with PREFIX.
+/// * `[--encoding-type none|url]` - How S3 encodes the keys.
+/// * `[--output text|json|csv]` - The output format. The default is text.
 /// * `[-r REGION]` - The Region in which the client is created.
 ///   If not supplied, uses the value of the **AWS_REGION** environment variable.
 ///   If the environment variable is not set, defaults to **us-west-2**.
@@ -52,6 +93,9 @@ async fn main() -> Result<(), Error> {
     let Opt {
         region,
         bucket,
+        prefix,
+        encoding_type,
+        output,
         verbose,
     } = Opt::from_args();
 
@@ -59,20 +103,26 @@ async fn main() -> Result<(), Error> {
         .or_default_provider()
         .or_else(Region::new("us-west-2"));
 
-    println!();
+    // Machine outputs keep stdout to themselves.
+    if output == ListOutput::Text {
+        println!();
+    }
 
     if verbose {
-        println!("S3 client version: {}", PKG_VERSION);
-        println!(
+        eprintln!("S3 client version: {}", PKG_VERSION);
+        eprintln!(
             "Region:            {}",
             region_provider.region().await.unwrap().as_ref()
         );
-        println!("Bucket:            {}", &bucket);
-        println!();
+        eprintln!("Bucket:            {}", &bucket);
+        eprintln!();
     }
 
     let shared_config = aws_config::from_env().region(region_provider).load().await;
     let client = Client::new(&shared_config);
 
-    show_objects(&client, &bucket).await
+    if output == ListOutput::Text && prefix.is_empty() && encoding_type == KeyEncoding::None {
+        return show_objects(&client, &bucket).await;
+    }
+    show_listing(&client, &bucket, &prefix, encoding_type, output).await
 }
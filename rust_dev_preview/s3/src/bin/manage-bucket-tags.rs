@@ -0,0 +1,120 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::bucket_tags::{
+    get_bucket_tags, merge_bucket_tags, remove_bucket_tags, replace_bucket_tags,
+};
+use s3_service::cli::parse_key_value;
+use std::collections::HashMap;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// A tag (key=value) to add.
+    #[structopt(long, parse(try_from_str = parse_key_value))]
+    add: Vec<(String, String)>,
+
+    /// The key of a tag to remove.
+    #[structopt(long)]
+    remove: Vec<String>,
+
+    /// Replace all tags with the --add tags instead of merging them. Without
+    /// --add, removes all tags.
+    #[structopt(long)]
+    replace_all: bool,
+
+    /// List the tags after applying the changes.
+    #[structopt(long)]
+    list: bool,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Adds, removes, replaces, and lists the tags on an Amazon S3 bucket.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `[--add KEY=VALUE]` - A tag to add. Can be repeated.
+/// * `[--remove KEY]` - The key of a tag to remove. Can be repeated.
+/// * `[--replace-all]` - Replace all existing tags with the `--add` tags.
+///   Without `--add`, removes all tags.
+/// * `[--list]` - List the tags on the bucket.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        add,
+        remove,
+        replace_all,
+        list,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Bucket:            {}", &bucket);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+
+    let tags: HashMap<String, String> = add.iter().cloned().collect();
+    if replace_all {
+        replace_bucket_tags(&client, &bucket, tags).await?;
+        println!("Replaced all tags.");
+    } else if !tags.is_empty() {
+        merge_bucket_tags(&client, &bucket, tags).await?;
+        println!("Added {} tags.", add.len());
+    }
+    if !remove.is_empty() {
+        remove_bucket_tags(&client, &bucket, &remove).await?;
+        println!("Removed {} tags.", remove.len());
+    }
+
+    if list {
+        let mut tags = get_bucket_tags(&client, &bucket)
+            .await?
+            .into_iter()
+            .collect::<Vec<_>>();
+        tags.sort();
+        for (key, value) in &tags {
+            println!("{} = {}", key, value);
+        }
+        println!();
+        println!("Found {} tags.", tags.len());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,127 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_sdk_s3::{Error, PKG_VERSION};
+use s3_service::connect::{connect, connect_sqs, ConnectOptions};
+use s3_service::pipeline::{
+    consume_queue, parse_visibility_timeout, ConsumerOptions, MAX_RECEIVE_MESSAGES,
+};
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The profile in the .aws/credentials file.
+    #[structopt(long)]
+    profile: Option<String>,
+
+    /// The URL of the SQS queue, as printed by pipeline-setup.
+    #[structopt(short, long)]
+    queue_url: String,
+
+    /// How long a received message is hidden, and how much it is extended by
+    /// while its object is processed.
+    #[structopt(long, parse(try_from_str = parse_visibility_timeout))]
+    visibility_timeout: Option<Duration>,
+
+    /// Messages received and processed at a time, at most 10.
+    #[structopt(short, long)]
+    concurrency: Option<i32>,
+
+    /// Stop after this many receives in a row, of up to 20 seconds each,
+    /// found the queue empty. Runs until interrupted if not supplied.
+    #[structopt(long)]
+    max_empty_receives: Option<u32>,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Receives upload notifications from an SQS queue, downloads each announced
+/// object, and prints its SHA-256 as a JSON line. A message is deleted once
+/// its object is processed, and its visibility is extended while it is.
+/// # Arguments
+///
+/// * `-q QUEUE-URL` - The URL of the SQS queue.
+/// * `[--visibility-timeout DURATION]` - How long a received message is
+///   hidden. Between 2s and 12h; the default is 1m.
+/// * `[-c CONCURRENCY]` - Messages received and processed at a time, at most 10.
+///   The default is 10.
+/// * `[--max-empty-receives N]` - Stop after N receives in a row found the
+///   queue empty.
+/// * `[--profile PROFILE]` - The profile in the .aws/credentials file.
+/// * `[-r REGION]` - The Region in which the clients are created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        profile,
+        queue_url,
+        visibility_timeout,
+        concurrency,
+        max_empty_receives,
+        verbose,
+    } = Opt::from_args();
+
+    let defaults = ConsumerOptions::default();
+    let max_messages = concurrency.unwrap_or(defaults.max_messages);
+    if !(1..=MAX_RECEIVE_MESSAGES).contains(&max_messages) {
+        return Err(Error::Unhandled(Box::from(format!(
+            "--concurrency must be between 1 and {}",
+            MAX_RECEIVE_MESSAGES
+        ))));
+    }
+    let consumer_options = ConsumerOptions {
+        visibility_timeout: visibility_timeout.unwrap_or(defaults.visibility_timeout),
+        max_messages,
+        max_empty_receives,
+        ..defaults
+    };
+
+    if verbose {
+        eprintln!("S3 client version: {}", PKG_VERSION);
+        eprintln!("Queue:             {}", queue_url);
+        eprintln!(
+            "Visibility:        {} s",
+            consumer_options.visibility_timeout.as_secs()
+        );
+    }
+
+    let options = ConnectOptions {
+        region,
+        profile,
+        ..Default::default()
+    };
+    let client = connect(&options).await?;
+    let sqs_client = connect_sqs(&options).await;
+    let summary = consume_queue(
+        &client,
+        &sqs_client,
+        &queue_url,
+        &consumer_options,
+        |object| println!("{}", serde_json::to_string(object).unwrap()),
+    )
+    .await?;
+    eprintln!(
+        "Received {} messages: {} objects processed, {} skipped, {} failed, {} not deleted",
+        summary.received,
+        summary.processed.len(),
+        summary.skipped.len(),
+        summary.failed.len(),
+        summary.undeleted.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_sdk_s3::{Error, PKG_VERSION};
+use s3_service::connect::{connect, connect_sns, ConnectOptions};
+use s3_service::notify::{notify_sns_after_upload, UploadPayload};
+use s3_service::upload::{upload_auto, AutoUploadConfig};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The profile in the .aws/credentials file.
+    #[structopt(long)]
+    profile: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The ARN of the SNS topic the uploads are announced on.
+    #[structopt(short, long)]
+    topic_arn: String,
+
+    /// Prepended to the file names to form the keys.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// The files to upload.
+    #[structopt(parse(from_os_str), required = true)]
+    files: Vec<PathBuf>,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Uploads files and announces each one on an SNS topic, for the consumers of
+/// the queue subscribed to it.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `-t TOPIC-ARN` - The ARN of the SNS topic, as printed by pipeline-setup.
+/// * `[-p PREFIX]` - Prepended to the file names to form the keys.
+/// * `FILE ...` - The files to upload.
+/// * `[--profile PROFILE]` - The profile in the .aws/credentials file.
+/// * `[-r REGION]` - The Region in which the clients are created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        profile,
+        bucket,
+        topic_arn,
+        prefix,
+        files,
+        verbose,
+    } = Opt::from_args();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!("Bucket:            {}", bucket);
+        println!("Topic:             {}", topic_arn);
+        println!();
+    }
+
+    let options = ConnectOptions {
+        region,
+        profile,
+        ..Default::default()
+    };
+    let client = connect(&options).await?;
+    let sns_client = connect_sns(&options).await;
+    let config = AutoUploadConfig::default();
+    for file in &files {
+        let name = file
+            .file_name()
+            .ok_or_else(|| {
+                Error::Unhandled(Box::from(format!("{} is not a file", file.display())))
+            })?
+            .to_string_lossy();
+        let key = format!("{}{}", prefix, name);
+        let size = std::fs::metadata(file)
+            .map_err(|err| Error::Unhandled(Box::new(err)))?
+            .len();
+        let e_tag = upload_auto(&client, &bucket, &key, &file.to_string_lossy(), config).await?;
+        let payload = UploadPayload {
+            bucket: bucket.clone(),
+            key: key.clone(),
+            e_tag,
+            size,
+        };
+        // The object is uploaded, so a failed notification does not stop the
+        // other files.
+        match notify_sns_after_upload(&sns_client, &topic_arn, payload).await {
+            Ok(message_id) => println!("Uploaded {} ({} bytes), message {}", key, size, message_id),
+            Err(err) => eprintln!(
+                "Warning: {} was uploaded, but not announced on {}: {}",
+                key, topic_arn, err
+            ),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_sdk_s3::{Error, PKG_VERSION};
+use s3_service::connect::{connect_sns, connect_sqs, ConnectOptions};
+use s3_service::pipeline::{parse_visibility_timeout, setup_pipeline, DEFAULT_VISIBILITY_TIMEOUT};
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The profile in the .aws/credentials file.
+    #[structopt(long)]
+    profile: Option<String>,
+
+    /// The name of the SNS topic the uploads are announced on.
+    #[structopt(short, long)]
+    topic: String,
+
+    /// The name of the SQS queue the consumers read.
+    #[structopt(short, long)]
+    queue: String,
+
+    /// How long a received message is hidden from the other consumers.
+    #[structopt(long, parse(try_from_str = parse_visibility_timeout))]
+    visibility_timeout: Option<Duration>,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Creates the SNS topic and the SQS queue of an upload processing pipeline,
+/// lets the topic send to the queue, and subscribes the queue to the topic
+/// with raw message delivery. Prints the ARNs and the queue URL as JSON.
+/// Run it once; running it again returns the existing resources.
+/// # Arguments
+///
+/// * `-t TOPIC` - The name of the SNS topic.
+/// * `-q QUEUE` - The name of the SQS queue.
+/// * `[--visibility-timeout DURATION]` - How long a received message is
+///   hidden from the other consumers. Between 2s and 12h; the default is 1m.
+/// * `[--profile PROFILE]` - The profile in the .aws/credentials file.
+/// * `[-r REGION]` - The Region in which the clients are created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        profile,
+        topic,
+        queue,
+        visibility_timeout,
+        verbose,
+    } = Opt::from_args();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!("Topic:             {}", topic);
+        println!("Queue:             {}", queue);
+        println!();
+    }
+
+    let options = ConnectOptions {
+        region,
+        profile,
+        ..Default::default()
+    };
+    let resources = setup_pipeline(
+        &connect_sns(&options).await,
+        &connect_sqs(&options).await,
+        &topic,
+        &queue,
+        visibility_timeout.unwrap_or(DEFAULT_VISIBILITY_TIMEOUT),
+    )
+    .await?;
+    println!("{}", serde_json::to_string_pretty(&resources).unwrap());
+
+    Ok(())
+}
@@ -0,0 +1,75 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::cli::parse_duration;
+use s3_service::replication::replication_dashboard;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the source bucket.
+    #[structopt(short, long)]
+    source: String,
+
+    /// How often the metrics are refreshed.
+    #[structopt(long, default_value = "30s", parse(try_from_str = parse_duration))]
+    interval: Duration,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Displays the replication latency and pending bytes of every replication
+/// destination of a bucket as a table redrawn in place, until interrupted.
+/// Latencies over 5 minutes and more than 1 GB pending are shown in red.
+/// # Arguments
+///
+/// * `-s SOURCE` - The name of the bucket with the replication configuration.
+/// * `[--interval DURATION]` - How often the metrics are refreshed. The default is 30s.
+/// * `[-r REGION]` - The Region in which the clients are created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        source,
+        interval,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Source bucket:     {}", &source);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+    let cw_client = aws_sdk_cloudwatch::Client::new(&shared_config);
+
+    replication_dashboard(&client, &source, &cw_client, interval).await
+}
@@ -0,0 +1,132 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::cli::parse_duration;
+use s3_service::replication::{
+    enable_replication_time_control, get_replication_metrics, ReplicationMetrics, RTC_MINUTES,
+};
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the source bucket.
+    #[structopt(short, long)]
+    source: String,
+
+    /// The name of the destination bucket.
+    #[structopt(short, long)]
+    destination: String,
+
+    /// Enable S3 RTC on the replication rule before monitoring.
+    #[structopt(long)]
+    enable: bool,
+
+    /// How often the metrics are refreshed.
+    #[structopt(long, default_value = "30s", parse(try_from_str = parse_duration))]
+    interval: Duration,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+fn show(value: Option<f64>) -> String {
+    value
+        .map(|v| format!("{:.0}", v))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Draws the latency as a bar against the 15-minute RTC threshold.
+fn latency_bar(metrics: &ReplicationMetrics) -> String {
+    const WIDTH: usize = 30;
+    let threshold = f64::from(RTC_MINUTES * 60);
+    let filled = metrics
+        .latency_seconds
+        .map(|l| ((l / threshold) * WIDTH as f64).round() as usize)
+        .unwrap_or(0)
+        .min(WIDTH);
+    format!("[{}{}]", "#".repeat(filled), " ".repeat(WIDTH - filled))
+}
+
+/// Enables S3 Replication Time Control between two buckets and displays the
+/// replication lag, refreshed periodically, until interrupted.
+/// # Arguments
+///
+/// * `-s SOURCE` - The name of the source bucket.
+/// * `-d DESTINATION` - The name of the destination bucket.
+/// * `[--enable]` - Enable S3 RTC on the existing replication rule first.
+/// * `[--interval DURATION]` - How often the metrics are refreshed. The default is 30s.
+/// * `[-r REGION]` - The Region in which the clients are created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        source,
+        destination,
+        enable,
+        interval,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version:  {}", PKG_VERSION);
+        println!(
+            "Region:             {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Source bucket:      {}", &source);
+        println!("Destination bucket: {}", &destination);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+    let cw_client = aws_sdk_cloudwatch::Client::new(&shared_config);
+
+    if enable {
+        let replica_arn = format!("arn:aws:s3:::{}", destination);
+        enable_replication_time_control(&client, &source, &replica_arn).await?;
+        println!("Enabled S3 RTC for replication to {}", replica_arn);
+        println!();
+    }
+
+    println!(
+        "{:<20} {:>12} {:>16} {:>12}  latency / {} min",
+        "Time (UTC)", "Latency (s)", "Bytes pending", "Ops pending", RTC_MINUTES
+    );
+    loop {
+        let time = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S");
+        match get_replication_metrics(&cw_client, &source, &destination).await {
+            Ok(metrics) => println!(
+                "{:<20} {:>12} {:>16} {:>12}  {}",
+                time,
+                show(metrics.latency_seconds),
+                show(metrics.bytes_pending),
+                show(metrics.operations_pending),
+                latency_bar(&metrics)
+            ),
+            Err(err) => eprintln!("{:<20} Error getting metrics: {}", time, err),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,132 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::cli::parse_duration;
+use s3_service::restore::{restore_from_deep_archive, wait_for_restore, GlacierRestoreTier};
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key of the archived object.
+    #[structopt(short, long)]
+    key: String,
+
+    /// How many days the restored copy stays available.
+    #[structopt(short, long, default_value = "1")]
+    days: i32,
+
+    /// The retrieval tier: bulk or standard.
+    #[structopt(short, long, default_value = "bulk")]
+    tier: GlacierRestoreTier,
+
+    /// Wait until the restored copy is available.
+    #[structopt(short, long)]
+    wait: bool,
+
+    /// With --wait, how often the object is checked.
+    #[structopt(long, default_value = "15m", parse(try_from_str = parse_duration))]
+    poll_interval: Duration,
+
+    /// With --wait, how long to wait at most. Defaults to the longest the
+    /// tier takes.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    max_wait: Option<Duration>,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Restores an object from S3 Glacier Deep Archive and prints when it
+/// should be available.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `-k KEY` - The key of the archived object.
+/// * `[-d DAYS]` - How many days the restored copy stays available. The default is 1.
+/// * `[-t TIER]` - The retrieval tier, bulk (the default, within 48 hours) or
+///   standard (within 12 hours). Deep Archive does not support expedited.
+/// * `[-w]` - Wait until the restored copy is available.
+/// * `[--poll-interval DURATION]` - How often the object is checked. The default is 15m.
+/// * `[--max-wait DURATION]` - How long to wait at most.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        key,
+        days,
+        tier,
+        wait,
+        poll_interval,
+        max_wait,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Bucket:            {}", &bucket);
+        println!("Key:               {}", &key);
+        println!("Tier:              {:?}", tier);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+
+    restore_from_deep_archive(&client, &bucket, &key, days, tier).await?;
+    // Rejected above for tiers without an estimate.
+    let longest = tier.deep_archive_duration().unwrap_or_default();
+    let by = chrono::Utc::now() + chrono::Duration::from_std(longest).unwrap();
+    println!(
+        "Requested a {:?} restore of {} for {} days",
+        tier, key, days
+    );
+    println!(
+        "It should be available within {} hours, by {}",
+        longest.as_secs() / 3600,
+        by.format("%Y-%m-%d %H:%M UTC")
+    );
+
+    if wait {
+        wait_for_restore(
+            &client,
+            &bucket,
+            &key,
+            poll_interval,
+            max_wait.unwrap_or(longest),
+        )
+        .await?;
+        println!("{} is restored", key);
+    }
+    Ok(())
+}
@@ -0,0 +1,1701 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_sdk_s3::{Error, PKG_VERSION};
+use s3_service::append_upload::{append_upload, AppendOptions};
+use s3_service::bucket_arn::{check_arn_addressing, region_for_arn, BucketArn};
+use s3_service::bucket_encryption::bucket_default_encryption;
+use s3_service::cli::{parse_duration, parse_endpoint_url, parse_size};
+use s3_service::config::{save_part_size_cap, TransferConfig};
+use s3_service::connect::{connect, connect_endpoints, connect_sns, ConnectOptions};
+use s3_service::dir_marker::{check_upload_key, make_dir_marker};
+use s3_service::download::download_into_window_with_fsync;
+use s3_service::durable::{FsyncOptions, FsyncStats};
+use s3_service::endpoint_template::{EndpointTemplate, TemplateStyle};
+use s3_service::error_hints::RenderedError;
+use s3_service::express::check_general_purpose_bucket;
+use s3_service::failover::EndpointPool;
+use s3_service::integrity::{get_integrity_manifest, put_integrity_manifest, UploadDigests};
+use s3_service::manifest::{download_and_verify_with_options, generate_manifest, sha256_window};
+use s3_service::memory_budget::MemoryBudget;
+use s3_service::notify::{notify_sns_after_upload, UploadPayload};
+use s3_service::parallel_download::{
+    download_parallel_with_events, ParallelDownloadOptions, WriteVerify, DEFAULT_PART_SIZE,
+};
+use s3_service::part_capture::PartCapture;
+use s3_service::part_size_cap::{plan_upload_capped, upload_multipart_window_split_with_digests};
+use s3_service::preflight::{
+    run_preflight, PreflightMode, PreflightOptions, PreflightReport, DEFAULT_PREFLIGHT_THRESHOLD,
+};
+use s3_service::progress::ProgressEvent;
+use s3_service::rate_limit::RequestLimiter;
+use s3_service::request_timing::RequestTimings;
+use s3_service::resume::{resume_upload_with_options, ResumeOptions, ResumeVerify};
+use s3_service::self_test::{self_test, SelfTestOptions};
+use s3_service::signing_debug::{DebugSigningMode, SigningDebugger};
+use s3_service::staged_upload::{
+    complete_staged_upload, start_staged_upload, upload_parts_range, PartsRangeOptions,
+    StageManifest,
+};
+use s3_service::units::{format_duration, format_size};
+use s3_service::upload::{
+    check_object_size, parse_expires, upload_chunk_with_digests,
+    upload_multipart_window_with_digests, SourceWindow, UploadHeaders, UploadPlan,
+    UploadPlanOptions, UploadStrategy, DEFAULT_MULTIPART_THRESHOLD,
+};
+use s3_service::upload_status::{upload_status, StatusOptions};
+use s3_service::upload_watch::{find_upload, watch_upload_with_hook, WatchEvent, WatchOptions};
+use s3_service::verbosity::{with_request_id, VerbosityConfig};
+use s3_service::zip_archive::{download_and_extract_zip, upload_as_zip_with_budget};
+use serde::Serialize;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long, global = true)]
+    region: Option<String>,
+
+    /// The profile in the .aws/credentials file.
+    #[structopt(long, global = true)]
+    profile: Option<String>,
+
+    /// The URL of an S3-compatible endpoint. Repeat to fail over to the next
+    /// endpoint when the current one cannot be reached.
+    #[structopt(
+        long,
+        global = true,
+        number_of_values = 1,
+        parse(try_from_str = parse_endpoint_url)
+    )]
+    endpoint_url: Vec<String>,
+
+    /// The URL of a multi-tenant gateway with the bucket as {bucket}, in the
+    /// host name (https://{bucket}.tenant-a.gw.internal) or as the last path
+    /// segment (https://gw.internal/tenant-a/{bucket}); {region} is replaced
+    /// by the Region.
+    #[structopt(
+        long,
+        global = true,
+        conflicts_with = "endpoint-url",
+        parse(try_from_str = EndpointTemplate::parse)
+    )]
+    endpoint_template: Option<EndpointTemplate>,
+
+    /// After a failover, send requests to the first endpoint again once this
+    /// long has passed, e.g. 5m. Without it the current endpoint is kept.
+    #[structopt(long, global = true, parse(try_from_str = parse_duration))]
+    reprobe_interval: Option<Duration>,
+
+    /// A TOML configuration file with default headers by file extension and
+    /// the part size caps of endpoints.
+    #[structopt(long, global = true, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// The local IP address to connect from, to choose the network interface
+    /// on hosts with several. It must be assigned to an interface.
+    #[structopt(long, global = true)]
+    local_address: Option<IpAddr>,
+
+    /// Send at most this many requests per second, retries included, across
+    /// all the requests of the command.
+    #[structopt(long, global = true)]
+    max_requests_per_second: Option<f64>,
+
+    /// Print what the signature of each failed request covered, to debug
+    /// SignatureDoesNotMatch; with =all, of every request.
+    #[structopt(long, global = true, require_equals = true)]
+    debug_signing: Option<Option<DebugSigningMode>>,
+
+    /// Record every attempt to upload this part number, headers and timings
+    /// included, to a JSON file to attach to a support ticket.
+    #[structopt(long, global = true)]
+    capture_part: Option<i32>,
+
+    /// The file the captured part is written to. Defaults to
+    /// part-N-capture.json.
+    #[structopt(long, global = true, parse(from_os_str))]
+    capture_file: Option<PathBuf>,
+
+    /// Record the time to first byte, total time, and connection reuse of
+    /// every part request, and print their percentiles at the end.
+    #[structopt(long, global = true)]
+    request_timings: bool,
+
+    /// The JSON file every timed request is written to, with the
+    /// percentiles. Defaults to request-timings.json.
+    #[structopt(long, global = true, parse(from_os_str))]
+    request_timings_file: Option<PathBuf>,
+
+    /// The memory the part buffers of the command may hold at the same time,
+    /// e.g. 2GiB. Defaults to half of the available memory.
+    #[structopt(long, global = true, parse(try_from_str = parse_size))]
+    memory_limit: Option<u64>,
+
+    /// Upload even to a key ending in /, which the console shows as a
+    /// folder rather than an object.
+    #[structopt(long, global = true)]
+    allow_dir_marker: bool,
+
+    /// Whether to display additional information, and a line with the size,
+    /// time, rate, and request ID of each part uploaded or downloaded.
+    #[structopt(short, long, global = true)]
+    verbose: bool,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Uploads a file, in parts when it is large.
+    Upload(UploadOpt),
+    /// Uploads a directory as a ZIP archive generated on the fly.
+    UploadZip(UploadZipOpt),
+    /// Downloads a ZIP archive and extracts it as it arrives.
+    DownloadZip(DownloadZipOpt),
+    /// Downloads an object in ranges written in parallel.
+    Download(DownloadOpt),
+    /// Downloads an object into a window of an existing file.
+    DownloadWindow(DownloadWindowOpt),
+    /// Writes the SHA-256 manifest of a directory before it is backed up.
+    Manifest(ManifestOpt),
+    /// Downloads the objects of a manifest and checks their SHA-256.
+    DownloadVerify(DownloadVerifyOpt),
+    /// Watches the progress of a multipart upload made elsewhere.
+    WatchUpload(WatchUploadOpt),
+    /// Tells how much of an interrupted multipart upload is already stored.
+    UploadStatus(UploadStatusOpt),
+    /// Resumes an interrupted multipart upload, uploading only what is missing.
+    ResumeUpload(ResumeUploadOpt),
+    /// Uploads a byte range of a file as a window of parts of an upload.
+    UploadParts(UploadPartsOpt),
+    /// Completes an upload from the manifests of the stages that uploaded it.
+    CompleteUpload(CompleteUploadOpt),
+    /// Uploads the bytes appended to a growing file since the last run.
+    AppendUpload(AppendUploadOpt),
+    /// Checks uploads, downloads, and aborts end to end under a scratch prefix.
+    SelfTest(SelfTestOpt),
+    /// Creates an empty folder, shown as such in the console.
+    MakeDir(MakeDirOpt),
+}
+
+impl Command {
+    /// The bucket argument of the command, if it has one.
+    fn bucket_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Command::Upload(opt) => Some(&mut opt.bucket),
+            Command::UploadZip(opt) => Some(&mut opt.bucket),
+            Command::DownloadZip(opt) => Some(&mut opt.bucket),
+            Command::Download(opt) => Some(&mut opt.bucket),
+            Command::DownloadWindow(opt) => Some(&mut opt.bucket),
+            Command::DownloadVerify(opt) => Some(&mut opt.bucket),
+            Command::WatchUpload(opt) => Some(&mut opt.bucket),
+            Command::UploadStatus(opt) => Some(&mut opt.bucket),
+            Command::ResumeUpload(opt) => Some(&mut opt.bucket),
+            Command::UploadParts(opt) => Some(&mut opt.bucket),
+            Command::AppendUpload(opt) => Some(&mut opt.bucket),
+            Command::SelfTest(opt) => Some(&mut opt.bucket),
+            Command::MakeDir(opt) => Some(&mut opt.bucket),
+            Command::Manifest(_) | Command::CompleteUpload(_) => None,
+        }
+    }
+
+    /// The key a file is uploaded to by the command, if it uploads one.
+    fn upload_key(&self) -> Option<&str> {
+        match self {
+            Command::Upload(opt) => Some(&opt.key),
+            Command::UploadZip(opt) => Some(&opt.key),
+            Command::ResumeUpload(opt) => Some(&opt.key),
+            Command::UploadParts(opt) => Some(&opt.key),
+            Command::AppendUpload(opt) => Some(&opt.key),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct MakeDirOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The path of the folder, such as photos/2021/. A trailing / is added
+    /// if missing.
+    #[structopt(short, long)]
+    key: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct ResumeUploadOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key being uploaded.
+    #[structopt(short, long)]
+    key: String,
+
+    /// The upload to resume.
+    #[structopt(short, long)]
+    upload_id: String,
+
+    /// The local file being uploaded.
+    #[structopt(short, long, parse(from_os_str))]
+    file: PathBuf,
+
+    /// How the parts already uploaded are checked: mtime keeps them unless
+    /// the file was modified after the upload started; content compares
+    /// their ETags with the MD5 of the local bytes.
+    #[structopt(long, default_value = "mtime")]
+    resume_verify: ResumeVerify,
+
+    /// Do not read the default encryption of the bucket, for roles without
+    /// s3:GetEncryptionConfiguration.
+    #[structopt(long)]
+    no_preflight_encryption: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct UploadPartsOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key being uploaded.
+    #[structopt(short, long)]
+    key: String,
+
+    /// The upload the parts are added to. A new upload is created, and its
+    /// ID printed, if not supplied.
+    #[structopt(short, long)]
+    upload_id: Option<String>,
+
+    /// The local file the parts are read from.
+    #[structopt(short, long)]
+    file: String,
+
+    /// Upload only the bytes of the file from this offset.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    source_offset: Option<u64>,
+
+    /// Upload only this many bytes of the file. Runs to the end of the file
+    /// if not supplied.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    source_length: Option<u64>,
+
+    /// The size of the parts.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    part_size: Option<u64>,
+
+    /// Added to the part numbers, which otherwise start at 1.
+    #[structopt(long, default_value = "0")]
+    part_number_offset: i32,
+
+    /// Upload even over parts already uploaded with the same numbers.
+    #[structopt(long)]
+    no_collision_check: bool,
+
+    /// The stage manifest the uploaded parts are written to.
+    #[structopt(short, long, parse(from_os_str))]
+    manifest: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct CompleteUploadOpt {
+    /// A stage manifest written by upload-parts. Repeat for each stage.
+    #[structopt(short, long, number_of_values = 1, required = true, parse(from_os_str))]
+    manifest: Vec<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+struct AppendUploadOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key of the object assembled.
+    #[structopt(short, long)]
+    key: String,
+
+    /// The append-only file.
+    #[structopt(short, long, parse(from_os_str))]
+    file: PathBuf,
+
+    /// The manifest of the upload, created on the first run.
+    #[structopt(short, long, parse(from_os_str))]
+    manifest: PathBuf,
+
+    /// The largest part uploaded. Defaults to 8 MiB.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    part_size: Option<u64>,
+
+    /// Upload the bytes carried over as the last part and complete the
+    /// object.
+    #[structopt(long)]
+    finalize: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct SelfTestOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The scratch prefix the test objects are created under.
+    #[structopt(short, long, default_value = "_selftest/")]
+    prefix: String,
+
+    /// The size of the file uploaded. The multipart upload has three parts
+    /// of at least 5 MiB on Amazon S3.
+    #[structopt(long, default_value = "16MiB", parse(try_from_str = parse_size))]
+    size: u64,
+
+    /// How long each stage may take before it fails.
+    #[structopt(long, default_value = "60s", parse(try_from_str = parse_duration))]
+    stage_timeout: Duration,
+
+    /// Print the result as JSON.
+    #[structopt(long)]
+    json: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct UploadStatusOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key being uploaded.
+    #[structopt(short, long)]
+    key: String,
+
+    /// The upload to inspect.
+    #[structopt(short, long)]
+    upload_id: String,
+
+    /// The local file being uploaded.
+    #[structopt(short, long, parse(from_os_str))]
+    file: PathBuf,
+
+    /// The expected transfer rate per second, e.g. 50MiB, to estimate the
+    /// time left.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    rate: Option<u64>,
+
+    /// Check the MD5 of a sample of the uploaded parts against the local
+    /// file, to detect a file changed since the upload started.
+    #[structopt(long)]
+    verify_local: bool,
+
+    /// How many parts `--verify-local` checks.
+    #[structopt(long, default_value = "8")]
+    sample: usize,
+
+    /// Print the status as JSON.
+    #[structopt(long)]
+    json: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct WatchUploadOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key being uploaded.
+    #[structopt(short, long)]
+    key: String,
+
+    /// The upload to watch. Defaults to the newest upload in progress for
+    /// the key.
+    #[structopt(short, long)]
+    upload_id: Option<String>,
+
+    /// How often the parts are listed.
+    #[structopt(long, default_value = "10s", parse(try_from_str = parse_duration))]
+    interval: Duration,
+
+    /// Give up when the upload is still in progress after this long.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    timeout: Option<Duration>,
+
+    /// The size of the file being uploaded, to estimate the time left.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    total_size: Option<u64>,
+
+    /// How progress is shown: live (one line updated in place), lines (one
+    /// line per poll), json (one JSON object per poll), or auto (live on a
+    /// terminal, lines otherwise).
+    #[structopt(long, default_value = "auto")]
+    output: WatchOutput,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WatchOutput {
+    Auto,
+    Live,
+    Lines,
+    Json,
+}
+
+impl std::str::FromStr for WatchOutput {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(WatchOutput::Auto),
+            "live" => Ok(WatchOutput::Live),
+            "lines" => Ok(WatchOutput::Lines),
+            "json" => Ok(WatchOutput::Json),
+            other => Err(format!("Unknown output: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ManifestOpt {
+    /// The directory to hash.
+    #[structopt(short, long, parse(from_os_str))]
+    directory: PathBuf,
+
+    /// The prefix the directory is uploaded under.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// The manifest file to write.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct DownloadVerifyOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The manifest file.
+    #[structopt(short, long, parse(from_os_str))]
+    manifest: PathBuf,
+
+    /// The directory the objects are downloaded to.
+    #[structopt(short, long, parse(from_os_str))]
+    directory: PathBuf,
+
+    /// Also check each object against the integrity manifest stored next to
+    /// it.
+    #[structopt(long)]
+    check_integrity_manifest: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct DownloadOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key of the object.
+    #[structopt(short, long)]
+    key: String,
+
+    /// The file, or block device, the object is written to.
+    #[structopt(short, long, parse(from_os_str))]
+    file: PathBuf,
+
+    /// The size of the ranges downloaded.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    part_size: Option<u64>,
+
+    /// The number of ranges downloaded at the same time.
+    #[structopt(short, long, default_value = "8")]
+    concurrency: usize,
+
+    /// Write into FILE itself instead of FILE.part renamed once complete.
+    #[structopt(long)]
+    write_in_place: bool,
+
+    /// Keep FILE as it is instead of cutting it to the size of the object:
+    /// it must exist and be at least as large, and the bytes past the
+    /// object are left untouched.
+    #[structopt(long, requires = "write-in-place")]
+    no_truncate: bool,
+
+    /// Write with O_DIRECT in blocks of this size, as some block devices
+    /// require, falling back to buffered writes where it is refused.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    alignment: Option<u64>,
+
+    /// How the bytes written are checked: none, sample[:N], or checksum,
+    /// against the integrity manifest stored next to the object.
+    #[structopt(long, default_value = "none")]
+    verify: WriteVerify,
+
+    /// Download the object again, once, when it is overwritten during the
+    /// download.
+    #[structopt(long)]
+    retry_on_change: bool,
+
+    /// Keep the ranges written by an interrupted run, if the object is still
+    /// the same.
+    #[structopt(long)]
+    resume: bool,
+
+    /// Sync FILE, and its directory entry, to disk before exiting, so it
+    /// survives a power loss.
+    #[structopt(long)]
+    fsync: bool,
+
+    /// Also sync FILE every time this many bytes were written.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    fsync_interval: Option<u64>,
+}
+
+#[derive(Debug, StructOpt)]
+struct DownloadWindowOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key of the object.
+    #[structopt(short, long)]
+    key: String,
+
+    /// The existing file the object is written into.
+    #[structopt(short, long, parse(from_os_str))]
+    file: PathBuf,
+
+    /// Where in the file the object is written. The object must fit in the
+    /// file from there.
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_size))]
+    dest_offset: u64,
+
+    /// The ranges of the object fetched at the same time. With 1, the object
+    /// is read in a single request.
+    #[structopt(long, default_value = "1")]
+    split: usize,
+
+    /// Check the bytes written against the integrity manifest stored next to
+    /// the object.
+    #[structopt(long)]
+    check_integrity_manifest: bool,
+
+    /// Sync FILE to disk before exiting, so it survives a power loss.
+    #[structopt(long)]
+    fsync: bool,
+
+    /// Also sync FILE every time this many bytes were written.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    fsync_interval: Option<u64>,
+}
+
+#[derive(Debug, StructOpt)]
+struct UploadZipOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key of the archive.
+    #[structopt(short, long)]
+    key: String,
+
+    /// The directory to archive.
+    #[structopt(short, long, parse(from_os_str))]
+    directory: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct DownloadZipOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key of the archive.
+    #[structopt(short, long)]
+    key: String,
+
+    /// The directory the archive is extracted to.
+    #[structopt(short, long, parse(from_os_str))]
+    directory: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct UploadOpt {
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key of the uploaded object.
+    #[structopt(short, long)]
+    key: String,
+
+    /// The file to upload.
+    #[structopt(short, long)]
+    file: String,
+
+    /// Upload only the bytes of the file from this offset.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    source_offset: Option<u64>,
+
+    /// Upload only this many bytes of the file. Runs to the end of the file
+    /// if not supplied.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    source_length: Option<u64>,
+
+    /// Files of at least this size are uploaded in parts.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    multipart_threshold: Option<u64>,
+
+    /// The minimum size of the parts. Chosen automatically if not supplied.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    part_size: Option<u64>,
+
+    /// The number of parts. Takes precedence over --part-size.
+    #[structopt(long)]
+    parts: Option<usize>,
+
+    /// When the endpoint rejects a part as too large, abort the upload and
+    /// upload again in smaller parts, saving the cap to --config.
+    #[structopt(long)]
+    auto_split_parts: bool,
+
+    /// Check permissions before uploading: on (the default when the flag has
+    /// no value), off, or auto (only for files of at least --preflight-threshold).
+    #[structopt(long)]
+    preflight: Option<Option<PreflightMode>>,
+
+    /// Run the multipart preflight checks on KEY.preflight.
+    #[structopt(long)]
+    preflight_key: bool,
+
+    /// Also check PutObject and DeleteObject with a 1-byte KEY.preflight object.
+    #[structopt(long)]
+    preflight_put: bool,
+
+    /// The file size from which --preflight auto runs the checks.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    preflight_threshold: Option<u64>,
+
+    /// After the upload, store the SHA-256 of the object and of each part
+    /// in KEY.integrity.json.
+    #[structopt(long)]
+    write_integrity_manifest: bool,
+
+    /// Replace an existing KEY.integrity.json instead of failing.
+    #[structopt(long)]
+    overwrite_integrity_manifest: bool,
+
+    /// After the upload, publish its bucket, key, ETag, and size to this
+    /// Amazon SNS topic. A failed notification is a warning, not an error.
+    #[structopt(long)]
+    notify_sns_topic_arn: Option<String>,
+
+    #[structopt(flatten)]
+    headers: HeaderOpt,
+}
+
+/// Headers stored with the object; they take precedence over the
+/// configuration file.
+#[derive(Debug, StructOpt)]
+struct HeaderOpt {
+    /// The Content-Type header.
+    #[structopt(long)]
+    content_type: Option<String>,
+
+    /// The Cache-Control header, such as "max-age=3600".
+    #[structopt(long)]
+    cache_control: Option<String>,
+
+    /// The Content-Encoding header, such as "gzip".
+    #[structopt(long)]
+    content_encoding: Option<String>,
+
+    /// The Content-Disposition header, such as "attachment".
+    #[structopt(long)]
+    content_disposition: Option<String>,
+
+    /// The Content-Language header, such as "en-US".
+    #[structopt(long)]
+    content_language: Option<String>,
+
+    /// The Expires header (RFC 3339 or HTTP date).
+    #[structopt(long, parse(try_from_str = parse_expires))]
+    expires: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl HeaderOpt {
+    fn headers(&self) -> UploadHeaders {
+        UploadHeaders {
+            content_type: self.content_type.clone(),
+            content_disposition: self.content_disposition.clone(),
+            content_encoding: self.content_encoding.clone(),
+            content_language: self.content_language.clone(),
+            cache_control: self.cache_control.clone(),
+            expires: self.expires,
+            ..Default::default()
+        }
+    }
+}
+
+/// Result printed as JSON.
+#[derive(Debug, Serialize)]
+struct UploadResult {
+    bucket: String,
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preflight: Option<PreflightReport>,
+    plan: UploadPlan,
+    /// The window uploaded, with --source-offset or --source-length.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<SourceWindow>,
+    /// The SHA-256 of the window, to compare with the output of
+    /// `download-window`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    e_tag: String,
+    /// The key of the integrity manifest, with --write-integrity-manifest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    integrity_manifest: Option<String>,
+    elapsed_ms: u64,
+    /// The MessageId of the notification, with --notify-sns-topic-arn.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sns_message_id: Option<String>,
+    /// The endpoint in use at the end of the upload.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    endpoint: Option<String>,
+    failovers: u64,
+}
+
+/// Where the configuration of `upload` comes from, and the endpoint its part
+/// size cap is kept under.
+struct UploadConfig<'a> {
+    config: &'a TransferConfig,
+    path: Option<&'a Path>,
+    endpoint: &'a str,
+}
+
+/// Saves the part size cap `found` by `--auto-split-parts` to the
+/// configuration file. The object is already uploaded, so a failure is only
+/// printed.
+fn save_found_cap(upload_config: &UploadConfig<'_>, found: u64) {
+    let path = match upload_config.path {
+        Some(path) => path,
+        None => {
+            eprintln!(
+                "{} accepts parts of at most {} bytes; pass --config FILE to remember it",
+                upload_config.endpoint, found
+            );
+            return;
+        }
+    };
+    match save_part_size_cap(path, upload_config.endpoint, found) {
+        Ok(()) => eprintln!(
+            "Saved the part size cap of {} bytes for {} to {}",
+            found,
+            upload_config.endpoint,
+            path.display()
+        ),
+        Err(err) => eprintln!(
+            "Warning: could not save the part size cap to {}: {}",
+            path.display(),
+            err
+        ),
+    }
+}
+
+async fn upload(
+    endpoints: &EndpointPool,
+    upload_config: UploadConfig<'_>,
+    opt: UploadOpt,
+    verbosity: VerbosityConfig,
+) -> Result<UploadResult, Error> {
+    let window = SourceWindow::for_file(&opt.file, opt.source_offset, opt.source_length)?;
+    let windowed = opt.source_offset.is_some() || opt.source_length.is_some();
+    let size = window.length;
+    check_general_purpose_bucket(&opt.bucket)?;
+    check_object_size(size)?;
+    let config = upload_config.config;
+    let threshold = opt
+        .multipart_threshold
+        .unwrap_or(DEFAULT_MULTIPART_THRESHOLD);
+    let plan_options = UploadPlanOptions {
+        multipart_threshold: threshold,
+        part_size: opt.part_size,
+        num_parts: opt.parts,
+    };
+    let cap = config.part_size_caps.get(upload_config.endpoint).copied();
+    let mut plan = plan_upload_capped(size, &plan_options, cap);
+    if let Some(cap) = cap {
+        eprintln!(
+            "Parts of at most {} bytes for {}, from the configuration file",
+            cap, upload_config.endpoint
+        );
+    }
+    eprintln!(
+        "{} is {}, threshold {}: using {:?} with {} parts of {} (last part {})",
+        opt.file,
+        format_size(size),
+        format_size(threshold),
+        plan.strategy,
+        plan.num_parts,
+        format_size(plan.part_size),
+        format_size(plan.last_part_size)
+    );
+
+    let mode = match opt.preflight {
+        None => PreflightMode::Off,
+        Some(None) => PreflightMode::Always,
+        Some(Some(mode)) => mode,
+    };
+    let preflight_threshold = opt
+        .preflight_threshold
+        .unwrap_or(DEFAULT_PREFLIGHT_THRESHOLD);
+    let preflight = if mode.should_run(size, preflight_threshold) {
+        let options = PreflightOptions {
+            sibling_key: opt.preflight_key,
+            put_object: opt.preflight_put,
+        };
+        let client = endpoints.client().1;
+        let report = run_preflight(&client, &opt.bucket, &opt.key, &options).await;
+        if !report.passed {
+            let output = serde_json::json!({
+                "bucket": opt.bucket,
+                "key": opt.key,
+                "preflight": report,
+            });
+            println!("{}", serde_json::to_string_pretty(&output).unwrap());
+            return Err(Error::Unhandled(Box::from(format!(
+                "Preflight failed, nothing was uploaded: {}",
+                report.failures().join("; ")
+            ))));
+        }
+        Some(report)
+    } else {
+        None
+    };
+
+    let headers = opt
+        .headers
+        .headers()
+        .or(&config.headers.headers_for(&opt.key));
+    // The manifest is hashed from the bytes the upload sends.
+    let digests = if opt.write_integrity_manifest {
+        Some(UploadDigests::new())
+    } else {
+        None
+    };
+    let start = Instant::now();
+    let e_tag = match plan.strategy {
+        UploadStrategy::PutObject => {
+            let (e_tag, request_id) = with_request_id(upload_chunk_with_digests(
+                endpoints,
+                &opt.bucket,
+                &opt.key,
+                &opt.file,
+                window.offset,
+                window.length,
+                Some(headers),
+                digests.as_ref(),
+            ))
+            .await;
+            let e_tag = e_tag?;
+            verbosity.report(
+                "part",
+                "uploaded",
+                1,
+                1,
+                window.length,
+                start.elapsed(),
+                request_id.as_deref(),
+            );
+            e_tag
+        }
+        UploadStrategy::Multipart if opt.auto_split_parts => {
+            let split = upload_multipart_window_split_with_digests(
+                endpoints,
+                &opt.bucket,
+                &opt.key,
+                &opt.file,
+                window,
+                &plan_options,
+                cap,
+                Some(headers),
+                verbosity,
+                digests.as_ref(),
+            )
+            .await?;
+            if let Some(found) = split.cap.filter(|found| Some(*found) != cap) {
+                save_found_cap(&upload_config, found);
+            }
+            plan = split.plan;
+            split.e_tag
+        }
+        UploadStrategy::Multipart => {
+            upload_multipart_window_with_digests(
+                endpoints,
+                &opt.bucket,
+                &opt.key,
+                &opt.file,
+                window,
+                plan.num_parts,
+                None,
+                Some(headers),
+                verbosity,
+                digests.as_ref(),
+            )
+            .await?
+        }
+    };
+    let not_stored = |err: String| {
+        Error::Unhandled(Box::from(format!(
+            "{} was uploaded, but not its integrity manifest: {}",
+            opt.key, err
+        )))
+    };
+    let manifest = match &digests {
+        Some(digests) => {
+            let manifest = digests
+                .manifest(&opt.key, window.length)
+                .map_err(not_stored)?;
+            let key = put_integrity_manifest(
+                &endpoints.client().1,
+                &opt.bucket,
+                &manifest,
+                opt.overwrite_integrity_manifest,
+            )
+            .await
+            .map_err(|err| not_stored(err.to_string()))?;
+            Some((manifest, key))
+        }
+        None => None,
+    };
+    let sha256 = match &manifest {
+        // The manifest already hashed the bytes sent.
+        Some((manifest, _)) if windowed => Some(manifest.sha256.clone()),
+        _ if windowed => {
+            let digest = sha256_window(Path::new(&opt.file), window)
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+            Some(digest)
+        }
+        _ => None,
+    };
+    let integrity_manifest = manifest.map(|(_, key)| key);
+    Ok(UploadResult {
+        bucket: opt.bucket,
+        key: opt.key,
+        preflight,
+        plan,
+        source: windowed.then(|| window),
+        sha256,
+        e_tag,
+        integrity_manifest,
+        elapsed_ms: start.elapsed().as_millis() as u64,
+        sns_message_id: None,
+        endpoint: endpoints
+            .has_alternatives()
+            .then(|| endpoints.current_endpoint().to_string()),
+        failovers: endpoints.failovers(),
+    })
+}
+
+/// Watches an upload until it ends, printing each poll as `opt.output` says.
+/// An aborted upload is an error.
+/// Prints the time spent in `fsync`, if any, to stderr.
+fn print_fsync(stats: &FsyncStats) {
+    if stats.syncs > 0 {
+        eprintln!(
+            "Spent {} in {} fsync calls",
+            format_duration(stats.time),
+            stats.syncs
+        );
+    }
+}
+
+async fn watch(client: &aws_sdk_s3::Client, opt: WatchUploadOpt) -> Result<(), Error> {
+    let upload = find_upload(client, &opt.bucket, &opt.key, opt.upload_id.as_deref()).await?;
+    let output = match opt.output {
+        WatchOutput::Auto if atty::is(atty::Stream::Stdout) => WatchOutput::Live,
+        WatchOutput::Auto => WatchOutput::Lines,
+        output => output,
+    };
+    if output != WatchOutput::Json {
+        println!("Watching upload {} of {}", upload.upload_id, opt.key);
+    }
+    let options = WatchOptions {
+        interval: opt.interval,
+        timeout: opt.timeout,
+        total_size: opt.total_size,
+    };
+    let event = watch_upload_with_hook(client, &opt.bucket, &opt.key, &upload, &options, |event| {
+        match output {
+            WatchOutput::Json => println!("{}", serde_json::to_string(event).unwrap()),
+            WatchOutput::Live => {
+                // Clears the line and rewrites it; the last event ends it.
+                print!("\r\x1b[2K{}", event.to_text());
+                if event.is_over() {
+                    println!();
+                }
+                let _ = std::io::stdout().flush();
+            }
+            _ => println!(
+                "{} {}",
+                chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+                event.to_text()
+            ),
+        }
+    })
+    .await?;
+    match event {
+        WatchEvent::Aborted => Err(Error::Unhandled(Box::from(format!(
+            "The upload {} of {} was aborted",
+            upload.upload_id, opt.key
+        )))),
+        _ => Ok(()),
+    }
+}
+
+/// Transfers files to and from Amazon S3 or an S3-compatible endpoint.
+///
+/// ## Usage
+/// ```
+/// s3-transfer [--endpoint-url URL ...] [--reprobe-interval DURATION] \
+///   [--config FILE] [--local-address IP] [--profile PROFILE] [-r REGION] [-v] \
+///   upload -b BUCKET -k KEY -f FILE [--source-offset SIZE] [--source-length SIZE] \
+///   [--multipart-threshold SIZE] [--part-size SIZE | --parts N] [--auto-split-parts] \
+///   [--preflight [on|off|auto] [--preflight-key] [--preflight-put] \
+///    [--preflight-threshold SIZE]] \
+///   [--write-integrity-manifest [--overwrite-integrity-manifest]] \
+///   [--notify-sns-topic-arn ARN] \
+///   [--content-type VALUE] [--cache-control VALUE] [--content-encoding VALUE] \
+///   [--content-disposition VALUE] [--content-language VALUE] [--expires DATE]
+/// s3-transfer [--endpoint-url URL ...] [--reprobe-interval DURATION] \
+///   [--config FILE] [--local-address IP] [--profile PROFILE] [-r REGION] [-v] \
+///   upload-zip -b BUCKET -k KEY -d DIRECTORY
+/// s3-transfer [--endpoint-url URL ...] [--reprobe-interval DURATION] \
+///   [--config FILE] [--local-address IP] [--profile PROFILE] [-r REGION] [-v] \
+///   download-zip -b BUCKET -k KEY -d DIRECTORY
+/// s3-transfer [--endpoint-url URL ...] [--reprobe-interval DURATION] \
+///   [--local-address IP] [--profile PROFILE] [-r REGION] [-v] \
+///   download -b BUCKET -k KEY -f FILE [--part-size SIZE] [-c CONCURRENCY] \
+///   [--write-in-place [--no-truncate]] [--alignment SIZE] [--verify none|sample[:N]|checksum] \
+///   [--retry-on-change] [--resume] [--memory-limit SIZE]
+/// s3-transfer [--endpoint-url URL ...] [--reprobe-interval DURATION] \
+///   [--local-address IP] [--profile PROFILE] [-r REGION] [-v] \
+///   download-window -b BUCKET -k KEY -f FILE [--dest-offset SIZE] [--split N] \
+///   [--check-integrity-manifest]
+/// s3-transfer manifest -d DIRECTORY [-p PREFIX] -o MANIFEST
+/// s3-transfer [--endpoint-url URL ...] [--reprobe-interval DURATION] \
+///   [--local-address IP] [--profile PROFILE] [-r REGION] [-v] \
+///   download-verify -b BUCKET -m MANIFEST -d DIRECTORY [--check-integrity-manifest]
+/// s3-transfer [--endpoint-url URL ...] [--local-address IP] [--profile PROFILE] \
+///   [-r REGION] [-v] watch-upload -b BUCKET -k KEY [-u UPLOAD_ID] [--interval DURATION] \
+///   [--timeout DURATION] [--total-size SIZE] [--output auto|live|lines|json]
+/// s3-transfer [--endpoint-url URL ...] [--local-address IP] [--profile PROFILE] \
+///   [-r REGION] [-v] upload-status -b BUCKET -k KEY -u UPLOAD_ID -f FILE [--rate SIZE] \
+///   [--verify-local [--sample N]] [--json]
+/// s3-transfer [--endpoint-url URL ...] [--local-address IP] [--profile PROFILE] \
+///   [-r REGION] [-v] upload-parts -b BUCKET -k KEY [-u UPLOAD_ID] -f FILE \
+///   [--source-offset SIZE] [--source-length SIZE] [--part-size SIZE] \
+///   [--part-number-offset N] [--no-collision-check] -m MANIFEST
+/// s3-transfer [--endpoint-url URL ...] [--local-address IP] [--profile PROFILE] \
+///   [-r REGION] [-v] complete-upload -m MANIFEST ...
+/// s3-transfer [--endpoint-url URL ...] [--local-address IP] [--profile PROFILE] \
+///   [-r REGION] [-v] append-upload -b BUCKET -k KEY -f FILE -m MANIFEST \
+///   [--part-size SIZE] [--finalize]
+/// s3-transfer [--endpoint-url URL ...] [--local-address IP] [--profile PROFILE] \
+///   [-r REGION] [-v] make-dir -b BUCKET -k FOLDER
+/// ```
+///
+/// `--endpoint-template TEMPLATE` can replace `--endpoint-url` in any of them
+/// to reach a multi-tenant gateway, whose URL for the bucket holds it as
+/// `{bucket}` in the host name or the last path segment.
+///
+/// With `--source-offset` and `--source-length`, `upload` sends only that
+/// window of the file and reports its SHA-256; `download-window` writes an
+/// object back into a window of an existing file and reports the SHA-256 of
+/// the bytes written. Windows extending past the end of the file are
+/// rejected. With `--split N`, `download-window` fetches N ranges of the
+/// object at a time and writes them in order; an endpoint that ignores
+/// Range fails the download.
+///
+/// Some S3-compatible endpoints reject parts well below 5 GiB with
+/// `EntityTooLarge`. With `--auto-split-parts`, `upload` then aborts the
+/// upload and starts again in parts of at most half the rejected size, and
+/// saves the size that worked to the `[part_size_caps]` section of
+/// `--config`, by `--endpoint-template` or the first `--endpoint-url`, so
+/// that later uploads to that endpoint start with parts within it.
+/// `upload-parts` cannot change its part numbers and fails instead, naming a
+/// smaller `--part-size` to use.
+///
+/// `--write-integrity-manifest` stores the SHA-256 of the object and of each
+/// part in `KEY.integrity.json`, failing if it already exists unless
+/// `--overwrite-integrity-manifest` is given. `--check-integrity-manifest`
+/// checks the downloaded bytes against it; a mismatch exits with code 1.
+///
+/// `BUCKET` can be an access point ARN, an S3 on Outposts access point ARN,
+/// or a bucket ARN. Without `-r`, the client is created in the Region of the
+/// ARN; access point ARNs are rejected with `--endpoint-url`.
+///
+/// `--notify-sns-topic-arn` publishes the bucket, key, ETag, and size of the
+/// uploaded object to an SNS topic, and adds its `sns_message_id` to the
+/// result. A notification that still fails after three attempts is printed
+/// as a warning; the upload is not failed for it.
+///
+/// `download` reads the object in ranges written in parallel, through
+/// `FILE.part` unless `--write-in-place` is given. With `--no-truncate`
+/// the existing file or block device is written without being resized,
+/// and must hold the object. A failed verification exits with code 1.
+///
+/// The results of `upload`, `download`, `download-window`, and
+/// `download-verify` are printed as JSON.
+/// `download-verify` exits with code 1 when an object is missing or does not
+/// match the manifest.
+///
+/// `watch-upload` polls ListParts until the upload is completed or aborted,
+/// printing the parts and bytes uploaded and the rate since the previous
+/// poll; it exits with code 1 when the upload was aborted.
+///
+/// `upload-status` matches the parts of an upload against the local file and
+/// prints the bytes and percentage uploaded, the missing parts, and the time
+/// left at `--rate`. With `--verify-local` it exits with code 1 when a
+/// sampled part no longer matches the file.
+///
+/// `resume-upload` uploads the parts of an interrupted upload that are
+/// missing or cannot be kept, and completes it. With `--resume-verify
+/// content` a part is kept when its ETag matches the MD5 of the local
+/// bytes, even if the file was regenerated since, unless the default
+/// encryption of the bucket is SSE-KMS; `--no-preflight-encryption` skips
+/// reading it.
+///
+/// `upload-parts` uploads a window of a file as parts numbered from
+/// `--part-number-offset` + 1 and writes them to a stage manifest; it
+/// refuses parts past 10,000 and, unless `--no-collision-check` is given,
+/// parts already uploaded. `complete-upload` completes the upload from the
+/// manifests of all the stages, once their part numbers are dense from 1.
+///
+/// `append-upload` uploads the bytes appended to an append-only file since
+/// its previous run as the next parts of one upload, recorded in MANIFEST.
+/// Fewer than 5 MiB are carried in the manifest to the next run, and
+/// `--finalize` uploads them as the last part and completes the object.
+///
+/// `make-dir` creates the empty `FOLDER/` object, with the Content-Type
+/// `application/x-directory`, that the console shows as a folder. `upload`,
+/// `upload-zip`, `resume-upload`, `upload-parts`, and `append-upload` refuse
+/// a key ending in `/`, which would hide the file under such a folder,
+/// unless `--allow-dir-marker` is given.
+///
+/// `self-test` uploads a generated file with PutObject and with a
+/// three-part multipart upload, downloads both objects whole and in
+/// ranges, checks that an aborted upload is no longer listed, and deletes
+/// what it created. It prints PASS or FAIL per stage and exits with code 1
+/// if any stage failed or took longer than `--stage-timeout`.
+///
+/// `--capture-part N` records the request and response headers, timings,
+/// and retries of each attempt to upload part N to `--capture-file`, with
+/// the credentials, signature, and SSE-C keys redacted.
+///
+/// `--request-timings` times every attempt to send a part: whether it
+/// opened a new connection and how long that took, the time to the
+/// response headers, and the total time. The p50, p95, and p99 are printed
+/// at the end, and every attempt is written to `--request-timings-file`.
+///
+/// `--memory-limit SIZE` caps the memory the part buffers of `download`
+/// and `upload-zip` hold at the same time, half of the available memory by
+/// default: a range waits for memory rather than being allocated past the
+/// limit. A warning
+/// suggests a lower concurrency or part size when they cannot all fit, and
+/// the peak and the waits are printed at the end.
+///
+/// With `-v`, `upload` and `download` print a line to stderr as each part
+/// or chunk is transferred, with its size, time, rate, and request ID.
+///
+/// `download` and `download-window` accept `--fsync`, which syncs the file
+/// to disk before exiting, and `--fsync-interval SIZE`, which also syncs it
+/// every SIZE bytes; the time spent syncing is printed at the end.
+///
+/// Every command accepts `--max-requests-per-second N`, which spaces all
+/// the requests it sends, retries included, to at most N per second, and
+/// prints the achieved rate at the end.
+///
+/// On failure, the error is printed as JSON with its code and a hint, when
+/// it is a known one, and the command exits with code 1.
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let opt = Opt::from_args();
+    let verbose = opt.verbose;
+    if let Err(err) = run(opt).await {
+        let rendered = RenderedError::new(&err, verbose);
+        let output = serde_json::json!({ "error": rendered });
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        eprintln!("{}", rendered.to_text());
+        std::process::exit(1);
+    }
+}
+
+async fn run(opt: Opt) -> Result<(), Error> {
+    let Opt {
+        region,
+        profile,
+        endpoint_url,
+        endpoint_template,
+        reprobe_interval,
+        config,
+        local_address,
+        max_requests_per_second,
+        debug_signing,
+        capture_part,
+        capture_file,
+        request_timings,
+        request_timings_file,
+        memory_limit,
+        allow_dir_marker,
+        verbose,
+        mut command,
+    } = opt;
+    if let Some(key) = command.upload_key() {
+        check_upload_key(key, allow_dir_marker)?;
+    }
+    let bucket_arn = match command.bucket_mut() {
+        Some(bucket) => {
+            let arn = BucketArn::parse(bucket)?;
+            if let Some(arn) = &arn {
+                check_arn_addressing(arn, &endpoint_url)?;
+                if arn.is_access_point() && endpoint_template.is_some() {
+                    return Err(Error::Unhandled(Box::from(format!(
+                        "{} is an access point ARN, which cannot be combined with \
+                         --endpoint-template",
+                        arn.bucket()
+                    ))));
+                }
+                *bucket = arn.bucket().to_string();
+            }
+            arn
+        }
+        None => None,
+    };
+    // Each request is sent for its own bucket; the bucket of the command is
+    // checked up front.
+    if let (Some(template), Some(bucket)) = (&endpoint_template, command.bucket_mut()) {
+        if template.style() == TemplateStyle::Host {
+            template.endpoint_for(bucket, "us-east-1").map_err(|err| {
+                Error::Unhandled(Box::from(format!("--endpoint-template: {}", err)))
+            })?;
+        }
+    }
+    let region = match &bucket_arn {
+        Some(arn) => region_for_arn(region, arn)?,
+        None => region,
+    };
+    let memory_budget = MemoryBudget::from_limit(memory_limit);
+    let request_limiter = max_requests_per_second
+        .map(RequestLimiter::new)
+        .transpose()
+        .map_err(|err| {
+            Error::Unhandled(Box::from(format!("--max-requests-per-second: {}", err)))
+        })?;
+    let config_path = config;
+    let config = match &config_path {
+        Some(path) => TransferConfig::load(path)?,
+        None => TransferConfig::default(),
+    };
+    // Part size caps are kept by the endpoint the upload starts on.
+    let cap_endpoint = match (&endpoint_template, endpoint_url.first()) {
+        (Some(template), _) => template.as_str().to_string(),
+        (None, Some(url)) => url.clone(),
+        (None, None) => "amazon-s3".to_string(),
+    };
+    if verbose {
+        eprintln!("S3 client version: {}", PKG_VERSION);
+        if let Some(template) = &endpoint_template {
+            eprintln!("Endpoint template: {}", template.as_str());
+        } else if endpoint_url.is_empty() {
+            eprintln!("Endpoint:          Amazon S3");
+        }
+        for url in &endpoint_url {
+            eprintln!("Endpoint:          {}", url);
+        }
+        if let Some(arn) = bucket_arn.as_ref().filter(|arn| arn.is_access_point()) {
+            eprintln!("Access point:      {}", arn.bucket());
+        }
+        if let Some(address) = local_address {
+            eprintln!("Local address:     {}", address);
+        }
+        if let Some(limit) = max_requests_per_second {
+            eprintln!("Request limit:     {} per second", limit);
+        }
+    }
+    let capture = capture_part.map(|part_number| {
+        let path = capture_file
+            .unwrap_or_else(|| PathBuf::from(format!("part-{}-capture.json", part_number)));
+        PartCapture::new(part_number, &path)
+    });
+    let timings = if request_timings || request_timings_file.is_some() {
+        Some(RequestTimings::new())
+    } else {
+        None
+    };
+    let options = ConnectOptions {
+        region,
+        profile,
+        endpoint_url: None,
+        endpoint_template,
+        local_address,
+        request_limiter: request_limiter.clone(),
+        debug_signing: debug_signing
+            .map(|mode| SigningDebugger::new(mode.unwrap_or(DebugSigningMode::Failures))),
+        capture_part: capture.clone(),
+        record_request_ids: verbose,
+        request_timings: timings.clone(),
+    };
+    let verbosity = VerbosityConfig::from_flag(verbose);
+    let endpoints = if endpoint_url.is_empty() {
+        EndpointPool::single(connect(&options).await?)
+    } else {
+        EndpointPool::new(
+            connect_endpoints(&options, &endpoint_url).await?,
+            reprobe_interval,
+        )
+    };
+    let client = endpoints.client().1;
+
+    match command {
+        Command::Upload(opt) => {
+            let topic_arn = opt.notify_sns_topic_arn.clone();
+            let upload_config = UploadConfig {
+                config: &config,
+                path: config_path.as_deref(),
+                endpoint: &cap_endpoint,
+            };
+            let mut result = upload(&endpoints, upload_config, opt, verbosity).await?;
+            if let Some(topic_arn) = topic_arn {
+                let payload = UploadPayload {
+                    bucket: result.bucket.clone(),
+                    key: result.key.clone(),
+                    e_tag: result.e_tag.clone(),
+                    size: result.plan.size,
+                };
+                let sns_client = connect_sns(&options).await;
+                match notify_sns_after_upload(&sns_client, &topic_arn, payload).await {
+                    Ok(message_id) => result.sns_message_id = Some(message_id),
+                    Err(err) => eprintln!(
+                        "Warning: {} was uploaded, but not announced on {}: {}",
+                        result.key, topic_arn, err
+                    ),
+                }
+            }
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        Command::UploadZip(opt) => {
+            upload_as_zip_with_budget(
+                &client,
+                &opt.bucket,
+                &opt.key,
+                &opt.directory,
+                Some(&memory_budget),
+            )
+            .await?;
+        }
+        Command::DownloadZip(opt) => {
+            let report =
+                download_and_extract_zip(&client, &opt.bucket, &opt.key, &opt.directory).await?;
+            println!(
+                "Extracted {} files ({}) to {}",
+                report.files,
+                format_size(report.bytes),
+                opt.directory.display()
+            );
+            if !report.failed.is_empty() {
+                println!("Failed entries:");
+                for entry in &report.failed {
+                    println!("  {}: {}", entry.name, entry.error);
+                }
+            }
+        }
+        Command::Download(opt) => {
+            let options = ParallelDownloadOptions {
+                part_size: opt.part_size.unwrap_or(DEFAULT_PART_SIZE),
+                concurrency: opt.concurrency,
+                write_in_place: opt.write_in_place,
+                truncate: !opt.no_truncate,
+                alignment: opt.alignment.map(|alignment| alignment as usize),
+                verify: opt.verify,
+                retry_on_change: opt.retry_on_change,
+                resume: opt.resume,
+                memory_budget: Some(memory_budget.clone()),
+                verbosity,
+                fsync: FsyncOptions {
+                    enabled: opt.fsync,
+                    interval: opt.fsync_interval,
+                },
+            };
+            if let Some(warning) = memory_budget.fit_warning(options.concurrency, options.part_size)
+            {
+                eprintln!("{}", warning);
+            }
+            // A resumed download reports where it starts and its progress.
+            let on_event = |event: &ProgressEvent| {
+                if opt.resume {
+                    eprintln!("{}", event.to_text())
+                }
+            };
+            let result = download_parallel_with_events(
+                &client,
+                &opt.bucket,
+                &opt.key,
+                &opt.file,
+                &options,
+                &on_event,
+            )
+            .await?;
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+            if result.totals.resumed_bytes > 0 {
+                eprintln!("Resumed download, {}", result.totals.summary());
+            }
+            if let Some(reason) = &result.fallback {
+                eprintln!(
+                    "O_DIRECT was refused, buffered writes were used: {}",
+                    reason
+                );
+            }
+            print_fsync(&result.fsync);
+            if !result.is_ok() {
+                eprintln!("{} does not match what was downloaded", opt.file.display());
+                std::process::exit(1);
+            }
+        }
+        Command::DownloadWindow(opt) => {
+            let result = download_into_window_with_fsync(
+                &client,
+                &opt.bucket,
+                &opt.key,
+                &opt.file,
+                opt.dest_offset,
+                opt.split,
+                &FsyncOptions {
+                    enabled: opt.fsync,
+                    interval: opt.fsync_interval,
+                },
+            )
+            .await?;
+            print_fsync(&result.fsync);
+            if opt.check_integrity_manifest {
+                let manifest = get_integrity_manifest(&client, &opt.bucket, &opt.key).await?;
+                let check = manifest
+                    .check_file(&opt.file, result.window)
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                let output = serde_json::json!({ "download": result, "integrity": check });
+                println!("{}", serde_json::to_string_pretty(&output).unwrap());
+                if !check.is_ok() {
+                    eprintln!(
+                        "{} does not match its integrity manifest (parts {:?})",
+                        opt.key, check.mismatched_parts
+                    );
+                    std::process::exit(1);
+                }
+            } else {
+                println!("{}", serde_json::to_string_pretty(&result).unwrap());
+            }
+        }
+        Command::Manifest(opt) => {
+            let manifest = generate_manifest(&opt.directory, &opt.prefix).await?;
+            manifest.save(&opt.output)?;
+            println!(
+                "Wrote the SHA-256 of {} files to {}",
+                manifest.objects.len(),
+                opt.output.display()
+            );
+        }
+        Command::DownloadVerify(opt) => {
+            let report = download_and_verify_with_options(
+                &client,
+                &opt.bucket,
+                &opt.manifest,
+                &opt.directory,
+                opt.check_integrity_manifest,
+            )
+            .await?;
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            if !report.is_ok() {
+                eprintln!(
+                    "{} verified, {} mismatched, {} missing",
+                    report.verified.len(),
+                    report.mismatched.len(),
+                    report.missing.len()
+                );
+                std::process::exit(1);
+            }
+        }
+        Command::WatchUpload(opt) => watch(&client, opt).await?,
+        Command::UploadStatus(opt) => {
+            let options = StatusOptions {
+                bytes_per_second: opt.rate,
+                verify_sample: opt.verify_local.then(|| opt.sample),
+            };
+            let status = upload_status(
+                &client,
+                &opt.bucket,
+                &opt.key,
+                &opt.upload_id,
+                &opt.file,
+                &options,
+            )
+            .await?;
+            if opt.json {
+                println!("{}", serde_json::to_string_pretty(&status).unwrap());
+            } else {
+                println!("{}", status.to_text());
+            }
+            if status.source_changed() {
+                eprintln!(
+                    "{} changed since the upload started; resuming would corrupt the object",
+                    opt.file.display()
+                );
+                std::process::exit(1);
+            }
+        }
+        Command::ResumeUpload(opt) => {
+            let bucket_encryption = if opt.no_preflight_encryption {
+                None
+            } else {
+                Some(bucket_default_encryption(&client, &opt.bucket).await?)
+            };
+            let options = ResumeOptions {
+                verify: opt.resume_verify,
+                bucket_encryption,
+            };
+            let result = resume_upload_with_options(
+                &client,
+                &opt.bucket,
+                &opt.key,
+                &opt.upload_id,
+                &opt.file,
+                &options,
+                &|event| eprintln!("{}", event.to_text()),
+            )
+            .await?;
+            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+        }
+        Command::UploadParts(opt) => {
+            let upload_id = match opt.upload_id {
+                Some(upload_id) => upload_id,
+                None => {
+                    let upload_id = start_staged_upload(&client, &opt.bucket, &opt.key).await?;
+                    println!("Created upload {}", upload_id);
+                    upload_id
+                }
+            };
+            let window = SourceWindow::for_file(&opt.file, opt.source_offset, opt.source_length)?;
+            let defaults = PartsRangeOptions::default();
+            let options = PartsRangeOptions {
+                part_size: opt.part_size.unwrap_or(defaults.part_size),
+                part_number_offset: opt.part_number_offset,
+                collision_check: !opt.no_collision_check,
+                ..defaults
+            };
+            let manifest = upload_parts_range(
+                &client,
+                &opt.bucket,
+                &opt.key,
+                &upload_id,
+                &opt.file,
+                window,
+                &options,
+            )
+            .await?;
+            manifest.save(&opt.manifest).await?;
+            println!(
+                "Uploaded parts {} to {} of upload {}, listed in {}",
+                manifest.parts[0].part_number,
+                manifest.parts[manifest.parts.len() - 1].part_number,
+                upload_id,
+                opt.manifest.display()
+            );
+        }
+        Command::CompleteUpload(opt) => {
+            let manifests = opt
+                .manifest
+                .iter()
+                .map(|path| StageManifest::load(path))
+                .collect::<Result<Vec<_>, _>>()?;
+            let e_tag = complete_staged_upload(&client, &manifests).await?;
+            println!(
+                "Completed {} with {} parts, ETag {}",
+                manifests[0].key,
+                manifests.iter().map(|m| m.parts.len()).sum::<usize>(),
+                e_tag
+            );
+        }
+        Command::AppendUpload(opt) => {
+            let defaults = AppendOptions::default();
+            let options = AppendOptions {
+                part_size: opt.part_size.unwrap_or(defaults.part_size),
+                ..defaults
+            };
+            let run = append_upload(
+                &client,
+                &opt.bucket,
+                &opt.key,
+                &opt.file,
+                &opt.manifest,
+                opt.finalize,
+                &options,
+            )
+            .await?;
+            println!("{}", serde_json::to_string_pretty(&run).unwrap());
+        }
+        Command::SelfTest(opt) => {
+            check_general_purpose_bucket(&opt.bucket)?;
+            let options = SelfTestOptions {
+                file_size: opt.size,
+                stage_timeout: opt.stage_timeout,
+            };
+            let report = self_test(&client, &opt.bucket, &opt.prefix, &options).await?;
+            if opt.json {
+                println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            } else {
+                print!("{}", report.to_text());
+            }
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+        }
+        Command::MakeDir(opt) => {
+            let key = make_dir_marker(&client, &opt.bucket, &opt.key).await?;
+            println!("Created folder {}", key);
+        }
+    }
+    if memory_budget.stats().peak > 0 {
+        eprintln!("{}", memory_budget.stats());
+    }
+    if let Some(limiter) = request_limiter {
+        eprintln!("{}", limiter.stats());
+    }
+    if let Some(timings) = timings {
+        let path = request_timings_file.unwrap_or_else(|| PathBuf::from("request-timings.json"));
+        timings.save(&path)?;
+        eprintln!("{}", timings.summary());
+        eprintln!(
+            "Wrote the timings of each part request to {}",
+            path.display()
+        );
+    }
+    if let Some(capture) = capture {
+        capture.save()?;
+        eprintln!(
+            "Captured {} attempts of part {} to {}",
+            capture.attempts().len(),
+            capture.part_number(),
+            capture.path().unwrap().display()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,412 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::batch::{BatchOptions, DEFAULT_MAX_ARCHIVE_SIZE};
+use s3_service::bisync::{bisync_directory, BisyncOptions, ConflictPolicy};
+use s3_service::cli::{parse_duration, parse_size};
+use s3_service::config::{RetrySettings, TransferConfig};
+use s3_service::error_hints::RenderedError;
+use s3_service::memory_budget::MemoryBudget;
+use s3_service::scheduler::SchedulerOptions;
+use s3_service::stamp::StampSampling;
+use s3_service::sync::{sync_directory, SyncOptions, MAX_REPLANS};
+use s3_service::units::format_duration;
+use std::path::PathBuf;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The local directory to sync.
+    #[structopt(short, long, parse(from_os_str))]
+    directory: PathBuf,
+
+    /// The prefix the files are uploaded under.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// Never overwrite an object that is newer than the local file.
+    #[structopt(long)]
+    no_overwrite_newer: bool,
+
+    /// Overwrite newer objects anyway.
+    #[structopt(long)]
+    force: bool,
+
+    /// Timestamps closer than this are considered equal.
+    #[structopt(long, default_value = "2s", parse(try_from_str = parse_duration))]
+    mtime_window: Duration,
+
+    /// The maximum number of files uploaded at the same time.
+    #[structopt(short, long, default_value = "8")]
+    concurrency: usize,
+
+    /// Pack files smaller than this size into archive objects.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    batch_small_objects: Option<u64>,
+
+    /// The maximum size of an archive object.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    max_archive_size: Option<u64>,
+
+    /// The memory the archives being uploaded may hold at the same time,
+    /// e.g. 1GiB. Defaults to half of the available memory.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    memory_limit: Option<u64>,
+
+    /// A TOML configuration file with default headers by file extension and
+    /// retry settings.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// The number of attempts to upload each file. The default is 4.
+    #[structopt(long)]
+    max_attempts: Option<u32>,
+
+    /// The backoff before the first retry, doubled for each later one.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    base_delay: Option<Duration>,
+
+    /// The longest backoff between two attempts.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    max_delay: Option<Duration>,
+
+    /// The largest random delay added to each backoff.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    jitter: Option<Duration>,
+
+    /// Record the mode, modification time, and owner of each file in its
+    /// object metadata, and upload symbolic links instead of skipping them.
+    #[structopt(long)]
+    preserve: bool,
+
+    /// Compare the files with the modification time recorded in the object
+    /// metadata instead of the time the object was uploaded.
+    #[structopt(long)]
+    use_preserved_mtime: bool,
+
+    /// Also download the objects that are missing or out of date locally.
+    #[structopt(long)]
+    bidirectional: bool,
+
+    /// How to resolve a file changed both locally and remotely with
+    /// --bidirectional: prefer-local, prefer-remote, error, or rename.
+    #[structopt(long, default_value = "error")]
+    conflict: ConflictPolicy,
+
+    /// List the prefix in N shards of its keys at the same time, for
+    /// prefixes with millions of objects.
+    #[structopt(long)]
+    parallel_list: Option<usize>,
+
+    /// Record the SHA-256 of each uploaded file in its object metadata, or
+    /// in the index of its archive.
+    #[structopt(long)]
+    stamp_content_hash: bool,
+
+    /// Check the SHA-256 recorded with --stamp-content-hash of the objects
+    /// found identical against their file: all, the default, or
+    /// sample=PERCENT%[,seed=N].
+    #[structopt(long, parse(try_from_str = StampSampling::parse))]
+    verify_stamps: Option<Option<StampSampling>>,
+
+    /// Only print what would be transferred, and why.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Uploads the files of a local directory that are missing or out of date in
+/// an Amazon S3 bucket.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `-d DIRECTORY` - The local directory to sync.
+/// * `[-p PREFIX]` - The prefix the files are uploaded under.
+/// * `[--no-overwrite-newer]` - Skip files whose object is newer than the file.
+/// * `[--force]` - Overwrite newer objects even with `--no-overwrite-newer`.
+/// * `[--mtime-window DURATION]` - The clock skew tolerance, such as `2s`.
+/// * `[-c CONCURRENCY]` - The number of files uploaded at the same time.
+/// * `[--batch-small-objects SIZE]` - Pack files smaller than SIZE into archives.
+/// * `[--max-archive-size SIZE]` - The maximum size of an archive. The default is 64MiB.
+/// * `[--memory-limit SIZE]` - The memory the archives being uploaded may hold at
+///   the same time. The default is half of the available memory.
+/// * `[--config FILE]` - A TOML file with the headers of the uploaded objects
+///   and the retry settings.
+/// * `[--max-attempts N]` - The number of attempts to upload each file.
+/// * `[--base-delay DURATION]` - The backoff before the first retry, such as `200ms`.
+/// * `[--max-delay DURATION]` - The longest backoff, such as `20s`.
+/// * `[--jitter DURATION]` - The largest random delay added to each backoff.
+/// * `[--preserve]` - Record the mode, modification time, owner, and symbolic link
+///   target of each file in its object metadata.
+/// * `[--use-preserved-mtime]` - Compare the files with the modification time
+///   recorded in the object metadata instead of LastModified.
+/// * `[--bidirectional]` - Also download the objects that are missing or out of date locally.
+/// * `[--conflict POLICY]` - How to resolve a file changed on both sides with `--bidirectional`:
+///   `prefer-local`, `prefer-remote`, `error` (the default), or `rename`.
+/// * `[--parallel-list N]` - List the prefix in N shards of its keys at the same time.
+/// * `[--stamp-content-hash]` - Record the SHA-256 of each uploaded file with its object.
+/// * `[--verify-stamps [SAMPLING]]` - Check the recorded SHA-256 of the objects found identical
+///   against their file, for all of them or a seeded sample such as `sample=5%,seed=7`.
+/// * `[--dry-run]` - Only print what would be transferred, and why.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let opt = Opt::from_args();
+    let verbose = opt.verbose;
+    if let Err(err) = run(opt).await {
+        eprintln!("{}", RenderedError::new(&err, verbose).to_text());
+        std::process::exit(1);
+    }
+}
+
+async fn run(opt: Opt) -> Result<(), Error> {
+    let Opt {
+        region,
+        bucket,
+        directory,
+        prefix,
+        no_overwrite_newer,
+        force,
+        mtime_window,
+        concurrency,
+        batch_small_objects,
+        max_archive_size,
+        memory_limit,
+        config,
+        max_attempts,
+        base_delay,
+        max_delay,
+        jitter,
+        preserve,
+        use_preserved_mtime,
+        bidirectional,
+        conflict,
+        parallel_list,
+        stamp_content_hash,
+        verify_stamps,
+        dry_run,
+        verbose,
+    } = opt;
+    if max_attempts == Some(0) {
+        return Err(Error::Unhandled(Box::from(
+            "--max-attempts must be at least 1",
+        )));
+    }
+    if bidirectional && (no_overwrite_newer || force || batch_small_objects.is_some()) {
+        return Err(Error::Unhandled(Box::from(
+            "--bidirectional cannot be combined with --no-overwrite-newer, --force, or --batch-small-objects",
+        )));
+    }
+    if parallel_list == Some(0) {
+        return Err(Error::Unhandled(Box::from(
+            "--parallel-list must be at least 1",
+        )));
+    }
+    if parallel_list.is_some() && bidirectional {
+        return Err(Error::Unhandled(Box::from(
+            "--parallel-list cannot be combined with --bidirectional",
+        )));
+    }
+    if preserve && (bidirectional || batch_small_objects.is_some()) {
+        return Err(Error::Unhandled(Box::from(
+            "--preserve cannot be combined with --bidirectional or --batch-small-objects",
+        )));
+    }
+    if bidirectional && (stamp_content_hash || verify_stamps.is_some()) {
+        return Err(Error::Unhandled(Box::from(
+            "--stamp-content-hash and --verify-stamps cannot be combined with --bidirectional",
+        )));
+    }
+    let config = match config {
+        Some(path) => TransferConfig::load(&path)?,
+        None => TransferConfig::default(),
+    };
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Bucket:            {}", &bucket);
+        println!("Directory:         {}", directory.display());
+        println!("Prefix:            {}", &prefix);
+        println!("Mtime window:      {:?}", mtime_window);
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+
+    if bidirectional {
+        let options = BisyncOptions {
+            conflict,
+            mtime_window,
+            scheduler: SchedulerOptions {
+                concurrency,
+                ..Default::default()
+            },
+        };
+        let summary =
+            bisync_directory(&client, &bucket, &directory, &prefix, &options, dry_run).await?;
+        println!("Uploaded {} files", summary.uploaded.len());
+        for key in &summary.uploaded {
+            println!("  uploaded: {}", key);
+        }
+        println!("Downloaded {} files", summary.downloaded.len());
+        for key in &summary.downloaded {
+            println!("  downloaded: {}", key);
+        }
+        for (key, renamed) in &summary.renamed {
+            println!("  conflict: kept the local {} as {}", key, renamed);
+        }
+        println!("Skipped {} identical files", summary.identical.len());
+        println!("Failed {} files", summary.failed.len());
+        for (key, err) in &summary.failed {
+            println!("  failed: {} ({})", key, err);
+        }
+        return Ok(());
+    }
+
+    let memory_budget = MemoryBudget::from_limit(memory_limit);
+    let batch = batch_small_objects.map(|threshold| BatchOptions {
+        threshold,
+        max_archive_size: max_archive_size.unwrap_or(DEFAULT_MAX_ARCHIVE_SIZE),
+        stamp_content_hash,
+    });
+    if let Some(batch) = &batch {
+        if let Some(warning) = memory_budget.fit_warning(concurrency, batch.max_archive_size) {
+            eprintln!("{}", warning);
+        }
+    }
+    let options = SyncOptions {
+        no_overwrite_newer,
+        force,
+        mtime_window,
+        concurrency,
+        batch,
+        headers: config.headers,
+        retry: RetrySettings {
+            max_attempts,
+            base_delay_ms: base_delay.map(|d| d.as_millis() as u64),
+            max_delay_ms: max_delay.map(|d| d.as_millis() as u64),
+            jitter_ms: jitter.map(|d| d.as_millis() as u64),
+        }
+        .apply(config.retry.apply(SyncOptions::default().retry)),
+        preserve,
+        use_preserved_mtime,
+        memory_budget: Some(memory_budget.clone()),
+        parallel_list,
+        max_replans: MAX_REPLANS,
+        stamp_content_hash,
+        verify_stamps: verify_stamps.map(|sampling| sampling.unwrap_or_default()),
+    };
+    let summary = sync_directory(&client, &bucket, &directory, &prefix, &options, dry_run).await?;
+
+    if let Some(listing) = &summary.listing {
+        println!(
+            "Listed {} objects in {} ({:.0} keys/s, {} shards, {} pages)",
+            listing.keys,
+            format_duration(listing.elapsed),
+            listing.keys_per_second(),
+            listing.shards,
+            listing.pages
+        );
+    }
+
+    println!("Uploaded {} files", summary.uploaded.len());
+    for key in &summary.uploaded {
+        println!("  uploaded: {}", key);
+    }
+    println!("Skipped {} identical files", summary.identical.len());
+    for key in &summary.identical {
+        println!("  identical: {}", key);
+    }
+    println!(
+        "Skipped {} files with a newer remote object",
+        summary.newer_remote.len()
+    );
+    for key in &summary.newer_remote {
+        println!("  newer remote: {}", key);
+    }
+    if summary.archives > 0 {
+        println!(
+            "Packed {} files into {} archives ({:.1} files per archive, {} requests saved)",
+            summary.packed_files,
+            summary.archives,
+            summary.packing_ratio(),
+            summary.requests_saved()
+        );
+        println!("{}", memory_budget.stats());
+    }
+    if let Some(stamps) = &summary.stamps {
+        println!(
+            "Verified the stamps of {} objects ({} matched, {} not sampled)",
+            stamps.checked, stamps.matched, stamps.sampled_out
+        );
+        for key in &stamps.unstamped {
+            println!("  unstamped: {}", key);
+        }
+        for key in &stamps.modified {
+            println!("  modified since stamped: {}", key);
+        }
+        if !stamps.mixups.is_empty() {
+            println!(
+                "Found {} objects holding the content of another file, a probable key mixup",
+                stamps.mixups.len()
+            );
+            for (key, source) in &stamps.mixups {
+                println!("  mixup: {} holds the content of {}", key, source);
+            }
+        }
+    }
+    let retried = summary.retried();
+    if !retried.is_empty() {
+        println!("Retried {} files", retried.len());
+        for (path, attempts) in retried {
+            println!("  retried: {} ({} attempts)", path.display(), attempts);
+        }
+    }
+    if !summary.unstable.is_empty() {
+        println!(
+            "Skipped {} files that kept changing",
+            summary.unstable.len()
+        );
+        for key in &summary.unstable {
+            println!("  unstable: {}", key);
+        }
+    }
+    println!("Failed {} files", summary.failed.len());
+    for (key, err) in &summary.failed {
+        println!("  failed: {} ({})", key, err);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,196 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use aws_sdk_s3control::model::JobStatus;
+use futures::StreamExt;
+use s3_service::batch_operations::{
+    create_batch_job_manifest, create_tagging_batch_job, monitor_batch_job, JobProgress,
+    REPORT_PREFIX,
+};
+use s3_service::cli::{parse_duration, parse_key_value};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket holding the manifest.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The key of the manifest.
+    #[structopt(short, long, default_value = "batch-operations-manifest.csv")]
+    manifest_key: String,
+
+    /// Write the manifest of the objects under this prefix of the bucket
+    /// first, instead of using an existing one.
+    #[structopt(short, long)]
+    prefix: Option<String>,
+
+    /// A tag (key=value) set on each object. Can be repeated.
+    #[structopt(
+        short,
+        long,
+        number_of_values = 1,
+        required = true,
+        parse(try_from_str = parse_key_value)
+    )]
+    tag: Vec<(String, String)>,
+
+    /// The ID of the AWS account that owns the bucket.
+    #[structopt(long)]
+    account_id: String,
+
+    /// The ARN of the IAM role the job runs as.
+    #[structopt(long)]
+    role_arn: String,
+
+    /// The bucket the completion report is written to. Defaults to the
+    /// bucket of the manifest.
+    #[structopt(long)]
+    report_bucket: Option<String>,
+
+    /// How often the progress of the job is refreshed.
+    #[structopt(long, default_value = "10s", parse(try_from_str = parse_duration))]
+    interval: Duration,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Draws the tasks over as a bar, followed by the counts and the status.
+fn progress_bar(progress: &JobProgress) -> String {
+    const WIDTH: usize = 30;
+    let filled = ((progress.progress_percent / 100.0) * WIDTH as f64).round() as usize;
+    let filled = filled.min(WIDTH);
+    format!(
+        "[{}{}] {:5.1}% {} of {} tasks, {} failed ({})",
+        "#".repeat(filled),
+        " ".repeat(WIDTH - filled),
+        progress.progress_percent,
+        progress.succeeded + progress.failed,
+        progress.total_tasks,
+        progress.failed,
+        progress.status.as_str()
+    )
+}
+
+/// Sets tags on the objects of a CSV manifest with an S3 Batch Operations
+/// job, and displays the progress of the job until it is over.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket holding the manifest.
+/// * `[-m MANIFEST-KEY]` - The key of the manifest.
+///   The default is batch-operations-manifest.csv.
+/// * `[-p PREFIX]` - Write the manifest of the objects under PREFIX first.
+/// * `-t KEY=VALUE ...` - The tags set on each object.
+/// * `--account-id ACCOUNT` - The ID of the account that owns the bucket.
+/// * `--role-arn ARN` - The IAM role the job runs as.
+/// * `[--report-bucket BUCKET]` - Where the completion report is written.
+///   If not supplied, uses BUCKET.
+/// * `[--interval DURATION]` - How often the progress is refreshed. The default is 10s.
+/// * `[-r REGION]` - The Region in which the clients are created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        manifest_key,
+        prefix,
+        tag,
+        account_id,
+        role_arn,
+        report_bucket,
+        interval,
+        verbose,
+    } = Opt::from_args();
+    let report_bucket = report_bucket.unwrap_or_else(|| bucket.clone());
+    let tag_set: HashMap<String, String> = tag.into_iter().collect();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Bucket:            {}", &bucket);
+        println!("Manifest key:      {}", &manifest_key);
+        println!("Tags:              {}", tag.join(", "));
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = Client::new(&shared_config);
+    let s3control_client = aws_sdk_s3control::Client::new(&shared_config);
+
+    if let Some(prefix) = prefix {
+        create_batch_job_manifest(&client, &bucket, &prefix, &manifest_key).await?;
+        println!("Wrote the manifest to s3://{}/{}", bucket, manifest_key);
+    }
+
+    let job_id = create_tagging_batch_job(
+        &s3control_client,
+        &client,
+        &account_id,
+        &bucket,
+        &manifest_key,
+        tag_set,
+        &role_arn,
+        &report_bucket,
+    )
+    .await?;
+    println!("Created job {}", job_id);
+
+    let mut last = None;
+    let mut progress = Box::pin(monitor_batch_job(
+        &s3control_client,
+        &account_id,
+        &job_id,
+        interval,
+    ));
+    while let Some(update) = progress.next().await {
+        let update = update?;
+        // Clears the line and rewrites it.
+        print!("\r\x1b[2K{}", progress_bar(&update));
+        let _ = std::io::stdout().flush();
+        last = Some(update);
+    }
+    println!();
+
+    println!(
+        "The report is written under s3://{}/{}/",
+        report_bucket, REPORT_PREFIX
+    );
+    if let Some(last) = last {
+        if last.status != JobStatus::Complete {
+            eprintln!("The job ended {}", last.status.as_str());
+            std::process::exit(1);
+        }
+        if last.failed > 0 {
+            eprintln!("{} objects could not be tagged", last.failed);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,557 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::adaptive::{
+    AdaptiveConcurrency, AimdController, Concurrency, DEFAULT_INITIAL_CONCURRENCY,
+    DEFAULT_MAX_CONCURRENCY,
+};
+use s3_service::checkpoint::CheckpointedUpload;
+use s3_service::cli::{parse_duration, parse_size};
+use s3_service::config::{save_tuning, TransferConfig, TuningSettings};
+use s3_service::error_hints::RenderedError;
+use s3_service::excludes::{build_excludes, walk_directory_with_excludes};
+use s3_service::expected_sha256::parse_sha256;
+use s3_service::express::check_general_purpose_bucket;
+use s3_service::memory_budget::MemoryBudget;
+use s3_service::rate_limit::{rate_limited_client, RequestLimiter};
+use s3_service::run_summary::{
+    group_failures, listed_sha256, read_files_from, render_failures, retry_command, select_listed,
+    write_event_log, write_retry_file,
+};
+use s3_service::scheduler::{
+    upload_files_with_hook, ResumeManifest, ScheduledFile, SchedulerOptions,
+};
+use s3_service::shutdown::{Shutdown, DEFAULT_GRACE_PERIOD};
+use s3_service::upload::{UploadPlanOptions, DEFAULT_MULTIPART_THRESHOLD};
+use s3_service::verified_put::HashAlgorithm;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The local directory to upload.
+    #[structopt(short, long, parse(from_os_str))]
+    directory: PathBuf,
+
+    /// The prefix the files are uploaded under.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// The number of files uploaded at the same time, or "auto" to adjust
+    /// it to what the endpoint accepts.
+    #[structopt(short, long, default_value = "4")]
+    concurrency: Concurrency,
+
+    /// With -c auto, the most files uploaded at the same time.
+    #[structopt(long, default_value = "64")]
+    max_concurrency: usize,
+
+    /// A TOML configuration file. With -c auto, its [tuning] section sets
+    /// the number of files the run starts from.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// With -c auto, write the number of files the run converged to in the
+    /// [tuning] section of the configuration file.
+    #[structopt(long)]
+    save_tuning: bool,
+
+    /// With -c auto, where the changes of the number of files are written,
+    /// as JSON Lines.
+    #[structopt(long, parse(from_os_str))]
+    tuning_log: Option<PathBuf>,
+
+    /// Files of at least this size are uploaded in parts.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    multipart_threshold: Option<u64>,
+
+    /// The minimum size of the parts. Chosen automatically if not supplied.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    part_size: Option<u64>,
+
+    /// The memory the files and parts being uploaded may hold at the same
+    /// time, e.g. 1GiB. Defaults to half of the available memory.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    memory_limit: Option<u64>,
+
+    /// A .gitignore-style pattern of files not to upload, such as "*.tmp" or
+    /// "node_modules/". Can be repeated.
+    #[structopt(long, number_of_values = 1)]
+    exclude: Vec<String>,
+
+    /// A file of patterns, as --exclude. Can be repeated.
+    #[structopt(long, parse(from_os_str), number_of_values = 1)]
+    exclude_from: Vec<PathBuf>,
+
+    /// After Ctrl-C, how long the requests in flight get to finish.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    grace_period: Option<Duration>,
+
+    /// Where the files left to upload are written when the run is
+    /// interrupted or a file fails.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        default_value = "upload-directory.resume.json"
+    )]
+    resume_file: PathBuf,
+
+    /// Upload only the files left by the run that wrote the resume file.
+    #[structopt(long)]
+    resume: bool,
+
+    /// Upload only the files listed in this file, one path relative to the
+    /// directory per line, such as the retry file of a run. A path can be
+    /// followed by a tab and the SHA-256 the file must be read with.
+    #[structopt(long, parse(from_os_str))]
+    files_from: Option<PathBuf>,
+
+    /// The SHA-256 the file must be read with, in hex, for a run uploading a
+    /// single file. A file read with another is not stored.
+    #[structopt(long, parse(try_from_str = parse_sha256))]
+    expected_sha256: Option<String>,
+
+    /// Send the checksum of each object or part for S3 to verify. Only
+    /// sha256 is supported.
+    #[structopt(long)]
+    checksum: Option<HashAlgorithm>,
+
+    /// Where the files that failed are listed for --files-from.
+    #[structopt(long, parse(from_os_str), default_value = "upload-directory.retry.txt")]
+    retry_file: PathBuf,
+
+    /// Where the outcome of every file is written, as JSON Lines.
+    #[structopt(long, parse(from_os_str))]
+    event_log: Option<PathBuf>,
+
+    /// Print the summary of the run as JSON.
+    #[structopt(long)]
+    json: bool,
+
+    /// Send at most this many requests per second, retries included, across
+    /// all the files uploaded at the same time.
+    #[structopt(long)]
+    max_requests_per_second: Option<f64>,
+
+    /// Where the files uploaded so far are written during the run. Files
+    /// listed in it are skipped.
+    #[structopt(long, parse(from_os_str))]
+    checkpoint_file: Option<PathBuf>,
+
+    /// Write the checkpoint every N uploaded files. 0 turns this off.
+    #[structopt(long, default_value = "100")]
+    checkpoint_every_files: u32,
+
+    /// Write the checkpoint at least this often while files are uploaded.
+    #[structopt(long, parse(try_from_str = parse_duration), default_value = "5m")]
+    checkpoint_every: Duration,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Uploads the files of a local directory, with a multipart upload for the
+/// large ones.
+///
+/// The first Ctrl-C stops starting new files and parts and lets the requests
+/// in flight finish; the second one, or the end of the grace period, drops
+/// them. Incomplete multipart uploads are aborted and the files left to
+/// upload are written to the resume file, for a later run with `--resume`.
+///
+/// With `--checkpoint-file`, the files uploaded so far are also written
+/// while the run goes on, so that a run that is killed outright can be
+/// restarted without uploading them again.
+///
+/// When files fail, the summary groups them by error class with a hint for
+/// each, writes them to the retry file, and prints the command uploading
+/// only them with `--files-from`.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `-d DIRECTORY` - The local directory to upload.
+/// * `[-p PREFIX]` - The prefix the files are uploaded under.
+/// * `[-c CONCURRENCY]` - The number of files uploaded at the same time,
+///   or `auto` to adjust it to what the endpoint accepts. The default is 4.
+/// * `[--max-concurrency N]` - With `-c auto`, the most files at the same time.
+///   The default is 64.
+/// * `[--config FILE]` - A TOML file whose `[tuning]` section sets where
+///   `-c auto` starts.
+/// * `[--save-tuning]` - Save the level `-c auto` converged to in the
+///   configuration file.
+/// * `[--tuning-log FILE]` - Where the changes of the level are written.
+/// * `[--multipart-threshold SIZE]` - Files of at least SIZE are uploaded in parts.
+/// * `[--part-size SIZE]` - The minimum size of the parts.
+/// * `[--memory-limit SIZE]` - The memory the files and parts being uploaded may
+///   hold at the same time. The default is half of the available memory.
+/// * `[--exclude PATTERN ...]` - Files not to upload, as .gitignore patterns.
+/// * `[--exclude-from FILE ...]` - Files of patterns, as --exclude. A
+///   .uploadignore file in the directory is always read first.
+/// * `[--grace-period DURATION]` - How long the requests in flight get after Ctrl-C.
+///   The default is 30s.
+/// * `[--resume-file FILE]` - Where the files left to upload are written.
+/// * `[--resume]` - Upload only the files listed in the resume file.
+/// * `[--files-from FILE]` - Upload only the files listed in FILE, relative
+///   to the directory, each optionally followed by a tab and its SHA-256.
+/// * `[--expected-sha256 HEX]` - The SHA-256 the single file uploaded must
+///   be read with.
+/// * `[--checksum sha256]` - Send SHA-256 checksums for S3 to verify.
+/// * `[--retry-file FILE]` - Where the files that failed are listed.
+///   The default is upload-directory.retry.txt.
+/// * `[--event-log FILE]` - Where the outcome of every file is written.
+/// * `[--json]` - Print the summary of the run as JSON.
+/// * `[--max-requests-per-second N]` - The most requests sent per second.
+/// * `[--checkpoint-file FILE]` - Where the files uploaded so far are written.
+/// * `[--checkpoint-every-files N]` - Write the checkpoint every N files.
+///   The default is 100.
+/// * `[--checkpoint-every DURATION]` - Write the checkpoint at least this often.
+///   The default is 5m.
+/// * `[-r REGION]` - The Region in which the client is created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let opt = Opt::from_args();
+    let verbose = opt.verbose;
+    if let Err(err) = run(opt).await {
+        eprintln!("{}", RenderedError::new(&err, verbose).to_text());
+        std::process::exit(1);
+    }
+}
+
+async fn run(opt: Opt) -> Result<(), Error> {
+    let Opt {
+        region,
+        bucket,
+        directory,
+        prefix,
+        concurrency,
+        max_concurrency,
+        config,
+        save_tuning: save,
+        tuning_log,
+        multipart_threshold,
+        part_size,
+        memory_limit,
+        exclude,
+        exclude_from,
+        grace_period,
+        resume_file,
+        resume,
+        files_from,
+        expected_sha256: single_sha256,
+        checksum,
+        retry_file,
+        event_log,
+        json,
+        max_requests_per_second,
+        checkpoint_file,
+        checkpoint_every_files,
+        checkpoint_every,
+        verbose,
+    } = opt;
+    // With --json, standard output holds only the summary.
+    let say = |line: &str| {
+        if json {
+            eprintln!("{}", line)
+        } else {
+            println!("{}", line)
+        }
+    };
+    check_general_purpose_bucket(&bucket)?;
+    let auto = concurrency == Concurrency::Auto;
+    if (save || tuning_log.is_some()) && !auto {
+        return Err(Error::Unhandled(Box::from(
+            "--save-tuning and --tuning-log need -c auto",
+        )));
+    }
+    if save && config.is_none() {
+        return Err(Error::Unhandled(Box::from("--save-tuning needs --config")));
+    }
+    if resume && files_from.is_some() {
+        return Err(Error::Unhandled(Box::from(
+            "--resume and --files-from cannot be combined",
+        )));
+    }
+    if checksum.map_or(false, |algorithm| algorithm != HashAlgorithm::Sha256) {
+        return Err(Error::Unhandled(Box::from(
+            "upload-directory supports only --checksum sha256",
+        )));
+    }
+    // A configuration file that does not exist yet is created by --save-tuning.
+    let tuning = match &config {
+        Some(path) if path.exists() => TransferConfig::load(path)?.tuning,
+        _ => TuningSettings::default(),
+    };
+    let limiter = max_requests_per_second
+        .map(RequestLimiter::new)
+        .transpose()
+        .map_err(|err| {
+            Error::Unhandled(Box::from(format!("--max-requests-per-second: {}", err)))
+        })?;
+
+    let mut expected_sha256 = HashMap::new();
+    let files = if resume {
+        let manifest = ResumeManifest::load(&resume_file)?;
+        if manifest.bucket != bucket {
+            return Err(Error::Unhandled(Box::from(format!(
+                "{} is for bucket {}, not {}",
+                resume_file.display(),
+                manifest.bucket,
+                bucket
+            ))));
+        }
+        expected_sha256 = manifest.expected_sha256();
+        manifest.files()
+    } else {
+        let patterns: Vec<&str> = exclude.iter().map(String::as_str).collect();
+        let excludes = build_excludes(&directory, &patterns, &exclude_from)?;
+        let files: Vec<ScheduledFile> =
+            walk_directory_with_excludes(&directory, &prefix, &excludes)
+                .map_err(|err| Error::Unhandled(Box::new(err)))?
+                .into_iter()
+                .map(ScheduledFile::from)
+                .collect();
+        match &files_from {
+            Some(path) => {
+                let listed = read_files_from(path)?;
+                expected_sha256 = listed_sha256(&directory, &listed);
+                select_listed(files, &directory, &listed, |file| &file.path)?
+            }
+            None => files,
+        }
+    };
+    if let Some(sha256) = single_sha256 {
+        match files.as_slice() {
+            [file] => {
+                expected_sha256.insert(file.path.clone(), sha256);
+            }
+            _ => {
+                return Err(Error::Unhandled(Box::from(format!(
+                    "--expected-sha256 needs a run uploading a single file, not {}; \
+                     give the SHA-256 of each file in --files-from instead",
+                    files.len()
+                ))))
+            }
+        }
+    }
+    let checkpoint = checkpoint_file.map(|path| {
+        CheckpointedUpload::new(&path, checkpoint_every_files, checkpoint_every.as_secs())
+    });
+    let files = match &checkpoint {
+        Some(checkpoint) => {
+            checkpoint.load(&bucket)?;
+            let total = files.len();
+            let remaining = checkpoint.remaining(files);
+            if remaining.len() < total {
+                say(&format!(
+                    "Skipping {} files already uploaded, listed in {}",
+                    total - remaining.len(),
+                    checkpoint.path().display()
+                ));
+            }
+            remaining
+        }
+        None => files,
+    };
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    say("");
+
+    if verbose {
+        say(&format!("S3 client version: {}", PKG_VERSION));
+        say(&format!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        ));
+        say(&format!("Bucket:            {}", &bucket));
+        say(&format!("Directory:         {}", directory.display()));
+        say(&format!("Prefix:            {}", &prefix));
+        say(&format!("Files:             {}", files.len()));
+        match concurrency {
+            Concurrency::Fixed(n) => say(&format!("Concurrency:       {}", n)),
+            Concurrency::Auto => say(&format!(
+                "Concurrency:       auto, from {}",
+                tuning.concurrency.unwrap_or(DEFAULT_INITIAL_CONCURRENCY)
+            )),
+        }
+        say("");
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let client = match &limiter {
+        Some(limiter) => rate_limited_client(
+            aws_sdk_s3::config::Builder::from(&shared_config).build(),
+            limiter,
+        ),
+        None => Client::new(&shared_config),
+    };
+
+    let shutdown = Shutdown::new(grace_period.unwrap_or(DEFAULT_GRACE_PERIOD));
+    shutdown.listen_for_ctrl_c();
+    let adaptive = auto.then(|| {
+        AdaptiveConcurrency::new(AimdController::new(
+            tuning.concurrency.unwrap_or(DEFAULT_INITIAL_CONCURRENCY),
+            1,
+            max_concurrency,
+        ))
+    });
+    let memory_budget = MemoryBudget::from_limit(memory_limit);
+    let options = SchedulerOptions {
+        concurrency: match concurrency {
+            Concurrency::Fixed(n) => n,
+            Concurrency::Auto => DEFAULT_MAX_CONCURRENCY,
+        },
+        plan: UploadPlanOptions {
+            multipart_threshold: multipart_threshold.unwrap_or(DEFAULT_MULTIPART_THRESHOLD),
+            part_size,
+            num_parts: None,
+        },
+        adaptive: adaptive.clone(),
+        expected_sha256,
+        checksum_sha256: checksum.is_some(),
+        memory_budget: Some(memory_budget.clone()),
+    };
+    // A file below the threshold is held whole, a larger one a part at a time.
+    let largest_buffer = options
+        .plan
+        .multipart_threshold
+        .max(part_size.unwrap_or_default());
+    if let Some(warning) = memory_budget.fit_warning(options.concurrency, largest_buffer) {
+        eprintln!("{}", warning);
+    }
+    let on_uploaded = |file: &ScheduledFile, e_tag: &str| {
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.record(&file.path, &file.key, e_tag);
+        }
+    };
+    let total = files.len();
+    let summary =
+        upload_files_with_hook(&client, &bucket, files, &options, &shutdown, &on_uploaded).await;
+    if let Some(checkpoint) = &checkpoint {
+        if summary.pending.is_empty() {
+            checkpoint.remove()?;
+        } else {
+            checkpoint.write()?;
+        }
+    }
+
+    say(&format!("Uploaded {} files", summary.completed.len()));
+    if memory_budget.stats().peak > 0 {
+        say(&memory_budget.stats().to_string());
+    }
+    if let Some(limiter) = &limiter {
+        say(&limiter.stats().to_string());
+    }
+    if let Some(adaptive) = &adaptive {
+        let events = adaptive.events();
+        say(&format!(
+            "Concurrency: {} files after {} adjustments",
+            adaptive.limit(),
+            events.len()
+        ));
+        if let Some(path) = &tuning_log {
+            let lines: String = events
+                .iter()
+                .map(|event| serde_json::to_string(event).unwrap() + "\n")
+                .collect();
+            std::fs::write(path, lines).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        }
+        if let (true, Some(path)) = (save, &config) {
+            save_tuning(
+                path,
+                &TuningSettings {
+                    concurrency: Some(adaptive.limit()),
+                },
+            )?;
+            say(&format!("Saved the concurrency to {}", path.display()));
+        }
+    }
+    if !summary.aborted_uploads.is_empty() {
+        say(&format!(
+            "Aborted {} incomplete multipart uploads",
+            summary.aborted_uploads.len()
+        ));
+        for key in &summary.aborted_uploads {
+            say(&format!("  aborted: {}", key));
+        }
+    }
+    if let Some(path) = &event_log {
+        write_event_log(path, &summary)?;
+    }
+    let groups = group_failures(&summary.pending);
+    let retry = if groups.is_empty() {
+        None
+    } else {
+        write_retry_file(
+            &retry_file,
+            &directory,
+            &summary.pending,
+            &options.expected_sha256,
+        )?;
+        let args: Vec<String> = std::env::args().collect();
+        Some(retry_command(&args, &retry_file))
+    };
+    if json {
+        let output = serde_json::json!({
+            "uploaded": summary.completed.len(),
+            "failed": summary.failed().count(),
+            "interrupted": summary.interrupted,
+            "failure_groups": groups,
+            "retry_file": retry.as_ref().map(|_| &retry_file),
+            "retry_command": retry,
+            "event_log": event_log,
+        });
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+    } else if let Some(command) = &retry {
+        let full_list = event_log.as_deref().unwrap_or(&retry_file);
+        say(render_failures(&groups, total, Some(full_list)).trim_end());
+        say("To upload only the failed files again, run:");
+        say(&format!("  {}", command));
+    }
+
+    if summary.pending.is_empty() {
+        if resume {
+            std::fs::remove_file(&resume_file).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        }
+        return Ok(());
+    }
+    summary.resume_manifest(&bucket).save(&resume_file)?;
+    say(&format!(
+        "{} {} files left to upload ({} bytes already sent will be sent again); \
+         run again with --resume to upload them",
+        if summary.interrupted {
+            "Interrupted:"
+        } else {
+            "Failed:"
+        },
+        summary.pending.len(),
+        summary
+            .pending
+            .iter()
+            .map(|file| file.bytes_transferred)
+            .sum::<u64>()
+    ));
+    say(&format!("Resume file: {}", resume_file.display()));
+    std::process::exit(1);
+}
@@ -1,52 +1,151 @@
-use aws_sdk_s3::types::ByteStream;
 use aws_sdk_s3::{Client, Endpoint, Error};
-use std::path::Path;
-use std::time::Instant;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
-use tokio_util::codec::{BytesCodec, FramedRead};
+use chrono::Utc;
+use http::Uri;
+use s3_service::cli::{parse_endpoint_uri, parse_interval};
+use s3_service::dir_marker::check_upload_key;
+use s3_service::units::summary_line;
+use s3_service::upload::{
+    parse_expires, upload_chunk, upload_chunk_auto_multipart, upload_chunk_with_progress,
+    UploadHeaders,
+};
+use s3_service::verbosity::{request_id_client, with_request_id, VerbosityConfig};
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The profile in the .aws/credentials file.
+    profile: String,
+
+    /// The endpoint URL.
+    #[structopt(parse(try_from_str = parse_endpoint_uri))]
+    url: Uri,
+
+    /// The name of the bucket.
+    bucket: String,
+
+    /// The key of the uploaded object.
+    key: String,
+
+    /// The file to read the chunk from.
+    file_name: String,
+
+    /// The offset of the chunk in the file.
+    start_offset: u64,
+
+    /// The size of the chunk, 0 for the whole file.
+    chunk_size: u64,
+
+    /// The Content-Disposition header stored with the object.
+    #[structopt(long)]
+    content_disposition: Option<String>,
+
+    /// The Cache-Control header stored with the object.
+    #[structopt(long)]
+    cache_control: Option<String>,
+
+    /// The Content-Encoding header stored with the object.
+    #[structopt(long)]
+    content_encoding: Option<String>,
+
+    /// The Content-Language header stored with the object.
+    #[structopt(long)]
+    content_language: Option<String>,
+
+    /// The Expires header stored with the object (RFC 3339 or HTTP date).
+    #[structopt(long, parse(try_from_str = parse_expires))]
+    expires: Option<chrono::DateTime<Utc>>,
+
+    /// Upload chunks over the 5 GiB PutObject limit with a multipart upload
+    /// instead of failing.
+    #[structopt(long)]
+    auto_multipart: bool,
+
+    /// Print the bytes sent and the live throughput, marked with the window
+    /// it averages over, this often, such as `1s`.
+    #[structopt(
+        long,
+        parse(try_from_str = parse_interval),
+        conflicts_with_all = &[
+            "auto-multipart",
+            "content-disposition",
+            "cache-control",
+            "content-encoding",
+            "content-language",
+            "expires",
+        ]
+    )]
+    progress: Option<Duration>,
+
+    /// Upload even to a key ending in /, which the console shows as a
+    /// folder rather than an object.
+    #[structopt(long)]
+    allow_dir_marker: bool,
+
+    /// Print the size, time, rate, and request ID of the upload.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
 /// # Upload file chunk
 ///
 /// ## Shows how to:
 ///
-/// * read a chunk of data from a file using:
-///    - `tokio::fs::File::seek`
-///    - `tokio::io::AsyncReadExt::take (added to tokio::fs::File,
-///                                    limits the number bytes read)`
-/// * minimize memory usage through tokio_util::FramedRead/BytesCodec which
-/// .  reuse an internal bytes::BytesMut to store data
+/// * read a chunk of data from a file at its offset, streaming it in
+///   buffers of at most 2 MiB rather than holding it in memory
 /// * upload the chunk to an S3 endpoint
 /// * extract and print returned etag
+/// * report progress with a rolling average throughput (`--progress`)
+/// * print the request ID of the upload (`-v`), the one of its
+///   CompleteMultipartUpload with `--auto-multipart`
+///
+/// A single PutObject is limited to 5 GiB: larger chunks are rejected before
+/// anything is sent, unless `--auto-multipart` uploads them in parts.
 ///
 /// usage:
 /// ```shell
 /// ./upload-file-chunk <profile> <url> <bucket> <key> <input file> \
-/// <start offset> <chunk size, 0 for whole file>
+/// <start offset> <chunk size, 0 for whole file> \
+/// [--content-disposition VALUE] [--cache-control VALUE] [--content-encoding VALUE] \
+/// [--content-language VALUE] [--expires DATE] [--auto-multipart] [--progress INTERVAL] \
+/// [--allow-dir-marker] [-v]
 /// ```
 #[tokio::main]
 async fn main() -> Result<(), aws_sdk_s3::Error> {
-    let args = std::env::args().collect::<Vec<_>>();
-    let usage = format!("{} <profile> <url> <bucket> <key> <input file> <start offset> <chunk size, 0 for whole file>", args[0]);
-    let profile = args.get(1).expect(&usage);
-    let url = args.get(2).expect(&usage);
-    let bucket = args.get(3).expect(&usage);
-    let key = args.get(4).expect(&usage);
-    let file_name = args.get(5).expect(&usage);
-    let start_offset = args
-        .get(6)
-        .expect(&usage)
-        .parse::<u64>()
-        .expect("Error parsing offset");
-    let chunk_size = args
-        .get(7)
-        .expect(&usage)
-        .parse::<u64>()
-        .expect("Error parsing chunk size");
+    let Opt {
+        profile,
+        url,
+        bucket,
+        key,
+        file_name,
+        start_offset,
+        chunk_size,
+        content_disposition,
+        cache_control,
+        content_encoding,
+        content_language,
+        expires,
+        auto_multipart,
+        progress,
+        allow_dir_marker,
+        verbose,
+    } = Opt::from_args();
+    let verbosity = VerbosityConfig::from_flag(verbose);
+    check_upload_key(&key, allow_dir_marker)?;
     let chunk_size = if chunk_size == 0 {
-        let md = std::fs::metadata(file_name).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        let md = std::fs::metadata(&file_name).map_err(|err| Error::Unhandled(Box::new(err)))?;
         md.len()
     } else {
         chunk_size
     };
+    let headers = UploadHeaders {
+        content_disposition,
+        cache_control,
+        content_encoding,
+        content_language,
+        expires,
+        ..Default::default()
+    };
 
     // credentials are read from .aws/credentials file
     let conf = aws_config::from_env()
@@ -58,55 +157,72 @@ async fn main() -> Result<(), aws_sdk_s3::Error> {
         )
         .load()
         .await;
-    let uri = url.parse::<http::uri::Uri>().expect("Invalid URL");
-    let ep = Endpoint::immutable(uri);
+    let ep = Endpoint::immutable(url);
     let s3_conf = aws_sdk_s3::config::Builder::from(&conf)
         .endpoint_resolver(ep)
         .build();
-    let client = Client::from_conf(s3_conf);
-    upload_chunk(&client, &bucket, &key, &file_name, start_offset, chunk_size).await?;
-    Ok(())
-}
-
-/// Upload file chunk to bucket/key; uses framed read to minimize copies
-pub async fn upload_chunk(
-    client: &Client,
-    bucket: &str,
-    key: &str,
-    file_name: &str,
-    start_offset: u64,
-    chunk_size: u64,
-) -> Result<(), Error> {
-    // minimize memory copies https://github.com/hyperium/hyper/issues/2166#issuecomment-612363623
-    let mut file = tokio::fs::File::open(Path::new(file_name))
-        .await
-        .map_err(|err| Error::Unhandled(Box::new(err)))?;
-    file.seek(std::io::SeekFrom::Start(start_offset))
-        .await
-        .map_err(|err| Error::Unhandled(Box::new(err)))?;
-    let file = file.take(chunk_size);
-    let stream = FramedRead::with_capacity(file, BytesCodec::new(), chunk_size as usize);
-    let b = hyper::Body::wrap_stream(stream);
-    let body = ByteStream::from(b);
+    let client = if verbosity.show_request_ids {
+        request_id_client(s3_conf)
+    } else {
+        Client::from_conf(s3_conf)
+    };
     let start = Instant::now();
-    let resp = client
-        .put_object()
-        .content_length(chunk_size as i64)
-        .bucket(bucket)
-        .key(key)
-        .body(body)
-        .send()
-        .await?;
+    let (etag, request_id) = with_request_id(async {
+        if let Some(tick_interval) = progress {
+            upload_chunk_with_progress(
+                &client,
+                &bucket,
+                &key,
+                &file_name,
+                start_offset,
+                chunk_size,
+                tick_interval,
+            )
+            .await
+        } else if auto_multipart {
+            upload_chunk_auto_multipart(
+                &client,
+                &bucket,
+                &key,
+                &file_name,
+                start_offset,
+                chunk_size,
+                Some(headers),
+            )
+            .await
+        } else {
+            upload_chunk(
+                &client,
+                &bucket,
+                &key,
+                &file_name,
+                start_offset,
+                chunk_size,
+                Some(headers),
+            )
+            .await
+        }
+    })
+    .await;
+    let etag = etag?;
     let elapsed = start.elapsed();
-    match resp.e_tag() {
-        Some(etag) => println!("etag: {}", etag.trim_matches('"')),
-        None => eprintln!("No etag in response"),
-    }
-    println!(
-        "Uploaded chunk of size {} from file {} in {:.2} s",
+    verbosity.report(
+        "chunk",
+        "uploaded",
+        1,
+        1,
         chunk_size,
-        file_name,
-        elapsed.as_secs_f32()
+        elapsed,
+        request_id.as_deref(),
+    );
+    println!("etag: {}", etag);
+    println!(
+        "{}",
+        summary_line(
+            &format!("Uploaded a chunk of {}:", file_name),
+            chunk_size,
+            elapsed
+        )
     );
     Ok(())
 }
@@ -1,46 +1,153 @@
-use aws_sdk_s3::model::CompletedMultipartUpload;
-use aws_sdk_s3::model::CompletedPart;
-use aws_sdk_s3::types::ByteStream;
-use aws_sdk_s3::{Client, Endpoint, Error};
-use std::time::Instant;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
-use tokio_util::codec::{BytesCodec, FramedRead};
+use aws_sdk_s3::{Client, Endpoint};
+use http::Uri;
+use s3_service::cli::{parse_duration, parse_endpoint_uri};
+#[cfg(feature = "debug-tools")]
+use s3_service::debug_schedule::{upload_multipart_parallel_with_schedule, DebugSchedule};
+use s3_service::dir_marker::check_upload_key;
+use s3_service::retry::RetryPolicy;
+use s3_service::shutdown::{Shutdown, DEFAULT_GRACE_PERIOD};
+use s3_service::units::{format_duration, summary_line};
+use s3_service::upload::{auto_buffer_capacity, upload_multipart_parallel_with_shutdown};
+use s3_service::verbosity::{request_id_client, VerbosityConfig};
+use s3_service::warmup::warm_connections;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
 #[cfg(not(target_env = "msvc"))]
 use tikv_jemallocator::Jemalloc;
 
 #[cfg(not(target_env = "msvc"))]
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The profile in the .aws/credentials file.
+    profile: String,
+
+    /// The endpoint URL.
+    #[structopt(parse(try_from_str = parse_endpoint_uri))]
+    url: Uri,
+
+    /// The name of the bucket.
+    bucket: String,
+
+    /// The key of the uploaded object.
+    key: String,
+
+    /// The file to upload.
+    file_name: String,
+
+    /// The number of parts.
+    num_parts: usize,
+
+    /// The read buffer size.
+    buffer_capacity: Option<usize>,
+
+    /// Size the read buffer from the part size and the available memory.
+    #[structopt(long, conflicts_with = "buffer-capacity")]
+    auto_buffer: bool,
+
+    /// The number of connections opened before the upload starts.
+    #[structopt(long, default_value = "0")]
+    warm_connections: usize,
+
+    /// Warm up with one-byte ranged GETs on this key instead of HeadBucket.
+    #[structopt(long)]
+    warm_key: Option<String>,
+
+    /// The number of attempts for each part.
+    #[structopt(long, default_value = "4")]
+    max_attempts: u32,
+
+    /// The longest wait honored from a Retry-After header.
+    #[structopt(long, default_value = "60s", parse(try_from_str = parse_duration))]
+    max_retry_after: Duration,
+
+    /// How long the parts in flight get to finish after Ctrl-C or SIGTERM,
+    /// before the upload is aborted.
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    graceful_timeout: Option<Duration>,
+
+    /// Start the parts in an order shuffled from a seed, as seed=N or
+    /// seed=N,concurrency=N, and print the order they ran in as JSON lines.
+    #[cfg(feature = "debug-tools")]
+    #[structopt(long)]
+    debug_schedule: Option<DebugSchedule>,
+
+    /// With --debug-schedule, a TOML file of fixed delays before some parts.
+    #[cfg(feature = "debug-tools")]
+    #[structopt(long, parse(from_os_str))]
+    debug_delays: Option<std::path::PathBuf>,
+
+    /// Upload even to a key ending in /, which the console shows as a
+    /// folder rather than an object.
+    #[structopt(long)]
+    allow_dir_marker: bool,
+
+    /// Print the size, time, rate, and request ID of each part.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
 /// Parallel multipart upload, one task per part.
 ///
 /// ## Usage
 /// ```
 /// upload-file-multipart-parallel <profile> <url> <bucket> <key> \
-///   <input file> <number of parts> [optional read buffer size]
+///   <input file> <number of parts> [optional read buffer size | --auto-buffer] \
+///   [--warm-connections N [--warm-key KEY]] \
+///   [--max-attempts N] [--max-retry-after DURATION] [--graceful-timeout DURATION] \
+///   [--debug-schedule seed=N[,concurrency=N] [--debug-delays FILE]] [--allow-dir-marker] [-v]
 /// ```
 ///
+/// With `-v`, a line is printed as each part is uploaded, with its size,
+/// time, rate, and request ID.
+///
+/// On Ctrl-C or SIGTERM no more parts are started, the parts in flight get
+/// `--graceful-timeout` (30 seconds by default) to finish, and the upload is
+/// aborted. The exit code is then 130 for Ctrl-C and 143 for SIGTERM. A
+/// second signal stops at once.
+///
+/// `--debug-schedule` is only available when built with the `debug-tools`
+/// feature. It replays the same interleaving of the parts for the same seed
+/// against a server answering in constant time, such as a mock server; the
+/// events are printed to stderr whether or not the upload succeeds.
+///
 #[tokio::main]
 async fn main() -> Result<(), aws_sdk_s3::Error> {
+    tracing_subscriber::fmt::init();
+
     const REGION: &str = "us-east-1";
-    let args = std::env::args().collect::<Vec<_>>();
-    let usage = format!(
-        "{} <profile> <url> <bucket> <key> <input file> <number of parts>",
-        args[0]
-    );
-    let profile = args.get(1).expect(&usage);
-    let url = args.get(2).expect(&usage);
-    let bucket = args.get(3).expect(&usage);
-    let key = args.get(4).expect(&usage);
-    let file_name = args.get(5).expect(&usage);
-    let num_parts = args
-        .get(6)
-        .expect(&usage)
-        .parse::<usize>()
-        .expect("Error parsing num parts");
-    let buffer_capacity = if let Some(arg) = args.get(7) {
-        Some(arg.parse::<usize>().expect("Wrong buffer size format"))
+    let Opt {
+        profile,
+        url,
+        bucket,
+        key,
+        file_name,
+        num_parts,
+        buffer_capacity,
+        auto_buffer,
+        warm_connections: warm_count,
+        warm_key,
+        max_attempts,
+        max_retry_after,
+        graceful_timeout,
+        #[cfg(feature = "debug-tools")]
+        debug_schedule,
+        #[cfg(feature = "debug-tools")]
+        debug_delays,
+        allow_dir_marker,
+        verbose,
+    } = Opt::from_args();
+    check_upload_key(&key, allow_dir_marker)?;
+    let verbosity = VerbosityConfig::from_flag(verbose);
+    let file_size = std::fs::metadata(&file_name)
+        .map_err(|err| aws_sdk_s3::Error::Unhandled(Box::new(err)))?
+        .len();
+    let buffer_capacity = if auto_buffer {
+        Some(auto_buffer_capacity(file_size, num_parts as u64))
     } else {
-        None
+        buffer_capacity
     };
     // credentials are read from .aws/credentials file
     let conf = aws_config::from_env()
@@ -52,155 +159,74 @@ async fn main() -> Result<(), aws_sdk_s3::Error> {
         )
         .load()
         .await;
-    let uri = url.parse::<http::uri::Uri>().expect("Invalid URL");
-    let ep = Endpoint::immutable(uri);
+    let ep = Endpoint::immutable(url);
     let s3_conf = aws_sdk_s3::config::Builder::from(&conf)
         .endpoint_resolver(ep)
         .build();
-    let client = Client::from_conf(s3_conf);
+    let client = if verbosity.show_request_ids {
+        request_id_client(s3_conf)
+    } else {
+        Client::from_conf(s3_conf)
+    };
+    if warm_count > 0 {
+        let warm_up = warm_connections(&client, &bucket, warm_key.as_deref(), warm_count).await?;
+        println!(
+            "Warmed up {} connections in {}",
+            warm_count,
+            format_duration(warm_up)
+        );
+    }
+    let policy = RetryPolicy {
+        max_attempts,
+        max_retry_after_ms: max_retry_after.as_millis() as u64,
+        ..Default::default()
+    };
     let start = Instant::now();
-    upload_multipart_parallel(
+    #[cfg(feature = "debug-tools")]
+    if let Some(mut schedule) = debug_schedule {
+        if let Some(path) = debug_delays {
+            schedule.load_delays(&path)?;
+        }
+        let result = upload_multipart_parallel_with_schedule(
+            &client,
+            &bucket,
+            &key,
+            &file_name,
+            num_parts,
+            buffer_capacity,
+            &policy,
+            &schedule,
+        )
+        .await;
+        for event in schedule.events() {
+            eprintln!("{}", serde_json::to_string(&event).unwrap());
+        }
+        println!("{}", result?);
+        println!("{}", summary_line("Uploaded", file_size, start.elapsed()));
+        return Ok(());
+    }
+    let shutdown = Shutdown::new(graceful_timeout.unwrap_or(DEFAULT_GRACE_PERIOD));
+    shutdown.listen_for_signals();
+    let result = upload_multipart_parallel_with_shutdown(
         &client,
         &bucket,
-        &file_name,
         &key,
+        &file_name,
         num_parts,
         buffer_capacity,
+        None,
+        &policy,
+        verbosity,
+        &shutdown,
     )
-    .await?;
-    let elapsed = start.elapsed();
-    println!("Uploaded file in {:.2} s", elapsed.as_secs_f32());
-    Ok(())
-}
-
-/// Parallel multipart upload, one task per part.
-pub async fn upload_multipart_parallel(
-    client: &Client,
-    bucket: &str,
-    file_name: &str,
-    key: &str,
-    num_parts: usize,
-    buffer_capacity: Option<usize>,
-) -> Result<(), Error> {
-    let len: u64 = std::fs::metadata(file_name)
-        .map_err(|err| Error::Unhandled(Box::new(err)))?
-        .len();
-    let num_parts = num_parts as u64;
-    let chunk_size = len / num_parts;
-    let last_chunk_size = chunk_size + len % num_parts;
-
-    // Initiate multipart upload and store upload id.
-    let u = client
-        .create_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .send()
-        .await?;
-    let uid = u.upload_id().ok_or(Error::NoSuchUpload(
-        aws_sdk_s3::error::NoSuchUpload::builder()
-            .message("No upload ID")
-            .build(),
-    ))?;
-    // Iterate over file chunks, changing the file pointer at each iteration
-    // and storing part id and associated etag into vector.
-    let mut handles = Vec::new();
-    for i in 0..num_parts {
-        let client = client.clone();
-        let bucket = bucket.to_string();
-        let key = key.to_string();
-        let part_id = (i + 1) as i32;
-        let size = if i != (num_parts - 1) {
-            chunk_size
-        } else {
-            last_chunk_size
-        };
-        let offset = (i * chunk_size) as u64;
-        let uid = uid.to_string();
-        let file_name = file_name.to_string();
-
-        let cp = tokio::spawn(async move {
-            upload_part(
-                client,
-                file_name,
-                bucket,
-                key,
-                part_id,
-                uid,
-                offset,
-                size,
-                buffer_capacity,
-            )
-        });
-        handles.push(cp);
-    }
-    let mut completed_parts = Vec::new();
-    for h in handles {
-        let p = h
-            .await
-            .map_err(|err| Error::Unhandled(Box::new(err)))?
-            .await?;
-        completed_parts.push(p);
-    }
-    // Complete multipart upload, sending the (etag, part id) list along the request.
-    let b = CompletedMultipartUpload::builder()
-        .set_parts(Some(completed_parts))
-        .build();
-    let completed = client
-        .complete_multipart_upload()
-        .multipart_upload(b)
-        .upload_id(uid.clone())
-        .bucket(bucket)
-        .key(key)
-        .send()
-        .await?;
-    // Print etag removing quotes.
-    if let Some(etag) = completed.e_tag {
-        println!("{}", etag.replace("\"", ""));
-    } else {
-        eprintln!("Error receiving etag");
+    .await;
+    if let (Some(signal), Err(err)) = (shutdown.signal(), &result) {
+        eprintln!("{}", err);
+        std::process::exit(signal.exit_code());
     }
+    let etag = result?;
+    let elapsed = start.elapsed();
+    println!("{}", etag);
+    println!("{}", summary_line("Uploaded", file_size, elapsed));
     Ok(())
 }
-
-/// Upload single plart and return etag
-async fn upload_part(
-    client: Client,
-    file_name: String,
-    bucket: String,
-    key: String,
-    part_num: i32,
-    uid: String,
-    offset: u64,
-    size: u64,
-    buffer_capacity: Option<usize>,
-) -> Result<CompletedPart, Error> {
-    let mut file = tokio::fs::File::open(file_name)
-        .await
-        .map_err(|err| Error::Unhandled(Box::new(err)))?;
-    file.seek(std::io::SeekFrom::Start(offset))
-        .await
-        .map_err(|err| Error::Unhandled(Box::new(err)))?;
-    let file_chunk = file.take(size);
-    let stream = if let Some(capacity) = buffer_capacity {
-        FramedRead::with_capacity(file_chunk, BytesCodec::new(), capacity)
-    } else {
-        FramedRead::new(file_chunk, BytesCodec::new())
-    };
-    let b = hyper::Body::wrap_stream(stream);
-    let body = ByteStream::from(b);
-    let up = client
-        .upload_part()
-        .bucket(bucket)
-        .key(key)
-        .content_length(size as i64)
-        .upload_id(uid)
-        .part_number(part_num)
-        .body(body)
-        .send()
-        .await?;
-    let cp = CompletedPart::builder()
-        .set_e_tag(up.e_tag)
-        .part_number(part_num)
-        .build();
-    Ok(cp)
-}
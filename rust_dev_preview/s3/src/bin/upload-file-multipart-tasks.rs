@@ -1,226 +1,304 @@
-use aws_sdk_s3::model::CompletedMultipartUpload;
-use aws_sdk_s3::model::CompletedPart;
-use aws_sdk_s3::types::ByteStream;
-use aws_sdk_s3::{Client, Endpoint, Error};
+use aws_sdk_s3::{Client, Endpoint};
+use s3_service::cli::parse_endpoint_uri;
+use s3_service::dir_marker::check_upload_key;
+use s3_service::notify::{notify_sns_after_upload, UploadPayload};
+use s3_service::runtime::{build_runtime, RuntimeFlavor};
+use s3_service::units::{format_duration, summary_line};
+use s3_service::upload::{
+    auto_buffer_capacity, upload_multipart_parallel_with_options, ParallelUploadOptions,
+};
+use s3_service::upload_config::{write_default_config, UploadConfig};
+use s3_service::verbosity::{request_id_client, VerbosityConfig};
+use s3_service::warmup::warm_connections;
+use std::path::PathBuf;
 use std::time::Instant;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
-use tokio_util::codec::{BytesCodec, FramedRead};
-use tokio::task;
+use structopt::StructOpt;
+use validator::Validate;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The profile in the .aws/credentials file.
+    profile: Option<String>,
+
+    /// The endpoint URL.
+    url: Option<String>,
+
+    /// The name of the bucket.
+    bucket: Option<String>,
+
+    /// The key of the uploaded object.
+    key: Option<String>,
+
+    /// The file to upload.
+    file_name: Option<String>,
+
+    /// The number of parts.
+    num_parts: Option<usize>,
+
+    /// The number of worker threads. Ignored with `--runtime current`.
+    num_threads: Option<usize>,
+
+    /// The read buffer size.
+    buffer_capacity: Option<usize>,
+
+    /// Size the read buffer from the part size and the available memory.
+    #[structopt(long, conflicts_with = "buffer-capacity")]
+    auto_buffer: bool,
+
+    /// The number of connections opened before the timed upload starts.
+    /// Defaults to the number of parts.
+    #[structopt(long)]
+    warm_connections: Option<usize>,
+
+    /// Warm up with one-byte ranged GETs on this key instead of HeadBucket.
+    #[structopt(long)]
+    warm_key: Option<String>,
+
+    /// Start the upload on cold connections.
+    #[structopt(long)]
+    no_warm_up: bool,
+
+    /// The prefix of the names of the worker threads, numbered from 0.
+    /// Defaults to `s3-upload`.
+    #[structopt(long)]
+    thread_name: Option<String>,
+
+    /// The Tokio runtime: `multi` (the default) for a pool of worker
+    /// threads, or `current` to run everything on the main thread.
+    #[structopt(long)]
+    runtime: Option<RuntimeFlavor>,
+
+    /// Upload even to a key ending in /, which the console shows as a
+    /// folder rather than an object.
+    #[structopt(long)]
+    allow_dir_marker: bool,
+
+    /// Print the size, time, rate, and request ID of each part.
+    #[structopt(short, long)]
+    verbose: bool,
+
+    /// A TOML file of settings, overridden by the command line.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Write a configuration file listing every setting, commented out.
+    WriteDefaultConfig {
+        #[structopt(parse(from_os_str), default_value = "upload.toml")]
+        path: PathBuf,
+    },
+}
+
+/// `Some(true)` for a flag that was passed, so that a flag left out does
+/// not override the configuration file.
+fn flag(set: bool) -> Option<bool> {
+    Some(true).filter(|_| set)
+}
+
+impl Opt {
+    fn into_config(self) -> UploadConfig {
+        UploadConfig {
+            profile: self.profile,
+            url: self.url,
+            bucket: self.bucket,
+            key: self.key,
+            file_name: self.file_name,
+            num_parts: self.num_parts,
+            num_threads: self.num_threads,
+            runtime: self.runtime,
+            thread_name: self.thread_name,
+            buffer_capacity: self.buffer_capacity,
+            auto_buffer: flag(self.auto_buffer),
+            warm_connections: self.warm_connections,
+            warm_key: self.warm_key,
+            no_warm_up: flag(self.no_warm_up),
+            allow_dir_marker: flag(self.allow_dir_marker),
+            verbose: flag(self.verbose),
+            ..Default::default()
+        }
+    }
+}
+
 /// Parallel multipart upload, one task per part.
 /// Number of worker threads and read buffer size can be configured from
 /// the command line.
 ///
+/// Before the timed upload, as many connections as there are parts are opened
+/// so that connection setup does not skew the measurement; the warm-up time is
+/// reported separately.
+///
+/// The worker threads are named `s3-upload-0`, `s3-upload-1`, and so on, or
+/// after `--thread-name`, to tell them apart in `top -H` and profilers.
+///
+/// The upload itself is `s3_service::upload::upload_multipart_parallel`,
+/// which runs in any Tokio runtime; this binary only builds one, with
+/// `--runtime current` to compare against a single thread.
+///
+/// The settings can also come from a TOML file passed with `--config`,
+/// which adds retries, a limit on the parts in flight, encryption, tags,
+/// and an SNS notification; the command line wins over the file.
+/// `write-default-config` writes a template of every setting.
+///
 /// ## Usage
 /// ```
-/// upload-file-multipart-parallel <profile> <url> <bucket> <key> \
-///   <input file> <number of parts> <number of workers> [optional read buffer size]
+/// upload-file-multipart-tasks <profile> <url> <bucket> <key> \
+///   <input file> <number of parts> <number of workers> \
+///   [optional read buffer size | --auto-buffer] \
+///   [--warm-connections N] [--warm-key KEY] [--no-warm-up] [--thread-name NAME] \
+///   [--runtime multi|current] [--allow-dir-marker] [-v] [--config FILE]
+/// upload-file-multipart-tasks --config FILE [arguments overriding FILE]
+/// upload-file-multipart-tasks write-default-config [FILE]
 /// ```
 ///
+/// With `-v`, a line is printed as each part is uploaded, with its size,
+/// time, rate, and request ID.
+///
 fn main() -> Result<(), aws_sdk_s3::Error> {
+    tracing_subscriber::fmt::init();
+
     const REGION: &str = "us-east-1";
-    let args = std::env::args().collect::<Vec<_>>();
-    let usage = format!(
-        "{} <profile> <url> <bucket> <key> <input file> <number of parts>",
-        args[0]
+    let mut opt = Opt::from_args();
+    if let Some(Command::WriteDefaultConfig { path }) = opt.command.take() {
+        write_default_config(&path)?;
+        println!("Wrote {}", path.display());
+        return Ok(());
+    }
+    let file = match &opt.config {
+        Some(path) => UploadConfig::load(path)?,
+        None => UploadConfig::default(),
+    };
+    let config = opt.into_config().or(file);
+    config
+        .validate()
+        .map_err(|err| aws_sdk_s3::Error::Unhandled(Box::new(err)))?;
+    let missing = config.missing();
+    if !missing.is_empty() {
+        return Err(aws_sdk_s3::Error::Unhandled(Box::from(format!(
+            "Missing {}: pass them as arguments or set them with --config",
+            missing.join(", ")
+        ))));
+    }
+    let headers = config.headers();
+    let policy = config.retry_policy();
+    let UploadConfig {
+        profile,
+        url,
+        bucket,
+        key,
+        file_name,
+        num_parts,
+        num_threads,
+        runtime,
+        thread_name,
+        buffer_capacity,
+        auto_buffer,
+        concurrency,
+        warm_connections: warm_count,
+        warm_key,
+        no_warm_up,
+        allow_dir_marker,
+        verbose,
+        notify_sns_topic_arn,
+        ..
+    } = config;
+    // `missing` is empty, so the required settings are all set.
+    let (profile, url, bucket, key, file_name, num_parts, num_threads) = (
+        profile.unwrap(),
+        url.unwrap(),
+        bucket.unwrap(),
+        key.unwrap(),
+        file_name.unwrap(),
+        num_parts.unwrap(),
+        num_threads.unwrap(),
     );
-    let profile = args.get(1).expect(&usage);
-    let url = args.get(2).expect(&usage);
-    let bucket = args.get(3).expect(&usage);
-    let key = args.get(4).expect(&usage);
-    let file_name = args.get(5).expect(&usage);
-    let num_parts = args
-        .get(6)
-        .expect(&usage)
-        .parse::<usize>()
-        .expect("Error parsing num parts");
-    let num_threads = args
-        .get(7)
-        .expect(&usage)
-        .parse::<usize>()
-        .expect("Error parsing num threads");
-    let buffer_capacity = if let Some(arg) = args.get(8) {
-        Some(arg.parse::<usize>().expect("Wrong buffer size format"))
+    check_upload_key(&key, allow_dir_marker.unwrap_or(false))?;
+    let uri =
+        parse_endpoint_uri(&url).map_err(|err| aws_sdk_s3::Error::Unhandled(Box::from(err)))?;
+    let verbosity = VerbosityConfig::from_flag(verbose.unwrap_or(false));
+    let file_size = std::fs::metadata(&file_name)
+        .map_err(|err| aws_sdk_s3::Error::Unhandled(Box::new(err)))?
+        .len();
+    let buffer_capacity = if auto_buffer.unwrap_or(false) {
+        Some(auto_buffer_capacity(file_size, num_parts as u64))
     } else {
-        None
+        buffer_capacity
     };
+    let warm_count = if no_warm_up.unwrap_or(false) {
+        0
+    } else {
+        warm_count.unwrap_or(num_parts)
+    };
+    let options = ParallelUploadOptions {
+        buffer_capacity,
+        headers,
+        policy,
+        verbosity,
+        max_concurrent_parts: concurrency,
+    };
+    let runtime = runtime.unwrap_or(RuntimeFlavor::MultiThread);
+    let thread_name = thread_name.unwrap_or_else(|| "s3-upload".to_string());
     //Note: the total number of threads spawn should be number or worker threads + 1
-    tokio::runtime::Builder::new_multi_thread()
-        .worker_threads(num_threads)
-        .enable_all()
-        .build()
-        .map_err(|err| Error::Unhandled(Box::new(err)))?
-        .block_on(async move {
-            // credentials are read from .aws/credentials file
-            let conf = aws_config::from_env()
-                .region(REGION)
-                .credentials_provider(
-                    aws_config::profile::ProfileFileCredentialsProvider::builder()
-                        .profile_name(profile)
-                        .build(),
-                )
-                .load()
-                .await;
-            let uri = url.parse::<http::uri::Uri>().expect("Invalid URL");
-            let ep = Endpoint::immutable(uri);
-            let s3_conf = aws_sdk_s3::config::Builder::from(&conf)
-                .endpoint_resolver(ep)
-                .build();
-            let client = Client::from_conf(s3_conf);
-            let start = Instant::now();
-            upload_multipart_parallel(
-                &client,
-                &bucket,
-                &file_name,
-                &key,
-                num_parts,
-                buffer_capacity,
+    build_runtime(runtime, num_threads, &thread_name).block_on(async move {
+        // credentials are read from .aws/credentials file
+        let conf = aws_config::from_env()
+            .region(REGION)
+            .credentials_provider(
+                aws_config::profile::ProfileFileCredentialsProvider::builder()
+                    .profile_name(profile)
+                    .build(),
             )
-            .await.expect("Error launching upload");
-            let elapsed = start.elapsed();
-            println!("Uploaded file in {:.2} s", elapsed.as_secs_f32());
-            Ok(())
-        })
-}
-//  to set number of threads:
-//    let mut rt = runtime::Builder::new()
-//                 .core_threads(4)
-//                 .build()
-//                 .unwrap();
-//    rt.spawn(...);
-/// Parallel multipart upload, one task per part.
-pub async fn upload_multipart_parallel(
-    client: &Client,
-    bucket: &str,
-    file_name: &str,
-    key: &str,
-    num_parts: usize,
-    buffer_capacity: Option<usize>,
-) -> Result<(), Error> {
-    let len: u64 = std::fs::metadata(file_name)
-        .map_err(|err| Error::Unhandled(Box::new(err)))?
-        .len();
-    let num_parts = num_parts as u64;
-    let chunk_size = len / num_parts;
-    let last_chunk_size = chunk_size + len % num_parts;
-
-    // Initiate multipart upload and store upload id.
-    let u = client
-        .create_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .send()
-        .await?;
-    let uid = u.upload_id().ok_or(Error::NoSuchUpload(
-        aws_sdk_s3::error::NoSuchUpload::builder()
-            .message("No upload ID")
-            .build(),
-    ))?;
-    // Iterate over file chunks, changing the file pointer at each iteration
-    // and storing part id and associated etag into vector.
-    let mut handles = Vec::new();
-    for i in 0..num_parts {
-        let client = client.clone();
-        let bucket = bucket.to_string();
-        let key = key.to_string();
-        let part_id = (i + 1) as i32;
-        let size = if i != (num_parts - 1) {
-            chunk_size
+            .load()
+            .await;
+        let ep = Endpoint::immutable(uri);
+        let s3_conf = aws_sdk_s3::config::Builder::from(&conf)
+            .endpoint_resolver(ep)
+            .build();
+        let client = if verbosity.show_request_ids {
+            request_id_client(s3_conf)
         } else {
-            last_chunk_size
+            Client::from_conf(s3_conf)
         };
-        let offset = (i * chunk_size) as u64;
-        let uid = uid.to_string();
-        let file_name = file_name.to_string();
-
-        let cp = tokio::spawn(async move {
-            #[cfg(debug_assertions)]
-            {
-                use std::thread;
-                println!("{:?}", thread::current().id());
-            }
-            task::block_in_place(move || {
-            upload_part(
-                client,
-                file_name,
-                bucket,
-                key,
-                part_id,
-                uid,
-                offset,
-                size,
-                buffer_capacity,
-            )})
-        });
-        handles.push(cp);
-    }
-    let mut completed_parts = Vec::new();
-    for h in handles {
-        let p = h
-            .await
-            .map_err(|err| Error::Unhandled(Box::new(err)))?
-            .await?;
-        completed_parts.push(p);
-    }
-    // Complete multipart upload, sending the (etag, part id) list along the request.
-    let b = CompletedMultipartUpload::builder()
-        .set_parts(Some(completed_parts))
-        .build();
-    let completed = client
-        .complete_multipart_upload()
-        .multipart_upload(b)
-        .upload_id(uid.clone())
-        .bucket(bucket)
-        .key(key)
-        .send()
-        .await?;
-    // Print etag removing quotes.
-    if let Some(etag) = completed.e_tag {
-        println!("{}", etag.replace("\"", ""));
-    } else {
-        eprintln!("Error receiving etag");
-    }
-    Ok(())
-}
-
-/// Upload single plart and return etag
-async fn upload_part(
-    client: Client,
-    file_name: String,
-    bucket: String,
-    key: String,
-    part_num: i32,
-    uid: String,
-    offset: u64,
-    size: u64,
-    buffer_capacity: Option<usize>,
-) -> Result<CompletedPart, Error> {
-    let mut file = tokio::fs::File::open(file_name)
+        if warm_count > 0 {
+            let warm_up =
+                warm_connections(&client, &bucket, warm_key.as_deref(), warm_count).await?;
+            println!(
+                "Warmed up {} connections in {}",
+                warm_count,
+                format_duration(warm_up)
+            );
+        }
+        let start = Instant::now();
+        let e_tag = upload_multipart_parallel_with_options(
+            &client, &bucket, &key, &file_name, num_parts, &options,
+        )
         .await
-        .map_err(|err| Error::Unhandled(Box::new(err)))?;
-    file.seek(std::io::SeekFrom::Start(offset))
-        .await
-        .map_err(|err| Error::Unhandled(Box::new(err)))?;
-    let file_chunk = file.take(size);
-    let stream = if let Some(capacity) = buffer_capacity {
-        FramedRead::with_capacity(file_chunk, BytesCodec::new(), capacity)
-    } else {
-        FramedRead::new(file_chunk, BytesCodec::new())
-    };
-    let b = hyper::Body::wrap_stream(stream);
-    let body = ByteStream::from(b);
-    let up = client
-        .upload_part()
-        .bucket(bucket)
-        .key(key)
-        .content_length(size as i64)
-        .upload_id(uid)
-        .part_number(part_num)
-        .body(body)
-        .send()
-        .await?;
-    let cp = CompletedPart::builder()
-        .set_e_tag(up.e_tag)
-        .part_number(part_num)
-        .build();
-    Ok(cp)
+        .expect("Error launching upload");
+        // Print etag removing quotes.
+        let e_tag = e_tag.replace("\"", "");
+        println!("{}", e_tag);
+        let elapsed = start.elapsed();
+        println!("{}", summary_line("Uploaded", file_size, elapsed));
+        if let Some(topic_arn) = notify_sns_topic_arn {
+            let message = UploadPayload {
+                bucket,
+                key: key.clone(),
+                e_tag,
+                size: file_size,
+            };
+            let sns_client = aws_sdk_sns::Client::new(&conf);
+            match notify_sns_after_upload(&sns_client, &topic_arn, message).await {
+                Ok(message_id) => println!("Published message {} to {}", message_id, topic_arn),
+                Err(err) => eprintln!(
+                    "Warning: {} was uploaded, but not announced on {}: {}",
+                    key, topic_arn, err
+                ),
+            }
+        }
+        Ok(())
+    })
 }
@@ -1,46 +1,166 @@
-use aws_sdk_s3::model::CompletedMultipartUpload;
-use aws_sdk_s3::model::CompletedPart;
-use aws_sdk_s3::types::ByteStream;
-use aws_sdk_s3::{Client, Endpoint, Error};
+use aws_sdk_s3::{Client, Endpoint};
+use chrono::Utc;
+use http::Uri;
+use s3_service::cli::{parse_endpoint_uri, parse_size};
+use s3_service::dir_marker::check_upload_key;
+use s3_service::failover::EndpointPool;
+use s3_service::publish::{publish_via_temp, PublishConditions};
+use s3_service::units::{format_duration, summary_line};
+use s3_service::upload::{
+    parse_expires, upload_multipart_window_with_verbosity, SourceWindow, UploadHeaders,
+};
+use s3_service::verbosity::{request_id_client, VerbosityConfig};
+use s3_service::warmup::warm_connections;
 use std::time::Instant;
-use tokio::io::{AsyncReadExt, AsyncSeekExt};
-use tokio_util::codec::{BytesCodec, FramedRead};
+use structopt::StructOpt;
 #[cfg(not(target_env = "msvc"))]
 use tikv_jemallocator::Jemalloc;
 
 #[cfg(not(target_env = "msvc"))]
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The profile in the .aws/credentials file.
+    profile: String,
+
+    /// The endpoint URL.
+    #[structopt(parse(try_from_str = parse_endpoint_uri))]
+    url: Uri,
+
+    /// The name of the bucket.
+    bucket: String,
+
+    /// The key of the uploaded object.
+    key: String,
+
+    /// The file to upload.
+    file_name: String,
+
+    /// The number of parts.
+    num_parts: usize,
+
+    /// The read buffer size.
+    buffer_capacity: Option<usize>,
+
+    /// Upload only the bytes of the file from this offset.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    source_offset: Option<u64>,
+
+    /// Upload only this many bytes of the file. Runs to the end of the file
+    /// if not supplied.
+    #[structopt(long, parse(try_from_str = parse_size))]
+    source_length: Option<u64>,
+
+    /// The Content-Disposition header stored with the object.
+    #[structopt(long)]
+    content_disposition: Option<String>,
+
+    /// The Cache-Control header stored with the object.
+    #[structopt(long)]
+    cache_control: Option<String>,
+
+    /// The Content-Encoding header stored with the object.
+    #[structopt(long)]
+    content_encoding: Option<String>,
+
+    /// The Content-Language header stored with the object.
+    #[structopt(long)]
+    content_language: Option<String>,
+
+    /// The Expires header stored with the object (RFC 3339 or HTTP date).
+    #[structopt(long, parse(try_from_str = parse_expires))]
+    expires: Option<chrono::DateTime<Utc>>,
+
+    /// The number of connections opened before the upload starts.
+    #[structopt(long, default_value = "0")]
+    warm_connections: usize,
+
+    /// Warm up with one-byte ranged GETs on this key instead of HeadBucket.
+    #[structopt(long)]
+    warm_key: Option<String>,
+
+    /// Upload to a temporary key, then copy it onto the key and delete it.
+    #[structopt(long)]
+    publish_via_temp: bool,
+
+    /// With --publish-via-temp, only replace an object with this ETag.
+    #[structopt(long)]
+    if_match: Option<String>,
+
+    /// With --publish-via-temp, only publish if the key does not exist:
+    /// `*`, the only value S3 accepts on a write.
+    #[structopt(long)]
+    if_none_match: Option<String>,
+
+    /// Upload even to a key ending in /, which the console shows as a
+    /// folder rather than an object.
+    #[structopt(long)]
+    allow_dir_marker: bool,
+
+    /// Print the size, time, rate, and request ID of each part.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
 /// Multipart upload example
 ///
 /// ## Usage
 /// ```shell
 /// upload-file-multipart <profile> <url> <bucket> <key> <input file> \
-///   <number of parts> [optional read buffer size]
+///   <number of parts> [optional read buffer size] \
+///   [--source-offset SIZE] [--source-length SIZE] \
+///   [--content-disposition VALUE] [--cache-control VALUE] [--content-encoding VALUE] \
+///   [--content-language VALUE] [--expires DATE] \
+///   [--warm-connections N [--warm-key KEY]] \
+///   [--publish-via-temp [--if-match ETAG] [--if-none-match '*']] [--allow-dir-marker] [-v]
 /// ```
 ///
+/// With `--source-offset` and `--source-length` only that window of the file
+/// is uploaded, split into the parts; a window extending past the end of the
+/// file is rejected. With `--publish-via-temp` the result is printed as JSON;
+/// the conditions are sent with the copy onto the key, so S3 checks them as
+/// it writes. Standard output holds only the ETag or the JSON, the timings
+/// go to stderr.
+/// With `-v`, a line is printed as each part is uploaded, with its size,
+/// time, rate, and request ID.
 #[tokio::main]
 async fn main() -> Result<(), aws_sdk_s3::Error> {
     const REGION: &str = "us-east-1";
-    let args = std::env::args().collect::<Vec<_>>();
-    let usage = format!(
-        "{} <profile> <url> <bucket> <key> <input file> <number of parts> [buffer size]",
-        args[0]
-    );
-    let profile = args.get(1).expect(&usage);
-    let url = args.get(2).expect(&usage);
-    let bucket = args.get(3).expect(&usage);
-    let key = args.get(4).expect(&usage);
-    let file_name = args.get(5).expect(&usage);
-    let num_parts = args
-        .get(6)
-        .expect(&usage)
-        .parse::<usize>()
-        .expect("Error parsing num parts");
-    let buffer_capacity = if let Some(arg) = args.get(7) {
-        Some(arg.parse::<usize>().expect("Wrong buffer size format"))
-    } else {
-        None
+    let Opt {
+        profile,
+        url,
+        bucket,
+        key,
+        file_name,
+        num_parts,
+        buffer_capacity,
+        source_offset,
+        source_length,
+        content_disposition,
+        cache_control,
+        content_encoding,
+        content_language,
+        expires,
+        warm_connections: warm_count,
+        warm_key,
+        publish_via_temp: via_temp,
+        if_match,
+        if_none_match,
+        allow_dir_marker,
+        verbose,
+    } = Opt::from_args();
+    check_upload_key(&key, allow_dir_marker)?;
+    let verbosity = VerbosityConfig::from_flag(verbose);
+    let window = SourceWindow::for_file(&file_name, source_offset, source_length)?;
+    let headers = UploadHeaders {
+        content_disposition,
+        cache_control,
+        content_encoding,
+        content_language,
+        expires,
+        ..Default::default()
     };
     // credentials are read from .aws/credentials file
     let conf = aws_config::from_env()
@@ -52,119 +172,64 @@ async fn main() -> Result<(), aws_sdk_s3::Error> {
         )
         .load()
         .await;
-    let uri = url.parse::<http::uri::Uri>().expect("Invalid URL");
-    let ep = Endpoint::immutable(uri);
+    let ep = Endpoint::immutable(url);
     let s3_conf = aws_sdk_s3::config::Builder::from(&conf)
         .endpoint_resolver(ep)
         .build();
-    let client = Client::from_conf(s3_conf);
+    let client = if verbosity.show_request_ids {
+        request_id_client(s3_conf)
+    } else {
+        Client::from_conf(s3_conf)
+    };
+    if warm_count > 0 {
+        let warm_up = warm_connections(&client, &bucket, warm_key.as_deref(), warm_count).await?;
+        eprintln!(
+            "Warmed up {} connections in {}",
+            warm_count,
+            format_duration(warm_up)
+        );
+    }
     let start = Instant::now();
-    upload_multipart(
-        &client,
-        &bucket,
-        &key,
-        &file_name,
-        num_parts,
-        buffer_capacity,
-    )
-    .await?;
-    println!("Uploaded file in {} s", start.elapsed().as_secs_f32());
-    Ok(())
-}
-
-/// Multipart upload
-///
-/// 1. retrieve `upload id`
-/// 2. iterate over file chunks and send each chunk as a separate part
-/// 3. store returned `etag` and `part number` into `Vec`
-/// 4. complete upload by sending list of `(etag, part id`) to server
-/// 5. print returned `etag` id
-pub async fn upload_multipart(
-    client: &Client,
-    bucket: &str,
-    key: &str,
-    file_name: &str,
-    num_parts: usize,
-    buffer_capacity: Option<usize>, // None for default
-) -> Result<(), Error> {
-    let len: u64 = std::fs::metadata(file_name)
-        .map_err(|err| Error::Unhandled(Box::new(err)))?
-        .len();
-    let num_parts = num_parts as u64;
-    let file = tokio::fs::File::open(file_name)
-        .await
-        .map_err(|err| Error::Unhandled(Box::new(err)))?;
-    let chunk_size = len / num_parts;
-    let last_chunk_size = chunk_size + len % num_parts;
-    // Initiate multipart upload and store upload id.
-    let u = client
-        .create_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .send()
-        .await?;
-    let uid = u.upload_id().ok_or(Error::NoSuchUpload(
-        aws_sdk_s3::error::NoSuchUpload::builder()
-            .message("No upload ID")
-            .build(),
-    ))?;
-    // Iterate over file chunks, changing the file pointer at each iteration
-    // and storing returned part id and associated etag into vector.
-    let mut completed_parts: Vec<CompletedPart> = Vec::new();
-    for i in 0..num_parts {
-        let size = if i != (num_parts - 1) {
-            chunk_size
-        } else {
-            last_chunk_size
-        };
-        let mut file = file
-            .try_clone()
-            .await
-            .map_err(|err| Error::Unhandled(Box::new(err)))?;
-        file.seek(std::io::SeekFrom::Start((i * chunk_size) as u64))
-            .await
-            .map_err(|err| Error::Unhandled(Box::new(err)))?;
-        let file_chunk = file.take(size);
-        let stream = if let Some(capacity) = buffer_capacity {
-            FramedRead::with_capacity(file_chunk, BytesCodec::new(), capacity)
-        } else {
-            FramedRead::new(file_chunk, BytesCodec::new())
+    if via_temp {
+        let conditions = PublishConditions {
+            if_match,
+            if_none_match,
         };
-        let b = hyper::Body::wrap_stream(stream);
-        let body = ByteStream::from(b);
-        let up = client
-            .upload_part()
-            .bucket(bucket)
-            .key(key)
-            .content_length(size as i64)
-            .upload_id(uid.clone())
-            .part_number((i + 1) as i32)
-            .body(body)
-            .send()
-            .await?;
-        let cp = CompletedPart::builder()
-            .set_e_tag(up.e_tag)
-            .part_number((i + 1) as i32)
-            .build();
-        completed_parts.push(cp);
-    }
-    // Complete multipart upload, sending the (etag, part id) list along the request.
-    let b = CompletedMultipartUpload::builder()
-        .set_parts(Some(completed_parts))
-        .build();
-    let completed = client
-        .complete_multipart_upload()
-        .multipart_upload(b)
-        .upload_id(uid.clone())
-        .bucket(bucket)
-        .key(key)
-        .send()
+        let result = publish_via_temp(
+            &client,
+            &bucket,
+            &key,
+            &file_name,
+            Some(window),
+            num_parts,
+            buffer_capacity,
+            Some(headers),
+            &conditions,
+        )
         .await?;
-    // Print etag removing quotes.
-    if let Some(etag) = completed.e_tag {
-        println!("{}", etag.replace("\"", ""));
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&result).expect("Error serializing result")
+        );
     } else {
-        eprintln!("No etag received");
+        let etag = upload_multipart_window_with_verbosity(
+            &EndpointPool::single(client),
+            &bucket,
+            &key,
+            &file_name,
+            window,
+            num_parts,
+            buffer_capacity,
+            Some(headers),
+            verbosity,
+        )
+        .await?;
+        println!("{}", etag);
     }
+    // Standard output holds only the ETag or the JSON result.
+    eprintln!(
+        "{}",
+        summary_line("Uploaded", window.length, start.elapsed())
+    );
     Ok(())
 }
@@ -0,0 +1,109 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Error, Region, PKG_VERSION};
+use s3_service::role_credentials::{AssumeRoleProvider, CachingCredentialsProvider};
+use s3_service::upload::{upload_auto, AutoUploadConfig};
+use std::path::Path;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// The AWS Region.
+    #[structopt(short, long)]
+    region: Option<String>,
+
+    /// The name of the bucket.
+    #[structopt(short, long)]
+    bucket: String,
+
+    /// The ARN of the role the files are uploaded with.
+    #[structopt(long)]
+    role_arn: String,
+
+    /// A file to upload. Can be repeated.
+    #[structopt(short, long, number_of_values = 1, required = true)]
+    file: Vec<String>,
+
+    /// Prepended to the name of each file to form its key.
+    #[structopt(short, long, default_value = "")]
+    prefix: String,
+
+    /// Whether to display additional information.
+    #[structopt(short, long)]
+    verbose: bool,
+}
+
+/// Uploads files to a bucket with the credentials of an assumed role,
+/// assuming it once for all the files rather than once per file.
+/// # Arguments
+///
+/// * `-b BUCKET` - The name of the bucket.
+/// * `--role-arn ARN` - The ARN of the role the files are uploaded with.
+/// * `-f FILE ...` - The files to upload.
+/// * `[-p PREFIX]` - Prepended to the name of each file to form its key.
+/// * `[-r REGION]` - The Region in which the clients are created.
+///   If not supplied, uses the value of the **AWS_REGION** environment variable.
+///   If the environment variable is not set, defaults to **us-west-2**.
+/// * `[-v]` - Whether to display additional information.
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+
+    let Opt {
+        region,
+        bucket,
+        role_arn,
+        file,
+        prefix,
+        verbose,
+    } = Opt::from_args();
+
+    let region_provider = RegionProviderChain::first_try(region.map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+
+    println!();
+
+    if verbose {
+        println!("S3 client version: {}", PKG_VERSION);
+        println!(
+            "Region:            {}",
+            region_provider.region().await.unwrap().as_ref()
+        );
+        println!("Bucket:            {}", &bucket);
+        println!("Role:              {}", &role_arn);
+        println!("Files:             {}", file.len());
+        println!();
+    }
+
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    let role = AssumeRoleProvider::new(aws_sdk_sts::Client::new(&shared_config), role_arn);
+    let client = Client::from_conf(
+        aws_sdk_s3::config::Builder::from(&shared_config)
+            .credentials_provider(CachingCredentialsProvider::new(role))
+            .build(),
+    );
+
+    for file_name in &file {
+        let name = Path::new(file_name)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| file_name.clone());
+        let key = format!("{}{}", prefix, name);
+        let e_tag = upload_auto(
+            &client,
+            &bucket,
+            &key,
+            file_name,
+            AutoUploadConfig::default(),
+        )
+        .await?;
+        println!("Uploaded {} to {} ({})", file_name, key, e_tag);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,540 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Two-way synchronization of a local directory and a bucket prefix.
+//!
+//! Each file and object is compared with the state recorded at the end of
+//! the previous run, in `.s3-bisync.json` at the root of the directory: a
+//! file whose size or modification time differs from the state changed
+//! locally, and an object whose ETag differs changed remotely. A change on
+//! one side is copied to the other, and a key changed on both sides is a
+//! conflict, resolved by the `ConflictPolicy`. Without a recorded state,
+//! a key that differs between the two sides is a conflict as well.
+//!
+//! Nothing is ever deleted: a file missing on one side is copied from the
+//! other, even when it was deleted there since the previous run.
+//!
+//! Objects are downloaded with the ETag they were listed with, so one
+//! overwritten during the run fails instead of being downloaded as a mix
+//! of both versions and recorded with the ETag of the first.
+
+use crate::download::local_path;
+use crate::ops::S3Ops;
+use crate::scheduler::{
+    transfer_files, ScheduledDownload, ScheduledFile, SchedulerOptions, Transfer, PARTIAL_SUFFIX,
+};
+use crate::shutdown::{Shutdown, DEFAULT_GRACE_PERIOD};
+use crate::sync::{
+    format_mtime, list_remote, newer_than, parse_mtime, walk_directory_filtered, LocalFile,
+    RemoteObject,
+};
+use aws_sdk_s3::{Client, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The state of the previous run, kept at the root of the directory. It is
+/// not synchronized itself.
+pub const BISYNC_STATE_FILE: &str = ".s3-bisync.json";
+
+/// What to do with a key changed on both sides.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    /// Upload the local file over the object.
+    PreferLocal,
+    /// Download the object over the local file.
+    PreferRemote,
+    /// Transfer nothing and fail.
+    Error,
+    /// Keep both: the local file is renamed, as by `conflict_key`, and
+    /// uploaded under its new name, and the object is downloaded in its
+    /// place.
+    Rename,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "prefer-local" => Ok(ConflictPolicy::PreferLocal),
+            "prefer-remote" => Ok(ConflictPolicy::PreferRemote),
+            "error" => Ok(ConflictPolicy::Error),
+            "rename" => Ok(ConflictPolicy::Rename),
+            _ => Err(format!(
+                "Unknown conflict policy {}, expected prefer-local, prefer-remote, error, or rename",
+                value
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BisyncOptions {
+    pub conflict: ConflictPolicy,
+    /// Modification times closer than this are considered equal.
+    pub mtime_window: Duration,
+    /// The concurrency and part size of the transfers in both directions.
+    pub scheduler: SchedulerOptions,
+}
+
+impl Default for BisyncOptions {
+    fn default() -> Self {
+        Self {
+            conflict: ConflictPolicy::Error,
+            mtime_window: Duration::from_secs(2),
+            scheduler: SchedulerOptions::default(),
+        }
+    }
+}
+
+/// A key as both sides had it after it was last synchronized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncedEntry {
+    pub size: u64,
+    /// The modification time of the local file, as by `format_mtime`.
+    pub mtime: String,
+    /// The ETag of the object, without quotes.
+    pub e_tag: String,
+}
+
+impl SyncedEntry {
+    fn local_changed(&self, file: &LocalFile, window: Duration) -> bool {
+        file.size != self.size
+            || parse_mtime(&self.mtime)
+                .map(|mtime| {
+                    newer_than(file.mtime, mtime, window) || newer_than(mtime, file.mtime, window)
+                })
+                .unwrap_or(true)
+    }
+
+    fn remote_changed(&self, object: &RemoteObject) -> bool {
+        object.e_tag.as_deref() != Some(self.e_tag.as_str())
+    }
+}
+
+/// The keys synchronized by previous runs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BisyncState {
+    pub entries: BTreeMap<String, SyncedEntry>,
+}
+
+impl BisyncState {
+    /// Reads the state at `path`, empty if there is none yet.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(Error::Unhandled(Box::new(err))),
+        };
+        serde_json::from_str(&content).map_err(|err| {
+            Error::Unhandled(Box::from(format!(
+                "Invalid sync state {}: {}",
+                path.display(),
+                err
+            )))
+        })
+    }
+
+    /// Writes the state to a temporary file renamed over `path`, so that a
+    /// crash leaves either the previous state or this one.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        let temporary = temporary_path(path);
+        std::fs::write(&temporary, content)
+            .and_then(|_| std::fs::rename(&temporary, path))
+            .map_err(|err| Error::Unhandled(Box::new(err)))
+    }
+
+    fn record(&mut self, file: &LocalFile, e_tag: Option<&str>) {
+        self.entries.insert(
+            file.key.clone(),
+            SyncedEntry {
+                size: file.size,
+                mtime: format_mtime(file.mtime),
+                e_tag: e_tag.unwrap_or_default().to_string(),
+            },
+        );
+    }
+}
+
+/// Why a file is transferred.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BisyncReason {
+    /// The object does not exist.
+    OnlyLocal,
+    /// The local file does not exist.
+    OnlyRemote,
+    ChangedLocally,
+    ChangedRemotely,
+    /// Changed on both sides, resolved by the conflict policy.
+    Conflict,
+}
+
+impl fmt::Display for BisyncReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BisyncReason::OnlyLocal => "missing remotely",
+            BisyncReason::OnlyRemote => "missing locally",
+            BisyncReason::ChangedLocally => "changed locally",
+            BisyncReason::ChangedRemotely => "changed remotely",
+            BisyncReason::Conflict => "changed on both sides",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BisyncAction {
+    Upload {
+        file: LocalFile,
+        reason: BisyncReason,
+    },
+    Download {
+        object: RemoteObject,
+        reason: BisyncReason,
+    },
+    /// A conflict resolved with `ConflictPolicy::Rename`.
+    KeepBoth {
+        file: LocalFile,
+        object: RemoteObject,
+        renamed_key: String,
+    },
+}
+
+impl BisyncAction {
+    /// The action and its reason, as one line of text.
+    pub fn describe(&self) -> String {
+        match self {
+            BisyncAction::Upload { file, reason } => format!("upload {} ({})", file.key, reason),
+            BisyncAction::Download { object, reason } => {
+                format!("download {} ({})", object.key, reason)
+            }
+            BisyncAction::KeepBoth {
+                file, renamed_key, ..
+            } => format!(
+                "upload {} as {} and download {} ({})",
+                file.key,
+                renamed_key,
+                file.key,
+                BisyncReason::Conflict
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BisyncPlan {
+    pub actions: Vec<BisyncAction>,
+    /// The keys that are the same on both sides.
+    pub identical: Vec<(LocalFile, RemoteObject)>,
+    /// The keys changed on both sides, with `ConflictPolicy::Error`.
+    pub conflicts: Vec<String>,
+}
+
+/// The key a conflicting local file is renamed to: `key` with the
+/// modification time of the file before its extension, such as
+/// `notes.conflict-1650000000.txt`.
+pub fn conflict_key(key: &str, mtime: SystemTime) -> String {
+    let secs = mtime
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let name_start = key.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match key[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{}.conflict-{}{}", &key[..dot], secs, &key[dot..])
+        }
+        _ => format!("{}.conflict-{}", key, secs),
+    }
+}
+
+/// Whether the file and the object look the same, without a recorded state.
+fn same_content(file: &LocalFile, object: &RemoteObject, window: Duration) -> bool {
+    file.size == object.size
+        && !newer_than(file.mtime, object.mtime(), window)
+        && !newer_than(object.mtime(), file.mtime, window)
+}
+
+/// Decides, for each key on either side, what to transfer, in key order.
+pub fn plan_bisync(
+    local: Vec<LocalFile>,
+    remote: &HashMap<String, RemoteObject>,
+    state: &BisyncState,
+    options: &BisyncOptions,
+) -> BisyncPlan {
+    let mut plan = BisyncPlan::default();
+    let mut local: HashMap<String, LocalFile> = local
+        .into_iter()
+        .map(|file| (file.key.clone(), file))
+        .collect();
+    let keys: BTreeSet<String> = local
+        .keys()
+        .chain(remote.keys().filter(|key| !key.ends_with('/')))
+        .cloned()
+        .collect();
+    for key in keys {
+        let (file, object) = match (local.remove(&key), remote.get(&key)) {
+            (Some(file), None) => {
+                plan.actions.push(BisyncAction::Upload {
+                    file,
+                    reason: BisyncReason::OnlyLocal,
+                });
+                continue;
+            }
+            (None, Some(object)) => {
+                plan.actions.push(BisyncAction::Download {
+                    object: object.clone(),
+                    reason: BisyncReason::OnlyRemote,
+                });
+                continue;
+            }
+            (Some(file), Some(object)) => (file, object.clone()),
+            (None, None) => continue,
+        };
+        let window = options.mtime_window;
+        let changed = match state.entries.get(&key) {
+            Some(entry) => (
+                entry.local_changed(&file, window),
+                entry.remote_changed(&object),
+            ),
+            None => (true, true),
+        };
+        let action = match changed {
+            (false, false) => None,
+            (true, false) => Some(BisyncAction::Upload {
+                file,
+                reason: BisyncReason::ChangedLocally,
+            }),
+            (false, true) => Some(BisyncAction::Download {
+                object,
+                reason: BisyncReason::ChangedRemotely,
+            }),
+            (true, true) if same_content(&file, &object, window) => None,
+            (true, true) => match options.conflict {
+                ConflictPolicy::PreferLocal => Some(BisyncAction::Upload {
+                    file,
+                    reason: BisyncReason::Conflict,
+                }),
+                ConflictPolicy::PreferRemote => Some(BisyncAction::Download {
+                    object,
+                    reason: BisyncReason::Conflict,
+                }),
+                ConflictPolicy::Rename => Some(BisyncAction::KeepBoth {
+                    renamed_key: conflict_key(&file.key, file.mtime),
+                    file,
+                    object,
+                }),
+                ConflictPolicy::Error => {
+                    plan.conflicts.push(key);
+                    continue;
+                }
+            },
+        };
+        match action {
+            Some(action) => plan.actions.push(action),
+            None => plan.identical.push((file, object)),
+        }
+    }
+    plan
+}
+
+/// Where `BisyncState::save` writes before renaming.
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    PathBuf::from(temporary)
+}
+
+/// Lists the files under `dir` to synchronize, leaving out the state, a
+/// state left half-written, and the partial downloads.
+pub fn walk_bisync_directory(dir: &Path, prefix: &str) -> std::io::Result<Vec<LocalFile>> {
+    let state = dir.join(BISYNC_STATE_FILE);
+    let temporary = temporary_path(&state);
+    walk_directory_filtered(dir, prefix, |path, is_dir| {
+        is_dir
+            || (path != state
+                && path != temporary
+                && !path.to_string_lossy().ends_with(PARTIAL_SUFFIX))
+    })
+}
+
+/// Outcome of `bisync_directory`.
+#[derive(Debug, Default, Clone)]
+pub struct BisyncSummary {
+    pub uploaded: Vec<String>,
+    pub downloaded: Vec<String>,
+    pub identical: Vec<String>,
+    /// Conflicting local files kept under a new key, with that key.
+    pub renamed: Vec<(String, String)>,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Performs `plan` between `dir` and `prefix` in `bucket`, and records the
+/// keys synchronized in `state`, saved in `dir`.
+///
+/// Fails before transferring anything if the plan has conflicts. A failed
+/// transfer keeps the previous state of its key, so it is retried by the
+/// next run.
+pub async fn execute_bisync<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    dir: &Path,
+    prefix: &str,
+    plan: BisyncPlan,
+    mut state: BisyncState,
+    options: &BisyncOptions,
+) -> Result<BisyncSummary, Error> {
+    if !plan.conflicts.is_empty() {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} keys changed both locally and remotely: {}. Choose how to resolve them with \
+             --conflict prefer-local, prefer-remote, or rename",
+            plan.conflicts.len(),
+            plan.conflicts.join(", ")
+        ))));
+    }
+
+    let mut summary = BisyncSummary::default();
+    for (file, object) in &plan.identical {
+        state.record(file, object.e_tag.as_deref());
+        summary.identical.push(file.key.clone());
+    }
+
+    let mut uploads = HashMap::new();
+    let mut downloads = HashMap::new();
+    let mut transfers = Vec::new();
+    for action in plan.actions {
+        let (file, object) = match action {
+            BisyncAction::Upload { file, .. } => (Some(file), None),
+            BisyncAction::Download { object, .. } => (None, Some(object)),
+            BisyncAction::KeepBoth {
+                file,
+                object,
+                renamed_key,
+            } => {
+                let path = local_path(dir, prefix, &renamed_key)?;
+                std::fs::rename(&file.path, &path)
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                summary
+                    .renamed
+                    .push((file.key.clone(), renamed_key.clone()));
+                let renamed = LocalFile {
+                    path,
+                    key: renamed_key,
+                    ..file
+                };
+                (Some(renamed), Some(object))
+            }
+        };
+        if let Some(file) = file {
+            transfers.push(Transfer::Upload(ScheduledFile::from(file.clone())));
+            uploads.insert(file.key.clone(), file);
+        }
+        if let Some(object) = object {
+            let path = local_path(dir, prefix, &object.key)?;
+            transfers.push(Transfer::Download(ScheduledDownload {
+                key: object.key.clone(),
+                path: path.clone(),
+                size: object.size,
+                e_tag: object.e_tag.clone(),
+            }));
+            downloads.insert(object.key.clone(), (path, object));
+        }
+    }
+
+    let shutdown = Shutdown::new(DEFAULT_GRACE_PERIOD);
+    let done = transfer_files(ops, bucket, transfers, &options.scheduler, &shutdown).await;
+
+    for key in done.completed {
+        if let Some(file) = uploads.get(&key) {
+            state.record(file, done.e_tags.get(&key).map(|t| t.as_str()));
+        }
+        summary.uploaded.push(key);
+    }
+    for key in done.downloaded {
+        if let Some((path, object)) = downloads.get(&key) {
+            let metadata =
+                std::fs::metadata(path).map_err(|err| Error::Unhandled(Box::new(err)))?;
+            let file = LocalFile {
+                path: path.clone(),
+                key: key.clone(),
+                size: metadata.len(),
+                mtime: metadata
+                    .modified()
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?,
+            };
+            state.record(&file, object.e_tag.as_deref());
+        }
+        summary.downloaded.push(key);
+    }
+    for file in done.pending {
+        let err = file.error.unwrap_or_else(|| "interrupted".to_string());
+        summary.failed.push((file.key, err));
+    }
+    for (key, err) in done.pending_downloads {
+        let err = err.unwrap_or_else(|| "interrupted".to_string());
+        summary.failed.push((key, err));
+    }
+    summary.uploaded.sort();
+    summary.downloaded.sort();
+    summary.failed.sort();
+
+    state.save(&dir.join(BISYNC_STATE_FILE))?;
+    Ok(summary)
+}
+
+/// Synchronizes `dir` and `prefix` in `bucket` both ways.
+///
+/// With `dry_run`, only prints each action and its reason, and the
+/// conflicts left unresolved.
+pub async fn bisync_directory(
+    client: &Client,
+    bucket: &str,
+    dir: &Path,
+    prefix: &str,
+    options: &BisyncOptions,
+    dry_run: bool,
+) -> Result<BisyncSummary, Error> {
+    let state = BisyncState::load(&dir.join(BISYNC_STATE_FILE))?;
+    let local =
+        walk_bisync_directory(dir, prefix).map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let remote = list_remote(client, bucket, prefix).await?;
+    let plan = plan_bisync(local, &remote, &state, options);
+
+    if dry_run {
+        let mut summary = BisyncSummary::default();
+        for action in &plan.actions {
+            println!("(dry run) {}", action.describe());
+            match action {
+                BisyncAction::Upload { file, .. } => summary.uploaded.push(file.key.clone()),
+                BisyncAction::Download { object, .. } => {
+                    summary.downloaded.push(object.key.clone())
+                }
+                BisyncAction::KeepBoth {
+                    file, renamed_key, ..
+                } => {
+                    summary.uploaded.push(renamed_key.clone());
+                    summary.downloaded.push(file.key.clone());
+                    summary
+                        .renamed
+                        .push((file.key.clone(), renamed_key.clone()));
+                }
+            }
+        }
+        for key in &plan.conflicts {
+            println!("(dry run) conflict {} ({})", key, BisyncReason::Conflict);
+        }
+        summary.identical = plan
+            .identical
+            .into_iter()
+            .map(|(file, _)| file.key)
+            .collect();
+        return Ok(summary);
+    }
+
+    execute_bisync(client, bucket, dir, prefix, plan, state, options).await
+}
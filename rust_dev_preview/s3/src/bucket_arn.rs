@@ -0,0 +1,180 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Bucket, access point, and S3 on Outposts ARNs given where a bucket name
+//! is expected.
+//!
+//! Where access goes through S3 Access Points, the "bucket" is an ARN such
+//! as `arn:aws:s3:us-west-2:123456789012:accesspoint/my-ap`. Requests carry
+//! the ARN in their bucket field and the SDK sends them to the access point,
+//! so the tools only need to let it through: bucket name checks do not
+//! apply to it, and the Region is the one in the ARN, which the client must
+//! be created for. A bucket ARN, `arn:aws:s3:::my-bucket`, is replaced by
+//! the bucket name.
+//!
+//! An access point is only reached with virtual-hosted requests to its own
+//! endpoint, so an ARN cannot be combined with `--endpoint-url`, whose
+//! S3-compatible endpoints are addressed path-style.
+
+use aws_sdk_s3::Error;
+
+/// A parsed ARN naming a bucket or an access point.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BucketArn {
+    /// `arn:{partition}:s3:::{name}`.
+    Bucket { name: String },
+    /// `arn:{partition}:s3:{region}:{account}:accesspoint/{name}`.
+    AccessPoint {
+        arn: String,
+        region: String,
+        account_id: String,
+        name: String,
+    },
+    /// `arn:{partition}:s3-outposts:{region}:{account}:outpost/{outpost}/accesspoint/{name}`.
+    OutpostAccessPoint {
+        arn: String,
+        region: String,
+        account_id: String,
+        outpost_id: String,
+        name: String,
+    },
+}
+
+fn invalid(bucket: &str, reason: &str) -> Error {
+    Error::Unhandled(Box::from(format!(
+        "{} is not a valid bucket or access point ARN: {}",
+        bucket, reason
+    )))
+}
+
+impl BucketArn {
+    /// Parses `bucket` if it is an ARN. A plain bucket name yields `None`; an
+    /// ARN of anything but a bucket or an access point is an error.
+    pub fn parse(bucket: &str) -> Result<Option<Self>, Error> {
+        if !bucket.starts_with("arn:") {
+            return Ok(None);
+        }
+        let fields: Vec<&str> = bucket.splitn(6, ':').collect();
+        if fields.len() != 6 || fields[1].is_empty() {
+            return Err(invalid(
+                bucket,
+                "expected arn:partition:service:region:account:resource",
+            ));
+        }
+        let (service, region, account_id, resource) = (fields[2], fields[3], fields[4], fields[5]);
+        // Access point resources are written with either `/` or `:`.
+        let resource: Vec<&str> = resource.split(|c| c == '/' || c == ':').collect();
+        let arn = bucket.to_string();
+        let parsed = match (service, resource.as_slice()) {
+            ("s3", [name]) if region.is_empty() && account_id.is_empty() => BucketArn::Bucket {
+                name: name.to_string(),
+            },
+            ("s3", ["accesspoint", name]) => BucketArn::AccessPoint {
+                arn,
+                region: region.to_string(),
+                account_id: account_id.to_string(),
+                name: name.to_string(),
+            },
+            ("s3-outposts", ["outpost", outpost_id, "accesspoint", name]) => {
+                BucketArn::OutpostAccessPoint {
+                    arn,
+                    region: region.to_string(),
+                    account_id: account_id.to_string(),
+                    outpost_id: outpost_id.to_string(),
+                    name: name.to_string(),
+                }
+            }
+            ("s3-outposts", ["outpost", _, "bucket", _]) => return Err(invalid(
+                bucket,
+                "S3 on Outposts buckets are reached through an access point; pass the ARN of one",
+            )),
+            _ => {
+                return Err(invalid(
+                    bucket,
+                    "only bucket, access point, and S3 on Outposts access point ARNs are supported",
+                ))
+            }
+        };
+        match &parsed {
+            BucketArn::Bucket { name } if name.is_empty() => {
+                Err(invalid(bucket, "the bucket name is missing"))
+            }
+            BucketArn::AccessPoint {
+                region,
+                account_id,
+                name,
+                ..
+            }
+            | BucketArn::OutpostAccessPoint {
+                region,
+                account_id,
+                name,
+                ..
+            } if region.is_empty() || account_id.is_empty() || name.is_empty() => Err(invalid(
+                bucket,
+                "an access point ARN needs a Region, an account ID, and a name",
+            )),
+            _ => Ok(Some(parsed)),
+        }
+    }
+
+    /// The Region of an access point; a bucket ARN has none.
+    pub fn region(&self) -> Option<&str> {
+        match self {
+            BucketArn::Bucket { .. } => None,
+            BucketArn::AccessPoint { region, .. }
+            | BucketArn::OutpostAccessPoint { region, .. } => Some(region),
+        }
+    }
+
+    /// The value sent in the bucket field of requests.
+    pub fn bucket(&self) -> &str {
+        match self {
+            BucketArn::Bucket { name } => name,
+            BucketArn::AccessPoint { arn, .. } | BucketArn::OutpostAccessPoint { arn, .. } => arn,
+        }
+    }
+
+    /// Whether requests go to an access point rather than a bucket.
+    pub fn is_access_point(&self) -> bool {
+        !matches!(self, BucketArn::Bucket { .. })
+    }
+}
+
+/// Whether `bucket` is written as an ARN.
+pub fn is_arn(bucket: &str) -> bool {
+    bucket.starts_with("arn:")
+}
+
+/// The Region a client for `arn` is created in: `region`, which must then
+/// match the ARN's, or else the ARN's.
+pub fn region_for_arn(region: Option<String>, arn: &BucketArn) -> Result<Option<String>, Error> {
+    match (region, arn.region()) {
+        (Some(region), Some(arn_region)) if region != arn_region => {
+            Err(Error::Unhandled(Box::from(format!(
+                "{} is in {}, but the Region is {}; leave out --region to use the Region of the ARN",
+                arn.bucket(),
+                arn_region,
+                region
+            ))))
+        }
+        (Some(region), _) => Ok(Some(region)),
+        (None, arn_region) => Ok(arn_region.map(str::to_string)),
+    }
+}
+
+/// Fails for an access point ARN sent to `endpoint_urls`, which would
+/// address it path-style.
+pub fn check_arn_addressing(arn: &BucketArn, endpoint_urls: &[String]) -> Result<(), Error> {
+    if arn.is_access_point() && !endpoint_urls.is_empty() {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} is an access point ARN, which is only reached with virtual-hosted requests \
+             to its own endpoint; --endpoint-url addresses buckets path-style. Leave out \
+             --endpoint-url, or pass a bucket name.",
+            arn.bucket()
+        ))));
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! The default encryption of a bucket, read before a transfer that relies
+//! on ETags.
+//!
+//! The ETag of a part, or of an object uploaded with PutObject, is the MD5
+//! of its bytes only when it is not encrypted with SSE-KMS. A bucket whose
+//! default encryption is SSE-KMS encrypts every new object that way, so
+//! comparing ETags with local MD5s can only fail there. The check is best
+//! effort: a role without `s3:GetEncryptionConfiguration`, or an endpoint
+//! without the operation, leaves the encryption unknown.
+
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use serde::Serialize;
+
+/// The default encryption of a bucket.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "default_encryption", rename_all = "snake_case")]
+pub enum BucketEncryption {
+    /// No default encryption configuration.
+    None,
+    /// SSE-S3 (`AES256`); ETags are still MD5s.
+    SseS3,
+    /// SSE-KMS, or DSSE-KMS; ETags are not MD5s.
+    SseKms { kms_key_id: Option<String> },
+    /// GetBucketEncryption failed, with this error code.
+    Unknown { reason: String },
+}
+
+impl BucketEncryption {
+    /// Whether the ETags of new objects and parts are the MD5 of their
+    /// bytes, or `None` when the encryption is unknown.
+    pub fn etags_are_md5(&self) -> Option<bool> {
+        match self {
+            BucketEncryption::None | BucketEncryption::SseS3 => Some(true),
+            BucketEncryption::SseKms { .. } => Some(false),
+            BucketEncryption::Unknown { .. } => None,
+        }
+    }
+}
+
+/// Reads the default encryption of `bucket` with GetBucketEncryption.
+///
+/// An error returned by S3, such as AccessDenied, gives
+/// `BucketEncryption::Unknown` rather than an error, so that the transfer
+/// goes on as it would without the check.
+pub async fn bucket_default_encryption(
+    client: &Client,
+    bucket: &str,
+) -> Result<BucketEncryption, Error> {
+    let resp = match client.get_bucket_encryption().bucket(bucket).send().await {
+        Ok(resp) => resp,
+        Err(SdkError::ServiceError { err, .. }) => {
+            return Ok(match err.code() {
+                Some("ServerSideEncryptionConfigurationNotFoundError") => BucketEncryption::None,
+                code => BucketEncryption::Unknown {
+                    reason: code.unwrap_or("unknown error").to_string(),
+                },
+            })
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let default = resp
+        .server_side_encryption_configuration()
+        .and_then(|config| config.rules())
+        .and_then(|rules| rules.first())
+        .and_then(|rule| rule.apply_server_side_encryption_by_default());
+    let default = match default {
+        Some(default) => default,
+        None => return Ok(BucketEncryption::None),
+    };
+    Ok(
+        match default.sse_algorithm().map(|algorithm| algorithm.as_str()) {
+            Some(algorithm) if algorithm.starts_with("aws:kms") => BucketEncryption::SseKms {
+                kms_key_id: default.kms_master_key_id().map(|id| id.to_string()),
+            },
+            Some(_) => BucketEncryption::SseS3,
+            None => BucketEncryption::None,
+        },
+    )
+}
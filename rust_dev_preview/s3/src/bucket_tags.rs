@@ -0,0 +1,114 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Bucket tag management.
+
+use aws_sdk_s3::model::{Tag, Tagging};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use std::collections::HashMap;
+
+/// Maximum number of tags on a bucket.
+pub const MAX_BUCKET_TAGS: usize = 50;
+
+fn validate_tag_count(tags: &HashMap<String, String>) -> Result<(), Error> {
+    if tags.len() > MAX_BUCKET_TAGS {
+        return Err(Error::Unhandled(Box::from(format!(
+            "A bucket can have at most {} tags, but {} were given",
+            MAX_BUCKET_TAGS,
+            tags.len()
+        ))));
+    }
+    Ok(())
+}
+
+/// Replaces all tags on `bucket` with `tags`.
+pub async fn put_bucket_tags(
+    client: &Client,
+    bucket: &str,
+    tags: HashMap<String, String>,
+) -> Result<(), Error> {
+    validate_tag_count(&tags)?;
+    let tag_set = tags
+        .into_iter()
+        .map(|(key, value)| Tag::builder().key(key).value(value).build())
+        .collect();
+    client
+        .put_bucket_tagging()
+        .bucket(bucket)
+        .tagging(Tagging::builder().set_tag_set(Some(tag_set)).build())
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Returns the tags on `bucket`; a bucket without tags yields an empty map.
+pub async fn get_bucket_tags(
+    client: &Client,
+    bucket: &str,
+) -> Result<HashMap<String, String>, Error> {
+    match client.get_bucket_tagging().bucket(bucket).send().await {
+        Ok(resp) => Ok(resp
+            .tag_set()
+            .unwrap_or_default()
+            .iter()
+            .map(|tag| {
+                (
+                    tag.key().unwrap_or_default().to_string(),
+                    tag.value().unwrap_or_default().to_string(),
+                )
+            })
+            .collect()),
+        Err(SdkError::ServiceError { err, .. }) if err.code() == Some("NoSuchTagSet") => {
+            Ok(HashMap::new())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Removes all tags from `bucket`.
+pub async fn delete_bucket_tags(client: &Client, bucket: &str) -> Result<(), Error> {
+    client.delete_bucket_tagging().bucket(bucket).send().await?;
+    Ok(())
+}
+
+/// Replaces all tags on `bucket` with `tags`, deleting the tag set when
+/// `tags` is empty.
+pub async fn replace_bucket_tags(
+    client: &Client,
+    bucket: &str,
+    tags: HashMap<String, String>,
+) -> Result<(), Error> {
+    if tags.is_empty() {
+        delete_bucket_tags(client, bucket).await
+    } else {
+        put_bucket_tags(client, bucket, tags).await
+    }
+}
+
+/// Adds `new_tags` to the existing tags on `bucket`, overwriting the values
+/// of keys that are already present.
+pub async fn merge_bucket_tags(
+    client: &Client,
+    bucket: &str,
+    new_tags: HashMap<String, String>,
+) -> Result<(), Error> {
+    let mut tags = get_bucket_tags(client, bucket).await?;
+    tags.extend(new_tags);
+    put_bucket_tags(client, bucket, tags).await
+}
+
+/// Removes the tags with the given keys from `bucket`.
+pub async fn remove_bucket_tags(
+    client: &Client,
+    bucket: &str,
+    keys: &[String],
+) -> Result<(), Error> {
+    let mut tags = get_bucket_tags(client, bucket).await?;
+    for key in keys {
+        tags.remove(key);
+    }
+    replace_bucket_tags(client, bucket, tags).await
+}
@@ -0,0 +1,219 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Periodic checkpoints of a long directory upload.
+//!
+//! A `ResumeManifest` is only written when a run ends. A run that is killed,
+//! or whose machine goes down, leaves nothing behind, and the next run
+//! starts over. A `CheckpointedUpload` records each file as it is uploaded
+//! and writes the files uploaded so far every so many files or seconds, so
+//! that a new run skips them, unless their size or modification time
+//! changed since.
+//!
+//! The checkpoint is written to a temporary file next to it, synced, and
+//! renamed over it, so a crash while writing leaves the previous checkpoint
+//! intact.
+
+use crate::scheduler::ScheduledFile;
+use aws_sdk_s3::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A file uploaded by a checkpointed run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointEntry {
+    pub local_path: PathBuf,
+    pub key: String,
+    /// Without quotes.
+    pub e_tag: String,
+    /// The size of the file when it was recorded; `None` if it could not be
+    /// read, and the file is then uploaded again.
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// The modification time of the file when it was recorded.
+    #[serde(default)]
+    pub mtime: Option<SystemTime>,
+}
+
+impl CheckpointEntry {
+    /// Whether the file at `local_path` still has the recorded size and
+    /// modification time.
+    fn is_unchanged(&self) -> bool {
+        match std::fs::metadata(&self.local_path) {
+            Ok(metadata) => {
+                self.size == Some(metadata.len()) && self.mtime == metadata.modified().ok()
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// The files uploaded so far, as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub bucket: String,
+    pub uploaded: Vec<CheckpointEntry>,
+}
+
+#[derive(Debug)]
+struct State {
+    checkpoint: Checkpoint,
+    /// Files recorded since the last write.
+    unsaved: u32,
+    last_write: Instant,
+}
+
+/// Records the files uploaded by a run and writes them to a checkpoint
+/// file.
+#[derive(Debug)]
+pub struct CheckpointedUpload {
+    path: PathBuf,
+    interval_files: u32,
+    interval: Duration,
+    state: Mutex<State>,
+}
+
+impl CheckpointedUpload {
+    /// Writes to `checkpoint_path` once `interval_files` files were uploaded
+    /// or `interval_secs` seconds went by since the last write, whichever
+    /// comes first. Zero turns either trigger off.
+    pub fn new(checkpoint_path: &Path, interval_files: u32, interval_secs: u64) -> Self {
+        Self {
+            path: checkpoint_path.to_path_buf(),
+            interval_files,
+            interval: Duration::from_secs(interval_secs),
+            state: Mutex::new(State {
+                checkpoint: Checkpoint::default(),
+                unsaved: 0,
+                last_write: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the checkpoint of an earlier run to `bucket`, if there is one,
+    /// and returns the number of files it uploaded.
+    pub fn load(&self, bucket: &str) -> Result<usize, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.checkpoint = match std::fs::read_to_string(&self.path) {
+            Ok(content) => {
+                let checkpoint: Checkpoint = serde_json::from_str(&content).map_err(|err| {
+                    Error::Unhandled(Box::from(format!(
+                        "Invalid checkpoint {}: {}",
+                        self.path.display(),
+                        err
+                    )))
+                })?;
+                if checkpoint.bucket != bucket {
+                    return Err(Error::Unhandled(Box::from(format!(
+                        "{} is for bucket {}, not {}",
+                        self.path.display(),
+                        checkpoint.bucket,
+                        bucket
+                    ))));
+                }
+                checkpoint
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Checkpoint {
+                bucket: bucket.to_string(),
+                uploaded: Vec::new(),
+            },
+            Err(err) => return Err(Error::Unhandled(Box::new(err))),
+        };
+        Ok(state.checkpoint.uploaded.len())
+    }
+
+    /// The files uploaded so far.
+    pub fn uploaded(&self) -> Vec<CheckpointEntry> {
+        self.state.lock().unwrap().checkpoint.uploaded.clone()
+    }
+
+    /// `files` without those already uploaded to the same key and unchanged
+    /// since, in order.
+    pub fn remaining(&self, files: Vec<ScheduledFile>) -> Vec<ScheduledFile> {
+        let state = self.state.lock().unwrap();
+        let done: HashMap<(&Path, &str), &CheckpointEntry> = state
+            .checkpoint
+            .uploaded
+            .iter()
+            .map(|entry| ((entry.local_path.as_path(), entry.key.as_str()), entry))
+            .collect();
+        files
+            .into_iter()
+            .filter(|file| {
+                !done
+                    .get(&(file.path.as_path(), file.key.as_str()))
+                    .map_or(false, |entry| entry.is_unchanged())
+            })
+            .collect()
+    }
+
+    /// Records an uploaded file with its current size and modification
+    /// time, and writes the checkpoint when an interval is reached. A failed
+    /// write is logged rather than failing the upload; the next write
+    /// includes the file again.
+    pub fn record(&self, local_path: &Path, key: &str, e_tag: &str) {
+        let metadata = std::fs::metadata(local_path).ok();
+        let mut state = self.state.lock().unwrap();
+        state.checkpoint.uploaded.push(CheckpointEntry {
+            local_path: local_path.to_path_buf(),
+            key: key.to_string(),
+            e_tag: e_tag.to_string(),
+            size: metadata.as_ref().map(|metadata| metadata.len()),
+            mtime: metadata.and_then(|metadata| metadata.modified().ok()),
+        });
+        state.unsaved += 1;
+        let by_files = self.interval_files > 0 && state.unsaved >= self.interval_files;
+        let by_time = self.interval > Duration::ZERO && state.last_write.elapsed() >= self.interval;
+        if by_files || by_time {
+            if let Err(err) = self.write_locked(&mut state) {
+                tracing::warn!(
+                    "Cannot write the checkpoint {}: {}",
+                    self.path.display(),
+                    err
+                );
+            }
+        }
+    }
+
+    /// Writes the checkpoint now.
+    pub fn write(&self) -> Result<(), Error> {
+        self.write_locked(&mut self.state.lock().unwrap())
+    }
+
+    fn write_locked(&self, state: &mut State) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(&state.checkpoint).unwrap();
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let written = std::fs::File::create(&tmp).and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        });
+        if let Err(err) = written.and_then(|_| std::fs::rename(&tmp, &self.path)) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(Error::Unhandled(Box::new(err)));
+        }
+        state.unsaved = 0;
+        state.last_write = Instant::now();
+        Ok(())
+    }
+
+    /// Deletes the checkpoint, once every file is uploaded.
+    pub fn remove(&self) -> Result<(), Error> {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::Unhandled(Box::new(err))),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Parsers for command-line values shared by the binaries.
+
+use http::Uri;
+use std::time::Duration;
+
+/// Parses a duration such as `2s`, `500ms`, `5m`, or `1h`. A bare number is
+/// taken as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or_else(|| value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86400.0,
+        other => return Err(format!("Unknown duration unit: {}", other)),
+    };
+    if !(0.0..u64::MAX as f64).contains(&seconds) {
+        return Err(format!("Duration out of range: {}", value));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parses an interval between two events, as `parse_duration` does; zero is
+/// refused, as a timer cannot tick that often.
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let interval = parse_duration(value)?;
+    if interval.is_zero() {
+        return Err(format!("The interval must be more than zero: {}", value));
+    }
+    Ok(interval)
+}
+
+/// Parses a size such as `4096`, `64KiB`, `8MiB`, `1.5GiB`, or `10MB`.
+/// Binary (`KiB`) and decimal (`KB`) units are both accepted; a bare number
+/// is taken as bytes.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or_else(|| value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid size: {}", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        other => return Err(format!("Unknown size unit: {}", other)),
+    };
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+/// Parses a `key=value` pair, as a tag or an item of metadata. The value can
+/// be empty and hold `=`; the key cannot be empty.
+pub fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("Expected key=value: {}", value)),
+    }
+}
+
+/// Parses the URL of an endpoint, such as `http://localhost:9000`: it needs
+/// an `http` or `https` scheme and a host.
+pub fn parse_endpoint_uri(value: &str) -> Result<Uri, String> {
+    let uri = value
+        .parse::<Uri>()
+        .map_err(|err| format!("Invalid endpoint URL {}: {}", value, err))?;
+    match (uri.scheme_str(), uri.host()) {
+        (Some("http"), Some(_)) | (Some("https"), Some(_)) => Ok(uri),
+        _ => Err(format!(
+            "Invalid endpoint URL {}: expected http:// or https:// and a host",
+            value
+        )),
+    }
+}
+
+/// Checks an endpoint URL as `parse_endpoint_uri` does, keeping it as it was
+/// given, since it also names the endpoint.
+pub fn parse_endpoint_url(value: &str) -> Result<String, String> {
+    parse_endpoint_uri(value).map(|_| value.to_string())
+}
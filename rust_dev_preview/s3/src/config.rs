@@ -0,0 +1,294 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! TOML configuration shared by the transfer binaries.
+//!
+//! ```toml
+//! # Headers of every uploaded object.
+//! [headers.default]
+//! cache_control = "max-age=300"
+//!
+//! # Headers by file extension, over the defaults.
+//! [headers.extensions.html]
+//! content_type = "text/html; charset=utf-8"
+//! cache_control = "no-cache"
+//!
+//! # Headers of files with a content hash in their name, such as
+//! # main.5d41402abc4b2a76.js, over the extension headers.
+//! [headers.hashed]
+//! cache_control = "public, max-age=31536000, immutable"
+//!
+//! # Retries of each file of a directory upload.
+//! [retry]
+//! max_attempts = 6
+//! base_delay_ms = 500
+//! max_delay_ms = 30000
+//! jitter_ms = 250
+//!
+//! # The number of files in flight that upload-directory -c auto starts
+//! # from, written by --save-tuning.
+//! [tuning]
+//! concurrency = 12
+//!
+//! # The largest part each endpoint accepts, by --endpoint-url, written by
+//! # s3-transfer upload --auto-split-parts.
+//! [part_size_caps]
+//! "https://minio.internal:9000" = 1073741824
+//! ```
+
+use crate::retry::RetryPolicy;
+use crate::upload::{parse_expires, UploadHeaders, MIN_PART_SIZE};
+use aws_sdk_s3::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// The numbers of hex digits of the content hashes bundlers put in file
+/// names: 64 and 80 bit truncations, MD5, and SHA-1.
+const HASH_LENS: &[usize] = &[16, 20, 32, 40];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawHeaders {
+    content_type: Option<String>,
+    cache_control: Option<String>,
+    content_encoding: Option<String>,
+    content_disposition: Option<String>,
+    content_language: Option<String>,
+    expires: Option<String>,
+}
+
+impl RawHeaders {
+    fn parse(self, section: &str) -> Result<UploadHeaders, String> {
+        let expires = match &self.expires {
+            Some(value) => Some(
+                parse_expires(value)
+                    .map_err(|err| format!("{}: invalid expires {:?}: {}", section, value, err))?,
+            ),
+            None => None,
+        };
+        Ok(UploadHeaders {
+            content_type: self.content_type,
+            content_disposition: self.content_disposition,
+            content_encoding: self.content_encoding,
+            content_language: self.content_language,
+            cache_control: self.cache_control,
+            expires,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawHeaderRules {
+    #[serde(default)]
+    default: RawHeaders,
+    #[serde(default)]
+    extensions: HashMap<String, RawHeaders>,
+    #[serde(default)]
+    hashed: RawHeaders,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    #[serde(default)]
+    headers: RawHeaderRules,
+    #[serde(default)]
+    retry: RetrySettings,
+    #[serde(default)]
+    tuning: TuningSettings,
+    #[serde(default)]
+    part_size_caps: BTreeMap<String, u64>,
+}
+
+/// Default upload headers, chosen by key.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderRules {
+    pub default: UploadHeaders,
+    /// By lowercase extension, without the dot.
+    pub extensions: HashMap<String, UploadHeaders>,
+    /// For file names with a content hash, see `is_hashed_name`.
+    pub hashed: UploadHeaders,
+}
+
+impl HeaderRules {
+    /// The default headers of `key`: the `hashed` headers if the name has a
+    /// content hash, over those of its extension, over `default`.
+    pub fn headers_for(&self, key: &str) -> UploadHeaders {
+        let name = key.rsplit('/').next().unwrap_or(key);
+        let mut headers = self.default.clone();
+        if let Some((_, extension)) = name.rsplit_once('.') {
+            if let Some(by_extension) = self.extensions.get(&extension.to_lowercase()) {
+                headers = by_extension.or(&headers);
+            }
+        }
+        if is_hashed_name(name) {
+            headers = self.hashed.or(&headers);
+        }
+        headers
+    }
+}
+
+/// Changes to a `RetryPolicy`, from the configuration file or the command
+/// line; unset values are left alone.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetrySettings {
+    pub max_attempts: Option<u32>,
+    pub base_delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+    pub jitter_ms: Option<u64>,
+}
+
+impl RetrySettings {
+    /// `policy` with the values that are set.
+    pub fn apply(&self, policy: RetryPolicy) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.unwrap_or(policy.max_attempts),
+            base_delay_ms: self.base_delay_ms.unwrap_or(policy.base_delay_ms),
+            max_delay_ms: self.max_delay_ms.unwrap_or(policy.max_delay_ms),
+            jitter_ms: self.jitter_ms.unwrap_or(policy.jitter_ms),
+            ..policy
+        }
+    }
+}
+
+/// What an adaptive run learned, for the next one to start from.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TuningSettings {
+    /// The number of files in flight the last run converged to.
+    pub concurrency: Option<usize>,
+}
+
+/// Whether a file name has a content hash, such as `main.5d41402abc4b2a76.js`
+/// or `app-d41d8cd98f00b204e9800998ecf8427e.css`: a segment of 16, 20, 32,
+/// or 40 hex digits, with at least one digit, that follows a `.`, `-`, or
+/// `_` before the extension.
+pub fn is_hashed_name(name: &str) -> bool {
+    let stem = match name.rsplit_once('.') {
+        Some((stem, _)) => stem,
+        None => return false,
+    };
+    // The first segment is the name itself, not a hash after a delimiter.
+    stem.split(|c| c == '.' || c == '-' || c == '_')
+        .skip(1)
+        .any(|segment| {
+            HASH_LENS.contains(&segment.len())
+                && segment.chars().all(|c| c.is_ascii_hexdigit())
+                && segment.chars().any(|c| c.is_ascii_digit())
+        })
+}
+
+/// Settings read from a configuration file.
+#[derive(Debug, Clone, Default)]
+pub struct TransferConfig {
+    pub headers: HeaderRules,
+    pub retry: RetrySettings,
+    pub tuning: TuningSettings,
+    /// The largest part size each endpoint accepts, by endpoint.
+    pub part_size_caps: BTreeMap<String, u64>,
+}
+
+impl TransferConfig {
+    /// Parses the TOML content of a configuration file.
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let raw: RawConfig = toml::from_str(content).map_err(|err| err.to_string())?;
+        if raw.retry.max_attempts == Some(0) {
+            return Err("retry: max_attempts must be at least 1".to_string());
+        }
+        if raw.tuning.concurrency == Some(0) {
+            return Err("tuning: concurrency must be at least 1".to_string());
+        }
+        for (endpoint, cap) in &raw.part_size_caps {
+            if *cap < MIN_PART_SIZE {
+                return Err(format!(
+                    "part_size_caps: the cap of {} must be at least {} bytes",
+                    endpoint, MIN_PART_SIZE
+                ));
+            }
+        }
+        let mut extensions = HashMap::new();
+        for (extension, headers) in raw.headers.extensions {
+            let section = format!("headers.extensions.{}", extension);
+            extensions.insert(
+                extension.trim_start_matches('.').to_lowercase(),
+                headers.parse(&section)?,
+            );
+        }
+        Ok(Self {
+            headers: HeaderRules {
+                default: raw.headers.default.parse("headers.default")?,
+                extensions,
+                hashed: raw.headers.hashed.parse("headers.hashed")?,
+            },
+            retry: raw.retry,
+            tuning: raw.tuning,
+            part_size_caps: raw.part_size_caps,
+        })
+    }
+
+    /// Reads the configuration file at `path`.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        Self::from_toml(&content)
+            .map_err(|err| Error::Unhandled(Box::from(format!("{}: {}", path.display(), err))))
+    }
+}
+
+/// Rewrites the configuration file at `path` after `change` edited its
+/// table, creating the file if it does not exist.
+fn edit_config(
+    path: &Path,
+    change: impl FnOnce(&mut toml::value::Table) -> Result<(), String>,
+) -> Result<(), Error> {
+    let invalid = |err: String| Error::Unhandled(Box::from(format!("{}: {}", path.display(), err)));
+    let mut config = match std::fs::read_to_string(path) {
+        Ok(content) => content
+            .parse::<toml::Value>()
+            .map_err(|err| invalid(err.to_string()))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            toml::Value::Table(Default::default())
+        }
+        Err(err) => return Err(Error::Unhandled(Box::new(err))),
+    };
+    change(
+        config
+            .as_table_mut()
+            .ok_or_else(|| invalid("not a table".to_string()))?,
+    )
+    .map_err(invalid)?;
+    let content = toml::to_string(&config).map_err(|err| invalid(err.to_string()))?;
+    std::fs::write(path, content).map_err(|err| Error::Unhandled(Box::new(err)))
+}
+
+/// Writes `tuning` as the `[tuning]` section of the configuration file at
+/// `path`, which is created if it does not exist. The other sections are
+/// kept, but not the comments or the layout of the file.
+pub fn save_tuning(path: &Path, tuning: &TuningSettings) -> Result<(), Error> {
+    edit_config(path, |config| {
+        let section = toml::Value::try_from(tuning).map_err(|err| err.to_string())?;
+        config.insert("tuning".to_string(), section);
+        Ok(())
+    })
+}
+
+/// Records `cap` as the largest part size `endpoint` accepts, in the
+/// `[part_size_caps]` section of the configuration file at `path`, as
+/// `save_tuning` does.
+pub fn save_part_size_cap(path: &Path, endpoint: &str, cap: u64) -> Result<(), Error> {
+    edit_config(path, |config| {
+        let caps = config
+            .entry("part_size_caps")
+            .or_insert_with(|| toml::Value::Table(Default::default()))
+            .as_table_mut()
+            .ok_or_else(|| "part_size_caps is not a table".to_string())?;
+        caps.insert(endpoint.to_string(), toml::Value::Integer(cap as i64));
+        Ok(())
+    })
+}
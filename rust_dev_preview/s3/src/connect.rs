@@ -0,0 +1,397 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Client construction for the `s3-transfer` tool, which talks to both
+//! Amazon S3 and S3-compatible endpoints.
+
+use crate::cli::parse_endpoint_uri;
+use crate::endpoint_template::{EndpointTemplate, TemplateResolver, TemplateStyle, VirtualHosted};
+use crate::part_capture::{CapturePart, PartCapture};
+use crate::rate_limit::{RateLimited, RequestLimiter};
+use crate::request_timing::{RequestTimings, TimeParts, TimedConnector};
+use crate::signing_debug::{SigningDebug, SigningDebugger};
+use crate::verbosity::RecordRequestId;
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{Client, Endpoint, Error, Region};
+use aws_smithy_client::hyper_ext;
+use aws_types::credentials::SharedCredentialsProvider;
+use futures::future::BoxFuture;
+use http::Uri;
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper_rustls::HttpsConnector;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpSocket, TcpStream};
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// The Region; defaults to the environment, then **us-west-2**.
+    pub region: Option<String>,
+    /// The profile in the .aws/credentials file; defaults to the standard
+    /// credential chain.
+    pub profile: Option<String>,
+    /// The URL of an S3-compatible endpoint.
+    pub endpoint_url: Option<String>,
+    /// Resolves the endpoint of each request from a template, such as that
+    /// of a multi-tenant gateway, when there is no endpoint URL.
+    pub endpoint_template: Option<EndpointTemplate>,
+    /// The local address S3 connections are made from, to pick the network
+    /// interface on hosts with several.
+    pub local_address: Option<IpAddr>,
+    /// Paces the requests of every client created from these options.
+    pub request_limiter: Option<RequestLimiter>,
+    /// Describes the signed requests, for endpoints that reject them.
+    pub debug_signing: Option<SigningDebugger>,
+    /// Records every attempt to upload one part.
+    pub capture_part: Option<PartCapture>,
+    /// Keeps the request ID of each response for `with_request_id`.
+    pub record_request_ids: bool,
+    /// Times every part request and tells new connections from reused ones.
+    pub request_timings: Option<RequestTimings>,
+}
+
+/// The connector the clients of this crate are built on: HTTPS with the
+/// native roots, or plain HTTP for local endpoints, over HTTP/1.1 or HTTP/2.
+pub fn https_connector() -> HttpsConnector<HttpConnector> {
+    hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .enable_http2()
+        .build()
+}
+
+/// `https_connector` as the SDK sends requests through it, for the clients
+/// that wrap it in a connector of their own.
+pub fn https_adapter() -> hyper_ext::Adapter<HttpsConnector<HttpConnector>> {
+    hyper_ext::Adapter::builder().build(https_connector())
+}
+
+/// Creates a client from `options`. Fails for an endpoint URL that
+/// `cli::parse_endpoint_url` refuses.
+pub async fn connect(options: &ConnectOptions) -> Result<Client, Error> {
+    let shared_config = load_config(options).await;
+    client_for(&shared_config, options.endpoint_url.as_deref(), options)
+}
+
+/// Creates one client per URL in `endpoint_urls`, all with the Region and
+/// credentials of `options`; `options.endpoint_url` is ignored.
+pub async fn connect_endpoints(
+    options: &ConnectOptions,
+    endpoint_urls: &[String],
+) -> Result<Vec<(String, Client)>, Error> {
+    let shared_config = load_config(options).await;
+    endpoint_urls
+        .iter()
+        .map(|url| {
+            let client = client_for(&shared_config, Some(url), options)?;
+            Ok((url.clone(), client))
+        })
+        .collect()
+}
+
+/// Creates an Amazon SNS client with the Region and credentials of
+/// `options`, for `notify_sns_after_upload`.
+pub async fn connect_sns(options: &ConnectOptions) -> aws_sdk_sns::Client {
+    aws_sdk_sns::Client::new(&load_config(options).await)
+}
+
+/// Creates an Amazon SQS client with the Region and credentials of
+/// `options`, for `pipeline::consume_queue`.
+pub async fn connect_sqs(options: &ConnectOptions) -> aws_sdk_sqs::Client {
+    aws_sdk_sqs::Client::new(&load_config(options).await)
+}
+
+async fn load_config(options: &ConnectOptions) -> aws_config::Config {
+    let region_provider = RegionProviderChain::first_try(options.region.clone().map(Region::new))
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"));
+    let mut loader = aws_config::from_env().region(region_provider);
+    if let Some(profile) = &options.profile {
+        loader = loader.credentials_provider(
+            aws_config::profile::ProfileFileCredentialsProvider::builder()
+                .profile_name(profile)
+                .build(),
+        );
+    }
+    loader.load().await
+}
+
+fn client_for(
+    shared_config: &aws_config::Config,
+    endpoint_url: Option<&str>,
+    options: &ConnectOptions,
+) -> Result<Client, Error> {
+    let mut s3_conf = aws_sdk_s3::config::Builder::from(shared_config);
+    if let Some(url) = endpoint_url {
+        let uri = parse_endpoint_uri(url).map_err(|err| Error::Unhandled(Box::from(err)))?;
+        s3_conf = s3_conf.endpoint_resolver(Endpoint::immutable(uri));
+    } else if let Some(template) = &options.endpoint_template {
+        s3_conf = s3_conf.endpoint_resolver(TemplateResolver::new(template.clone()));
+    }
+    // Host templates are filled in for each request by the connector.
+    let virtual_host = options
+        .endpoint_template
+        .clone()
+        .filter(|template| endpoint_url.is_none() && template.style() == TemplateStyle::Host)
+        .map(|template| (template, shared_config.credentials_provider().cloned()));
+    let debugger = options.debug_signing.clone();
+    let capture = options.capture_part.clone();
+    let timings = options.request_timings.clone();
+    let wrapped = virtual_host.is_some()
+        || debugger.is_some()
+        || capture.is_some()
+        || timings.is_some()
+        || options.record_request_ids;
+    let client = match (options.local_address, &options.request_limiter, wrapped) {
+        (Some(local_address), limiter, _) => bound_interface_client(
+            s3_conf.build(),
+            local_address,
+            limiter.as_ref(),
+            virtual_host,
+            debugger,
+            capture,
+            timings,
+        ),
+        (None, None, false) => Client::from_conf(s3_conf.build()),
+        (None, limiter, _) => {
+            let connector = TimedConnector::new(https_connector(), timings.clone());
+            let adapter = RecordRequestId::new(virtual_hosted(
+                SigningDebug::new(
+                    CapturePart::new(
+                        TimeParts::new(hyper_ext::Adapter::builder().build(connector), timings),
+                        capture,
+                    ),
+                    debugger,
+                ),
+                virtual_host,
+            ));
+            match limiter {
+                Some(limiter) => Client::from_conf_conn(
+                    s3_conf.build(),
+                    RateLimited::new(adapter, limiter.clone()),
+                ),
+                None => Client::from_conf_conn(s3_conf.build(), adapter),
+            }
+        }
+    };
+    Ok(client)
+}
+
+/// Creates a client whose connections are made from `local_addr`, so S3
+/// traffic goes through the interface with that address, such as the WAN
+/// interface of a host that also has a LAN one.
+///
+/// `local_addr` must be assigned to an interface of this host: otherwise
+/// every connection fails with `EADDRNOTAVAIL` ("Cannot assign requested
+/// address"). Only hosts resolving to an address of the same family (IPv4
+/// or IPv6) can be reached. The credential providers of `config` still use
+/// the default route.
+pub fn build_s3_client_bound_interface(config: aws_sdk_s3::Config, local_addr: IpAddr) -> Client {
+    bound_interface_client(config, local_addr, None, None, None, None, None)
+}
+
+/// `inner` under a `VirtualHosted` connector for the host template and
+/// credentials of `virtual_host`, if any.
+fn virtual_hosted<C>(
+    inner: C,
+    virtual_host: Option<(EndpointTemplate, Option<SharedCredentialsProvider>)>,
+) -> VirtualHosted<C> {
+    let (template, credentials) = match virtual_host {
+        Some((template, credentials)) => (Some(template), credentials),
+        None => (None, None),
+    };
+    VirtualHosted::new(inner, template, credentials)
+}
+
+fn bound_interface_client(
+    config: aws_sdk_s3::Config,
+    local_addr: IpAddr,
+    limiter: Option<&RequestLimiter>,
+    virtual_host: Option<(EndpointTemplate, Option<SharedCredentialsProvider>)>,
+    debugger: Option<SigningDebugger>,
+    capture: Option<PartCapture>,
+    timings: Option<RequestTimings>,
+) -> Client {
+    tracing::debug!(%local_addr, "Binding S3 connections to local address");
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .enable_http2()
+        .wrap_connector(BoundConnector { local_addr });
+    let connector = TimedConnector::new(connector, timings.clone());
+    let adapter = RecordRequestId::new(virtual_hosted(
+        SigningDebug::new(
+            CapturePart::new(
+                TimeParts::new(hyper_ext::Adapter::builder().build(connector), timings),
+                capture,
+            ),
+            debugger,
+        ),
+        virtual_host,
+    ));
+    match limiter {
+        Some(limiter) => Client::from_conf_conn(config, RateLimited::new(adapter, limiter.clone())),
+        None => Client::from_conf_conn(config, adapter),
+    }
+}
+
+/// A Hyper connector that binds each TCP socket to a local address before
+/// connecting.
+#[derive(Debug, Clone, Copy)]
+pub struct BoundConnector {
+    pub local_addr: IpAddr,
+}
+
+impl Service<Uri> for BoundConnector {
+    type Response = TcpStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let local_addr = self.local_addr;
+        Box::pin(async move {
+            let host = uri
+                .host()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URI without a host"))?
+                .trim_start_matches('[')
+                .trim_end_matches(']');
+            let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+                Some("http") => 80,
+                _ => 443,
+            });
+            let mut last_err = None;
+            for remote in tokio::net::lookup_host((host, port)).await? {
+                if remote.is_ipv4() != local_addr.is_ipv4() {
+                    continue;
+                }
+                let socket = if local_addr.is_ipv4() {
+                    TcpSocket::new_v4()?
+                } else {
+                    TcpSocket::new_v6()?
+                };
+                socket.bind(SocketAddr::new(local_addr, 0))?;
+                tracing::debug!(%local_addr, %remote, "Connecting from bound local address");
+                match socket.connect(remote).await {
+                    Ok(stream) => {
+                        stream.set_nodelay(true)?;
+                        return Ok(stream);
+                    }
+                    Err(err) => last_err = Some(err),
+                }
+            }
+            Err(last_err.unwrap_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!(
+                        "{} has no address of the same family as {}",
+                        host, local_addr
+                    ),
+                )
+            }))
+        })
+    }
+}
+
+/// Creates a client that stops reusing a connection once it is older than
+/// `max_connection_age`, so a long-running process does not send a request
+/// over a connection the endpoint or a load balancer in between has since
+/// dropped, which fails with a reset instead of a response.
+///
+/// The Hyper pool only evicts connections that have been idle for a while,
+/// which a busy process never lets happen, and gives no way to evict one
+/// before. So the client starts a new pool every `max_connection_age`: new
+/// requests go to the new pool, while requests in flight complete on the
+/// old one, whose connections are closed once the last of them returns. A
+/// connection opened late in the life of a pool is replaced early with it.
+///
+/// Each replacement connection costs a TCP and TLS handshake, a round trip
+/// or two, so a short age trades many handshakes for few stale connections.
+/// For uploads running more than an hour, 30 minutes is a good balance.
+pub fn build_s3_client_with_max_age(
+    config: aws_sdk_s3::Config,
+    max_connection_age: Duration,
+) -> Client {
+    let adapter = MaxAgeAdapter::new(max_connection_age, https_adapter);
+    Client::from_conf_conn(config, adapter)
+}
+
+/// A connector sending requests through a pool that is replaced by a new
+/// one, from `new_pool`, once it is older than `max_age`.
+#[derive(Clone)]
+pub struct MaxAgeAdapter<C> {
+    new_pool: Arc<dyn Fn() -> C + Send + Sync>,
+    max_age: Duration,
+    /// The pool requests are sent to and when it was created, shared by
+    /// the clones of the connector the client makes.
+    current: Arc<Mutex<(C, Instant)>>,
+}
+
+impl<C> MaxAgeAdapter<C> {
+    pub fn new(max_age: Duration, new_pool: impl Fn() -> C + Send + Sync + 'static) -> Self {
+        let current = Arc::new(Mutex::new((new_pool(), Instant::now())));
+        Self {
+            new_pool: Arc::new(new_pool),
+            max_age,
+            current,
+        }
+    }
+}
+
+impl<C: Clone> MaxAgeAdapter<C> {
+    /// The pool for the next request, a new one if the current one is past
+    /// its age.
+    fn pool(&self) -> C {
+        let mut current = self.current.lock().unwrap();
+        if current.1.elapsed() >= self.max_age {
+            *current = ((self.new_pool)(), Instant::now());
+        }
+        current.0.clone()
+    }
+}
+
+impl<C> std::fmt::Debug for MaxAgeAdapter<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MaxAgeAdapter")
+            .field("max_age", &self.max_age)
+            .finish()
+    }
+}
+
+impl<C, B> Service<http::Request<B>> for MaxAgeAdapter<C>
+where
+    C: Service<http::Request<B>> + Clone + Send + 'static,
+    C::Future: Send + 'static,
+    C::Error: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<C::Response, C::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The pool is picked, and made ready, for each request.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let mut pool = self.pool();
+        Box::pin(async move {
+            futures::future::poll_fn(|cx| pool.poll_ready(cx)).await?;
+            pool.call(request).await
+        })
+    }
+}
@@ -0,0 +1,198 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! The Content-Type of an uploaded file, from its extension or its first
+//! bytes.
+//!
+//! Without a Content-Type, S3 stores `binary/octet-stream`, and a browser
+//! downloads an image or a PDF instead of showing it. The extension is not
+//! always there, or right: files written by a tool under a temporary name,
+//! or an export saved as `.dat`. The first `SNIFF_LEN` bytes of the object
+//! then tell most binary formats apart by their magic number.
+
+/// How many bytes of the object are read to recognize its format.
+pub const SNIFF_LEN: usize = 512;
+
+/// The Content-Type of an object whose format is not recognized.
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// The Content-Type of each extension, lowercase.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("7z", "application/x-7z-compressed"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("bz2", "application/x-bzip2"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("doc", "application/msword"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("epub", "application/epub+zip"),
+    ("flac", "audio/flac"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("heic", "image/heic"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/x-icon"),
+    ("jar", "application/java-archive"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("jsonl", "application/x-ndjson"),
+    ("md", "text/markdown"),
+    ("mjs", "text/javascript"),
+    ("mkv", "video/x-matroska"),
+    ("mov", "video/quicktime"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("otf", "font/otf"),
+    ("parquet", "application/vnd.apache.parquet"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("rar", "application/vnd.rar"),
+    ("rtf", "application/rtf"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("ttf", "font/ttf"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("wav", "audio/wav"),
+    ("webm", "video/webm"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xls", "application/vnd.ms-excel"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("xml", "application/xml"),
+    ("xz", "application/x-xz"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("zip", "application/zip"),
+    ("zst", "application/zstd"),
+];
+
+/// Magic numbers, as the `(offset, bytes)` that must all match, and the
+/// Content-Type they give. The more specific signatures come first.
+const SIGNATURES: &[(&[(usize, &[u8])], &str)] = &[
+    (&[(0, b"\x89PNG\r\n\x1a\n")], "image/png"),
+    (&[(0, b"\xff\xd8\xff")], "image/jpeg"),
+    (&[(0, b"GIF87a")], "image/gif"),
+    (&[(0, b"GIF89a")], "image/gif"),
+    (&[(0, b"RIFF"), (8, b"WEBP")], "image/webp"),
+    (&[(0, b"RIFF"), (8, b"WAVE")], "audio/wav"),
+    (&[(0, b"RIFF"), (8, b"AVI ")], "video/x-msvideo"),
+    (&[(0, b"II*\0")], "image/tiff"),
+    (&[(0, b"MM\0*")], "image/tiff"),
+    (&[(0, b"\0\0\x01\0")], "image/x-icon"),
+    (&[(4, b"ftypavif")], "image/avif"),
+    (&[(4, b"ftypheic")], "image/heic"),
+    (&[(4, b"ftypqt  ")], "video/quicktime"),
+    (&[(4, b"ftyp")], "video/mp4"),
+    (&[(0, b"\x1a\x45\xdf\xa3")], "video/webm"),
+    (&[(0, b"ID3")], "audio/mpeg"),
+    (&[(0, b"\xff\xfb")], "audio/mpeg"),
+    (&[(0, b"OggS")], "audio/ogg"),
+    (&[(0, b"fLaC")], "audio/flac"),
+    (&[(0, b"%PDF-")], "application/pdf"),
+    (&[(0, b"%!PS")], "application/postscript"),
+    (&[(0, b"{\\rtf")], "application/rtf"),
+    (&[(0, b"PK\x03\x04")], "application/zip"),
+    (&[(0, b"PK\x05\x06")], "application/zip"),
+    (&[(0, b"\x1f\x8b")], "application/gzip"),
+    (&[(0, b"BZh")], "application/x-bzip2"),
+    (&[(0, b"\xfd7zXZ\0")], "application/x-xz"),
+    (&[(0, b"\x28\xb5\x2f\xfd")], "application/zstd"),
+    (&[(0, b"7z\xbc\xaf\x27\x1c")], "application/x-7z-compressed"),
+    (&[(0, b"Rar!\x1a\x07")], "application/vnd.rar"),
+    (&[(257, b"ustar")], "application/x-tar"),
+    (
+        &[(0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1")],
+        "application/x-ole-storage",
+    ),
+    (&[(0, b"SQLite format 3\0")], "application/vnd.sqlite3"),
+    (&[(0, b"PAR1")], "application/vnd.apache.parquet"),
+    (&[(0, b"\x7fELF")], "application/x-elf"),
+    (&[(0, b"\0asm")], "application/wasm"),
+    (
+        &[(0, b"MZ")],
+        "application/vnd.microsoft.portable-executable",
+    ),
+    (&[(0, b"wOFF")], "font/woff"),
+    (&[(0, b"wOF2")], "font/woff2"),
+    (&[(0, b"OTTO")], "font/otf"),
+    (&[(0, b"\0\x01\0\0\0")], "font/ttf"),
+    (&[(0, b"<?xml")], "application/xml"),
+];
+
+/// Containers whose signature is shared by several formats, such as the
+/// ZIP of a `.docx` or the XML of an `.svg`; a known extension is more
+/// precise than them.
+const CONTAINERS: &[&str] = &[
+    "application/zip",
+    "application/x-ole-storage",
+    "application/xml",
+];
+
+/// The Content-Type of `file_name` from its extension, or `None` if it has
+/// none or an unknown one.
+pub fn detect_content_type(file_name: &str) -> Option<&'static str> {
+    let name = file_name.rsplit(|c| c == '/' || c == '\\').next()?;
+    let (stem, extension) = name.rsplit_once('.')?;
+    if stem.is_empty() {
+        // A dot file, such as `.profile`, has no extension.
+        return None;
+    }
+    let extension = extension.to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, content_type)| *content_type)
+}
+
+/// The Content-Type of the format whose magic number starts `first_bytes`,
+/// or `None` if none does.
+pub fn magic_content_type(first_bytes: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(patterns, _)| {
+            patterns.iter().all(|(offset, magic)| {
+                first_bytes
+                    .get(*offset..offset + magic.len())
+                    .map_or(false, |bytes| bytes == *magic)
+            })
+        })
+        .map(|(_, content_type)| *content_type)
+}
+
+/// The Content-Type of a file from its name and its first bytes, at most
+/// `SNIFF_LEN` of them.
+///
+/// A recognized magic number wins over the extension, so that a PNG saved
+/// as `.txt` is still an image, except for the containers that several
+/// formats share. Without either, the type is `DEFAULT_CONTENT_TYPE`.
+pub fn sniff_content_type(file_name: &str, first_bytes: &[u8]) -> &'static str {
+    let first_bytes = &first_bytes[..first_bytes.len().min(SNIFF_LEN)];
+    let from_extension = detect_content_type(file_name);
+    match magic_content_type(first_bytes) {
+        Some(magic) if !CONTAINERS.contains(&magic) => magic,
+        magic => from_extension.or(magic).unwrap_or(DEFAULT_CONTENT_TYPE),
+    }
+}
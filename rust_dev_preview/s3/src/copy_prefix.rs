@@ -0,0 +1,478 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Server-side replication of every object under a prefix from one bucket to
+//! another. Object bytes never travel through the client: small objects are
+//! copied with `CopyObject`, larger ones with a multipart `UploadPartCopy` flow.
+
+use aws_sdk_s3::model::{
+    CompletedMultipartUpload, CompletedPart, MetadataDirective, StorageClass, TaggingDirective,
+};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use http::HeaderMap;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Largest object that a single `CopyObject` request can copy.
+pub const MAX_COPY_OBJECT_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// The metadata a multipart copy records the ETag of its source in: its own
+/// ETag is of its parts, so it never matches the source.
+pub const COPY_SOURCE_ETAG: &str = "copy-source-etag";
+
+/// Characters escaped in the `x-amz-copy-source` header; `/` is kept so that
+/// the key hierarchy stays readable.
+const COPY_SOURCE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'+')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+/// Options controlling a prefix copy.
+#[derive(Clone, Debug)]
+pub struct CopyPrefixOptions {
+    /// Objects larger than this are copied with `UploadPartCopy`.
+    pub multipart_threshold: u64,
+    /// Size of each copied part in the multipart flow.
+    pub part_size: u64,
+    /// Maximum number of objects copied at the same time.
+    pub concurrency: usize,
+    /// Replace the source metadata with `metadata` instead of copying it.
+    pub replace_metadata: bool,
+    /// Metadata applied when `replace_metadata` is set.
+    pub metadata: HashMap<String, String>,
+    /// Skip objects whose destination already has the same size and ETag,
+    /// or, when copied in parts, records the ETag of the same source.
+    pub skip_existing: bool,
+}
+
+impl Default for CopyPrefixOptions {
+    fn default() -> Self {
+        Self {
+            multipart_threshold: MAX_COPY_OBJECT_SIZE,
+            part_size: 512 * 1024 * 1024,
+            concurrency: 16,
+            replace_metadata: false,
+            metadata: HashMap::new(),
+            skip_existing: false,
+        }
+    }
+}
+
+/// Outcome of a prefix copy.
+#[derive(Debug, Default)]
+pub struct CopyPrefixSummary {
+    pub copied: Vec<String>,
+    pub skipped: Vec<String>,
+    pub failed: Vec<(String, String)>,
+    pub bytes_copied: u64,
+}
+
+enum Outcome {
+    Copied(u64),
+    Skipped,
+}
+
+/// Builds the `x-amz-copy-source` value for `bucket/key`.
+pub fn copy_source(bucket: &str, key: &str) -> String {
+    format!("{}/{}", bucket, utf8_percent_encode(key, COPY_SOURCE))
+}
+
+/// Copies every object under `source_prefix` in `source_bucket` to
+/// `destination_bucket`, replacing the prefix with `destination_prefix`.
+///
+/// Per-object failures do not stop the copy; they are collected in the
+/// returned summary.
+pub async fn copy_prefix(
+    client: &Client,
+    source_bucket: &str,
+    source_prefix: &str,
+    destination_bucket: &str,
+    destination_prefix: &str,
+    options: &CopyPrefixOptions,
+) -> Result<CopyPrefixSummary, Error> {
+    let semaphore = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let objects_done = Arc::new(AtomicU64::new(0));
+    let bytes_done = Arc::new(AtomicU64::new(0));
+    let mut handles = Vec::new();
+    let mut total_objects = 0u64;
+    let mut continuation_token: Option<String> = None;
+
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(source_bucket)
+            .prefix(source_prefix)
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+
+        for object in resp.contents().unwrap_or_default() {
+            let source_key = match object.key() {
+                Some(key) => key.to_string(),
+                None => continue,
+            };
+            let destination_key = format!(
+                "{}{}",
+                destination_prefix,
+                &source_key[source_prefix.len()..]
+            );
+            let size = object.size() as u64;
+            let e_tag = object.e_tag().map(|t| t.to_string());
+            let storage_class = object
+                .storage_class()
+                .map(|class| StorageClass::from(class.as_str()));
+            let client = client.clone();
+            let source_bucket = source_bucket.to_string();
+            let destination_bucket = destination_bucket.to_string();
+            let options = options.clone();
+            let objects_done = objects_done.clone();
+            let bytes_done = bytes_done.clone();
+            total_objects += 1;
+
+            // Taken before spawning, so that the listing waits for a free
+            // slot rather than starting a task for every object.
+            let permit = semaphore
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+            handles.push(tokio::spawn(async move {
+                let outcome = copy_one(
+                    &client,
+                    &source_bucket,
+                    &source_key,
+                    &destination_bucket,
+                    &destination_key,
+                    size,
+                    e_tag,
+                    storage_class,
+                    &options,
+                )
+                .await;
+                drop(permit);
+                let done = objects_done.fetch_add(1, Ordering::SeqCst) + 1;
+                if let Ok(Outcome::Copied(bytes)) = outcome {
+                    bytes_done.fetch_add(bytes, Ordering::SeqCst);
+                }
+                println!(
+                    "Processed {} objects, {} bytes copied",
+                    done,
+                    bytes_done.load(Ordering::SeqCst)
+                );
+                (source_key, outcome)
+            }));
+        }
+
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+    println!("Listed {} objects under {}", total_objects, source_prefix);
+
+    let mut summary = CopyPrefixSummary::default();
+    for handle in handles {
+        let (key, outcome) = handle
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        match outcome {
+            Ok(Outcome::Copied(bytes)) => {
+                summary.bytes_copied += bytes;
+                summary.copied.push(key);
+            }
+            Ok(Outcome::Skipped) => summary.skipped.push(key),
+            Err(err) => summary.failed.push((key, err.to_string())),
+        }
+    }
+    Ok(summary)
+}
+
+/// Copies a single object, choosing between `CopyObject` and the multipart flow.
+#[allow(clippy::too_many_arguments)]
+async fn copy_one(
+    client: &Client,
+    source_bucket: &str,
+    source_key: &str,
+    destination_bucket: &str,
+    destination_key: &str,
+    size: u64,
+    e_tag: Option<String>,
+    storage_class: Option<StorageClass>,
+    options: &CopyPrefixOptions,
+) -> Result<Outcome, Error> {
+    if options.skip_existing {
+        if let Some(existing) = head_if_exists(client, destination_bucket, destination_key).await? {
+            let copied_in_parts_from = existing
+                .metadata()
+                .and_then(|metadata| metadata.get(COPY_SOURCE_ETAG))
+                .map(|e_tag| e_tag.as_str());
+            if existing.content_length() as u64 == size
+                && e_tag.is_some()
+                && (existing.e_tag() == e_tag.as_deref()
+                    || copied_in_parts_from == e_tag.as_deref())
+            {
+                return Ok(Outcome::Skipped);
+            }
+        }
+    }
+
+    if size <= options.multipart_threshold.min(MAX_COPY_OBJECT_SIZE) {
+        let mut request = client
+            .copy_object()
+            .copy_source(copy_source(source_bucket, source_key))
+            .bucket(destination_bucket)
+            .key(destination_key)
+            .tagging_directive(TaggingDirective::Copy)
+            .set_storage_class(storage_class);
+        if options.replace_metadata {
+            request = request
+                .metadata_directive(MetadataDirective::Replace)
+                .set_metadata(Some(options.metadata.clone()));
+        }
+        request.send().await?;
+    } else {
+        copy_object_multipart(
+            client,
+            source_bucket,
+            source_key,
+            destination_bucket,
+            destination_key,
+            size,
+            storage_class,
+            options,
+        )
+        .await?;
+    }
+    Ok(Outcome::Copied(size))
+}
+
+/// Returns the destination object's metadata, or `None` if it does not exist.
+async fn head_if_exists(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<aws_sdk_s3::output::HeadObjectOutput>, Error> {
+    match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(head) => Ok(Some(head)),
+        Err(SdkError::ServiceError { err, .. }) if err.is_not_found() => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Copies an object larger than `multipart_threshold` with `UploadPartCopy`.
+///
+/// Unlike `CopyObject`, the multipart flow does not carry metadata or tags over
+/// from the source, so they are read up front and set on the new upload,
+/// with the ETag of the source in `COPY_SOURCE_ETAG`.
+#[allow(clippy::too_many_arguments)]
+pub async fn copy_object_multipart(
+    client: &Client,
+    source_bucket: &str,
+    source_key: &str,
+    destination_bucket: &str,
+    destination_key: &str,
+    size: u64,
+    storage_class: Option<StorageClass>,
+    options: &CopyPrefixOptions,
+) -> Result<String, Error> {
+    copy_object_multipart_with_headers(
+        client,
+        source_bucket,
+        source_key,
+        destination_bucket,
+        destination_key,
+        size,
+        storage_class,
+        options,
+        &HeaderMap::new(),
+    )
+    .await
+}
+
+/// Same as `copy_object_multipart`, adding `headers` to the
+/// CompleteMultipartUpload, such as `If-Match` or `If-None-Match` to only
+/// replace the destination if it is still as expected. The upload is
+/// aborted when the completion fails.
+#[allow(clippy::too_many_arguments)]
+pub async fn copy_object_multipart_with_headers(
+    client: &Client,
+    source_bucket: &str,
+    source_key: &str,
+    destination_bucket: &str,
+    destination_key: &str,
+    size: u64,
+    storage_class: Option<StorageClass>,
+    options: &CopyPrefixOptions,
+    headers: &HeaderMap,
+) -> Result<String, Error> {
+    let source = client
+        .head_object()
+        .bucket(source_bucket)
+        .key(source_key)
+        .send()
+        .await?;
+    let tags = client
+        .get_object_tagging()
+        .bucket(source_bucket)
+        .key(source_key)
+        .send()
+        .await?;
+    let tagging = tags
+        .tag_set()
+        .unwrap_or_default()
+        .iter()
+        .map(|tag| {
+            format!(
+                "{}={}",
+                utf8_percent_encode(tag.key().unwrap_or_default(), COPY_SOURCE),
+                utf8_percent_encode(tag.value().unwrap_or_default(), COPY_SOURCE)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    let mut metadata = if options.replace_metadata {
+        options.metadata.clone()
+    } else {
+        source.metadata().cloned().unwrap_or_default()
+    };
+    if let Some(e_tag) = source.e_tag() {
+        metadata.insert(COPY_SOURCE_ETAG.to_string(), e_tag.to_string());
+    }
+
+    let u = client
+        .create_multipart_upload()
+        .bucket(destination_bucket)
+        .key(destination_key)
+        .set_metadata(Some(metadata))
+        .set_content_type(source.content_type().map(|t| t.to_string()))
+        .set_storage_class(storage_class)
+        .set_tagging(if tagging.is_empty() {
+            None
+        } else {
+            Some(tagging)
+        })
+        .send()
+        .await?;
+    let uid = u.upload_id().ok_or(Error::NoSuchUpload(
+        aws_sdk_s3::error::NoSuchUpload::builder()
+            .message("No upload ID")
+            .build(),
+    ))?;
+
+    let result = copy_parts(
+        client,
+        source_bucket,
+        source_key,
+        destination_bucket,
+        destination_key,
+        uid,
+        size,
+        options.part_size,
+    )
+    .await;
+    let completed_parts = match result {
+        Ok(parts) => parts,
+        Err(err) => {
+            client
+                .abort_multipart_upload()
+                .bucket(destination_bucket)
+                .key(destination_key)
+                .upload_id(uid)
+                .send()
+                .await?;
+            return Err(err);
+        }
+    };
+
+    let b = CompletedMultipartUpload::builder()
+        .set_parts(Some(completed_parts))
+        .build();
+    let mut request = client
+        .complete_multipart_upload()
+        .multipart_upload(b)
+        .upload_id(uid)
+        .bucket(destination_bucket)
+        .key(destination_key)
+        .customize()
+        .await?;
+    request.request_mut().headers_mut().extend(headers.clone());
+    let completed = match request.send().await {
+        Ok(completed) => completed,
+        Err(err) => {
+            client
+                .abort_multipart_upload()
+                .bucket(destination_bucket)
+                .key(destination_key)
+                .upload_id(uid)
+                .send()
+                .await?;
+            return Err(err.into());
+        }
+    };
+    Ok(completed
+        .e_tag()
+        .unwrap_or_default()
+        .trim_matches('"')
+        .to_string())
+}
+
+/// Copies `size` bytes of the source in `part_size` ranges.
+#[allow(clippy::too_many_arguments)]
+async fn copy_parts(
+    client: &Client,
+    source_bucket: &str,
+    source_key: &str,
+    destination_bucket: &str,
+    destination_key: &str,
+    uid: &str,
+    size: u64,
+    part_size: u64,
+) -> Result<Vec<CompletedPart>, Error> {
+    let mut completed_parts = Vec::new();
+    let mut offset = 0u64;
+    let mut part_number = 1;
+    while offset < size {
+        let end = (offset + part_size).min(size) - 1;
+        let up = client
+            .upload_part_copy()
+            .copy_source(copy_source(source_bucket, source_key))
+            .copy_source_range(format!("bytes={}-{}", offset, end))
+            .bucket(destination_bucket)
+            .key(destination_key)
+            .upload_id(uid)
+            .part_number(part_number)
+            .send()
+            .await?;
+        let cp = CompletedPart::builder()
+            .set_e_tag(
+                up.copy_part_result()
+                    .and_then(|r| r.e_tag())
+                    .map(|t| t.to_string()),
+            )
+            .part_number(part_number)
+            .build();
+        completed_parts.push(cp);
+        offset = end + 1;
+        part_number += 1;
+    }
+    Ok(completed_parts)
+}
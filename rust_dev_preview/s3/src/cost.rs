@@ -0,0 +1,194 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Rough monthly cost estimates for the objects in a bucket.
+//!
+//! The unit prices are embedded from `pricing.json` and are only updated by
+//! hand, so they may be outdated; see https://aws.amazon.com/s3/pricing/ for
+//! current prices.
+
+use aws_sdk_s3::{Client, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+const PRICING_JSON: &str = include_str!("pricing.json");
+
+/// Region whose prices are used for Regions missing from `pricing.json`.
+pub const FALLBACK_PRICING_REGION: &str = "us-east-1";
+
+const GB: f64 = (1u64 << 30) as f64;
+
+#[derive(Debug, Clone, Deserialize)]
+struct ClassPricing {
+    storage_per_gb_month: f64,
+    put_per_1000: f64,
+    get_per_1000: f64,
+    /// Objects smaller than this are billed as if they were this size.
+    #[serde(default)]
+    min_billable_bytes: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RegionPricing {
+    data_transfer_out_per_gb: f64,
+    storage_classes: HashMap<String, ClassPricing>,
+}
+
+fn pricing() -> HashMap<String, RegionPricing> {
+    serde_json::from_str(PRICING_JSON).expect("pricing.json is valid")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size_bytes: u64,
+    /// The storage class as listed, such as `STANDARD` or `GLACIER`.
+    pub storage_class: String,
+}
+
+/// Usage assumptions of the estimate.
+#[derive(Debug, Clone)]
+pub struct CostOptions {
+    /// GET requests per object per month.
+    pub gets_per_object_per_month: f64,
+    /// PUT requests per object per month (rewrites).
+    pub puts_per_object_per_month: f64,
+    /// Fraction of the stored bytes downloaded to the internet per month.
+    pub transfer_out_fraction: f64,
+}
+
+impl Default for CostOptions {
+    fn default() -> Self {
+        Self {
+            gets_per_object_per_month: 1.0,
+            puts_per_object_per_month: 0.0,
+            transfer_out_fraction: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StorageClassCost {
+    pub objects: u64,
+    pub bytes: u64,
+    pub storage: f64,
+    pub requests: f64,
+}
+
+/// Monthly cost estimate, in USD.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CostEstimate {
+    pub region: String,
+    /// The Region whose prices were used; differs from `region` when it has
+    /// no prices in `pricing.json`.
+    pub pricing_region: String,
+    pub by_storage_class: BTreeMap<String, StorageClassCost>,
+    pub storage: f64,
+    pub requests: f64,
+    pub data_transfer: f64,
+    pub total: f64,
+    /// Storage classes without a price, estimated as STANDARD.
+    pub unknown_storage_classes: Vec<String>,
+}
+
+/// Estimates the monthly cost of storing `objects` in `region`, assuming one
+/// GET per object per month and no data transfer out.
+pub fn estimate_monthly_cost(objects: &[ObjectInfo], region: &str) -> CostEstimate {
+    estimate_monthly_cost_with(objects, region, &CostOptions::default())
+}
+
+/// Estimates the monthly cost of storing `objects` in `region`.
+pub fn estimate_monthly_cost_with(
+    objects: &[ObjectInfo],
+    region: &str,
+    options: &CostOptions,
+) -> CostEstimate {
+    let mut pricing = pricing();
+    let pricing_region = if pricing.contains_key(region) {
+        region
+    } else {
+        FALLBACK_PRICING_REGION
+    };
+    let prices = pricing
+        .remove(pricing_region)
+        .expect("fallback Region is priced");
+    let standard = &prices.storage_classes["STANDARD"];
+
+    let mut estimate = CostEstimate {
+        region: region.to_string(),
+        pricing_region: pricing_region.to_string(),
+        ..Default::default()
+    };
+    let mut total_bytes = 0;
+    for object in objects {
+        let class = match prices.storage_classes.get(&object.storage_class) {
+            Some(class) => class,
+            None => {
+                if !estimate
+                    .unknown_storage_classes
+                    .contains(&object.storage_class)
+                {
+                    estimate
+                        .unknown_storage_classes
+                        .push(object.storage_class.clone());
+                }
+                standard
+            }
+        };
+        let billable = object.size_bytes.max(class.min_billable_bytes);
+        let cost = estimate
+            .by_storage_class
+            .entry(object.storage_class.clone())
+            .or_default();
+        cost.objects += 1;
+        cost.bytes += object.size_bytes;
+        cost.storage += billable as f64 / GB * class.storage_per_gb_month;
+        cost.requests += options.gets_per_object_per_month * class.get_per_1000 / 1000.0
+            + options.puts_per_object_per_month * class.put_per_1000 / 1000.0;
+        total_bytes += object.size_bytes;
+    }
+
+    estimate.storage = estimate.by_storage_class.values().map(|c| c.storage).sum();
+    estimate.requests = estimate.by_storage_class.values().map(|c| c.requests).sum();
+    estimate.data_transfer =
+        total_bytes as f64 / GB * options.transfer_out_fraction * prices.data_transfer_out_per_gb;
+    estimate.total = estimate.storage + estimate.requests + estimate.data_transfer;
+    estimate
+}
+
+/// Lists every object under `prefix` with its size and storage class.
+pub async fn list_all_objects(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+) -> Result<Vec<ObjectInfo>, Error> {
+    let mut objects = Vec::new();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+        for object in resp.contents().unwrap_or_default() {
+            objects.push(ObjectInfo {
+                key: object.key().unwrap_or_default().to_string(),
+                size_bytes: object.size() as u64,
+                storage_class: object
+                    .storage_class()
+                    .map(|c| c.as_str())
+                    .unwrap_or("STANDARD")
+                    .to_string(),
+            });
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+    Ok(objects)
+}
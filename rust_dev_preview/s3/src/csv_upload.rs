@@ -0,0 +1,237 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Streaming CSV upload with per-row schema validation.
+
+use crate::multipart_writer::MultipartWriter;
+use aws_sdk_s3::{Client, Error};
+use chrono::NaiveDate;
+use csv_async::{AsyncReaderBuilder, AsyncWriter, AsyncWriterBuilder, StringRecord};
+use futures::StreamExt;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Number of row errors kept in `CsvUploadReport::sample_errors`.
+const MAX_SAMPLE_ERRORS: usize = 10;
+
+/// Type of a CSV column. Empty values are accepted for every type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    String,
+    Integer,
+    Float,
+    /// A date in `YYYY-MM-DD` format.
+    Date,
+}
+
+#[derive(Debug, Clone)]
+pub struct CsvColumn {
+    pub name: String,
+    pub column_type: ColumnType,
+}
+
+/// Expected header and column types of a CSV file.
+#[derive(Debug, Clone, Default)]
+pub struct CsvSchema {
+    pub columns: Vec<CsvColumn>,
+}
+
+impl CsvSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a column to the schema.
+    pub fn column(mut self, name: &str, column_type: ColumnType) -> Self {
+        self.columns.push(CsvColumn {
+            name: name.to_string(),
+            column_type,
+        });
+        self
+    }
+
+    /// Checks a data row against the schema.
+    pub fn validate(&self, record: &StringRecord) -> Result<(), String> {
+        if record.len() != self.columns.len() {
+            return Err(format!(
+                "expected {} fields, found {}",
+                self.columns.len(),
+                record.len()
+            ));
+        }
+        for (field, column) in record.iter().zip(&self.columns) {
+            if field.is_empty() {
+                continue;
+            }
+            let valid = match column.column_type {
+                ColumnType::String => true,
+                ColumnType::Integer => field.parse::<i64>().is_ok(),
+                ColumnType::Float => field.parse::<f64>().is_ok(),
+                ColumnType::Date => NaiveDate::parse_from_str(field, "%Y-%m-%d").is_ok(),
+            };
+            if !valid {
+                return Err(format!(
+                    "column {}: {:?} is not a valid {:?}",
+                    column.name, field, column.column_type
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of `upload_csv_validated`.
+#[derive(Debug, Default, Clone)]
+pub struct CsvUploadReport {
+    pub total_rows: u64,
+    pub valid_rows: u64,
+    pub error_rows: u64,
+    pub sample_errors: Vec<String>,
+}
+
+/// Streams a CSV file from `reader` to `bucket/key`, dropping the rows that
+/// do not match `schema`.
+///
+/// The header row must match the schema's column names. Rejected rows are
+/// logged to stderr; the valid rows are re-serialized and uploaded in 8 MB
+/// parts.
+pub async fn upload_csv_validated(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    reader: impl AsyncRead + Unpin + Send,
+    schema: CsvSchema,
+) -> Result<CsvUploadReport, Error> {
+    let mut csv_reader = AsyncReaderBuilder::new()
+        .has_headers(true)
+        .create_reader(reader);
+    let headers = csv_reader
+        .headers()
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+        .clone();
+    let expected = schema.columns.iter().map(|c| c.name.as_str());
+    if !headers.iter().eq(expected) {
+        return Err(Error::Unhandled(Box::from(format!(
+            "CSV header {:?} does not match the schema",
+            headers
+        ))));
+    }
+
+    let mut writer = MultipartWriter::new(client, bucket, key);
+    let mut rows = RowWriter::new();
+    let mut report = CsvUploadReport::default();
+    let result = async {
+        writer.write(&rows.serialize(&headers).await?).await?;
+        let mut records = csv_reader.records();
+        while let Some(record) = records.next().await {
+            report.total_rows += 1;
+            // A quoted field can span lines, so the line is where the row
+            // starts, as the reader counts it.
+            let position = match &record {
+                Ok(record) => record.position(),
+                Err(err) => err.position(),
+            };
+            let line = position.map_or_else(|| "?".to_string(), |p| p.line().to_string());
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    reject(&mut report, format!("line {}: {}", line, err));
+                    continue;
+                }
+            };
+            match schema.validate(&record) {
+                Ok(()) => {
+                    report.valid_rows += 1;
+                    writer.write(&rows.serialize(&record).await?).await?;
+                }
+                Err(err) => reject(&mut report, format!("line {}: {}", line, err)),
+            }
+        }
+        Ok::<(), Error>(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => {
+            writer.finish().await?;
+            Ok(report)
+        }
+        Err(err) => {
+            writer.abort().await?;
+            Err(err)
+        }
+    }
+}
+
+fn reject(report: &mut CsvUploadReport, message: String) {
+    eprintln!("Rejected row: {}", message);
+    report.error_rows += 1;
+    if report.sample_errors.len() < MAX_SAMPLE_ERRORS {
+        report.sample_errors.push(message);
+    }
+}
+
+/// One CSV writer for every row of an upload, serializing each row into a
+/// buffer the row is then taken out of.
+struct RowWriter {
+    writer: AsyncWriter<RowBuffer>,
+    buffer: RowBuffer,
+}
+
+impl RowWriter {
+    fn new() -> Self {
+        let buffer = RowBuffer::default();
+        let writer = AsyncWriterBuilder::new()
+            .has_headers(false)
+            .create_writer(buffer.clone());
+        Self { writer, buffer }
+    }
+
+    /// The bytes of `record`, as a line of CSV.
+    async fn serialize(&mut self, record: &StringRecord) -> Result<Vec<u8>, Error> {
+        self.writer
+            .write_record(record)
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        self.writer
+            .flush()
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        Ok(self.buffer.take())
+    }
+}
+
+/// Bytes written by a `RowWriter`, shared with the CSV writer it owns.
+#[derive(Clone, Default)]
+struct RowBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl RowBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl AsyncWrite for RowBuffer {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,161 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Reproducible part schedules for `upload_multipart_parallel`, to chase
+//! bugs that depend on the interleaving of the parts. Only built with the
+//! `debug-tools` feature.
+//!
+//! A schedule starts the parts in an order shuffled from a seed, a few at a
+//! time, and can hold chosen parts back for a fixed delay. The order in
+//! which the parts were started and finished is recorded, so a failing seed
+//! found against a mock server can be replayed there with the same result.
+
+use crate::cli::parse_duration;
+use crate::retry::RetryPolicy;
+use crate::upload::{upload_multipart_parallel_with_hooks, DispatchHooks, PartEvent};
+use aws_sdk_s3::{Client, Error};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+/// Parts in flight at once under a schedule, unless it sets `concurrency`.
+pub const DEFAULT_SCHEDULE_CONCURRENCY: usize = 4;
+
+/// A `--debug-schedule` value such as `seed=42` or `seed=42,concurrency=2`.
+#[derive(Debug, Clone)]
+pub struct DebugSchedule {
+    pub seed: u64,
+    pub concurrency: usize,
+    /// A wait before uploading each of these parts, by part number.
+    pub delays: HashMap<i32, Duration>,
+    log: Arc<Mutex<Vec<PartEvent>>>,
+}
+
+impl std::str::FromStr for DebugSchedule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut seed = None;
+        let mut concurrency = DEFAULT_SCHEDULE_CONCURRENCY;
+        for setting in value.split(',') {
+            let (name, number) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("Expected name=value, not {}", setting))?;
+            let number = number
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid {}: {}", name, number))?;
+            match name.trim() {
+                "seed" => seed = Some(number),
+                "concurrency" if number > 0 => concurrency = number as usize,
+                "concurrency" => return Err("concurrency must be at least 1".to_string()),
+                other => return Err(format!("Unknown schedule setting: {}", other)),
+            }
+        }
+        Ok(Self {
+            seed: seed.ok_or("The schedule needs a seed, as seed=<n>")?,
+            concurrency,
+            delays: HashMap::new(),
+            log: Arc::default(),
+        })
+    }
+}
+
+/// A delays file, such as:
+///
+/// ```toml
+/// [[part]]
+/// number = 3
+/// delay = "250ms"
+/// ```
+#[derive(Deserialize)]
+struct DelaysFile {
+    #[serde(default)]
+    part: Vec<PartDelay>,
+}
+
+#[derive(Deserialize)]
+struct PartDelay {
+    number: i32,
+    delay: String,
+}
+
+/// Parses the TOML content of a delays file.
+pub fn parse_delays(content: &str) -> Result<HashMap<i32, Duration>, String> {
+    let file: DelaysFile = toml::from_str(content).map_err(|err| err.to_string())?;
+    file.part
+        .into_iter()
+        .map(|part| {
+            let delay = parse_duration(&part.delay)
+                .map_err(|err| format!("part {}: {}", part.number, err))?;
+            Ok((part.number, delay))
+        })
+        .collect()
+}
+
+impl DebugSchedule {
+    /// Adds the per-part delays of the delays file at `path`.
+    pub fn load_delays(&mut self, path: &Path) -> Result<(), Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        let delays = parse_delays(&content)
+            .map_err(|err| Error::Unhandled(Box::from(format!("{}: {}", path.display(), err))))?;
+        self.delays.extend(delays);
+        Ok(())
+    }
+
+    /// The indexes of `num_parts` parts in the order they are started.
+    pub fn dispatch_order(&self, num_parts: usize) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..num_parts).collect();
+        order.shuffle(&mut StdRng::seed_from_u64(self.seed));
+        order
+    }
+
+    /// The events recorded by the uploads run with this schedule.
+    pub fn events(&self) -> Vec<PartEvent> {
+        self.log.lock().unwrap().clone()
+    }
+}
+
+/// `upload_multipart_parallel` with the parts started and delayed as
+/// `schedule` says. The events are recorded in `schedule` even when the
+/// upload fails.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_multipart_parallel_with_schedule(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    policy: &RetryPolicy,
+    schedule: &DebugSchedule,
+) -> Result<String, Error> {
+    let hooks = DispatchHooks {
+        order: Some(schedule.dispatch_order(num_parts.max(1))),
+        permits: Some(Arc::new(Semaphore::new(schedule.concurrency))),
+        delays: schedule.delays.clone(),
+        log: Some(schedule.log.clone()),
+        ..Default::default()
+    };
+    upload_multipart_parallel_with_hooks(
+        client,
+        bucket,
+        key,
+        file_name,
+        num_parts,
+        buffer_capacity,
+        None,
+        policy,
+        hooks,
+    )
+    .await
+}
@@ -0,0 +1,66 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Directory markers: the empty objects, with a key ending in `/`, that the
+//! Amazon S3 console creates as folders.
+//!
+//! A bucket has no directories, only keys, so a marker is just an object
+//! whose key the console shows as a folder. Uploading a file to such a key
+//! hides its content under what looks like an empty folder, so the upload
+//! tools refuse keys ending in `/` unless they are told otherwise, and
+//! markers are created on purpose with `make_dir_marker`.
+
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Error};
+
+/// The Content-Type of a directory marker.
+pub const DIRECTORY_CONTENT_TYPE: &str = "application/x-directory";
+
+/// Whether `key` names a directory marker.
+pub fn is_dir_marker(key: &str) -> bool {
+    key.ends_with('/')
+}
+
+/// Fails for a key ending in `/` unless `allow_dir_marker` is set, so that
+/// a file is not uploaded under a folder name by mistake.
+pub fn check_upload_key(key: &str, allow_dir_marker: bool) -> Result<(), Error> {
+    if is_dir_marker(key) && !allow_dir_marker {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} ends with /, which the console shows as a folder rather than an object. \
+             Use make-dir to create a folder, name the object, or pass --allow-dir-marker \
+             to upload to this key anyway.",
+            key
+        ))));
+    }
+    Ok(())
+}
+
+/// The key of the marker of the directory `dir`, which gets a trailing `/`
+/// if it has none.
+pub fn dir_marker_key(dir: &str) -> String {
+    if is_dir_marker(dir) {
+        dir.to_string()
+    } else {
+        format!("{}/", dir)
+    }
+}
+
+/// Creates the marker of the directory `dir` in `bucket`: an empty object
+/// with the Content-Type `application/x-directory`. Returns its key.
+pub async fn make_dir_marker(client: &Client, bucket: &str, dir: &str) -> Result<String, Error> {
+    let key = dir_marker_key(dir);
+    if key == "/" {
+        return Err(Error::Unhandled(Box::from("The directory name is empty")));
+    }
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(&key)
+        .content_type(DIRECTORY_CONTENT_TYPE)
+        .body(ByteStream::from(Vec::new()))
+        .send()
+        .await?;
+    Ok(key)
+}
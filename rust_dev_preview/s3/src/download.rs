@@ -0,0 +1,666 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Object downloads.
+
+use crate::bandwidth::{ThrottledRead, TokenBucket};
+use crate::batch::{expand_remote, read_indexes, PackedLocation};
+use crate::dir_marker::is_dir_marker;
+use crate::download_reader::fetch_in_order;
+use crate::durable::{write_file, FsyncOptions, FsyncStats, SyncingWriter};
+use crate::parallel_download::{download_ranges, ObjectPin, DEFAULT_PART_SIZE};
+use crate::preserve::{apply, create_symlink, FileMetadata, RestoreOptions};
+use crate::sync::{list_remote, RemoteObject, MAX_REPLANS};
+use crate::upload::SourceWindow;
+use crate::verbosity::{with_request_id, VerbosityConfig};
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder};
+use aws_sdk_s3::output::GetObjectOutput;
+use aws_sdk_s3::{Client, Error};
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::StreamReader;
+
+/// Outcome of `download_auto_decompress`.
+#[derive(Debug, Clone)]
+pub struct DecompressResult {
+    /// The file the object was written to.
+    pub path: PathBuf,
+    /// Bytes received from Amazon S3.
+    pub compressed_bytes: u64,
+    /// Bytes written to `path`.
+    pub decompressed_bytes: u64,
+    /// `decompressed_bytes / compressed_bytes`; 1.0 for uncompressed objects.
+    pub compression_ratio: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Gzip,
+    Brotli,
+}
+
+/// Downloads `bucket/key` to `dest_path`, decompressing it on the fly.
+///
+/// Gzip is detected from a `content-encoding: gzip` header or a `.gz` key;
+/// Brotli from `content-encoding: br`. Anything else is written as is. When
+/// decompressing because of the extension, `.gz` is stripped from
+/// `dest_path`.
+pub async fn download_auto_decompress(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    dest_path: &Path,
+) -> Result<DecompressResult, Error> {
+    let resp = client.get_object().bucket(bucket).key(key).send().await?;
+
+    let encoding = resp
+        .content_encoding()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let by_extension = key.ends_with(".gz");
+    let compression = match encoding.as_str() {
+        "gzip" | "x-gzip" => Compression::Gzip,
+        "br" => Compression::Brotli,
+        _ if by_extension => Compression::Gzip,
+        _ => Compression::None,
+    };
+    let path = match dest_path.to_str() {
+        Some(name) if compression == Compression::Gzip => match name.strip_suffix(".gz") {
+            Some(stripped) => PathBuf::from(stripped),
+            None => dest_path.to_path_buf(),
+        },
+        _ => dest_path.to_path_buf(),
+    };
+
+    let compressed_bytes = Arc::new(AtomicU64::new(0));
+    let counter = compressed_bytes.clone();
+    let body = resp
+        .body
+        .inspect_ok(move |chunk| {
+            counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        })
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err));
+    let reader = StreamReader::new(body);
+    let mut reader: Box<dyn AsyncRead + Unpin + Send> = match compression {
+        Compression::Gzip => {
+            let mut decoder = GzipDecoder::new(reader);
+            // Concatenated gzip members are valid and common for appended logs.
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        Compression::Brotli => Box::new(BrotliDecoder::new(reader)),
+        Compression::None => Box::new(reader),
+    };
+
+    let mut file = tokio::fs::File::create(&path)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let decompressed_bytes = tokio::io::copy(&mut reader, &mut file)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    file.flush()
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+
+    let compressed_bytes = compressed_bytes.load(Ordering::Relaxed);
+    let compression_ratio = if compressed_bytes == 0 {
+        1.0
+    } else {
+        decompressed_bytes as f64 / compressed_bytes as f64
+    };
+    Ok(DecompressResult {
+        path,
+        compressed_bytes,
+        decompressed_bytes,
+        compression_ratio,
+    })
+}
+
+/// Outcome of `download_prefix`.
+#[derive(Debug, Default, Clone)]
+pub struct PrefixDownloadSummary {
+    pub files: u64,
+    pub bytes: u64,
+    /// Files extracted from archive objects.
+    pub unpacked_files: u64,
+    pub fsync: FsyncStats,
+    /// Symbolic links recreated from their preserved metadata.
+    pub symlinks: u64,
+    /// Directories created for the directory markers under the prefix.
+    pub directories: u64,
+    /// What could not be restored of the preserved metadata, each once.
+    pub warnings: Vec<String>,
+    /// Keys of the objects that kept changing between the listing and
+    /// their download, which were not written.
+    pub unstable: Vec<String>,
+    /// From the listing to the last file written.
+    pub elapsed: Duration,
+}
+
+impl PrefixDownloadSummary {
+    /// The throughput achieved over `elapsed`.
+    pub fn bytes_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.bytes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    fn warn(&mut self, warnings: Vec<String>) {
+        for warning in warnings {
+            if !self.warnings.contains(&warning) {
+                self.warnings.push(warning);
+            }
+        }
+    }
+}
+
+/// GetObject of `object` as it was listed.
+///
+/// A response with another size or ETag is from an object replaced since
+/// the listing: its body is dropped, and the download is planned again for
+/// the new size and ETag and sent again. After `MAX_REPLANS` changes,
+/// returns `None`, for an object too unstable to download.
+async fn get_as_listed(
+    client: &Client,
+    bucket: &str,
+    object: &RemoteObject,
+) -> Result<Option<GetObjectOutput>, Error> {
+    let mut size = object.size;
+    let mut e_tag = object.e_tag.clone();
+    for replans in 0..=MAX_REPLANS {
+        let resp = client
+            .get_object()
+            .bucket(bucket)
+            .key(&object.key)
+            .send()
+            .await?;
+        let got_size = resp.content_length().max(0) as u64;
+        let got_e_tag = resp.e_tag().map(|t| t.trim_matches('"').to_string());
+        let same_e_tag = match (&e_tag, &got_e_tag) {
+            (Some(planned), Some(got)) => planned == got,
+            _ => true,
+        };
+        if got_size == size && same_e_tag {
+            return Ok(Some(resp));
+        }
+        if replans < MAX_REPLANS {
+            eprintln!(
+                "{} changed since it was listed ({} bytes, now {}); planning it again",
+                object.key, size, got_size
+            );
+        }
+        size = got_size;
+        e_tag = got_e_tag;
+    }
+    Ok(None)
+}
+
+/// Downloads every object under `prefix` to `dest_dir`, recreating the key
+/// hierarchy below the prefix as directories. A directory marker, such as
+/// the folders created in the console, becomes an empty directory.
+///
+/// With `unpack_batches`, the archives written by a batched sync are
+/// expanded into the files they hold (each archive is fetched once) and the
+/// archive and index objects themselves are not downloaded.
+///
+/// An object whose GetObject does not have the size and ETag it was listed
+/// with is planned again with `get_as_listed`, and listed in `unstable`
+/// if it keeps changing.
+pub async fn download_prefix(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    dest_dir: &Path,
+    unpack_batches: bool,
+) -> Result<PrefixDownloadSummary, Error> {
+    download_prefix_with_options(
+        client,
+        bucket,
+        prefix,
+        dest_dir,
+        unpack_batches,
+        &FsyncOptions::default(),
+        None,
+    )
+    .await
+}
+
+/// Same as `download_prefix`, syncing the files as set by `fsync`. Each file
+/// is written to `<name>.part` and renamed once complete.
+///
+/// With `preserve`, the file metadata recorded by `sync-directory
+/// --preserve` is applied to each file once written. The symbolic links are
+/// created last, so that a link to a directory cannot redirect the files
+/// written below it. Files extracted from archives have no metadata.
+pub async fn download_prefix_with_options(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    dest_dir: &Path,
+    unpack_batches: bool,
+    fsync: &FsyncOptions,
+    preserve: Option<&RestoreOptions>,
+) -> Result<PrefixDownloadSummary, Error> {
+    download_prefix_with_verbosity(
+        client,
+        bucket,
+        prefix,
+        dest_dir,
+        unpack_batches,
+        fsync,
+        preserve,
+        VerbosityConfig::default(),
+    )
+    .await
+}
+
+/// Same as `download_prefix_with_options`, printing a line with the request
+/// ID of its GetObject as each object that is not in an archive is written,
+/// as `verbosity` says.
+#[allow(clippy::too_many_arguments)]
+pub async fn download_prefix_with_verbosity(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    dest_dir: &Path,
+    unpack_batches: bool,
+    fsync: &FsyncOptions,
+    preserve: Option<&RestoreOptions>,
+    verbosity: VerbosityConfig,
+) -> Result<PrefixDownloadSummary, Error> {
+    let start = Instant::now();
+    let mut remote = list_remote(client, bucket, prefix).await?;
+    let packed = if unpack_batches {
+        let indexes = read_indexes(client, bucket, &remote).await?;
+        expand_remote(&mut remote, &indexes)
+    } else {
+        HashMap::new()
+    };
+
+    let objects = remote
+        .keys()
+        .filter(|key| !packed.contains_key(*key) && !is_dir_marker(key))
+        .count();
+    let mut index = 0;
+    let mut summary = PrefixDownloadSummary::default();
+    let mut symlinks = Vec::new();
+    let mut by_archive: HashMap<&str, Vec<(&str, &PackedLocation)>> = HashMap::new();
+    for (key, object) in &remote {
+        match packed.get(key) {
+            Some(location) => by_archive
+                .entry(location.archive_key.as_str())
+                .or_default()
+                .push((key.as_str(), location)),
+            None if is_dir_marker(key) => {
+                let path = local_path(dest_dir, prefix, key)?;
+                tokio::fs::create_dir_all(&path)
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                summary.directories += 1;
+            }
+            None => {
+                let path = local_path(dest_dir, prefix, key)?;
+                create_parent(&path).await?;
+                index += 1;
+                let object_start = Instant::now();
+                let (resp, request_id) =
+                    with_request_id(get_as_listed(client, bucket, object)).await;
+                let resp = match resp? {
+                    Some(resp) => resp,
+                    None => {
+                        summary.unstable.push(key.clone());
+                        continue;
+                    }
+                };
+                let preserved = preserve.map(|_| {
+                    FileMetadata::from_metadata(resp.metadata().unwrap_or(&HashMap::new()))
+                });
+                if preserved
+                    .as_ref()
+                    .map_or(false, |m| m.symlink_target.is_some())
+                {
+                    symlinks.push((path, preserved.unwrap_or_default()));
+                    continue;
+                }
+                let mut body = StreamReader::new(
+                    resp.body
+                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)),
+                );
+                let (bytes, stats) = write_file(&mut body, &path, fsync)
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                verbosity.report(
+                    "object",
+                    "downloaded",
+                    index,
+                    objects,
+                    bytes,
+                    object_start.elapsed(),
+                    request_id.as_deref(),
+                );
+                summary.bytes += bytes;
+                summary.fsync.add(&stats);
+                summary.files += 1;
+                if let (Some(options), Some(metadata)) = (preserve, preserved) {
+                    let warnings = apply(&path, &metadata, options)
+                        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                    summary.warn(warnings);
+                }
+            }
+        }
+    }
+
+    for (archive_key, entries) in by_archive {
+        let data = client
+            .get_object()
+            .bucket(bucket)
+            .key(archive_key)
+            .send()
+            .await?
+            .body
+            .collect()
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?
+            .into_bytes();
+        for (key, location) in entries {
+            let start = location.offset as usize;
+            let end = start + location.size as usize;
+            let content = data.get(start..end).ok_or_else(|| {
+                Error::Unhandled(Box::from(format!(
+                    "Archive {} is too short for {}",
+                    archive_key, key
+                )))
+            })?;
+            let path = local_path(dest_dir, prefix, key)?;
+            create_parent(&path).await?;
+            let (_, stats) = write_file(&mut &content[..], &path, fsync)
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+            summary.fsync.add(&stats);
+            summary.files += 1;
+            summary.unpacked_files += 1;
+            summary.bytes += location.size;
+        }
+    }
+
+    if let Some(options) = preserve {
+        for (path, metadata) in symlinks {
+            restore_symlink(&path, &metadata, options, &mut summary)
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        }
+    }
+    summary.unstable.sort();
+    summary.elapsed = start.elapsed();
+    Ok(summary)
+}
+
+/// Same as `download_prefix`, downloading `concurrency` objects at a time
+/// with a combined throughput of at most `max_bps` bytes per second, and
+/// syncing the files as set by `fsync`.
+///
+/// The bodies of all the downloads read from one `TokenBucket`; see the
+/// `bandwidth` module. Archives are not unpacked and no metadata is
+/// restored.
+pub async fn download_prefix_rate_limited(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    dest_dir: &Path,
+    max_bps: u64,
+    concurrency: usize,
+    fsync: &FsyncOptions,
+) -> Result<PrefixDownloadSummary, Error> {
+    let start = Instant::now();
+    let limit = Arc::new(Mutex::new(
+        TokenBucket::new(max_bps).map_err(|err| Error::Unhandled(Box::from(err)))?,
+    ));
+    let remote = list_remote(client, bucket, prefix).await?;
+
+    let mut summary = PrefixDownloadSummary::default();
+    let mut objects = Vec::new();
+    for (key, object) in &remote {
+        if is_dir_marker(key) {
+            let path = local_path(dest_dir, prefix, key)?;
+            tokio::fs::create_dir_all(&path)
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+            summary.directories += 1;
+        } else {
+            objects.push(object);
+        }
+    }
+    let results = stream::iter(objects)
+        .map(|object| {
+            let limit = limit.clone();
+            async move {
+                let path = local_path(dest_dir, prefix, &object.key)?;
+                create_parent(&path).await?;
+                let resp = match get_as_listed(client, bucket, object).await? {
+                    Some(resp) => resp,
+                    None => return Ok((&object.key, None)),
+                };
+                let mut body = ThrottledRead::new(
+                    StreamReader::new(
+                        resp.body
+                            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)),
+                    ),
+                    limit,
+                );
+                let written = write_file(&mut body, &path, fsync)
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                Ok::<_, Error>((&object.key, Some(written)))
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    for result in results {
+        match result? {
+            (_, Some((bytes, stats))) => {
+                summary.bytes += bytes;
+                summary.fsync.add(&stats);
+                summary.files += 1;
+            }
+            (key, None) => summary.unstable.push(key.clone()),
+        }
+    }
+    summary.unstable.sort();
+    summary.elapsed = start.elapsed();
+    Ok(summary)
+}
+
+/// Replaces `path` with the symbolic link of `metadata`. Where links cannot
+/// be created, the link is skipped with a warning.
+fn restore_symlink(
+    path: &Path,
+    metadata: &FileMetadata,
+    options: &RestoreOptions,
+    summary: &mut PrefixDownloadSummary,
+) -> std::io::Result<()> {
+    let target = match &metadata.symlink_target {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+    if std::fs::symlink_metadata(path).is_ok() {
+        std::fs::remove_file(path)?;
+    }
+    if let Err(err) = create_symlink(target, path) {
+        if cfg!(unix) {
+            return Err(err);
+        }
+        summary.warn(vec![err.to_string()]);
+        return Ok(());
+    }
+    let warnings = apply(path, metadata, options)?;
+    summary.warn(warnings);
+    summary.symlinks += 1;
+    Ok(())
+}
+
+/// Outcome of `download_into_window`.
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowDownload {
+    pub window: SourceWindow,
+    /// Lowercase hex SHA-256 of the bytes written.
+    pub sha256: String,
+    pub fsync: FsyncStats,
+}
+
+/// Downloads `bucket/key` into an existing file, overwriting the bytes from
+/// `offset` on and leaving the rest of the file untouched; the mirror of an
+/// upload with `--source-offset`.
+///
+/// The object must fit in the file: a window extending past the end of the
+/// file is rejected before anything is written.
+pub async fn download_into_window(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    offset: u64,
+) -> Result<WindowDownload, Error> {
+    download_into_window_with_split(client, bucket, key, path, offset, 1).await
+}
+
+/// Like `download_into_window`, but with `split` above 1 the object is read
+/// in ranges pinned to its ETag or version, `split` at a time, and written
+/// in order; a single slow stream then no longer sets the pace.
+///
+/// Each range is a `split`th of the object, capped at `DEFAULT_PART_SIZE`
+/// so that at most `2 * split` ranges are held in memory; an object smaller
+/// than `split` bytes is read in fewer, single-byte ranges. An endpoint that
+/// ignores Range and sends the whole object fails the download instead of
+/// corrupting the window.
+pub async fn download_into_window_with_split(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    offset: u64,
+    split: usize,
+) -> Result<WindowDownload, Error> {
+    download_into_window_with_fsync(
+        client,
+        bucket,
+        key,
+        path,
+        offset,
+        split,
+        &FsyncOptions::default(),
+    )
+    .await
+}
+
+/// Like `download_into_window_with_split`, syncing the file as set by
+/// `fsync`. The file is written in place, so there is no directory entry
+/// to sync.
+pub async fn download_into_window_with_fsync(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    offset: u64,
+    split: usize,
+    fsync: &FsyncOptions,
+) -> Result<WindowDownload, Error> {
+    let file_len = tokio::fs::metadata(path)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+        .len();
+    let (length, mut chunks): (u64, BoxStream<'static, Result<Bytes, Error>>) = if split <= 1 {
+        let resp = client.get_object().bucket(bucket).key(key).send().await?;
+        let body = resp
+            .body
+            .map_err(|err| Error::Unhandled(Box::new(err)))
+            .boxed();
+        (resp.content_length() as u64, body)
+    } else {
+        let head = client.head_object().bucket(bucket).key(key).send().await?;
+        let size = head.content_length().max(0) as u64;
+        let pin = ObjectPin::new(head.e_tag(), head.version_id());
+        let part_size = ((size + split as u64 - 1) / split as u64).min(DEFAULT_PART_SIZE);
+        let ranges = download_ranges(size, part_size, None);
+        let fetches = fetch_in_order(client, bucket, key, pin, ranges, split, 2 * split);
+        (size, fetches.boxed())
+    };
+    let window = SourceWindow::resolve(file_len, Some(offset), Some(length))
+        .map_err(|msg| Error::Unhandled(Box::from(format!("{}: {}", path.display(), msg))))?;
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    file.seek(std::io::SeekFrom::Start(window.offset))
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let mut file = SyncingWriter::new(file, *fsync);
+    let mut hasher = Sha256::new();
+    let mut written = 0;
+    while let Some(chunk) = chunks.try_next().await? {
+        written += chunk.len() as u64;
+        if written > window.length {
+            return Err(Error::Unhandled(Box::from(format!(
+                "{} sent more than its {} bytes",
+                key, window.length
+            ))));
+        }
+        hasher.update(&chunk);
+        file.write_all(&chunk)
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    }
+    if written != window.length {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} ended after {} of its {} bytes",
+            key, written, window.length
+        ))));
+    }
+    let (_, stats) = file
+        .finish()
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    Ok(WindowDownload {
+        window,
+        sha256: format!("{:x}", hasher.finalize()),
+        fsync: stats,
+    })
+}
+
+/// Maps `key` to a path under `dest_dir`, refusing keys that would escape it.
+pub(crate) fn local_path(dest_dir: &Path, prefix: &str, key: &str) -> Result<PathBuf, Error> {
+    let relative = key.strip_prefix(prefix).unwrap_or(key);
+    if relative.split('/').any(|part| part == "..") {
+        return Err(Error::Unhandled(Box::from(format!(
+            "Refusing to download {} outside of {}",
+            key,
+            dest_dir.display()
+        ))));
+    }
+    Ok(dest_dir.join(relative.trim_start_matches('/')))
+}
+
+async fn create_parent(path: &Path) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    }
+    Ok(())
+}
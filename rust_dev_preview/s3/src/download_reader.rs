@@ -0,0 +1,120 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! The bytes of an object, in order, while ranges are fetched ahead in
+//! parallel.
+//!
+//! `download_multipart_reader` returns an `AsyncRead` to stream a large
+//! object into a parser without a file. Like `download_parallel`, it reads
+//! the object in ranges pinned to the ETag or version found by HeadObject,
+//! `concurrency` at a time. A range that arrives before the ones ahead of it
+//! is held in a reorder window of `max_buffered_parts` ranges, counting the
+//! ones in flight; once the window is full, no further range is requested
+//! until the reader consumes the oldest one, so a slow reader or a slow
+//! range holds at most `max_buffered_parts` ranges in memory.
+//!
+//! Nothing is requested until the reader is first read. Errors, including
+//! the `ObjectChanged` of an overwritten object, surface as an
+//! `std::io::Error` wrapping the `aws_sdk_s3::Error`.
+
+use crate::parallel_download::{download_ranges, get_range, ObjectPin, DEFAULT_PART_SIZE};
+use aws_sdk_s3::{Client, Error};
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use std::sync::Arc;
+use tokio::io::AsyncRead;
+use tokio::sync::Semaphore;
+use tokio_util::io::StreamReader;
+
+#[derive(Debug, Clone)]
+pub struct OrderedReadOptions {
+    pub part_size: u64,
+    /// The ranges requested at the same time.
+    pub concurrency: usize,
+    /// The ranges in flight or waiting for the reader. At least
+    /// `concurrency`, or fewer ranges are in flight.
+    pub max_buffered_parts: usize,
+}
+
+impl Default for OrderedReadOptions {
+    fn default() -> Self {
+        Self {
+            part_size: DEFAULT_PART_SIZE,
+            concurrency: 8,
+            max_buffered_parts: 16,
+        }
+    }
+}
+
+/// A reader of the bytes of `bucket/key`, in order, fetched in ranges as
+/// `options` says.
+pub fn download_multipart_reader(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    options: &OrderedReadOptions,
+) -> impl AsyncRead + Send + Unpin {
+    let client = client.clone();
+    let bucket = bucket.to_string();
+    let key = key.to_string();
+    let options = options.clone();
+    let ranges = stream::once(async move {
+        let head = client
+            .head_object()
+            .bucket(&bucket)
+            .key(&key)
+            .send()
+            .await?;
+        let size = head.content_length().max(0) as u64;
+        let pin = ObjectPin::new(head.e_tag(), head.version_id());
+        Ok::<_, Error>(fetch_in_order(
+            &client,
+            &bucket,
+            &key,
+            pin,
+            download_ranges(size, options.part_size, None),
+            options.concurrency,
+            options.max_buffered_parts,
+        ))
+    })
+    .try_flatten()
+    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err));
+    StreamReader::new(Box::pin(ranges))
+}
+
+/// The `ranges` of the object `pin` names, fetched `concurrency` at a time
+/// and yielded in order, with at most `max_buffered` ranges in flight or
+/// waiting to be consumed.
+pub(crate) fn fetch_in_order(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    pin: ObjectPin,
+    ranges: Vec<(u64, u64)>,
+    concurrency: usize,
+    max_buffered: usize,
+) -> impl Stream<Item = Result<Bytes, Error>> + Send {
+    let client = client.clone();
+    let bucket = bucket.to_string();
+    let key = key.to_string();
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+    let fetches = ranges.into_iter().map(move |(offset, length)| {
+        let client = client.clone();
+        let bucket = bucket.clone();
+        let key = key.clone();
+        let pin = pin.clone();
+        let permits = permits.clone();
+        async move {
+            let _permit = permits
+                .acquire()
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+            get_range(&client, &bucket, &key, pin, offset, length).await
+        }
+    });
+    // `buffered` yields in order, holding the ranges that finish early,
+    // and starts no more than the window.
+    stream::iter(fetches).buffered(max_buffered.max(1))
+}
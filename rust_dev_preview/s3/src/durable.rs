@@ -0,0 +1,185 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Durable writes of downloaded files and state files.
+//!
+//! Downloaded files are written to `<name>.part` and renamed once complete,
+//! so a file under its final name is never a partial download. That alone
+//! only holds until a power loss: the data may still be in the page cache.
+//! With `FsyncOptions::enabled`, the file is fsynced before the rename and
+//! its directory after it, so once the download returns the file survives a
+//! crash. `FsyncOptions::interval` also syncs during the write, to bound the
+//! data lost from very large files.
+//!
+//! State files, such as the manifests of resumable transfers, are written
+//! the same way, so a crash leaves either the previous file or the new one.
+
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// A file that can be flushed to the storage device. Implemented for
+/// `tokio::fs::File`; tests use it to observe the syncs.
+pub trait SyncFile: AsyncWrite + Unpin + Send {
+    /// Syncs the data and metadata of the file, as `fsync`.
+    fn sync_all(&mut self) -> BoxFuture<'_, std::io::Result<()>>;
+}
+
+impl SyncFile for tokio::fs::File {
+    fn sync_all(&mut self) -> BoxFuture<'_, std::io::Result<()>> {
+        Box::pin(tokio::fs::File::sync_all(self))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsyncOptions {
+    /// Sync each file, and its directory entry, before returning.
+    pub enabled: bool,
+    /// Also sync each time this many bytes were written since the last sync.
+    pub interval: Option<u64>,
+}
+
+/// The syncs of one or more files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct FsyncStats {
+    pub syncs: u64,
+    /// The time spent in `fsync`.
+    pub time: Duration,
+}
+
+impl FsyncStats {
+    /// Counts a sync that started at `start` and just returned.
+    pub(crate) fn record(&mut self, start: Instant) {
+        self.syncs += 1;
+        self.time += start.elapsed();
+    }
+
+    pub fn add(&mut self, other: &FsyncStats) {
+        self.syncs += other.syncs;
+        self.time += other.time;
+    }
+}
+
+/// Writes to a file, syncing it according to `FsyncOptions`.
+pub struct SyncingWriter<F> {
+    file: F,
+    options: FsyncOptions,
+    unsynced: u64,
+    stats: FsyncStats,
+}
+
+impl<F: SyncFile> SyncingWriter<F> {
+    pub fn new(file: F, options: FsyncOptions) -> Self {
+        Self {
+            file,
+            options,
+            unsynced: 0,
+            stats: FsyncStats::default(),
+        }
+    }
+
+    pub async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(buf).await?;
+        if let Some(interval) = self.options.interval {
+            self.unsynced += buf.len() as u64;
+            if self.unsynced >= interval {
+                self.sync().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies `reader` to the file, returning the number of bytes copied.
+    pub async fn copy<R: AsyncRead + Unpin>(&mut self, reader: &mut R) -> std::io::Result<u64> {
+        let mut buffer = vec![0; 64 * 1024];
+        let mut copied = 0;
+        loop {
+            let n = reader.read(&mut buffer).await?;
+            if n == 0 {
+                return Ok(copied);
+            }
+            self.write_all(&buffer[..n]).await?;
+            copied += n as u64;
+        }
+    }
+
+    async fn sync(&mut self) -> std::io::Result<()> {
+        self.file.flush().await?;
+        let start = Instant::now();
+        self.file.sync_all().await?;
+        self.stats.record(start);
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Flushes the file, and syncs it if enabled. Returns the file and the
+    /// syncs made while writing it.
+    pub async fn finish(mut self) -> std::io::Result<(F, FsyncStats)> {
+        if self.options.enabled {
+            self.sync().await?;
+        } else {
+            self.file.flush().await?;
+        }
+        Ok((self.file, self.stats))
+    }
+}
+
+/// `path` with `.part` appended, where a file is written before it is
+/// complete.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Syncs the directory holding `path`, so that a rename into it is durable.
+/// Directories cannot be opened for syncing on Windows, where this does
+/// nothing.
+pub(crate) async fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    if cfg!(unix) {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        tokio::fs::File::open(dir).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+/// Writes `reader` to `path` through `<path>.part`, renamed once complete.
+/// Returns the number of bytes written and the syncs made.
+///
+/// The `.part` file is removed if the write fails.
+pub async fn write_file<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    path: &Path,
+    options: &FsyncOptions,
+) -> std::io::Result<(u64, FsyncStats)> {
+    let part = part_path(path);
+    let written = async {
+        let file = tokio::fs::File::create(&part).await?;
+        let mut writer = SyncingWriter::new(file, *options);
+        let bytes = writer.copy(reader).await?;
+        let (_, stats) = writer.finish().await?;
+        Ok((bytes, stats))
+    }
+    .await;
+    let (bytes, mut stats) = match written {
+        Ok(written) => written,
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&part).await;
+            return Err(err);
+        }
+    };
+    tokio::fs::rename(&part, path).await?;
+    if options.enabled {
+        let start = Instant::now();
+        sync_parent_dir(path).await?;
+        stats.record(start);
+    }
+    Ok((bytes, stats))
+}
@@ -0,0 +1,323 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Endpoints of multi-tenant S3 gateways, which give each bucket its own URL
+//! instead of one URL for every bucket.
+//!
+//! A template holds the bucket as `{bucket}`, either in the host name, such
+//! as `https://{bucket}.tenant-a.gw.internal`, or as the last segment of the
+//! path, such as `https://gw.internal/tenant-a/{bucket}`; `{region}` is
+//! replaced by the Region of the client.
+//!
+//! Requests go to a path template as the SDK addresses them, path-style,
+//! as `/bucket/key` appended to the URL before `/{bucket}`, which
+//! `TemplateResolver` gives the SDK; that works for any bucket. A host
+//! template needs virtual-hosted addressing, the bucket in the host name and
+//! `/key` in the path, which the SDK version of these examples does not do,
+//! and its endpoint resolver is only given the Region, not the bucket. So
+//! the `VirtualHosted` connector takes the bucket out of the path of each
+//! request, sends the request to the URL of the template for that bucket,
+//! and signs it again (see `sigv4`).
+
+use crate::sigv4::{self, error_response, signed_region, split_bucket};
+use aws_endpoint::{AwsEndpoint, BoxError, CredentialScope, ResolveAwsEndpoint};
+use aws_sdk_s3::{Endpoint, Region};
+use aws_types::credentials::{ProvideCredentials, SharedCredentialsProvider};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::Uri;
+use hyper::service::Service;
+use std::fmt;
+use std::task::{Context, Poll};
+
+/// The placeholders a template can hold.
+pub const PLACEHOLDERS: &[&str] = &["bucket", "region"];
+
+/// The bucket of the endpoint the SDK is given for a host template, which
+/// `VirtualHosted` replaces with the bucket of each request.
+const STAND_IN_BUCKET: &str = "bucket";
+
+/// The error code of the requests that cannot be sent to a template.
+const TEMPLATE_ERROR_CODE: &str = "InvalidEndpointTemplate";
+
+/// Where a template puts the bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TemplateStyle {
+    /// In the host name, such as `https://{bucket}.tenant-a.gw.internal`.
+    Host,
+    /// As the last segment of the path, such as
+    /// `https://gw.internal/tenant-a/{bucket}`.
+    Path,
+}
+
+/// A validated endpoint URL template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointTemplate {
+    template: String,
+    style: TemplateStyle,
+}
+
+impl EndpointTemplate {
+    /// Fails on an unknown placeholder, unbalanced braces, a template without
+    /// exactly one `{bucket}`, or one that is not an http or https URL once
+    /// filled in.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut buckets = 0;
+        let mut rest = template;
+        while let Some(at) = rest.find(|c| c == '{' || c == '}') {
+            if rest[at..].starts_with('}') {
+                return Err(format!("Unbalanced }} in endpoint template {}", template));
+            }
+            let end = rest[at..]
+                .find('}')
+                .map(|end| at + end)
+                .ok_or_else(|| format!("Unbalanced {{ in endpoint template {}", template))?;
+            let name = &rest[at + 1..end];
+            if name.contains('{') {
+                return Err(format!("Unbalanced {{ in endpoint template {}", template));
+            }
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in endpoint template {}; use {{bucket}} and \
+                     {{region}}",
+                    name, template
+                ));
+            }
+            if name == "bucket" {
+                buckets += 1;
+            }
+            rest = &rest[end + 1..];
+        }
+        if buckets != 1 {
+            return Err(format!(
+                "The endpoint template {} must hold {{bucket}} exactly once",
+                template
+            ));
+        }
+
+        let after_scheme = template
+            .strip_prefix("https://")
+            .or_else(|| template.strip_prefix("http://"))
+            .ok_or_else(|| {
+                format!(
+                    "The endpoint template {} must start with https:// or http://",
+                    template
+                )
+            })?;
+        if after_scheme.contains(|c| c == '?' || c == '#') {
+            return Err(format!(
+                "The endpoint template {} cannot have a query or a fragment",
+                template
+            ));
+        }
+        let authority = after_scheme.split('/').next().unwrap_or_default();
+        let style = if authority.contains("{bucket}") {
+            TemplateStyle::Host
+        } else if after_scheme.trim_end_matches('/').ends_with("/{bucket}") {
+            TemplateStyle::Path
+        } else {
+            return Err(format!(
+                "{{bucket}} must be in the host name or the last segment of the path of the \
+                 endpoint template {}",
+                template
+            ));
+        };
+        let parsed = Self {
+            template: template.to_string(),
+            style,
+        };
+        parsed
+            .bucket_url("bucket", "us-east-1")
+            .parse::<Uri>()
+            .map_err(|err| format!("Invalid endpoint template {}: {}", template, err))?;
+        Ok(parsed)
+    }
+
+    pub fn style(&self) -> TemplateStyle {
+        self.style
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// The URL of `bucket` in `region`, with every placeholder filled in.
+    pub fn bucket_url(&self, bucket: &str, region: &str) -> String {
+        self.template
+            .replace("{bucket}", bucket)
+            .replace("{region}", region)
+    }
+
+    /// The endpoint the requests for `bucket` in `region` are sent to: the
+    /// bucket URL of a host template, followed by the key, and the URL before
+    /// `/{bucket}` of a path template, as the SDK appends the bucket to it.
+    ///
+    /// Fails if `bucket` cannot be part of a host name.
+    pub fn endpoint_for(&self, bucket: &str, region: &str) -> Result<Uri, String> {
+        let url = match self.style {
+            TemplateStyle::Host => {
+                if !is_host_bucket(bucket) {
+                    return Err(format!(
+                        "The bucket {} cannot be part of the host name of the endpoint \
+                         template {}",
+                        bucket, self.template
+                    ));
+                }
+                self.bucket_url(bucket, region)
+            }
+            TemplateStyle::Path => {
+                let base = self.template.trim_end_matches('/');
+                base[..base.len() - "/{bucket}".len()].replace("{region}", region)
+            }
+        };
+        url.parse::<Uri>()
+            .map_err(|err| format!("Invalid endpoint {}: {}", url, err))
+    }
+}
+
+/// Whether `bucket` is made of DNS labels: lowercase letters, digits, and
+/// hyphens, separated by dots.
+fn is_host_bucket(bucket: &str) -> bool {
+    bucket.split('.').all(|label| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    })
+}
+
+/// An endpoint resolver filling in an `EndpointTemplate` for the Region of
+/// the client. For a host template it gives the URL of a stand-in bucket,
+/// for `VirtualHosted` to replace.
+#[derive(Debug, Clone)]
+pub struct TemplateResolver {
+    template: EndpointTemplate,
+}
+
+impl TemplateResolver {
+    pub fn new(template: EndpointTemplate) -> Self {
+        Self { template }
+    }
+
+    pub fn template(&self) -> &EndpointTemplate {
+        &self.template
+    }
+}
+
+impl ResolveAwsEndpoint for TemplateResolver {
+    fn resolve_endpoint(&self, region: &Region) -> Result<AwsEndpoint, BoxError> {
+        let uri = self
+            .template
+            .endpoint_for(STAND_IN_BUCKET, region.as_ref())?;
+        Ok(AwsEndpoint::new(
+            Endpoint::immutable(uri),
+            CredentialScope::builder().build(),
+        ))
+    }
+}
+
+/// A connector sending each request to the URL of a host template for its
+/// bucket, with the key as the path, signed again with `credentials`.
+/// Without a host template, requests go through untouched.
+#[derive(Clone)]
+pub struct VirtualHosted<C> {
+    inner: C,
+    template: Option<EndpointTemplate>,
+    credentials: Option<SharedCredentialsProvider>,
+}
+
+impl<C> VirtualHosted<C> {
+    pub fn new(
+        inner: C,
+        template: Option<EndpointTemplate>,
+        credentials: Option<SharedCredentialsProvider>,
+    ) -> Self {
+        let template = template.filter(|template| template.style() == TemplateStyle::Host);
+        Self {
+            inner,
+            template,
+            credentials,
+        }
+    }
+}
+
+impl<C> fmt::Debug for VirtualHosted<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VirtualHosted")
+            .field("template", &self.template)
+            .finish()
+    }
+}
+
+impl<C, B, RB> Service<http::Request<B>> for VirtualHosted<C>
+where
+    C: Service<http::Request<B>, Response = http::Response<RB>> + Clone + Send + 'static,
+    C::Future: Send + 'static,
+    B: Send + 'static,
+    RB: From<Bytes> + Send + 'static,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<C::Response, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        // As in `RateLimited`, the connector made ready serves this request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let template = match &self.template {
+            Some(template) => template.clone(),
+            None => return Box::pin(inner.call(request)),
+        };
+        let credentials = self.credentials.clone();
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            if let Err(message) = readdress(&template, credentials.as_ref(), &mut parts).await {
+                return Ok(error_response(TEMPLATE_ERROR_CODE, &message));
+            }
+            inner.call(http::Request::from_parts(parts, body)).await
+        })
+    }
+}
+
+/// Moves a path-style request to the URL of `template` for its bucket, as
+/// `/key`, and signs it for the Region the SDK signed it for.
+async fn readdress(
+    template: &EndpointTemplate,
+    credentials: Option<&SharedCredentialsProvider>,
+    parts: &mut http::request::Parts,
+) -> Result<(), String> {
+    let (bucket, key_path) = split_bucket(parts.uri.path()).ok_or_else(|| {
+        format!(
+            "The endpoint template {} needs a bucket, which {} {} has not",
+            template.as_str(),
+            parts.method,
+            parts.uri.path()
+        )
+    })?;
+    let region = signed_region(&parts.headers)
+        .ok_or_else(|| "The request was not signed by the SDK".to_string())?;
+    let endpoint = template.endpoint_for(bucket, &region)?;
+    let base = endpoint.to_string();
+    let base = base.trim_end_matches('/');
+    let uri = match parts.uri.query() {
+        Some(query) => format!("{}{}?{}", base, key_path, query),
+        None => format!("{}{}", base, key_path),
+    };
+    parts.uri = uri
+        .parse()
+        .map_err(|err| format!("Invalid endpoint {}: {}", uri, err))?;
+    let credentials = credentials
+        .ok_or_else(|| "No credentials to sign the request with".to_string())?
+        .provide_credentials()
+        .await
+        .map_err(|err| format!("Could not load the credentials: {}", err))?;
+    sigv4::sign(parts, &credentials, &region, "s3", "x-amz-security-token")
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Readable explanations of the most common failures, with a hint on how to
+//! fix them.
+//!
+//! Errors are matched on the text of the error and its sources, so the same
+//! catalogue works for `aws_sdk_s3::Error`, `SdkError`, and `OpError`. To add
+//! a case, add an entry to `CATALOGUE` and a test to `tests/test-error-hints.rs`.
+
+use crate::ops::OpError;
+use serde::Serialize;
+use std::error::Error as StdError;
+
+/// An entry of the catalogue.
+#[derive(Debug, PartialEq)]
+pub struct ErrorHint {
+    /// The S3 error code, or a name for failures without one.
+    pub code: &'static str,
+    pub explanation: &'static str,
+    pub hint: &'static str,
+    /// Matched as whole words in the text of the error.
+    patterns: &'static [&'static str],
+}
+
+pub const CATALOGUE: &[ErrorHint] = &[
+    ErrorHint {
+        code: "NoSuchBucket",
+        explanation: "The bucket does not exist, or it is not in the Region the request was sent to.",
+        hint: "Check the bucket name and pass the bucket's Region with --region.",
+        patterns: &["NoSuchBucket"],
+    },
+    ErrorHint {
+        code: "AccessDenied",
+        explanation: "The credentials in use are not allowed to perform this operation on this bucket or object.",
+        hint: "Check which profile is in use (--profile, AWS_PROFILE) and that its policy, and the bucket policy, allow the operation.",
+        patterns: &["AccessDenied"],
+    },
+    ErrorHint {
+        code: "ExpiredToken",
+        explanation: "The temporary credentials in use have expired.",
+        hint: "Refresh your credentials, for example with `aws sso login`, and run the command again.",
+        patterns: &[
+            "ExpiredToken",
+            "ExpiredTokenException",
+            "The provided token has expired",
+        ],
+    },
+    ErrorHint {
+        code: "SignatureDoesNotMatch",
+        explanation: "The request signature computed with your secret key does not match the one Amazon S3 computed.",
+        hint: "Check the secret access key of the profile, and that nothing between you and the endpoint rewrites the request.",
+        patterns: &["SignatureDoesNotMatch"],
+    },
+    ErrorHint {
+        code: "RequestTimeTooSkewed",
+        explanation: "The clock of this machine differs from the server's by more than 15 minutes, so the request signature is rejected.",
+        hint: "Your clock is off by more than 15 minutes: synchronize it, for example with NTP.",
+        patterns: &["RequestTimeTooSkewed"],
+    },
+    ErrorHint {
+        code: "EntityTooSmall",
+        explanation: "A part of the multipart upload, other than the last one, is smaller than 5 MiB.",
+        hint: "Part below 5 MiB: increase --part-size or use fewer parts.",
+        patterns: &["EntityTooSmall"],
+    },
+    ErrorHint {
+        code: "EntityTooLarge",
+        explanation: "The object or one of its parts is larger than the endpoint accepts; some S3-compatible endpoints cap parts well below 5 GiB.",
+        hint: "Part too large: use a smaller --part-size, or --auto-split-parts with s3-transfer upload.",
+        patterns: &["EntityTooLarge"],
+    },
+    ErrorHint {
+        code: "InvalidPart",
+        explanation: "A part listed when completing the multipart upload was not found, or its ETag does not match.",
+        hint: "Upload the file again; if it keeps failing, check that the file did not change during the upload.",
+        patterns: &["InvalidPart"],
+    },
+    ErrorHint {
+        code: "Sha256Mismatch",
+        explanation: "The bytes read from the file do not have the SHA-256 it was expected to have, so they were not stored.",
+        hint: "Check whether the file is corrupt or changed after its SHA-256 was computed, and rebuild or copy it again.",
+        patterns: &["Sha256Mismatch"],
+    },
+    ErrorHint {
+        code: "NoSuchUpload",
+        explanation: "The multipart upload does not exist: it was completed or aborted, possibly by a lifecycle rule.",
+        hint: "Start a new upload; check the bucket's lifecycle rules for AbortIncompleteMultipartUpload.",
+        patterns: &["NoSuchUpload"],
+    },
+    ErrorHint {
+        code: "SlowDown",
+        explanation: "Amazon S3 is throttling the requests to this prefix.",
+        hint: "Lower the concurrency (-c) or spread the keys over more prefixes, and try again.",
+        patterns: &["SlowDown"],
+    },
+    ErrorHint {
+        code: "ConnectionRefused",
+        explanation: "Nothing accepted the connection at the endpoint.",
+        hint: "Check --endpoint-url, including the port, and that the server is running.",
+        patterns: &["ConnectionRefused", "Connection refused"],
+    },
+    ErrorHint {
+        code: "DnsFailure",
+        explanation: "The endpoint host name could not be resolved.",
+        hint: "Check --endpoint-url and --region for typos, and your network or DNS settings.",
+        patterns: &[
+            "dns error",
+            "failed to lookup address",
+            "Name or service not known",
+            "nodename nor servname provided",
+        ],
+    },
+];
+
+/// Whether `pattern` occurs in `text` as whole words.
+pub(crate) fn contains_word(text: &str, pattern: &str) -> bool {
+    let is_word = |c: Option<char>| c.map(|c| c.is_alphanumeric()).unwrap_or(false);
+    text.match_indices(pattern).any(|(start, _)| {
+        !is_word(text[..start].chars().next_back())
+            && !is_word(text[start + pattern.len()..].chars().next())
+    })
+}
+
+/// The catalogue entry matching `text`, such as the `Debug` output of an
+/// error.
+pub fn explain_text(text: &str) -> Option<&'static ErrorHint> {
+    CATALOGUE.iter().find(|entry| {
+        entry
+            .patterns
+            .iter()
+            .any(|pattern| contains_word(text, pattern))
+    })
+}
+
+/// The catalogue entry for an S3 error code.
+pub fn explain_code(code: &str) -> Option<&'static ErrorHint> {
+    CATALOGUE.iter().find(|entry| entry.code == code)
+}
+
+/// The catalogue entry matching `err` or one of its sources.
+pub fn explain(err: &(dyn StdError + 'static)) -> Option<&'static ErrorHint> {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(entry) = explain_text(&format!("{} {:?}", err, err)) {
+            return Some(entry);
+        }
+        current = err.source();
+    }
+    None
+}
+
+/// The class of errors neither in the catalogue nor with an error code.
+pub const OTHER_ERROR_CLASS: &str = "Other";
+
+/// The class failures are grouped by in the summary of a run: the catalogue
+/// code of `err`, else the S3 error code of an `OpError` or the kind of an
+/// I/O error, from `err` or one of its sources, else `OTHER_ERROR_CLASS`.
+pub fn error_class(err: &(dyn StdError + 'static)) -> String {
+    if let Some(entry) = explain(err) {
+        return entry.code.to_string();
+    }
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(code) = err
+            .downcast_ref::<OpError>()
+            .and_then(|err| err.code.as_ref())
+        {
+            return code.clone();
+        }
+        if let Some(err) = err.downcast_ref::<std::io::Error>() {
+            return format!("{:?}", err.kind());
+        }
+        current = err.source();
+    }
+    OTHER_ERROR_CLASS.to_string()
+}
+
+/// An error as printed by the binaries.
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderedError {
+    /// The catalogue code, when the error is known.
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub explanation: Option<&'static str>,
+    pub hint: Option<&'static str>,
+    /// The full error, with `--verbose`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+impl RenderedError {
+    pub fn new(err: &(dyn StdError + 'static), verbose: bool) -> Self {
+        let entry = explain(err);
+        Self {
+            code: entry.map(|entry| entry.code),
+            message: err.to_string(),
+            explanation: entry.map(|entry| entry.explanation),
+            hint: entry.map(|entry| entry.hint),
+            details: verbose.then(|| format!("{:#?}", err)),
+        }
+    }
+
+    /// The explanation and hint as a paragraph, or the error message for
+    /// errors not in the catalogue, followed by the details.
+    pub fn to_text(&self) -> String {
+        let mut text = match (self.code, self.explanation, self.hint) {
+            (Some(code), Some(explanation), Some(hint)) => {
+                format!("Error: {} ({}) Hint: {}", explanation, code, hint)
+            }
+            _ => format!("Error: {}", self.message),
+        };
+        match &self.details {
+            Some(details) => {
+                text.push_str("\n\n");
+                text.push_str(details);
+            }
+            None if self.code.is_some() => text.push_str("\nRun with -v for the full error."),
+            None => {}
+        }
+        text
+    }
+}
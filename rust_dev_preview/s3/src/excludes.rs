@@ -0,0 +1,95 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Files left out of directory uploads, with `.gitignore` patterns.
+//!
+//! Patterns such as `*.tmp`, `.DS_Store`, or `node_modules/` come from a
+//! `.uploadignore` file at the root of the directory, from exclude files,
+//! and from the command line, in that order: as in a `.gitignore`, a later
+//! pattern wins, so `!keep.log` after `*.log` uploads `keep.log`. Excluded
+//! directories are not walked at all.
+
+use crate::ops::S3Ops;
+use crate::scheduler::{upload_files, ScheduleSummary, ScheduledFile, SchedulerOptions};
+use crate::shutdown::{Shutdown, DEFAULT_GRACE_PERIOD};
+use crate::sync::{walk_directory_filtered, LocalFile};
+use aws_sdk_s3::Error;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Path, PathBuf};
+
+/// The exclude file read from the root of an uploaded directory. It is not
+/// uploaded itself.
+pub const UPLOAD_IGNORE_FILE: &str = ".uploadignore";
+
+/// Builds the exclusions of `local_dir`: its `.uploadignore` if any, then
+/// the `exclude_files`, then `patterns`.
+pub fn build_excludes(
+    local_dir: &Path,
+    patterns: &[&str],
+    exclude_files: &[PathBuf],
+) -> Result<Gitignore, Error> {
+    let invalid = |err: ignore::Error| Error::Unhandled(Box::from(err.to_string()));
+    let mut builder = GitignoreBuilder::new(local_dir);
+    let upload_ignore = local_dir.join(UPLOAD_IGNORE_FILE);
+    if upload_ignore.is_file() {
+        if let Some(err) = builder.add(&upload_ignore) {
+            return Err(invalid(err));
+        }
+    }
+    for path in exclude_files {
+        // A missing file is reported here rather than silently ignored.
+        if !path.is_file() {
+            return Err(Error::Unhandled(Box::from(format!(
+                "Cannot read the exclude file {}",
+                path.display()
+            ))));
+        }
+        if let Some(err) = builder.add(path) {
+            return Err(invalid(err));
+        }
+    }
+    for pattern in patterns {
+        builder.add_line(None, pattern).map_err(invalid)?;
+    }
+    builder.build().map_err(invalid)
+}
+
+/// Lists the files under `local_dir` that `excludes` keeps, keyed under
+/// `s3_prefix` as `walk_directory` does.
+pub fn walk_directory_with_excludes(
+    local_dir: &Path,
+    s3_prefix: &str,
+    excludes: &Gitignore,
+) -> std::io::Result<Vec<LocalFile>> {
+    let upload_ignore = local_dir.join(UPLOAD_IGNORE_FILE);
+    walk_directory_filtered(local_dir, s3_prefix, |path, is_dir| {
+        path != upload_ignore && !excludes.matched(path, is_dir).is_ignore()
+    })
+}
+
+/// Uploads the files under `local_dir` to `bucket` under `s3_prefix`,
+/// except those matching `excludes` or the `.uploadignore` file, with
+/// `concurrency` files at a time.
+pub async fn upload_directory_with_excludes<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    local_dir: &Path,
+    s3_prefix: &str,
+    excludes: &[&str],
+    concurrency: usize,
+) -> Result<ScheduleSummary, Error> {
+    let excludes = build_excludes(local_dir, excludes, &[])?;
+    let files = walk_directory_with_excludes(local_dir, s3_prefix, &excludes)
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+        .into_iter()
+        .map(ScheduledFile::from)
+        .collect();
+    let options = SchedulerOptions {
+        concurrency,
+        ..Default::default()
+    };
+    let shutdown = Shutdown::new(DEFAULT_GRACE_PERIOD);
+    Ok(upload_files(ops, bucket, files, &options, &shutdown).await)
+}
@@ -0,0 +1,55 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! SHA-256 digests supplied by the caller, checked against the bytes an
+//! upload reads.
+//!
+//! A build system that already knows the digest of each artifact hands it
+//! to the uploader, and an upload whose bytes read from disk hash to
+//! something else fails with `Sha256Mismatch` instead of storing them: a
+//! PutObject is not sent, and a multipart upload is aborted rather than
+//! completed. The digest is computed over the bytes as they are sent, so
+//! the file is read once, and the same reads give the SHA-256 checksums S3
+//! verifies when they are requested too.
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// Parses a hex SHA-256 digest, returning it in lowercase.
+pub fn parse_sha256(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(value.to_ascii_lowercase())
+    } else {
+        Err(format!(
+            "{} is not a SHA-256 digest: expected 64 hexadecimal digits",
+            value
+        ))
+    }
+}
+
+/// The bytes read from a file do not have the SHA-256 the caller expected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sha256Mismatch {
+    pub path: PathBuf,
+    /// Lowercase hex.
+    pub expected: String,
+    /// Lowercase hex, of the bytes read.
+    pub actual: String,
+}
+
+impl fmt::Display for Sha256Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} was read with SHA-256 {}, expected {}; nothing was stored",
+            self.path.display(),
+            self.actual,
+            self.expected
+        )
+    }
+}
+
+impl std::error::Error for Sha256Mismatch {}
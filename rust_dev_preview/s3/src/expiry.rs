@@ -0,0 +1,286 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Deletion of expired objects selected by tag or by age.
+//!
+//! Lifecycle rules expire objects by prefix or tag after a fixed number of
+//! days. When each object has its own expiry date, the objects are instead
+//! listed, filtered by tag, and deleted once their date has passed. Objects
+//! can also be deleted once they are older than a given age, for a one-off
+//! cleanup that a lifecycle rule would only start at the next daily run.
+
+use crate::upload::parse_expires;
+use aws_sdk_s3::model::{Delete, ObjectIdentifier};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How many objects are checked at the same time.
+pub const TAG_CHECK_CONCURRENCY: usize = 16;
+
+/// Most keys in one DeleteObjects request.
+const MAX_DELETE_KEYS: usize = 1000;
+
+/// Parses the expiry date of an `x-amz-expiration` header such as
+/// `expiry-date="Fri, 23 Dec 2012 00:00:00 GMT", rule-id="picture-deletion-rule"`.
+pub fn parse_expiration_header(value: &str) -> Option<chrono::DateTime<Utc>> {
+    let start = value.find("expiry-date=\"")? + "expiry-date=\"".len();
+    let end = value[start..].find('"')? + start;
+    parse_expires(&value[start..end]).ok()
+}
+
+/// When `bucket/key` expires: the `Expiration` date of the lifecycle rule
+/// that applies to it, or `None` without one, or once the object is gone.
+/// The `Expires` header is a cache directive, not a retention date, and is
+/// ignored.
+async fn expiry_date(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<chrono::DateTime<Utc>>, Error> {
+    match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(head) => Ok(head.expiration().and_then(parse_expiration_header)),
+        Err(SdkError::ServiceError { err, .. }) if err.is_not_found() => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Whether `bucket/key` has the tag `tag_key=tag_value`. An object deleted
+/// since it was listed has no tags.
+async fn has_tag(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    tag_key: &str,
+    tag_value: &str,
+) -> Result<bool, Error> {
+    match client
+        .get_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+    {
+        Ok(resp) => Ok(resp
+            .tag_set()
+            .unwrap_or_default()
+            .iter()
+            .any(|tag| tag.key() == Some(tag_key) && tag.value() == Some(tag_value))),
+        Err(SdkError::ServiceError { err, .. }) if err.code() == Some("NoSuchKey") => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Whether `bucket/key` has the tag and an expiry date that has passed.
+async fn is_expired(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    tag_key: &str,
+    tag_value: &str,
+) -> Result<bool, Error> {
+    if !has_tag(client, bucket, key, tag_key, tag_value).await? {
+        return Ok(false);
+    }
+    Ok(expiry_date(client, bucket, key)
+        .await?
+        .map(|date| date <= Utc::now())
+        .unwrap_or(false))
+}
+
+/// Deletes `keys`, at most `MAX_DELETE_KEYS`, with one DeleteObjects
+/// request. Returns the keys S3 could not delete, with the reason.
+async fn delete_batch(
+    client: &Client,
+    bucket: &str,
+    keys: &[String],
+) -> Result<Vec<(String, String)>, Error> {
+    let objects = keys
+        .iter()
+        .map(|key| ObjectIdentifier::builder().key(key).build())
+        .collect();
+    let resp = client
+        .delete_objects()
+        .bucket(bucket)
+        .delete(
+            Delete::builder()
+                .set_objects(Some(objects))
+                .quiet(true)
+                .build(),
+        )
+        .send()
+        .await?;
+    Ok(resp
+        .errors()
+        .unwrap_or_default()
+        .iter()
+        .map(|error| {
+            (
+                error.key().unwrap_or_default().to_string(),
+                error.message().unwrap_or_default().to_string(),
+            )
+        })
+        .collect())
+}
+
+/// Deletes `keys` from `bucket` with DeleteObjects, returning how many were
+/// deleted. Keys S3 could not delete are reported and not counted.
+pub async fn delete_keys(client: &Client, bucket: &str, keys: &[String]) -> Result<u64, Error> {
+    let mut deleted = 0;
+    for batch in keys.chunks(MAX_DELETE_KEYS) {
+        let errors = delete_batch(client, bucket, batch).await?;
+        for (key, message) in &errors {
+            eprintln!("Could not delete {}: {}", key, message);
+        }
+        deleted += (batch.len() - errors.len()) as u64;
+    }
+    Ok(deleted)
+}
+
+/// Outcome of `delete_expired_tagged_objects`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExpirySummary {
+    /// How many expired objects were deleted.
+    pub deleted: u64,
+    /// The keys that could not be checked or deleted, with the error, by
+    /// key.
+    pub failed: Vec<(String, String)>,
+}
+
+/// Deletes the objects of `bucket` tagged `tag_key=tag_value` whose expiry
+/// date has passed.
+///
+/// Each object costs a GetObjectTagging, and each tagged one a HeadObject,
+/// run `TAG_CHECK_CONCURRENCY` at a time. The bucket is handled a listing
+/// page at a time: the expired objects of a page are deleted before the
+/// next page is listed. An object that cannot be checked or deleted is
+/// recorded in `ExpirySummary::failed` and the others go on; only a failed
+/// listing stops the run.
+pub async fn delete_expired_tagged_objects(
+    client: &Client,
+    bucket: &str,
+    tag_key: &str,
+    tag_value: &str,
+) -> Result<ExpirySummary, Error> {
+    let mut summary = ExpirySummary::default();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+        let keys: Vec<String> = resp
+            .contents()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|object| object.key().map(|key| key.to_string()))
+            .collect();
+        let checked: Vec<_> = stream::iter(keys)
+            .map(|key| async move {
+                let expired = is_expired(client, bucket, &key, tag_key, tag_value).await;
+                (key, expired)
+            })
+            .buffer_unordered(TAG_CHECK_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut expired = Vec::new();
+        for (key, result) in checked {
+            match result {
+                Ok(true) => expired.push(key),
+                Ok(false) => {}
+                Err(err) => summary.failed.push((key, err.to_string())),
+            }
+        }
+        for batch in expired.chunks(MAX_DELETE_KEYS) {
+            match delete_batch(client, bucket, batch).await {
+                Ok(errors) => {
+                    summary.deleted += (batch.len() - errors.len()) as u64;
+                    summary.failed.extend(errors);
+                }
+                Err(err) => summary
+                    .failed
+                    .extend(batch.iter().map(|key| (key.clone(), err.to_string()))),
+            }
+        }
+
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+    summary.failed.sort();
+    Ok(summary)
+}
+
+/// The objects found older than an age.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AgedObjects {
+    pub keys: Vec<String>,
+    /// The total size of the objects.
+    pub bytes: u64,
+}
+
+/// Lists the objects of `bucket` under `prefix` last modified more than
+/// `max_age` ago. An object modified in the future, as with a skewed clock,
+/// is not old.
+pub async fn find_objects_older_than(
+    client: &Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    max_age: Duration,
+) -> Result<AgedObjects, Error> {
+    let now = SystemTime::now();
+    let mut aged = AgedObjects::default();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .set_prefix(prefix.map(|p| p.to_string()))
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+        for object in resp.contents().unwrap_or_default() {
+            let (key, last_modified) = match (object.key(), object.last_modified()) {
+                (Some(key), Some(last_modified)) => (key, last_modified),
+                _ => continue,
+            };
+            let modified = UNIX_EPOCH + Duration::from_secs(last_modified.secs().max(0) as u64);
+            let old = now
+                .duration_since(modified)
+                .map(|age| age > max_age)
+                .unwrap_or(false);
+            if old {
+                aged.keys.push(key.to_string());
+                aged.bytes += object.size().max(0) as u64;
+            }
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+    Ok(aged)
+}
+
+/// Deletes the objects of `bucket` under `prefix` last modified more than
+/// `max_age` ago, and returns how many were deleted.
+///
+/// The whole prefix is listed before anything is deleted, so the objects
+/// are those older than `max_age` when the command started.
+pub async fn delete_objects_older_than(
+    client: &Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    max_age: Duration,
+) -> Result<u64, Error> {
+    let aged = find_objects_older_than(client, bucket, prefix, max_age).await?;
+    delete_keys(client, bucket, &aged.keys).await
+}
@@ -0,0 +1,168 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Client-side failover between the gateway nodes of an S3-compatible
+//! cluster.
+//!
+//! An `EndpointPool` holds one client per endpoint, all built from the same
+//! configuration. Requests go to the current endpoint; when one fails at the
+//! connection level (refused, DNS, TLS, timeout) the pool moves to the next
+//! endpoint, and the retry of that request and every later request use it.
+//! Error responses from a reachable endpoint, 4xx or 5xx, never trigger a
+//! failover.
+
+use crate::retry::{retry_sdk, RetryPolicy, SlowDownCoordinator};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::Client;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Whether `err` means the endpoint could not be reached, as opposed to an
+/// error response.
+pub fn is_connection_error<E>(err: &SdkError<E>) -> bool {
+    matches!(
+        err,
+        SdkError::DispatchFailure(_) | SdkError::TimeoutError(_)
+    )
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// The endpoint URLs, for the log, and their clients.
+    endpoints: Vec<(String, Client)>,
+    current: AtomicUsize,
+    failovers: AtomicU64,
+    reprobe_interval: Option<Duration>,
+    failed_over_at: Mutex<Option<Instant>>,
+}
+
+/// The endpoints of a cluster, in order of preference. Cloning is cheap and
+/// clones share the current endpoint and the failover count.
+#[derive(Debug, Clone)]
+pub struct EndpointPool {
+    inner: Arc<Inner>,
+}
+
+impl EndpointPool {
+    /// Creates a pool that starts with the first endpoint.
+    ///
+    /// With a `reprobe_interval`, the pool is sticky: after failing over it
+    /// stays on the new endpoint until the interval has passed, then sends
+    /// the next request to the first endpoint again to see if it is back.
+    pub fn new(endpoints: Vec<(String, Client)>, reprobe_interval: Option<Duration>) -> Self {
+        assert!(!endpoints.is_empty(), "An endpoint pool needs an endpoint");
+        Self {
+            inner: Arc::new(Inner {
+                endpoints,
+                current: AtomicUsize::new(0),
+                failovers: AtomicU64::new(0),
+                reprobe_interval,
+                failed_over_at: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// A pool with a single endpoint, which never fails over.
+    pub fn single(client: Client) -> Self {
+        Self::new(vec![("default".to_string(), client)], None)
+    }
+
+    /// Whether there is more than one endpoint to fail over to.
+    pub fn has_alternatives(&self) -> bool {
+        self.inner.endpoints.len() > 1
+    }
+
+    /// The number of failovers so far, including failed re-probes.
+    pub fn failovers(&self) -> u64 {
+        self.inner.failovers.load(Ordering::SeqCst)
+    }
+
+    /// The URL of the current endpoint.
+    pub fn current_endpoint(&self) -> &str {
+        &self.inner.endpoints[self.inner.current.load(Ordering::SeqCst)].0
+    }
+
+    /// The index and client of the endpoint the next request should use.
+    pub fn client(&self) -> (usize, Client) {
+        let inner = &*self.inner;
+        let mut index = inner.current.load(Ordering::SeqCst);
+        if index != 0 {
+            if let Some(interval) = inner.reprobe_interval {
+                let mut failed_over_at = inner.failed_over_at.lock().unwrap();
+                let due = failed_over_at
+                    .map(|at| at.elapsed() >= interval)
+                    .unwrap_or(false);
+                if due
+                    && inner
+                        .current
+                        .compare_exchange(index, 0, Ordering::SeqCst, Ordering::SeqCst)
+                        .is_ok()
+                {
+                    eprintln!("Re-probing preferred endpoint {}", inner.endpoints[0].0);
+                    *failed_over_at = None;
+                    index = 0;
+                }
+            }
+        }
+        (index, inner.endpoints[index].1.clone())
+    }
+
+    /// Records the failure of a request sent to endpoint `index`. A
+    /// connection-level failure moves the pool to the next endpoint, unless
+    /// another request already moved it away from `index`.
+    pub fn report_failure<E>(&self, index: usize, err: &SdkError<E>)
+    where
+        E: std::error::Error + 'static,
+    {
+        let inner = &*self.inner;
+        if inner.endpoints.len() < 2 || !is_connection_error(err) {
+            return;
+        }
+        let next = (index + 1) % inner.endpoints.len();
+        if inner
+            .current
+            .compare_exchange(index, next, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            let count = inner.failovers.fetch_add(1, Ordering::SeqCst) + 1;
+            *inner.failed_over_at.lock().unwrap() = Some(Instant::now());
+            eprintln!(
+                "Endpoint {} failed ({}), failing over to {} (failover {})",
+                inner.endpoints[index].0, err, inner.endpoints[next].0, count
+            );
+        }
+    }
+
+    /// `retry_sdk` for an operation that can run against any endpoint: each
+    /// attempt gets the current client, so an attempt that failed because
+    /// its endpoint went away is retried on the next one.
+    pub async fn retry<T, E, F, Fut>(
+        &self,
+        policy: &RetryPolicy,
+        coordinator: &SlowDownCoordinator,
+        what: &str,
+        mut op: F,
+    ) -> Result<T, SdkError<E>>
+    where
+        F: FnMut(Client) -> Fut,
+        Fut: Future<Output = Result<T, SdkError<E>>>,
+        E: std::error::Error + 'static,
+    {
+        retry_sdk(policy, coordinator, what, || {
+            let (index, client) = self.client();
+            let attempt = op(client);
+            async move {
+                let result = attempt.await;
+                if let Err(err) = &result {
+                    self.report_failure(index, err);
+                }
+                result
+            }
+        })
+        .await
+    }
+}
@@ -0,0 +1,254 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Clients that multiplex requests over HTTP/2 connections.
+//!
+//! Over HTTP/1.1 a connection carries one request at a time, so a run of
+//! concurrent requests opens as many connections, each with its own TCP and
+//! TLS handshake. Over HTTP/2 they share one connection as streams, and only
+//! the first request pays for the setup. HTTP/2 is negotiated with ALPN, so
+//! `https` endpoints that do not offer it are still reached over HTTP/1.1;
+//! plain `http` endpoints always are.
+//!
+//! The flow-control windows are raised from the 64 KiB of the protocol's
+//! defaults, which would otherwise stall a stream after each window until
+//! the peer acknowledges it. They bound what the endpoint may send before
+//! an acknowledgment, such as a response body; how much of an upload is in
+//! flight is bounded by the windows the endpoint advertises.
+//!
+//! Multiplexing has a cost: the streams of a connection share one TCP
+//! congestion window, so a lost packet slows every request on it at once,
+//! where separate HTTP/1.1 connections would each recover on their own, and
+//! a few connections can reach more bandwidth than one. It suits many small
+//! requests, where setup dominates, better than a few large parts on a
+//! lossy link. `compare-http-versions` measures both on an endpoint.
+
+use crate::connect::https_connector;
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Error};
+use aws_smithy_client::hyper_ext;
+use futures::future::BoxFuture;
+use http::Uri;
+use hyper::service::Service;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+/// The window of each stream.
+pub const HTTP2_STREAM_WINDOW: u32 = 8 * 1024 * 1024;
+
+/// The window of a connection, shared by its streams.
+pub const HTTP2_CONNECTION_WINDOW: u32 = 32 * 1024 * 1024;
+
+/// The HTTP version a client prefers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HttpVersion {
+    Http1,
+    /// HTTP/2, with at most this many requests in flight.
+    Http2 {
+        max_concurrent_streams: u32,
+    },
+}
+
+/// Counts the connections a client opens.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionCounter {
+    opened: Arc<AtomicU64>,
+}
+
+impl ConnectionCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn opened(&self) -> u64 {
+        self.opened.load(Ordering::SeqCst)
+    }
+}
+
+/// A connector that counts the connections it opens into a
+/// `ConnectionCounter`.
+#[derive(Debug, Clone)]
+pub struct CountConnections<C> {
+    inner: C,
+    counter: ConnectionCounter,
+}
+
+impl<C> CountConnections<C> {
+    pub fn new(inner: C, counter: ConnectionCounter) -> Self {
+        Self { inner, counter }
+    }
+}
+
+impl<C> Service<Uri> for CountConnections<C>
+where
+    C: Service<Uri>,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = C::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        self.counter.opened.fetch_add(1, Ordering::SeqCst);
+        self.inner.call(uri)
+    }
+}
+
+/// A connector that lets at most a number of requests be in flight at a
+/// time, as streams of its HTTP/2 connections.
+///
+/// Hyper opens a stream for every request it is given, up to the limit the
+/// endpoint sets in its SETTINGS, and queues the others; the client has no
+/// setting of its own for it.
+#[derive(Debug, Clone)]
+pub struct StreamLimited<C> {
+    inner: C,
+    streams: Arc<Semaphore>,
+}
+
+impl<C> StreamLimited<C> {
+    pub fn new(inner: C, max_concurrent_streams: u32) -> Self {
+        Self {
+            inner,
+            streams: Arc::new(Semaphore::new(max_concurrent_streams.max(1) as usize)),
+        }
+    }
+}
+
+impl<C, R> Service<R> for StreamLimited<C>
+where
+    C: Service<R> + Clone + Send + 'static,
+    C::Future: Send + 'static,
+    R: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<C::Response, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        // The connector that was made ready serves this request; the clone
+        // left in its place is made ready for the next one.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let streams = self.streams.clone();
+        Box::pin(async move {
+            // The semaphore is never closed.
+            let _stream = streams.acquire_owned().await.unwrap();
+            inner.call(request).await
+        })
+    }
+}
+
+/// Creates a client that prefers HTTP/2, with windows sized for large
+/// transfers and at most `max_concurrent_streams` requests in flight.
+pub fn build_s3_client_http2(config: aws_sdk_s3::Config, max_concurrent_streams: u32) -> Client {
+    build_s3_client_counted(
+        config,
+        HttpVersion::Http2 {
+            max_concurrent_streams,
+        },
+        &ConnectionCounter::new(),
+    )
+}
+
+/// Creates a client using `version`, counting the connections it opens into
+/// `counter`.
+pub fn build_s3_client_counted(
+    config: aws_sdk_s3::Config,
+    version: HttpVersion,
+    counter: &ConnectionCounter,
+) -> Client {
+    match version {
+        HttpVersion::Http1 => {
+            // Without h2 in ALPN, the endpoint cannot pick HTTP/2.
+            let connector = hyper_rustls::HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_or_http()
+                .enable_http1()
+                .build();
+            let connector = CountConnections::new(connector, counter.clone());
+            Client::from_conf_conn(config, hyper_ext::Adapter::builder().build(connector))
+        }
+        HttpVersion::Http2 {
+            max_concurrent_streams,
+        } => {
+            let connector = CountConnections::new(https_connector(), counter.clone());
+            let mut hyper_builder = hyper::Client::builder();
+            hyper_builder
+                .http2_initial_stream_window_size(HTTP2_STREAM_WINDOW)
+                .http2_initial_connection_window_size(HTTP2_CONNECTION_WINDOW);
+            let adapter = hyper_ext::Adapter::builder()
+                .hyper_builder(hyper_builder)
+                .build(connector);
+            Client::from_conf_conn(config, StreamLimited::new(adapter, max_concurrent_streams))
+        }
+    }
+}
+
+/// How long a run of sequential uploads took.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SequentialTimings {
+    pub uploads: usize,
+    /// The first upload, which opens the connection.
+    pub first: Duration,
+    /// All of them.
+    pub total: Duration,
+}
+
+impl SequentialTimings {
+    /// The mean of the uploads after the first, on a reused connection.
+    pub fn mean_reused(&self) -> Duration {
+        match self.uploads {
+            0 | 1 => Duration::ZERO,
+            n => (self.total - self.first) / (n as u32 - 1),
+        }
+    }
+
+    /// What the first upload took over the others: the connection setup.
+    pub fn setup_overhead(&self) -> Duration {
+        self.first.saturating_sub(self.mean_reused())
+    }
+}
+
+/// Uploads `count` objects of `size` bytes one after the other, as
+/// `{prefix}0`, `{prefix}1`, and so on, and times them.
+pub async fn time_sequential_uploads(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    count: usize,
+    size: usize,
+) -> Result<SequentialTimings, Error> {
+    let body = vec![0u8; size];
+    let start = Instant::now();
+    let mut first = Duration::ZERO;
+    for n in 0..count {
+        client
+            .put_object()
+            .bucket(bucket)
+            .key(format!("{}{}", prefix, n))
+            .body(ByteStream::from(body.clone()))
+            .send()
+            .await?;
+        if n == 0 {
+            first = start.elapsed();
+        }
+    }
+    Ok(SequentialTimings {
+        uploads: count,
+        first,
+        total: start.elapsed(),
+    })
+}
@@ -0,0 +1,178 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Uploads that do nothing when the object already holds the file.
+//!
+//! Upload IDs are generated by Amazon S3, so two processes uploading the
+//! same file to the same key cannot share one. They can instead skip the
+//! transfer: the ETag of an object uploaded without SSE-KMS or SSE-C is
+//! the MD5 of its content, or for a multipart upload the MD5 of the MD5s of
+//! its parts followed by `-` and the number of parts. `upload_idempotent`
+//! computes that ETag from the local file and compares it with the one of
+//! the existing object before uploading.
+//!
+//! The layout of a multipart object is read back from the object: the
+//! number of parts from its ETag, and the size of the first part from
+//! HeadObject with `partNumber=1`. Every part but the last has that size,
+//! which holds both for the uploads of this crate and for the AWS CLI.
+
+use crate::upload::{upload_auto, AutoUploadConfig};
+use crate::upload_status::{is_md5, md5_digest_range};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use md5::{Digest, Md5};
+use std::path::Path;
+
+/// The outcome of `upload_idempotent`, with the ETag of the object,
+/// without quotes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdempotentUploadResult {
+    /// The object already held the file; nothing was sent.
+    AlreadyUploaded(String),
+    /// The object was missing or held other content, and the file was
+    /// uploaded.
+    Uploaded(String),
+}
+
+impl IdempotentUploadResult {
+    pub fn e_tag(&self) -> &str {
+        match self {
+            Self::AlreadyUploaded(e_tag) | Self::Uploaded(e_tag) => e_tag,
+        }
+    }
+}
+
+/// How an object was split into parts: every part but the last has
+/// `part_size` bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PartLayout {
+    pub num_parts: u64,
+    pub part_size: u64,
+}
+
+/// The number of parts of a multipart ETag, such as 3 for
+/// `d41d8cd98f00b204e9800998ecf8427e-3`.
+pub fn multipart_etag_parts(e_tag: &str) -> Option<u64> {
+    let (md5, parts) = e_tag.trim_matches('"').split_once('-')?;
+    if !is_md5(md5) {
+        return None;
+    }
+    parts.parse().ok().filter(|parts| *parts > 0)
+}
+
+/// The ETag of an object uploaded from the `size` bytes of the file at
+/// `path`: in one request without `layout`, else in the parts of `layout`.
+/// Fails when the parts do not cover the file.
+pub async fn local_etag(
+    path: &Path,
+    size: u64,
+    layout: Option<PartLayout>,
+) -> std::io::Result<String> {
+    let layout = match layout {
+        None => {
+            let digest = md5_digest_range(path, 0, size).await?;
+            return Ok(hex(&digest));
+        }
+        Some(layout) => layout,
+    };
+    let fits = layout.num_parts > 0
+        && layout.part_size > 0
+        && layout
+            .part_size
+            .checked_mul(layout.num_parts - 1)
+            .map_or(false, |covered| covered < size);
+    if !fits {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{} parts of {} bytes do not fit a file of {} bytes",
+                layout.num_parts, layout.part_size, size
+            ),
+        ));
+    }
+    let mut hasher = Md5::new();
+    for index in 0..layout.num_parts {
+        let offset = index * layout.part_size;
+        let length = if index == layout.num_parts - 1 {
+            size - offset
+        } else {
+            layout.part_size
+        };
+        hasher.update(md5_digest_range(path, offset, length).await?);
+    }
+    Ok(format!("{}-{}", hex(&hasher.finalize()), layout.num_parts))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The ETag of bucket/key when the object holds the `size` bytes of the
+/// file at `path`; `None` when it is missing, holds other content, or has
+/// an ETag that is not an MD5, as with SSE-KMS.
+pub async fn existing_upload(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    size: u64,
+) -> Result<Option<String>, Error> {
+    let head = match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(head) => head,
+        Err(SdkError::ServiceError { err, .. }) if err.is_not_found() => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if head.content_length().max(0) as u64 != size {
+        return Ok(None);
+    }
+    let e_tag = head.e_tag().unwrap_or_default().trim_matches('"');
+    let layout = if is_md5(e_tag) {
+        None
+    } else if let Some(num_parts) = multipart_etag_parts(e_tag) {
+        let first = client
+            .head_object()
+            .bucket(bucket)
+            .key(key)
+            .part_number(1)
+            .send()
+            .await?;
+        Some(PartLayout {
+            num_parts,
+            part_size: first.content_length().max(0) as u64,
+        })
+    } else {
+        return Ok(None);
+    };
+    match local_etag(path, size, layout).await {
+        Ok(local) if local.eq_ignore_ascii_case(e_tag) => Ok(Some(e_tag.to_string())),
+        Ok(_) => Ok(None),
+        // A layout that does not fit the file is another file.
+        Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => Ok(None),
+        Err(err) => Err(Error::Unhandled(Box::new(err))),
+    }
+}
+
+/// Uploads `file_name` to bucket/key with `upload_auto`, unless the object
+/// already holds it, as told by its ETag.
+///
+/// Two processes uploading the same file at the same time may both find
+/// the object missing and both upload it; the object ends up holding the
+/// file either way.
+pub async fn upload_idempotent(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+) -> Result<IdempotentUploadResult, Error> {
+    let size = tokio::fs::metadata(file_name)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+        .len();
+    if let Some(e_tag) = existing_upload(client, bucket, key, Path::new(file_name), size).await? {
+        return Ok(IdempotentUploadResult::AlreadyUploaded(e_tag));
+    }
+    let e_tag = upload_auto(client, bucket, key, file_name, AutoUploadConfig::default()).await?;
+    Ok(IdempotentUploadResult::Uploaded(e_tag))
+}
@@ -0,0 +1,422 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Integrity manifests stored next to uploaded objects.
+//!
+//! After an upload, `<key>.integrity.json` records the SHA-256 of the whole
+//! object and of each of its parts, so a download can later be checked
+//! against the bucket itself rather than a local file:
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "key": "records/2022/03.csv",
+//!   "size": 20971520,
+//!   "uploaded_at": "2022-03-01T12:00:00Z",
+//!   "parts": [
+//!     { "number": 1, "offset": 0, "size": 10485760, "sha256": "5c1e…" },
+//!     { "number": 2, "offset": 10485760, "size": 10485760, "sha256": "e3b0…" }
+//!   ],
+//!   "sha256": "9f86…",
+//!   "hostname": "ingest-01",
+//!   "tool_version": "s3_code_examples 0.1.0"
+//! }
+//! ```
+//!
+//! Readers reject manifests with a newer `schema_version` than they know.
+//!
+//! The digests are taken from the bytes the upload sends, as it reads them,
+//! with `UploadDigests`, so the file is read once and the manifest describes
+//! what S3 received rather than what the file held afterwards.
+
+use crate::upload::{part_ranges, SourceWindow};
+use aws_sdk_s3::types::{ByteStream, SdkError};
+use aws_sdk_s3::{Client, Error};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// The version of the manifests written by this tool.
+pub const INTEGRITY_SCHEMA_VERSION: u32 = 1;
+
+/// Appended to the object key to form the key of its manifest.
+pub const INTEGRITY_MANIFEST_SUFFIX: &str = ".integrity.json";
+
+/// The SHA-256 of one part of the object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartDigest {
+    pub number: u32,
+    /// From the start of the object.
+    pub offset: u64,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IntegrityManifest {
+    pub schema_version: u32,
+    pub key: String,
+    pub size: u64,
+    /// RFC 3339.
+    pub uploaded_at: String,
+    pub parts: Vec<PartDigest>,
+    /// Lowercase hex SHA-256 of the whole object.
+    pub sha256: String,
+    pub hostname: String,
+    pub tool_version: String,
+}
+
+/// The key of the manifest of `key`.
+pub fn manifest_key(key: &str) -> String {
+    format!("{}{}", key, INTEGRITY_MANIFEST_SUFFIX)
+}
+
+/// The name of this host, from the environment or `/etc/hostname`.
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Hashes `num_parts` parts of `window` in the file at `path`, each part
+/// and the whole window, in the order they are read.
+async fn hash_parts(
+    path: &Path,
+    window: SourceWindow,
+    num_parts: usize,
+) -> std::io::Result<(Vec<PartDigest>, String)> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut whole = Sha256::new();
+    let mut parts = Vec::new();
+    let mut buffer = vec![0; 64 * 1024];
+    for (i, (offset, size)) in part_ranges(window, num_parts).into_iter().enumerate() {
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        let mut part = Sha256::new();
+        let mut left = size;
+        while left > 0 {
+            let want = buffer.len().min(left as usize);
+            let n = file.read(&mut buffer[..want]).await?;
+            if n == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("{} is shorter than expected", path.display()),
+                ));
+            }
+            part.update(&buffer[..n]);
+            whole.update(&buffer[..n]);
+            left -= n as u64;
+        }
+        parts.push(PartDigest {
+            number: (i + 1) as u32,
+            offset: offset - window.offset,
+            size,
+            sha256: format!("{:x}", part.finalize()),
+        });
+    }
+    Ok((parts, format!("{:x}", whole.finalize())))
+}
+
+/// The digests of the parts of an upload, taken from the bodies of its
+/// requests as they are sent.
+///
+/// Parts must be sent in order, each after the previous one was stored: the
+/// digest of the whole object carries on from the last complete read of the
+/// previous part. A part sent again, after a failed attempt or when an
+/// upload starts over in other parts, replaces what was read for it and for
+/// the parts after it. Clones share the digests.
+#[derive(Debug, Clone, Default)]
+pub struct UploadDigests {
+    inner: Arc<Mutex<ReadParts>>,
+}
+
+#[derive(Debug, Default)]
+struct ReadParts {
+    /// Each part with the digest of the whole object up to its end.
+    parts: Vec<(PartDigest, Sha256)>,
+    /// Set when a part was read before the one ahead of it.
+    out_of_order: bool,
+}
+
+/// The digests of one attempt at sending a part.
+struct PartRead {
+    size: u64,
+    part: Sha256,
+    whole: Sha256,
+}
+
+impl UploadDigests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hashes the chunks of `stream`, the body of part `number`, as the
+    /// request reads them. The part is recorded once the stream has ended,
+    /// so a body the request did not read to the end is not.
+    pub fn reader<S, B, E>(&self, number: u32, stream: S) -> impl Stream<Item = Result<B, E>>
+    where
+        S: Stream<Item = Result<B, E>>,
+        B: AsRef<[u8]>,
+    {
+        let whole = {
+            let read = self.inner.lock().unwrap();
+            match number.checked_sub(1).filter(|previous| *previous > 0) {
+                None => Sha256::new(),
+                Some(previous) => read
+                    .parts
+                    .iter()
+                    .find(|(part, _)| part.number == previous)
+                    .map(|(_, whole)| whole.clone())
+                    .unwrap_or_default(),
+            }
+        };
+        let hashers = Arc::new(Mutex::new(Some(PartRead {
+            size: 0,
+            part: Sha256::new(),
+            whole,
+        })));
+        let reading = hashers.clone();
+        let digests = self.clone();
+        stream
+            .inspect(move |chunk| {
+                if let (Ok(bytes), Some(read)) = (chunk, reading.lock().unwrap().as_mut()) {
+                    read.size += bytes.as_ref().len() as u64;
+                    read.part.update(bytes.as_ref());
+                    read.whole.update(bytes.as_ref());
+                }
+            })
+            .chain(futures::stream::poll_fn(move |_| {
+                if let Some(read) = hashers.lock().unwrap().take() {
+                    digests.record(number, read);
+                }
+                Poll::Ready(None)
+            }))
+    }
+
+    fn record(&self, number: u32, read: PartRead) {
+        let mut parts = self.inner.lock().unwrap();
+        let previous = number.saturating_sub(1) as usize;
+        if parts.parts.len() < previous {
+            parts.out_of_order = true;
+        }
+        parts.parts.truncate(previous);
+        let offset = parts
+            .parts
+            .last()
+            .map(|(part, _)| part.offset + part.size)
+            .unwrap_or(0);
+        let digest = PartDigest {
+            number,
+            offset,
+            size: read.size,
+            sha256: format!("{:x}", read.part.finalize()),
+        };
+        parts.parts.push((digest, read.whole));
+    }
+
+    /// The manifest of `key`, of `size` bytes, from the parts read. Fails
+    /// unless they were read in order and cover the object.
+    pub fn manifest(&self, key: &str, size: u64) -> Result<IntegrityManifest, String> {
+        let read = self.inner.lock().unwrap();
+        let covered: u64 = read.parts.iter().map(|(part, _)| part.size).sum();
+        let whole = match read.parts.last() {
+            Some((_, whole)) if !read.out_of_order && covered == size => whole.clone(),
+            _ => {
+                return Err(format!(
+                    "The upload of {} read {} of its {} bytes in order, not the whole object",
+                    key, covered, size
+                ))
+            }
+        };
+        Ok(IntegrityManifest::new(
+            key,
+            size,
+            read.parts.iter().map(|(part, _)| part.clone()).collect(),
+            format!("{:x}", whole.finalize()),
+        ))
+    }
+}
+
+impl IntegrityManifest {
+    fn new(key: &str, size: u64, parts: Vec<PartDigest>, sha256: String) -> Self {
+        Self {
+            schema_version: INTEGRITY_SCHEMA_VERSION,
+            key: key.to_string(),
+            size,
+            uploaded_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            parts,
+            sha256,
+            hostname: hostname(),
+            tool_version: format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        }
+    }
+
+    /// Describes `key`, uploaded from `window` of the file at `path` in
+    /// `num_parts` parts laid out as by `part_ranges`, reading the file
+    /// again. A single PutObject is one part. `UploadDigests` describes an
+    /// upload without the second read.
+    pub async fn for_upload(
+        key: &str,
+        path: &Path,
+        window: SourceWindow,
+        num_parts: usize,
+    ) -> std::io::Result<Self> {
+        let (parts, sha256) = hash_parts(path, window, num_parts).await?;
+        Ok(Self::new(key, window.length, parts, sha256))
+    }
+
+    /// Parses a manifest, rejecting schema versions newer than this tool's.
+    pub fn from_json(content: &[u8]) -> Result<Self, String> {
+        #[derive(Deserialize)]
+        struct Version {
+            schema_version: u32,
+        }
+        let version: Version = serde_json::from_slice(content)
+            .map_err(|err| format!("Invalid integrity manifest: {}", err))?;
+        if version.schema_version > INTEGRITY_SCHEMA_VERSION {
+            return Err(format!(
+                "Integrity manifest schema version {} is newer than the supported {}",
+                version.schema_version, INTEGRITY_SCHEMA_VERSION
+            ));
+        }
+        serde_json::from_slice(content)
+            .map_err(|err| format!("Invalid integrity manifest: {}", err))
+    }
+
+    /// Checks `window` of the file at `path`, where the object was
+    /// downloaded, against the manifest.
+    pub async fn check_file(
+        &self,
+        path: &Path,
+        window: SourceWindow,
+    ) -> std::io::Result<IntegrityCheck> {
+        let mut check = IntegrityCheck {
+            key: self.key.clone(),
+            expected_sha256: self.sha256.clone(),
+            actual_sha256: None,
+            size_matches: window.length == self.size,
+            mismatched_parts: Vec::new(),
+        };
+        if !check.size_matches {
+            return Ok(check);
+        }
+        let (parts, sha256) = hash_parts(path, window, self.parts.len()).await?;
+        check.mismatched_parts = self
+            .parts
+            .iter()
+            .zip(&parts)
+            .filter(|(expected, actual)| {
+                expected.offset != actual.offset
+                    || expected.size != actual.size
+                    || !expected.sha256.eq_ignore_ascii_case(&actual.sha256)
+            })
+            .map(|(expected, _)| expected.number)
+            .collect();
+        check.actual_sha256 = Some(sha256);
+        Ok(check)
+    }
+}
+
+/// Outcome of `IntegrityManifest::check_file`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IntegrityCheck {
+    pub key: String,
+    pub expected_sha256: String,
+    /// Not computed when the size differs.
+    pub actual_sha256: Option<String>,
+    pub size_matches: bool,
+    /// The parts whose bytes differ, to locate the damage.
+    pub mismatched_parts: Vec<u32>,
+}
+
+impl IntegrityCheck {
+    pub fn is_ok(&self) -> bool {
+        self.size_matches
+            && self.mismatched_parts.is_empty()
+            && self
+                .actual_sha256
+                .as_deref()
+                .map(|actual| actual.eq_ignore_ascii_case(&self.expected_sha256))
+                .unwrap_or(false)
+    }
+}
+
+/// Stores `manifest` at `manifest_key(&manifest.key)` and returns that key.
+///
+/// Unless `overwrite` is set, an existing manifest is never replaced: the
+/// PutObject is sent with `If-None-Match: *`, so S3 refuses it with
+/// PreconditionFailed when the key exists, even if another writer stored it
+/// after this one started. PutObject has no field for the header in this
+/// SDK, so it is added to the request.
+pub async fn put_integrity_manifest(
+    client: &Client,
+    bucket: &str,
+    manifest: &IntegrityManifest,
+    overwrite: bool,
+) -> Result<String, Error> {
+    let key = manifest_key(&manifest.key);
+    let body = serde_json::to_vec_pretty(manifest).unwrap();
+    let mut request = client
+        .put_object()
+        .bucket(bucket)
+        .key(&key)
+        .content_type("application/json")
+        .body(ByteStream::from(body))
+        .customize()
+        .await?;
+    if !overwrite {
+        request
+            .request_mut()
+            .headers_mut()
+            .insert("If-None-Match", http::HeaderValue::from_static("*"));
+    }
+    match request.send().await {
+        Ok(_) => Ok(key),
+        Err(SdkError::ServiceError { err, .. }) if err.code() == Some("PreconditionFailed") => {
+            Err(Error::Unhandled(Box::from(format!(
+                "Precondition failed: the integrity manifest {} already exists",
+                key
+            ))))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Fetches the manifest of `key`.
+pub async fn get_integrity_manifest(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<IntegrityManifest, Error> {
+    let manifest_key = manifest_key(key);
+    let resp = client
+        .get_object()
+        .bucket(bucket)
+        .key(&manifest_key)
+        .send()
+        .await?;
+    let content = resp
+        .body
+        .collect()
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+        .into_bytes();
+    let manifest = IntegrityManifest::from_json(&content)
+        .map_err(|err| Error::Unhandled(Box::from(format!("{}: {}", manifest_key, err))))?;
+    if manifest.key != key {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} describes {}, not {}",
+            manifest_key, manifest.key, key
+        ))));
+    }
+    Ok(manifest)
+}
@@ -0,0 +1,342 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Object counts and sizes of every bucket of several accounts, by storage
+//! class and by Region, for cost tools.
+//!
+//! A read-only role is assumed in each account, named `INVENTORY_ROLE_NAME`
+//! unless another name is given. It needs `s3:ListAllMyBuckets`,
+//! `s3:GetBucketLocation`, and `s3:ListBucket`, and must trust the account
+//! running the aggregation. Every object is listed, which costs one
+//! ListObjectsV2 request per 1,000 objects; for accounts with billions of
+//! objects, S3 Inventory reports are cheaper.
+//!
+//! An account whose role cannot be assumed, or a bucket that cannot be read,
+//! because of AccessDenied is recorded in the report and skipped. Other
+//! errors stop the run. A bucket is added to the totals only once it is
+//! listed in full, so one denied part way through counts for nothing.
+//!
+//! The credentials of each account come from a `CachingCredentialsProvider`,
+//! so a listing that outlasts them goes on with the role assumed again.
+
+use crate::role_credentials::{AssumeRoleProvider, CachingCredentialsProvider};
+use aws_sdk_s3::model::BucketLocationConstraint;
+use aws_sdk_s3::types::{ByteStream, SdkError};
+use aws_sdk_s3::{Client, Error, Region};
+use aws_types::credentials::SharedCredentialsProvider;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The role assumed in each account by `aggregate_inventory`.
+pub const INVENTORY_ROLE_NAME: &str = "S3InventoryReadOnly";
+
+/// Where the reports are written in the destination bucket.
+pub const REPORT_PREFIX: &str = "inventory-aggregate/";
+
+const SESSION_NAME: &str = "s3-inventory-aggregation";
+
+const ACCESS_DENIED: &str = "AccessDenied";
+
+/// A number of objects and their size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+impl Totals {
+    fn add(&mut self, bytes: u64) {
+        self.objects += 1;
+        self.bytes += bytes;
+    }
+
+    fn merge(&mut self, other: &Totals) {
+        self.objects += other.objects;
+        self.bytes += other.bytes;
+    }
+}
+
+/// What was found in one account.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AccountInventory {
+    pub account_id: String,
+    /// The buckets that were listed.
+    pub buckets: u64,
+    pub total_objects: u64,
+    pub total_bytes: u64,
+    /// The account or buckets that were skipped, and why.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub access_denied: Vec<String>,
+}
+
+/// The totals of all the accounts, as uploaded to the destination bucket.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AggregateReport {
+    /// When the aggregation started, in RFC 3339.
+    pub generated_at: String,
+    pub total_objects: u64,
+    pub total_bytes: u64,
+    /// By storage class, such as `STANDARD` or `GLACIER`.
+    pub storage_class_breakdown: BTreeMap<String, Totals>,
+    /// By Region of the bucket, such as `us-east-1`.
+    pub region_breakdown: BTreeMap<String, Totals>,
+    pub accounts: Vec<AccountInventory>,
+    /// The key the report was uploaded to.
+    #[serde(skip)]
+    pub key: String,
+}
+
+impl AggregateReport {
+    /// The accounts that were skipped entirely.
+    pub fn denied_accounts(&self) -> impl Iterator<Item = &AccountInventory> {
+        self.accounts
+            .iter()
+            .filter(|account| account.buckets == 0 && !account.access_denied.is_empty())
+    }
+
+    fn add_bucket(&mut self, account: &mut AccountInventory, bucket: &BucketInventory) {
+        for (class, totals) in &bucket.by_class {
+            self.total_objects += totals.objects;
+            self.total_bytes += totals.bytes;
+            self.storage_class_breakdown
+                .entry(class.clone())
+                .or_default()
+                .merge(totals);
+            self.region_breakdown
+                .entry(bucket.region.clone())
+                .or_default()
+                .merge(totals);
+            account.total_objects += totals.objects;
+            account.total_bytes += totals.bytes;
+        }
+        account.buckets += 1;
+    }
+}
+
+/// The objects of one bucket, by storage class.
+#[derive(Debug, Default)]
+struct BucketInventory {
+    region: String,
+    by_class: BTreeMap<String, Totals>,
+}
+
+/// Why a bucket was not added to the report.
+enum BucketError {
+    /// Skipped, with the error.
+    AccessDenied(String),
+    Other(Error),
+}
+
+/// Whether the service refused the request with the AccessDenied code.
+fn is_access_denied<E>(err: &SdkError<E>, code: impl FnOnce(&E) -> Option<&str>) -> bool {
+    matches!(err, SdkError::ServiceError { err, .. } if code(err) == Some(ACCESS_DENIED))
+}
+
+fn bucket_error<E>(err: SdkError<E>, code: impl FnOnce(&E) -> Option<&str>) -> BucketError
+where
+    E: std::error::Error + 'static,
+    Error: From<SdkError<E>>,
+{
+    if is_access_denied(&err, code) {
+        BucketError::AccessDenied(err.to_string())
+    } else {
+        BucketError::Other(err.into())
+    }
+}
+
+/// The code of an STS error. AssumeRole does not model AccessDenied, which
+/// comes back as an unhandled error with its code.
+fn sts_error_code(err: &aws_sdk_sts::Error) -> Option<&str> {
+    match err {
+        aws_sdk_sts::Error::Unhandled(inner) => {
+            inner.downcast_ref::<aws_smithy_types::Error>()?.code()
+        }
+        _ => None,
+    }
+}
+
+/// The Region of a bucket from its location constraint, which is empty for
+/// us-east-1 and `EU` for the oldest eu-west-1 buckets.
+pub fn bucket_region(constraint: Option<&BucketLocationConstraint>) -> String {
+    match constraint.map(|c| c.as_str()) {
+        None | Some("") => "us-east-1".to_string(),
+        Some("EU") => "eu-west-1".to_string(),
+        Some(region) => region.to_string(),
+    }
+}
+
+/// Makes the clients of `aggregate_inventory_with_options` from
+/// `shared_config`, with the assumed role in place of its credentials.
+pub fn assumed_role_clients(
+    shared_config: &aws_config::Config,
+) -> impl Fn(SharedCredentialsProvider, String) -> Client + Sync + '_ {
+    move |credentials, region| {
+        Client::from_conf(
+            aws_sdk_s3::config::Builder::from(shared_config)
+                .set_credentials_provider(Some(credentials))
+                .region(Region::new(region))
+                .build(),
+        )
+    }
+}
+
+/// Aggregates the objects of every bucket of `account_ids`, assuming
+/// `INVENTORY_ROLE_NAME` in each of them, and uploads the report as JSON to
+/// `dest_bucket` with the credentials of `shared_config`.
+pub async fn aggregate_inventory(
+    sts_client: &aws_sdk_sts::Client,
+    shared_config: &aws_config::Config,
+    account_ids: &[&str],
+    dest_bucket: &str,
+) -> Result<AggregateReport, Error> {
+    aggregate_inventory_with_options(
+        sts_client,
+        &Client::new(shared_config),
+        account_ids,
+        dest_bucket,
+        INVENTORY_ROLE_NAME,
+        &assumed_role_clients(shared_config),
+    )
+    .await
+}
+
+/// Like `aggregate_inventory`, assuming `role_name` and reading the accounts
+/// through the clients made by `client_for` from the assumed role and a
+/// Region. The report is uploaded through `dest_client`.
+pub async fn aggregate_inventory_with_options(
+    sts_client: &aws_sdk_sts::Client,
+    dest_client: &Client,
+    account_ids: &[&str],
+    dest_bucket: &str,
+    role_name: &str,
+    client_for: &(dyn Fn(SharedCredentialsProvider, String) -> Client + Sync),
+) -> Result<AggregateReport, Error> {
+    let started = chrono::Utc::now();
+    let mut report = AggregateReport {
+        generated_at: started.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        ..Default::default()
+    };
+    for account_id in account_ids {
+        let mut account = AccountInventory {
+            account_id: account_id.to_string(),
+            ..Default::default()
+        };
+        let role = CachingCredentialsProvider::new(
+            AssumeRoleProvider::new(
+                sts_client.clone(),
+                format!("arn:aws:iam::{}:role/{}", account_id, role_name),
+            )
+            .session_name(SESSION_NAME),
+        );
+        // The role is assumed once up front, so an account that does not
+        // trust the caller is skipped before any bucket.
+        match role.load().await {
+            Ok(_) => {
+                let credentials = SharedCredentialsProvider::new(role);
+                aggregate_account(&mut report, &mut account, credentials, client_for).await?
+            }
+            Err(err) if sts_error_code(&err) == Some(ACCESS_DENIED) => {
+                eprintln!("Skipping account {}: {}", account_id, err);
+                account.access_denied.push(format!("account: {}", err));
+            }
+            Err(err) => return Err(Error::Unhandled(Box::new(err))),
+        }
+        report.accounts.push(account);
+    }
+
+    report.key = format!("{}{}.json", REPORT_PREFIX, started.format("%Y%m%dT%H%M%SZ"));
+    let body = serde_json::to_vec_pretty(&report).unwrap();
+    dest_client
+        .put_object()
+        .bucket(dest_bucket)
+        .key(&report.key)
+        .content_type("application/json")
+        .body(ByteStream::from(body))
+        .send()
+        .await?;
+    Ok(report)
+}
+
+/// Adds the buckets of one account to `report`.
+async fn aggregate_account(
+    report: &mut AggregateReport,
+    account: &mut AccountInventory,
+    credentials: SharedCredentialsProvider,
+    client_for: &(dyn Fn(SharedCredentialsProvider, String) -> Client + Sync),
+) -> Result<(), Error> {
+    // Buckets are listed, and located, from any Region.
+    let client = client_for(credentials.clone(), "us-east-1".to_string());
+    let buckets = match client.list_buckets().send().await {
+        Ok(resp) => resp,
+        Err(err) if is_access_denied(&err, |e| e.code()) => {
+            eprintln!("Skipping account {}: {}", account.account_id, err);
+            account.access_denied.push(format!("account: {}", err));
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    for bucket in buckets.buckets().unwrap_or_default() {
+        let name = bucket.name().unwrap_or_default();
+        match aggregate_bucket(&client, &credentials, client_for, name).await {
+            Ok(inventory) => report.add_bucket(account, &inventory),
+            Err(BucketError::AccessDenied(err)) => {
+                eprintln!(
+                    "Skipping bucket {} of account {}: {}",
+                    name, account.account_id, err
+                );
+                account.access_denied.push(format!("{}: {}", name, err));
+            }
+            Err(BucketError::Other(err)) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Lists every object of `bucket`.
+async fn aggregate_bucket(
+    client: &Client,
+    credentials: &SharedCredentialsProvider,
+    client_for: &(dyn Fn(SharedCredentialsProvider, String) -> Client + Sync),
+    bucket: &str,
+) -> Result<BucketInventory, BucketError> {
+    let location = client
+        .get_bucket_location()
+        .bucket(bucket)
+        .send()
+        .await
+        .map_err(|err| bucket_error(err, |e| e.code()))?;
+    let mut inventory = BucketInventory {
+        region: bucket_region(location.location_constraint()),
+        ..Default::default()
+    };
+    // A bucket is only listed through its own Region.
+    let regional = client_for(credentials.clone(), inventory.region.clone());
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let resp = regional
+            .list_objects_v2()
+            .bucket(bucket)
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await
+            .map_err(|err| bucket_error(err, |e| e.code()))?;
+        for object in resp.contents().unwrap_or_default() {
+            let class = object
+                .storage_class()
+                .map(|c| c.as_str())
+                .unwrap_or("STANDARD");
+            inventory
+                .by_class
+                .entry(class.to_string())
+                .or_default()
+                .add(object.size() as u64);
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+    Ok(inventory)
+}
@@ -0,0 +1,59 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Streaming upload of JSON Lines objects.
+
+use crate::multipart_writer::MultipartWriter;
+use aws_sdk_s3::{Client, Error};
+use futures::{pin_mut, Stream, StreamExt};
+use serde::Serialize;
+
+/// Statistics for a completed streaming upload.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct UploadStats {
+    pub record_count: u64,
+    pub total_bytes: u64,
+    pub part_count: usize,
+}
+
+/// Serializes each record of `records` as one line of JSON and uploads the
+/// result to `bucket/key`.
+///
+/// Records are buffered until an 8 MB part is full, so the whole dataset is
+/// never held in memory. If serialization or an upload fails, the multipart
+/// upload is aborted.
+pub async fn upload_jsonl<T: Serialize + Send + 'static>(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    records: impl Stream<Item = T> + Send + 'static,
+) -> Result<UploadStats, Error> {
+    let mut writer = MultipartWriter::new(client, bucket, key);
+    let mut record_count = 0u64;
+    pin_mut!(records);
+    while let Some(record) = records.next().await {
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(err) => {
+                writer.abort().await?;
+                return Err(Error::Unhandled(Box::new(err)));
+            }
+        };
+        line.push(b'\n');
+        if let Err(err) = writer.write(&line).await {
+            writer.abort().await?;
+            return Err(err);
+        }
+        record_count += 1;
+    }
+
+    let total_bytes = writer.bytes_written();
+    let finished = writer.finish().await?;
+    Ok(UploadStats {
+        record_count,
+        total_bytes,
+        part_count: finished.part_count,
+    })
+}
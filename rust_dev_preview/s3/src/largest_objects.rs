@@ -0,0 +1,129 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! The largest objects of a bucket, for cost reviews.
+//!
+//! A handful of objects often hold most of the bytes of a bucket, such as
+//! forgotten database dumps or build caches. ListObjectsV2 returns the
+//! objects in key order, so the whole bucket is listed, keeping only the
+//! `top_n` largest seen so far in a min-heap: the memory used depends on
+//! `top_n`, not on the number of objects.
+
+use crate::units::format_size;
+use aws_sdk_s3::model::Object;
+use aws_sdk_s3::{Client, Error};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// An object ordered by size, then by key with the first key the largest,
+/// so that of objects of the same size the first ones by key are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectSize(pub Object);
+
+impl ObjectSize {
+    fn size(&self) -> i64 {
+        self.0.size()
+    }
+
+    fn key(&self) -> &str {
+        self.0.key().unwrap_or_default()
+    }
+}
+
+impl Eq for ObjectSize {}
+
+impl Ord for ObjectSize {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.size()
+            .cmp(&other.size())
+            .then_with(|| other.key().cmp(self.key()))
+    }
+}
+
+impl PartialOrd for ObjectSize {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Lists the objects of `bucket`, under `prefix` if given, and returns the
+/// `top_n` largest, the largest first.
+pub async fn list_largest_objects(
+    client: &Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    top_n: usize,
+) -> Result<Vec<Object>, Error> {
+    // Grows with the listing: `top_n` comes from the user and may be huge.
+    let mut largest: BinaryHeap<Reverse<ObjectSize>> = BinaryHeap::new();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .set_prefix(prefix.map(|prefix| prefix.to_string()))
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+        for object in resp.contents().unwrap_or_default() {
+            largest.push(Reverse(ObjectSize(object.clone())));
+            if largest.len() > top_n {
+                largest.pop();
+            }
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+    // Ascending `Reverse` order is descending by size.
+    Ok(largest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(ObjectSize(object))| object)
+        .collect())
+}
+
+/// A table of `objects` with their key, size, storage class, and
+/// last-modified time in UTC.
+pub fn render_largest_table(objects: &[Object]) -> String {
+    let width = objects
+        .iter()
+        .map(|object| object.key().unwrap_or_default().len())
+        .chain(std::iter::once("Key".len()))
+        .max()
+        .unwrap_or_default();
+    let mut lines = vec![format!(
+        "{:<width$}  {:>10}  {:<19}  {}",
+        "Key",
+        "Size",
+        "Storage class",
+        "Last modified",
+        width = width
+    )];
+    for object in objects {
+        let last_modified = object
+            .last_modified()
+            .map(|t| {
+                let time = UNIX_EPOCH + Duration::from_secs(t.secs().max(0) as u64);
+                chrono::DateTime::<chrono::Utc>::from(time)
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            })
+            .unwrap_or_else(|| "-".to_string());
+        lines.push(format!(
+            "{:<width$}  {:>10}  {:<19}  {}",
+            object.key().unwrap_or_default(),
+            format_size(object.size().max(0) as u64),
+            object
+                .storage_class()
+                .map(|class| class.as_str())
+                .unwrap_or("STANDARD"),
+            last_modified,
+            width = width
+        ));
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,200 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Object listings for people and for other systems.
+//!
+//! Keys may hold any UTF-8 character, including newlines and other control
+//! characters, which break line-based and naive CSV ingestion and cannot
+//! be carried in the XML of a listing. With `KeyEncoding::Url`, S3 returns
+//! the keys URL-encoded, as `application/x-www-form-urlencoded` with spaces
+//! as `+`. The machine outputs, JSON and CSV, emit the keys exactly as
+//! listed, so they are safe to pass on; the text output decodes them and
+//! escapes control characters, one key per line.
+
+use aws_sdk_s3::model::EncodingType;
+use aws_sdk_s3::{Client, Error};
+use csv_async::AsyncWriterBuilder;
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// How the keys of a listing are encoded by S3.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyEncoding {
+    None,
+    Url,
+}
+
+impl std::str::FromStr for KeyEncoding {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(KeyEncoding::None),
+            "url" => Ok(KeyEncoding::Url),
+            other => Err(format!("Unknown encoding type: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListOutput {
+    Text,
+    Json,
+    Csv,
+}
+
+impl std::str::FromStr for ListOutput {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(ListOutput::Text),
+            "json" => Ok(ListOutput::Json),
+            "csv" => Ok(ListOutput::Csv),
+            other => Err(format!("Unknown output: {}", other)),
+        }
+    }
+}
+
+/// An object of a listing.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListedObject {
+    /// As listed: URL-encoded with `KeyEncoding::Url`.
+    pub key: String,
+    pub size: u64,
+    /// RFC 3339, in UTC.
+    pub last_modified: Option<String>,
+    /// Without quotes.
+    pub e_tag: Option<String>,
+}
+
+/// A listing and the encoding of its keys.
+#[derive(Debug, Clone, Serialize)]
+pub struct Listing {
+    /// `"url"` when the keys are URL-encoded.
+    pub encoding_type: Option<&'static str>,
+    pub objects: Vec<ListedObject>,
+}
+
+/// Lists every object under `prefix`, with the keys encoded as `encoding`.
+pub async fn list_objects(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    encoding: KeyEncoding,
+) -> Result<Listing, Error> {
+    let mut objects = Vec::new();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let mut request = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_continuation_token(continuation_token.take());
+        if encoding == KeyEncoding::Url {
+            request = request.encoding_type(EncodingType::Url);
+        }
+        let resp = request.send().await?;
+        for object in resp.contents().unwrap_or_default() {
+            objects.push(ListedObject {
+                key: object.key().unwrap_or_default().to_string(),
+                size: object.size().max(0) as u64,
+                last_modified: object.last_modified().map(|t| {
+                    let time = UNIX_EPOCH + Duration::from_secs(t.secs().max(0) as u64);
+                    chrono::DateTime::<chrono::Utc>::from(time)
+                        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                }),
+                e_tag: object.e_tag().map(|t| t.trim_matches('"').to_string()),
+            });
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+    Ok(Listing {
+        encoding_type: match encoding {
+            KeyEncoding::None => None,
+            KeyEncoding::Url => Some("url"),
+        },
+        objects,
+    })
+}
+
+/// Decodes a key listed with `KeyEncoding::Url`. Invalid UTF-8 is replaced,
+/// rather than failing the listing.
+pub fn decode_key(key: &str) -> String {
+    percent_decode_str(&key.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// The key as shown to a person: decoded, with control characters escaped
+/// as in Rust strings, so that each key stays on one line.
+pub fn display_key(key: &str, encoding: KeyEncoding) -> String {
+    let key = match encoding {
+        KeyEncoding::None => key.to_string(),
+        KeyEncoding::Url => decode_key(key),
+    };
+    key.chars()
+        .map(|c| {
+            if c.is_control() {
+                c.escape_default().to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+impl Listing {
+    /// One key per line.
+    pub fn to_text(&self) -> String {
+        let encoding = match self.encoding_type {
+            Some(_) => KeyEncoding::Url,
+            None => KeyEncoding::None,
+        };
+        self.objects
+            .iter()
+            .map(|object| format!("{}\n", display_key(&object.key, encoding)))
+            .collect()
+    }
+
+    /// CSV with a header, the fields holding a comma, a quote, or a line
+    /// break quoted.
+    pub async fn to_csv(&self) -> Result<String, Error> {
+        let mut writer = AsyncWriterBuilder::new()
+            .has_headers(false)
+            .create_writer(Vec::new());
+        let to_error = |err: csv_async::Error| Error::Unhandled(Box::new(err));
+        writer
+            .write_record(&["key", "size", "last_modified", "e_tag"])
+            .await
+            .map_err(to_error)?;
+        for object in &self.objects {
+            let size = object.size.to_string();
+            writer
+                .write_record(&[
+                    object.key.as_str(),
+                    size.as_str(),
+                    object.last_modified.as_deref().unwrap_or_default(),
+                    object.e_tag.as_deref().unwrap_or_default(),
+                ])
+                .await
+                .map_err(to_error)?;
+        }
+        let csv = writer
+            .into_inner()
+            .await
+            .map_err(|err| Error::Unhandled(Box::from(err.to_string())))?;
+        // Every field written is UTF-8.
+        Ok(String::from_utf8(csv).unwrap())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
@@ -0,0 +1,246 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! SHA-256 manifests of backed up files, and verification of a restore
+//! against them.
+//!
+//! A manifest is a JSON document:
+//!
+//! ```json
+//! {
+//!   "prefix": "backups/2022-02-01/",
+//!   "objects": [
+//!     { "key": "backups/2022-02-01/db/dump.sql", "size": 1048576, "sha256": "9f86d0…" }
+//!   ]
+//! }
+//! ```
+
+use crate::download::local_path;
+use crate::integrity::{get_integrity_manifest, manifest_key, IntegrityCheck};
+use crate::sync::walk_directory;
+use crate::upload::SourceWindow;
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// One object of a manifest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub key: String,
+    pub size: u64,
+    /// Lowercase hex SHA-256 of the content.
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Removed from the keys to form the local paths on download.
+    pub prefix: String,
+    pub objects: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Reads a manifest written by `save`.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        serde_json::from_str(&content).map_err(|err| {
+            Error::Unhandled(Box::from(format!(
+                "Invalid manifest {}: {}",
+                path.display(),
+                err
+            )))
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(path, content).map_err(|err| Error::Unhandled(Box::new(err)))
+    }
+}
+
+/// Lowercase hex SHA-256 of the file at `path`.
+pub async fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let file = tokio::fs::File::open(path).await?;
+    sha256_reader(file).await
+}
+
+/// Lowercase hex SHA-256 of the bytes of `window` in the file at `path`, as
+/// uploaded with `--source-offset` and `--source-length`.
+pub async fn sha256_window(path: &Path, window: SourceWindow) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(window.offset)).await?;
+    sha256_reader(file.take(window.length)).await
+}
+
+async fn sha256_reader<R: AsyncRead + Unpin>(mut file: R) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hashes the files under `dir`, keyed as `sync_directory` uploads them
+/// under `prefix`.
+pub async fn generate_manifest(dir: &Path, prefix: &str) -> Result<Manifest, Error> {
+    let mut files = walk_directory(dir, prefix).map_err(|err| Error::Unhandled(Box::new(err)))?;
+    files.sort_by(|a, b| a.key.cmp(&b.key));
+    let mut objects = Vec::with_capacity(files.len());
+    for file in files {
+        let sha256 = sha256_file(&file.path)
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        objects.push(ManifestEntry {
+            key: file.key,
+            size: file.size,
+            sha256,
+        });
+    }
+    Ok(Manifest {
+        prefix: prefix.to_string(),
+        objects,
+    })
+}
+
+/// Outcome of `download_and_verify`.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct VerificationReport {
+    /// Keys whose download matches the manifest.
+    pub verified: Vec<String>,
+    /// Key, expected hash, and actual hash of the downloads that differ.
+    pub mismatched: Vec<(String, String, String)>,
+    /// Keys of the manifest that are not in the bucket.
+    pub missing: Vec<String>,
+    /// The checks against the integrity manifests stored in the bucket, when
+    /// requested.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub integrity: Vec<IntegrityCheck>,
+}
+
+impl VerificationReport {
+    /// Whether every object was found and matched.
+    pub fn is_ok(&self) -> bool {
+        self.mismatched.is_empty()
+            && self.missing.is_empty()
+            && self.integrity.iter().all(IntegrityCheck::is_ok)
+    }
+}
+
+/// Downloads the objects listed in the manifest at `manifest_path` to
+/// `dest_dir`, hashing each one as it is written, and compares the hashes
+/// with the manifest.
+///
+/// A mismatch does not stop the download of the other objects; the file is
+/// left in place for inspection.
+pub async fn download_and_verify(
+    client: &Client,
+    bucket: &str,
+    manifest_path: &Path,
+    dest_dir: &Path,
+) -> Result<VerificationReport, Error> {
+    download_and_verify_with_options(client, bucket, manifest_path, dest_dir, false).await
+}
+
+/// As `download_and_verify`, also checking each download against the
+/// integrity manifest stored next to the object if `check_integrity` is set.
+/// An object without an integrity manifest is reported with the missing
+/// keys, under the key of its manifest.
+pub async fn download_and_verify_with_options(
+    client: &Client,
+    bucket: &str,
+    manifest_path: &Path,
+    dest_dir: &Path,
+    check_integrity: bool,
+) -> Result<VerificationReport, Error> {
+    let manifest = Manifest::load(manifest_path)?;
+    let mut report = VerificationReport::default();
+    for entry in manifest.objects {
+        let path = local_path(dest_dir, &manifest.prefix, &entry.key)?;
+        let resp = match client
+            .get_object()
+            .bucket(bucket)
+            .key(&entry.key)
+            .send()
+            .await
+        {
+            Ok(resp) => resp,
+            Err(SdkError::ServiceError { err, .. }) if err.is_no_such_key() => {
+                eprintln!("Missing: {}", entry.key);
+                report.missing.push(entry.key);
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        }
+        let mut file = tokio::fs::File::create(&path)
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        let mut hasher = Sha256::new();
+        let mut body = resp.body;
+        while let Some(chunk) = body
+            .try_next()
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?
+        {
+            hasher.update(&chunk);
+            file.write_all(&chunk)
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        }
+        file.flush()
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+
+        let actual = format!("{:x}", hasher.finalize());
+        if check_integrity {
+            match get_integrity_manifest(client, bucket, &entry.key).await {
+                Ok(integrity) => {
+                    let window = SourceWindow::for_file(&path.to_string_lossy(), None, None)?;
+                    let check = integrity
+                        .check_file(&path, window)
+                        .await
+                        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                    if !check.is_ok() {
+                        eprintln!(
+                            "Integrity manifest mismatch: {} (parts {:?})",
+                            entry.key, check.mismatched_parts
+                        );
+                    }
+                    report.integrity.push(check);
+                }
+                Err(Error::NoSuchKey(_)) => {
+                    let key = manifest_key(&entry.key);
+                    eprintln!("Missing: {}", key);
+                    report.missing.push(key);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        if actual.eq_ignore_ascii_case(&entry.sha256) {
+            report.verified.push(entry.key);
+        } else {
+            eprintln!(
+                "Mismatch: {} (expected {}, got {})",
+                entry.key, entry.sha256, actual
+            );
+            report.mismatched.push((entry.key, entry.sha256, actual));
+        }
+    }
+    Ok(report)
+}
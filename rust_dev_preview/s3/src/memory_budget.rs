@@ -0,0 +1,279 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Soft accounting of the memory held by transfer buffers.
+//!
+//! The features that hold whole parts in memory, such as parallel
+//! downloads, archive batches, and the `MultipartWriter`, reserve their
+//! buffer from a `MemoryBudget` shared by the run before allocating it, and
+//! give it back when the buffer is dropped. A reservation that does not fit
+//! waits for others to be given back instead of allocating anyway, so a run
+//! on a small container slows down rather than being killed for running
+//! out of memory. Waiting reservations are served in the order they came,
+//! and none is granted past them, so a large one is not starved by smaller
+//! ones that keep fitting in what is left.
+//!
+//! The accounting is soft: only the buffers that reserve are counted, not
+//! the allocator, the SDK, or the rest of the process. A buffer larger than
+//! the whole budget is let through once nothing else is reserved, so that
+//! it does not wait forever.
+
+use crate::units::format_size;
+use crate::upload::available_memory;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// The share of the available memory a default budget allows.
+pub const DEFAULT_MEMORY_FRACTION: f64 = 0.5;
+
+#[derive(Debug, Default)]
+struct State {
+    used: u64,
+    peak: u64,
+    /// Reservations that had to wait.
+    waits: u64,
+    /// The tickets of the waiting reservations, first come first.
+    queue: VecDeque<u64>,
+    next_ticket: u64,
+}
+
+#[derive(Debug)]
+struct Shared {
+    limit: u64,
+    state: Mutex<State>,
+    released: Notify,
+}
+
+/// The bytes the buffers of a run may hold at the same time.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    shared: Arc<Shared>,
+}
+
+/// What a `MemoryBudget` saw, for the statistics of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MemoryStats {
+    pub limit: u64,
+    pub peak: u64,
+    pub waits: u64,
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.limit == u64::MAX {
+            return write!(
+                f,
+                "Buffers held at most {}, without a memory limit",
+                format_size(self.peak)
+            );
+        }
+        write!(
+            f,
+            "Buffers held at most {} of the {} memory limit; {} waited for memory",
+            format_size(self.peak),
+            format_size(self.limit),
+            self.waits
+        )
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                limit,
+                state: Mutex::new(State::default()),
+                released: Notify::new(),
+            }),
+        }
+    }
+
+    /// `DEFAULT_MEMORY_FRACTION` of the available memory, or no limit when
+    /// it cannot be read.
+    pub fn for_system() -> Self {
+        let limit = available_memory()
+            .map(|available| (available as f64 * DEFAULT_MEMORY_FRACTION) as u64)
+            .unwrap_or(u64::MAX);
+        tracing::debug!(limit, "Memory budget");
+        Self::new(limit)
+    }
+
+    /// `--memory-limit` when given, otherwise the default budget.
+    pub fn from_limit(limit: Option<u64>) -> Self {
+        match limit {
+            Some(limit) => Self::new(limit),
+            None => Self::for_system(),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.shared.limit
+    }
+
+    /// The bytes reserved now.
+    pub fn used(&self) -> u64 {
+        self.shared.state.lock().unwrap().used
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        let state = self.shared.state.lock().unwrap();
+        MemoryStats {
+            limit: self.shared.limit,
+            peak: state.peak,
+            waits: state.waits,
+        }
+    }
+
+    /// Reserves `bytes` if they fit now and no reservation is waiting.
+    pub fn try_acquire(&self, bytes: u64) -> Option<MemoryReservation> {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.queue.is_empty() {
+            return None;
+        }
+        self.reserve_if_fits(&mut state, bytes)
+    }
+
+    /// Reserves `bytes`, waiting until they fit and the reservations that
+    /// waited before are served. Dropping the future while it waits
+    /// reserves nothing.
+    pub async fn acquire(&self, bytes: u64) -> MemoryReservation {
+        if let Some(reservation) = self.try_acquire(bytes) {
+            return reservation;
+        }
+        let mut place = {
+            let mut state = self.shared.state.lock().unwrap();
+            let ticket = state.next_ticket;
+            state.next_ticket += 1;
+            state.queue.push_back(ticket);
+            state.waits += 1;
+            QueuePlace {
+                budget: self,
+                ticket,
+                served: false,
+            }
+        };
+        loop {
+            // Created before the check, so a release in between is not missed.
+            let released = self.shared.released.notified();
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.queue.front() == Some(&place.ticket) {
+                    if let Some(reservation) = self.reserve_if_fits(&mut state, bytes) {
+                        state.queue.pop_front();
+                        place.served = true;
+                        drop(state);
+                        // The next in line may fit in what is left.
+                        self.shared.released.notify_waiters();
+                        return reservation;
+                    }
+                }
+            }
+            released.await;
+        }
+    }
+
+    fn reserve_if_fits(&self, state: &mut State, bytes: u64) -> Option<MemoryReservation> {
+        let fits = state.used == 0 || state.used.saturating_add(bytes) <= self.shared.limit;
+        if !fits {
+            return None;
+        }
+        state.used += bytes;
+        state.peak = state.peak.max(state.used);
+        Some(MemoryReservation {
+            budget: self.clone(),
+            bytes,
+        })
+    }
+
+    /// A warning, when `concurrency` buffers of `buffer_size` bytes exceed the
+    /// budget, suggesting values that fit.
+    pub fn fit_warning(&self, concurrency: usize, buffer_size: u64) -> Option<String> {
+        let needed = (concurrency as u64).saturating_mul(buffer_size);
+        if needed <= self.shared.limit || buffer_size == 0 {
+            return None;
+        }
+        let max_concurrency = self.shared.limit / buffer_size;
+        let max_buffer_size = self.shared.limit / concurrency.max(1) as u64;
+        let mut suggestions = Vec::new();
+        if max_concurrency > 0 {
+            suggestions.push(format!("a concurrency of at most {}", max_concurrency));
+        }
+        suggestions.push(format!(
+            "a part size of at most {}",
+            format_size(max_buffer_size)
+        ));
+        suggestions.push("a larger --memory-limit".to_string());
+        let warning = format!(
+            "{} transfers of {} need {}, more than the {} memory limit, \
+             so they will wait for memory; use {}",
+            concurrency,
+            format_size(buffer_size),
+            format_size(needed),
+            format_size(self.shared.limit),
+            suggestions.join(", or ")
+        );
+        Some(warning)
+    }
+
+    fn release(&self, bytes: u64) {
+        self.shared.state.lock().unwrap().used -= bytes;
+        self.shared.released.notify_waiters();
+    }
+}
+
+/// The place of a waiting `acquire` in the queue, given up if the future is
+/// dropped before it is served.
+struct QueuePlace<'a> {
+    budget: &'a MemoryBudget,
+    ticket: u64,
+    served: bool,
+}
+
+impl Drop for QueuePlace<'_> {
+    fn drop(&mut self) {
+        if self.served {
+            return;
+        }
+        let shared = &self.budget.shared;
+        shared
+            .state
+            .lock()
+            .unwrap()
+            .queue
+            .retain(|ticket| *ticket != self.ticket);
+        // The next in line may be first now.
+        shared.released.notify_waiters();
+    }
+}
+
+/// Bytes reserved from a `MemoryBudget`, until dropped.
+#[derive(Debug)]
+pub struct MemoryReservation {
+    budget: MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryReservation {
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+/// Reserves `bytes` from `budget`, if there is one.
+pub async fn reserve(budget: Option<&MemoryBudget>, bytes: u64) -> Option<MemoryReservation> {
+    match budget {
+        Some(budget) => Some(budget.acquire(bytes).await),
+        None => None,
+    }
+}
@@ -0,0 +1,270 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Concatenation of several objects into one, as for aggregating logs or
+//! merging the files of a day, without downloading and uploading them
+//! again. This is the inverse of `split::split_object`.
+//!
+//! Each source large enough to be a part is copied by S3 with
+//! `UploadPartCopy`. Every part but the last must be at least 5 MiB, so
+//! smaller sources are downloaded and buffered until they add up to a part,
+//! which is sent with `UploadPart`. A buffer that is not yet a part when a
+//! large source comes next is topped up from the start of that source, and
+//! the rest of the source is copied. At most twice the minimum part size is
+//! held in memory.
+
+use crate::copy_prefix::copy_source;
+use crate::upload::{abort_upload, MAX_PART_SIZE, MIN_PART_SIZE};
+use aws_sdk_s3::model::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Error};
+use bytes::{Bytes, BytesMut};
+
+#[derive(Debug, Clone, Copy)]
+pub struct MergeOptions {
+    /// Parts smaller than this are only sent last.
+    pub min_part_size: u64,
+    /// Larger sources are copied in several ranges.
+    pub max_part_size: u64,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self {
+            min_part_size: MIN_PART_SIZE,
+            max_part_size: MAX_PART_SIZE,
+        }
+    }
+}
+
+/// Splits `length` bytes from `offset` into the fewest ranges of at most
+/// `max_part_size` bytes, of sizes as even as possible. Returns inclusive
+/// `(first, last)` byte positions.
+pub fn copy_ranges(offset: u64, length: u64, max_part_size: u64) -> Vec<(u64, u64)> {
+    if length == 0 {
+        return Vec::new();
+    }
+    let count = (length + max_part_size - 1) / max_part_size;
+    let base = length / count;
+    let mut ranges = Vec::with_capacity(count as usize);
+    let mut start = offset;
+    for i in 0..count {
+        let size = base + if i < length % count { 1 } else { 0 };
+        ranges.push((start, start + size - 1));
+        start += size;
+    }
+    ranges
+}
+
+/// Concatenates `src_keys` of `bucket`, in order, into `dst_key` and
+/// returns its ETag.
+pub async fn merge_objects(
+    client: &Client,
+    bucket: &str,
+    src_keys: &[&str],
+    dst_key: &str,
+) -> Result<String, Error> {
+    merge_objects_with_options(client, bucket, src_keys, dst_key, &MergeOptions::default()).await
+}
+
+/// `merge_objects` with the part sizes of `options`.
+pub async fn merge_objects_with_options(
+    client: &Client,
+    bucket: &str,
+    src_keys: &[&str],
+    dst_key: &str,
+    options: &MergeOptions,
+) -> Result<String, Error> {
+    if src_keys.is_empty() {
+        return Err(Error::Unhandled(Box::from("No objects to merge")));
+    }
+    if options.max_part_size == 0 || options.max_part_size < options.min_part_size {
+        return Err(Error::Unhandled(Box::from(
+            "The maximum part size must be positive and at least the minimum",
+        )));
+    }
+    // Sizes are read up front, so that a missing source fails before the
+    // upload is created.
+    let mut sources = Vec::with_capacity(src_keys.len());
+    for key in src_keys {
+        let head = client.head_object().bucket(bucket).key(*key).send().await?;
+        sources.push((*key, head.content_length().max(0) as u64));
+    }
+
+    let u = client
+        .create_multipart_upload()
+        .bucket(bucket)
+        .key(dst_key)
+        .send()
+        .await?;
+    let uid = u.upload_id().ok_or(Error::NoSuchUpload(
+        aws_sdk_s3::error::NoSuchUpload::builder()
+            .message("No upload ID")
+            .build(),
+    ))?;
+
+    let mut merge = Merge {
+        client,
+        bucket,
+        dst_key,
+        uid,
+        options,
+        parts: Vec::new(),
+        buffer: BytesMut::new(),
+    };
+    let parts = match merge.run(&sources).await {
+        Ok(()) => merge.parts,
+        Err(err) => {
+            abort_upload(client, bucket, dst_key, uid).await;
+            return Err(err);
+        }
+    };
+
+    let completed = client
+        .complete_multipart_upload()
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build(),
+        )
+        .upload_id(uid)
+        .bucket(bucket)
+        .key(dst_key)
+        .send()
+        .await?;
+    Ok(completed
+        .e_tag()
+        .unwrap_or_default()
+        .trim_matches('"')
+        .to_string())
+}
+
+struct Merge<'a> {
+    client: &'a Client,
+    bucket: &'a str,
+    dst_key: &'a str,
+    uid: &'a str,
+    options: &'a MergeOptions,
+    parts: Vec<CompletedPart>,
+    /// Bytes of small sources not yet sent.
+    buffer: BytesMut,
+}
+
+impl Merge<'_> {
+    async fn run(&mut self, sources: &[(&str, u64)]) -> Result<(), Error> {
+        let min = self.options.min_part_size;
+        for (key, size) in sources {
+            let (key, size) = (*key, *size);
+            let mut offset = 0;
+            if !self.buffer.is_empty() {
+                let needed = min.saturating_sub(self.buffer.len() as u64);
+                if size >= needed + min {
+                    // Tops up the buffer and copies the rest.
+                    self.buffer_range(key, 0, needed).await?;
+                    self.flush().await?;
+                    offset = needed;
+                } else {
+                    self.buffer_range(key, 0, size).await?;
+                    if self.buffer.len() as u64 >= min {
+                        self.flush().await?;
+                    }
+                    continue;
+                }
+            }
+            if size - offset >= min {
+                for (first, last) in copy_ranges(offset, size - offset, self.options.max_part_size)
+                {
+                    self.copy_part(key, first, last).await?;
+                }
+            } else {
+                self.buffer_range(key, offset, size - offset).await?;
+            }
+        }
+        // The last part may be small; an upload needs at least one part,
+        // even if every source is empty.
+        if !self.buffer.is_empty() || self.parts.is_empty() {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    fn next_part_number(&self) -> i32 {
+        self.parts.len() as i32 + 1
+    }
+
+    /// Appends `length` bytes of `key` from `offset` to the buffer.
+    async fn buffer_range(&mut self, key: &str, offset: u64, length: u64) -> Result<(), Error> {
+        if length == 0 {
+            return Ok(());
+        }
+        let resp = self
+            .client
+            .get_object()
+            .bucket(self.bucket)
+            .key(key)
+            .range(format!("bytes={}-{}", offset, offset + length - 1))
+            .send()
+            .await?;
+        let data = resp
+            .body
+            .collect()
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?
+            .into_bytes();
+        self.buffer.extend_from_slice(&data);
+        Ok(())
+    }
+
+    /// Sends the buffer as the next part.
+    async fn flush(&mut self) -> Result<(), Error> {
+        let part_number = self.next_part_number();
+        let data: Bytes = self.buffer.split().freeze();
+        let up = self
+            .client
+            .upload_part()
+            .bucket(self.bucket)
+            .key(self.dst_key)
+            .content_length(data.len() as i64)
+            .upload_id(self.uid)
+            .part_number(part_number)
+            .body(ByteStream::from(data))
+            .send()
+            .await?;
+        self.parts.push(
+            CompletedPart::builder()
+                .set_e_tag(up.e_tag)
+                .part_number(part_number)
+                .build(),
+        );
+        Ok(())
+    }
+
+    /// Copies bytes `first` to `last` of `key` as the next part.
+    async fn copy_part(&mut self, key: &str, first: u64, last: u64) -> Result<(), Error> {
+        let part_number = self.next_part_number();
+        let up = self
+            .client
+            .upload_part_copy()
+            .copy_source(copy_source(self.bucket, key))
+            .copy_source_range(format!("bytes={}-{}", first, last))
+            .bucket(self.bucket)
+            .key(self.dst_key)
+            .upload_id(self.uid)
+            .part_number(part_number)
+            .send()
+            .await?;
+        self.parts.push(
+            CompletedPart::builder()
+                .set_e_tag(
+                    up.copy_part_result()
+                        .and_then(|r| r.e_tag())
+                        .map(|t| t.to_string()),
+                )
+                .part_number(part_number)
+                .build(),
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Buffered writer that turns a sequence of byte slices into an object.
+//!
+//! Data is accumulated in memory until a part is full, then uploaded as the
+//! next part of a multipart upload. The multipart upload is only created once
+//! the first part is ready, so small payloads are sent with a single
+//! `PutObject` instead.
+//!
+//! With a `MemoryBudget`, the buffer is only allocated once a part's worth
+//! of memory is reserved from it, on the first write, and the reservation
+//! is held until the writer is dropped.
+
+use crate::memory_budget::{MemoryBudget, MemoryReservation};
+use crate::upload::UploadHeaders;
+use aws_sdk_s3::model::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Error};
+use std::collections::HashMap;
+
+/// Default size of the parts uploaded by a `MultipartWriter`.
+pub const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Smallest part size accepted by S3 for all but the last part.
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Result of `MultipartWriter::finish`.
+#[derive(Debug, Clone)]
+pub struct FinishedUpload {
+    pub e_tag: String,
+    /// Number of parts, or 1 when the object was sent with `PutObject`.
+    pub part_count: usize,
+}
+
+pub struct MultipartWriter {
+    client: Client,
+    bucket: String,
+    key: String,
+    part_size: usize,
+    upload_id: Option<String>,
+    buffer: Vec<u8>,
+    parts: Vec<CompletedPart>,
+    bytes_written: u64,
+    memory_budget: Option<MemoryBudget>,
+    reservation: Option<MemoryReservation>,
+    headers: UploadHeaders,
+    metadata: Option<HashMap<String, String>>,
+}
+
+impl MultipartWriter {
+    pub fn new(client: &Client, bucket: &str, key: &str) -> Self {
+        Self {
+            client: client.clone(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            part_size: DEFAULT_PART_SIZE,
+            upload_id: None,
+            buffer: Vec::with_capacity(DEFAULT_PART_SIZE),
+            parts: Vec::new(),
+            bytes_written: 0,
+            memory_budget: None,
+            reservation: None,
+            headers: UploadHeaders::default(),
+            metadata: None,
+        }
+    }
+
+    /// Sets the part size; values below the S3 minimum are raised to it.
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(MIN_PART_SIZE);
+        self.buffer = Vec::with_capacity(self.part_size);
+        self
+    }
+
+    /// Reserves the buffer from `budget` before allocating it.
+    pub fn with_memory_budget(mut self, budget: &MemoryBudget) -> Self {
+        self.memory_budget = Some(budget.clone());
+        self.buffer = Vec::new();
+        self
+    }
+
+    /// Stores the object with `headers`.
+    pub fn with_headers(mut self, headers: UploadHeaders) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Stores the object with the user-defined `metadata`.
+    pub fn with_metadata(mut self, metadata: HashMap<String, String>) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Total number of bytes passed to `write`.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Number of parts uploaded so far.
+    pub fn parts_uploaded(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Buffers `data`, uploading a part each time the buffer fills up.
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        if let (Some(budget), None) = (&self.memory_budget, &self.reservation) {
+            self.reservation = Some(budget.acquire(self.part_size as u64).await);
+            self.buffer.reserve(self.part_size);
+        }
+        self.bytes_written += data.len() as u64;
+        while !data.is_empty() {
+            let room = self.part_size - self.buffer.len();
+            let n = room.min(data.len());
+            self.buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buffer.len() == self.part_size {
+                self.flush_part().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Uploads the buffered data and completes the object.
+    ///
+    /// On failure the multipart upload, if any, is aborted.
+    pub async fn finish(mut self) -> Result<FinishedUpload, Error> {
+        if self.upload_id.is_none() {
+            let body = std::mem::take(&mut self.buffer);
+            let request = self
+                .client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .set_metadata(self.metadata.clone())
+                .content_length(body.len() as i64)
+                .body(ByteStream::from(body));
+            let resp = self.headers.apply_to_put_object(request).send().await?;
+            return Ok(FinishedUpload {
+                e_tag: resp
+                    .e_tag()
+                    .unwrap_or_default()
+                    .trim_matches('"')
+                    .to_string(),
+                part_count: 1,
+            });
+        }
+
+        if !self.buffer.is_empty() {
+            if let Err(err) = self.flush_part().await {
+                self.abort().await?;
+                return Err(err);
+            }
+        }
+        let uid = self.upload_id.clone().unwrap_or_default();
+        let part_count = self.parts.len();
+        let b = CompletedMultipartUpload::builder()
+            .set_parts(Some(std::mem::take(&mut self.parts)))
+            .build();
+        let completed = self
+            .client
+            .complete_multipart_upload()
+            .multipart_upload(b)
+            .upload_id(uid)
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .send()
+            .await;
+        match completed {
+            Ok(completed) => Ok(FinishedUpload {
+                e_tag: completed
+                    .e_tag()
+                    .unwrap_or_default()
+                    .trim_matches('"')
+                    .to_string(),
+                part_count,
+            }),
+            Err(err) => {
+                self.abort().await?;
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Aborts the multipart upload, discarding the parts uploaded so far.
+    pub async fn abort(self) -> Result<(), Error> {
+        if let Some(uid) = self.upload_id {
+            self.client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .upload_id(uid)
+                .send()
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Uploads the buffer as the next part, creating the upload if needed.
+    async fn flush_part(&mut self) -> Result<(), Error> {
+        let uid = match &self.upload_id {
+            Some(uid) => uid.clone(),
+            None => {
+                let request = self
+                    .client
+                    .create_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(&self.key)
+                    .set_metadata(self.metadata.clone());
+                let u = self
+                    .headers
+                    .apply_to_create_multipart_upload(request)
+                    .send()
+                    .await?;
+                let uid = u
+                    .upload_id()
+                    .ok_or(Error::NoSuchUpload(
+                        aws_sdk_s3::error::NoSuchUpload::builder()
+                            .message("No upload ID")
+                            .build(),
+                    ))?
+                    .to_string();
+                self.upload_id = Some(uid.clone());
+                uid
+            }
+        };
+        let part_number = (self.parts.len() + 1) as i32;
+        let body = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.part_size));
+        let up = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .content_length(body.len() as i64)
+            .upload_id(uid)
+            .part_number(part_number)
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+        let cp = CompletedPart::builder()
+            .set_e_tag(up.e_tag)
+            .part_number(part_number)
+            .build();
+        self.parts.push(cp);
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Announces a finished upload on an Amazon SNS topic.
+//!
+//! An SNS topic fans the notification out to every subscriber at once, such
+//! as a Lambda function, an SQS queue, and an HTTP endpoint, so the tool that
+//! uploaded the object does not need to know who processes it. The message
+//! is the `UploadPayload` as JSON.
+//!
+//! `Publish` is sent again, up to three attempts in all, when the topic's KMS
+//! key is disabled, a subscribed endpoint is disabled, or the request could
+//! not reach SNS; these can clear up within seconds. Other errors, such as a
+//! missing topic or a denied `sns:Publish`, are returned after one attempt.
+
+use crate::retry::RetryPolicy;
+use crate::units::format_duration;
+use aws_sdk_sns::error::PublishError;
+use aws_sdk_sns::types::SdkError;
+use aws_sdk_sns::{Client, Error};
+use serde::{Deserialize, Serialize};
+
+/// `Publish` error codes that are retried.
+pub const SNS_RETRY_CODES: &[&str] = &["KMSDisabled", "EndpointDisabled"];
+
+/// The attempts of `notify_sns_after_upload`, the first one included.
+pub const NOTIFY_ATTEMPTS: u32 = 3;
+
+/// The message published for an uploaded object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadPayload {
+    pub bucket: String,
+    pub key: String,
+    /// Without quotes.
+    pub e_tag: String,
+    pub size: u64,
+}
+
+fn should_retry(err: &SdkError<PublishError>) -> bool {
+    match err {
+        SdkError::DispatchFailure(_) | SdkError::TimeoutError(_) => true,
+        SdkError::ServiceError { err, .. } => err
+            .code()
+            .map(|code| SNS_RETRY_CODES.contains(&code))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Publishes `message` to `topic_arn` and returns the `MessageId` SNS gave
+/// it, retrying as the module says.
+pub async fn notify_sns_after_upload(
+    sns_client: &Client,
+    topic_arn: &str,
+    message: UploadPayload,
+) -> Result<String, Error> {
+    let policy = RetryPolicy {
+        max_attempts: NOTIFY_ATTEMPTS,
+        ..Default::default()
+    };
+    notify_sns_with_policy(sns_client, topic_arn, &message, &policy).await
+}
+
+/// Like `notify_sns_after_upload`, with the attempts and backoff of `policy`.
+pub async fn notify_sns_with_policy(
+    sns_client: &Client,
+    topic_arn: &str,
+    message: &UploadPayload,
+    policy: &RetryPolicy,
+) -> Result<String, Error> {
+    let body = serde_json::to_string(message).map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let mut attempt = 0;
+    loop {
+        let err = match sns_client
+            .publish()
+            .topic_arn(topic_arn)
+            .message(&body)
+            .send()
+            .await
+        {
+            Ok(resp) => return Ok(resp.message_id().unwrap_or_default().to_string()),
+            Err(err) => err,
+        };
+        attempt += 1;
+        if attempt >= policy.max_attempts || !should_retry(&err) {
+            return Err(err.into());
+        }
+        let delay = policy.jittered_backoff(attempt - 1);
+        eprintln!(
+            "Retrying the notification to {} in {}, attempt {} of {}: {}",
+            topic_arn,
+            format_duration(delay),
+            attempt + 1,
+            policy.max_attempts,
+            err
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
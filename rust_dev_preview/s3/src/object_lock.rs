@@ -0,0 +1,200 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Puts the objects under a prefix in Object Lock compliance mode until a
+//! date.
+//!
+//! An object locked in compliance mode cannot be deleted or overwritten by
+//! anyone, including the root user, until its retention date, and neither
+//! the mode nor the date can be lowered. The bucket must have been created
+//! with Object Lock enabled.
+//!
+//! The retention of each object is read first, and only the objects not
+//! already in compliance mode until at least the date are updated. Objects
+//! in governance mode are upgraded to compliance mode, keeping their date if
+//! it is later. Only the current version of each object is locked.
+
+use aws_sdk_s3::model::{ObjectLockRetention, ObjectLockRetentionMode};
+use aws_sdk_s3::types::{DateTime, SdkError};
+use aws_sdk_s3::{Client, Error};
+use chrono::{TimeZone, Utc};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// How many objects are checked and updated at the same time.
+pub const LOCK_CONCURRENCY: usize = 16;
+
+/// Parses a retention date, either RFC 3339 such as `2030-01-01T00:00:00Z`
+/// or a day such as `2030-01-01`, taken as midnight UTC.
+pub fn parse_retain_until(value: &str) -> Result<chrono::DateTime<Utc>, String> {
+    if let Ok(date) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|day| Utc.from_utc_datetime(&day.and_hms(0, 0, 0)))
+        .map_err(|_| format!("Invalid retention date: {}", value))
+}
+
+/// The retention date an object must be given so that it is in compliance
+/// mode until at least `retain_until`, or `None` if it already is.
+///
+/// `mode` and `current_until` are the object's current retention, if any. A
+/// governance date later than `retain_until` is kept, so that upgrading the
+/// mode never shortens the retention.
+pub fn compliance_date(
+    mode: Option<&ObjectLockRetentionMode>,
+    current_until: Option<chrono::DateTime<Utc>>,
+    retain_until: chrono::DateTime<Utc>,
+) -> Option<chrono::DateTime<Utc>> {
+    match (mode, current_until) {
+        (Some(ObjectLockRetentionMode::Compliance), Some(until)) if until >= retain_until => None,
+        (Some(ObjectLockRetentionMode::Governance), Some(until)) => Some(until.max(retain_until)),
+        _ => Some(retain_until),
+    }
+}
+
+/// The current retention of `bucket/key`, if it has one.
+async fn retention(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<ObjectLockRetention>, Error> {
+    match client
+        .get_object_retention()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+    {
+        Ok(resp) => Ok(resp.retention().cloned()),
+        Err(SdkError::ServiceError { err, .. })
+            if err.code() == Some("NoSuchObjectLockConfiguration") =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Locks `bucket/key` in compliance mode until at least `retain_until`,
+/// unless it already is. Returns whether it needed the update, which is
+/// only made if `dry_run` is false.
+async fn lock_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    retain_until: chrono::DateTime<Utc>,
+    dry_run: bool,
+) -> Result<bool, Error> {
+    let current = retention(client, bucket, key).await?;
+    let mode = current.as_ref().and_then(|r| r.mode());
+    let current_until = current
+        .as_ref()
+        .and_then(|r| r.retain_until_date())
+        .map(|date| Utc.timestamp(date.secs(), 0));
+    let until = match compliance_date(mode, current_until, retain_until) {
+        Some(until) => until,
+        None => return Ok(false),
+    };
+    if dry_run {
+        return Ok(true);
+    }
+    // Any change to a governance lock, even to the stricter compliance mode,
+    // needs the governance bypass.
+    let governance = mode == Some(&ObjectLockRetentionMode::Governance);
+    client
+        .put_object_retention()
+        .bucket(bucket)
+        .key(key)
+        .retention(
+            ObjectLockRetention::builder()
+                .mode(ObjectLockRetentionMode::Compliance)
+                .retain_until_date(DateTime::from_secs(until.timestamp()))
+                .build(),
+        )
+        .set_bypass_governance_retention(governance.then(|| true))
+        .send()
+        .await?;
+    Ok(true)
+}
+
+/// Locks the objects under `prefix` in compliance mode until at least
+/// `retain_until`, and returns how many were updated.
+pub async fn enforce_compliance_lock(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    retain_until: chrono::DateTime<Utc>,
+) -> Result<u64, Error> {
+    enforce_compliance_lock_with_options(client, bucket, prefix, retain_until, false).await
+}
+
+/// Like `enforce_compliance_lock`, but with `dry_run` only counts the
+/// objects that would be updated.
+///
+/// Each object costs a GetObjectRetention, and each one updated a
+/// PutObjectRetention, run `LOCK_CONCURRENCY` at a time. A failure does not
+/// stop the other objects; the first one is returned once all are done.
+pub async fn enforce_compliance_lock_with_options(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    retain_until: chrono::DateTime<Utc>,
+    dry_run: bool,
+) -> Result<u64, Error> {
+    let semaphore = Arc::new(Semaphore::new(LOCK_CONCURRENCY));
+    let mut handles = Vec::new();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+        for object in resp.contents().unwrap_or_default() {
+            let key = match object.key() {
+                Some(key) => key.to_string(),
+                None => continue,
+            };
+            let permit = semaphore
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+            let client = client.clone();
+            let bucket = bucket.to_string();
+            handles.push(tokio::spawn(async move {
+                let updated = lock_object(&client, &bucket, &key, retain_until, dry_run).await;
+                drop(permit);
+                updated
+            }));
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+
+    let mut updated = 0;
+    let mut first_error = None;
+    for handle in handles {
+        match handle
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?
+        {
+            Ok(true) => updated += 1,
+            Ok(false) => {}
+            Err(err) => {
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+    match first_error {
+        Some(err) => Err(err),
+        None => Ok(updated),
+    }
+}
@@ -0,0 +1,605 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! A trait over the S3 operations used by the transfer logic, so that logic
+//! can be tested against a mock instead of a live endpoint.
+//!
+//! The form of the methods depends on the features the crate is built with:
+//!
+//! - By default, each method returns a `BoxFuture`. `S3Ops` can be used as
+//!   `dyn S3Ops`, and every call allocates its future.
+//! - With `async-trait`, the trait is declared with the `async-trait` crate:
+//!   still usable as `dyn`, with the futures boxed the same way.
+//! - With `nightly`, each method returns `impl Future + Send`, so no call
+//!   allocates, but the trait can no longer be used as `dyn`. This once
+//!   needed a nightly compiler; it needs Rust 1.75 or later.
+//!
+//! The transfer logic is generic over `S3Ops`, so it builds in every form.
+//! Implementations are written once, as `async fn` methods in
+//! `impl_s3_ops!`, which turns them into the form that was built. The
+//! `bench-ops` example times the same calls in each form.
+
+use aws_sdk_s3::model::{ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::types::{ByteStream, SdkError};
+use aws_sdk_s3::Client;
+use std::fmt;
+
+#[cfg(all(feature = "async-trait", feature = "nightly"))]
+compile_error!("The async-trait and nightly features cannot be enabled together");
+
+/// What the expansion of `impl_s3_ops!` refers to in this crate.
+#[doc(hidden)]
+pub mod __macro_support {
+    #[cfg(feature = "async-trait")]
+    pub use async_trait::async_trait;
+    pub use futures::future::BoxFuture;
+}
+
+/// Implements `S3Ops` from `async fn` methods, in the form of the trait that
+/// was built. Borrowed arguments are written `&str`, without a lifetime, and
+/// arguments are not declared `mut`.
+///
+/// ```ignore
+/// impl_s3_ops! {
+///     impl S3Ops for Mock {
+///         async fn head_bucket(&self, _bucket: &str) -> Result<(), OpError> {
+///             Ok(())
+///         }
+///         // ...
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! impl_s3_ops {
+    ($($item:tt)*) => {
+        $crate::__s3_ops_item!([] $($item)*);
+    };
+}
+
+// Splits a trait or impl into its header, which may have generics, and its
+// methods.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __s3_ops_item {
+    ([$($head:tt)*] { $($methods:tt)* }) => {
+        $crate::__s3_ops_emit!([$($head)*] $($methods)*);
+    };
+    ([$($head:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__s3_ops_item!([$($head)* $next] $($rest)*);
+    };
+}
+
+#[cfg(feature = "async-trait")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __s3_ops_emit {
+    ([$($head:tt)*] $($methods:tt)*) => {
+        #[$crate::ops::__macro_support::async_trait]
+        $($head)* {
+            $($methods)*
+        }
+    };
+}
+
+#[cfg(not(feature = "async-trait"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __s3_ops_emit {
+    ([$($head:tt)*] $($methods:tt)*) => {
+        $($head)* {
+            $crate::__s3_ops_methods!($($methods)*);
+        }
+    };
+}
+
+// Each `async fn` returns a `BoxFuture` borrowing `self` and the borrowed
+// arguments for `'a`, which the arguments are collected one by one to add.
+#[cfg(not(any(feature = "async-trait", feature = "nightly")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __s3_ops_methods {
+    () => {};
+    (
+        $(#[$attr:meta])*
+        async fn $name:ident(&$s:ident $(, $($args:tt)*)?) -> $ret:ty;
+        $($rest:tt)*
+    ) => {
+        $crate::__s3_ops_methods!(@args [$(#[$attr])* $name $s ($ret) ;] [] $($($args)*)?);
+        $crate::__s3_ops_methods!($($rest)*);
+    };
+    (
+        $(#[$attr:meta])*
+        async fn $name:ident(&$s:ident $(, $($args:tt)*)?) -> $ret:ty { $($body:tt)* }
+        $($rest:tt)*
+    ) => {
+        $crate::__s3_ops_methods!(
+            @args [$(#[$attr])* $name $s ($ret) { $($body)* }] [] $($($args)*)?
+        );
+        $crate::__s3_ops_methods!($($rest)*);
+    };
+    (@args $method:tt [$($done:tt)*] $arg:ident: &$ty:ty $(, $($rest:tt)*)?) => {
+        $crate::__s3_ops_methods!(@args $method [$($done)* $arg: &'a $ty,] $($($rest)*)?);
+    };
+    (@args $method:tt [$($done:tt)*] $arg:ident: $ty:ty $(, $($rest:tt)*)?) => {
+        $crate::__s3_ops_methods!(@args $method [$($done)* $arg: $ty,] $($($rest)*)?);
+    };
+    (@args [$(#[$attr:meta])* $name:ident $s:ident ($ret:ty) ;] [$($args:tt)*]) => {
+        $(#[$attr])*
+        fn $name<'a>(&'a $s, $($args)*) -> $crate::ops::__macro_support::BoxFuture<'a, $ret>;
+    };
+    (@args [$(#[$attr:meta])* $name:ident $s:ident ($ret:ty) $body:block] [$($args:tt)*]) => {
+        $(#[$attr])*
+        fn $name<'a>(&'a $s, $($args)*) -> $crate::ops::__macro_support::BoxFuture<'a, $ret> {
+            ::std::boxed::Box::pin(async move $body)
+        }
+    };
+}
+
+// Each `async fn` returns `impl Future + Send`: an `async fn` declared in a
+// trait would not promise callers a `Send` future.
+#[cfg(feature = "nightly")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __s3_ops_methods {
+    () => {};
+    (
+        $(#[$attr:meta])*
+        async fn $name:ident(&$s:ident $(, $arg:ident: $ty:ty)* $(,)?) -> $ret:ty;
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        fn $name(&$s $(, $arg: $ty)*) -> impl ::std::future::Future<Output = $ret> + Send;
+        $crate::__s3_ops_methods!($($rest)*);
+    };
+    (
+        $(#[$attr:meta])*
+        async fn $name:ident(&$s:ident $(, $arg:ident: $ty:ty)* $(,)?) -> $ret:ty {
+            $($body:tt)*
+        }
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        fn $name(&$s $(, $arg: $ty)*) -> impl ::std::future::Future<Output = $ret> + Send {
+            async move { $($body)* }
+        }
+        $crate::__s3_ops_methods!($($rest)*);
+    };
+}
+
+/// An operation failure, reduced to what callers inspect.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpError {
+    /// The HTTP status, for errors returned by the service.
+    pub status: Option<u16>,
+    /// The S3 error code, such as `AccessDenied`.
+    pub code: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for OpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.code, self.status) {
+            (Some(code), Some(status)) => write!(f, "{} ({}): {}", code, status, self.message),
+            (None, Some(status)) => write!(f, "HTTP {}: {}", status, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for OpError {}
+
+impl OpError {
+    /// 503 SlowDown and 429 Too Many Requests, as `retry::is_throttling`.
+    pub fn is_throttling(&self) -> bool {
+        matches!(self.status, Some(429) | Some(503))
+    }
+
+    fn from_sdk<E, F>(err: SdkError<E>, code: F) -> Self
+    where
+        E: std::error::Error + 'static,
+        F: FnOnce(&E) -> Option<&str>,
+    {
+        match &err {
+            SdkError::ServiceError { err: inner, raw } => OpError {
+                status: Some(raw.http().status().as_u16()),
+                code: code(inner).map(|c| c.to_string()),
+                message: err.to_string(),
+            },
+            _ => OpError {
+                status: None,
+                code: None,
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+__s3_ops_item!([]
+    pub trait S3Ops: Send + Sync {
+        async fn head_bucket(&self, bucket: &str) -> Result<(), OpError>;
+
+        /// Returns the upload id.
+        async fn create_multipart_upload(&self, bucket: &str, key: &str) -> Result<String, OpError>;
+
+        async fn abort_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+        ) -> Result<(), OpError>;
+
+        /// Returns the ETag of the part, with its quotes, as the completion
+        /// expects it.
+        async fn upload_part(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+        ) -> Result<String, OpError>;
+
+        /// Completes an upload from the part numbers and ETags of its parts,
+        /// in order. Returns the ETag, without quotes.
+        async fn complete_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String)>,
+        ) -> Result<String, OpError>;
+
+        /// Returns the ETag, without quotes.
+        async fn put_object(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: Vec<u8>,
+        ) -> Result<String, OpError>;
+
+        async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), OpError>;
+
+        /// Returns `length` bytes of the object from `offset`, with a ranged
+        /// GetObject. `length` is at least 1.
+        async fn get_object_range(
+            &self,
+            bucket: &str,
+            key: &str,
+            offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>, OpError>;
+
+        /// As `get_object_range`, failing with 412 PreconditionFailed once the
+        /// ETag of the object, without quotes, is no longer `e_tag`. By default
+        /// the ETag is not checked, for mocks that do not keep versions.
+        async fn get_object_range_if_match(
+            &self,
+            bucket: &str,
+            key: &str,
+            offset: u64,
+            length: u64,
+            _e_tag: &str,
+        ) -> Result<Vec<u8>, OpError> {
+            self.get_object_range(bucket, key, offset, length).await
+        }
+
+        // The `_sha256` methods send the base64 SHA-256 checksum of each body
+        // for S3 to verify. By default they drop it, for mocks that do not
+        // check it.
+
+        /// As `create_multipart_upload`, for parts sent with
+        /// `upload_part_sha256`.
+        async fn create_multipart_upload_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> Result<String, OpError> {
+            self.create_multipart_upload(bucket, key).await
+        }
+
+        async fn upload_part_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+            _checksum: String,
+        ) -> Result<String, OpError> {
+            self.upload_part(bucket, key, upload_id, part_number, body).await
+        }
+
+        /// As `complete_multipart_upload`, from the part number, ETag, and
+        /// checksum of each part.
+        async fn complete_multipart_upload_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String, String)>,
+        ) -> Result<String, OpError> {
+            let parts = parts
+                .into_iter()
+                .map(|(part_number, e_tag, _)| (part_number, e_tag))
+                .collect();
+            self.complete_multipart_upload(bucket, key, upload_id, parts).await
+        }
+
+        async fn put_object_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: Vec<u8>,
+            _checksum: String,
+        ) -> Result<String, OpError> {
+            self.put_object(bucket, key, body).await
+        }
+    }
+);
+
+// The inherent `Client` methods are called by path, so they are not confused
+// with the trait methods of the same name.
+impl_s3_ops! {
+    impl S3Ops for Client {
+        async fn head_bucket(&self, bucket: &str) -> Result<(), OpError> {
+            Client::head_bucket(self)
+                .bucket(bucket)
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(())
+        }
+
+        async fn create_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> Result<String, OpError> {
+            let resp = Client::create_multipart_upload(self)
+                .bucket(bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(resp.upload_id().unwrap_or_default().to_string())
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+        ) -> Result<(), OpError> {
+            Client::abort_multipart_upload(self)
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(())
+        }
+
+        async fn upload_part(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            let resp = Client::upload_part(self)
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .content_length(body.len() as i64)
+                .body(ByteStream::from(body))
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(resp.e_tag().unwrap_or_default().to_string())
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String)>,
+        ) -> Result<String, OpError> {
+            let parts = parts
+                .into_iter()
+                .map(|(part_number, e_tag)| {
+                    CompletedPart::builder()
+                        .part_number(part_number)
+                        .e_tag(e_tag)
+                        .build()
+                })
+                .collect();
+            let resp = Client::complete_multipart_upload(self)
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .multipart_upload(
+                    CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(resp.e_tag().unwrap_or_default().replace('"', ""))
+        }
+
+        async fn put_object(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            let resp = Client::put_object(self)
+                .bucket(bucket)
+                .key(key)
+                .body(ByteStream::from(body))
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(resp
+                .e_tag()
+                .unwrap_or_default()
+                .trim_matches('"')
+                .to_string())
+        }
+
+        async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), OpError> {
+            Client::delete_object(self)
+                .bucket(bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(())
+        }
+
+        async fn get_object_range(
+            &self,
+            bucket: &str,
+            key: &str,
+            offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>, OpError> {
+            get_range(self, bucket, key, offset, length, None).await
+        }
+
+        async fn get_object_range_if_match(
+            &self,
+            bucket: &str,
+            key: &str,
+            offset: u64,
+            length: u64,
+            e_tag: &str,
+        ) -> Result<Vec<u8>, OpError> {
+            get_range(self, bucket, key, offset, length, Some(e_tag)).await
+        }
+
+        async fn create_multipart_upload_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> Result<String, OpError> {
+            let resp = Client::create_multipart_upload(self)
+                .bucket(bucket)
+                .key(key)
+                .checksum_algorithm(ChecksumAlgorithm::Sha256)
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(resp.upload_id().unwrap_or_default().to_string())
+        }
+
+        async fn upload_part_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+            checksum: String,
+        ) -> Result<String, OpError> {
+            let resp = Client::upload_part(self)
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .content_length(body.len() as i64)
+                .checksum_sha256(checksum)
+                .body(ByteStream::from(body))
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(resp.e_tag().unwrap_or_default().to_string())
+        }
+
+        async fn complete_multipart_upload_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String, String)>,
+        ) -> Result<String, OpError> {
+            let parts = parts
+                .into_iter()
+                .map(|(part_number, e_tag, checksum)| {
+                    CompletedPart::builder()
+                        .part_number(part_number)
+                        .e_tag(e_tag)
+                        .checksum_sha256(checksum)
+                        .build()
+                })
+                .collect();
+            let resp = Client::complete_multipart_upload(self)
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .multipart_upload(
+                    CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(resp.e_tag().unwrap_or_default().replace('"', ""))
+        }
+
+        async fn put_object_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: Vec<u8>,
+            checksum: String,
+        ) -> Result<String, OpError> {
+            let resp = Client::put_object(self)
+                .bucket(bucket)
+                .key(key)
+                .checksum_sha256(checksum)
+                .body(ByteStream::from(body))
+                .send()
+                .await
+                .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+            Ok(resp
+                .e_tag()
+                .unwrap_or_default()
+                .trim_matches('"')
+                .to_string())
+        }
+    }
+}
+
+/// A ranged GetObject, from the object whose ETag is `if_match` if given.
+async fn get_range(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    offset: u64,
+    length: u64,
+    if_match: Option<&str>,
+) -> Result<Vec<u8>, OpError> {
+    let resp = Client::get_object(client)
+        .bucket(bucket)
+        .key(key)
+        .range(format!("bytes={}-{}", offset, offset + length - 1))
+        .set_if_match(if_match.map(|e_tag| format!("\"{}\"", e_tag)))
+        .send()
+        .await
+        .map_err(|err| OpError::from_sdk(err, |e| e.code()))?;
+    let body = resp.body.collect().await.map_err(|err| OpError {
+        status: None,
+        code: None,
+        message: err.to_string(),
+    })?;
+    Ok(body.into_bytes().to_vec())
+}
@@ -0,0 +1,188 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Inventory of the objects of a bucket by owner.
+//!
+//! In a bucket shared with other accounts, each object is owned by the
+//! account that wrote it unless Object Ownership is set to bucket owner
+//! enforced. ListObjectsV2 only returns the owner with `fetch-owner`, and not
+//! at all on some S3-compatible endpoints, so the owner of each object is
+//! read from its ACL, the canonical user ID of `Owner`. Objects owned by
+//! other accounts are the ones to audit. Their owner often keeps the ACL
+//! from the bucket owner, so an object whose ACL cannot be read is grouped
+//! under `ACCESS_DENIED_OWNER` rather than failing the inventory.
+
+use crate::units::format_size;
+use aws_sdk_s3::model::Object;
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use std::collections::HashMap;
+use tokio::task::JoinError;
+
+/// How many ACLs are read at the same time.
+pub const ACL_CHECK_CONCURRENCY: usize = 16;
+
+/// The owner of objects whose ACL has no owner ID.
+pub const UNKNOWN_OWNER: &str = "unknown";
+
+/// The owner of objects whose ACL could not be read, for AccessDenied.
+pub const ACCESS_DENIED_OWNER: &str = "access-denied";
+
+/// The canonical user ID of the owner of `bucket/key`, `ACCESS_DENIED_OWNER`
+/// if its ACL cannot be read, or `None` if the object was deleted since it
+/// was listed.
+async fn object_owner(client: &Client, bucket: &str, key: &str) -> Result<Option<String>, Error> {
+    match client.get_object_acl().bucket(bucket).key(key).send().await {
+        Ok(resp) => Ok(Some(
+            resp.owner()
+                .and_then(|owner| owner.id())
+                .unwrap_or(UNKNOWN_OWNER)
+                .to_string(),
+        )),
+        Err(SdkError::ServiceError { err, .. }) if err.code() == Some("NoSuchKey") => Ok(None),
+        Err(SdkError::ServiceError { err, .. }) if err.code() == Some("AccessDenied") => {
+            Ok(Some(ACCESS_DENIED_OWNER.to_string()))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+type Owned = Result<Result<Option<(String, Object)>, Error>, JoinError>;
+
+/// Adds the object of a finished GetObjectAcl to `owners`.
+fn record(owners: &mut HashMap<String, Vec<Object>>, owned: Owned) -> Result<(), Error> {
+    let owned = owned.map_err(|err| Error::Unhandled(Box::new(err)))?;
+    if let Some((owner, object)) = owned? {
+        owners.entry(owner).or_default().push(object);
+    }
+    Ok(())
+}
+
+/// The objects of `bucket` grouped by the canonical user ID of their owner.
+///
+/// Each object costs a GetObjectAcl, run `ACL_CHECK_CONCURRENCY` at a time.
+pub async fn objects_by_owner(
+    client: &Client,
+    bucket: &str,
+) -> Result<HashMap<String, Vec<Object>>, Error> {
+    objects_by_owner_with_concurrency(client, bucket, ACL_CHECK_CONCURRENCY).await
+}
+
+/// Same as `objects_by_owner`, with `concurrency` GetObjectAcl requests at
+/// a time. The requests are sent while the bucket is listed, and their
+/// results collected as they finish, so no more than `concurrency` of them
+/// are held at once.
+pub async fn objects_by_owner_with_concurrency(
+    client: &Client,
+    bucket: &str,
+    concurrency: usize,
+) -> Result<HashMap<String, Vec<Object>>, Error> {
+    let concurrency = concurrency.max(1);
+    let mut owners: HashMap<String, Vec<Object>> = HashMap::new();
+    let mut in_flight = FuturesUnordered::new();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+        for object in resp.contents().unwrap_or_default() {
+            let key = match object.key() {
+                Some(key) => key.to_string(),
+                None => continue,
+            };
+            if in_flight.len() >= concurrency {
+                if let Some(owned) = in_flight.next().await {
+                    record(&mut owners, owned)?;
+                }
+            }
+            let client = client.clone();
+            let bucket = bucket.to_string();
+            let object = object.clone();
+            in_flight.push(tokio::spawn(async move {
+                let owner = object_owner(&client, &bucket, &key).await;
+                owner.map(|owner| owner.map(|owner| (owner, object)))
+            }));
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+
+    while let Some(owned) = in_flight.next().await {
+        record(&mut owners, owned)?;
+    }
+    Ok(owners)
+}
+
+/// The objects and bytes of one owner.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnerTotals {
+    pub owner_id: String,
+    pub objects: usize,
+    pub bytes: u64,
+}
+
+/// The totals of each owner of `owners`, the largest first.
+pub fn owner_totals(owners: &HashMap<String, Vec<Object>>) -> Vec<OwnerTotals> {
+    let mut totals: Vec<OwnerTotals> = owners
+        .iter()
+        .map(|(owner_id, objects)| OwnerTotals {
+            owner_id: owner_id.clone(),
+            objects: objects.len(),
+            bytes: objects
+                .iter()
+                .map(|object| object.size().max(0) as u64)
+                .sum(),
+        })
+        .collect();
+    totals.sort_by(|a, b| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then_with(|| a.owner_id.cmp(&b.owner_id))
+    });
+    totals
+}
+
+/// Formats `totals` as a table with a line per owner, under the number of
+/// owners.
+pub fn render_owner_table(totals: &[OwnerTotals]) -> String {
+    let width = totals
+        .iter()
+        .map(|row| row.owner_id.len())
+        .chain(std::iter::once("Owner".len()))
+        .max()
+        .unwrap_or_default();
+    let mut lines = vec![
+        format!(
+            "{} owner{}",
+            totals.len(),
+            if totals.len() == 1 { "" } else { "s" }
+        ),
+        String::new(),
+        format!(
+            "{:<width$}  {:>10}  {:>16}",
+            "Owner",
+            "Objects",
+            "Size",
+            width = width
+        ),
+    ];
+    for row in totals {
+        lines.push(format!(
+            "{:<width$}  {:>10}  {:>16}",
+            row.owner_id,
+            row.objects,
+            format_size(row.bytes),
+            width = width
+        ));
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,790 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Parallel ranged downloads of one object, optionally written in place.
+//!
+//! The object is read in ranges of `part_size` bytes, `concurrency` at a
+//! time, and each range is written at its offset. By default the ranges go
+//! to `<name>.part`, renamed once complete, as `durable::write_file`. With
+//! `write_in_place` they go straight into the target, as when restoring a
+//! disk image onto a block device or a preallocated sparse file. With
+//! `truncate` off as well, the target must already exist and be at least as
+//! large as the object. It is never resized, and the bytes outside the
+//! object's ranges are left as they were.
+//!
+//! Block devices may need `O_DIRECT` writes, which bypass the page cache
+//! and must be aligned in offset, length, and memory. With `alignment`, the
+//! parts are sized in multiples of it and written with `O_DIRECT` on Linux.
+//! The tail of an object that is not a multiple of the alignment is written
+//! through the page cache. Where `O_DIRECT` is refused, as on tmpfs or
+//! other systems, every write falls back to the page cache with a warning.
+//!
+//! Every range is read from the object found by the first HeadObject: its
+//! version when the bucket is versioned, otherwise its ETag with `If-Match`,
+//! so that an overwrite during the download fails with 412 instead of
+//! mixing ranges of two objects. With `retry_on_change`, the download then
+//! starts over once, with the new object. With `resume`, the ETag or version
+//! and the ranges written are kept in `<target>.download.json`, and a later
+//! run fetches the missing ranges only if the object is still the same. A
+//! range is synced to disk before it is recorded, the state is replaced
+//! atomically, and the ranges kept are hashed again when resuming: those
+//! that no longer match, as after a crash, are fetched again.
+
+use crate::durable::{part_path, sync_parent_dir, write_file, FsyncOptions, FsyncStats};
+use crate::integrity::{get_integrity_manifest, IntegrityCheck};
+use crate::manifest::sha256_window;
+use crate::memory_budget::{reserve, MemoryBudget};
+use crate::progress::{ProgressEvent, ProgressTracker, TransferTotals};
+use crate::upload::SourceWindow;
+use crate::upload_status::sample_indices;
+use crate::verbosity::{with_request_id, VerbosityConfig};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use bytes::Bytes;
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::AsyncSeekExt;
+
+pub const DEFAULT_PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The parts read back by `WriteVerify::Sample` when no count is given.
+pub const DEFAULT_SAMPLE: usize = 8;
+
+/// How the bytes written are checked once the download is complete.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteVerify {
+    None,
+    /// Reads back this many of the parts and compares them with the bytes
+    /// received.
+    Sample(usize),
+    /// Checks the whole object against its integrity manifest.
+    Checksum,
+}
+
+impl std::str::FromStr for WriteVerify {
+    type Err = String;
+
+    /// Parses `none`, `sample`, `sample:N`, or `checksum`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(WriteVerify::None),
+            "sample" => Ok(WriteVerify::Sample(DEFAULT_SAMPLE)),
+            "checksum" => Ok(WriteVerify::Checksum),
+            other => match other.strip_prefix("sample:").map(str::parse) {
+                Some(Ok(sample)) => Ok(WriteVerify::Sample(sample)),
+                _ => Err(format!(
+                    "Unknown verification: {} (none, sample[:N], or checksum)",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParallelDownloadOptions {
+    pub part_size: u64,
+    /// Number of ranges downloaded at the same time.
+    pub concurrency: usize,
+    /// Write into the target itself, rather than into `<name>.part`
+    /// renamed once complete.
+    pub write_in_place: bool,
+    /// Create the target, or cut it, to the size of the object. Can only be
+    /// off with `write_in_place`.
+    pub truncate: bool,
+    /// Write with `O_DIRECT` in blocks of this many bytes.
+    pub alignment: Option<usize>,
+    pub verify: WriteVerify,
+    /// Start over once with the new object when it is overwritten during
+    /// the download.
+    pub retry_on_change: bool,
+    /// Keep the ranges written by an earlier run of the same download.
+    pub resume: bool,
+    /// Reserve each range from this budget before reading it into memory.
+    pub memory_budget: Option<MemoryBudget>,
+    /// Print a line as each range is written.
+    pub verbosity: VerbosityConfig,
+    /// The target is always synced once all the ranges are written; with
+    /// `enabled`, its directory is also synced after the rename, and with
+    /// `interval`, the target every so many bytes written.
+    pub fsync: FsyncOptions,
+}
+
+impl Default for ParallelDownloadOptions {
+    fn default() -> Self {
+        Self {
+            part_size: DEFAULT_PART_SIZE,
+            concurrency: 8,
+            write_in_place: false,
+            truncate: true,
+            alignment: None,
+            verify: WriteVerify::None,
+            retry_on_change: false,
+            resume: false,
+            memory_budget: None,
+            verbosity: VerbosityConfig::default(),
+            fsync: FsyncOptions::default(),
+        }
+    }
+}
+
+/// Outcome of `download_parallel`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ParallelDownload {
+    pub size: u64,
+    /// The size of the target before the download, when it was kept.
+    pub target_size: Option<u64>,
+    pub parts: usize,
+    /// The parts kept from an earlier run, with `resume`.
+    pub resumed_parts: usize,
+    /// The object, the bytes kept from an earlier run, and those downloaded
+    /// in this one.
+    pub totals: TransferTotals,
+    /// Whether the object was overwritten and the download started over.
+    pub restarted_on_change: bool,
+    /// Whether the aligned writes used `O_DIRECT`.
+    pub direct_io: bool,
+    /// Why the writes went through the page cache despite `alignment`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<String>,
+    pub fsync: FsyncStats,
+    /// The part numbers read back, with `WriteVerify::Sample`.
+    pub sampled_parts: Vec<u32>,
+    /// The part numbers read back with other bytes than those received.
+    pub mismatched_parts: Vec<u32>,
+    /// With `WriteVerify::Checksum`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<IntegrityCheck>,
+}
+
+impl ParallelDownload {
+    pub fn is_ok(&self) -> bool {
+        self.mismatched_parts.is_empty() && self.integrity.as_ref().map_or(true, |c| c.is_ok())
+    }
+}
+
+/// Splits an object of `size` bytes into `(offset, length)` ranges of
+/// `part_size` bytes, rounded up to a multiple of `alignment`; the last one
+/// takes what is left.
+pub fn download_ranges(size: u64, part_size: u64, alignment: Option<usize>) -> Vec<(u64, u64)> {
+    let align = alignment.unwrap_or(1).max(1) as u64;
+    let part_size = (part_size.max(1) + align - 1) / align * align;
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let length = part_size.min(size - offset);
+        ranges.push((offset, length));
+        offset += length;
+    }
+    ranges
+}
+
+/// What the ranges of a download are read from.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ObjectPin {
+    pub e_tag: Option<String>,
+    /// Set in versioned buckets, where it is used instead of the ETag.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<String>,
+}
+
+impl ObjectPin {
+    /// The `null` version of an unversioned or suspended bucket can be
+    /// overwritten, so it does not pin anything.
+    pub fn new(e_tag: Option<&str>, version_id: Option<&str>) -> Self {
+        Self {
+            e_tag: e_tag.map(str::to_string),
+            version_id: version_id
+                .filter(|version_id| *version_id != "null")
+                .map(str::to_string),
+        }
+    }
+}
+
+impl fmt::Display for ObjectPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.version_id, &self.e_tag) {
+            (Some(version_id), _) => write!(f, "version {}", version_id),
+            (None, Some(e_tag)) => write!(f, "ETag {}", e_tag),
+            (None, None) => write!(f, "no ETag"),
+        }
+    }
+}
+
+/// The object is no longer the one a download started with.
+#[derive(Debug)]
+pub struct ObjectChanged {
+    pub key: String,
+    pub pin: ObjectPin,
+}
+
+impl fmt::Display for ObjectChanged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} was overwritten during the download: it no longer has the {} it started with",
+            self.key, self.pin
+        )
+    }
+}
+
+impl std::error::Error for ObjectChanged {}
+
+/// Whether `err` is an `ObjectChanged`.
+pub fn is_object_changed(err: &Error) -> bool {
+    matches!(err, Error::Unhandled(inner) if inner.downcast_ref::<ObjectChanged>().is_some())
+}
+
+/// The progress of a download, kept next to its target with `resume`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DownloadState {
+    pub key: String,
+    pub size: u64,
+    pub part_size: u64,
+    pub pin: ObjectPin,
+    /// The SHA-256 of the parts written, by index.
+    pub completed: BTreeMap<usize, String>,
+}
+
+impl DownloadState {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        serde_json::from_str(&content).map_err(|err| {
+            Error::Unhandled(Box::from(format!(
+                "Invalid download state {}: {}",
+                path.display(),
+                err
+            )))
+        })
+    }
+
+    /// Writes the state through a temporary file renamed over `path`, so a
+    /// crash leaves either the previous state or this one.
+    pub async fn save(&self, path: &Path) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        let options = FsyncOptions {
+            enabled: true,
+            interval: None,
+        };
+        write_file(&mut content.as_bytes(), path, &options)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::Unhandled(Box::new(err)))
+    }
+
+    /// Drops the parts whose bytes in `target`, at their range of `ranges`,
+    /// no longer have the SHA-256 recorded, and returns their indexes.
+    pub async fn verify_completed(
+        &mut self,
+        target: &Path,
+        ranges: &[(u64, u64)],
+    ) -> Result<Vec<usize>, Error> {
+        let mut mismatched = Vec::new();
+        for (index, sha256) in &self.completed {
+            let matches = match ranges.get(*index) {
+                Some(&(offset, length)) => {
+                    let actual = sha256_window(target, SourceWindow { offset, length })
+                        .await
+                        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                    actual == *sha256
+                }
+                None => false,
+            };
+            if !matches {
+                mismatched.push(*index);
+            }
+        }
+        for index in &mismatched {
+            self.completed.remove(index);
+        }
+        Ok(mismatched)
+    }
+}
+
+/// Where the state of a download into `target` is kept: `<target>.download.json`.
+pub fn state_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(".download.json");
+    target.with_file_name(name)
+}
+
+/// Downloads `bucket/key` to `path` in ranges written in parallel.
+pub async fn download_parallel(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    options: &ParallelDownloadOptions,
+) -> Result<ParallelDownload, Error> {
+    download_parallel_with_events(client, bucket, key, path, options, &|_| {}).await
+}
+
+/// As `download_parallel`, calling `on_event` before the first range, as
+/// each range is written, and at the end. With `resume`, the ranges kept
+/// from an earlier run count as already stored.
+pub async fn download_parallel_with_events(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    options: &ParallelDownloadOptions,
+    on_event: &(dyn Fn(&ProgressEvent) + Sync),
+) -> Result<ParallelDownload, Error> {
+    if !options.truncate && !options.write_in_place {
+        return Err(Error::Unhandled(Box::from(
+            "Keeping the target without truncating it requires writing in place",
+        )));
+    }
+    if options.alignment == Some(0) {
+        return Err(Error::Unhandled(Box::from(
+            "The alignment must be positive",
+        )));
+    }
+    match download_pinned(client, bucket, key, path, options, on_event).await {
+        Err(err) if options.retry_on_change && is_object_changed(&err) => {
+            eprintln!("{}; downloading it again", err);
+            let mut result = download_pinned(client, bucket, key, path, options, on_event).await?;
+            result.restarted_on_change = true;
+            Ok(result)
+        }
+        result => result,
+    }
+}
+
+/// One download, pinned to the object found by its HeadObject, or to the
+/// one recorded in the state of an earlier run.
+async fn download_pinned(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    options: &ParallelDownloadOptions,
+    on_event: &(dyn Fn(&ProgressEvent) + Sync),
+) -> Result<ParallelDownload, Error> {
+    let head = client.head_object().bucket(bucket).key(key).send().await?;
+    let size = head.content_length().max(0) as u64;
+    let pin = ObjectPin::new(head.e_tag(), head.version_id());
+
+    let target_path = if options.write_in_place {
+        path.to_path_buf()
+    } else {
+        part_path(path)
+    };
+    let state_path = state_path(&target_path);
+    let ranges = download_ranges(size, options.part_size, options.alignment);
+    let earlier = if options.resume && state_path.exists() && target_path.exists() {
+        Some(DownloadState::load(&state_path)?)
+    } else {
+        None
+    };
+    let (state, target_size) = match earlier {
+        Some(mut state) if state.key == key && state.part_size == options.part_size => {
+            if state.pin != pin || state.size != size {
+                // The ranges kept are of another object.
+                let _ = std::fs::remove_file(&state_path);
+                if !options.write_in_place {
+                    let _ = std::fs::remove_file(&target_path);
+                }
+                return Err(Error::Unhandled(Box::new(ObjectChanged {
+                    key: key.to_string(),
+                    pin: state.pin,
+                })));
+            }
+            let target_size = prepare_target(&target_path, size, false).await?;
+            let mismatched = state.verify_completed(&target_path, &ranges).await?;
+            if !mismatched.is_empty() {
+                eprintln!(
+                    "{} of the {} ranges kept no longer match their SHA-256; downloading them again",
+                    mismatched.len(),
+                    mismatched.len() + state.completed.len()
+                );
+            }
+            (state, target_size.filter(|_| !options.truncate))
+        }
+        _ => {
+            let target_size = prepare_target(&target_path, size, options.truncate).await?;
+            let state = DownloadState {
+                key: key.to_string(),
+                size,
+                part_size: options.part_size,
+                pin: pin.clone(),
+                ..Default::default()
+            };
+            (state, target_size)
+        }
+    };
+    let resumed: BTreeMap<usize, String> = state.completed.clone();
+    let state = tokio::sync::Mutex::new(state);
+    // A range must be on disk before the state says it is.
+    let target = Arc::new(Target::new(
+        target_path.clone(),
+        options.alignment,
+        options.resume,
+        options.fsync.interval,
+    ));
+    let total = ranges.len();
+    let resumed_parts = resumed.len();
+    let resumed_bytes: u64 = resumed
+        .keys()
+        .filter_map(|index| ranges.get(*index))
+        .map(|(_, length)| length)
+        .sum();
+    let tracker = Mutex::new(ProgressTracker::new(size, resumed_bytes));
+    on_event(&tracker.lock().unwrap().started());
+
+    let pending = ranges
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(index, _)| !resumed.contains_key(index));
+    let written = stream::iter(pending)
+        .map(|(index, (offset, length))| {
+            let target = target.clone();
+            let pin = pin.clone();
+            let state = &state;
+            let state_path = &state_path;
+            let tracker = &tracker;
+            async move {
+                // Held until the range is written and its buffer dropped.
+                let _reservation = reserve(options.memory_budget.as_ref(), length).await;
+                let start = Instant::now();
+                let (data, request_id) =
+                    with_request_id(get_range(client, bucket, key, pin, offset, length)).await;
+                let data = data?;
+                let bytes = data.len() as u64;
+                let sha256 = format!("{:x}", Sha256::digest(&data));
+                tokio::task::spawn_blocking(move || target.write_at(offset, &data))
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                options.verbosity.report(
+                    "chunk",
+                    "downloaded",
+                    index + 1,
+                    total,
+                    length,
+                    start.elapsed(),
+                    request_id.as_deref(),
+                );
+                let event = tracker.lock().unwrap().part(index as i32 + 1, bytes);
+                on_event(&event);
+                if options.resume {
+                    let mut state = state.lock().await;
+                    state.completed.insert(index, sha256.clone());
+                    state.save(state_path).await?;
+                }
+                Ok::<_, Error>((index, sha256))
+            }
+        })
+        .buffer_unordered(options.concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await;
+    let written: BTreeMap<usize, String> = match written {
+        Ok(written) => written.into_iter().chain(resumed).collect(),
+        Err(err) => {
+            // A resumable download keeps its ranges, unless they are of an
+            // object that is gone.
+            if !options.resume || is_object_changed(&err) {
+                let _ = std::fs::remove_file(&state_path);
+                if !options.write_in_place {
+                    let _ = tokio::fs::remove_file(&target_path).await;
+                }
+            }
+            return Err(err);
+        }
+    };
+
+    // Every range writer is done, so this sync covers all of them.
+    target
+        .sync_all()
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let _ = std::fs::remove_file(&state_path);
+    let tracker = tracker.into_inner().unwrap();
+    on_event(&tracker.finished());
+
+    let mut result = ParallelDownload {
+        size,
+        target_size,
+        parts: ranges.len(),
+        resumed_parts,
+        totals: tracker.totals(),
+        direct_io: options.alignment.is_some() && target.direct.load(Ordering::SeqCst),
+        fallback: target.fallback.lock().unwrap().clone(),
+        fsync: *target.fsync.lock().unwrap(),
+        ..Default::default()
+    };
+    match options.verify {
+        WriteVerify::None => {}
+        WriteVerify::Sample(sample) => {
+            for index in sample_indices(ranges.len(), sample) {
+                let (offset, length) = ranges[index];
+                let part_number = index as u32 + 1;
+                let actual = sha256_window(&target_path, SourceWindow { offset, length })
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                result.sampled_parts.push(part_number);
+                if Some(&actual) != written.get(&index) {
+                    result.mismatched_parts.push(part_number);
+                }
+            }
+        }
+        WriteVerify::Checksum => {
+            let manifest = get_integrity_manifest(client, bucket, key).await?;
+            result.integrity = Some(
+                manifest
+                    .check_file(&target_path, SourceWindow::whole(size))
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?,
+            );
+        }
+    }
+
+    // A download that does not verify never takes the final name.
+    if !options.write_in_place {
+        if result.is_ok() {
+            tokio::fs::rename(&target_path, path)
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        } else {
+            let _ = tokio::fs::remove_file(&target_path).await;
+        }
+    }
+    if options.fsync.enabled && result.is_ok() {
+        let start = Instant::now();
+        sync_parent_dir(path)
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        result.fsync.record(start);
+    }
+    Ok(result)
+}
+
+/// Creates the target with the size of the object, or checks that the
+/// existing one can hold it. Returns the size of a kept target.
+async fn prepare_target(path: &Path, size: u64, truncate: bool) -> Result<Option<u64>, Error> {
+    if truncate {
+        let file = tokio::fs::File::create(path)
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        file.set_len(size)
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        return Ok(None);
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map_err(|err| {
+            Error::Unhandled(Box::from(format!(
+                "Cannot open {} to write in place: {}",
+                path.display(),
+                err
+            )))
+        })?;
+    // Block devices report a length of zero; their end tells their size.
+    let target_size = file
+        .seek(std::io::SeekFrom::End(0))
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    if target_size < size {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} holds {} bytes, less than the {} bytes of the object",
+            path.display(),
+            target_size,
+            size
+        ))));
+    }
+    Ok(Some(target_size))
+}
+
+/// Reads `length` bytes of `key` from `offset`, failing with
+/// `ObjectChanged` if the object is no longer the one of `pin`.
+pub(crate) async fn get_range(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    pin: ObjectPin,
+    offset: u64,
+    length: u64,
+) -> Result<Bytes, Error> {
+    let request = client.get_object().bucket(bucket).key(key).range(format!(
+        "bytes={}-{}",
+        offset,
+        offset + length - 1
+    ));
+    let request = match &pin.version_id {
+        Some(version_id) => request.version_id(version_id),
+        None => request.set_if_match(pin.e_tag.clone()),
+    };
+    let resp = match request.send().await {
+        Ok(resp) => resp,
+        Err(SdkError::ServiceError { raw, .. }) if raw.http().status().as_u16() == 412 => {
+            return Err(Error::Unhandled(Box::new(ObjectChanged {
+                key: key.to_string(),
+                pin,
+            })));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    // A server that ignores Range answers 200 with the whole object; the
+    // status is not exposed, but the length gives it away before the body
+    // is read.
+    if resp.content_length() as u64 > length {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} answered the range bytes={}-{} with {} bytes; the endpoint ignores Range requests",
+            key,
+            offset,
+            offset + length - 1,
+            resp.content_length()
+        ))));
+    }
+    let data = resp
+        .body
+        .collect()
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+        .into_bytes();
+    if data.len() as u64 != length {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} sent {} bytes from {} instead of {}",
+            key,
+            data.len(),
+            offset,
+            length
+        ))));
+    }
+    Ok(data)
+}
+
+/// The file the ranges are written into.
+struct Target {
+    path: PathBuf,
+    alignment: Option<usize>,
+    /// Sync each range once written.
+    sync: bool,
+    /// Sync once this many bytes were written since the last sync.
+    interval: Option<u64>,
+    unsynced: AtomicU64,
+    fsync: Mutex<FsyncStats>,
+    /// Cleared when an `O_DIRECT` write fails.
+    direct: AtomicBool,
+    fallback: Mutex<Option<String>>,
+}
+
+impl Target {
+    fn new(path: PathBuf, alignment: Option<usize>, sync: bool, interval: Option<u64>) -> Self {
+        Self {
+            path,
+            alignment,
+            sync,
+            interval,
+            unsynced: AtomicU64::new(0),
+            fsync: Mutex::new(FsyncStats::default()),
+            direct: AtomicBool::new(alignment.is_some()),
+            fallback: Mutex::new(None),
+        }
+    }
+
+    fn fall_back(&self, reason: String) {
+        if self.direct.swap(false, Ordering::SeqCst) {
+            tracing::warn!(
+                "{}: O_DIRECT writes failed, falling back to buffered writes: {}",
+                self.path.display(),
+                reason
+            );
+            *self.fallback.lock().unwrap() = Some(reason);
+        }
+    }
+
+    /// Writes `data` at `offset`, then syncs it if enabled or if `interval`
+    /// bytes were written since the last sync. Blocks.
+    fn write_at(&self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        self.write_unsynced(offset, data)?;
+        let length = data.len() as u64;
+        let due = self.interval.map_or(false, |interval| {
+            self.unsynced.fetch_add(length, Ordering::SeqCst) + length >= interval
+        });
+        if self.sync || due {
+            self.unsynced.store(0, Ordering::SeqCst);
+            let file = std::fs::OpenOptions::new().write(true).open(&self.path)?;
+            let start = Instant::now();
+            file.sync_data()?;
+            self.fsync.lock().unwrap().record(start);
+        }
+        Ok(())
+    }
+
+    /// Syncs the data and metadata of the target. Blocks.
+    fn sync_all(&self) -> std::io::Result<()> {
+        let file = std::fs::OpenOptions::new().write(true).open(&self.path)?;
+        let start = Instant::now();
+        file.sync_all()?;
+        self.fsync.lock().unwrap().record(start);
+        Ok(())
+    }
+
+    fn write_unsynced(&self, mut offset: u64, mut data: &[u8]) -> std::io::Result<()> {
+        if let Some(align) = self.alignment {
+            let direct_len = data.len() - data.len() % align;
+            if direct_len > 0 && self.direct.load(Ordering::SeqCst) {
+                match write_direct(&self.path, offset, &data[..direct_len], align) {
+                    Ok(()) => {
+                        offset += direct_len as u64;
+                        data = &data[direct_len..];
+                    }
+                    Err(err) => self.fall_back(err.to_string()),
+                }
+            }
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        let mut file = std::fs::OpenOptions::new().write(true).open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)
+    }
+}
+
+/// Writes `data`, a multiple of `align` bytes, at `offset` with `O_DIRECT`,
+/// from a buffer aligned in memory as well.
+#[cfg(target_os = "linux")]
+fn write_direct(path: &Path, offset: u64, data: &[u8], align: usize) -> std::io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if offset % align as u64 != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("offset {} is not aligned to {} bytes", offset, align),
+        ));
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)?;
+    let mut buffer = vec![0u8; data.len() + align];
+    let start = buffer.as_ptr().align_offset(align);
+    let aligned = &mut buffer[start..start + data.len()];
+    aligned.copy_from_slice(data);
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(aligned)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_direct(_path: &Path, _offset: u64, _data: &[u8], _align: usize) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "O_DIRECT is only supported on Linux",
+    ))
+}
@@ -0,0 +1,235 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Listing of a huge prefix in shards of its keyspace, listed at the same
+//! time.
+//!
+//! ListObjectsV2 returns at most 1,000 keys a page, and each page needs the
+//! continuation token of the previous one, so tens of millions of keys take
+//! hours to list one page after the other. Here the keys under the prefix
+//! are split into ranges, each listed from its lower bound with
+//! `start-after` until a key passes its upper bound. The bounds are the
+//! common prefixes of a first page listed with the `/` delimiter, when they
+//! all fit in it, or else characters following the prefix.
+//!
+//! The shards finish in any order, and their objects are merged by key, so
+//! the result has no order; the ranges do not overlap, so no key is listed
+//! twice.
+
+use crate::sync::{remote_object, RemoteObject};
+use aws_sdk_s3::{Client, Error};
+use futures::{stream, StreamExt};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The characters after the prefix that split the keyspace when the
+/// delimiter gives too few common prefixes, in the byte order of S3.
+pub const SHARD_CHARACTERS: &str =
+    "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz";
+
+/// The keys greater than `start_after` and not greater than `end`; a
+/// missing bound does not limit the range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyRange {
+    pub start_after: Option<String>,
+    pub end: Option<String>,
+}
+
+impl KeyRange {
+    pub fn contains(&self, key: &str) -> bool {
+        self.start_after
+            .as_deref()
+            .map_or(true, |start| key > start)
+            && self.end.as_deref().map_or(true, |end| key <= end)
+    }
+}
+
+/// Throughput of a listing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListStats {
+    pub keys: usize,
+    /// ListObjectsV2 requests, with the delimiter pass.
+    pub pages: usize,
+    pub shards: usize,
+    /// Keys listed by more than one shard. The ranges do not overlap, and
+    /// each shard drops the keys outside of its range, even from an
+    /// endpoint that ignores `start-after`, so this is 0.
+    pub duplicates: usize,
+    pub elapsed: Duration,
+}
+
+impl ListStats {
+    pub fn keys_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.keys as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The ranges between consecutive `boundaries`, which must be sorted; each
+/// boundary is the end of a range and the start of the next one.
+pub fn shard_ranges(boundaries: &[String]) -> Vec<KeyRange> {
+    let mut ranges = Vec::with_capacity(boundaries.len() + 1);
+    let mut start_after = None;
+    for boundary in boundaries {
+        ranges.push(KeyRange {
+            start_after: start_after.take(),
+            end: Some(boundary.clone()),
+        });
+        start_after = Some(boundary.clone());
+    }
+    ranges.push(KeyRange {
+        start_after,
+        end: None,
+    });
+    ranges
+}
+
+/// `shards - 1` boundaries spread evenly over `candidates`, sorted and
+/// without duplicates, or fewer if there are not enough candidates.
+pub fn pick_boundaries(candidates: &[String], shards: usize) -> Vec<String> {
+    let mut candidates = candidates.to_vec();
+    candidates.sort();
+    candidates.dedup();
+    let mut boundaries: Vec<String> = (1..shards.max(1))
+        .map(|i| i * candidates.len() / shards)
+        .filter(|index| *index < candidates.len())
+        .map(|index| candidates[index].clone())
+        .collect();
+    boundaries.dedup();
+    boundaries
+}
+
+/// Boundaries made of `prefix` and one of `SHARD_CHARACTERS`, for
+/// `shards` ranges.
+pub fn character_boundaries(prefix: &str, shards: usize) -> Vec<String> {
+    let candidates: Vec<String> = SHARD_CHARACTERS
+        .chars()
+        .map(|c| format!("{}{}", prefix, c))
+        .collect();
+    pick_boundaries(&candidates, shards)
+}
+
+/// The boundaries of `shards` ranges of the keys under `prefix`, from one
+/// page listed with the `/` delimiter.
+async fn discover_boundaries(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    shards: usize,
+) -> Result<Vec<String>, Error> {
+    let resp = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(prefix)
+        .delimiter("/")
+        .send()
+        .await?;
+    let common_prefixes: Vec<String> = resp
+        .common_prefixes()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|common| common.prefix().map(|p| p.to_string()))
+        .collect();
+    // A truncated page only has the first common prefixes, which would put
+    // most of the keys in the last shard.
+    if !resp.is_truncated() && common_prefixes.len() >= 2 {
+        Ok(pick_boundaries(&common_prefixes, shards))
+    } else {
+        Ok(character_boundaries(prefix, shards))
+    }
+}
+
+/// The objects under `prefix` in `range`, and the number of pages listed.
+async fn list_range(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    range: &KeyRange,
+) -> Result<(Vec<RemoteObject>, usize), Error> {
+    let mut objects = Vec::new();
+    let mut pages = 0;
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_start_after(range.start_after.clone())
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+        pages += 1;
+        let mut past_end = false;
+        for object in resp.contents().unwrap_or_default() {
+            let object = remote_object(object);
+            if range
+                .end
+                .as_deref()
+                .map_or(false, |end| object.key.as_str() > end)
+            {
+                past_end = true;
+                break;
+            }
+            if range.contains(&object.key) {
+                objects.push(object);
+            }
+        }
+        if past_end || !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+    Ok((objects, pages))
+}
+
+/// Lists the objects under `prefix`, keyed by object key, like
+/// `sync::list_remote`, with `shards` listings at the same time.
+pub async fn list_remote_parallel(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    shards: usize,
+) -> Result<(HashMap<String, RemoteObject>, ListStats), Error> {
+    let start = Instant::now();
+    let shards = shards.max(1);
+    let (boundaries, mut pages) = if shards > 1 {
+        (
+            discover_boundaries(client, bucket, prefix, shards).await?,
+            1,
+        )
+    } else {
+        (Vec::new(), 0)
+    };
+    let ranges = shard_ranges(&boundaries);
+    let results = stream::iter(ranges.iter())
+        .map(|range| list_range(client, bucket, prefix, range))
+        .buffer_unordered(shards)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut objects = HashMap::new();
+    let mut duplicates = 0;
+    for result in results {
+        let (listed, listed_pages) = result?;
+        pages += listed_pages;
+        for object in listed {
+            if objects.insert(object.key.clone(), object).is_some() {
+                duplicates += 1;
+            }
+        }
+    }
+    let stats = ListStats {
+        keys: objects.len(),
+        pages,
+        shards: ranges.len(),
+        duplicates,
+        elapsed: start.elapsed(),
+    };
+    Ok((objects, stats))
+}
@@ -0,0 +1,296 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! A record of every attempt to upload one part, to attach to a ticket.
+//!
+//! A `CapturePart` connector sits under the SDK's retries, like
+//! `SigningDebug`. It recognizes the UploadPart (and UploadPartCopy)
+//! requests of the chosen part number from their query, and records for each
+//! attempt the request line and headers, the response status and headers,
+//! and when the response headers and the last byte of the body arrived. The
+//! file is written again after each attempt, so it survives a run that is
+//! killed. Other requests go straight through: they are only checked for the
+//! part number in their query.
+//!
+//! Hyper reuses connections without telling its callers, so the time to the
+//! response headers includes connecting, when a connection was made, and
+//! sending the body.
+//!
+//! The Authorization header, the session token, the SSE-C keys, and the
+//! signature and credential of presigned queries are redacted.
+
+use crate::connect::https_adapter;
+use aws_sdk_s3::{Client, Error};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use hyper::service::Service;
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+const REDACTED: &str = "<redacted>";
+
+/// Headers whose values are secrets, lowercase.
+const REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "x-amz-security-token",
+    "x-amz-server-side-encryption-customer-key",
+    "x-amz-copy-source-server-side-encryption-customer-key",
+];
+
+/// Query parameters of presigned requests whose values are secrets.
+const REDACTED_QUERY: &[&str] = &[
+    "X-Amz-Signature",
+    "X-Amz-Credential",
+    "X-Amz-Security-Token",
+];
+
+/// One attempt to send the captured part.
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedAttempt {
+    pub attempt: usize,
+    /// When the request was handed to the connection, in RFC 3339.
+    pub started_at: String,
+    pub method: String,
+    /// The path and query.
+    pub uri: String,
+    pub request_headers: Vec<(String, String)>,
+    pub status: Option<u16>,
+    pub response_headers: Vec<(String, String)>,
+    /// Until the response headers, connecting and sending the body included.
+    pub first_byte_ms: Option<f64>,
+    pub last_byte_ms: Option<f64>,
+    /// The error body, or why no response was received.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Capture {
+    part_number: i32,
+    attempts: Vec<CapturedAttempt>,
+}
+
+/// The part to capture, the attempts recorded so far, and the file they are
+/// written to.
+#[derive(Debug, Clone)]
+pub struct PartCapture {
+    capture: Arc<Mutex<Capture>>,
+    path: Option<PathBuf>,
+}
+
+impl PartCapture {
+    /// Captures `part_number` to the JSON file `path`.
+    pub fn new(part_number: i32, path: &Path) -> Self {
+        Self {
+            path: Some(path.to_path_buf()),
+            ..Self::in_memory(part_number)
+        }
+    }
+
+    /// Keeps the attempts for `attempts` only, without writing them.
+    pub fn in_memory(part_number: i32) -> Self {
+        Self {
+            capture: Arc::new(Mutex::new(Capture {
+                part_number,
+                attempts: Vec::new(),
+            })),
+            path: None,
+        }
+    }
+
+    pub fn part_number(&self) -> i32 {
+        self.capture.lock().unwrap().part_number
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The attempts recorded, oldest first.
+    pub fn attempts(&self) -> Vec<CapturedAttempt> {
+        self.capture.lock().unwrap().attempts.clone()
+    }
+
+    /// The capture as it is written to the file.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&*self.capture.lock().unwrap()).unwrap()
+    }
+
+    /// Whether `uri` is an UploadPart or UploadPartCopy of the part.
+    fn matches(&self, uri: &http::Uri) -> bool {
+        let query = match uri.query() {
+            Some(query) => query,
+            None => return false,
+        };
+        let part_number = self.part_number().to_string();
+        let mut upload = false;
+        let mut part = false;
+        for pair in query.split('&') {
+            upload |= pair.starts_with("uploadId=");
+            part |= pair.strip_prefix("partNumber=") == Some(part_number.as_str());
+        }
+        upload && part
+    }
+
+    fn record(&self, mut attempt: CapturedAttempt) {
+        let json = {
+            let mut capture = self.capture.lock().unwrap();
+            attempt.attempt = capture.attempts.len() + 1;
+            capture.attempts.push(attempt);
+            serde_json::to_string_pretty(&*capture).unwrap()
+        };
+        if let Some(path) = &self.path {
+            if let Err(err) = std::fs::write(path, json) {
+                eprintln!("Cannot write the capture to {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// Writes the capture to its file now, even without attempts.
+    pub fn save(&self) -> Result<(), Error> {
+        match &self.path {
+            Some(path) => {
+                std::fs::write(path, self.to_json()).map_err(|err| Error::Unhandled(Box::new(err)))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// `headers` as name and value pairs, the secret ones redacted.
+pub fn redact_headers(headers: &http::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// The path and query of `uri`, the secret query parameters redacted.
+pub fn redact_uri(uri: &http::Uri) -> String {
+    let query = match uri.query() {
+        Some(query) => query,
+        None => return uri.path().to_string(),
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| {
+            let name = pair.split('=').next().unwrap_or_default();
+            if REDACTED_QUERY.contains(&name) {
+                format!("{}={}", name, REDACTED)
+            } else {
+                pair.to_string()
+            }
+        })
+        .collect();
+    format!("{}?{}", uri.path(), query.join("&"))
+}
+
+fn millis(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+/// A connector that records the attempts to send the part of `capture`.
+/// Without a capture, requests go through untouched.
+#[derive(Debug, Clone)]
+pub struct CapturePart<C> {
+    inner: C,
+    capture: Option<PartCapture>,
+}
+
+impl<C> CapturePart<C> {
+    pub fn new(inner: C, capture: Option<PartCapture>) -> Self {
+        Self { inner, capture }
+    }
+}
+
+impl<C, B, RB> Service<http::Request<B>> for CapturePart<C>
+where
+    C: Service<http::Request<B>, Response = http::Response<RB>> + Clone + Send + 'static,
+    C::Future: Send + 'static,
+    C::Error: fmt::Display,
+    B: Send + 'static,
+    RB: hyper::body::HttpBody + From<Bytes> + Send + 'static,
+    RB::Data: Send,
+    RB::Error: fmt::Display,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<C::Response, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        // As in `RateLimited`, the connector made ready serves this request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let capture = match &self.capture {
+            Some(capture) if capture.matches(request.uri()) => capture.clone(),
+            _ => return Box::pin(inner.call(request)),
+        };
+        let mut attempt = CapturedAttempt {
+            attempt: 0,
+            started_at: chrono::Utc::now().to_rfc3339(),
+            method: request.method().to_string(),
+            uri: redact_uri(request.uri()),
+            request_headers: redact_headers(request.headers()),
+            status: None,
+            response_headers: Vec::new(),
+            first_byte_ms: None,
+            last_byte_ms: None,
+            error: None,
+        };
+        Box::pin(async move {
+            let start = Instant::now();
+            let response = match inner.call(request).await {
+                Ok(response) => response,
+                Err(err) => {
+                    attempt.error = Some(err.to_string());
+                    capture.record(attempt);
+                    return Err(err);
+                }
+            };
+            attempt.first_byte_ms = Some(millis(start));
+            attempt.status = Some(response.status().as_u16());
+            attempt.response_headers = redact_headers(response.headers());
+            // UploadPart answers with headers only, so the body is read whole
+            // and handed on.
+            let failed = !response.status().is_success();
+            let (parts, body) = response.into_parts();
+            let bytes = match hyper::body::to_bytes(body).await {
+                Ok(bytes) => {
+                    if failed {
+                        attempt.error = Some(String::from_utf8_lossy(&bytes).into_owned());
+                    }
+                    bytes
+                }
+                Err(err) => {
+                    attempt.error = Some(format!("The body could not be read: {}", err));
+                    Bytes::new()
+                }
+            };
+            attempt.last_byte_ms = Some(millis(start));
+            capture.record(attempt);
+            Ok(http::Response::from_parts(parts, RB::from(bytes)))
+        })
+    }
+}
+
+/// Creates a client that records the attempts to send the part of `capture`.
+pub fn part_capture_client(config: aws_sdk_s3::Config, capture: &PartCapture) -> Client {
+    let adapter = https_adapter();
+    Client::from_conf_conn(config, CapturePart::new(adapter, Some(capture.clone())))
+}
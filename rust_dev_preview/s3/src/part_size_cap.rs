@@ -0,0 +1,209 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Part size caps of S3-compatible endpoints that reject parts well below
+//! the 5 GiB of Amazon S3 with `EntityTooLarge`.
+//!
+//! `upload_multipart_window_split` uploads a file in parts no larger than a
+//! known cap. When a part is rejected anyway, the upload is aborted, and a
+//! new one started in parts of at most half the largest part rejected,
+//! until the endpoint accepts them or the parts reach `MIN_PART_SIZE`. The
+//! SDK does not expose the maximum size an endpoint reports with the error,
+//! so the cap is found by halving; the caller keeps it, for example with
+//! `config::save_part_size_cap`, so that the next run plans within it from
+//! the start.
+//!
+//! The part numbers of an upload cannot be changed once parts are uploaded,
+//! so uploads whose caller chose the part numbers, such as the stages of
+//! `staged_upload`, fail with `part_too_large_error` instead.
+
+use crate::failover::EndpointPool;
+use crate::integrity::UploadDigests;
+use crate::upload::{
+    plan_upload, upload_multipart_window_with_digests, SourceWindow, UploadHeaders, UploadPlan,
+    UploadPlanOptions, UploadStrategy, MIN_PART_SIZE,
+};
+use crate::verbosity::VerbosityConfig;
+use aws_sdk_s3::Error;
+
+/// The error code of a part over the size an endpoint accepts.
+pub const ENTITY_TOO_LARGE: &str = "EntityTooLarge";
+
+/// Whether `err` is an `EntityTooLarge` rejection.
+pub fn is_entity_too_large(err: &Error) -> bool {
+    match err {
+        Error::Unhandled(inner) => {
+            inner
+                .downcast_ref::<aws_smithy_types::Error>()
+                .and_then(|err| err.code())
+                == Some(ENTITY_TOO_LARGE)
+        }
+        _ => false,
+    }
+}
+
+/// The cap to try after a part of `rejected` bytes was refused: half of it,
+/// but not below `MIN_PART_SIZE`. `None` once parts cannot get smaller.
+pub fn next_part_cap(rejected: u64) -> Option<u64> {
+    if rejected <= MIN_PART_SIZE {
+        None
+    } else {
+        Some((rejected / 2).max(MIN_PART_SIZE))
+    }
+}
+
+/// `plan_upload`, with more parts if needed so that none is over `cap`.
+///
+/// A cap below `MIN_PART_SIZE` is raised to it, as smaller parts would be
+/// rejected by Amazon S3; a single `PutObject` is not a part and is kept.
+pub fn plan_upload_capped(size: u64, options: &UploadPlanOptions, cap: Option<u64>) -> UploadPlan {
+    let plan = plan_upload(size, options);
+    let cap = match cap {
+        Some(cap) if plan.strategy == UploadStrategy::Multipart => cap.max(MIN_PART_SIZE),
+        _ => return plan,
+    };
+    if plan.last_part_size <= cap {
+        return plan;
+    }
+    let mut num_parts = ((size + cap - 1) / cap).max(plan.num_parts as u64) as usize;
+    loop {
+        let capped = plan_upload(
+            size,
+            &UploadPlanOptions {
+                num_parts: Some(num_parts),
+                ..options.clone()
+            },
+        );
+        // `plan_upload` stops adding parts at the part limits.
+        if capped.last_part_size <= cap || capped.num_parts < num_parts {
+            return capped;
+        }
+        num_parts += 1;
+    }
+}
+
+/// The error of a part of `part_size` bytes rejected as too large, `err`,
+/// in an upload whose part numbers cannot be changed.
+pub fn part_too_large_error(part_size: u64, err: Error) -> Error {
+    let hint = match next_part_cap(part_size) {
+        Some(cap) => format!(", such as {} bytes", cap),
+        None => String::new(),
+    };
+    Error::Unhandled(Box::from(format!(
+        "The endpoint rejected a part of {} bytes as too large. The part numbers of this \
+         upload are fixed, so its parts are not split automatically: upload this range \
+         again with a smaller --part-size{}. {}",
+        part_size, hint, err
+    )))
+}
+
+/// Outcome of `upload_multipart_window_split`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitUpload {
+    /// Without quotes.
+    pub e_tag: String,
+    /// The layout of the upload that completed.
+    pub plan: UploadPlan,
+    /// The cap the upload completed under, lower than the one given after
+    /// a rejection.
+    pub cap: Option<u64>,
+    /// The uploads aborted after a rejection.
+    pub replans: u32,
+}
+
+/// Uploads `window` of `file_name` in parts laid out by `plan_upload_capped`
+/// under `cap`, starting again with smaller parts each time the endpoint
+/// rejects one with `EntityTooLarge`; see the module documentation.
+///
+/// The window must be large enough for a multipart upload by `options`.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_multipart_window_split(
+    endpoints: &EndpointPool,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    window: SourceWindow,
+    options: &UploadPlanOptions,
+    cap: Option<u64>,
+    headers: Option<UploadHeaders>,
+    verbosity: VerbosityConfig,
+) -> Result<SplitUpload, Error> {
+    upload_multipart_window_split_with_digests(
+        endpoints, bucket, key, file_name, window, options, cap, headers, verbosity, None,
+    )
+    .await
+}
+
+/// Same as `upload_multipart_window_split`, hashing the bytes of each part
+/// into `digests` as they are sent. An upload started again in smaller
+/// parts replaces the parts read before.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_multipart_window_split_with_digests(
+    endpoints: &EndpointPool,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    window: SourceWindow,
+    options: &UploadPlanOptions,
+    cap: Option<u64>,
+    headers: Option<UploadHeaders>,
+    verbosity: VerbosityConfig,
+    digests: Option<&UploadDigests>,
+) -> Result<SplitUpload, Error> {
+    let mut cap = cap;
+    let mut replans = 0;
+    loop {
+        let plan = plan_upload_capped(window.length, options, cap);
+        let result = upload_multipart_window_with_digests(
+            endpoints,
+            bucket,
+            key,
+            file_name,
+            window,
+            plan.num_parts,
+            None,
+            headers.clone(),
+            verbosity,
+            digests,
+        )
+        .await;
+        let err = match result {
+            Ok(e_tag) => {
+                return Ok(SplitUpload {
+                    e_tag,
+                    plan,
+                    cap,
+                    replans,
+                })
+            }
+            Err(err) if is_entity_too_large(&err) => err,
+            Err(err) => return Err(err),
+        };
+        // Halving can stop making progress once the parts near
+        // `MIN_PART_SIZE`, where `plan_upload` keeps a larger last part.
+        let next =
+            next_part_cap(plan.last_part_size).filter(|next| cap.map_or(true, |cap| *next < cap));
+        let next = match next {
+            Some(next) => next,
+            None => {
+                return Err(Error::Unhandled(Box::from(format!(
+                    "The endpoint rejected parts of {} bytes as too large, and parts cannot be \
+                     smaller than {} bytes: {}",
+                    plan.last_part_size, MIN_PART_SIZE, err
+                ))))
+            }
+        };
+        eprintln!(
+            "{} rejected parts of up to {} bytes as too large; uploading {} again in parts of \
+             at most {} bytes",
+            endpoints.current_endpoint(),
+            plan.last_part_size,
+            key,
+            next
+        );
+        cap = Some(next);
+        replans += 1;
+    }
+}
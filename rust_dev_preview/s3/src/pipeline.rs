@@ -0,0 +1,434 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! A fan-out pipeline processing uploaded objects: the producer announces
+//! each upload on an Amazon SNS topic (see `notify`), an Amazon SQS queue
+//! subscribed to the topic keeps the notifications, and consumers receive
+//! them from the queue, download each object, and compute its SHA-256.
+//!
+//! `setup_pipeline` creates the topic, the queue, the queue policy letting
+//! the topic send to it, and the subscription, once. The subscription uses
+//! raw message delivery, so that the body of each message is the
+//! `UploadPayload` itself; `parse_notification` also reads the SNS envelope
+//! of a subscription without it.
+//!
+//! A received message is hidden from other consumers for the visibility
+//! timeout. `consume_queue` extends it while the object is processed, and
+//! deletes the message once done. A message whose processing failed is left
+//! in the queue, to be received again after the timeout; give the queue a
+//! redrive policy to move such messages to a dead-letter queue after a few
+//! receives. A message that could not be deleted is also received again, and
+//! its object processed a second time.
+
+use crate::cli::parse_duration;
+use crate::notify::UploadPayload;
+use aws_sdk_s3::{Client, Error};
+use aws_sdk_sqs::model::{Message, QueueAttributeName};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+/// The visibility timeout of the queue created by `setup_pipeline`.
+pub const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The shortest visibility timeout: `consume_queue` extends it every half
+/// of it, and SQS takes whole seconds.
+pub const MIN_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The longest visibility timeout SQS allows.
+pub const MAX_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(12 * 3600);
+
+/// The most messages one ReceiveMessage returns.
+pub const MAX_RECEIVE_MESSAGES: i32 = 10;
+
+/// How long ReceiveMessage waits for a message to arrive, the longest SQS
+/// allows, so that an idle consumer sends few requests.
+pub const RECEIVE_WAIT_TIME: Duration = Duration::from_secs(20);
+
+/// What `setup_pipeline` created.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PipelineResources {
+    pub topic_arn: String,
+    pub queue_url: String,
+    pub queue_arn: String,
+    pub subscription_arn: String,
+}
+
+/// The access policy of `queue_arn` letting the SNS topic `topic_arn`, and
+/// only it, send messages to the queue.
+pub fn queue_policy(queue_arn: &str, topic_arn: &str) -> String {
+    serde_json::json!({
+        "Version": "2012-10-17",
+        "Statement": [{
+            "Sid": "AllowUploadNotifications",
+            "Effect": "Allow",
+            "Principal": { "Service": "sns.amazonaws.com" },
+            "Action": "sqs:SendMessage",
+            "Resource": queue_arn,
+            "Condition": { "ArnEquals": { "aws:SourceArn": topic_arn } }
+        }]
+    })
+    .to_string()
+}
+
+/// Checks that `timeout` is between `MIN_VISIBILITY_TIMEOUT` and
+/// `MAX_VISIBILITY_TIMEOUT`.
+pub fn check_visibility_timeout(timeout: Duration) -> Result<(), String> {
+    if timeout < MIN_VISIBILITY_TIMEOUT || timeout > MAX_VISIBILITY_TIMEOUT {
+        return Err(format!(
+            "The visibility timeout must be between {} s and {} s, not {} s",
+            MIN_VISIBILITY_TIMEOUT.as_secs(),
+            MAX_VISIBILITY_TIMEOUT.as_secs(),
+            timeout.as_secs_f64()
+        ));
+    }
+    Ok(())
+}
+
+/// Parses a visibility timeout as `parse_duration` does, and checks it with
+/// `check_visibility_timeout`.
+pub fn parse_visibility_timeout(value: &str) -> Result<Duration, String> {
+    let timeout = parse_duration(value)?;
+    check_visibility_timeout(timeout)?;
+    Ok(timeout)
+}
+
+fn sns_error(err: impl Into<aws_sdk_sns::Error>) -> Error {
+    Error::Unhandled(Box::new(err.into()))
+}
+
+fn sqs_error(err: impl Into<aws_sdk_sqs::Error>) -> Error {
+    Error::Unhandled(Box::new(err.into()))
+}
+
+/// Creates the topic `topic_name` and the queue `queue_name`, with a
+/// visibility timeout of `visibility_timeout`, and subscribes the queue to
+/// the topic with raw message delivery.
+///
+/// CreateTopic, CreateQueue, and Subscribe return the existing resource when
+/// called again with the same settings, so running it twice is harmless.
+pub async fn setup_pipeline(
+    sns_client: &aws_sdk_sns::Client,
+    sqs_client: &aws_sdk_sqs::Client,
+    topic_name: &str,
+    queue_name: &str,
+    visibility_timeout: Duration,
+) -> Result<PipelineResources, Error> {
+    check_visibility_timeout(visibility_timeout).map_err(|err| Error::Unhandled(Box::from(err)))?;
+    let topic = sns_client
+        .create_topic()
+        .name(topic_name)
+        .send()
+        .await
+        .map_err(sns_error)?;
+    let topic_arn = topic.topic_arn().unwrap_or_default().to_string();
+
+    let queue = sqs_client
+        .create_queue()
+        .queue_name(queue_name)
+        .attributes(
+            QueueAttributeName::VisibilityTimeout,
+            visibility_timeout.as_secs().to_string(),
+        )
+        .send()
+        .await
+        .map_err(sqs_error)?;
+    let queue_url = queue.queue_url().unwrap_or_default().to_string();
+    let attributes = sqs_client
+        .get_queue_attributes()
+        .queue_url(&queue_url)
+        .attribute_names(QueueAttributeName::QueueArn)
+        .send()
+        .await
+        .map_err(sqs_error)?;
+    let queue_arn = attributes
+        .attributes()
+        .and_then(|attributes| attributes.get(&QueueAttributeName::QueueArn))
+        .cloned()
+        .ok_or_else(|| Error::Unhandled(Box::from("GetQueueAttributes returned no QueueArn")))?;
+
+    sqs_client
+        .set_queue_attributes()
+        .queue_url(&queue_url)
+        .attributes(
+            QueueAttributeName::Policy,
+            queue_policy(&queue_arn, &topic_arn),
+        )
+        .send()
+        .await
+        .map_err(sqs_error)?;
+    let subscription = sns_client
+        .subscribe()
+        .topic_arn(&topic_arn)
+        .protocol("sqs")
+        .endpoint(&queue_arn)
+        .attributes("RawMessageDelivery", "true")
+        .return_subscription_arn(true)
+        .send()
+        .await
+        .map_err(sns_error)?;
+    Ok(PipelineResources {
+        topic_arn,
+        queue_url,
+        queue_arn,
+        subscription_arn: subscription
+            .subscription_arn()
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+/// The envelope SNS wraps a message in without raw message delivery.
+#[derive(Deserialize)]
+struct SnsEnvelope {
+    #[serde(rename = "Type")]
+    kind: String,
+    #[serde(rename = "Message")]
+    message: String,
+}
+
+/// Reads the upload announced by the body of a queue message, delivered raw
+/// or in its SNS envelope.
+pub fn parse_notification(body: &str) -> Result<UploadPayload, String> {
+    if let Ok(payload) = serde_json::from_str::<UploadPayload>(body) {
+        return Ok(payload);
+    }
+    let envelope: SnsEnvelope =
+        serde_json::from_str(body).map_err(|err| format!("Not an upload notification: {}", err))?;
+    if envelope.kind != "Notification" {
+        return Err(format!("Not an upload notification: {}", envelope.kind));
+    }
+    serde_json::from_str(&envelope.message)
+        .map_err(|err| format!("Not an upload notification: {}", err))
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsumerOptions {
+    /// How long a received message stays hidden, and how much it is extended
+    /// by while its object is processed.
+    pub visibility_timeout: Duration,
+    /// Messages received at a time, and processed concurrently; at most
+    /// `MAX_RECEIVE_MESSAGES`.
+    pub max_messages: i32,
+    pub wait_time: Duration,
+    /// Stop after this many receives in a row found the queue empty. Runs
+    /// until interrupted if not supplied.
+    pub max_empty_receives: Option<u32>,
+}
+
+impl Default for ConsumerOptions {
+    fn default() -> Self {
+        Self {
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            max_messages: MAX_RECEIVE_MESSAGES,
+            wait_time: RECEIVE_WAIT_TIME,
+            max_empty_receives: None,
+        }
+    }
+}
+
+/// An object processed by `consume_queue`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcessedObject {
+    pub message_id: String,
+    pub bucket: String,
+    pub key: String,
+    /// The bytes downloaded.
+    pub size: u64,
+    pub sha256: String,
+    /// The visibility timeout extensions the message needed.
+    pub extensions: u32,
+}
+
+/// Outcome of `consume_queue`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ConsumerSummary {
+    pub received: usize,
+    pub processed: Vec<ProcessedObject>,
+    /// IDs of the messages deleted without processing, as they announce no
+    /// upload.
+    pub skipped: Vec<String>,
+    /// IDs of the messages left in the queue after their processing failed,
+    /// with the error.
+    pub failed: Vec<(String, String)>,
+    /// IDs of the messages processed or skipped that could not be deleted,
+    /// with the error. They are received again after the timeout.
+    pub undeleted: Vec<(String, String)>,
+}
+
+/// Downloads the object at `key` and returns its size and SHA-256.
+pub async fn sha256_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<(u64, String), Error> {
+    let resp = client.get_object().bucket(bucket).key(key).send().await?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut body = resp.body;
+    while let Some(chunk) = body
+        .try_next()
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+    {
+        hasher.update(&chunk);
+        size += chunk.len() as u64;
+    }
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+/// Extends the visibility of the message of `receipt_handle` by
+/// `options.visibility_timeout` every half of it, until dropped.
+async fn keep_hidden(
+    sqs_client: &aws_sdk_sqs::Client,
+    queue_url: &str,
+    receipt_handle: &str,
+    options: &ConsumerOptions,
+    extensions: &mut u32,
+) {
+    loop {
+        tokio::time::sleep(options.visibility_timeout / 2).await;
+        let extended = sqs_client
+            .change_message_visibility()
+            .queue_url(queue_url)
+            .receipt_handle(receipt_handle)
+            .visibility_timeout(options.visibility_timeout.as_secs() as i32)
+            .send()
+            .await;
+        match extended {
+            Ok(_) => *extensions += 1,
+            // The message may be received again by another consumer; the
+            // processing goes on, as it is idempotent.
+            Err(err) => eprintln!("Could not extend the visibility of a message: {}", err),
+        }
+    }
+}
+
+enum Outcome {
+    Processed(ProcessedObject),
+    Skipped(String),
+    Failed(String, String),
+}
+
+/// Processes one message and deletes it, unless its processing failed.
+/// Returns the outcome, and the message ID with the error of the
+/// DeleteMessage if it failed.
+async fn handle_message(
+    client: &Client,
+    sqs_client: &aws_sdk_sqs::Client,
+    queue_url: &str,
+    message: Message,
+    options: &ConsumerOptions,
+) -> (Outcome, Option<(String, String)>) {
+    let message_id = message.message_id().unwrap_or_default().to_string();
+    let receipt_handle = message.receipt_handle().unwrap_or_default().to_string();
+    let payload = parse_notification(message.body().unwrap_or_default());
+    let outcome = match payload {
+        Err(err) => {
+            eprintln!("Deleting message {}: {}", message_id, err);
+            Outcome::Skipped(message_id.clone())
+        }
+        Ok(payload) => {
+            let mut extensions = 0;
+            let processed = {
+                let heartbeat = keep_hidden(
+                    sqs_client,
+                    queue_url,
+                    &receipt_handle,
+                    options,
+                    &mut extensions,
+                );
+                tokio::pin!(heartbeat);
+                tokio::select! {
+                    _ = &mut heartbeat => unreachable!(),
+                    processed = sha256_object(client, &payload.bucket, &payload.key) => processed,
+                }
+            };
+            match processed {
+                Ok((size, sha256)) => Outcome::Processed(ProcessedObject {
+                    message_id: message_id.clone(),
+                    bucket: payload.bucket,
+                    key: payload.key,
+                    size,
+                    sha256,
+                    extensions,
+                }),
+                Err(err) => return (Outcome::Failed(message_id, err.to_string()), None),
+            }
+        }
+    };
+    let deleted = sqs_client
+        .delete_message()
+        .queue_url(queue_url)
+        .receipt_handle(&receipt_handle)
+        .send()
+        .await;
+    let undeleted = deleted
+        .err()
+        .map(|err| (message_id, sqs_error(err).to_string()));
+    (outcome, undeleted)
+}
+
+/// Receives the notifications of `queue_url` and processes the object of
+/// each one, calling `on_processed` as each object is done; see the module
+/// documentation.
+pub async fn consume_queue(
+    client: &Client,
+    sqs_client: &aws_sdk_sqs::Client,
+    queue_url: &str,
+    options: &ConsumerOptions,
+    mut on_processed: impl FnMut(&ProcessedObject),
+) -> Result<ConsumerSummary, Error> {
+    check_visibility_timeout(options.visibility_timeout)
+        .map_err(|err| Error::Unhandled(Box::from(err)))?;
+    let mut summary = ConsumerSummary::default();
+    let mut empty_receives = 0;
+    loop {
+        let received = sqs_client
+            .receive_message()
+            .queue_url(queue_url)
+            .max_number_of_messages(options.max_messages)
+            .wait_time_seconds(options.wait_time.as_secs() as i32)
+            .visibility_timeout(options.visibility_timeout.as_secs() as i32)
+            .send()
+            .await
+            .map_err(sqs_error)?;
+        let messages = received.messages().unwrap_or_default().to_vec();
+        if messages.is_empty() {
+            empty_receives += 1;
+            if options
+                .max_empty_receives
+                .map_or(false, |max| empty_receives >= max)
+            {
+                return Ok(summary);
+            }
+            continue;
+        }
+        empty_receives = 0;
+        summary.received += messages.len();
+
+        let mut outcomes = stream::iter(messages)
+            .map(|message| handle_message(client, sqs_client, queue_url, message, options))
+            .buffer_unordered(options.max_messages.max(1) as usize);
+        while let Some((outcome, undeleted)) = outcomes.next().await {
+            if let Some((message_id, err)) = undeleted {
+                eprintln!("Could not delete message {}: {}", message_id, err);
+                summary.undeleted.push((message_id, err));
+            }
+            match outcome {
+                Outcome::Processed(object) => {
+                    on_processed(&object);
+                    summary.processed.push(object);
+                }
+                Outcome::Skipped(message_id) => summary.skipped.push(message_id),
+                Outcome::Failed(message_id, err) => {
+                    eprintln!("Failed to process message {}: {}", message_id, err);
+                    summary.failed.push((message_id, err));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Permission checks run before a long transfer.
+//!
+//! A few cheap requests exercise the operations an upload needs, so missing
+//! permissions are reported before any data moves instead of hours into the
+//! transfer.
+
+use crate::ops::{OpError, S3Ops};
+use serde::Serialize;
+
+/// Size from which `PreflightMode::Auto` runs the checks.
+pub const DEFAULT_PREFLIGHT_THRESHOLD: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PreflightMode {
+    Off,
+    Always,
+    /// Only for transfers of at least the threshold.
+    Auto,
+}
+
+impl std::str::FromStr for PreflightMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "off" => Ok(PreflightMode::Off),
+            "on" | "always" => Ok(PreflightMode::Always),
+            "auto" => Ok(PreflightMode::Auto),
+            other => Err(format!("Unknown preflight mode: {}", other)),
+        }
+    }
+}
+
+impl PreflightMode {
+    /// Whether to run the checks before transferring `size` bytes.
+    pub fn should_run(self, size: u64, threshold: u64) -> bool {
+        match self {
+            PreflightMode::Off => false,
+            PreflightMode::Always => true,
+            PreflightMode::Auto => size >= threshold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PreflightOptions {
+    /// Run the multipart checks on `<key>.preflight` instead of the target
+    /// key.
+    pub sibling_key: bool,
+    /// Also write and delete a 1-byte object.
+    pub put_object: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckResult {
+    pub operation: String,
+    pub passed: bool,
+    /// The IAM permission that is probably missing.
+    pub missing_permission: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PreflightReport {
+    pub passed: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl PreflightReport {
+    /// One line per failed check, for error messages.
+    pub fn failures(&self) -> Vec<String> {
+        self.checks
+            .iter()
+            .filter(|c| !c.passed)
+            .map(|c| match &c.missing_permission {
+                Some(permission) => format!("{}: missing {}", c.operation, permission),
+                None => format!(
+                    "{}: {}",
+                    c.operation,
+                    c.error.as_deref().unwrap_or_default()
+                ),
+            })
+            .collect()
+    }
+}
+
+/// The permission an operation needs, reported when it is denied.
+pub fn permission_for(operation: &str) -> Option<&'static str> {
+    match operation {
+        "HeadBucket" => Some("s3:ListBucket"),
+        "CreateMultipartUpload" | "PutObject" => Some("s3:PutObject"),
+        "AbortMultipartUpload" => Some("s3:AbortMultipartUpload"),
+        "DeleteObject" => Some("s3:DeleteObject"),
+        _ => None,
+    }
+}
+
+fn record(checks: &mut Vec<CheckResult>, operation: &str, result: &Result<(), OpError>) -> bool {
+    let (missing_permission, error) = match result {
+        Ok(()) => (None, None),
+        Err(err) => {
+            let denied = err.status == Some(403);
+            (
+                permission_for(operation)
+                    .filter(|_| denied)
+                    .map(|p| p.to_string()),
+                Some(err.to_string()),
+            )
+        }
+    };
+    checks.push(CheckResult {
+        operation: operation.to_string(),
+        passed: result.is_ok(),
+        missing_permission,
+        error,
+    });
+    result.is_ok()
+}
+
+/// Checks that an upload to `bucket/key` is permitted: HeadBucket, then a
+/// multipart upload that is immediately aborted, then optionally a 1-byte
+/// object that is immediately deleted.
+///
+/// Each step only runs if the previous one passed, since it would fail the
+/// same way or leave something behind.
+pub async fn run_preflight<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    key: &str,
+    options: &PreflightOptions,
+) -> PreflightReport {
+    let mut checks = Vec::new();
+    let check_key = if options.sibling_key {
+        format!("{}.preflight", key)
+    } else {
+        key.to_string()
+    };
+
+    let mut passed = record(&mut checks, "HeadBucket", &ops.head_bucket(bucket).await);
+    if passed {
+        let created = ops.create_multipart_upload(bucket, &check_key).await;
+        passed = record(
+            &mut checks,
+            "CreateMultipartUpload",
+            &created.as_ref().map(|_| ()).map_err(|e| e.clone()),
+        );
+        if let Ok(upload_id) = created {
+            let aborted = ops
+                .abort_multipart_upload(bucket, &check_key, &upload_id)
+                .await;
+            if aborted.is_err() {
+                eprintln!(
+                    "Preflight upload {} of {} could not be aborted and must be cleaned up",
+                    upload_id, check_key
+                );
+            }
+            passed = record(&mut checks, "AbortMultipartUpload", &aborted);
+        }
+    }
+    if passed && options.put_object {
+        let put_key = format!("{}.preflight", key);
+        let put = ops.put_object(bucket, &put_key, vec![0]).await.map(|_| ());
+        passed = record(&mut checks, "PutObject", &put);
+        if passed {
+            let deleted = ops.delete_object(bucket, &put_key).await;
+            passed = record(&mut checks, "DeleteObject", &deleted);
+        }
+    }
+
+    PreflightReport { passed, checks }
+}
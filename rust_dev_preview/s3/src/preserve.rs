@@ -0,0 +1,265 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! POSIX file metadata stored as object metadata, for backups that restore
+//! more than the content of the files.
+//!
+//! The mode, the modification time, the owner, and the target of symbolic
+//! links are recorded in `x-amz-meta-file-*` entries. A symbolic link is
+//! stored as an empty object whose metadata holds its target.
+//!
+//! When restoring, the mode and the modification time are applied after the
+//! file is written; the owner only by root and only when asked for, as
+//! `tar --numeric-owner` does, since the IDs mean nothing on another host.
+//! Windows has no mode, owner, or unprivileged symbolic links: only the
+//! modification time and the read-only flag are applied there, and a
+//! warning names what was not.
+
+use crate::sync::{format_mtime, parse_mtime};
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, CONTROLS};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Metadata key of the permission bits, in octal.
+pub const MODE_METADATA: &str = "file-mode";
+/// Metadata key of the modification time, formatted as `source-mtime` is.
+pub const MTIME_METADATA: &str = "file-mtime";
+pub const UID_METADATA: &str = "file-uid";
+pub const GID_METADATA: &str = "file-gid";
+/// Metadata key of the target of a symbolic link, percent-encoded.
+pub const SYMLINK_TARGET_METADATA: &str = "file-symlink-target";
+
+/// Metadata values must be printable ASCII; the other bytes of a link target
+/// are percent-encoded.
+const TARGET_ENCODE_SET: &AsciiSet = &CONTROLS.add(b'%');
+
+/// The metadata of a local file that is preserved.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileMetadata {
+    /// The permission bits, including setuid, setgid, and sticky.
+    pub mode: Option<u32>,
+    pub mtime: Option<SystemTime>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Set for symbolic links, which are not followed.
+    pub symlink_target: Option<PathBuf>,
+}
+
+impl FileMetadata {
+    /// Reads the metadata of `path`, without following a symbolic link.
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        let metadata = std::fs::symlink_metadata(path)?;
+        let is_symlink = metadata.file_type().is_symlink();
+        let symlink_target = if is_symlink {
+            Some(std::fs::read_link(path)?)
+        } else {
+            None
+        };
+        #[cfg(unix)]
+        let (mode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            // The mode of a link is meaningless.
+            let mode = (!is_symlink).then(|| metadata.mode() & 0o7777);
+            (mode, Some(metadata.uid()), Some(metadata.gid()))
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (None, None, None);
+        Ok(Self {
+            mode,
+            mtime: Some(metadata.modified()?),
+            uid,
+            gid,
+            symlink_target,
+        })
+    }
+
+    /// The object metadata recording this, without the `x-amz-meta-` prefix.
+    pub fn to_metadata(&self) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        if let Some(mode) = self.mode {
+            metadata.insert(MODE_METADATA.to_string(), format!("{:o}", mode));
+        }
+        if let Some(mtime) = self.mtime {
+            metadata.insert(MTIME_METADATA.to_string(), format_mtime(mtime));
+        }
+        if let Some(uid) = self.uid {
+            metadata.insert(UID_METADATA.to_string(), uid.to_string());
+        }
+        if let Some(gid) = self.gid {
+            metadata.insert(GID_METADATA.to_string(), gid.to_string());
+        }
+        if let Some(target) = &self.symlink_target {
+            metadata.insert(SYMLINK_TARGET_METADATA.to_string(), encode_target(target));
+        }
+        metadata
+    }
+
+    /// Reads the entries written by `to_metadata`, ignoring invalid ones.
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Self {
+        Self {
+            mode: metadata
+                .get(MODE_METADATA)
+                .and_then(|v| u32::from_str_radix(v, 8).ok()),
+            mtime: metadata.get(MTIME_METADATA).and_then(|v| parse_mtime(v)),
+            uid: metadata.get(UID_METADATA).and_then(|v| v.parse().ok()),
+            gid: metadata.get(GID_METADATA).and_then(|v| v.parse().ok()),
+            symlink_target: metadata
+                .get(SYMLINK_TARGET_METADATA)
+                .map(|v| decode_target(v)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn encode_target(target: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    percent_encode(target.as_os_str().as_bytes(), TARGET_ENCODE_SET).to_string()
+}
+
+#[cfg(not(unix))]
+fn encode_target(target: &Path) -> String {
+    percent_encode(target.to_string_lossy().as_bytes(), TARGET_ENCODE_SET).to_string()
+}
+
+#[cfg(unix)]
+fn decode_target(value: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(
+        percent_decode_str(value).collect(),
+    ))
+}
+
+#[cfg(not(unix))]
+fn decode_target(value: &str) -> PathBuf {
+    PathBuf::from(percent_decode_str(value).decode_utf8_lossy().into_owned())
+}
+
+/// How the preserved metadata is restored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RestoreOptions {
+    /// Restore the owner by its numeric IDs. Only root can.
+    pub numeric_owner: bool,
+}
+
+/// Applies `metadata` to `path`, which is not followed if it is a symbolic
+/// link. Returns warnings about what could not be applied; they do not name
+/// `path`, so that a caller can report each one once.
+pub fn apply(
+    path: &Path,
+    metadata: &FileMetadata,
+    options: &RestoreOptions,
+) -> std::io::Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    let has_owner = metadata.uid.is_some() || metadata.gid.is_some();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // The owner goes first: chown clears the setuid and setgid bits.
+        if options.numeric_owner && has_owner {
+            if unsafe { libc::geteuid() } == 0 {
+                lchown(path, metadata.uid, metadata.gid)?;
+            } else {
+                warnings.push("not running as root: the owners were not restored".to_string());
+            }
+        }
+        if metadata.symlink_target.is_none() {
+            if let Some(mode) = metadata.mode {
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+            }
+        }
+        if let Some(mtime) = metadata.mtime {
+            set_mtime_no_follow(path, mtime)?;
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        // The file must still be writable to set its time.
+        if let (Some(mtime), None) = (metadata.mtime, &metadata.symlink_target) {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(path)?
+                .set_modified(mtime)?;
+        }
+        if let Some(mode) = metadata.mode {
+            let mut permissions = std::fs::metadata(path)?.permissions();
+            permissions.set_readonly(mode & 0o222 == 0);
+            std::fs::set_permissions(path, permissions)?;
+            warnings.push(
+                "file modes were only applied as the read-only flag on this platform".to_string(),
+            );
+        }
+        if options.numeric_owner && has_owner {
+            warnings.push("owners cannot be restored on this platform".to_string());
+        }
+    }
+    Ok(warnings)
+}
+
+/// Creates `path` as a symbolic link to `target`.
+#[cfg(unix)]
+pub fn create_symlink(target: &Path, path: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Creates `path` as a symbolic link to `target`.
+#[cfg(not(unix))]
+pub fn create_symlink(_target: &Path, _path: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "symbolic links cannot be restored on this platform",
+    ))
+}
+
+#[cfg(unix)]
+fn c_path(path: &Path) -> std::io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))
+}
+
+#[cfg(unix)]
+fn lchown(path: &Path, uid: Option<u32>, gid: Option<u32>) -> std::io::Result<()> {
+    let path = c_path(path)?;
+    // -1 leaves the ID unchanged.
+    let uid = uid.unwrap_or(u32::MAX) as libc::uid_t;
+    let gid = gid.unwrap_or(u32::MAX) as libc::gid_t;
+    if unsafe { libc::lchown(path.as_ptr(), uid, gid) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Sets the modification time of `path`, or of the link itself if it is a
+/// symbolic link, leaving its access time unchanged.
+#[cfg(unix)]
+fn set_mtime_no_follow(path: &Path, mtime: SystemTime) -> std::io::Result<()> {
+    let since_epoch = mtime
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: since_epoch.as_secs() as libc::time_t,
+            tv_nsec: since_epoch.subsec_nanos() as _,
+        },
+    ];
+    let path = c_path(path)?;
+    let result = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
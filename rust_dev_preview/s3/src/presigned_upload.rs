@@ -0,0 +1,119 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Uploads through a presigned PUT URL, on nodes without AWS credentials.
+//!
+//! When the credentials live on a separate authorization service, that
+//! service presigns a PutObject request and hands out the URL; the node
+//! holding the data only sends the bytes with a plain HTTP PUT, so it needs
+//! neither credentials nor an `aws_sdk_s3::Client`. Connection failures,
+//! timeouts, 429, and 5xx responses are retried with a `RetryPolicy`, the
+//! chunk being read from the file again for each attempt. The URL must stay
+//! valid for all of them.
+
+use crate::retry::{retry_after_header, RetryPolicy};
+use crate::units::format_duration;
+use crate::upload::{file_stream, put_object_content_length};
+use aws_sdk_s3::Error;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Uploads `chunk_size` bytes of `file_name` from `start_offset` with a PUT
+/// to `presigned_url`, retrying with the default `RetryPolicy`.
+/// Returns the `etag` of the new object, without quotes.
+pub async fn upload_via_presigned_url(
+    presigned_url: &str,
+    file_name: &str,
+    start_offset: u64,
+    chunk_size: u64,
+) -> Result<String, Error> {
+    upload_via_presigned_url_with_policy(
+        &reqwest::Client::new(),
+        &RetryPolicy::default(),
+        presigned_url,
+        file_name,
+        start_offset,
+        chunk_size,
+    )
+    .await
+}
+
+/// Same as `upload_via_presigned_url`, sending through `http_client` and
+/// retrying as `policy` says.
+pub async fn upload_via_presigned_url_with_policy(
+    http_client: &reqwest::Client,
+    policy: &RetryPolicy,
+    presigned_url: &str,
+    file_name: &str,
+    start_offset: u64,
+    chunk_size: u64,
+) -> Result<String, Error> {
+    let content_length = put_object_content_length(chunk_size)?;
+    let url = reqwest::Url::parse(presigned_url)
+        .map_err(|err| Error::Unhandled(Box::from(format!("Invalid presigned URL: {}", err))))?;
+    // The query carries the signature, so only the object is named in errors.
+    let what = format!("{}{}", url.host_str().unwrap_or_default(), url.path());
+    let file = tokio::fs::File::open(Path::new(file_name))
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+
+    let mut attempt = 0;
+    loop {
+        let body = file_stream(&file, start_offset, chunk_size, None)
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        let result = http_client
+            .put(url.clone())
+            .header(reqwest::header::CONTENT_LENGTH, content_length)
+            .body(reqwest::Body::wrap_stream(body))
+            .send()
+            .await;
+        attempt += 1;
+        let (err, retry_after) = match result {
+            Ok(resp) if resp.status().is_success() => return etag(&resp),
+            Ok(resp) => {
+                let status = resp.status();
+                let retry_after = retry_after_header(resp.headers(), SystemTime::now());
+                let body = resp.text().await.unwrap_or_default();
+                let err = format!("PUT to {} returned {}: {}", what, status, body.trim());
+                if !(status.is_server_error() || status.as_u16() == 429) {
+                    return Err(Error::Unhandled(Box::from(err)));
+                }
+                (err, retry_after)
+            }
+            Err(err) if err.is_connect() || err.is_timeout() => {
+                // reqwest errors display the URL, signature included.
+                let err = err.without_url();
+                (format!("PUT to {} failed: {}", what, err), None)
+            }
+            Err(err) => {
+                return Err(Error::Unhandled(Box::new(err.without_url())));
+            }
+        };
+        if attempt >= policy.max_attempts {
+            return Err(Error::Unhandled(Box::from(err)));
+        }
+        let (delay, source) = policy.delay(attempt - 1, retry_after);
+        eprintln!(
+            "Retrying PUT to {} in {} (wait from {}), attempt {} of {}: {}",
+            what,
+            format_duration(delay),
+            source,
+            attempt + 1,
+            policy.max_attempts,
+            err
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// The ETag header of a successful PUT, without quotes.
+fn etag(resp: &reqwest::Response) -> Result<String, Error> {
+    resp.headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim_matches('"').to_string())
+        .ok_or_else(|| Error::Unhandled(Box::from("The PUT response has no ETag header")))
+}
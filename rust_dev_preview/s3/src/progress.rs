@@ -0,0 +1,348 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Progress of an upload, with the throughput averaged over the last
+//! `units::LIVE_RATE_WINDOW` rather than since the start, so that a stall
+//! or a speed-up shows within one window. The summary at the end gives the
+//! average of the whole run instead.
+//!
+//! A resumed transfer, reported with `ProgressEvent`s, starts with the bytes
+//! an earlier run stored: its percentage is of the whole object, and its
+//! rate and time left of the bytes moved in this run only.
+
+use crate::units::{
+    average_rate, format_duration, format_live_rate, format_overall_rate, format_size,
+    LIVE_RATE_WINDOW,
+};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// The window the throughput is averaged over.
+pub const THROUGHPUT_WINDOW: Duration = LIVE_RATE_WINDOW;
+
+/// Counts the bytes of each chunk of `stream` into `written` as the chunk is
+/// handed to the request, which pulls the body as it sends it.
+pub fn progress_reader<S, B, E>(stream: S, written: Arc<AtomicU64>) -> impl Stream<Item = S::Item>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+{
+    stream.inspect(move |chunk| {
+        if let Ok(bytes) = chunk {
+            written.fetch_add(bytes.as_ref().len() as u64, Ordering::SeqCst);
+        }
+    })
+}
+
+/// Throughput over a rolling window, from samples of a running total.
+#[derive(Debug)]
+pub struct RollingThroughput {
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RollingThroughput {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Records that `total` bytes were written by `now`, dropping the samples
+    /// that no longer cover the window. The oldest sample kept is the last
+    /// one at or before the start of the window.
+    pub fn record(&mut self, now: Instant, total: u64) {
+        self.samples.push_back((now, total));
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= self.window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Bytes per second between the oldest and the newest sample, or `None`
+    /// before two samples are recorded.
+    pub fn bytes_per_sec(&self) -> Option<f64> {
+        let (first_time, first_total) = self.samples.front()?;
+        let (last_time, last_total) = self.samples.back()?;
+        let elapsed = last_time.duration_since(*first_time).as_secs_f64();
+        if elapsed == 0.0 {
+            return None;
+        }
+        Some(last_total.saturating_sub(*first_total) as f64 / elapsed)
+    }
+}
+
+/// A task printing the progress of an upload of `total` bytes every `tick`.
+pub struct ProgressReporter {
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl ProgressReporter {
+    /// Starts printing `label`, the bytes counted in `written`, and the
+    /// throughput over `THROUGHPUT_WINDOW`.
+    pub fn spawn(label: String, total: u64, written: Arc<AtomicU64>, tick: Duration) -> Self {
+        let (stop, mut stopped) = oneshot::channel();
+        let handle = tokio::spawn(async move {
+            let mut throughput = RollingThroughput::new(THROUGHPUT_WINDOW);
+            throughput.record(Instant::now(), 0);
+            let mut interval = tokio::time::interval(tick);
+            // The first tick completes at once.
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = &mut stopped => break,
+                    _ = interval.tick() => {
+                        let done = written.load(Ordering::SeqCst);
+                        throughput.record(Instant::now(), done);
+                        println!("{}", progress_line(&label, done, total, throughput.bytes_per_sec()));
+                    }
+                }
+            }
+        });
+        Self { stop, handle }
+    }
+
+    /// Stops the task and waits for it, so nothing is printed after this
+    /// returns.
+    pub async fn stop(self) {
+        // The task only ends when told to, so the receiver is still there.
+        let _ = self.stop.send(());
+        let _ = self.handle.await;
+    }
+}
+
+/// One line of progress, with a rate over `THROUGHPUT_WINDOW`, such as
+/// `data.bin: 50.0 MiB of 100.0 MiB (50.0%), 10.0 MiB/s (10s avg)`.
+pub fn progress_line(label: &str, done: u64, total: u64, bytes_per_sec: Option<f64>) -> String {
+    let percent = if total == 0 {
+        100.0
+    } else {
+        done as f64 * 100.0 / total as f64
+    };
+    format!(
+        "{}: {} of {} ({:.1}%), {}",
+        label,
+        format_size(done),
+        format_size(total),
+        percent,
+        format_live_rate(bytes_per_sec)
+    )
+}
+
+/// The bytes of a transfer that may continue one stopped earlier.
+///
+/// The percentage is of the whole object, counting what an earlier run
+/// already stored, while rates and the time left only count the bytes moved
+/// in this run, so that resuming at 60% neither starts the display at 0%
+/// nor makes the rate look higher than the network allows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct TransferTotals {
+    /// The whole object.
+    pub total_bytes: u64,
+    /// Stored by an earlier run and not moved again.
+    pub resumed_bytes: u64,
+    /// Moved in this run.
+    pub transferred_bytes: u64,
+}
+
+impl TransferTotals {
+    /// The totals at the start of a run resuming with `resumed_bytes` of
+    /// `total_bytes` already stored.
+    pub fn resumed(total_bytes: u64, resumed_bytes: u64) -> Self {
+        Self {
+            total_bytes,
+            resumed_bytes,
+            transferred_bytes: 0,
+        }
+    }
+
+    pub fn completed_bytes(&self) -> u64 {
+        self.resumed_bytes + self.transferred_bytes
+    }
+
+    pub fn percent(&self) -> f64 {
+        if self.total_bytes == 0 {
+            100.0
+        } else {
+            self.completed_bytes() as f64 * 100.0 / self.total_bytes as f64
+        }
+    }
+
+    /// The time left to move the rest at `bytes_per_sec`, a rate of this
+    /// run.
+    pub fn eta(&self, bytes_per_sec: Option<f64>) -> Option<Duration> {
+        let remaining = self.total_bytes.saturating_sub(self.completed_bytes());
+        match bytes_per_sec {
+            _ if remaining == 0 => Some(Duration::ZERO),
+            Some(rate) if rate > 0.0 => Some(Duration::from_secs_f64(remaining as f64 / rate)),
+            _ => None,
+        }
+    }
+
+    /// Such as `transferred this run: 400 B; total object: 1000 B of 1000 B
+    /// (100.0%), 600 B resumed`.
+    pub fn summary(&self) -> String {
+        format!(
+            "transferred this run: {}; total object: {} of {} ({:.1}%), {} resumed",
+            format_size(self.transferred_bytes),
+            format_size(self.completed_bytes()),
+            format_size(self.total_bytes),
+            self.percent(),
+            format_size(self.resumed_bytes)
+        )
+    }
+}
+
+/// What happened to a transfer, as reported by a `ProgressTracker`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// Before anything is moved; `totals` counts what is already stored.
+    Started { totals: TransferTotals },
+    Part {
+        part_number: i32,
+        bytes: u64,
+        totals: TransferTotals,
+        /// Over `THROUGHPUT_WINDOW`, of this run.
+        bytes_per_second: Option<f64>,
+        eta_ms: Option<u64>,
+    },
+    Finished {
+        totals: TransferTotals,
+        elapsed_ms: u64,
+        /// Since the start of this run.
+        bytes_per_second: Option<f64>,
+    },
+}
+
+impl ProgressEvent {
+    /// The event as one line of text.
+    pub fn to_text(&self) -> String {
+        match self {
+            ProgressEvent::Started { totals } if totals.resumed_bytes > 0 => format!(
+                "Resuming at {} of {} ({:.1}%)",
+                format_size(totals.resumed_bytes),
+                format_size(totals.total_bytes),
+                totals.percent()
+            ),
+            ProgressEvent::Started { totals } => {
+                format!("Starting {}", format_size(totals.total_bytes))
+            }
+            ProgressEvent::Part {
+                part_number,
+                bytes,
+                totals,
+                bytes_per_second,
+                eta_ms,
+            } => {
+                let mut text = format!(
+                    "Part {}: {}, {}",
+                    part_number,
+                    format_size(*bytes),
+                    progress_line(
+                        "total",
+                        totals.completed_bytes(),
+                        totals.total_bytes,
+                        *bytes_per_second
+                    )
+                );
+                if let Some(eta) = eta_ms {
+                    text.push_str(&format!(
+                        ", {} left",
+                        format_duration(Duration::from_millis(*eta))
+                    ));
+                }
+                text
+            }
+            ProgressEvent::Finished {
+                totals,
+                elapsed_ms,
+                bytes_per_second,
+            } => format!(
+                "Finished in {}, {}: {}",
+                format_duration(Duration::from_millis(*elapsed_ms)),
+                format_overall_rate(*bytes_per_second),
+                totals.summary()
+            ),
+        }
+    }
+}
+
+/// Turns the parts of a transfer, possibly resumed, into `ProgressEvent`s.
+#[derive(Debug)]
+pub struct ProgressTracker {
+    totals: TransferTotals,
+    start: Instant,
+    throughput: RollingThroughput,
+}
+
+impl ProgressTracker {
+    /// A run of a `total_bytes` transfer with `resumed_bytes` already stored,
+    /// starting now.
+    pub fn new(total_bytes: u64, resumed_bytes: u64) -> Self {
+        Self::starting_at(total_bytes, resumed_bytes, Instant::now())
+    }
+
+    pub fn starting_at(total_bytes: u64, resumed_bytes: u64, start: Instant) -> Self {
+        let mut throughput = RollingThroughput::new(THROUGHPUT_WINDOW);
+        throughput.record(start, 0);
+        Self {
+            totals: TransferTotals::resumed(total_bytes, resumed_bytes),
+            start,
+            throughput,
+        }
+    }
+
+    pub fn totals(&self) -> TransferTotals {
+        self.totals
+    }
+
+    pub fn started(&self) -> ProgressEvent {
+        ProgressEvent::Started {
+            totals: self.totals,
+        }
+    }
+
+    /// Counts `bytes` of part `part_number`, moved by `now`.
+    pub fn part_at(&mut self, part_number: i32, bytes: u64, now: Instant) -> ProgressEvent {
+        self.totals.transferred_bytes += bytes;
+        self.throughput.record(now, self.totals.transferred_bytes);
+        let bytes_per_second = self.throughput.bytes_per_sec();
+        ProgressEvent::Part {
+            part_number,
+            bytes,
+            totals: self.totals,
+            bytes_per_second,
+            eta_ms: self
+                .totals
+                .eta(bytes_per_second)
+                .map(|eta| eta.as_millis() as u64),
+        }
+    }
+
+    pub fn part(&mut self, part_number: i32, bytes: u64) -> ProgressEvent {
+        self.part_at(part_number, bytes, Instant::now())
+    }
+
+    pub fn finished_at(&self, now: Instant) -> ProgressEvent {
+        let elapsed = now.duration_since(self.start);
+        ProgressEvent::Finished {
+            totals: self.totals,
+            elapsed_ms: elapsed.as_millis() as u64,
+            bytes_per_second: average_rate(self.totals.transferred_bytes, elapsed),
+        }
+    }
+
+    pub fn finished(&self) -> ProgressEvent {
+        self.finished_at(Instant::now())
+    }
+}
@@ -0,0 +1,273 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! "Upload then swap" publishing of a file under a stable key.
+//!
+//! The file is uploaded to a temporary sibling key, verified, and then copied
+//! server-side onto the final key, so readers of the final key only ever see
+//! the previous object or the complete new one.
+//!
+//! The conditions on the final key are sent as `If-Match` and
+//! `If-None-Match` headers of the copy, so S3 evaluates them when it writes
+//! the object and a writer racing with the publish cannot be overwritten.
+
+use crate::copy_prefix::{
+    copy_object_multipart_with_headers, copy_source, CopyPrefixOptions, MAX_COPY_OBJECT_SIZE,
+};
+use crate::failover::EndpointPool;
+use crate::upload::{upload_multipart_window, SourceWindow, UploadHeaders};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use http::{HeaderMap, HeaderValue};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Preconditions S3 checks against the final key as it replaces it.
+#[derive(Debug, Default, Clone)]
+pub struct PublishConditions {
+    /// Only replace the final object if its current ETag matches.
+    pub if_match: Option<String>,
+    /// Only publish if the final key does not exist yet. S3 only accepts
+    /// `*` here.
+    pub if_none_match: Option<String>,
+}
+
+impl PublishConditions {
+    /// The headers of the copy onto the final key, with the ETag of
+    /// `if_match` quoted.
+    pub fn headers(&self) -> Result<HeaderMap, Error> {
+        let mut headers = HeaderMap::new();
+        if let Some(e_tag) = &self.if_match {
+            let value = HeaderValue::from_str(&format!("\"{}\"", e_tag.trim_matches('"')))
+                .map_err(|_| {
+                    Error::Unhandled(Box::from(format!("Invalid ETag in --if-match: {}", e_tag)))
+                })?;
+            headers.insert(http::header::IF_MATCH, value);
+        }
+        match self.if_none_match.as_deref() {
+            None => {}
+            Some("*") => {
+                headers.insert(http::header::IF_NONE_MATCH, HeaderValue::from_static("*"));
+            }
+            Some(other) => {
+                return Err(Error::Unhandled(Box::from(format!(
+                    "If-None-Match only accepts * on a write, not {}",
+                    other
+                ))))
+            }
+        }
+        Ok(headers)
+    }
+}
+
+/// Result of a publish, printed as JSON by the upload binary.
+#[derive(Debug, Clone, Serialize)]
+pub struct PublishResult {
+    pub temp_key: String,
+    pub key: String,
+    pub e_tag: String,
+    pub version_id: Option<String>,
+}
+
+/// Uploads `file_name`, or only the bytes of `window` in it, to `key` via a
+/// temporary key.
+///
+/// On failure at any stage the final key is left untouched and the temporary
+/// object is deleted.
+#[allow(clippy::too_many_arguments)]
+pub async fn publish_via_temp(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    window: Option<SourceWindow>,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    headers: Option<UploadHeaders>,
+    conditions: &PublishConditions,
+) -> Result<PublishResult, Error> {
+    publish_via_temp_with_hook(
+        client,
+        bucket,
+        key,
+        file_name,
+        window,
+        num_parts,
+        buffer_capacity,
+        headers,
+        conditions,
+        |_| Ok(()),
+    )
+    .await
+}
+
+/// Same as `publish_via_temp`, calling `before_copy` with the temporary key
+/// once it has been uploaded and verified. Returning an error from the hook
+/// aborts the publish; tests use it to inject failures.
+#[allow(clippy::too_many_arguments)]
+pub async fn publish_via_temp_with_hook<F>(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    window: Option<SourceWindow>,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    headers: Option<UploadHeaders>,
+    conditions: &PublishConditions,
+    before_copy: F,
+) -> Result<PublishResult, Error>
+where
+    F: FnOnce(&str) -> Result<(), Error>,
+{
+    let headers = conditions.headers()?;
+    let temp_key = format!("{}.tmp-{}", key, Uuid::new_v4().to_simple());
+    let window = match window {
+        Some(window) => window,
+        None => SourceWindow::for_file(file_name, None, None)?,
+    };
+    let size = window.length;
+
+    upload_multipart_window(
+        &EndpointPool::single(client.clone()),
+        bucket,
+        &temp_key,
+        file_name,
+        window,
+        num_parts,
+        buffer_capacity,
+        headers,
+    )
+    .await?;
+
+    let published = async {
+        verify_temp(client, bucket, &temp_key, size).await?;
+        before_copy(&temp_key)?;
+        copy_to_final(client, bucket, &temp_key, key, size, &headers).await
+    }
+    .await;
+
+    if let Err(err) = client
+        .delete_object()
+        .bucket(bucket)
+        .key(&temp_key)
+        .send()
+        .await
+    {
+        eprintln!("Error deleting temporary object {}: {}", temp_key, err);
+    }
+
+    let (e_tag, version_id) = published?;
+    Ok(PublishResult {
+        temp_key,
+        key: key.to_string(),
+        e_tag,
+        version_id,
+    })
+}
+
+/// Checks that the temporary object has the size of the uploaded window.
+async fn verify_temp(
+    client: &Client,
+    bucket: &str,
+    temp_key: &str,
+    size: u64,
+) -> Result<(), Error> {
+    let head = client
+        .head_object()
+        .bucket(bucket)
+        .key(temp_key)
+        .send()
+        .await?;
+    if head.content_length() as u64 != size {
+        return Err(Error::Unhandled(Box::from(format!(
+            "Temporary object {} has {} bytes, expected {}",
+            temp_key,
+            head.content_length(),
+            size
+        ))));
+    }
+    Ok(())
+}
+
+/// Copies the temporary object onto the final key with the conditional
+/// `headers`, returning its ETag and version id.
+async fn copy_to_final(
+    client: &Client,
+    bucket: &str,
+    temp_key: &str,
+    key: &str,
+    size: u64,
+    headers: &HeaderMap,
+) -> Result<(String, Option<String>), Error> {
+    if size <= MAX_COPY_OBJECT_SIZE {
+        let mut request = client
+            .copy_object()
+            .copy_source(copy_source(bucket, temp_key))
+            .bucket(bucket)
+            .key(key)
+            .customize()
+            .await?;
+        request.request_mut().headers_mut().extend(headers.clone());
+        let resp = match request.send().await {
+            Ok(resp) => resp,
+            Err(SdkError::ServiceError { err, .. }) if err.code() == Some("PreconditionFailed") => {
+                return Err(precondition_failed(key))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let e_tag = resp
+            .copy_object_result()
+            .and_then(|r| r.e_tag())
+            .unwrap_or_default()
+            .trim_matches('"')
+            .to_string();
+        Ok((e_tag, resp.version_id().map(|v| v.to_string())))
+    } else {
+        let e_tag = copy_object_multipart_with_headers(
+            client,
+            bucket,
+            temp_key,
+            bucket,
+            key,
+            size,
+            None,
+            &CopyPrefixOptions::default(),
+            headers,
+        )
+        .await
+        .map_err(|err| {
+            if is_precondition_failed(&err) {
+                precondition_failed(key)
+            } else {
+                err
+            }
+        })?;
+        let head = client.head_object().bucket(bucket).key(key).send().await?;
+        Ok((e_tag, head.version_id().map(|v| v.to_string())))
+    }
+}
+
+/// Whether S3 refused a write for its conditions. CompleteMultipartUpload
+/// does not model PreconditionFailed, which comes back unhandled with its
+/// code.
+fn is_precondition_failed(err: &Error) -> bool {
+    match err {
+        Error::Unhandled(inner) => {
+            inner
+                .downcast_ref::<aws_smithy_types::Error>()
+                .and_then(|err| err.code())
+                == Some("PreconditionFailed")
+        }
+        _ => false,
+    }
+}
+
+fn precondition_failed(key: &str) -> Error {
+    Error::Unhandled(Box::from(format!(
+        "Precondition failed: {} does not match the conditions of the publish",
+        key
+    )))
+}
@@ -0,0 +1,181 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Pacing of the requests sent to S3, for buckets shared under an agreed
+//! budget of requests per second.
+//!
+//! A `RequestLimiter` is a token bucket holding a single token, so requests
+//! are spaced evenly rather than sent in bursts. It wraps the connector of
+//! the client, so every HTTP request waits for a token, whichever operation
+//! sends it: parts, listings, HeadObject, and every retry, including the
+//! SDK's own. Clients built with clones of one limiter share its budget.
+//!
+//! A pause of the `SlowDownCoordinator` happens before the request reaches
+//! the limiter, and the limiter does not count the time of the pause
+//! against the request: the request waits for the longer of the two, not
+//! their sum.
+
+use crate::connect::https_adapter;
+use crate::units::format_duration;
+use aws_sdk_s3::Client;
+use futures::future::BoxFuture;
+use hyper::service::Service;
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct LimiterState {
+    /// When the next request may be sent.
+    next: Instant,
+    requests: u64,
+    waited: Duration,
+}
+
+/// Spaces the requests of every client sharing it to at most
+/// `requests_per_second`.
+#[derive(Debug, Clone)]
+pub struct RequestLimiter {
+    interval: Duration,
+    started: Instant,
+    state: Arc<Mutex<LimiterState>>,
+}
+
+impl RequestLimiter {
+    /// Fails unless `requests_per_second` is positive.
+    pub fn new(requests_per_second: f64) -> Result<Self, String> {
+        if !(requests_per_second > 0.0 && requests_per_second.is_finite()) {
+            return Err(format!(
+                "The request rate must be positive, not {}",
+                requests_per_second
+            ));
+        }
+        let now = Instant::now();
+        Ok(Self {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            started: now,
+            state: Arc::new(Mutex::new(LimiterState {
+                next: now,
+                requests: 0,
+                waited: Duration::ZERO,
+            })),
+        })
+    }
+
+    /// The configured limit.
+    pub fn requests_per_second(&self) -> f64 {
+        1.0 / self.interval.as_secs_f64()
+    }
+
+    /// Waits for the turn of one request. Requests are served in the order
+    /// they ask.
+    pub async fn acquire(&self) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let at = state.next.max(now);
+            state.next = at + self.interval;
+            state.requests += 1;
+            state.waited += at - now;
+            at - now
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// The requests sent so far and the rate achieved.
+    pub fn stats(&self) -> RequestStats {
+        let state = self.state.lock().unwrap();
+        let elapsed = self.started.elapsed();
+        let seconds = elapsed.as_secs_f64();
+        RequestStats {
+            requests: state.requests,
+            elapsed_ms: elapsed.as_millis() as u64,
+            requests_per_second: if seconds > 0.0 {
+                state.requests as f64 / seconds
+            } else {
+                0.0
+            },
+            limit: self.requests_per_second(),
+            waited_ms: state.waited.as_millis() as u64,
+        }
+    }
+}
+
+/// What a `RequestLimiter` let through.
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestStats {
+    pub requests: u64,
+    /// Since the limiter was created.
+    pub elapsed_ms: u64,
+    /// The achieved rate.
+    pub requests_per_second: f64,
+    pub limit: f64,
+    /// The total time requests spent waiting for their turn.
+    pub waited_ms: u64,
+}
+
+impl fmt::Display for RequestStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Sent {} requests in {}: {:.2} requests per second (limit {}), {} spent waiting",
+            self.requests,
+            format_duration(Duration::from_millis(self.elapsed_ms)),
+            self.requests_per_second,
+            self.limit,
+            format_duration(Duration::from_millis(self.waited_ms))
+        )
+    }
+}
+
+/// A connector that takes a token from `limiter` before each request.
+#[derive(Debug, Clone)]
+pub struct RateLimited<C> {
+    inner: C,
+    limiter: RequestLimiter,
+}
+
+impl<C> RateLimited<C> {
+    pub fn new(inner: C, limiter: RequestLimiter) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl<C, R> Service<R> for RateLimited<C>
+where
+    C: Service<R> + Clone + Send + 'static,
+    C::Future: Send + 'static,
+    R: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<C::Response, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        // The connector that was made ready serves this request; the clone
+        // left in its place is made ready for the next one.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+        Box::pin(async move {
+            limiter.acquire().await;
+            inner.call(request).await
+        })
+    }
+}
+
+/// Creates a client whose requests are paced by `limiter`.
+pub fn rate_limited_client(config: aws_sdk_s3::Config, limiter: &RequestLimiter) -> Client {
+    let adapter = https_adapter();
+    Client::from_conf_conn(config, RateLimited::new(adapter, limiter.clone()))
+}
@@ -0,0 +1,155 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Re-encrypts the objects under a prefix with a new AWS KMS key, so that an
+//! old key can be retired.
+//!
+//! Each object encrypted with the old key is copied onto itself with
+//! `CopyObject`, keeping its metadata, tags, and storage class, and naming
+//! the new key. The bytes never leave S3. The copy is conditional on the
+//! ETag read by `HeadObject`, so an object overwritten in the meantime is
+//! reported as an error instead of being replaced by its older version.
+
+use crate::copy_prefix::{copy_source, MAX_COPY_OBJECT_SIZE};
+use aws_sdk_s3::model::{MetadataDirective, ServerSideEncryption, TaggingDirective};
+use aws_sdk_s3::{Client, Error};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Outcome of a re-encryption.
+#[derive(Debug, Default)]
+pub struct ReencryptReport {
+    pub reencrypted: Vec<String>,
+    /// Objects not encrypted with the old key.
+    pub skipped: Vec<String>,
+    pub errors: Vec<(String, String)>,
+}
+
+enum Outcome {
+    Reencrypted,
+    Skipped,
+}
+
+/// Whether `listed`, the key ID that `HeadObject` returns, names the key
+/// `key_id`.
+///
+/// S3 returns the key ARN; `key_id` may be the ARN or the bare key ID.
+/// Aliases cannot be matched, since S3 does not return them.
+pub fn is_same_key(listed: &str, key_id: &str) -> bool {
+    listed == key_id || listed.ends_with(&format!(":key/{}", key_id))
+}
+
+/// Copies every object under `prefix` that is encrypted with `old_key_id`
+/// onto itself, encrypted with `new_key_id`, with at most `concurrency`
+/// objects at the same time.
+///
+/// Per-object failures do not stop the run; they are collected in the
+/// returned report. Objects over 5 GiB cannot be copied with `CopyObject`
+/// and are reported as errors.
+pub async fn reencrypt_prefix(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    old_key_id: &str,
+    new_key_id: &str,
+    concurrency: usize,
+) -> Result<ReencryptReport, Error> {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut handles = Vec::new();
+    let mut continuation_token: Option<String> = None;
+
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+
+        for object in resp.contents().unwrap_or_default() {
+            let key = match object.key() {
+                Some(key) => key.to_string(),
+                None => continue,
+            };
+            let client = client.clone();
+            let bucket = bucket.to_string();
+            let old_key_id = old_key_id.to_string();
+            let new_key_id = new_key_id.to_string();
+            // Waiting here keeps a large prefix from starting a task per
+            // object before any of them runs.
+            let permit = semaphore
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+
+            handles.push(tokio::spawn(async move {
+                let outcome = reencrypt_one(&client, &bucket, &key, &old_key_id, &new_key_id).await;
+                drop(permit);
+                (key, outcome)
+            }));
+        }
+
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+
+    let mut report = ReencryptReport::default();
+    for handle in handles {
+        let (key, outcome) = handle
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        match outcome {
+            Ok(Outcome::Reencrypted) => report.reencrypted.push(key),
+            Ok(Outcome::Skipped) => report.skipped.push(key),
+            Err(err) => report.errors.push((key, err.to_string())),
+        }
+    }
+    Ok(report)
+}
+
+async fn reencrypt_one(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    old_key_id: &str,
+    new_key_id: &str,
+) -> Result<Outcome, Error> {
+    let head = client.head_object().bucket(bucket).key(key).send().await?;
+    let encrypted_with_old_key = head.server_side_encryption()
+        == Some(&ServerSideEncryption::AwsKms)
+        && head
+            .ssekms_key_id()
+            .map_or(false, |listed| is_same_key(listed, old_key_id));
+    if !encrypted_with_old_key {
+        return Ok(Outcome::Skipped);
+    }
+    if head.content_length() as u64 > MAX_COPY_OBJECT_SIZE {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} is {} bytes, more than CopyObject can copy",
+            key,
+            head.content_length()
+        ))));
+    }
+
+    // Without a storage class, the copy would be stored as STANDARD.
+    client
+        .copy_object()
+        .copy_source(copy_source(bucket, key))
+        .set_copy_source_if_match(head.e_tag().map(|t| t.to_string()))
+        .bucket(bucket)
+        .key(key)
+        .metadata_directive(MetadataDirective::Copy)
+        .tagging_directive(TaggingDirective::Copy)
+        .set_storage_class(head.storage_class().cloned())
+        .server_side_encryption(ServerSideEncryption::AwsKms)
+        .ssekms_key_id(new_key_id)
+        .send()
+        .await?;
+    Ok(Outcome::Reencrypted)
+}
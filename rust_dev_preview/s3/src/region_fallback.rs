@@ -0,0 +1,117 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Uploads that fall back to a second Region when the first one is down.
+//!
+//! In a multi-Region active-active setup both Regions hold a replica of the
+//! bucket, so an upload that cannot reach the primary Region can be written
+//! to the other one and replicated back later. The caller learns where the
+//! object went from the returned `UploadLocation`, to update its routing.
+
+use crate::failover::is_connection_error;
+use crate::upload::upload_multipart;
+use aws_sdk_s3::error::{
+    CompleteMultipartUploadError, CreateMultipartUploadError, UploadPartError,
+};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use serde::Serialize;
+
+/// Where an upload was written, with the ETag of the object.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "region", content = "e_tag", rename_all = "snake_case")]
+pub enum UploadLocation {
+    Primary(String),
+    Fallback(String),
+}
+
+impl UploadLocation {
+    pub fn e_tag(&self) -> &str {
+        match self {
+            UploadLocation::Primary(e_tag) | UploadLocation::Fallback(e_tag) => e_tag,
+        }
+    }
+}
+
+/// Whether `err` means the Region is down rather than that the request was
+/// refused: 503 ServiceUnavailable, or no connection or response at all.
+///
+/// Throttling (503 SlowDown) is not a reason to move to another Region, and
+/// is retried by the upload itself.
+pub fn is_region_unavailable(err: &Error) -> bool {
+    let inner = match err {
+        Error::Unhandled(inner) => inner,
+        _ => return false,
+    };
+    if let Some(err) = inner.downcast_ref::<aws_smithy_types::Error>() {
+        return err.code() == Some("ServiceUnavailable");
+    }
+    // Without a response, the whole `SdkError` of the operation is kept.
+    is_connection_failure::<CreateMultipartUploadError>(inner)
+        || is_connection_failure::<UploadPartError>(inner)
+        || is_connection_failure::<CompleteMultipartUploadError>(inner)
+}
+
+fn is_connection_failure<E>(inner: &(dyn std::error::Error + Send + Sync + 'static)) -> bool
+where
+    E: std::error::Error + 'static,
+{
+    inner
+        .downcast_ref::<SdkError<E>>()
+        .map_or(false, is_connection_error)
+}
+
+/// Uploads `file_name` to `bucket/key` with a multipart upload in
+/// `num_parts` parts, through `primary_client`, or through
+/// `fallback_client` if the primary Region is unavailable.
+///
+/// The multipart upload started in the primary Region is aborted, on a best
+/// effort basis since the Region may not answer, before the whole file is
+/// uploaded again in the fallback Region. Other errors are returned without
+/// trying the fallback.
+pub async fn upload_with_fallback(
+    primary_client: &Client,
+    fallback_client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    num_parts: usize,
+) -> Result<UploadLocation, Error> {
+    match upload_multipart(
+        primary_client,
+        bucket,
+        key,
+        file_name,
+        num_parts,
+        None,
+        None,
+    )
+    .await
+    {
+        Ok(e_tag) => {
+            eprintln!("Uploaded {} through the primary Region", key);
+            Ok(UploadLocation::Primary(e_tag))
+        }
+        Err(err) if is_region_unavailable(&err) => {
+            eprintln!(
+                "Primary Region unavailable ({}), uploading {} through the fallback Region",
+                err, key
+            );
+            let e_tag = upload_multipart(
+                fallback_client,
+                bucket,
+                key,
+                file_name,
+                num_parts,
+                None,
+                None,
+            )
+            .await?;
+            eprintln!("Uploaded {} through the fallback Region", key);
+            Ok(UploadLocation::Fallback(e_tag))
+        }
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,357 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! S3 Replication Time Control (S3 RTC) configuration and monitoring.
+
+use crate::units::{format_duration, format_size};
+use aws_sdk_cloudwatch::model::MetricDataQuery;
+use aws_sdk_cloudwatch::types::DateTime;
+use aws_sdk_s3::model::{
+    Destination, Metrics, MetricsStatus, ReplicationConfiguration, ReplicationRule,
+    ReplicationTime, ReplicationTimeStatus, ReplicationTimeValue,
+};
+use aws_sdk_s3::{Client, Error};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The replication time S3 RTC is designed to meet.
+pub const RTC_MINUTES: i32 = 15;
+
+/// Enables S3 RTC, and the replication metrics it requires, on the rules of
+/// `source_bucket` that replicate to `replica_arn`, such as
+/// `arn:aws:s3:::doc-example-replica`.
+///
+/// The bucket must already have a replication configuration with such a rule,
+/// and the rule must use the current schema (a `Filter` rather than a
+/// `Prefix`); S3 rejects RTC on older rules.
+pub async fn enable_replication_time_control(
+    client: &Client,
+    source_bucket: &str,
+    replica_arn: &str,
+) -> Result<(), Error> {
+    let resp = client
+        .get_bucket_replication()
+        .bucket(source_bucket)
+        .send()
+        .await?;
+    let config = resp.replication_configuration().ok_or_else(|| {
+        Error::Unhandled(Box::from(format!(
+            "Bucket {} has no replication configuration",
+            source_bucket
+        )))
+    })?;
+
+    let mut found = false;
+    let rules = config
+        .rules()
+        .unwrap_or_default()
+        .iter()
+        .map(|rule| match rule.destination() {
+            Some(destination) if destination.bucket() == Some(replica_arn) => {
+                found = true;
+                with_time_control(rule, destination)
+            }
+            _ => rule.clone(),
+        })
+        .collect::<Vec<_>>();
+    if !found {
+        return Err(Error::Unhandled(Box::from(format!(
+            "Bucket {} has no replication rule for {}",
+            source_bucket, replica_arn
+        ))));
+    }
+
+    let config = ReplicationConfiguration::builder()
+        .set_role(config.role().map(|r| r.to_string()))
+        .set_rules(Some(rules))
+        .build();
+    client
+        .put_bucket_replication()
+        .bucket(source_bucket)
+        .replication_configuration(config)
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Copies `rule`, adding the 15-minute replication time and the metrics to
+/// its destination.
+fn with_time_control(rule: &ReplicationRule, destination: &Destination) -> ReplicationRule {
+    let fifteen_minutes = || ReplicationTimeValue::builder().minutes(RTC_MINUTES).build();
+    let destination = Destination::builder()
+        .set_bucket(destination.bucket().map(|b| b.to_string()))
+        .set_account(destination.account().map(|a| a.to_string()))
+        .set_storage_class(destination.storage_class().cloned())
+        .set_access_control_translation(destination.access_control_translation().cloned())
+        .set_encryption_configuration(destination.encryption_configuration().cloned())
+        .replication_time(
+            ReplicationTime::builder()
+                .status(ReplicationTimeStatus::Enabled)
+                .time(fifteen_minutes())
+                .build(),
+        )
+        .metrics(
+            Metrics::builder()
+                .status(MetricsStatus::Enabled)
+                .event_threshold(fifteen_minutes())
+                .build(),
+        )
+        .build();
+    ReplicationRule::builder()
+        .set_id(rule.id().map(|i| i.to_string()))
+        .priority(rule.priority())
+        .set_filter(rule.filter().cloned())
+        .set_status(rule.status().cloned())
+        .set_source_selection_criteria(rule.source_selection_criteria().cloned())
+        .set_existing_object_replication(rule.existing_object_replication().cloned())
+        .set_delete_marker_replication(rule.delete_marker_replication().cloned())
+        .destination(destination)
+        .build()
+}
+
+/// Latest replication metrics between two buckets, over all their rules.
+/// A value is `None` when CloudWatch has no recent data point for it.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicationMetrics {
+    /// The maximum replication latency, in seconds.
+    pub latency_seconds: Option<f64>,
+    pub bytes_pending: Option<f64>,
+    pub operations_pending: Option<f64>,
+}
+
+/// Fetches the S3 RTC metrics of the replication from `source_bucket` to
+/// `destination_bucket`.
+///
+/// S3 publishes the metrics per rule (with a `RuleId` dimension), so they are
+/// found with a CloudWatch `SEARCH` and combined: the largest latency, and
+/// the sum of the pending bytes and operations.
+pub async fn get_replication_metrics(
+    cw_client: &aws_sdk_cloudwatch::Client,
+    source_bucket: &str,
+    destination_bucket: &str,
+) -> Result<ReplicationMetrics, aws_sdk_cloudwatch::Error> {
+    let query = |id: &str, function: &str, metric: &str, statistic: &str| {
+        MetricDataQuery::builder()
+            .id(id)
+            .expression(format!(
+                "{}(SEARCH('{{AWS/S3,DestinationBucket,RuleId,SourceBucket}} \
+                 SourceBucket=\"{}\" DestinationBucket=\"{}\" MetricName=\"{}\"', '{}', 60))",
+                function, source_bucket, destination_bucket, metric, statistic
+            ))
+            .return_data(true)
+            .build()
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    // Metrics are published every minute; look back far enough to find the
+    // latest point even when publishing lags.
+    let start = now - Duration::from_secs(15 * 60);
+    let resp = cw_client
+        .get_metric_data()
+        .metric_data_queries(query("latency", "MAX", "ReplicationLatency", "Maximum"))
+        .metric_data_queries(query("bytes", "SUM", "BytesPendingReplication", "Maximum"))
+        .metric_data_queries(query(
+            "operations",
+            "SUM",
+            "OperationsPendingReplication",
+            "Maximum",
+        ))
+        .start_time(DateTime::from_secs(start.as_secs() as i64))
+        .end_time(DateTime::from_secs(now.as_secs() as i64))
+        .send()
+        .await?;
+
+    let mut metrics = ReplicationMetrics::default();
+    for result in resp.metric_data_results().unwrap_or_default() {
+        // Values are returned newest first.
+        let latest = result.values().and_then(|v| v.first()).copied();
+        match result.id() {
+            Some("latency") => metrics.latency_seconds = latest,
+            Some("bytes") => metrics.bytes_pending = latest,
+            Some("operations") => metrics.operations_pending = latest,
+            _ => {}
+        }
+    }
+    Ok(metrics)
+}
+
+/// Replication latency above which the dashboard shows a warning: 5 minutes.
+pub const LAG_WARNING_SECONDS: f64 = 300.0;
+
+/// Pending bytes above which the dashboard shows a warning: 1 GB.
+pub const PENDING_WARNING_BYTES: f64 = 1_000_000_000.0;
+
+/// The names of the buckets `source_bucket` replicates to, in the order of
+/// its rules, each once.
+pub async fn replication_destinations(
+    client: &Client,
+    source_bucket: &str,
+) -> Result<Vec<String>, Error> {
+    let resp = client
+        .get_bucket_replication()
+        .bucket(source_bucket)
+        .send()
+        .await?;
+    let mut destinations: Vec<String> = Vec::new();
+    let rules = resp
+        .replication_configuration()
+        .and_then(|config| config.rules())
+        .unwrap_or_default();
+    for rule in rules {
+        // Destinations are bucket ARNs, in any partition.
+        let bucket = match rule.destination().and_then(|d| d.bucket()) {
+            Some(arn) => arn.rsplit(":::").next().unwrap_or(arn).to_string(),
+            None => continue,
+        };
+        if !destinations.contains(&bucket) {
+            destinations.push(bucket);
+        }
+    }
+    Ok(destinations)
+}
+
+/// One line of the replication dashboard: the metrics of a destination, or
+/// the error getting them.
+#[derive(Debug, Clone)]
+pub struct DestinationRow {
+    pub destination: String,
+    pub metrics: Result<ReplicationMetrics, String>,
+}
+
+fn format_latency(seconds: Option<f64>) -> String {
+    match seconds {
+        Some(s) => format_duration(Duration::from_secs_f64(s.max(0.0))),
+        None => "-".to_string(),
+    }
+}
+
+fn format_bytes(bytes: Option<f64>) -> String {
+    match bytes {
+        Some(b) => format_size(b.max(0.0) as u64),
+        None => "-".to_string(),
+    }
+}
+
+/// `cell`, in bold red when `warn` is set.
+fn highlight(cell: String, warn: bool) -> String {
+    if warn {
+        format!("\x1b[1;31m{}\x1b[0m", cell)
+    } else {
+        cell
+    }
+}
+
+/// Formats the dashboard of the replication from `source_bucket` as a table
+/// with a line per destination, latencies above `LAG_WARNING_SECONDS` and
+/// pending bytes above `PENDING_WARNING_BYTES` in red. Each line clears the
+/// rest of the terminal line, so that a frame can be drawn over the last.
+pub fn render_dashboard(source_bucket: &str, time: &str, rows: &[DestinationRow]) -> String {
+    let width = rows
+        .iter()
+        .map(|row| row.destination.len())
+        .chain(std::iter::once("Destination".len()))
+        .max()
+        .unwrap_or_default();
+    let mut lines = vec![
+        format!("Replication from {} at {} (UTC)", source_bucket, time),
+        String::new(),
+        format!(
+            "{:<width$}  {:>10}  {:>14}  {:>12}",
+            "Destination",
+            "Latency",
+            "Bytes pending",
+            "Ops pending",
+            width = width
+        ),
+    ];
+    if rows.is_empty() {
+        lines.push("No replication rules".to_string());
+    }
+    for row in rows {
+        let line = match &row.metrics {
+            Ok(metrics) => format!(
+                "{:<width$}  {}  {}  {:>12}",
+                row.destination,
+                highlight(
+                    format!("{:>10}", format_latency(metrics.latency_seconds)),
+                    metrics.latency_seconds.unwrap_or(0.0) > LAG_WARNING_SECONDS
+                ),
+                highlight(
+                    format!("{:>14}", format_bytes(metrics.bytes_pending)),
+                    metrics.bytes_pending.unwrap_or(0.0) > PENDING_WARNING_BYTES
+                ),
+                metrics
+                    .operations_pending
+                    .map(|ops| format!("{:.0}", ops))
+                    .unwrap_or_else(|| "-".to_string()),
+                width = width
+            ),
+            Err(err) => format!(
+                "{:<width$}  Error getting metrics: {}",
+                row.destination,
+                err,
+                width = width
+            ),
+        };
+        lines.push(line);
+    }
+    lines
+        .into_iter()
+        .map(|line| format!("{}\x1b[K\n", line))
+        .collect()
+}
+
+/// Shows the replication lag and pending bytes of every destination of
+/// `src_bucket` as a table redrawn in place every `poll_interval`, until
+/// Ctrl-C. The destinations are read again on each poll, so rules added
+/// during a migration show up.
+pub async fn replication_dashboard(
+    src_client: &Client,
+    src_bucket: &str,
+    cw_client: &aws_sdk_cloudwatch::Client,
+    poll_interval: Duration,
+) -> Result<(), Error> {
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut drawn_lines = 0;
+    loop {
+        let poll = async {
+            let destinations = replication_destinations(src_client, src_bucket).await?;
+            let mut rows = Vec::new();
+            for destination in destinations {
+                let metrics = get_replication_metrics(cw_client, src_bucket, &destination)
+                    .await
+                    .map_err(|err| err.to_string());
+                rows.push(DestinationRow {
+                    destination,
+                    metrics,
+                });
+            }
+            Ok::<_, Error>(rows)
+        };
+        let rows = tokio::select! {
+            _ = &mut ctrl_c => break,
+            rows = poll => rows?,
+        };
+
+        let time = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let frame = render_dashboard(src_bucket, &time, &rows);
+        // Move back to the top of the last frame, draw over it, and clear
+        // whatever is left below when the new frame is shorter.
+        if drawn_lines > 0 {
+            print!("\x1b[{}A", drawn_lines);
+        }
+        print!("\r{}\x1b[J", frame);
+        std::io::Write::flush(&mut std::io::stdout())
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        drawn_lines = frame.lines().count();
+
+        tokio::select! {
+            _ = &mut ctrl_c => break,
+            _ = tokio::time::sleep(poll_interval) => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,413 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Time to first byte and connection reuse of every part request, to chase
+//! a slow network path to an endpoint.
+//!
+//! A `TimedConnector` wraps the connector of the client and tags each
+//! connection it opens with a number and the time its TCP and TLS
+//! handshakes took. Hyper copies the tag of the connection a response came
+//! on into the extensions of the response, where `TimeParts`, a layer under
+//! the SDK's retries like `CapturePart`, finds it: the first response on a
+//! connection is counted as a new connection, later ones as reuse. For each
+//! attempt to send an UploadPart (or UploadPartCopy), it records the time
+//! to the response headers, sending the body included, and to the last
+//! byte of the response. A response body that fails part way is handed on
+//! failing with the same error, for the SDK to retry the attempt.
+//!
+//! The wrappers are only installed when timings are asked for; other
+//! requests only have their query checked for a part number.
+
+use crate::connect::https_connector;
+use aws_sdk_s3::{Client, Error};
+use aws_smithy_client::hyper_ext;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::Uri;
+use hyper::client::connect::{Connected, Connection};
+use hyper::service::Service;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// One attempt to send a part.
+#[derive(Debug, Clone, Serialize)]
+pub struct PartTiming {
+    pub part_number: i32,
+    pub status: Option<u16>,
+    /// Whether the attempt opened its connection; unknown when no response
+    /// was received.
+    pub new_connection: Option<bool>,
+    /// The TCP and TLS handshakes of a new connection.
+    pub connect_ms: Option<f64>,
+    /// Until the response headers, connecting and sending the body included.
+    pub first_byte_ms: Option<f64>,
+    /// Until the last byte of the response, or the error.
+    pub total_ms: f64,
+    /// Why no response was received.
+    pub error: Option<String>,
+}
+
+/// The 50th, 95th, and 99th percentiles of some durations, in
+/// milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl Percentiles {
+    /// The nearest-rank percentiles of `values`; `None` without values.
+    pub fn of(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let rank = |p: f64| {
+            let index = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted[index.max(1) - 1]
+        };
+        Some(Self {
+            p50: rank(50.0),
+            p95: rank(95.0),
+            p99: rank(99.0),
+        })
+    }
+}
+
+impl fmt::Display for Percentiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms",
+            self.p50, self.p95, self.p99
+        )
+    }
+}
+
+/// The percentiles of the attempts recorded by `RequestTimings`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingSummary {
+    pub attempts: usize,
+    pub new_connections: usize,
+    pub reused_connections: usize,
+    pub connect_ms: Option<Percentiles>,
+    pub first_byte_ms: Option<Percentiles>,
+    pub total_ms: Option<Percentiles>,
+}
+
+impl fmt::Display for TimingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed {} part requests, {} on new connections and {} on reused ones",
+            self.attempts, self.new_connections, self.reused_connections
+        )?;
+        let lines = [
+            ("Time to first byte", &self.first_byte_ms),
+            ("Total time", &self.total_ms),
+            ("Connect time", &self.connect_ms),
+        ];
+        for (name, percentiles) in lines.iter() {
+            if let Some(percentiles) = percentiles {
+                write!(f, "\n  {}: {}", name, percentiles)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct TimingState {
+    attempts: Vec<PartTiming>,
+    /// The connections a response was already received on.
+    seen: HashSet<u64>,
+}
+
+/// The part attempts timed by the clients sharing it.
+#[derive(Debug, Clone, Default)]
+pub struct RequestTimings {
+    state: Arc<Mutex<TimingState>>,
+    next_connection: Arc<AtomicU64>,
+}
+
+impl RequestTimings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The attempts recorded, in the order they ended.
+    pub fn attempts(&self) -> Vec<PartTiming> {
+        self.state.lock().unwrap().attempts.clone()
+    }
+
+    pub fn summary(&self) -> TimingSummary {
+        let attempts = self.attempts();
+        let values = |field: fn(&PartTiming) -> Option<f64>| {
+            attempts.iter().filter_map(field).collect::<Vec<_>>()
+        };
+        TimingSummary {
+            attempts: attempts.len(),
+            new_connections: attempts
+                .iter()
+                .filter(|a| a.new_connection == Some(true))
+                .count(),
+            reused_connections: attempts
+                .iter()
+                .filter(|a| a.new_connection == Some(false))
+                .count(),
+            connect_ms: Percentiles::of(&values(|a| a.connect_ms)),
+            first_byte_ms: Percentiles::of(&values(|a| a.first_byte_ms)),
+            total_ms: Percentiles::of(&values(|a| Some(a.total_ms))),
+        }
+    }
+
+    /// The summary and every attempt, as JSON.
+    pub fn to_json(&self) -> String {
+        let output = serde_json::json!({
+            "summary": self.summary(),
+            "attempts": self.attempts(),
+        });
+        serde_json::to_string_pretty(&output).unwrap()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.to_json()).map_err(|err| Error::Unhandled(Box::new(err)))
+    }
+
+    fn record(&self, mut timing: PartTiming, tag: Option<ConnectionTag>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(tag) = tag {
+            let new = state.seen.insert(tag.id);
+            timing.new_connection = Some(new);
+            timing.connect_ms = if new { Some(tag.connect_ms) } else { None };
+        }
+        state.attempts.push(timing);
+    }
+}
+
+/// The number of a connection and how long it took to open, set by
+/// `TimedConnector` in the extensions of the responses received on it.
+#[derive(Debug, Clone)]
+pub struct ConnectionTag {
+    pub id: u64,
+    pub connect_ms: f64,
+}
+
+/// A connector whose connections carry a `ConnectionTag` when `timings` is
+/// set. Without it, connections go through untagged.
+#[derive(Debug, Clone)]
+pub struct TimedConnector<C> {
+    inner: C,
+    timings: Option<RequestTimings>,
+}
+
+impl<C> TimedConnector<C> {
+    pub fn new(inner: C, timings: Option<RequestTimings>) -> Self {
+        Self { inner, timings }
+    }
+}
+
+impl<C> Service<Uri> for TimedConnector<C>
+where
+    C: Service<Uri>,
+    C::Future: Send + 'static,
+{
+    type Response = TimedConnection<C::Response>;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.inner.call(uri);
+        let timings = self.timings.clone();
+        Box::pin(async move {
+            let start = Instant::now();
+            let inner = connecting.await?;
+            let tag = timings.map(|timings| ConnectionTag {
+                id: timings.next_connection.fetch_add(1, Ordering::SeqCst),
+                connect_ms: millis(start),
+            });
+            Ok(TimedConnection { inner, tag })
+        })
+    }
+}
+
+/// A connection made by `TimedConnector`.
+#[derive(Debug)]
+pub struct TimedConnection<T> {
+    inner: T,
+    tag: Option<ConnectionTag>,
+}
+
+impl<T: Connection> Connection for TimedConnection<T> {
+    fn connected(&self) -> Connected {
+        let connected = self.inner.connected();
+        match &self.tag {
+            Some(tag) => connected.extra(tag.clone()),
+            None => connected,
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for TimedConnection<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for TimedConnection<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// The part number of an UploadPart or UploadPartCopy request.
+fn part_number(uri: &Uri) -> Option<i32> {
+    let query = uri.query()?;
+    let mut upload = false;
+    let mut part = None;
+    for pair in query.split('&') {
+        upload |= pair.starts_with("uploadId=");
+        if let Some(number) = pair.strip_prefix("partNumber=") {
+            part = number.parse().ok();
+        }
+    }
+    part.filter(|_| upload)
+}
+
+fn millis(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+/// A connector that times the part requests into `timings`. Without
+/// timings, requests go through untouched.
+#[derive(Debug, Clone)]
+pub struct TimeParts<C> {
+    inner: C,
+    timings: Option<RequestTimings>,
+}
+
+impl<C> TimeParts<C> {
+    pub fn new(inner: C, timings: Option<RequestTimings>) -> Self {
+        Self { inner, timings }
+    }
+}
+
+impl<C, B, RB> Service<http::Request<B>> for TimeParts<C>
+where
+    C: Service<http::Request<B>, Response = http::Response<RB>> + Clone + Send + 'static,
+    C::Future: Send + 'static,
+    C::Error: fmt::Display,
+    B: Send + 'static,
+    RB: hyper::body::HttpBody + From<hyper::Body> + Send + 'static,
+    RB::Data: Send,
+    RB::Error: Into<Box<dyn std::error::Error + Send + Sync>> + fmt::Display,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<C::Response, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        // As in `RateLimited`, the connector made ready serves this request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let (timings, part_number) = match (&self.timings, part_number(request.uri())) {
+            (Some(timings), Some(part_number)) => (timings.clone(), part_number),
+            _ => return Box::pin(inner.call(request)),
+        };
+        let mut timing = PartTiming {
+            part_number,
+            status: None,
+            new_connection: None,
+            connect_ms: None,
+            first_byte_ms: None,
+            total_ms: 0.0,
+            error: None,
+        };
+        Box::pin(async move {
+            let start = Instant::now();
+            let response = match inner.call(request).await {
+                Ok(response) => response,
+                Err(err) => {
+                    timing.total_ms = millis(start);
+                    timing.error = Some(err.to_string());
+                    timings.record(timing, None);
+                    return Err(err);
+                }
+            };
+            timing.first_byte_ms = Some(millis(start));
+            timing.status = Some(response.status().as_u16());
+            let tag = response.extensions().get::<ConnectionTag>().cloned();
+            // Part responses are headers only, so the body is read whole and
+            // handed on.
+            let (parts, body) = response.into_parts();
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(bytes) => hyper::Body::from(bytes),
+                Err(err) => {
+                    timing.error = Some(format!("The body could not be read: {}", err));
+                    hyper::Body::wrap_stream(futures::stream::once(
+                        async move { Err::<Bytes, _>(err) },
+                    ))
+                }
+            };
+            timing.total_ms = millis(start);
+            timings.record(timing, tag);
+            Ok(http::Response::from_parts(parts, RB::from(body)))
+        })
+    }
+}
+
+/// Creates a client that times its part requests into `timings`.
+pub fn timed_client(config: aws_sdk_s3::Config, timings: &RequestTimings) -> Client {
+    let connector = TimedConnector::new(https_connector(), Some(timings.clone()));
+    let adapter = hyper_ext::Adapter::builder().build(connector);
+    Client::from_conf_conn(config, TimeParts::new(adapter, Some(timings.clone())))
+}
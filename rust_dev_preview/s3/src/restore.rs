@@ -0,0 +1,178 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Restores of objects archived in S3 Glacier Deep Archive.
+//!
+//! A restore makes a temporary copy of the object readable for a number of
+//! days. It runs in the background; `wait_for_restore` polls the object
+//! until the copy is available.
+
+use crate::units::format_duration;
+use aws_sdk_s3::model::{GlacierJobParameters, RestoreRequest, StorageClass, Tier};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use std::time::{Duration, Instant};
+
+const HOUR: u64 = 60 * 60;
+
+/// How fast an archived object is restored; faster tiers cost more.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GlacierRestoreTier {
+    /// The cheapest tier: within 48 hours for Deep Archive.
+    Bulk,
+    /// Within 12 hours for Deep Archive.
+    Standard,
+    /// Within minutes, for S3 Glacier Flexible Retrieval only: Deep Archive
+    /// does not support it.
+    Expedited,
+}
+
+impl std::str::FromStr for GlacierRestoreTier {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "bulk" => Ok(GlacierRestoreTier::Bulk),
+            "standard" => Ok(GlacierRestoreTier::Standard),
+            "expedited" => Ok(GlacierRestoreTier::Expedited),
+            other => Err(format!("Unknown restore tier: {}", other)),
+        }
+    }
+}
+
+impl GlacierRestoreTier {
+    /// The longest a Deep Archive restore takes with this tier, or `None`
+    /// for `Expedited`.
+    pub fn deep_archive_duration(self) -> Option<Duration> {
+        match self {
+            GlacierRestoreTier::Bulk => Some(Duration::from_secs(48 * HOUR)),
+            GlacierRestoreTier::Standard => Some(Duration::from_secs(12 * HOUR)),
+            GlacierRestoreTier::Expedited => None,
+        }
+    }
+
+    fn tier(self) -> Tier {
+        match self {
+            GlacierRestoreTier::Bulk => Tier::Bulk,
+            GlacierRestoreTier::Standard => Tier::Standard,
+            GlacierRestoreTier::Expedited => Tier::Expedited,
+        }
+    }
+}
+
+/// Requests a restore of `bucket/key` from Deep Archive for `days` days.
+///
+/// `Expedited` is rejected without sending a request. A restore already in
+/// progress is not an error, so the request can be repeated.
+pub async fn restore_from_deep_archive(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    days: i32,
+    tier: GlacierRestoreTier,
+) -> Result<(), Error> {
+    if tier.deep_archive_duration().is_none() {
+        return Err(Error::Unhandled(Box::from(
+            "The Expedited tier is not available for S3 Glacier Deep Archive; use Standard or Bulk",
+        )));
+    }
+    if days < 1 {
+        return Err(Error::Unhandled(Box::from(format!(
+            "A restore lasts at least one day, not {}",
+            days
+        ))));
+    }
+    let request = RestoreRequest::builder()
+        .days(days)
+        .glacier_job_parameters(GlacierJobParameters::builder().tier(tier.tier()).build())
+        .build();
+    match client
+        .restore_object()
+        .bucket(bucket)
+        .key(key)
+        .restore_request(request)
+        .send()
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(SdkError::ServiceError { err, .. })
+            if err.code() == Some("RestoreAlreadyInProgress") =>
+        {
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The state of a restore, from the `x-amz-restore` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestoreStatus {
+    pub ongoing: bool,
+    /// When the restored copy is removed, as an HTTP date.
+    pub expiry_date: Option<String>,
+}
+
+/// Parses an `x-amz-restore` header such as
+/// `ongoing-request="false", expiry-date="Fri, 21 Dec 2012 00:00:00 GMT"`.
+pub fn parse_restore_header(value: &str) -> Option<RestoreStatus> {
+    let field = |name: &str| {
+        let start = value.find(&format!("{}=\"", name))? + name.len() + 2;
+        let end = value[start..].find('"')? + start;
+        Some(value[start..end].to_string())
+    };
+    let ongoing = match field("ongoing-request")?.as_str() {
+        "true" => true,
+        "false" => false,
+        _ => return None,
+    };
+    Some(RestoreStatus {
+        ongoing,
+        expiry_date: field("expiry-date"),
+    })
+}
+
+/// Polls `bucket/key` every `poll_interval` until its restored copy is
+/// available, failing when the next poll would come after `max_wait`.
+///
+/// Objects that are not archived are available at once. Archived objects
+/// without a restore request fail immediately, since waiting would not help.
+pub async fn wait_for_restore(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    poll_interval: Duration,
+    max_wait: Duration,
+) -> Result<(), Error> {
+    let start = Instant::now();
+    loop {
+        let head = client.head_object().bucket(bucket).key(key).send().await?;
+        let archived = matches!(
+            head.storage_class(),
+            Some(StorageClass::DeepArchive) | Some(StorageClass::Glacier)
+        );
+        if !archived {
+            return Ok(());
+        }
+        match head.restore().and_then(parse_restore_header) {
+            Some(status) if !status.ongoing => return Ok(()),
+            Some(_) => {}
+            None => {
+                return Err(Error::Unhandled(Box::from(format!(
+                    "No restore was requested for {}",
+                    key
+                ))))
+            }
+        }
+        let elapsed = start.elapsed();
+        if elapsed + poll_interval > max_wait {
+            return Err(Error::Unhandled(Box::from(format!(
+                "The restore of {} is still in progress after {}",
+                key,
+                format_duration(elapsed)
+            ))));
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
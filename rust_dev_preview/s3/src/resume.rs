@@ -0,0 +1,351 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Resuming an interrupted multipart upload of a local file.
+//!
+//! The parts already uploaded are listed and matched against the layout of
+//! the file, as by `upload_status::match_parts`; only the parts that are
+//! missing, or that cannot be kept, are uploaded before the upload is
+//! completed. Which parts can be kept depends on `ResumeVerify`:
+//!
+//! - `Mtime` trusts the parts whose size fits the layout, unless the file
+//!   was modified after the upload was created, in which case every part is
+//!   uploaded again. A file regenerated with the same content is therefore
+//!   uploaded in full.
+//! - `Content` ignores the modification time and recomputes the MD5 of each
+//!   part from the file, keeping the parts whose ETag matches. This reads
+//!   the bytes of the completed parts again, but sends none of them.
+//!
+//! The ETag of a part is only its MD5 without SSE-KMS or SSE-C encryption.
+//! When a listed ETag is not an MD5, `Content` cannot tell and falls back
+//! to `Mtime` with a warning. Given the default encryption of the bucket,
+//! `resume_upload_with_options` falls back before listing anything when it
+//! is SSE-KMS.
+
+use crate::bucket_encryption::BucketEncryption;
+use crate::progress::{ProgressEvent, ProgressTracker, TransferTotals};
+use crate::units::format_size;
+use crate::upload::{plan_upload, upload_remaining_parts, UploadPlanOptions};
+use crate::upload_status::{expected_part, is_md5, match_parts, md5_range, MatchedPart};
+use crate::upload_watch::{list_upload_parts, list_uploads, ListedPart};
+use aws_sdk_s3::{Client, Error};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How the parts already uploaded are checked before they are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResumeVerify {
+    Mtime,
+    Content,
+}
+
+impl std::str::FromStr for ResumeVerify {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "mtime" => Ok(ResumeVerify::Mtime),
+            "content" => Ok(ResumeVerify::Content),
+            other => Err(format!("Unknown resume verification: {}", other)),
+        }
+    }
+}
+
+/// The verification to use on a bucket with `encryption` when `requested`,
+/// and why it differs. `Content` becomes `Mtime` under SSE-KMS, where no
+/// part ETag is an MD5.
+pub fn verify_for_encryption(
+    requested: ResumeVerify,
+    bucket: &str,
+    encryption: &BucketEncryption,
+) -> (ResumeVerify, Option<String>) {
+    if requested == ResumeVerify::Content && encryption.etags_are_md5() == Some(false) {
+        let reason = format!(
+            "{} encrypts new objects with SSE-KMS by default, so the ETags of its parts are \
+             not MD5s; the modification time is checked instead. Upload with a SHA-256 \
+             checksum (x-amz-checksum-sha256) to have S3 verify the bytes of each part",
+            bucket
+        );
+        return (ResumeVerify::Mtime, Some(reason));
+    }
+    (requested, None)
+}
+
+/// A part of the file still to upload.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PartToUpload {
+    pub part_number: i32,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// The parts of an upload to keep and to upload.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ResumePlan {
+    pub upload_id: String,
+    /// The verification used, after any fallback.
+    pub verify: ResumeVerify,
+    /// Why `Content` fell back to `Mtime`.
+    pub fallback: Option<String>,
+    pub part_size: u64,
+    pub kept: Vec<MatchedPart>,
+    pub to_upload: Vec<PartToUpload>,
+}
+
+impl ResumePlan {
+    /// The bytes already uploaded that are kept.
+    pub fn verified_bytes(&self) -> u64 {
+        self.kept.iter().map(|part| part.size).sum()
+    }
+
+    pub fn bytes_to_upload(&self) -> u64 {
+        self.to_upload.iter().map(|part| part.size).sum()
+    }
+}
+
+/// Plans the resume of `upload_id`, whose `parts` are listed, from the file
+/// at `path`.
+///
+/// `initiated` is when the upload was created; with `Mtime`, an unknown
+/// creation time is taken as a changed file.
+pub async fn plan_resume(
+    upload_id: &str,
+    parts: &[ListedPart],
+    path: &Path,
+    initiated: Option<SystemTime>,
+    verify: ResumeVerify,
+) -> std::io::Result<ResumePlan> {
+    let metadata = tokio::fs::metadata(path).await?;
+    let file_size = metadata.len();
+    let matched = match_parts(parts, file_size);
+    let (part_size, total_parts) = match (matched.part_size, matched.total_parts) {
+        (Some(part_size), Some(total_parts)) => (part_size, total_parts),
+        // Nothing to keep: the parts are laid out as for a new upload.
+        _ => {
+            let options = UploadPlanOptions {
+                multipart_threshold: 0,
+                ..Default::default()
+            };
+            let plan = plan_upload(file_size, &options);
+            (plan.part_size.max(1), plan.num_parts as u64)
+        }
+    };
+
+    let mut verify = verify;
+    let mut fallback = None;
+    if verify == ResumeVerify::Content {
+        if let Some(part) = matched.matched.iter().find(|part| !is_md5(&part.e_tag)) {
+            let reason = format!(
+                "the ETag of part {} is not an MD5, as with SSE-KMS; \
+                 the modification time is checked instead",
+                part.part_number
+            );
+            tracing::warn!("{}", reason);
+            fallback = Some(reason);
+            verify = ResumeVerify::Mtime;
+        }
+    }
+
+    let kept = match verify {
+        ResumeVerify::Mtime => {
+            let unchanged = match (initiated, metadata.modified()) {
+                (Some(initiated), Ok(modified)) => modified <= initiated,
+                _ => false,
+            };
+            if unchanged {
+                matched.matched
+            } else {
+                Vec::new()
+            }
+        }
+        ResumeVerify::Content => {
+            let mut kept = Vec::new();
+            for part in matched.matched {
+                let local_md5 = md5_range(path, part.offset, part.size).await?;
+                if part.e_tag.eq_ignore_ascii_case(&local_md5) {
+                    kept.push(part);
+                }
+            }
+            kept
+        }
+    };
+
+    let to_upload = (1..=total_parts as i32)
+        .filter(|n| !kept.iter().any(|part| part.part_number == *n))
+        .filter_map(|part_number| {
+            expected_part(part_number, part_size, total_parts, file_size).map(|(offset, size)| {
+                PartToUpload {
+                    part_number,
+                    offset,
+                    size,
+                }
+            })
+        })
+        .collect();
+    Ok(ResumePlan {
+        upload_id: upload_id.to_string(),
+        verify,
+        fallback,
+        part_size,
+        kept,
+        to_upload,
+    })
+}
+
+/// The outcome of a resumed upload.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResumeResult {
+    pub e_tag: String,
+    #[serde(flatten)]
+    pub plan: ResumePlan,
+    /// The default encryption of the bucket, when it was read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket_encryption: Option<BucketEncryption>,
+    /// Bytes already stored and kept, which were not sent again.
+    pub verified_existing_bytes: u64,
+    pub uploaded_bytes: u64,
+    /// The object, what was stored before, and what was sent in this run.
+    pub totals: TransferTotals,
+}
+
+/// Resumes the multipart upload `upload_id` of the file at `path` to `key`,
+/// uploading the parts `plan_resume` does not keep, and completes it.
+///
+/// The progress is printed to stderr. A failure leaves the upload in
+/// place, so that it can be resumed again.
+pub async fn resume_upload(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    path: &Path,
+    verify: ResumeVerify,
+) -> Result<ResumeResult, Error> {
+    resume_upload_with_events(client, bucket, key, upload_id, path, verify, &|event| {
+        eprintln!("{}", event.to_text())
+    })
+    .await
+}
+
+/// As `resume_upload`, calling `on_event` when the parts to upload are
+/// known, after each part, and once completed, instead of printing the
+/// progress. The kept parts count as completed from the first event on.
+pub async fn resume_upload_with_events(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    path: &Path,
+    verify: ResumeVerify,
+    on_event: &(dyn Fn(&ProgressEvent) + Sync),
+) -> Result<ResumeResult, Error> {
+    let options = ResumeOptions {
+        verify,
+        bucket_encryption: None,
+    };
+    resume_upload_with_options(client, bucket, key, upload_id, path, &options, on_event).await
+}
+
+#[derive(Debug, Clone)]
+pub struct ResumeOptions {
+    pub verify: ResumeVerify,
+    /// The default encryption of the bucket, from
+    /// `bucket_encryption::bucket_default_encryption`, to choose the
+    /// verification with `verify_for_encryption` and report in the result.
+    pub bucket_encryption: Option<BucketEncryption>,
+}
+
+/// As `resume_upload_with_events`, with the settings of `options`.
+pub async fn resume_upload_with_options(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    path: &Path,
+    options: &ResumeOptions,
+    on_event: &(dyn Fn(&ProgressEvent) + Sync),
+) -> Result<ResumeResult, Error> {
+    let (verify, encryption_fallback) = match &options.bucket_encryption {
+        Some(encryption) => verify_for_encryption(options.verify, bucket, encryption),
+        None => (options.verify, None),
+    };
+    if let Some(reason) = &encryption_fallback {
+        tracing::warn!("{}", reason);
+    }
+    let no_upload = || {
+        Error::Unhandled(Box::from(format!(
+            "The upload {} of {} no longer exists",
+            upload_id, key
+        )))
+    };
+    let initiated = list_uploads(client, bucket, key)
+        .await?
+        .into_iter()
+        .find(|upload| upload.upload_id == upload_id)
+        .ok_or_else(no_upload)?
+        .initiated
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64));
+    let parts = list_upload_parts(client, bucket, key, upload_id)
+        .await?
+        .ok_or_else(no_upload)?;
+    let mut plan = plan_resume(upload_id, &parts, path, initiated, verify)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    if encryption_fallback.is_some() {
+        plan.fallback = encryption_fallback;
+    }
+    eprintln!(
+        "Verified existing: {} in {} parts; to upload: {} in {} parts",
+        format_size(plan.verified_bytes()),
+        plan.kept.len(),
+        format_size(plan.bytes_to_upload()),
+        plan.to_upload.len()
+    );
+
+    let verified_existing_bytes = plan.verified_bytes();
+    let tracker = Mutex::new(ProgressTracker::new(
+        verified_existing_bytes + plan.bytes_to_upload(),
+        verified_existing_bytes,
+    ));
+    on_event(&tracker.lock().unwrap().started());
+    let kept: Vec<(i32, String)> = plan
+        .kept
+        .iter()
+        .map(|part| (part.part_number, part.e_tag.clone()))
+        .collect();
+    let to_upload: Vec<(i32, u64, u64)> = plan
+        .to_upload
+        .iter()
+        .map(|part| (part.part_number, part.offset, part.size))
+        .collect();
+    let e_tag = upload_remaining_parts(
+        client,
+        bucket,
+        key,
+        &path.to_string_lossy(),
+        upload_id,
+        &kept,
+        &to_upload,
+        &|part_number, size| {
+            let event = tracker.lock().unwrap().part(part_number, size);
+            on_event(&event);
+        },
+    )
+    .await?;
+    let tracker = tracker.into_inner().unwrap();
+    on_event(&tracker.finished());
+    let totals = tracker.totals();
+    Ok(ResumeResult {
+        e_tag,
+        verified_existing_bytes,
+        uploaded_bytes: totals.transferred_bytes,
+        totals,
+        plan,
+        bucket_encryption: options.bucket_encryption.clone(),
+    })
+}
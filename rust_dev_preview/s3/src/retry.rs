@@ -0,0 +1,238 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Application-level retries on top of the SDK's own retry logic.
+//!
+//! Long transfers outlive the SDK's few quick retries when an endpoint is
+//! throttling, so part uploads are retried again here with exponential
+//! backoff. Throttling responses (503 SlowDown, 429) pause every task sharing
+//! a `SlowDownCoordinator`, not just the one that was throttled, and a
+//! `Retry-After` header on the response is honored up to a cap.
+
+use crate::units::format_duration;
+use aws_sdk_s3::types::SdkError;
+use rand::Rng;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    /// Upper bound of the computed backoff.
+    pub max_delay_ms: u64,
+    /// Upper bound of the random delay added to the backoff, so clients
+    /// failing together do not all retry at the same time.
+    pub jitter_ms: u64,
+    /// Upper bound of a wait requested with `Retry-After`, so a hostile or
+    /// broken header cannot stall a transfer indefinitely.
+    pub max_retry_after_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay_ms: 200,
+            max_delay_ms: 20_000,
+            jitter_ms: 0,
+            max_retry_after_ms: 60_000,
+        }
+    }
+}
+
+/// Where the wait before a retry came from, for the retry log line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitSource {
+    Backoff,
+    RetryAfter,
+    /// `Retry-After` asked for more than `max_retry_after_ms`.
+    RetryAfterCapped,
+}
+
+impl fmt::Display for WaitSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitSource::Backoff => write!(f, "computed backoff"),
+            WaitSource::RetryAfter => write!(f, "Retry-After header"),
+            WaitSource::RetryAfterCapped => write!(f, "Retry-After header, capped"),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The backoff before retry number `attempt` (0 for the first retry).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.backoff_with_jitter(attempt, 0)
+    }
+
+    /// `min(base * 2^attempt + jitter_ms, max_delay)`.
+    pub fn backoff_with_jitter(&self, attempt: u32, jitter_ms: u64) -> Duration {
+        let factor = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+        Duration::from_millis(
+            self.base_delay_ms
+                .saturating_mul(factor)
+                .saturating_add(jitter_ms)
+                .min(self.max_delay_ms),
+        )
+    }
+
+    /// The backoff before retry number `attempt`, with a random jitter of up
+    /// to `jitter_ms`.
+    pub fn jittered_backoff(&self, attempt: u32) -> Duration {
+        let jitter = if self.jitter_ms > 0 {
+            rand::thread_rng().gen_range(0..=self.jitter_ms)
+        } else {
+            0
+        };
+        self.backoff_with_jitter(attempt, jitter)
+    }
+
+    /// The wait before retry number `attempt`: the computed backoff with its
+    /// jitter, or the (capped) `Retry-After` value when that is longer.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> (Duration, WaitSource) {
+        let backoff = self.jittered_backoff(attempt);
+        let retry_after = match retry_after {
+            Some(retry_after) => retry_after,
+            None => return (backoff, WaitSource::Backoff),
+        };
+        let cap = Duration::from_millis(self.max_retry_after_ms);
+        let (wait, source) = if retry_after > cap {
+            (cap, WaitSource::RetryAfterCapped)
+        } else {
+            (retry_after, WaitSource::RetryAfter)
+        };
+        if wait >= backoff {
+            (wait, source)
+        } else {
+            (backoff, WaitSource::Backoff)
+        }
+    }
+}
+
+/// Parses a `Retry-After` value, either delay-seconds or an HTTP date.
+/// Dates in the past yield a zero wait.
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let date = SystemTime::UNIX_EPOCH + Duration::from_secs(date.timestamp().max(0) as u64);
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// Reads the `Retry-After` header of a response.
+pub fn retry_after_header(headers: &http::HeaderMap, now: SystemTime) -> Option<Duration> {
+    headers
+        .get(http::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| parse_retry_after(v, now))
+}
+
+fn status<E>(err: &SdkError<E>) -> Option<u16> {
+    match err {
+        SdkError::ServiceError { raw, .. } => Some(raw.http().status().as_u16()),
+        _ => None,
+    }
+}
+
+/// The `Retry-After` wait requested by the error's raw response, if any.
+pub fn retry_after<E>(err: &SdkError<E>) -> Option<Duration> {
+    match err {
+        SdkError::ServiceError { raw, .. } => {
+            retry_after_header(raw.http().headers(), SystemTime::now())
+        }
+        _ => None,
+    }
+}
+
+/// Timeouts, connection failures, throttling, and 5xx responses are worth
+/// retrying; other errors will fail the same way again.
+pub fn is_retryable<E>(err: &SdkError<E>) -> bool {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) => true,
+        _ => matches!(status(err), Some(429) | Some(500..=599)),
+    }
+}
+
+/// 503 SlowDown and 429 Too Many Requests.
+pub fn is_throttling<E>(err: &SdkError<E>) -> bool {
+    matches!(status(err), Some(429) | Some(503))
+}
+
+/// Pauses all the tasks of a transfer after one of them is throttled.
+#[derive(Debug, Clone, Default)]
+pub struct SlowDownCoordinator {
+    resume_at: Arc<Mutex<Option<Instant>>>,
+}
+
+impl SlowDownCoordinator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Holds every task back for at least `delay` from now.
+    pub fn slow_down(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut resume_at = self.resume_at.lock().unwrap();
+        if resume_at.map(|current| until > current).unwrap_or(true) {
+            *resume_at = Some(until);
+        }
+    }
+
+    /// Waits until the current pause, if any, is over.
+    pub async fn wait(&self) {
+        let resume_at = *self.resume_at.lock().unwrap();
+        if let Some(resume_at) = resume_at {
+            tokio::time::sleep_until(resume_at.into()).await;
+        }
+    }
+}
+
+/// Runs `op` until it succeeds, fails with an error that is not retryable, or
+/// `policy.max_attempts` is reached. `what` names the operation in the log.
+pub async fn retry_sdk<T, E, F, Fut>(
+    policy: &RetryPolicy,
+    coordinator: &SlowDownCoordinator,
+    what: &str,
+    mut op: F,
+) -> Result<T, SdkError<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, SdkError<E>>>,
+    E: std::error::Error + 'static,
+{
+    let mut attempt = 0;
+    loop {
+        coordinator.wait().await;
+        let err = match op().await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        attempt += 1;
+        if attempt >= policy.max_attempts || !is_retryable(&err) {
+            return Err(err);
+        }
+        let (delay, source) = policy.delay(attempt - 1, retry_after(&err));
+        eprintln!(
+            "Retrying {} in {} (wait from {}), attempt {} of {}: {}",
+            what,
+            format_duration(delay),
+            source,
+            attempt + 1,
+            policy.max_attempts,
+            err
+        );
+        if is_throttling(&err) {
+            coordinator.slow_down(delay);
+        } else {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Credentials of an assumed role, reused until they are about to expire.
+//!
+//! Assuming the role for each upload makes one AssumeRole call per upload,
+//! and a burst of uploads runs into the STS rate limit. The credentials
+//! returned by AssumeRole are valid until their `Expiration`, usually an
+//! hour, so `CachingCredentialsProvider` keeps them and assumes the role
+//! again only `REFRESH_BEFORE_EXPIRY` before that. The cache is behind a
+//! `RwLock`: requests read it at the same time, and only a refresh waits
+//! for the others.
+
+use aws_sdk_s3::Credentials;
+use aws_types::credentials::{self, future, CredentialsError, ProvideCredentials};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+/// How long before their expiry cached credentials are replaced.
+pub const REFRESH_BEFORE_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+/// The session name of `AssumeRoleProvider`, unless set.
+pub const DEFAULT_SESSION_NAME: &str = "s3-code-examples";
+
+/// Assumes a role with STS on each call.
+#[derive(Debug, Clone)]
+pub struct AssumeRoleProvider {
+    sts_client: aws_sdk_sts::Client,
+    role_arn: String,
+    session_name: String,
+}
+
+impl AssumeRoleProvider {
+    pub fn new(sts_client: aws_sdk_sts::Client, role_arn: impl Into<String>) -> Self {
+        Self {
+            sts_client,
+            role_arn: role_arn.into(),
+            session_name: DEFAULT_SESSION_NAME.to_string(),
+        }
+    }
+
+    pub fn session_name(mut self, session_name: impl Into<String>) -> Self {
+        self.session_name = session_name.into();
+        self
+    }
+
+    /// Calls AssumeRole, returning credentials that expire at the
+    /// `Expiration` of the response.
+    pub async fn assume_role(&self) -> Result<Credentials, aws_sdk_sts::Error> {
+        let resp = self
+            .sts_client
+            .assume_role()
+            .role_arn(&self.role_arn)
+            .role_session_name(&self.session_name)
+            .send()
+            .await?;
+        let credentials = resp.credentials().ok_or_else(|| {
+            aws_sdk_sts::Error::Unhandled(Box::from("AssumeRole returned no credentials"))
+        })?;
+        let expiry = credentials
+            .expiration()
+            .map(|t| UNIX_EPOCH + Duration::from_secs(t.secs().max(0) as u64));
+        Ok(Credentials::new(
+            credentials.access_key_id().unwrap_or_default(),
+            credentials.secret_access_key().unwrap_or_default(),
+            credentials.session_token().map(|token| token.to_string()),
+            expiry,
+            "AssumeRole",
+        ))
+    }
+}
+
+/// Whether `credentials` can still be used at `now`: they have no expiry,
+/// or it is more than `REFRESH_BEFORE_EXPIRY` away.
+pub fn is_fresh(credentials: &Credentials, now: SystemTime) -> bool {
+    match credentials.expiry() {
+        Some(expiry) => now + REFRESH_BEFORE_EXPIRY < expiry,
+        None => true,
+    }
+}
+
+/// An `AssumeRoleProvider` whose credentials are cached until
+/// `REFRESH_BEFORE_EXPIRY` before they expire, for the `credentials_provider`
+/// of a client.
+#[derive(Debug)]
+pub struct CachingCredentialsProvider {
+    inner: AssumeRoleProvider,
+    cache: RwLock<Option<Credentials>>,
+}
+
+impl CachingCredentialsProvider {
+    pub fn new(inner: AssumeRoleProvider) -> Self {
+        Self {
+            inner,
+            cache: RwLock::new(None),
+        }
+    }
+
+    /// The cached credentials, assuming the role again when there are none
+    /// or they are about to expire.
+    pub async fn credentials(&self) -> credentials::Result {
+        self.load()
+            .await
+            .map_err(|err| CredentialsError::ProviderError(Box::new(err)))
+    }
+
+    /// As `credentials`, with the error of AssumeRole as returned by STS.
+    pub async fn load(&self) -> Result<Credentials, aws_sdk_sts::Error> {
+        if let Some(cached) = self.cache.read().await.as_ref() {
+            if is_fresh(cached, SystemTime::now()) {
+                return Ok(cached.clone());
+            }
+        }
+        let mut cache = self.cache.write().await;
+        // Another request may have refreshed them while this one waited.
+        if let Some(cached) = cache.as_ref() {
+            if is_fresh(cached, SystemTime::now()) {
+                return Ok(cached.clone());
+            }
+        }
+        let credentials = self.inner.assume_role().await?;
+        *cache = Some(credentials.clone());
+        Ok(credentials)
+    }
+}
+
+impl ProvideCredentials for CachingCredentialsProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.credentials())
+    }
+}
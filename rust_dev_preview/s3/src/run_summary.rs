@@ -0,0 +1,279 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! The summary printed at the end of a batch upload that partly failed.
+//!
+//! After 3 failures among 5,000 files, the log holds what to do next but
+//! not where it can be found. `group_failures` groups the failed files by
+//! error class, with the explanation and hint of the `error_hints`
+//! catalogue, `write_retry_file` lists them for `--files-from`, and
+//! `retry_command` is the command line of the run with `--files-from` the
+//! retry file, so that running it again uploads only the failed files.
+//!
+//! A retry file holds the path of each file relative to the uploaded
+//! directory, one per line, followed by a tab and the SHA-256 the file must
+//! be read with when one was expected. Blank lines and lines starting with
+//! `#` are ignored when it is read.
+
+use crate::error_hints::{explain_code, OTHER_ERROR_CLASS};
+use crate::expected_sha256::parse_sha256;
+use crate::scheduler::{PendingFile, ScheduleSummary};
+use aws_sdk_s3::Error;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// The keys printed per failure group; the others are counted.
+pub const MAX_LISTED_KEYS: usize = 10;
+
+/// The failed files of one error class.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailureGroup {
+    pub class: String,
+    pub explanation: Option<&'static str>,
+    pub hint: Option<&'static str>,
+    /// Sorted.
+    pub keys: Vec<String>,
+}
+
+/// Groups the files of `pending` that failed, ignoring the interrupted
+/// ones, by error class, the largest groups first.
+pub fn group_failures(pending: &[PendingFile]) -> Vec<FailureGroup> {
+    let mut by_class: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for file in pending.iter().filter(|file| file.error.is_some()) {
+        let class = file.error_class.as_deref().unwrap_or(OTHER_ERROR_CLASS);
+        by_class.entry(class).or_default().push(file.key.clone());
+    }
+    let mut groups: Vec<FailureGroup> = by_class
+        .into_iter()
+        .map(|(class, mut keys)| {
+            keys.sort();
+            let entry = explain_code(class);
+            FailureGroup {
+                class: class.to_string(),
+                explanation: entry.map(|entry| entry.explanation),
+                hint: entry.map(|entry| entry.hint),
+                keys,
+            }
+        })
+        .collect();
+    // Stable, so groups of the same size stay sorted by class.
+    groups.sort_by(|a, b| b.keys.len().cmp(&a.keys.len()));
+    groups
+}
+
+/// The groups as text: each class with its explanation and hint, and at
+/// most `MAX_LISTED_KEYS` of its keys, pointing to `full_list` for the rest.
+pub fn render_failures(groups: &[FailureGroup], total: usize, full_list: Option<&Path>) -> String {
+    let failed: usize = groups.iter().map(|group| group.keys.len()).sum();
+    let mut text = format!("{} of {} files failed:\n", failed, total);
+    for group in groups {
+        let count = group.keys.len();
+        let files = if count == 1 { "file" } else { "files" };
+        match group.explanation {
+            Some(explanation) => text.push_str(&format!(
+                "  {}, {} {}: {}\n",
+                group.class, count, files, explanation
+            )),
+            None => text.push_str(&format!("  {}, {} {}\n", group.class, count, files)),
+        }
+        if let Some(hint) = group.hint {
+            text.push_str(&format!("    Hint: {}\n", hint));
+        }
+        for key in group.keys.iter().take(MAX_LISTED_KEYS) {
+            text.push_str(&format!("    {}\n", key));
+        }
+        if count > MAX_LISTED_KEYS {
+            let more = count - MAX_LISTED_KEYS;
+            match full_list {
+                Some(path) => text.push_str(&format!(
+                    "    ... and {} more, listed in {}\n",
+                    more,
+                    path.display()
+                )),
+                None => text.push_str(&format!("    ... and {} more\n", more)),
+            }
+        }
+    }
+    text
+}
+
+/// Writes the failed files of `pending` to `path`, relative to `directory`,
+/// with their SHA-256 from `expected_sha256` when it has one, and returns
+/// how many there are.
+pub fn write_retry_file(
+    path: &Path,
+    directory: &Path,
+    pending: &[PendingFile],
+    expected_sha256: &HashMap<PathBuf, String>,
+) -> Result<usize, Error> {
+    let mut content = String::new();
+    let mut count = 0;
+    for file in pending.iter().filter(|file| file.error.is_some()) {
+        let relative = file.path.strip_prefix(directory).unwrap_or(&file.path);
+        content.push_str(&relative.to_string_lossy());
+        if let Some(sha256) = expected_sha256.get(&file.path) {
+            content.push('\t');
+            content.push_str(sha256);
+        }
+        content.push('\n');
+        count += 1;
+    }
+    std::fs::write(path, content).map_err(|err| Error::Unhandled(Box::new(err)))?;
+    Ok(count)
+}
+
+/// A file listed by `--files-from`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedFile {
+    /// Relative to the uploaded directory.
+    pub path: PathBuf,
+    /// The SHA-256 the file must be read with, in lowercase hex.
+    pub sha256: Option<String>,
+}
+
+/// Reads the files listed in the file at `path`, as written by
+/// `write_retry_file`.
+pub fn read_files_from(path: &Path) -> Result<Vec<ListedFile>, Error> {
+    let content = std::fs::read_to_string(path).map_err(|err| {
+        Error::Unhandled(Box::from(format!(
+            "--files-from {}: {}",
+            path.display(),
+            err
+        )))
+    })?;
+    let mut listed = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (file, sha256) = match line.split_once('\t') {
+            Some((file, sha256)) => {
+                let sha256 = parse_sha256(sha256).map_err(|err| {
+                    Error::Unhandled(Box::from(format!(
+                        "--files-from {}, line {}: {}",
+                        path.display(),
+                        number + 1,
+                        err
+                    )))
+                })?;
+                (file, Some(sha256))
+            }
+            None => (line, None),
+        };
+        listed.push(ListedFile {
+            path: PathBuf::from(file),
+            sha256,
+        });
+    }
+    Ok(listed)
+}
+
+/// The SHA-256 of the `listed` files that have one, by their path in
+/// `directory`, for `SchedulerOptions::expected_sha256`.
+pub fn listed_sha256(directory: &Path, listed: &[ListedFile]) -> HashMap<PathBuf, String> {
+    listed
+        .iter()
+        .filter_map(|file| {
+            let sha256 = file.sha256.clone()?;
+            Some((directory.join(&file.path), sha256))
+        })
+        .collect()
+}
+
+/// Keeps the files of `files` listed in `listed`, relative to `directory`,
+/// and fails if a listed file is not among them.
+pub fn select_listed<T>(
+    files: Vec<T>,
+    directory: &Path,
+    listed: &[ListedFile],
+    path_of: impl Fn(&T) -> &Path,
+) -> Result<Vec<T>, Error> {
+    let mut wanted: HashSet<&Path> = listed.iter().map(|file| file.path.as_path()).collect();
+    let selected: Vec<T> = files
+        .into_iter()
+        .filter(|file| {
+            let path = path_of(file);
+            wanted.remove(path.strip_prefix(directory).unwrap_or(path))
+        })
+        .collect();
+    if let Some(missing) = wanted.into_iter().min() {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} is listed by --files-from but is not a file of {} to upload",
+            missing.display(),
+            directory.display()
+        ))));
+    }
+    Ok(selected)
+}
+
+/// Quotes `arg` for a POSIX shell, unless it is made of characters that
+/// need none.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// The command line `args`, such as `std::env::args()`, with
+/// `--files-from retry_file` in place of its `--files-from` and `--resume`
+/// options.
+pub fn retry_command(args: &[String], retry_file: &Path) -> String {
+    let mut kept = Vec::with_capacity(args.len() + 2);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--files-from" => {
+                args.next();
+            }
+            "--resume" => {}
+            _ if arg.starts_with("--files-from=") => {}
+            _ => kept.push(shell_quote(arg)),
+        }
+    }
+    kept.push("--files-from".to_string());
+    kept.push(shell_quote(&retry_file.to_string_lossy()));
+    kept.join(" ")
+}
+
+/// One line of the event log of a run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileEvent<'a> {
+    pub key: &'a str,
+    /// `uploaded`, `failed`, or `interrupted`.
+    pub outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_class: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+}
+
+/// Writes the outcome of every file of `summary` to `path`, as JSON Lines.
+pub fn write_event_log(path: &Path, summary: &ScheduleSummary) -> Result<(), Error> {
+    let uploaded = summary.completed.iter().map(|key| FileEvent {
+        key,
+        outcome: "uploaded",
+        error_class: None,
+        error: None,
+    });
+    let pending = summary.pending.iter().map(|file| FileEvent {
+        key: &file.key,
+        outcome: if file.error.is_some() {
+            "failed"
+        } else {
+            "interrupted"
+        },
+        error_class: file.error_class.as_deref(),
+        error: file.error.as_deref(),
+    });
+    let lines: String = uploaded
+        .chain(pending)
+        .map(|event| serde_json::to_string(&event).unwrap() + "\n")
+        .collect();
+    std::fs::write(path, lines).map_err(|err| Error::Unhandled(Box::new(err)))
+}
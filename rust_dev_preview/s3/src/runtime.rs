@@ -0,0 +1,79 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Tokio runtimes for the binaries that size their own thread pool.
+//!
+//! The upload functions of this crate only spawn Tokio tasks, so they run
+//! in any Tokio runtime, multi-threaded or current-thread, including one an
+//! application already has. Only the binaries build one here.
+
+use serde::Deserialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Builds a multi-threaded runtime of `worker_threads` workers named
+/// `thread_name-0`, `thread_name-1`, and so on, as shown by `top -H`, `perf`,
+/// and debuggers. Linux cuts thread names to 15 bytes.
+///
+/// # Panics
+///
+/// When the runtime cannot be built, as `#[tokio::main]` does.
+pub fn build_runtime_named(worker_threads: usize, thread_name: &str) -> tokio::runtime::Runtime {
+    let thread_name = thread_name.to_string();
+    let next_id = AtomicUsize::new(0);
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads)
+        // Blocking threads share the counter, so every name is distinct.
+        .thread_name_fn(move || {
+            format!("{}-{}", thread_name, next_id.fetch_add(1, Ordering::SeqCst))
+        })
+        .enable_all()
+        .build()
+        .expect("Cannot build the Tokio runtime")
+}
+
+/// The kind of Tokio runtime a binary runs on, `multi` or `current` in
+/// flags and configuration files.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum RuntimeFlavor {
+    /// A pool of worker threads, as `#[tokio::main]` builds.
+    #[serde(rename = "multi")]
+    MultiThread,
+    /// Everything on the thread that calls `block_on`.
+    #[serde(rename = "current")]
+    CurrentThread,
+}
+
+impl std::str::FromStr for RuntimeFlavor {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "multi" => Ok(RuntimeFlavor::MultiThread),
+            "current" => Ok(RuntimeFlavor::CurrentThread),
+            other => Err(format!("Unknown runtime: {} (use multi or current)", other)),
+        }
+    }
+}
+
+/// Builds a runtime of `flavor`. A multi-threaded one has `worker_threads`
+/// workers named as `build_runtime_named` does; a current-thread one has no
+/// workers, so both are ignored.
+///
+/// # Panics
+///
+/// When the runtime cannot be built, as `#[tokio::main]` does.
+pub fn build_runtime(
+    flavor: RuntimeFlavor,
+    worker_threads: usize,
+    thread_name: &str,
+) -> tokio::runtime::Runtime {
+    match flavor {
+        RuntimeFlavor::MultiThread => build_runtime_named(worker_threads, thread_name),
+        RuntimeFlavor::CurrentThread => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Cannot build the Tokio runtime"),
+    }
+}
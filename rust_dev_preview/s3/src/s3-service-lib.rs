@@ -146,3 +146,90 @@ pub async fn create_bucket(client: &Client, bucket_name: &str, region: &str) ->
 }
 // snippet-end:[rust.example_code.s3.basics.create_bucket]
 // snippet-end:[rust.example_code.s3.scenario_getting_started.lib]
+
+pub mod adaptive;
+pub mod append_upload;
+pub mod bandwidth;
+pub mod batch;
+pub mod batch_operations;
+pub mod bisync;
+pub mod bucket_arn;
+pub mod bucket_encryption;
+pub mod bucket_tags;
+pub mod checkpoint;
+pub mod cli;
+pub mod config;
+pub mod connect;
+pub mod content_type;
+pub mod copy_prefix;
+pub mod cost;
+pub mod csv_upload;
+#[cfg(feature = "debug-tools")]
+pub mod debug_schedule;
+pub mod dir_marker;
+pub mod download;
+pub mod download_reader;
+pub mod durable;
+pub mod endpoint_template;
+pub mod error_hints;
+pub mod excludes;
+pub mod expected_sha256;
+pub mod expiry;
+pub mod express;
+pub mod failover;
+pub mod http2;
+pub mod idempotent_upload;
+pub mod integrity;
+pub mod inventory;
+pub mod jsonl;
+pub mod largest_objects;
+pub mod listing;
+pub mod manifest;
+pub mod memory_budget;
+pub mod merge;
+pub mod multipart_writer;
+pub mod notify;
+pub mod object_lock;
+pub mod ops;
+pub mod ownership;
+pub mod parallel_download;
+pub mod parallel_list;
+pub mod part_capture;
+pub mod part_size_cap;
+pub mod pipeline;
+pub mod preflight;
+pub mod preserve;
+pub mod presigned_upload;
+pub mod progress;
+pub mod publish;
+pub mod rate_limit;
+pub mod reencrypt;
+pub mod region_fallback;
+pub mod replication;
+pub mod request_timing;
+pub mod restore;
+pub mod resume;
+pub mod retry;
+pub mod role_credentials;
+pub mod run_summary;
+pub mod runtime;
+pub mod scheduler;
+pub mod self_test;
+pub mod shutdown;
+pub mod sigv4;
+pub mod signing_debug;
+pub mod soft_delete;
+pub mod split;
+pub mod staged_upload;
+pub mod stamp;
+pub mod sync;
+pub mod units;
+pub mod upload;
+pub mod upload_config;
+pub mod upload_from_s3;
+pub mod upload_status;
+pub mod upload_watch;
+pub mod verbosity;
+pub mod verified_put;
+pub mod warmup;
+pub mod zip_archive;
@@ -0,0 +1,723 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Uploads of many files that can be interrupted and resumed.
+//!
+//! Each file is uploaded with `PutObject` or, from the multipart threshold
+//! on, a multipart upload with its parts sent in order. On a `Shutdown`, no
+//! new file or part starts; the requests in flight finish, or are dropped on
+//! a hard stop. Multipart uploads left incomplete are aborted, and every
+//! file that was not uploaded is listed in a `ResumeManifest`, so a later
+//! run uploads exactly those files.
+//!
+//! Downloads can share a run with the uploads, within the same concurrency.
+//! Each object is read in ranges laid out as the parts of its upload would
+//! be, one range at a time, into a partial file renamed into place once
+//! complete, so the memory held by a run is bounded by the concurrency and
+//! the part size in both directions.
+//!
+//! With an `AdaptiveConcurrency` in the options, the number of files in
+//! flight follows it instead of being fixed, and every request reports to
+//! it whether it was throttled. With a `MemoryBudget`, every body and part
+//! is reserved from it before it is read.
+
+use crate::adaptive::{AdaptiveConcurrency, AdaptiveOps};
+use crate::error_hints::error_class;
+use crate::expected_sha256::Sha256Mismatch;
+use crate::memory_budget::{reserve, MemoryBudget};
+use crate::ops::{OpError, S3Ops};
+use crate::shutdown::Shutdown;
+use crate::sync::LocalFile;
+use crate::upload::{check_object_size, plan_upload, UploadPlanOptions, UploadStrategy};
+use aws_sdk_s3::Error;
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// Appended to the name of a file being downloaded.
+pub const PARTIAL_SUFFIX: &str = ".s3-partial";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledFile {
+    pub path: PathBuf,
+    pub key: String,
+    pub size: u64,
+}
+
+impl From<LocalFile> for ScheduledFile {
+    fn from(file: LocalFile) -> Self {
+        Self {
+            path: file.path,
+            key: file.key,
+            size: file.size,
+        }
+    }
+}
+
+/// An object to download to `path`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledDownload {
+    pub key: String,
+    pub path: PathBuf,
+    pub size: u64,
+    /// The ETag the object was listed with, without quotes. When set, every
+    /// range is read from that object only, and the download fails if it
+    /// is overwritten in the middle, instead of mixing the two.
+    pub e_tag: Option<String>,
+}
+
+/// One file to transfer in either direction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transfer {
+    Upload(ScheduledFile),
+    Download(ScheduledDownload),
+}
+
+#[derive(Debug, Clone)]
+pub struct SchedulerOptions {
+    /// Number of files transferred at the same time.
+    pub concurrency: usize,
+    pub plan: UploadPlanOptions,
+    /// When set, decides the number of files in flight in place of
+    /// `concurrency`. Its level carries over from one file to the next.
+    pub adaptive: Option<AdaptiveConcurrency>,
+    /// The SHA-256 each file must be read with, in lowercase hex, by path.
+    /// A file read with another fails with `Sha256Mismatch` and is not
+    /// stored.
+    pub expected_sha256: HashMap<PathBuf, String>,
+    /// Whether objects and parts are sent with their SHA-256 checksum for
+    /// S3 to verify, computed from the same reads.
+    pub checksum_sha256: bool,
+    /// Reserve each body and part from this budget before reading it, and
+    /// hold it until it is sent or written.
+    pub memory_budget: Option<MemoryBudget>,
+}
+
+impl Default for SchedulerOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            plan: UploadPlanOptions::default(),
+            adaptive: None,
+            expected_sha256: HashMap::new(),
+            checksum_sha256: false,
+            memory_budget: None,
+        }
+    }
+}
+
+/// A file that still has to be uploaded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingFile {
+    pub path: PathBuf,
+    pub key: String,
+    pub size: u64,
+    /// Bytes of the parts sent before the upload was interrupted. They are
+    /// sent again on resume, since the incomplete upload is aborted.
+    #[serde(default)]
+    pub bytes_transferred: u64,
+    /// Why the upload failed, when it was not interrupted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The class of `error`, as `error_hints::error_class`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_class: Option<String>,
+    /// The SHA-256 the file must be read with, from
+    /// `SchedulerOptions::expected_sha256`, so a resumed run checks it too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_sha256: Option<String>,
+}
+
+/// The files left to upload by an interrupted or failed run, as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResumeManifest {
+    pub bucket: String,
+    pub pending: Vec<PendingFile>,
+}
+
+impl ResumeManifest {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        serde_json::from_str(&content).map_err(|err| {
+            Error::Unhandled(Box::from(format!(
+                "Invalid resume manifest {}: {}",
+                path.display(),
+                err
+            )))
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(path, content).map_err(|err| Error::Unhandled(Box::new(err)))
+    }
+
+    /// The files to upload to resume the run.
+    pub fn files(&self) -> Vec<ScheduledFile> {
+        self.pending
+            .iter()
+            .map(|file| ScheduledFile {
+                path: file.path.clone(),
+                key: file.key.clone(),
+                size: file.size,
+            })
+            .collect()
+    }
+
+    /// The SHA-256 the pending files must be read with, by path, for
+    /// `SchedulerOptions::expected_sha256`.
+    pub fn expected_sha256(&self) -> HashMap<PathBuf, String> {
+        self.pending
+            .iter()
+            .filter_map(|file| Some((file.path.clone(), file.expected_sha256.clone()?)))
+            .collect()
+    }
+}
+
+/// Outcome of `upload_files`.
+#[derive(Debug, Default, Clone)]
+pub struct ScheduleSummary {
+    /// Keys of the uploaded files.
+    pub completed: Vec<String>,
+    /// ETags of the uploaded files, without quotes, by key.
+    pub e_tags: HashMap<String, String>,
+    /// Files not uploaded, interrupted or failed, by key.
+    pub pending: Vec<PendingFile>,
+    /// Keys of the multipart uploads that were aborted.
+    pub aborted_uploads: Vec<String>,
+    /// Keys of the downloaded objects.
+    pub downloaded: Vec<String>,
+    /// Downloads not completed, by key, with the error when they failed.
+    pub pending_downloads: Vec<(String, Option<String>)>,
+    /// Whether a shutdown was requested.
+    pub interrupted: bool,
+}
+
+impl ScheduleSummary {
+    /// The pending files that failed with an error.
+    pub fn failed(&self) -> impl Iterator<Item = &PendingFile> {
+        self.pending.iter().filter(|file| file.error.is_some())
+    }
+
+    pub fn resume_manifest(&self, bucket: &str) -> ResumeManifest {
+        ResumeManifest {
+            bucket: bucket.to_string(),
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+struct FileResult {
+    file: ScheduledFile,
+    completed: bool,
+    e_tag: Option<String>,
+    bytes_transferred: u64,
+    error: Option<String>,
+    error_class: Option<String>,
+    aborted_upload: bool,
+}
+
+impl FileResult {
+    fn new(file: ScheduledFile) -> Self {
+        Self {
+            file,
+            completed: false,
+            e_tag: None,
+            bytes_transferred: 0,
+            error: None,
+            error_class: None,
+            aborted_upload: false,
+        }
+    }
+
+    fn fail(&mut self, err: &(dyn StdError + 'static)) {
+        self.error = Some(err.to_string());
+        self.error_class = Some(error_class(err));
+    }
+}
+
+struct DownloadResult {
+    download: ScheduledDownload,
+    completed: bool,
+    error: Option<String>,
+}
+
+enum TransferResult {
+    Upload(FileResult),
+    Download(DownloadResult),
+}
+
+/// Uploads `files` to `bucket` until they are all done or `shutdown` stops
+/// the run.
+pub async fn upload_files<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    files: Vec<ScheduledFile>,
+    options: &SchedulerOptions,
+    shutdown: &Shutdown,
+) -> ScheduleSummary {
+    upload_files_with_hook(ops, bucket, files, options, shutdown, &|_, _| {}).await
+}
+
+/// As `upload_files`, calling `on_uploaded` with each file and its ETag as
+/// soon as it is uploaded, as for `checkpoint::CheckpointedUpload`.
+pub async fn upload_files_with_hook<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    files: Vec<ScheduledFile>,
+    options: &SchedulerOptions,
+    shutdown: &Shutdown,
+    on_uploaded: &(dyn Fn(&ScheduledFile, &str) + Sync),
+) -> ScheduleSummary {
+    let transfers = files.into_iter().map(Transfer::Upload).collect();
+    transfer_files_with_hook(ops, bucket, transfers, options, shutdown, on_uploaded).await
+}
+
+/// Uploads and downloads `transfers` until they are all done or `shutdown`
+/// stops the run, with at most `options.concurrency` files in flight in
+/// both directions together, or as many as `options.adaptive` allows.
+pub async fn transfer_files<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    transfers: Vec<Transfer>,
+    options: &SchedulerOptions,
+    shutdown: &Shutdown,
+) -> ScheduleSummary {
+    transfer_files_with_hook(ops, bucket, transfers, options, shutdown, &|_, _| {}).await
+}
+
+/// As `transfer_files`, calling `on_uploaded` as `upload_files_with_hook`.
+pub async fn transfer_files_with_hook<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    transfers: Vec<Transfer>,
+    options: &SchedulerOptions,
+    shutdown: &Shutdown,
+    on_uploaded: &(dyn Fn(&ScheduledFile, &str) + Sync),
+) -> ScheduleSummary {
+    let results = match &options.adaptive {
+        Some(adaptive) => {
+            let ops = AdaptiveOps::new(ops, adaptive);
+            let in_flight = adaptive.max();
+            run_transfers(
+                &ops,
+                bucket,
+                transfers,
+                in_flight,
+                options,
+                shutdown,
+                on_uploaded,
+            )
+            .await
+        }
+        None => {
+            let in_flight = options.concurrency;
+            run_transfers(
+                ops,
+                bucket,
+                transfers,
+                in_flight,
+                options,
+                shutdown,
+                on_uploaded,
+            )
+            .await
+        }
+    };
+
+    let mut summary = ScheduleSummary {
+        interrupted: shutdown.is_stopping(),
+        ..Default::default()
+    };
+    for result in results {
+        let result = match result {
+            TransferResult::Upload(result) => result,
+            TransferResult::Download(result) => {
+                if result.completed {
+                    summary.downloaded.push(result.download.key);
+                } else {
+                    summary
+                        .pending_downloads
+                        .push((result.download.key, result.error));
+                }
+                continue;
+            }
+        };
+        if result.aborted_upload {
+            summary.aborted_uploads.push(result.file.key.clone());
+        }
+        if result.completed {
+            if let Some(e_tag) = result.e_tag {
+                summary.e_tags.insert(result.file.key.clone(), e_tag);
+            }
+            summary.completed.push(result.file.key);
+        } else {
+            summary.pending.push(PendingFile {
+                expected_sha256: options.expected_sha256.get(&result.file.path).cloned(),
+                path: result.file.path,
+                key: result.file.key,
+                size: result.file.size,
+                bytes_transferred: result.bytes_transferred,
+                error: result.error,
+                error_class: result.error_class,
+            });
+        }
+    }
+    summary.pending.sort_by(|a, b| a.key.cmp(&b.key));
+    summary.pending_downloads.sort();
+    summary
+}
+
+/// The transfers of `transfer_files_with_hook`, `in_flight` at a time, in
+/// the order they finish.
+async fn run_transfers<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    transfers: Vec<Transfer>,
+    in_flight: usize,
+    options: &SchedulerOptions,
+    shutdown: &Shutdown,
+    on_uploaded: &(dyn Fn(&ScheduledFile, &str) + Sync),
+) -> Vec<TransferResult> {
+    stream::iter(transfers)
+        .map(|transfer| async move {
+            let _permit = match &options.adaptive {
+                Some(adaptive) => Some(adaptive.acquire().await),
+                None => None,
+            };
+            match transfer {
+                Transfer::Upload(file) => {
+                    let result = upload_file(ops, bucket, file, options, shutdown).await;
+                    if result.completed {
+                        on_uploaded(&result.file, result.e_tag.as_deref().unwrap_or_default());
+                    }
+                    TransferResult::Upload(result)
+                }
+                Transfer::Download(download) => TransferResult::Download(
+                    download_file(ops, bucket, download, options, shutdown).await,
+                ),
+            }
+        })
+        .buffer_unordered(in_flight.max(1))
+        .collect::<Vec<_>>()
+        .await
+}
+
+/// The `Sha256Mismatch` of the file at `path` read with the SHA-256
+/// `actual`, when it was expected to have another.
+fn sha256_mismatch(
+    path: &Path,
+    expected: Option<&String>,
+    actual: String,
+) -> Option<Sha256Mismatch> {
+    expected
+        .filter(|expected| **expected != actual)
+        .map(|expected| Sha256Mismatch {
+            path: path.to_path_buf(),
+            expected: expected.clone(),
+            actual,
+        })
+}
+
+async fn upload_file<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    file: ScheduledFile,
+    options: &SchedulerOptions,
+    shutdown: &Shutdown,
+) -> FileResult {
+    let mut result = FileResult::new(file);
+    if shutdown.is_stopping() {
+        return result;
+    }
+    let key = result.file.key.clone();
+    if let Err(err) = check_object_size(result.file.size) {
+        result.fail(&err);
+        return result;
+    }
+    let expected = options.expected_sha256.get(&result.file.path);
+    let plan = plan_upload(result.file.size, &options.plan);
+    if plan.strategy == UploadStrategy::PutObject {
+        let _reservation = tokio::select! {
+            reservation = reserve(options.memory_budget.as_ref(), result.file.size) => reservation,
+            _ = shutdown.aborted() => return result,
+        };
+        let body = match tokio::fs::read(&result.file.path).await {
+            Ok(body) => body,
+            Err(err) => {
+                result.fail(&err);
+                return result;
+            }
+        };
+        let digest = (expected.is_some() || options.checksum_sha256).then(|| Sha256::digest(&body));
+        if let Some(digest) = &digest {
+            let actual = format!("{:x}", digest);
+            if let Some(err) = sha256_mismatch(&result.file.path, expected, actual) {
+                result.fail(&err);
+                return result;
+            }
+        }
+        let put = match digest.filter(|_| options.checksum_sha256) {
+            Some(digest) => ops.put_object_sha256(bucket, &key, body, base64::encode(digest)),
+            None => ops.put_object(bucket, &key, body),
+        };
+        tokio::select! {
+            put = put => match put {
+                Ok(e_tag) => {
+                    result.completed = true;
+                    result.e_tag = Some(e_tag);
+                }
+                Err(err) => result.fail(&err),
+            },
+            _ = shutdown.aborted() => {}
+        }
+        return result;
+    }
+
+    let create = if options.checksum_sha256 {
+        ops.create_multipart_upload_sha256(bucket, &key)
+    } else {
+        ops.create_multipart_upload(bucket, &key)
+    };
+    let upload_id = tokio::select! {
+        created = create => match created {
+            Ok(upload_id) => upload_id,
+            Err(err) => {
+                result.fail(&err);
+                return result;
+            }
+        },
+        _ = shutdown.aborted() => return result,
+    };
+
+    let mut parts = Vec::with_capacity(plan.num_parts);
+    let mut checksums = Vec::new();
+    // The parts are read in order, so the digest of the file is computed
+    // from the bytes sent.
+    let mut whole = expected.map(|_| Sha256::new());
+    match tokio::fs::File::open(&result.file.path).await {
+        Ok(mut local) => {
+            for index in 0..plan.num_parts {
+                if shutdown.is_stopping() {
+                    break;
+                }
+                let part_number = index as i32 + 1;
+                let size = if index + 1 == plan.num_parts {
+                    plan.last_part_size
+                } else {
+                    plan.part_size
+                };
+                let _reservation = tokio::select! {
+                    reservation = reserve(options.memory_budget.as_ref(), size) => reservation,
+                    _ = shutdown.aborted() => break,
+                };
+                let mut body = vec![0; size as usize];
+                let read = async {
+                    local
+                        .seek(std::io::SeekFrom::Start(index as u64 * plan.part_size))
+                        .await?;
+                    local.read_exact(&mut body).await
+                };
+                if let Err(err) = read.await {
+                    result.fail(&err);
+                    break;
+                }
+                if let Some(whole) = whole.as_mut() {
+                    whole.update(&body);
+                }
+                let checksum = options
+                    .checksum_sha256
+                    .then(|| base64::encode(Sha256::digest(&body)));
+                let send = match checksum.clone() {
+                    Some(checksum) => ops.upload_part_sha256(
+                        bucket,
+                        &key,
+                        &upload_id,
+                        part_number,
+                        body,
+                        checksum,
+                    ),
+                    None => ops.upload_part(bucket, &key, &upload_id, part_number, body),
+                };
+                tokio::select! {
+                    sent = send => match sent {
+                        Ok(e_tag) => {
+                            parts.push((part_number, e_tag));
+                            checksums.extend(checksum);
+                            result.bytes_transferred += size;
+                        }
+                        Err(err) => {
+                            result.fail(&err);
+                            break;
+                        }
+                    },
+                    _ = shutdown.aborted() => break,
+                }
+            }
+        }
+        Err(err) => result.fail(&err),
+    }
+
+    if parts.len() == plan.num_parts {
+        let actual = whole.map(|whole| format!("{:x}", whole.finalize()));
+        let mismatch =
+            actual.and_then(|actual| sha256_mismatch(&result.file.path, expected, actual));
+        if let Some(err) = mismatch {
+            result.fail(&err);
+        } else {
+            let complete = if options.checksum_sha256 {
+                let parts = parts
+                    .into_iter()
+                    .zip(checksums)
+                    .map(|((part_number, e_tag), checksum)| (part_number, e_tag, checksum))
+                    .collect();
+                ops.complete_multipart_upload_sha256(bucket, &key, &upload_id, parts)
+            } else {
+                ops.complete_multipart_upload(bucket, &key, &upload_id, parts)
+            };
+            tokio::select! {
+                completed = complete => match completed {
+                    Ok(e_tag) => {
+                        result.completed = true;
+                        result.e_tag = Some(e_tag);
+                        return result;
+                    }
+                    Err(err) => result.fail(&err),
+                },
+                _ = shutdown.aborted() => {}
+            }
+        }
+    }
+
+    // The parts already sent would be billed until the upload is aborted;
+    // the file starts over on resume.
+    if let Err(err) = ops.abort_multipart_upload(bucket, &key, &upload_id).await {
+        eprintln!("Could not abort upload {} of {}: {}", upload_id, key, err);
+    }
+    result.aborted_upload = true;
+    result
+}
+
+/// The file `path` is downloaded to before it is complete.
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(PARTIAL_SUFFIX);
+    path.with_file_name(name)
+}
+
+async fn download_file<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    download: ScheduledDownload,
+    options: &SchedulerOptions,
+    shutdown: &Shutdown,
+) -> DownloadResult {
+    let mut result = DownloadResult {
+        download,
+        completed: false,
+        error: None,
+    };
+    if shutdown.is_stopping() {
+        return result;
+    }
+    // A download that does not finish leaves the destination untouched.
+    let partial = partial_path(&result.download.path);
+    match download_parts(ops, bucket, &result.download, &partial, options, shutdown).await {
+        Ok(true) => match tokio::fs::rename(&partial, &result.download.path).await {
+            Ok(()) => result.completed = true,
+            Err(err) => result.error = Some(err.to_string()),
+        },
+        Ok(false) => {}
+        Err(err) => result.error = Some(err),
+    }
+    if !result.completed {
+        let _ = tokio::fs::remove_file(&partial).await;
+    }
+    result
+}
+
+async fn read_range<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    download: &ScheduledDownload,
+    offset: u64,
+    length: u64,
+) -> Result<Vec<u8>, OpError> {
+    match &download.e_tag {
+        Some(e_tag) => {
+            ops.get_object_range_if_match(bucket, &download.key, offset, length, e_tag)
+                .await
+        }
+        None => {
+            ops.get_object_range(bucket, &download.key, offset, length)
+                .await
+        }
+    }
+}
+
+/// Writes the object to `partial` range by range. Returns `false` when
+/// `shutdown` stopped the download.
+async fn download_parts<O: S3Ops + ?Sized>(
+    ops: &O,
+    bucket: &str,
+    download: &ScheduledDownload,
+    partial: &Path,
+    options: &SchedulerOptions,
+    shutdown: &Shutdown,
+) -> Result<bool, String> {
+    if let Some(parent) = partial.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    let mut local = tokio::fs::File::create(partial)
+        .await
+        .map_err(|err| err.to_string())?;
+    let plan = plan_upload(download.size, &options.plan);
+    for index in 0..plan.num_parts {
+        if shutdown.is_stopping() {
+            return Ok(false);
+        }
+        let size = if index + 1 == plan.num_parts {
+            plan.last_part_size
+        } else {
+            plan.part_size
+        };
+        if size == 0 {
+            continue;
+        }
+        let offset = index as u64 * plan.part_size;
+        let _reservation = tokio::select! {
+            reservation = reserve(options.memory_budget.as_ref(), size) => reservation,
+            _ = shutdown.aborted() => return Ok(false),
+        };
+        let body = tokio::select! {
+            read = read_range(ops, bucket, download, offset, size) => {
+                read.map_err(|err| err.to_string())?
+            }
+            _ = shutdown.aborted() => return Ok(false),
+        };
+        if body.len() as u64 != size {
+            return Err(format!(
+                "Expected {} bytes of {} from offset {}, received {}",
+                size,
+                download.key,
+                offset,
+                body.len()
+            ));
+        }
+        local
+            .write_all(&body)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    local.flush().await.map_err(|err| err.to_string())?;
+    Ok(true)
+}
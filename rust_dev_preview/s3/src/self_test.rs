@@ -0,0 +1,383 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! A one-shot health check of a bucket, and of the endpoint serving it, that
+//! goes through the whole transfer pipeline under a scratch prefix.
+//!
+//! A deterministic file is uploaded with a single PutObject and with a
+//! three-part multipart upload, and each object is downloaded back with one
+//! GetObject and in parallel ranges and compared byte for byte. A multipart
+//! upload is then started and aborted, and must no longer be listed. Each
+//! stage has a timeout, so a hung endpoint fails the check instead of
+//! stalling it, and the objects and uploads created are deleted whatever
+//! happened.
+
+use crate::parallel_download::{download_parallel, ParallelDownloadOptions};
+use crate::units::format_duration;
+use crate::upload::{upload_chunk, upload_multipart};
+use crate::upload_watch::list_uploads;
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Error};
+use serde::Serialize;
+use std::future::Future;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Options of a self-test.
+#[derive(Debug, Clone)]
+pub struct SelfTestOptions {
+    /// The size of the file uploaded. Each of the three parts of the
+    /// multipart upload must be at least 5 MiB, except on endpoints that do
+    /// not enforce the minimum.
+    pub file_size: u64,
+    /// How long each stage may take.
+    pub stage_timeout: Duration,
+}
+
+impl Default for SelfTestOptions {
+    fn default() -> Self {
+        Self {
+            file_size: 16 * 1024 * 1024,
+            stage_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The outcome of one stage.
+#[derive(Debug, Clone, Serialize)]
+pub struct StageResult {
+    pub name: String,
+    pub passed: bool,
+    pub elapsed_ms: u128,
+    pub error: Option<String>,
+}
+
+/// The outcome of each stage, in the order they ran.
+#[derive(Debug, Default, Serialize)]
+pub struct SelfTestReport {
+    /// The prefix the objects were created under.
+    pub prefix: String,
+    pub stages: Vec<StageResult>,
+}
+
+impl SelfTestReport {
+    pub fn is_ok(&self) -> bool {
+        self.stages.iter().all(|stage| stage.passed)
+    }
+
+    /// One line per stage, such as `PASS  put-object (35 ms)`.
+    pub fn to_text(&self) -> String {
+        self.stages
+            .iter()
+            .map(|stage| {
+                let mut line = format!(
+                    "{}  {} ({} ms)",
+                    if stage.passed { "PASS" } else { "FAIL" },
+                    stage.name,
+                    stage.elapsed_ms
+                );
+                if let Some(error) = &stage.error {
+                    line.push_str(&format!(": {}", error));
+                }
+                line.push('\n');
+                line
+            })
+            .collect()
+    }
+
+    /// Runs `stage` as `name` within `timeout`, records its outcome, and
+    /// returns its value if it passed.
+    async fn run<T>(
+        &mut self,
+        name: &str,
+        timeout: Duration,
+        stage: impl Future<Output = Result<T, Error>>,
+    ) -> Option<T> {
+        let start = Instant::now();
+        let (value, error) = match tokio::time::timeout(timeout, stage).await {
+            Ok(Ok(value)) => (Some(value), None),
+            Ok(Err(err)) => (None, Some(err.to_string())),
+            Err(_) => (
+                None,
+                Some(format!("timed out after {}", format_duration(timeout))),
+            ),
+        };
+        self.stages.push(StageResult {
+            name: name.to_string(),
+            passed: error.is_none(),
+            elapsed_ms: start.elapsed().as_millis(),
+            error,
+        });
+        value
+    }
+
+    fn skip(&mut self, name: &str, reason: &str) {
+        self.stages.push(StageResult {
+            name: name.to_string(),
+            passed: false,
+            elapsed_ms: 0,
+            error: Some(format!("skipped, {}", reason)),
+        });
+    }
+}
+
+/// Deterministic content for the file uploaded: a xorshift sequence, which
+/// unlike a repeating pattern shows bytes written at the wrong offset.
+pub fn test_data(size: u64) -> Vec<u8> {
+    let mut state: u32 = 0x9E37_79B9;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// Runs the self-test under `prefix` in `bucket`. Each run uses its own
+/// sub-prefix, so that concurrent runs do not interfere.
+///
+/// Only failing to create the local file is an error; the failures of the
+/// stages are in the report.
+pub async fn self_test(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    options: &SelfTestOptions,
+) -> Result<SelfTestReport, Error> {
+    let run_prefix = format!("{}{}/", prefix, uuid::Uuid::new_v4());
+    let dir = std::env::temp_dir().join(format!("s3-self-test-{}", uuid::Uuid::new_v4()));
+    let data = test_data(options.file_size);
+    let source = dir.join("source");
+    std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(&source, &data))
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+
+    let report = run_stages(client, bucket, &run_prefix, &dir, &source, &data, options).await;
+    // The local files are only scratch copies.
+    let _ = std::fs::remove_dir_all(&dir);
+    Ok(report)
+}
+
+async fn run_stages(
+    client: &Client,
+    bucket: &str,
+    run_prefix: &str,
+    dir: &Path,
+    source: &Path,
+    data: &[u8],
+    options: &SelfTestOptions,
+) -> SelfTestReport {
+    let timeout = options.stage_timeout;
+    let source_name = source.to_string_lossy();
+    let put_key = format!("{}put-object", run_prefix);
+    let multipart_key = format!("{}multipart-upload", run_prefix);
+    let abort_key = format!("{}aborted-upload", run_prefix);
+    let mut report = SelfTestReport {
+        prefix: run_prefix.to_string(),
+        ..Default::default()
+    };
+
+    let put = report
+        .run(
+            "put-object",
+            timeout,
+            upload_chunk(
+                client,
+                bucket,
+                &put_key,
+                &source_name,
+                0,
+                data.len() as u64,
+                None,
+            ),
+        )
+        .await;
+    let multipart = report
+        .run(
+            "multipart-upload",
+            timeout,
+            upload_multipart(client, bucket, &multipart_key, &source_name, 3, None, None),
+        )
+        .await;
+
+    let uploaded = [
+        ("put-object", &put_key, put.is_some()),
+        ("multipart-upload", &multipart_key, multipart.is_some()),
+    ];
+    for (upload, key, ok) in uploaded.iter() {
+        let name = format!("get-object {}", upload);
+        if !ok {
+            report.skip(&name, "the upload failed");
+            continue;
+        }
+        report
+            .run(&name, timeout, async {
+                let resp = client.get_object().bucket(bucket).key(*key).send().await?;
+                let body = resp
+                    .body
+                    .collect()
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                compare(data, &body.into_bytes())
+            })
+            .await;
+    }
+    for (upload, key, ok) in uploaded.iter() {
+        let name = format!("ranged-get {}", upload);
+        if !ok {
+            report.skip(&name, "the upload failed");
+            continue;
+        }
+        let path = dir.join(format!("{}.download", upload));
+        report
+            .run(
+                &name,
+                timeout,
+                ranged_round_trip(client, bucket, key, &path, data),
+            )
+            .await;
+    }
+
+    report
+        .run("abort-upload", timeout, abort(client, bucket, &abort_key))
+        .await;
+
+    // A stage that timed out may have left its object or its upload behind.
+    report
+        .run(
+            "cleanup",
+            timeout,
+            cleanup(
+                client,
+                bucket,
+                &[&put_key, &multipart_key],
+                &[&multipart_key, &abort_key],
+            ),
+        )
+        .await;
+    report
+}
+
+/// Deletes the objects at `objects` and aborts the uploads in progress to
+/// `uploads`. Every step is tried; their errors are returned together.
+async fn cleanup(
+    client: &Client,
+    bucket: &str,
+    objects: &[&str],
+    uploads: &[&str],
+) -> Result<(), Error> {
+    let mut errors = Vec::new();
+    for key in objects {
+        if let Err(err) = client.delete_object().bucket(bucket).key(*key).send().await {
+            errors.push(format!("deleting {}: {}", key, err));
+        }
+    }
+    for key in uploads {
+        let listed = match list_uploads(client, bucket, key).await {
+            Ok(listed) => listed,
+            Err(err) => {
+                errors.push(format!("listing the uploads of {}: {}", key, err));
+                continue;
+            }
+        };
+        for upload in listed {
+            let aborted = client
+                .abort_multipart_upload()
+                .bucket(bucket)
+                .key(*key)
+                .upload_id(&upload.upload_id)
+                .send()
+                .await;
+            if let Err(err) = aborted {
+                errors.push(format!(
+                    "aborting upload {} of {}: {}",
+                    upload.upload_id, key, err
+                ));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Unhandled(Box::from(errors.join("; "))))
+    }
+}
+
+fn compare(expected: &[u8], actual: &[u8]) -> Result<(), Error> {
+    if expected.len() != actual.len() {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} bytes downloaded instead of {}",
+            actual.len(),
+            expected.len()
+        ))));
+    }
+    match expected.iter().zip(actual).position(|(a, b)| a != b) {
+        None => Ok(()),
+        Some(offset) => Err(Error::Unhandled(Box::from(format!(
+            "the bytes downloaded differ from offset {}",
+            offset
+        )))),
+    }
+}
+
+/// Downloads `key` in four ranges to `path` and compares it with `data`.
+async fn ranged_round_trip(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    path: &Path,
+    data: &[u8],
+) -> Result<(), Error> {
+    let options = ParallelDownloadOptions {
+        part_size: (data.len() as u64 / 4).max(1),
+        concurrency: 4,
+        ..Default::default()
+    };
+    download_parallel(client, bucket, key, path, &options).await?;
+    let downloaded = std::fs::read(path).map_err(|err| Error::Unhandled(Box::new(err)))?;
+    compare(data, &downloaded)
+}
+
+/// Starts a multipart upload of `key` with one part, aborts it, and checks
+/// that it is no longer listed.
+async fn abort(client: &Client, bucket: &str, key: &str) -> Result<(), Error> {
+    let upload = client
+        .create_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await?;
+    let upload_id = upload
+        .upload_id()
+        .ok_or_else(|| Error::Unhandled(Box::from("No upload ID")))?;
+    let part = client
+        .upload_part()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .part_number(1)
+        .body(ByteStream::from_static(b"aborted"))
+        .send()
+        .await;
+    let aborted = client
+        .abort_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .send()
+        .await;
+    part?;
+    aborted?;
+    let listed = list_uploads(client, bucket, key).await?;
+    if listed.iter().any(|upload| upload.upload_id == upload_id) {
+        return Err(Error::Unhandled(Box::from(format!(
+            "upload {} is still listed after it was aborted",
+            upload_id
+        ))));
+    }
+    Ok(())
+}
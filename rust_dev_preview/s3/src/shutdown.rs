@@ -0,0 +1,212 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Two-phase shutdown of long-running transfers.
+//!
+//! The first request to stop is graceful: no new work starts, and the
+//! requests in flight get `grace_period` to finish. The second request, or
+//! the end of the grace period, is a hard stop: the work in flight is
+//! dropped and cleaned up.
+//!
+//! A stop is requested with Ctrl-C (SIGINT) or, on Unix, SIGTERM, which is
+//! what Kubernetes and systemd send before they kill a process. Give the
+//! grace period a little less than theirs, 30 seconds by default for a pod,
+//! so that the cleanup runs before the process is killed.
+
+use crate::units::format_duration;
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Grace period used when none is given.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// A shutdown signal shared by a transfer and whatever requests its stop.
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    stop: CancellationToken,
+    abort: CancellationToken,
+    grace_period: Duration,
+    requests: Arc<AtomicU32>,
+    signal: Arc<Mutex<Option<StopSignal>>>,
+}
+
+impl Shutdown {
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            stop: CancellationToken::new(),
+            abort: CancellationToken::new(),
+            grace_period,
+            requests: Arc::new(AtomicU32::new(0)),
+            signal: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Requests a stop: graceful the first time, hard the second time.
+    pub fn trigger(&self) {
+        if self.requests.fetch_add(1, Ordering::SeqCst) == 0 {
+            self.stop.cancel();
+            let abort = self.abort.clone();
+            let grace_period = self.grace_period;
+            tokio::spawn(async move {
+                tokio::time::sleep(grace_period).await;
+                abort.cancel();
+            });
+        } else {
+            self.abort.cancel();
+        }
+    }
+
+    /// Whether new work should no longer start.
+    pub fn is_stopping(&self) -> bool {
+        self.stop.is_cancelled()
+    }
+
+    /// Whether the work in flight should be dropped.
+    pub fn is_aborted(&self) -> bool {
+        self.abort.is_cancelled()
+    }
+
+    /// Completes when a stop is requested.
+    pub async fn stopping(&self) {
+        self.stop.cancelled().await
+    }
+
+    /// Completes on a hard stop.
+    pub async fn aborted(&self) {
+        self.abort.cancelled().await
+    }
+
+    /// The signal that first requested a stop, if one did.
+    pub fn signal(&self) -> Option<StopSignal> {
+        *self.signal.lock().unwrap()
+    }
+
+    /// Calls `trigger` on each Ctrl-C, for the life of the runtime.
+    pub fn listen_for_ctrl_c(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if shutdown.is_stopping() {
+                    eprintln!("Stopping now, aborting the transfers in flight");
+                } else {
+                    eprintln!(
+                        "Stopping after the requests in flight (up to {}); \
+                         press Ctrl-C again to stop now",
+                        format_duration(shutdown.grace_period)
+                    );
+                }
+                shutdown.trigger();
+            }
+        });
+    }
+
+    /// Calls `trigger` on each Ctrl-C or SIGTERM, for the life of the
+    /// runtime.
+    pub fn listen_for_signals(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            let mut signals = match ShutdownSignal::new() {
+                Ok(signals) => signals,
+                Err(err) => {
+                    eprintln!("Cannot listen for SIGTERM: {}", err);
+                    return shutdown.listen_for_ctrl_c();
+                }
+            };
+            while let Ok(signal) = signals.recv().await {
+                shutdown.signal.lock().unwrap().get_or_insert(signal);
+                if shutdown.is_stopping() {
+                    eprintln!("{}: stopping now, aborting the transfers in flight", signal);
+                } else {
+                    eprintln!(
+                        "{}: stopping after the requests in flight (up to {}); \
+                         signal again to stop now",
+                        signal,
+                        format_duration(shutdown.grace_period)
+                    );
+                }
+                shutdown.trigger();
+            }
+        });
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new(DEFAULT_GRACE_PERIOD)
+    }
+}
+
+/// A signal that requested a stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopSignal {
+    /// SIGINT, from Ctrl-C.
+    Interrupt,
+    /// SIGTERM.
+    Terminate,
+}
+
+impl StopSignal {
+    /// The exit code of a process stopped by the signal: 128 plus its
+    /// number, as a shell reports it.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            StopSignal::Interrupt => 130,
+            StopSignal::Terminate => 143,
+        }
+    }
+}
+
+impl std::fmt::Display for StopSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopSignal::Interrupt => write!(f, "SIGINT"),
+            StopSignal::Terminate => write!(f, "SIGTERM"),
+        }
+    }
+}
+
+/// Listens for Ctrl-C and, on Unix, SIGTERM.
+///
+/// Once created, SIGTERM no longer kills the process: it is only reported
+/// by `recv`.
+#[derive(Debug)]
+pub struct ShutdownSignal {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl ShutdownSignal {
+    #[cfg(unix)]
+    pub fn new() -> io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self {
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {})
+    }
+
+    /// Waits for the next signal.
+    #[cfg(unix)]
+    pub async fn recv(&mut self) -> io::Result<StopSignal> {
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| StopSignal::Interrupt),
+            _ = self.terminate.recv() => Ok(StopSignal::Terminate),
+        }
+    }
+
+    /// Waits for the next signal.
+    #[cfg(not(unix))]
+    pub async fn recv(&mut self) -> io::Result<StopSignal> {
+        tokio::signal::ctrl_c().await.map(|_| StopSignal::Interrupt)
+    }
+}
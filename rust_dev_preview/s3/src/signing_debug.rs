@@ -0,0 +1,308 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Diagnostics for endpoints that reject our signatures.
+//!
+//! When an S3-compatible endpoint answers SignatureDoesNotMatch, the cause
+//! is usually a difference between the request the SDK signed and the one
+//! the endpoint checked: a proxy rewriting the host, a path encoded twice, a
+//! header dropped on the way. A `SigningDebug` connector sits under the
+//! SDK's retries, so it sees every attempt as sent, and prints the elements
+//! of the canonical request that the signature covers: the method, the
+//! canonical URI and query, the signed headers with their values, the
+//! credential scope, and how the payload was hashed, followed by the error
+//! body of the endpoint.
+//!
+//! The signature, the access key ID, and the session token are redacted, in
+//! the request and in the error body, so the output can be shared.
+
+use crate::connect::https_adapter;
+use aws_sdk_s3::Client;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use hyper::service::Service;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+const REDACTED: &str = "<redacted>";
+
+/// Elements of the error bodies that carry credentials or signatures.
+const REDACTED_ELEMENTS: &[&str] = &["AWSAccessKeyId", "SignatureProvided"];
+
+/// Which requests are described.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DebugSigningMode {
+    /// Requests answered with a 4xx or 5xx status.
+    Failures,
+    All,
+}
+
+impl FromStr for DebugSigningMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "failures" => Ok(DebugSigningMode::Failures),
+            "all" => Ok(DebugSigningMode::All),
+            _ => Err(format!(
+                "Invalid signing debug mode: {} (expected failures or all)",
+                value
+            )),
+        }
+    }
+}
+
+/// Where the diagnostics go: stderr, or a buffer that tests read.
+#[derive(Debug, Clone)]
+pub struct SigningDebugger {
+    mode: DebugSigningMode,
+    captured: Option<Arc<Mutex<Vec<String>>>>,
+}
+
+impl SigningDebugger {
+    /// Prints to stderr.
+    pub fn new(mode: DebugSigningMode) -> Self {
+        Self {
+            mode,
+            captured: None,
+        }
+    }
+
+    /// Keeps the blocks for `blocks` instead of printing them.
+    pub fn capturing(mode: DebugSigningMode) -> Self {
+        Self {
+            mode,
+            captured: Some(Arc::new(Mutex::new(Vec::new()))),
+        }
+    }
+
+    pub fn mode(&self) -> DebugSigningMode {
+        self.mode
+    }
+
+    /// The blocks kept by a capturing debugger, oldest first.
+    pub fn blocks(&self) -> Vec<String> {
+        self.captured
+            .as_ref()
+            .map(|captured| captured.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    fn emit(&self, block: String) {
+        match &self.captured {
+            Some(captured) => captured.lock().unwrap().push(block),
+            None => eprint!("{}", block),
+        }
+    }
+}
+
+/// What the signature of a request covered, taken from the request as sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedRequest {
+    pub method: String,
+    pub canonical_uri: String,
+    /// The query parameters, sorted as in the canonical request.
+    pub canonical_query: String,
+    /// The names of the signed headers, in order, with their values.
+    pub signed_headers: Vec<(String, String)>,
+    /// Date, Region, and service, without the access key ID.
+    pub credential_scope: Option<String>,
+    pub payload_hash: String,
+}
+
+impl SignedRequest {
+    pub fn from_parts(method: &http::Method, uri: &http::Uri, headers: &http::HeaderMap) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+        };
+        let authorization = header("authorization").unwrap_or_default();
+        let field = |name: &str| {
+            authorization
+                .split(|c| c == ' ' || c == ',')
+                .find_map(|part| part.strip_prefix(name))
+                .map(|value| value.to_string())
+        };
+        let credential_scope = field("Credential=").map(|credential| {
+            // ACCESS-KEY-ID/date/region/service/aws4_request
+            credential
+                .splitn(2, '/')
+                .nth(1)
+                .unwrap_or_default()
+                .to_string()
+        });
+        let signed_headers = field("SignedHeaders=")
+            .unwrap_or_default()
+            .split(';')
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let value = match name {
+                    "authorization" | "x-amz-security-token" => REDACTED.to_string(),
+                    // The host of HTTP/2 requests is in the URI only.
+                    "host" => header(name)
+                        .or_else(|| uri.authority().map(|a| a.to_string()))
+                        .unwrap_or_default(),
+                    _ => header(name).unwrap_or_default(),
+                };
+                (name.to_string(), value)
+            })
+            .collect();
+        let payload_hash = match header("x-amz-content-sha256").as_deref() {
+            None => "not sent".to_string(),
+            Some("UNSIGNED-PAYLOAD") => "UNSIGNED-PAYLOAD".to_string(),
+            Some(hash) if hash.starts_with("STREAMING-") => hash.to_string(),
+            Some(hash) => format!("signed, SHA-256 {}", hash),
+        };
+        let mut query: Vec<&str> = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .collect();
+        query.sort_unstable();
+        Self {
+            method: method.to_string(),
+            canonical_uri: uri.path().to_string(),
+            canonical_query: query.join("&"),
+            signed_headers,
+            credential_scope,
+            payload_hash,
+        }
+    }
+}
+
+impl fmt::Display for SignedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "method:           {}", self.method)?;
+        writeln!(f, "canonical URI:    {}", self.canonical_uri)?;
+        writeln!(f, "canonical query:  {}", self.canonical_query)?;
+        writeln!(
+            f,
+            "credential scope: {}",
+            self.credential_scope.as_deref().unwrap_or("(not signed)")
+        )?;
+        let names: Vec<&str> = self
+            .signed_headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        writeln!(f, "signed headers:   {}", names.join(";"))?;
+        for (name, value) in &self.signed_headers {
+            writeln!(f, "  {}: {}", name, value)?;
+        }
+        writeln!(f, "payload hash:     {}", self.payload_hash)?;
+        writeln!(f, "signature:        {}", REDACTED)
+    }
+}
+
+/// `body` with the content of the elements that carry credentials replaced.
+pub fn redact_error_body(body: &str) -> String {
+    let mut body = body.to_string();
+    for element in REDACTED_ELEMENTS {
+        let open = format!("<{}>", element);
+        let close = format!("</{}>", element);
+        let mut from = 0;
+        while let Some(start) = body[from..].find(&open).map(|i| from + i + open.len()) {
+            let end = match body[start..].find(&close) {
+                Some(i) => start + i,
+                None => break,
+            };
+            body.replace_range(start..end, REDACTED);
+            from = start + REDACTED.len() + close.len();
+        }
+    }
+    body
+}
+
+/// One diagnostic block.
+fn describe(request: &SignedRequest, status: http::StatusCode, body: Option<&str>) -> String {
+    let mut block = String::new();
+    let _ = writeln!(
+        block,
+        "--- signing debug: {} {} -> {} ---",
+        request.method, request.canonical_uri, status
+    );
+    let _ = write!(block, "{}", request);
+    if let Some(body) = body {
+        let _ = writeln!(block, "error body:");
+        let _ = writeln!(block, "{}", redact_error_body(body.trim_end()));
+    }
+    let _ = writeln!(block, "--- end of signing debug ---");
+    block
+}
+
+/// A connector that describes the requests `debugger` asks for. Without a
+/// debugger, requests go through untouched.
+#[derive(Debug, Clone)]
+pub struct SigningDebug<C> {
+    inner: C,
+    debugger: Option<SigningDebugger>,
+}
+
+impl<C> SigningDebug<C> {
+    pub fn new(inner: C, debugger: Option<SigningDebugger>) -> Self {
+        Self { inner, debugger }
+    }
+}
+
+impl<C, B, RB> Service<http::Request<B>> for SigningDebug<C>
+where
+    C: Service<http::Request<B>, Response = http::Response<RB>> + Clone + Send + 'static,
+    C::Future: Send + 'static,
+    B: Send + 'static,
+    RB: hyper::body::HttpBody + From<Bytes> + Send + 'static,
+    RB::Data: Send,
+    RB::Error: fmt::Display,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<C::Response, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        // As in `RateLimited`, the connector made ready serves this request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let debugger = match &self.debugger {
+            Some(debugger) => debugger.clone(),
+            None => return Box::pin(inner.call(request)),
+        };
+        let signed = SignedRequest::from_parts(request.method(), request.uri(), request.headers());
+        Box::pin(async move {
+            let response = inner.call(request).await?;
+            let status = response.status();
+            let failed = status.is_client_error() || status.is_server_error();
+            if !failed {
+                if debugger.mode() == DebugSigningMode::All {
+                    debugger.emit(describe(&signed, status, None));
+                }
+                return Ok(response);
+            }
+            // Error bodies are small, so they are read whole and handed on.
+            let (parts, body) = response.into_parts();
+            let (bytes, text) = match hyper::body::to_bytes(body).await {
+                Ok(bytes) => {
+                    let text = String::from_utf8_lossy(&bytes).into_owned();
+                    (bytes, text)
+                }
+                Err(err) => (Bytes::new(), format!("(could not be read: {})", err)),
+            };
+            debugger.emit(describe(&signed, status, Some(&text)));
+            Ok(http::Response::from_parts(parts, RB::from(bytes)))
+        })
+    }
+}
+
+/// Creates a client whose requests are described by `debugger`.
+pub fn debug_signing_client(config: aws_sdk_s3::Config, debugger: &SigningDebugger) -> Client {
+    let adapter = https_adapter();
+    Client::from_conf_conn(config, SigningDebug::new(adapter, Some(debugger.clone())))
+}
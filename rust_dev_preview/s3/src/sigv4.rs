@@ -0,0 +1,248 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Signature Version 4 for requests a connector sends somewhere else than
+//! the SDK addressed them.
+//!
+//! The SDK version of these examples addresses every bucket in the path,
+//! as `/bucket/key`, and signs the request for that URL. Endpoints that
+//! take the bucket in the host name, as S3 Express One Zone zonal endpoints
+//! and some gateways do, need `/key` instead, and the signature covers the
+//! host and the path, so a connector moving the bucket has to sign the
+//! request again. `sign` does that with the date and payload hash the SDK
+//! already set, covering the headers the SDK signed plus the host and the
+//! session token header.
+//!
+//! The payload is not read again: a request whose payload is signed in
+//! chunks (`STREAMING-` hashes) cannot be signed again and is refused.
+
+use aws_sdk_s3::Credentials;
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// The payload hash of an empty body.
+pub const EMPTY_PAYLOAD_SHA256: &str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// The format of `x-amz-date`.
+pub const AMZ_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+/// Splits a path-style path, as `/bucket/key`, into the bucket and the
+/// path of the key, `/key`. Fails for a path without a bucket.
+pub fn split_bucket(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix('/')?;
+    match rest.find('/') {
+        Some(at) => Some((&rest[..at], &rest[at..])),
+        None if !rest.is_empty() => Some((rest, "/")),
+        None => None,
+    }
+}
+
+/// The Region of the credential scope of a signed request, as in
+/// `Credential=AKID/20220301/us-west-2/s3/aws4_request`.
+pub fn signed_region(headers: &http::HeaderMap) -> Option<String> {
+    let authorization = headers.get(http::header::AUTHORIZATION)?.to_str().ok()?;
+    let credential = field(authorization, "Credential=")?;
+    credential
+        .split('/')
+        .nth(2)
+        .map(|region| region.to_string())
+}
+
+/// Signs the request of `parts` for its current URI with `credentials`,
+/// for `region` and `service`, replacing the signature the SDK made.
+///
+/// The session token of `credentials`, if any, is sent in `token_header`:
+/// `x-amz-security-token` for S3, `x-amz-s3session-token` for a session of
+/// S3 Express One Zone. The `x-amz-date` and `x-amz-content-sha256`
+/// headers must be set.
+pub fn sign(
+    parts: &mut http::request::Parts,
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    token_header: &'static str,
+) -> Result<(), String> {
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let amz_date = header("x-amz-date").ok_or("The request has no x-amz-date header")?;
+    if amz_date.len() != 16 {
+        return Err(format!("Invalid x-amz-date {}", amz_date));
+    }
+    let payload_hash =
+        header("x-amz-content-sha256").ok_or("The request has no x-amz-content-sha256 header")?;
+    if payload_hash.starts_with("STREAMING-") {
+        return Err(format!(
+            "A request with a {} payload cannot be signed again",
+            payload_hash
+        ));
+    }
+    let authority = parts
+        .uri
+        .authority()
+        .ok_or("The request URI has no host")?
+        .to_string();
+    let previously_signed: Option<Vec<String>> = header("authorization").map(|authorization| {
+        field(&authorization, "SignedHeaders=")
+            .unwrap_or_default()
+            .split(';')
+            .map(|name| name.to_string())
+            .collect()
+    });
+
+    // The host is signed from the URI, as hyper sends it.
+    parts.headers.remove(http::header::HOST);
+    parts.headers.remove(http::header::AUTHORIZATION);
+    parts.headers.remove("x-amz-security-token");
+    parts.headers.remove(token_header);
+    if let Some(token) = credentials.session_token() {
+        let value = http::HeaderValue::from_str(token)
+            .map_err(|err| format!("Invalid session token: {}", err))?;
+        parts.headers.insert(token_header, value);
+    }
+
+    let mut names: Vec<String> = match previously_signed {
+        Some(names) => names
+            .into_iter()
+            .filter(|name| parts.headers.contains_key(name.as_str()))
+            .collect(),
+        None => parts
+            .headers
+            .keys()
+            .map(|name| name.as_str())
+            .filter(|name| name.starts_with("x-amz-"))
+            .map(|name| name.to_string())
+            .collect(),
+    };
+    names.push("host".to_string());
+    if credentials.session_token().is_some() {
+        names.push(token_header.to_string());
+    }
+    names.sort_unstable();
+    names.dedup();
+
+    let mut canonical_headers = String::new();
+    for name in &names {
+        let value = if name == "host" {
+            authority.clone()
+        } else {
+            parts
+                .headers
+                .get_all(name.as_str())
+                .iter()
+                .map(|value| canonical_value(&String::from_utf8_lossy(value.as_bytes())))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        canonical_headers.push_str(&format!("{}:{}\n", name, value));
+    }
+    let signed_headers = names.join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        parts.method,
+        parts.uri.path(),
+        canonical_query(parts.uri.query().unwrap_or_default()),
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+
+    let scope = format!("{}/{}/{}/aws4_request", &amz_date[..8], region, service);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{:x}",
+        ALGORITHM,
+        amz_date,
+        scope,
+        Sha256::digest(canonical_request.as_bytes())
+    );
+    let mut key = hmac(
+        format!("AWS4{}", credentials.secret_access_key()).as_bytes(),
+        &amz_date[..8],
+    );
+    for part in [region, service, "aws4_request"] {
+        key = hmac(&key, part);
+    }
+    let signature: String = hmac(&key, &string_to_sign)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    let authorization = format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        ALGORITHM,
+        credentials.access_key_id(),
+        scope,
+        signed_headers,
+        signature
+    );
+    let value = http::HeaderValue::from_str(&authorization)
+        .map_err(|err| format!("Invalid access key ID: {}", err))?;
+    parts.headers.insert(http::header::AUTHORIZATION, value);
+    Ok(())
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// `name` followed by its value, in an Authorization header.
+fn field<'a>(authorization: &'a str, name: &str) -> Option<&'a str> {
+    authorization
+        .split(|c| c == ' ' || c == ',')
+        .find_map(|part| part.strip_prefix(name))
+}
+
+/// The value trimmed, with runs of spaces made one.
+fn canonical_value(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The query parameters, already encoded by the SDK, sorted by name and
+/// then value, each with an `=`.
+fn canonical_query(query: &str) -> String {
+    let mut pairs: Vec<(&str, &str)> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (name, value),
+            None => (pair, ""),
+        })
+        .collect();
+    pairs.sort_unstable();
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// An S3 error response with `code`, for the SDK to report `message` as it
+/// reports the errors of the service, when a connector cannot send a
+/// request.
+pub(crate) fn error_response<RB: From<Bytes>>(code: &str, message: &str) -> http::Response<RB> {
+    let message = message
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <Error><Code>{}</Code><Message>{}</Message></Error>",
+        code, message
+    );
+    http::Response::builder()
+        .status(http::StatusCode::BAD_REQUEST)
+        .body(RB::from(Bytes::from(body)))
+        .expect("a status and a body make a valid response")
+}
@@ -0,0 +1,308 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Soft deletion of objects with tags, for recycle-bin semantics in buckets
+//! without versioning.
+//!
+//! A soft-deleted object keeps its data and is tagged `deleted=true` and
+//! `deleted_at` with the time it was deleted; restoring it removes both
+//! tags. Readers that honor the tombstone skip such objects, and they are
+//! removed for good either by `purge_soft_deleted`, which goes by the
+//! `deleted_at` tag, or by the lifecycle rule of `put_soft_delete_lifecycle`.
+
+use crate::expiry::{delete_keys, TAG_CHECK_CONCURRENCY};
+use aws_sdk_s3::model::{
+    BucketLifecycleConfiguration, ExpirationStatus, LifecycleExpiration, LifecycleRule,
+    LifecycleRuleAndOperator, LifecycleRuleFilter, Tag, Tagging,
+};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The tag marking an object as deleted, with the value `true`.
+pub const DELETED_TAG: &str = "deleted";
+
+/// The tag holding when an object was soft-deleted, as an ISO-8601 time.
+pub const DELETED_AT_TAG: &str = "deleted_at";
+
+/// The ID of the lifecycle rule of `put_soft_delete_lifecycle`.
+pub const SOFT_DELETE_RULE_ID: &str = "expire-soft-deleted";
+
+/// Maximum number of tags on an object.
+pub const MAX_OBJECT_TAGS: usize = 10;
+
+/// An object tagged as deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoftDeletedObject {
+    pub key: String,
+    pub size: u64,
+    /// `None` if the `deleted_at` tag is missing or not a valid time.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// Returns the tags on `bucket/key`; an object deleted since it was listed
+/// yields `None`.
+async fn get_object_tags(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Option<HashMap<String, String>>, Error> {
+    match client
+        .get_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+    {
+        Ok(resp) => Ok(Some(
+            resp.tag_set()
+                .unwrap_or_default()
+                .iter()
+                .map(|tag| {
+                    (
+                        tag.key().unwrap_or_default().to_string(),
+                        tag.value().unwrap_or_default().to_string(),
+                    )
+                })
+                .collect(),
+        )),
+        Err(SdkError::ServiceError { err, .. }) if err.code() == Some("NoSuchKey") => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Replaces the tags on `bucket/key` with `tags`, or removes them all if
+/// there are none.
+async fn put_object_tags(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    tags: HashMap<String, String>,
+) -> Result<(), Error> {
+    if tags.is_empty() {
+        client
+            .delete_object_tagging()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await?;
+        return Ok(());
+    }
+    let tag_set = tags
+        .into_iter()
+        .map(|(key, value)| Tag::builder().key(key).value(value).build())
+        .collect();
+    client
+        .put_object_tagging()
+        .bucket(bucket)
+        .key(key)
+        .tagging(Tagging::builder().set_tag_set(Some(tag_set)).build())
+        .send()
+        .await?;
+    Ok(())
+}
+
+fn is_deleted(tags: &HashMap<String, String>) -> bool {
+    tags.get(DELETED_TAG).map(|value| value.as_str()) == Some("true")
+}
+
+/// Tags `bucket/key` as deleted now, keeping its other tags. Deleting an
+/// object that is already soft-deleted keeps its first deletion time.
+///
+/// Fails if the object does not exist, or if it has no room for the two
+/// tags.
+pub async fn soft_delete(client: &Client, bucket: &str, key: &str) -> Result<(), Error> {
+    let mut tags = get_object_tags(client, bucket, key)
+        .await?
+        .ok_or_else(|| Error::Unhandled(Box::from(format!("{} does not exist", key))))?;
+    if is_deleted(&tags) {
+        return Ok(());
+    }
+    tags.remove(DELETED_AT_TAG);
+    if tags.len() + 2 > MAX_OBJECT_TAGS {
+        return Err(Error::Unhandled(Box::from(format!(
+            "{} has {} tags, which leaves no room for the {} and {} tags of at most {}",
+            key,
+            tags.len(),
+            DELETED_TAG,
+            DELETED_AT_TAG,
+            MAX_OBJECT_TAGS
+        ))));
+    }
+    tags.insert(DELETED_TAG.to_string(), "true".to_string());
+    tags.insert(
+        DELETED_AT_TAG.to_string(),
+        Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+    );
+    put_object_tags(client, bucket, key, tags).await
+}
+
+/// Removes the deletion tags from `bucket/key`, keeping its other tags.
+/// Restoring an object that is not soft-deleted does nothing.
+pub async fn soft_delete_restore(client: &Client, bucket: &str, key: &str) -> Result<(), Error> {
+    let mut tags = get_object_tags(client, bucket, key)
+        .await?
+        .ok_or_else(|| Error::Unhandled(Box::from(format!("{} does not exist", key))))?;
+    let had_deleted = tags.remove(DELETED_TAG).is_some();
+    let had_deleted_at = tags.remove(DELETED_AT_TAG).is_some();
+    if !had_deleted && !had_deleted_at {
+        return Ok(());
+    }
+    put_object_tags(client, bucket, key, tags).await
+}
+
+/// Lists the soft-deleted objects under `prefix`, in key order.
+///
+/// Each object costs a GetObjectTagging, run `TAG_CHECK_CONCURRENCY` at a
+/// time. Objects deleted for good while the prefix is listed are left out.
+pub async fn list_soft_deleted(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+) -> Result<Vec<SoftDeletedObject>, Error> {
+    let mut objects = Vec::new();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+        for object in resp.contents().unwrap_or_default() {
+            if let Some(key) = object.key() {
+                objects.push((key.to_string(), object.size().max(0) as u64));
+            }
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+
+    let checked = stream::iter(objects)
+        .map(|(key, size)| async move {
+            let tags = get_object_tags(client, bucket, &key).await?;
+            Ok::<_, Error>(tags.filter(is_deleted).map(|tags| {
+                SoftDeletedObject {
+                    key,
+                    size,
+                    deleted_at: tags
+                        .get(DELETED_AT_TAG)
+                        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+                        .map(|time| time.with_timezone(&Utc)),
+                }
+            }))
+        })
+        .buffer_unordered(TAG_CHECK_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+    let mut deleted = Vec::new();
+    for object in checked {
+        deleted.extend(object?);
+    }
+    deleted.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(deleted)
+}
+
+/// Permanently deletes the objects under `prefix` soft-deleted more than
+/// `max_age` ago, and returns how many were deleted. Objects without a
+/// valid `deleted_at` tag are kept.
+pub async fn purge_soft_deleted(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    max_age: Duration,
+) -> Result<u64, Error> {
+    let max_age =
+        chrono::Duration::from_std(max_age).map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let now = Utc::now();
+    let expired: Vec<String> = list_soft_deleted(client, bucket, prefix)
+        .await?
+        .into_iter()
+        .filter(|object| {
+            object
+                .deleted_at
+                .map_or(false, |deleted_at| now - deleted_at > max_age)
+        })
+        .map(|object| object.key)
+        .collect();
+    delete_keys(client, bucket, &expired).await
+}
+
+/// A lifecycle rule expiring the objects under `prefix` tagged
+/// `deleted=true` once they are `days` old.
+///
+/// Lifecycle ages count from when the object was created, not from when it
+/// was tagged: an object older than `days` is removed at the next daily run
+/// after it is soft-deleted. Use `purge_soft_deleted` to keep every object
+/// `days` in the recycle bin.
+pub fn soft_delete_lifecycle_rule(prefix: &str, days: i32) -> LifecycleRule {
+    let tag = Tag::builder().key(DELETED_TAG).value("true").build();
+    let filter = if prefix.is_empty() {
+        LifecycleRuleFilter::Tag(tag)
+    } else {
+        LifecycleRuleFilter::And(
+            LifecycleRuleAndOperator::builder()
+                .prefix(prefix)
+                .tags(tag)
+                .build(),
+        )
+    };
+    LifecycleRule::builder()
+        .id(SOFT_DELETE_RULE_ID)
+        .filter(filter)
+        .status(ExpirationStatus::Enabled)
+        .expiration(LifecycleExpiration::builder().days(days).build())
+        .build()
+}
+
+/// Adds the rule of `soft_delete_lifecycle_rule` to the lifecycle
+/// configuration of `bucket`, replacing an earlier one and keeping the
+/// other rules.
+pub async fn put_soft_delete_lifecycle(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    days: i32,
+) -> Result<(), Error> {
+    if days <= 0 {
+        return Err(Error::Unhandled(Box::from(format!(
+            "Soft-deleted objects must be kept at least 1 day, not {}",
+            days
+        ))));
+    }
+    let mut rules: Vec<LifecycleRule> = match client
+        .get_bucket_lifecycle_configuration()
+        .bucket(bucket)
+        .send()
+        .await
+    {
+        Ok(resp) => resp.rules().unwrap_or_default().to_vec(),
+        Err(SdkError::ServiceError { err, .. })
+            if err.code() == Some("NoSuchLifecycleConfiguration") =>
+        {
+            Vec::new()
+        }
+        Err(err) => return Err(err.into()),
+    };
+    rules.retain(|rule| rule.id() != Some(SOFT_DELETE_RULE_ID));
+    rules.push(soft_delete_lifecycle_rule(prefix, days));
+    client
+        .put_bucket_lifecycle_configuration()
+        .bucket(bucket)
+        .lifecycle_configuration(
+            BucketLifecycleConfiguration::builder()
+                .set_rules(Some(rules))
+                .build(),
+        )
+        .send()
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,84 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Splitting of one large object into smaller objects, so that the pieces
+//! can be processed in parallel downstream. This is the inverse of
+//! `copy_prefix::copy_object_multipart` assembling parts into one object.
+//!
+//! The source is streamed: at most one chunk is held in memory at a time.
+
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Error};
+use bytes::{Bytes, BytesMut};
+use futures::TryStreamExt;
+
+/// The key of chunk `index` under `output_prefix`.
+pub fn chunk_key(output_prefix: &str, index: usize) -> String {
+    format!("{}/{}", output_prefix.trim_end_matches('/'), index)
+}
+
+/// Streams `bucket/src_key` and uploads it to `{output_prefix}/0`,
+/// `{output_prefix}/1`, ... in chunks of `max_part_size` bytes. Only the
+/// last chunk may be smaller.
+///
+/// Returns the keys of the chunks in order; an empty source produces no
+/// chunks. Chunks uploaded before a failure are left in place.
+pub async fn split_object(
+    client: &Client,
+    bucket: &str,
+    src_key: &str,
+    output_prefix: &str,
+    max_part_size: u64,
+) -> Result<Vec<String>, Error> {
+    if max_part_size == 0 {
+        return Err(Error::Unhandled(Box::from(
+            "The chunk size must be at least one byte",
+        )));
+    }
+    let max_part_size = max_part_size as usize;
+    let resp = client
+        .get_object()
+        .bucket(bucket)
+        .key(src_key)
+        .send()
+        .await?;
+    let mut body = resp.body;
+
+    let mut keys = Vec::new();
+    let mut buffer = BytesMut::new();
+    while let Some(data) = body
+        .try_next()
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+    {
+        buffer.extend_from_slice(&data);
+        while buffer.len() >= max_part_size {
+            let chunk = buffer.split_to(max_part_size).freeze();
+            keys.push(put_chunk(client, bucket, output_prefix, keys.len(), chunk).await?);
+        }
+    }
+    if !buffer.is_empty() {
+        keys.push(put_chunk(client, bucket, output_prefix, keys.len(), buffer.freeze()).await?);
+    }
+    Ok(keys)
+}
+
+async fn put_chunk(
+    client: &Client,
+    bucket: &str,
+    output_prefix: &str,
+    index: usize,
+    chunk: Bytes,
+) -> Result<String, Error> {
+    let key = chunk_key(output_prefix, index);
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(&key)
+        .body(ByteStream::from(chunk))
+        .send()
+        .await?;
+    Ok(key)
+}
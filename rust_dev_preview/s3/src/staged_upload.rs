@@ -0,0 +1,376 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Multipart uploads built across several job stages.
+//!
+//! Each stage uploads a byte range of its source to a window of part
+//! numbers of the same upload, chosen with `part_number_offset`: a stage
+//! uploading 100 parts with an offset of 100 writes parts 101 to 200. The
+//! parts a stage uploaded are written to a `StageManifest`, and the final
+//! stage completes the upload from the manifests of all the stages.
+//!
+//! Before uploading, the window is checked against the 10,000 parts of an
+//! upload and, unless `collision_check` is off, against the parts already
+//! listed by ListParts, since uploading a part number again silently
+//! replaces it. Before completing, the part numbers of the manifests must
+//! be dense from 1, since a gap is a stage that did not run and makes S3
+//! reject the completion.
+//!
+//! S3 rejects the completion of an upload with a part other than the last
+//! below `MIN_PART_SIZE`, so a stage that does not end the file must upload
+//! a multiple of the part size: its last part would otherwise be short. A
+//! stage is checked for that before it uploads anything.
+//!
+//! A part rejected as too large fails the stage with the error of
+//! `part_size_cap::part_too_large_error`, as the part numbers of the other
+//! stages leave no room to split it.
+
+use crate::durable::{write_file, FsyncOptions};
+use crate::failover::EndpointPool;
+use crate::part_size_cap::{is_entity_too_large, part_too_large_error};
+use crate::retry::{RetryPolicy, SlowDownCoordinator};
+use crate::upload::{
+    complete_upload, create_upload, upload_part, PartTarget, SourceWindow, DEFAULT_PART_SIZE,
+    MAX_PARTS, MIN_PART_SIZE,
+};
+use crate::upload_watch::list_upload_parts;
+use aws_sdk_s3::model::CompletedPart;
+use aws_sdk_s3::{Client, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A part uploaded by a stage.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StagedPart {
+    pub part_number: i32,
+    /// Where the part was read in the source of the stage.
+    pub offset: u64,
+    pub size: u64,
+    /// Without the quotes.
+    pub e_tag: String,
+}
+
+/// The parts uploaded by one stage, as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StageManifest {
+    pub bucket: String,
+    pub key: String,
+    pub upload_id: String,
+    pub parts: Vec<StagedPart>,
+}
+
+impl StageManifest {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        serde_json::from_str(&content).map_err(|err| {
+            Error::Unhandled(Box::from(format!(
+                "Invalid stage manifest {}: {}",
+                path.display(),
+                err
+            )))
+        })
+    }
+
+    /// Writes the manifest through a temporary file renamed over `path`, so
+    /// a crash leaves either the previous manifest or this one.
+    pub async fn save(&self, path: &Path) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        let options = FsyncOptions {
+            enabled: true,
+            interval: None,
+        };
+        write_file(&mut content.as_bytes(), path, &options)
+            .await
+            .map(|_| ())
+            .map_err(|err| Error::Unhandled(Box::new(err)))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PartsRangeOptions {
+    pub part_size: u64,
+    /// Added to the part numbers, which otherwise start at 1.
+    pub part_number_offset: i32,
+    /// Refuse to upload over the parts already listed in the window.
+    pub collision_check: bool,
+    /// The smallest part but the last of the upload S3 accepts.
+    pub min_part_size: u64,
+}
+
+impl Default for PartsRangeOptions {
+    fn default() -> Self {
+        Self {
+            part_size: DEFAULT_PART_SIZE,
+            part_number_offset: 0,
+            collision_check: true,
+            min_part_size: MIN_PART_SIZE,
+        }
+    }
+}
+
+/// The `(part number, offset, size)` of the parts of `window`, numbered from
+/// `part_number_offset + 1`, or an error if they do not fit in an upload.
+pub fn number_parts(
+    window: SourceWindow,
+    part_size: u64,
+    part_number_offset: i32,
+) -> Result<Vec<(i32, u64, u64)>, Error> {
+    if part_number_offset < 0 {
+        return Err(Error::Unhandled(Box::from(format!(
+            "The part number offset cannot be negative ({})",
+            part_number_offset
+        ))));
+    }
+    let part_size = part_size.max(1);
+    let count = ((window.length + part_size - 1) / part_size).max(1);
+    let last = part_number_offset as u64 + count;
+    if last > MAX_PARTS {
+        return Err(Error::Unhandled(Box::from(format!(
+            "Parts {} to {} go past the {} parts of an upload",
+            part_number_offset as u64 + 1,
+            last,
+            MAX_PARTS
+        ))));
+    }
+    Ok((0..count)
+        .map(|i| {
+            let offset = i * part_size;
+            let size = part_size.min(window.length - offset.min(window.length));
+            (
+                part_number_offset + i as i32 + 1,
+                window.offset + offset,
+                size,
+            )
+        })
+        .collect())
+}
+
+/// Checks that the parts of a stage uploading `window` of a file of
+/// `file_size` bytes, in parts of `part_size`, can all be completed: every
+/// part but the last of the file must be at least `min_part_size`, so a
+/// stage ending before the end of the file must upload a multiple of the
+/// part size.
+pub fn check_stage_layout(
+    window: SourceWindow,
+    file_size: u64,
+    part_size: u64,
+    min_part_size: u64,
+) -> Result<(), Error> {
+    let ends_file = window.offset + window.length >= file_size;
+    if !ends_file && window.length % part_size.max(1) != 0 {
+        return Err(Error::Unhandled(Box::from(format!(
+            "The stage ends before the end of the file, so its {} bytes must be a multiple of \
+             the part size {}: its last part would be too small to complete the upload",
+            window.length, part_size
+        ))));
+    }
+    let single_last_part = ends_file && window.length <= part_size;
+    if part_size < min_part_size && !single_last_part {
+        return Err(Error::Unhandled(Box::from(format!(
+            "The part size {} is below the {} bytes of every part but the last of an upload",
+            part_size, min_part_size
+        ))));
+    }
+    Ok(())
+}
+
+/// Creates the multipart upload the stages add parts to, and returns its ID.
+pub async fn start_staged_upload(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<String, Error> {
+    create_upload(
+        &EndpointPool::single(client.clone()),
+        bucket,
+        key,
+        None,
+        &RetryPolicy::default(),
+        &SlowDownCoordinator::new(),
+    )
+    .await
+}
+
+/// Uploads the bytes of `window` in `file_name` as parts of `upload_id`, in
+/// parts of `options.part_size` numbered from `options.part_number_offset + 1`.
+/// Returns the manifest of the parts uploaded, for `complete_staged_upload`.
+///
+/// The layout is first checked with `check_stage_layout`, the end of the
+/// file being that of the last stage. A failure leaves the upload as it is,
+/// so that the stage can run again.
+pub async fn upload_parts_range(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    file_name: &str,
+    window: SourceWindow,
+    options: &PartsRangeOptions,
+) -> Result<StageManifest, Error> {
+    let parts = number_parts(window, options.part_size, options.part_number_offset)?;
+    let file_size = tokio::fs::metadata(file_name)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+        .len();
+    check_stage_layout(window, file_size, options.part_size, options.min_part_size)?;
+    if options.collision_check {
+        let listed = list_upload_parts(client, bucket, key, upload_id)
+            .await?
+            .ok_or_else(|| {
+                Error::Unhandled(Box::from(format!(
+                    "No multipart upload {} for {}",
+                    upload_id, key
+                )))
+            })?;
+        let first = parts[0].0;
+        let last = parts[parts.len() - 1].0;
+        let taken: Vec<String> = listed
+            .iter()
+            .filter(|part| (first..=last).contains(&part.part_number))
+            .map(|part| part.part_number.to_string())
+            .collect();
+        if !taken.is_empty() {
+            return Err(Error::Unhandled(Box::from(format!(
+                "Parts {} of upload {} are already uploaded; choose another part number \
+                 offset, or skip the check to replace them",
+                taken.join(", "),
+                upload_id
+            ))));
+        }
+    }
+
+    let file = tokio::fs::File::open(file_name)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let endpoints = EndpointPool::single(client.clone());
+    let policy = RetryPolicy::default();
+    let coordinator = SlowDownCoordinator::new();
+    let mut manifest = StageManifest {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        upload_id: upload_id.to_string(),
+        parts: Vec::new(),
+    };
+    for (part_number, offset, size) in parts {
+        let part = upload_part(
+            &endpoints,
+            &file,
+            PartTarget {
+                bucket,
+                key,
+                uid: upload_id,
+                part_number,
+                offset,
+                size,
+            },
+            None,
+            &policy,
+            &coordinator,
+        )
+        .await
+        .map_err(|err| {
+            if is_entity_too_large(&err) {
+                part_too_large_error(size, err)
+            } else {
+                err
+            }
+        })?;
+        manifest.parts.push(StagedPart {
+            part_number,
+            offset,
+            size,
+            e_tag: part.e_tag().unwrap_or_default().replace("\"", ""),
+        });
+    }
+    Ok(manifest)
+}
+
+/// The parts of all `manifests`, by part number, checking that they are of
+/// the same upload, that no part number is in two of them, and that the
+/// part numbers are dense from 1.
+pub fn merge_stage_manifests(manifests: &[StageManifest]) -> Result<Vec<StagedPart>, Error> {
+    let first = manifests
+        .first()
+        .ok_or_else(|| Error::Unhandled(Box::from("No stage manifests to complete from")))?;
+    let mut parts: BTreeMap<i32, StagedPart> = BTreeMap::new();
+    for manifest in manifests {
+        if (&manifest.bucket, &manifest.key, &manifest.upload_id)
+            != (&first.bucket, &first.key, &first.upload_id)
+        {
+            return Err(Error::Unhandled(Box::from(format!(
+                "The stage manifests are of different uploads: {} of {} and {} of {}",
+                first.upload_id, first.key, manifest.upload_id, manifest.key
+            ))));
+        }
+        for part in &manifest.parts {
+            if parts.insert(part.part_number, part.clone()).is_some() {
+                return Err(Error::Unhandled(Box::from(format!(
+                    "Part {} is in more than one stage manifest",
+                    part.part_number
+                ))));
+            }
+        }
+    }
+    let part_numbers: Vec<i32> = parts.keys().copied().collect();
+    check_dense(&part_numbers)?;
+    Ok(parts.into_iter().map(|(_, part)| part).collect())
+}
+
+/// Checks that sorted `part_numbers` are 1, 2, 3... and names the missing
+/// ones otherwise.
+pub fn check_dense(part_numbers: &[i32]) -> Result<(), Error> {
+    let mut missing = Vec::new();
+    let mut expected = 1;
+    for &part_number in part_numbers {
+        if part_number > expected {
+            missing.push(if part_number - 1 == expected {
+                expected.to_string()
+            } else {
+                format!("{} to {}", expected, part_number - 1)
+            });
+        }
+        expected = part_number + 1;
+    }
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Unhandled(Box::from(format!(
+            "The part numbers must be dense from 1, but parts {} are missing",
+            missing.join(", ")
+        ))))
+    }
+}
+
+/// Completes the upload of `manifests` with the parts of all of them, once
+/// `merge_stage_manifests` accepts them. Returns the ETag of the object,
+/// without quotes.
+pub async fn complete_staged_upload(
+    client: &Client,
+    manifests: &[StageManifest],
+) -> Result<String, Error> {
+    let parts = merge_stage_manifests(manifests)?;
+    let manifest = &manifests[0];
+    let completed_parts = parts
+        .iter()
+        .map(|part| {
+            CompletedPart::builder()
+                .e_tag(format!("\"{}\"", part.e_tag))
+                .part_number(part.part_number)
+                .build()
+        })
+        .collect();
+    complete_upload(
+        &EndpointPool::single(client.clone()),
+        &manifest.bucket,
+        &manifest.key,
+        &manifest.upload_id,
+        completed_parts,
+        &RetryPolicy::default(),
+        &SlowDownCoordinator::new(),
+    )
+    .await
+}
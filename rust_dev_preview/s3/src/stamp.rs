@@ -0,0 +1,246 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Content hashes stamped on uploaded objects, to catch an object holding
+//! the content of another file.
+//!
+//! Sizes and modification times cannot tell two files of the same size
+//! apart, so a planner uploading each file to the key of another goes
+//! unnoticed by later syncs. With stamping, each object records the SHA-256
+//! of the file it was uploaded from in its `content-sha256` metadata, or in
+//! its archive index when packed. `verify_stamps` hashes the local files
+//! again and compares: a stamp matching another local file is a probable
+//! key mixup, and a stamp matching none is an ordinary modification.
+
+use crate::batch::PackedLocation;
+use crate::expiry::TAG_CHECK_CONCURRENCY;
+use crate::manifest::sha256_file;
+use crate::sync::LocalFile;
+use aws_sdk_s3::{Client, Error};
+use futures::{stream, StreamExt};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Metadata key (`x-amz-meta-content-sha256`) holding the lowercase hex
+/// SHA-256 of the file an object was uploaded from.
+pub const CONTENT_SHA256_METADATA: &str = "content-sha256";
+
+/// The seed of a sample without `seed=`.
+pub const DEFAULT_SAMPLE_SEED: u64 = 0;
+
+/// Which objects `verify_stamps` checks: all of them, or a fraction picked
+/// from their keys and a seed, so that the same seed checks the same
+/// objects on every run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StampSampling {
+    /// Between 0 (excluded) and 1.
+    pub fraction: f64,
+    pub seed: u64,
+}
+
+impl Default for StampSampling {
+    fn default() -> Self {
+        Self {
+            fraction: 1.0,
+            seed: DEFAULT_SAMPLE_SEED,
+        }
+    }
+}
+
+impl StampSampling {
+    /// Parses `all`, or `sample=PERCENT%` optionally followed by `,seed=N`,
+    /// such as `sample=5%,seed=42`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value == "all" {
+            return Ok(Self::default());
+        }
+        let mut parts = value.split(',');
+        let percent = parts
+            .next()
+            .and_then(|part| part.strip_prefix("sample="))
+            .and_then(|part| part.strip_suffix('%'))
+            .ok_or_else(|| {
+                format!(
+                    "Invalid stamp sampling {}; use all or sample=PERCENT%[,seed=N]",
+                    value
+                )
+            })?;
+        let percent: f64 = percent
+            .parse()
+            .map_err(|_| format!("Invalid sample percentage {}%", percent))?;
+        if percent.is_nan() || percent <= 0.0 || percent > 100.0 {
+            return Err(format!(
+                "The sample percentage must be above 0 and at most 100, not {}",
+                percent
+            ));
+        }
+        let mut seed = DEFAULT_SAMPLE_SEED;
+        for part in parts {
+            let value = part
+                .strip_prefix("seed=")
+                .ok_or_else(|| format!("Unknown stamp sampling option {}", part))?;
+            seed = value
+                .parse()
+                .map_err(|_| format!("Invalid sample seed {}", value))?;
+        }
+        Ok(Self {
+            fraction: percent / 100.0,
+            seed,
+        })
+    }
+
+    /// Whether the object at `key` is in the sample.
+    pub fn selects(&self, key: &str) -> bool {
+        if self.fraction >= 1.0 {
+            return true;
+        }
+        // FNV-1a of the key, mixed with the seed by the SplitMix64 finalizer.
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in key.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        let mut mixed = hash ^ self.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        mixed ^= mixed >> 31;
+        (mixed as f64) < self.fraction * u64::MAX as f64
+    }
+}
+
+/// Outcome of `verify_stamps`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StampReport {
+    /// Objects whose stamp was read, stamped or not.
+    pub checked: usize,
+    /// Objects left out of the sample.
+    pub sampled_out: usize,
+    pub matched: usize,
+    /// Keys of the objects uploaded without stamping.
+    pub unstamped: Vec<String>,
+    /// Keys of the objects whose stamp matches no local file.
+    pub modified: Vec<String>,
+    /// Keys of the objects holding the content of another local file, with
+    /// the key of that file.
+    pub mixups: Vec<(String, String)>,
+}
+
+impl StampReport {
+    /// The keys of the objects whose content is not the one of their file.
+    pub fn mismatched(&self) -> impl Iterator<Item = &str> {
+        self.modified
+            .iter()
+            .map(|key| key.as_str())
+            .chain(self.mixups.iter().map(|(key, _)| key.as_str()))
+    }
+}
+
+/// Returns the stamp of the object at `key`, from `packed` for a file in an
+/// archive and from a HeadObject otherwise.
+async fn read_stamp(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    packed: &HashMap<String, PackedLocation>,
+) -> Result<Option<String>, Error> {
+    if let Some(location) = packed.get(key) {
+        return Ok(location.sha256.clone());
+    }
+    let head = client.head_object().bucket(bucket).key(key).send().await?;
+    Ok(head
+        .metadata()
+        .and_then(|metadata| metadata.get(CONTENT_SHA256_METADATA))
+        .cloned())
+}
+
+async fn hash(path: &std::path::Path) -> Result<String, Error> {
+    sha256_file(path)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))
+}
+
+/// Checks the stamp of the object of each of `files` selected by
+/// `sampling` against the file.
+///
+/// `local` holds all the files of the sync: an object whose stamp does not
+/// match its file is compared with the other files of the same size, and
+/// reported as a mixup with the one it matches. Each object costs a
+/// HeadObject, unless it is packed in an archive of `packed`, and its file
+/// is hashed; `TAG_CHECK_CONCURRENCY` objects are checked at a time.
+pub async fn verify_stamps(
+    client: &Client,
+    bucket: &str,
+    files: &[LocalFile],
+    local: &[LocalFile],
+    packed: &HashMap<String, PackedLocation>,
+    sampling: &StampSampling,
+) -> Result<StampReport, Error> {
+    let mut report = StampReport::default();
+    let sampled: Vec<&LocalFile> = files
+        .iter()
+        .filter(|file| sampling.selects(&file.key))
+        .collect();
+    report.sampled_out = files.len() - sampled.len();
+    report.checked = sampled.len();
+
+    let checked = stream::iter(sampled)
+        .map(|file| async move {
+            let stamp = match read_stamp(client, bucket, &file.key, packed).await? {
+                Some(stamp) => stamp,
+                None => return Ok::<_, Error>((file, None)),
+            };
+            let sha256 = hash(&file.path).await?;
+            Ok((file, Some((stamp, sha256))))
+        })
+        .buffer_unordered(TAG_CHECK_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut hashes: HashMap<PathBuf, String> = HashMap::new();
+    let mut mismatched = Vec::new();
+    for result in checked {
+        let (file, stamp) = result?;
+        match stamp {
+            None => report.unstamped.push(file.key.clone()),
+            Some((stamp, sha256)) => {
+                hashes.insert(file.path.clone(), sha256.clone());
+                if stamp == sha256 {
+                    report.matched += 1;
+                } else {
+                    mismatched.push((file, stamp));
+                }
+            }
+        }
+    }
+
+    for (file, stamp) in mismatched {
+        let mut source = None;
+        for candidate in local {
+            if candidate.size != file.size || candidate.path == file.path {
+                continue;
+            }
+            let sha256 = match hashes.get(&candidate.path) {
+                Some(sha256) => sha256.clone(),
+                None => {
+                    let sha256 = hash(&candidate.path).await?;
+                    hashes.insert(candidate.path.clone(), sha256.clone());
+                    sha256
+                }
+            };
+            if sha256 == stamp {
+                source = Some(candidate.key.clone());
+                break;
+            }
+        }
+        match source {
+            Some(source) => report.mixups.push((file.key.clone(), source)),
+            None => report.modified.push(file.key.clone()),
+        }
+    }
+    report.unstamped.sort();
+    report.modified.sort();
+    report.mixups.sort();
+    Ok(report)
+}
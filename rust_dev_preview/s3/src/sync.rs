@@ -0,0 +1,769 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! One-way synchronization of a local directory to a bucket prefix.
+//!
+//! Syncing is split into a pure planner, which compares the local files with
+//! the remote objects and decides what to do with each file, and an executor
+//! that performs the uploads.
+
+use crate::batch::{
+    expand_remote, plan_batches, read_indexes, upload_archive_with_budget, BatchOptions,
+};
+use crate::config::HeaderRules;
+use crate::manifest::sha256_file;
+use crate::memory_budget::MemoryBudget;
+use crate::parallel_list::{list_remote_parallel, ListStats};
+use crate::preserve::{FileMetadata, MTIME_METADATA};
+use crate::retry::{is_retryable, RetryPolicy};
+use crate::stamp::{verify_stamps, StampReport, StampSampling, CONTENT_SHA256_METADATA};
+use crate::units::{format_duration, format_size};
+use crate::upload::UploadHeaders;
+use aws_sdk_s3::error::PutObjectError;
+use aws_sdk_s3::model::Object;
+use aws_sdk_s3::types::{ByteStream, SdkError};
+use aws_sdk_s3::{Client, Error};
+use futures::{stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Metadata key (`x-amz-meta-source-mtime`) recording the modification time
+/// of the local file an object was uploaded from.
+pub const SOURCE_MTIME_METADATA: &str = "source-mtime";
+
+/// How many times a file or object that changed since it was planned is
+/// planned again before it is reported as unstable and skipped.
+pub const MAX_REPLANS: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalFile {
+    pub path: PathBuf,
+    pub key: String,
+    pub size: u64,
+    pub mtime: SystemTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: SystemTime,
+    /// The `source-mtime` metadata value, when known.
+    pub source_mtime: Option<SystemTime>,
+    /// Without quotes, when listed.
+    pub e_tag: Option<String>,
+}
+
+impl RemoteObject {
+    /// The best estimate of the modification time of the object's content.
+    pub fn mtime(&self) -> SystemTime {
+        self.source_mtime.unwrap_or(self.last_modified)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Never overwrite an object that is newer than the local file.
+    pub no_overwrite_newer: bool,
+    /// Overwrite newer objects even with `no_overwrite_newer`.
+    pub force: bool,
+    /// Timestamps closer than this are considered equal, to tolerate clock
+    /// skew and timestamp precision differences.
+    pub mtime_window: Duration,
+    pub concurrency: usize,
+    /// Pack small files into archive objects; see the `batch` module.
+    pub batch: Option<BatchOptions>,
+    /// Headers of the uploaded objects, by key. Files packed in archives
+    /// have no headers of their own.
+    pub headers: HeaderRules,
+    /// Retries of each file uploaded as its own object, so a transient
+    /// failure neither stops the sync nor loses the file.
+    pub retry: RetryPolicy,
+    /// Record the mode, modification time, and owner of each file in its
+    /// object metadata, and upload symbolic links as empty objects holding
+    /// their target instead of skipping them. Not for files packed in
+    /// archives; see the `preserve` module.
+    pub preserve: bool,
+    /// Compare the local files with the modification time recorded in the
+    /// object metadata rather than with LastModified, which is when the
+    /// object was uploaded. Costs one HeadObject per object.
+    pub use_preserved_mtime: bool,
+    /// Reserve each archive from this budget before reading its files into
+    /// memory.
+    pub memory_budget: Option<MemoryBudget>,
+    /// List the prefix in this many shards at the same time instead of one
+    /// page after the other; see the `parallel_list` module.
+    pub parallel_list: Option<usize>,
+    /// Plan a file again at most this many times when it changes between
+    /// the planning and its upload, or while it is uploaded.
+    pub max_replans: u32,
+    /// Record the SHA-256 of each uploaded file in its object metadata, or
+    /// in the index of its archive; see the `stamp` module.
+    pub stamp_content_hash: bool,
+    /// Check the stamps of the objects planned as identical against their
+    /// files, and upload again the ones that do not match.
+    pub verify_stamps: Option<StampSampling>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            no_overwrite_newer: false,
+            force: false,
+            mtime_window: Duration::from_secs(2),
+            concurrency: 8,
+            batch: None,
+            headers: HeaderRules::default(),
+            retry: RetryPolicy {
+                jitter_ms: 100,
+                ..Default::default()
+            },
+            preserve: false,
+            use_preserved_mtime: false,
+            memory_budget: None,
+            parallel_list: None,
+            max_replans: MAX_REPLANS,
+            stamp_content_hash: false,
+            verify_stamps: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UploadReason {
+    New,
+    Modified,
+    SizeChanged,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SyncPlan {
+    pub uploads: Vec<(LocalFile, UploadReason)>,
+    /// Keys whose object already matches the local file.
+    pub identical: Vec<String>,
+    /// Keys skipped because the object is newer than the local file.
+    pub newer_remote: Vec<String>,
+}
+
+/// Returns `true` if `a` is later than `b` by more than `window`.
+pub(crate) fn newer_than(a: SystemTime, b: SystemTime, window: Duration) -> bool {
+    a.duration_since(b).map(|d| d > window).unwrap_or(false)
+}
+
+/// Decides, for each local file, whether it must be uploaded.
+pub fn plan_sync(
+    local: Vec<LocalFile>,
+    remote: &HashMap<String, RemoteObject>,
+    options: &SyncOptions,
+) -> SyncPlan {
+    let mut plan = SyncPlan::default();
+    for file in local {
+        let object = match remote.get(&file.key) {
+            Some(object) => object,
+            None => {
+                plan.uploads.push((file, UploadReason::New));
+                continue;
+            }
+        };
+        let remote_newer = newer_than(object.mtime(), file.mtime, options.mtime_window);
+        if remote_newer && options.no_overwrite_newer && !options.force {
+            if object.size != file.size || object.source_mtime.is_some() {
+                eprintln!(
+                    "Warning: skipping {}, the object is newer than {}",
+                    file.key,
+                    file.path.display()
+                );
+                plan.newer_remote.push(file.key);
+            } else {
+                plan.identical.push(file.key);
+            }
+        } else if newer_than(file.mtime, object.mtime(), options.mtime_window) {
+            plan.uploads.push((file, UploadReason::Modified));
+        } else if object.size != file.size {
+            plan.uploads.push((file, UploadReason::SizeChanged));
+        } else {
+            plan.identical.push(file.key);
+        }
+    }
+    plan
+}
+
+/// Formats a time as `source-mtime` metadata: seconds since the epoch with
+/// millisecond precision.
+pub fn format_mtime(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "{}.{:03}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Parses a `source-mtime` metadata value. Metadata is set by whoever
+/// wrote the object, so a value that is not a time, such as `inf`, `NaN`, a
+/// negative number, or one past what `SystemTime` holds, gives `None`.
+pub fn parse_mtime(value: &str) -> Option<SystemTime> {
+    let seconds = value.parse::<f64>().ok()?;
+    if !(0.0..u64::MAX as f64).contains(&seconds) {
+        return None;
+    }
+    UNIX_EPOCH.checked_add(Duration::from_secs_f64(seconds))
+}
+
+/// Lists the files under `dir`, mapping each to `prefix` + its relative path.
+/// Symbolic links are skipped.
+pub fn walk_directory(dir: &Path, prefix: &str) -> std::io::Result<Vec<LocalFile>> {
+    walk_directory_filtered(dir, prefix, |_, _| true)
+}
+
+/// As `walk_directory`, also listing the symbolic links, with a size of 0
+/// and the modification time of the link itself. Links to directories are
+/// not entered.
+pub fn walk_directory_with_symlinks(dir: &Path, prefix: &str) -> std::io::Result<Vec<LocalFile>> {
+    walk(dir, prefix, true, |_, _| true)
+}
+
+/// As `walk_directory`, listing only the files and entering only the
+/// directories for which `keep(path, is_dir)` is true.
+pub fn walk_directory_filtered(
+    dir: &Path,
+    prefix: &str,
+    keep: impl Fn(&Path, bool) -> bool,
+) -> std::io::Result<Vec<LocalFile>> {
+    walk(dir, prefix, false, keep)
+}
+
+fn walk(
+    dir: &Path,
+    prefix: &str,
+    symlinks: bool,
+    keep: impl Fn(&Path, bool) -> bool,
+) -> std::io::Result<Vec<LocalFile>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let path = entry.path();
+            if !keep(&path, metadata.is_dir()) {
+                continue;
+            }
+            if metadata.is_dir() {
+                pending.push(path);
+            } else if metadata.is_file() || (symlinks && metadata.file_type().is_symlink()) {
+                let relative = path
+                    .strip_prefix(dir)
+                    .unwrap_or(&path)
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push(LocalFile {
+                    key: format!("{}{}", prefix, relative),
+                    size: if metadata.is_file() {
+                        metadata.len()
+                    } else {
+                        0
+                    },
+                    mtime: metadata.modified()?,
+                    path,
+                });
+            }
+        }
+    }
+    files.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(files)
+}
+
+/// The `RemoteObject` of an object of a listing.
+pub(crate) fn remote_object(object: &Object) -> RemoteObject {
+    let last_modified = object
+        .last_modified()
+        .map(|t| UNIX_EPOCH + Duration::from_secs(t.secs().max(0) as u64))
+        .unwrap_or(UNIX_EPOCH);
+    RemoteObject {
+        key: object.key().unwrap_or_default().to_string(),
+        size: object.size() as u64,
+        last_modified,
+        source_mtime: None,
+        e_tag: object.e_tag().map(|t| t.trim_matches('"').to_string()),
+    }
+}
+
+/// Lists the objects under `prefix`, keyed by object key.
+pub async fn list_remote(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+) -> Result<HashMap<String, RemoteObject>, Error> {
+    let mut objects = HashMap::new();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let resp = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .prefix(prefix)
+            .set_continuation_token(continuation_token.take())
+            .send()
+            .await?;
+        for object in resp.contents().unwrap_or_default() {
+            let object = remote_object(object);
+            objects.insert(object.key.clone(), object);
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        continuation_token = resp.next_continuation_token().map(|t| t.to_string());
+    }
+    Ok(objects)
+}
+
+/// Reads the `source-mtime` metadata of the objects that also exist locally,
+/// or the `file-mtime` of `--preserve` when there is none.
+///
+/// Listings do not include metadata, so this costs one HeadObject per object,
+/// `concurrency` at a time; it is only worth doing when the precise
+/// timestamps matter. An object deleted since it was listed is skipped.
+pub async fn fetch_source_mtimes(
+    client: &Client,
+    bucket: &str,
+    local: &[LocalFile],
+    remote: &mut HashMap<String, RemoteObject>,
+    concurrency: usize,
+) -> Result<(), Error> {
+    let keys: Vec<String> = local
+        .iter()
+        .filter(|file| remote.contains_key(&file.key))
+        .map(|file| file.key.clone())
+        .collect();
+    let mut heads = stream::iter(keys)
+        .map(|key| async move {
+            let head = client.head_object().bucket(bucket).key(&key).send().await;
+            (key, head)
+        })
+        .buffer_unordered(concurrency.max(1));
+    while let Some((key, head)) = heads.next().await {
+        let head = match head {
+            Ok(head) => head,
+            Err(SdkError::ServiceError { err, .. }) if err.is_not_found() => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if let Some(object) = remote.get_mut(&key) {
+            object.source_mtime = head.metadata().and_then(|m| {
+                m.get(SOURCE_MTIME_METADATA)
+                    .or_else(|| m.get(MTIME_METADATA))
+                    .and_then(|v| parse_mtime(v))
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Uploads a file, stamping its modification time into the object metadata.
+///
+/// With `preserve`, the metadata of `FileMetadata` is added, and a symbolic
+/// link is uploaded as an empty object rather than followed. A
+/// `content_sha256` is recorded as `content-sha256` metadata, except on a
+/// symbolic link.
+pub async fn upload_stamped(
+    client: &Client,
+    bucket: &str,
+    file: &LocalFile,
+    headers: &UploadHeaders,
+    preserve: bool,
+    content_sha256: Option<&str>,
+) -> Result<(), SdkError<PutObjectError>> {
+    let preserved = if preserve {
+        Some(
+            FileMetadata::read(&file.path)
+                .map_err(|err| SdkError::ConstructionFailure(Box::new(err)))?,
+        )
+    } else {
+        None
+    };
+    let is_symlink = preserved
+        .as_ref()
+        .map_or(false, |m| m.symlink_target.is_some());
+    let body = if is_symlink {
+        ByteStream::from_static(b"")
+    } else {
+        ByteStream::from_path(&file.path)
+            .await
+            .map_err(|err| SdkError::ConstructionFailure(Box::new(err)))?
+    };
+    let mut request = client
+        .put_object()
+        .bucket(bucket)
+        .key(&file.key)
+        .metadata(SOURCE_MTIME_METADATA, format_mtime(file.mtime))
+        .body(body);
+    if let Some(sha256) = content_sha256.filter(|_| !is_symlink) {
+        request = request.metadata(CONTENT_SHA256_METADATA, sha256);
+    }
+    for (key, value) in preserved.map(|m| m.to_metadata()).unwrap_or_default() {
+        request = request.metadata(key, value);
+    }
+    headers.apply_to_put_object(request).send().await?;
+    Ok(())
+}
+
+/// Uploads a file with `upload_stamped`, retrying transient failures after
+/// `policy.jittered_backoff`. Returns the number of attempts made.
+pub async fn upload_with_retries(
+    client: &Client,
+    bucket: &str,
+    file: &LocalFile,
+    headers: &UploadHeaders,
+    policy: &RetryPolicy,
+    preserve: bool,
+    content_sha256: Option<&str>,
+) -> (u32, Result<(), Error>) {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let err =
+            match upload_stamped(client, bucket, file, headers, preserve, content_sha256).await {
+                Ok(()) => return (attempt, Ok(())),
+                Err(err) => err,
+            };
+        if attempt >= policy.max_attempts || !is_retryable(&err) {
+            return (attempt, Err(err.into()));
+        }
+        let delay = policy.jittered_backoff(attempt - 1);
+        eprintln!(
+            "Retrying {} in {}, attempt {} of {}: {}",
+            file.key,
+            format_duration(delay),
+            attempt + 1,
+            policy.max_attempts,
+            err
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// `file` with the size and modification time its path has now, measured
+/// as `walk_directory_with_symlinks` does.
+pub fn restat(file: &LocalFile) -> std::io::Result<LocalFile> {
+    let metadata = std::fs::symlink_metadata(&file.path)?;
+    Ok(LocalFile {
+        size: if metadata.is_file() {
+            metadata.len()
+        } else {
+            0
+        },
+        mtime: metadata.modified()?,
+        ..file.clone()
+    })
+}
+
+/// What `upload_revalidated` did with a file planned for upload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevalidatedUpload {
+    Uploaded,
+    /// Planned again after a change, the file matches the object.
+    Identical,
+    /// Planned again after a change, the object is newer than the file.
+    NewerRemote,
+    /// The file kept changing and was given up on. An object written while
+    /// it changed is left as it is.
+    Unstable,
+}
+
+/// Uploads `file` with `upload_with_retries` if it is still as planned.
+///
+/// The file is stat'ed again just before the upload and once it is done.
+/// When it changed, it is planned again against `remote`, its object when
+/// listed, and uploaded as it is now if it still needs to be; after
+/// `options.max_replans` changes it is `Unstable`. With
+/// `options.stamp_content_hash`, the file is hashed before each upload.
+/// Returns the number of attempts made, over all the uploads.
+pub async fn upload_revalidated(
+    client: &Client,
+    bucket: &str,
+    file: &LocalFile,
+    remote: Option<&RemoteObject>,
+    options: &SyncOptions,
+) -> (u32, Result<RevalidatedUpload, Error>) {
+    let mut planned = file.clone();
+    let mut attempts = 0;
+    let mut replans = 0;
+    loop {
+        let current = match restat(&planned) {
+            Ok(current) => current,
+            Err(err) => return (attempts, Err(Error::Unhandled(Box::new(err)))),
+        };
+        if current != planned {
+            if replans == options.max_replans {
+                return (attempts, Ok(RevalidatedUpload::Unstable));
+            }
+            replans += 1;
+            eprintln!(
+                "{} changed since it was planned ({} bytes, now {}); planning it again",
+                planned.path.display(),
+                planned.size,
+                current.size
+            );
+            planned = current;
+            let remote: HashMap<String, RemoteObject> = remote
+                .map(|object| (object.key.clone(), object.clone()))
+                .into_iter()
+                .collect();
+            let plan = plan_sync(vec![planned.clone()], &remote, options);
+            if !plan.identical.is_empty() {
+                return (attempts, Ok(RevalidatedUpload::Identical));
+            }
+            if !plan.newer_remote.is_empty() {
+                return (attempts, Ok(RevalidatedUpload::NewerRemote));
+            }
+            continue;
+        }
+        let content_sha256 = if options.stamp_content_hash {
+            match sha256_file(&planned.path).await {
+                Ok(sha256) => Some(sha256),
+                Err(err) => return (attempts, Err(Error::Unhandled(Box::new(err)))),
+            }
+        } else {
+            None
+        };
+        let headers = options.headers.headers_for(&planned.key);
+        let (made, result) = upload_with_retries(
+            client,
+            bucket,
+            &planned,
+            &headers,
+            &options.retry,
+            options.preserve,
+            content_sha256.as_deref(),
+        )
+        .await;
+        attempts += made;
+        if let Err(err) = result {
+            return (attempts, Err(err));
+        }
+        // A file written to while it was read may have been sent torn;
+        // the next pass plans it again.
+        match restat(&planned) {
+            Ok(after) if after == planned => return (attempts, Ok(RevalidatedUpload::Uploaded)),
+            Ok(_) => {}
+            Err(err) => return (attempts, Err(Error::Unhandled(Box::new(err)))),
+        }
+    }
+}
+
+/// Outcome of `sync_directory`.
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    pub uploaded: Vec<String>,
+    pub identical: Vec<String>,
+    pub newer_remote: Vec<String>,
+    pub failed: Vec<(String, String)>,
+    /// Keys of the files that kept changing between planning and upload.
+    pub unstable: Vec<String>,
+    /// Archive objects written in batch mode.
+    pub archives: usize,
+    /// Uploaded files that went into an archive.
+    pub packed_files: usize,
+    /// Number of upload attempts of each file uploaded as its own object.
+    pub attempts: HashMap<PathBuf, u32>,
+    /// The throughput of the listing, with `parallel_list`.
+    pub listing: Option<ListStats>,
+    /// The stamps checked, with `verify_stamps`. The mismatched files are
+    /// in `uploaded` rather than `identical`.
+    pub stamps: Option<StampReport>,
+}
+
+impl SyncSummary {
+    /// Average number of files per archive.
+    pub fn packing_ratio(&self) -> f64 {
+        if self.archives == 0 {
+            0.0
+        } else {
+            self.packed_files as f64 / self.archives as f64
+        }
+    }
+
+    /// Requests saved by packing, compared to one PutObject per file.
+    pub fn requests_saved(&self) -> i64 {
+        self.packed_files as i64 - 2 * self.archives as i64
+    }
+
+    /// The files that needed more than one attempt, with their attempts.
+    pub fn retried(&self) -> Vec<(&Path, u32)> {
+        let mut retried = self
+            .attempts
+            .iter()
+            .filter(|(_, attempts)| **attempts > 1)
+            .map(|(path, attempts)| (path.as_path(), *attempts))
+            .collect::<Vec<_>>();
+        retried.sort();
+        retried
+    }
+}
+
+/// Uploads the files under `dir` that are missing or out of date under
+/// `prefix` in `bucket`.
+///
+/// With `options.batch`, the files packed in existing archives are compared
+/// like ordinary objects, and the small files to upload are packed into new
+/// archives. With `options.preserve`, symbolic links are synchronized too.
+///
+/// Each file uploaded as its own object is checked again when its upload
+/// starts, with `upload_revalidated`; the files packed in archives are not.
+///
+/// With `options.verify_stamps`, the files planned as identical are checked
+/// with `verify_stamps`, and those whose object holds other content are
+/// uploaded again as their own object, even in batch mode, so that the new
+/// object wins over the archived copy.
+pub async fn sync_directory(
+    client: &Client,
+    bucket: &str,
+    dir: &Path,
+    prefix: &str,
+    options: &SyncOptions,
+    dry_run: bool,
+) -> Result<SyncSummary, Error> {
+    let local = if options.preserve {
+        walk_directory_with_symlinks(dir, prefix)
+    } else {
+        walk_directory(dir, prefix)
+    }
+    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let (mut remote, listing) = match options.parallel_list {
+        Some(shards) => {
+            let (remote, stats) = list_remote_parallel(client, bucket, prefix, shards).await?;
+            (remote, Some(stats))
+        }
+        None => (list_remote(client, bucket, prefix).await?, None),
+    };
+    if (options.no_overwrite_newer && !options.force) || options.use_preserved_mtime {
+        fetch_source_mtimes(client, bucket, &local, &mut remote, options.concurrency).await?;
+    }
+    let packed = if options.batch.is_some() {
+        let indexes = read_indexes(client, bucket, &remote).await?;
+        expand_remote(&mut remote, &indexes)
+    } else {
+        HashMap::new()
+    };
+    let all_local = if options.verify_stamps.is_some() {
+        local.clone()
+    } else {
+        Vec::new()
+    };
+    let plan = plan_sync(local, &remote, options);
+
+    let mut summary = SyncSummary {
+        identical: plan.identical,
+        newer_remote: plan.newer_remote,
+        listing,
+        ..Default::default()
+    };
+    let mut restamped = Vec::new();
+    if let Some(sampling) = &options.verify_stamps {
+        let identical: HashSet<&str> = summary.identical.iter().map(|k| k.as_str()).collect();
+        let checked: Vec<LocalFile> = all_local
+            .iter()
+            .filter(|file| identical.contains(file.key.as_str()))
+            .cloned()
+            .collect();
+        let report = verify_stamps(client, bucket, &checked, &all_local, &packed, sampling).await?;
+        let mismatched: HashSet<String> = report.mismatched().map(|k| k.to_string()).collect();
+        summary.identical.retain(|key| !mismatched.contains(key));
+        restamped.extend(
+            checked
+                .into_iter()
+                .filter(|file| mismatched.contains(&file.key)),
+        );
+        summary.stamps = Some(report);
+    }
+    let files = plan.uploads.into_iter().map(|(file, _)| file).collect();
+    let (archives, mut individual) = match &options.batch {
+        Some(batch) => {
+            let batch = BatchOptions {
+                stamp_content_hash: batch.stamp_content_hash || options.stamp_content_hash,
+                ..*batch
+            };
+            let batch_plan = plan_batches(files, &batch);
+            (batch_plan.archives, batch_plan.individual)
+        }
+        None => (Vec::new(), files),
+    };
+    individual.extend(restamped);
+    if dry_run {
+        for archive in &archives {
+            println!(
+                "(dry run) pack {} files ({}) in {}",
+                archive.files.len(),
+                format_size(archive.size),
+                archive.directory
+            );
+            summary.archives += 1;
+            summary.packed_files += archive.files.len();
+            summary
+                .uploaded
+                .extend(archive.files.iter().map(|f| f.key.clone()));
+        }
+        for file in individual {
+            println!("(dry run) upload {}", file.key);
+            summary.uploaded.push(file.key);
+        }
+        return Ok(summary);
+    }
+
+    let archive_results = stream::iter(archives)
+        .map(|archive| async move {
+            let result = upload_archive_with_budget(
+                client,
+                bucket,
+                &archive,
+                options.memory_budget.as_ref(),
+            )
+            .await;
+            (archive, result)
+        })
+        .buffer_unordered(options.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    for (archive, result) in archive_results {
+        let keys = archive.files.into_iter().map(|f| f.key);
+        match result {
+            Ok(_) => {
+                summary.archives += 1;
+                summary.packed_files += keys.len();
+                summary.uploaded.extend(keys);
+            }
+            Err(err) => {
+                let err = err.to_string();
+                summary.failed.extend(keys.map(|key| (key, err.clone())));
+            }
+        }
+    }
+
+    let remote = &remote;
+    let results = stream::iter(individual)
+        .map(|file| async move {
+            let (attempts, result) =
+                upload_revalidated(client, bucket, &file, remote.get(&file.key), options).await;
+            (file, attempts, result)
+        })
+        .buffer_unordered(options.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    for (file, attempts, result) in results {
+        if attempts > 0 {
+            summary.attempts.insert(file.path, attempts);
+        }
+        match result {
+            Ok(RevalidatedUpload::Uploaded) => summary.uploaded.push(file.key),
+            Ok(RevalidatedUpload::Identical) => summary.identical.push(file.key),
+            Ok(RevalidatedUpload::NewerRemote) => summary.newer_remote.push(file.key),
+            Ok(RevalidatedUpload::Unstable) => summary.unstable.push(file.key),
+            Err(err) => summary.failed.push((file.key, err.to_string())),
+        }
+    }
+    Ok(summary)
+}
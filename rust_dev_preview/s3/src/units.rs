@@ -0,0 +1,104 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Sizes, rates, and durations as printed for people.
+//!
+//! Sizes and rates are in binary units, 1 KiB being 1024 bytes, the units
+//! sizes such as `--part-size 8MiB` are given in. Rates are always in MiB/s
+//! with one decimal, whatever their magnitude, so that lines compare at a
+//! glance, and say what they average: a live rate is over the last
+//! `LIVE_RATE_WINDOW`, a summary rate over the whole run. Durations read as
+//! `1m 23.4s`, and the latencies of single requests, which a tenth of a
+//! second would hide, as `12.3 ms`.
+//!
+//! JSON and statistics keep raw integers, bytes and milliseconds, for the
+//! programs reading them.
+
+use std::time::Duration;
+
+pub const KIB: u64 = 1024;
+pub const MIB: u64 = 1024 * KIB;
+
+/// The window live rates are averaged over.
+pub const LIVE_RATE_WINDOW: Duration = Duration::from_secs(10);
+
+const SIZE_UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+/// Such as `512 B`, `1.5 KiB`, or `8.0 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    if bytes < KIB {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    // Moves up a unit rather than print 1024.0.
+    while value >= 1023.95 && unit < SIZE_UNITS.len() - 1 {
+        value /= KIB as f64;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, SIZE_UNITS[unit])
+}
+
+/// Such as `17.8 MiB/s`.
+pub fn format_rate(bytes_per_second: f64) -> String {
+    format!("{:.1} MiB/s", bytes_per_second / MIB as f64)
+}
+
+/// `bytes` over `elapsed`, in bytes per second, or `None` when no time
+/// passed.
+pub fn average_rate(bytes: u64, elapsed: Duration) -> Option<f64> {
+    let seconds = elapsed.as_secs_f64();
+    (seconds > 0.0).then(|| bytes as f64 / seconds)
+}
+
+/// A rate over the last `LIVE_RATE_WINDOW`, such as `17.8 MiB/s (10s avg)`,
+/// or `-- MiB/s (10s avg)` before there is one.
+pub fn format_live_rate(bytes_per_second: Option<f64>) -> String {
+    labeled_rate(
+        bytes_per_second,
+        &format!("{}s avg", LIVE_RATE_WINDOW.as_secs()),
+    )
+}
+
+/// A rate over a whole run, such as `17.8 MiB/s (overall avg)`.
+pub fn format_overall_rate(bytes_per_second: Option<f64>) -> String {
+    labeled_rate(bytes_per_second, "overall avg")
+}
+
+fn labeled_rate(bytes_per_second: Option<f64>, label: &str) -> String {
+    match bytes_per_second {
+        Some(rate) => format!("{} ({})", format_rate(rate), label),
+        None => format!("-- MiB/s ({})", label),
+    }
+}
+
+/// Such as `0.4s`, `1m 23.4s`, or `2h 5m 0.0s`, to a tenth of a second.
+pub fn format_duration(duration: Duration) -> String {
+    let tenths = (duration.as_secs_f64() * 10.0).round() as u64;
+    let seconds = format!("{}.{}s", tenths % 600 / 10, tenths % 10);
+    let minutes = tenths / 600;
+    match minutes {
+        0 => seconds,
+        1..=59 => format!("{}m {}", minutes, seconds),
+        _ => format!("{}h {}m {}", minutes / 60, minutes % 60, seconds),
+    }
+}
+
+/// The latency of a request, such as `12.3 ms`.
+pub fn format_latency(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// The summary line of a transfer of `bytes` in `elapsed`, starting with
+/// `label`, such as `Uploaded 1.5 GiB in 1m 23.4s, 18.4 MiB/s (overall avg)`.
+pub fn summary_line(label: &str, bytes: u64, elapsed: Duration) -> String {
+    format!(
+        "{} {} in {}, {}",
+        label,
+        format_size(bytes),
+        format_duration(elapsed),
+        format_overall_rate(average_rate(bytes, elapsed))
+    )
+}
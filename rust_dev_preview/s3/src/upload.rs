@@ -0,0 +1,1739 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! File upload building blocks shared by the upload binaries.
+
+use crate::content_type::{sniff_content_type, SNIFF_LEN};
+use crate::failover::EndpointPool;
+use crate::integrity::UploadDigests;
+use crate::progress::{progress_reader, ProgressReporter};
+use crate::retry::{RetryPolicy, SlowDownCoordinator};
+use crate::shutdown::Shutdown;
+use crate::verbosity::{with_request_id, VerbosityConfig};
+use aws_sdk_s3::client::fluent_builders::{CreateMultipartUpload, PutObject};
+use aws_sdk_s3::model::CompletedMultipartUpload;
+use aws_sdk_s3::model::CompletedPart;
+use aws_sdk_s3::model::ServerSideEncryption;
+use aws_sdk_s3::types::{ByteStream, DateTime, SdkError};
+use aws_sdk_s3::{Client, Error};
+use bytes::Bytes;
+use chrono::Utc;
+use futures::{stream, Stream};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+/// Standard HTTP headers stored with an uploaded object and returned on
+/// every GET, and its encryption and tags. For multipart uploads they are
+/// set when the upload is created.
+#[derive(Debug, Clone, Default)]
+pub struct UploadHeaders {
+    pub content_type: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_language: Option<String>,
+    pub cache_control: Option<String>,
+    pub expires: Option<chrono::DateTime<Utc>>,
+    /// `AES256` or `aws:kms`.
+    pub server_side_encryption: Option<String>,
+    /// The KMS key of `aws:kms` encryption; the bucket's default without it.
+    pub ssekms_key_id: Option<String>,
+    /// The tags of the object, URL-encoded as `key1=value1&key2=value2`.
+    pub tagging: Option<String>,
+}
+
+impl UploadHeaders {
+    /// Each header of `self`, or of `fallback` where `self` has none.
+    pub fn or(&self, fallback: &UploadHeaders) -> UploadHeaders {
+        UploadHeaders {
+            content_type: self
+                .content_type
+                .clone()
+                .or_else(|| fallback.content_type.clone()),
+            content_disposition: self
+                .content_disposition
+                .clone()
+                .or_else(|| fallback.content_disposition.clone()),
+            content_encoding: self
+                .content_encoding
+                .clone()
+                .or_else(|| fallback.content_encoding.clone()),
+            content_language: self
+                .content_language
+                .clone()
+                .or_else(|| fallback.content_language.clone()),
+            cache_control: self
+                .cache_control
+                .clone()
+                .or_else(|| fallback.cache_control.clone()),
+            expires: self.expires.or(fallback.expires),
+            server_side_encryption: self
+                .server_side_encryption
+                .clone()
+                .or_else(|| fallback.server_side_encryption.clone()),
+            ssekms_key_id: self
+                .ssekms_key_id
+                .clone()
+                .or_else(|| fallback.ssekms_key_id.clone()),
+            tagging: self.tagging.clone().or_else(|| fallback.tagging.clone()),
+        }
+    }
+
+    fn expires_value(&self) -> Option<DateTime> {
+        self.expires.map(|e| DateTime::from_secs(e.timestamp()))
+    }
+
+    fn encryption_value(&self) -> Option<ServerSideEncryption> {
+        self.server_side_encryption
+            .as_deref()
+            .map(ServerSideEncryption::from)
+    }
+
+    pub fn apply_to_put_object(&self, builder: PutObject) -> PutObject {
+        builder
+            .set_content_type(self.content_type.clone())
+            .set_content_disposition(self.content_disposition.clone())
+            .set_content_encoding(self.content_encoding.clone())
+            .set_content_language(self.content_language.clone())
+            .set_cache_control(self.cache_control.clone())
+            .set_expires(self.expires_value())
+            .set_server_side_encryption(self.encryption_value())
+            .set_ssekms_key_id(self.ssekms_key_id.clone())
+            .set_tagging(self.tagging.clone())
+    }
+
+    pub fn apply_to_create_multipart_upload(
+        &self,
+        builder: CreateMultipartUpload,
+    ) -> CreateMultipartUpload {
+        builder
+            .set_content_type(self.content_type.clone())
+            .set_content_disposition(self.content_disposition.clone())
+            .set_content_encoding(self.content_encoding.clone())
+            .set_content_language(self.content_language.clone())
+            .set_cache_control(self.cache_control.clone())
+            .set_expires(self.expires_value())
+            .set_server_side_encryption(self.encryption_value())
+            .set_ssekms_key_id(self.ssekms_key_id.clone())
+            .set_tagging(self.tagging.clone())
+    }
+}
+
+/// Parses an `--expires` value, either RFC 3339 (`2022-01-31T00:00:00Z`) or
+/// an HTTP date (`Mon, 31 Jan 2022 00:00:00 GMT`).
+pub fn parse_expires(value: &str) -> Result<chrono::DateTime<Utc>, chrono::ParseError> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .or_else(|_| chrono::DateTime::parse_from_rfc2822(value))
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// Default size from which `plan_upload` switches to a multipart upload, as
+/// with the AWS CLI's `multipart_threshold`.
+pub const DEFAULT_MULTIPART_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+/// Size aimed for by the automatic part sizing.
+pub const DEFAULT_PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Smallest size of every part but the last.
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+/// Largest size of a part.
+pub const MAX_PART_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Largest number of parts in a multipart upload.
+pub const MAX_PARTS: u64 = 10_000;
+
+/// Largest object a single `PutObject` request can upload.
+pub const MAX_PUT_OBJECT_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Largest object Amazon S3 stores, whatever the upload.
+pub const MAX_OBJECT_SIZE: u64 = 5 * 1024 * 1024 * 1024 * 1024;
+
+/// Rejects objects over `MAX_OBJECT_SIZE` before anything is sent, rather
+/// than have Amazon S3 refuse the completion after thousands of parts.
+pub fn check_object_size(size: u64) -> Result<(), Error> {
+    if size <= MAX_OBJECT_SIZE {
+        return Ok(());
+    }
+    Err(Error::Unhandled(Box::from(format!(
+        "{} bytes is over the 5 TiB ({} bytes) limit of an Amazon S3 object. \
+         Split the data into several objects: upload windows of the file to separate keys \
+         with s3-transfer upload --source-offset and --source-length, split the file first \
+         (for example with split -b 4T), or compress it if it compresses well",
+        size, MAX_OBJECT_SIZE
+    ))))
+}
+
+/// The `Content-Length` of a `PutObject` of `chunk_size` bytes.
+///
+/// Chunks over `MAX_PUT_OBJECT_SIZE` are rejected before anything is sent,
+/// rather than after streaming gigabytes to have Amazon S3 refuse them.
+pub fn put_object_content_length(chunk_size: u64) -> Result<i64, Error> {
+    if chunk_size > MAX_PUT_OBJECT_SIZE {
+        return Err(Error::Unhandled(Box::from(format!(
+            "A chunk of {} bytes is over the 5 GiB limit of a single PutObject; \
+             use upload-file-multipart or s3-transfer upload, or pass --auto-multipart",
+            chunk_size
+        ))));
+    }
+    i64::try_from(chunk_size).map_err(|err| Error::Unhandled(Box::new(err)))
+}
+
+/// `a / b` rounded up, without overflowing for any `a`.
+fn div_ceil(a: u64, b: u64) -> u64 {
+    a / b + if a % b == 0 { 0 } else { 1 }
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadPlanOptions {
+    /// Files of at least this size are uploaded in parts.
+    pub multipart_threshold: u64,
+    /// Requested part size; the parts are at least this large.
+    pub part_size: Option<u64>,
+    /// Requested number of parts; takes precedence over `part_size`.
+    pub num_parts: Option<usize>,
+}
+
+impl Default for UploadPlanOptions {
+    fn default() -> Self {
+        Self {
+            multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+            part_size: None,
+            num_parts: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadStrategy {
+    PutObject,
+    Multipart,
+}
+
+/// How a file is uploaded. The parts have `part_size` bytes, except the last
+/// one which also takes the remainder, as in `upload_multipart`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UploadPlan {
+    pub strategy: UploadStrategy,
+    pub size: u64,
+    pub num_parts: usize,
+    pub part_size: u64,
+    pub last_part_size: u64,
+}
+
+/// Chooses between a single `PutObject` and a multipart upload for a file of
+/// `size` bytes, and the part layout of the latter.
+///
+/// Without a requested layout, parts of about `DEFAULT_PART_SIZE` are used,
+/// growing for files that would otherwise need more than `MAX_PARTS`. Any
+/// layout is adjusted so every part but the last is within `MIN_PART_SIZE`
+/// and `MAX_PART_SIZE`, and the last one, with the remainder, is within
+/// `MAX_PART_SIZE` too.
+///
+/// Sizes over `MAX_OBJECT_SIZE` cannot be laid out within those limits;
+/// they are rejected by `check_object_size`.
+pub fn plan_upload(size: u64, options: &UploadPlanOptions) -> UploadPlan {
+    if size < options.multipart_threshold {
+        return UploadPlan {
+            strategy: UploadStrategy::PutObject,
+            size,
+            num_parts: 1,
+            part_size: size,
+            last_part_size: size,
+        };
+    }
+    let requested = match (options.num_parts, options.part_size) {
+        (Some(num_parts), _) => num_parts as u64,
+        (None, Some(part_size)) => size / part_size.max(1),
+        (None, None) => {
+            let part_size = DEFAULT_PART_SIZE.max(div_ceil(size, MAX_PARTS));
+            size / part_size
+        }
+    };
+    let most = (size / MIN_PART_SIZE).min(MAX_PARTS).max(1);
+    let fewest = div_ceil(size, MAX_PART_SIZE).max(1);
+    let mut num_parts = requested.max(fewest).min(most);
+    // The last part also takes the remainder, which can push it over the
+    // limit when the other parts are just under it.
+    if size / num_parts + size % num_parts > MAX_PART_SIZE && num_parts < most {
+        num_parts += 1;
+    }
+    let part_size = size / num_parts;
+    UploadPlan {
+        strategy: UploadStrategy::Multipart,
+        size,
+        num_parts: num_parts as usize,
+        part_size,
+        last_part_size: part_size + size % num_parts,
+    }
+}
+
+/// The bytes of a file a multipart upload sends: `length` bytes from
+/// `offset`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SourceWindow {
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl SourceWindow {
+    /// The whole of a file of `file_len` bytes.
+    pub fn whole(file_len: u64) -> Self {
+        Self {
+            offset: 0,
+            length: file_len,
+        }
+    }
+
+    /// The window given by `--source-offset` and `--source-length` in a file
+    /// of `file_len` bytes. Without a length the window runs to the end of
+    /// the file. Windows extending past the end of the file are rejected.
+    pub fn resolve(
+        file_len: u64,
+        offset: Option<u64>,
+        length: Option<u64>,
+    ) -> Result<Self, String> {
+        let offset = offset.unwrap_or(0);
+        if offset > file_len {
+            return Err(format!(
+                "Source offset {} is past the end of the file ({} bytes)",
+                offset, file_len
+            ));
+        }
+        let length = length.unwrap_or(file_len - offset);
+        match offset.checked_add(length) {
+            Some(end) if end <= file_len => Ok(Self { offset, length }),
+            _ => Err(format!(
+                "Source window of {} bytes at offset {} extends past the end of the file ({} bytes)",
+                length, offset, file_len
+            )),
+        }
+    }
+
+    /// Reads the size of `file_name` and resolves the window in it.
+    pub fn for_file(
+        file_name: &str,
+        offset: Option<u64>,
+        length: Option<u64>,
+    ) -> Result<Self, Error> {
+        let file_len = std::fs::metadata(file_name)
+            .map_err(|err| Error::Unhandled(Box::new(err)))?
+            .len();
+        Self::resolve(file_len, offset, length).map_err(|msg| Error::Unhandled(Box::from(msg)))
+    }
+
+    /// The offset just past the window.
+    pub fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+}
+
+/// The `(offset, size)` in the file of each of the `num_parts` parts of
+/// `window`. The parts have the same size, except the last one which also
+/// takes the remainder. A window of fewer bytes than `num_parts` has a part
+/// per byte rather than empty parts, and an empty window is sent as a
+/// single empty part.
+pub fn part_ranges(window: SourceWindow, num_parts: usize) -> Vec<(u64, u64)> {
+    let num_parts = (num_parts as u64).min(window.length).max(1);
+    let chunk_size = window.length / num_parts;
+    (0..num_parts)
+        .map(|i| {
+            let size = if i != num_parts - 1 {
+                chunk_size
+            } else {
+                chunk_size + window.length % num_parts
+            };
+            (window.offset + i * chunk_size, size)
+        })
+        .collect()
+}
+
+/// Read buffer of each part when the memory of the host is unknown.
+pub const FALLBACK_BUFFER_CAPACITY: usize = 8 * 1024 * 1024;
+
+/// Smallest read buffer `auto_buffer_capacity` chooses.
+const MIN_BUFFER_CAPACITY: usize = 8 * 1024;
+
+/// A read buffer size for each of `num_parts` parts read at the same time:
+/// the size of a part, but small enough for all the buffers, counted twice
+/// for the copies in flight, to fit in the available memory. Falls back to
+/// `FALLBACK_BUFFER_CAPACITY` when the memory cannot be read.
+pub fn auto_buffer_capacity(file_size: u64, num_parts: u64) -> usize {
+    let capacity = buffer_capacity_for(file_size, num_parts, available_memory());
+    tracing::debug!(file_size, num_parts, capacity, "Chose the read buffer size");
+    capacity
+}
+
+/// `auto_buffer_capacity` with `available_memory` in bytes, if known.
+pub fn buffer_capacity_for(file_size: u64, num_parts: u64, available_memory: Option<u64>) -> usize {
+    let num_parts = num_parts.max(1);
+    let part_size = file_size / num_parts;
+    let limit = match available_memory {
+        Some(available) => available / (num_parts * 2),
+        None => FALLBACK_BUFFER_CAPACITY as u64,
+    };
+    let capacity = usize::try_from(part_size.min(limit)).unwrap_or(usize::MAX);
+    capacity.max(MIN_BUFFER_CAPACITY)
+}
+
+/// The memory available to new allocations, in bytes: `MemAvailable` of
+/// `/proc/meminfo` on Linux, `sysctl hw.memsize` on macOS, which is the
+/// physical memory. `None` elsewhere or when it cannot be read.
+pub fn available_memory() -> Option<u64> {
+    if cfg!(target_os = "linux") {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+        parse_meminfo(&meminfo)
+    } else if cfg!(target_os = "macos") {
+        let output = std::process::Command::new("sysctl")
+            .args(&["-n", "hw.memsize"])
+            .output()
+            .ok()?;
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+/// The `MemAvailable` line of `/proc/meminfo`, in bytes.
+pub fn parse_meminfo(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kib: u64 = line.trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Upload file chunk to bucket/key; uses framed read to minimize copies.
+/// Returns the `etag` of the new object, without quotes.
+///
+/// Without a Content-Type in `headers`, it is sniffed from the first bytes
+/// of the chunk, or else from the extension of `file_name`.
+pub async fn upload_chunk(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    start_offset: u64,
+    chunk_size: u64,
+    headers: Option<UploadHeaders>,
+) -> Result<String, Error> {
+    let content_length = put_object_content_length(chunk_size)?;
+    let file = tokio::fs::File::open(Path::new(file_name))
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let headers = with_sniffed_content_type(
+        &file,
+        file_name,
+        start_offset,
+        chunk_size,
+        headers.unwrap_or_default(),
+    )
+    .await
+    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let body = file_body(
+        &file,
+        start_offset,
+        chunk_size,
+        usize::try_from(chunk_size).ok(),
+    )
+    .await
+    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let request = client
+        .put_object()
+        .content_length(content_length)
+        .bucket(bucket)
+        .key(key)
+        .body(body);
+    let resp = headers.apply_to_put_object(request).send().await?;
+    Ok(resp
+        .e_tag()
+        .unwrap_or_default()
+        .trim_matches('"')
+        .to_string())
+}
+
+/// Same as `upload_chunk`, printing every `tick_interval` how many bytes
+/// were sent and the throughput over `progress::THROUGHPUT_WINDOW`.
+///
+/// The bytes are counted as the request reads them from the file. The
+/// progress task is stopped before this returns, whether the upload
+/// succeeded or not.
+pub async fn upload_chunk_with_progress(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    start_offset: u64,
+    chunk_size: u64,
+    tick_interval: Duration,
+) -> Result<String, Error> {
+    let content_length = put_object_content_length(chunk_size)?;
+    let file = tokio::fs::File::open(Path::new(file_name))
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let stream = file_stream(
+        &file,
+        start_offset,
+        chunk_size,
+        usize::try_from(chunk_size).ok(),
+    )
+    .await
+    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let written = Arc::new(AtomicU64::new(0));
+    let body = ByteStream::from(hyper::Body::wrap_stream(progress_reader(
+        stream,
+        written.clone(),
+    )));
+
+    let reporter = ProgressReporter::spawn(key.to_string(), chunk_size, written, tick_interval);
+    let resp = client
+        .put_object()
+        .content_length(content_length)
+        .bucket(bucket)
+        .key(key)
+        .body(body)
+        .send()
+        .await;
+    reporter.stop().await;
+    Ok(resp?
+        .e_tag()
+        .unwrap_or_default()
+        .trim_matches('"')
+        .to_string())
+}
+
+/// Same as `upload_chunk`, sending the object to the current endpoint of
+/// `endpoints` and retrying with the default `RetryPolicy`, on the next
+/// endpoint if the current one cannot be reached.
+pub async fn upload_chunk_with_endpoints(
+    endpoints: &EndpointPool,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    start_offset: u64,
+    chunk_size: u64,
+    headers: Option<UploadHeaders>,
+) -> Result<String, Error> {
+    upload_chunk_with_digests(
+        endpoints,
+        bucket,
+        key,
+        file_name,
+        start_offset,
+        chunk_size,
+        headers,
+        None,
+    )
+    .await
+}
+
+/// Same as `upload_chunk_with_endpoints`, hashing the bytes sent into
+/// `digests` as part 1.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_chunk_with_digests(
+    endpoints: &EndpointPool,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    start_offset: u64,
+    chunk_size: u64,
+    headers: Option<UploadHeaders>,
+    digests: Option<&UploadDigests>,
+) -> Result<String, Error> {
+    let content_length = put_object_content_length(chunk_size)?;
+    let file = tokio::fs::File::open(Path::new(file_name))
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let headers = with_sniffed_content_type(
+        &file,
+        file_name,
+        start_offset,
+        chunk_size,
+        headers.unwrap_or_default(),
+    )
+    .await
+    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let file = &file;
+    let headers = &headers;
+    let resp = endpoints
+        .retry(
+            &RetryPolicy::default(),
+            &SlowDownCoordinator::new(),
+            key,
+            move |client| async move {
+                let body = hashed_file_body(
+                    file,
+                    start_offset,
+                    chunk_size,
+                    usize::try_from(chunk_size).ok(),
+                    digests.map(|digests| (digests, 1)),
+                )
+                .await
+                .map_err(|err| SdkError::ConstructionFailure(Box::new(err)))?;
+                let request = client
+                    .put_object()
+                    .content_length(content_length)
+                    .bucket(bucket)
+                    .key(key)
+                    .body(body);
+                headers.apply_to_put_object(request).send().await
+            },
+        )
+        .await?;
+    Ok(resp
+        .e_tag()
+        .unwrap_or_default()
+        .trim_matches('"')
+        .to_string())
+}
+
+/// Same as `upload_chunk`, uploading chunks over `MAX_PUT_OBJECT_SIZE` with
+/// a multipart upload of the same bytes, in parts chosen by `plan_upload`.
+///
+/// The `etag` of a multipart upload is not the MD5 of the content: it ends
+/// with `-` and the number of parts.
+pub async fn upload_chunk_auto_multipart(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    start_offset: u64,
+    chunk_size: u64,
+    headers: Option<UploadHeaders>,
+) -> Result<String, Error> {
+    if chunk_size <= MAX_PUT_OBJECT_SIZE {
+        return upload_chunk(
+            client,
+            bucket,
+            key,
+            file_name,
+            start_offset,
+            chunk_size,
+            headers,
+        )
+        .await;
+    }
+    let window = SourceWindow::for_file(file_name, Some(start_offset), Some(chunk_size))?;
+    let plan = plan_upload(chunk_size, &UploadPlanOptions::default());
+    upload_multipart_window(
+        &EndpointPool::single(client.clone()),
+        bucket,
+        key,
+        file_name,
+        window,
+        plan.num_parts,
+        None,
+        headers,
+    )
+    .await
+}
+
+/// Multipart upload
+///
+/// 1. retrieve `upload id`
+/// 2. iterate over file chunks and send each chunk as a separate part
+/// 3. store returned `etag` and `part number` into `Vec`
+/// 4. complete upload by sending list of `(etag, part id`) to server
+/// 5. return the `etag` of the new object, without quotes
+///
+/// Parts are retried with the default `RetryPolicy`. If a part or the
+/// completion still fails the multipart upload is aborted, so no orphaned
+/// parts are left behind.
+///
+/// Without a Content-Type in `headers`, it is sniffed as by `upload_chunk`.
+pub async fn upload_multipart(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    num_parts: usize,
+    buffer_capacity: Option<usize>, // None for default
+    headers: Option<UploadHeaders>,
+) -> Result<String, Error> {
+    upload_multipart_with_endpoints(
+        &EndpointPool::single(client.clone()),
+        bucket,
+        key,
+        file_name,
+        num_parts,
+        buffer_capacity,
+        headers,
+    )
+    .await
+}
+
+/// Same as `upload_multipart`, sending each request to the current endpoint
+/// of `endpoints`. A part whose endpoint goes away is retried on the next
+/// one, and the upload continues there.
+pub async fn upload_multipart_with_endpoints(
+    endpoints: &EndpointPool,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    headers: Option<UploadHeaders>,
+) -> Result<String, Error> {
+    let window = SourceWindow::for_file(file_name, None, None)?;
+    upload_multipart_window(
+        endpoints,
+        bucket,
+        key,
+        file_name,
+        window,
+        num_parts,
+        buffer_capacity,
+        headers,
+    )
+    .await
+}
+
+/// Same as `upload_multipart_with_endpoints`, uploading only the bytes of
+/// `window` instead of the whole file.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_multipart_window(
+    endpoints: &EndpointPool,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    window: SourceWindow,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    headers: Option<UploadHeaders>,
+) -> Result<String, Error> {
+    upload_multipart_window_with_verbosity(
+        endpoints,
+        bucket,
+        key,
+        file_name,
+        window,
+        num_parts,
+        buffer_capacity,
+        headers,
+        VerbosityConfig::default(),
+    )
+    .await
+}
+
+/// Same as `upload_multipart_window`, printing a line per part as
+/// `verbosity` says.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_multipart_window_with_verbosity(
+    endpoints: &EndpointPool,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    window: SourceWindow,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    headers: Option<UploadHeaders>,
+    verbosity: VerbosityConfig,
+) -> Result<String, Error> {
+    upload_multipart_window_with_digests(
+        endpoints,
+        bucket,
+        key,
+        file_name,
+        window,
+        num_parts,
+        buffer_capacity,
+        headers,
+        verbosity,
+        None,
+    )
+    .await
+}
+
+/// Same as `upload_multipart_window_with_verbosity`, hashing the bytes of
+/// each part into `digests` as they are sent.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_multipart_window_with_digests(
+    endpoints: &EndpointPool,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    window: SourceWindow,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    headers: Option<UploadHeaders>,
+    verbosity: VerbosityConfig,
+    digests: Option<&UploadDigests>,
+) -> Result<String, Error> {
+    check_object_size(window.length)?;
+    let file = tokio::fs::File::open(file_name)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let headers = with_sniffed_content_type(
+        &file,
+        file_name,
+        window.offset,
+        window.length,
+        headers.unwrap_or_default(),
+    )
+    .await
+    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let policy = RetryPolicy::default();
+    let coordinator = SlowDownCoordinator::new();
+    let uid = create_upload(endpoints, bucket, key, Some(headers), &policy, &coordinator).await?;
+    let uid = uid.as_str();
+    // Iterate over file chunks, changing the file pointer at each iteration
+    // and storing returned part id and associated etag into vector.
+    let mut completed_parts: Vec<CompletedPart> = Vec::new();
+    let ranges = part_ranges(window, num_parts);
+    let total = ranges.len();
+    for (i, (offset, size)) in ranges.into_iter().enumerate() {
+        let start = Instant::now();
+        let (part, request_id) = with_request_id(upload_part_with_digests(
+            endpoints,
+            &file,
+            PartTarget {
+                bucket,
+                key,
+                uid,
+                part_number: (i + 1) as i32,
+                offset,
+                size,
+            },
+            buffer_capacity,
+            &policy,
+            &coordinator,
+            digests,
+        ))
+        .await;
+        match part {
+            Ok(cp) => {
+                verbosity.report(
+                    "part",
+                    "uploaded",
+                    i + 1,
+                    total,
+                    size,
+                    start.elapsed(),
+                    request_id.as_deref(),
+                );
+                completed_parts.push(cp)
+            }
+            Err(err) => {
+                abort_upload(&endpoints.client().1, bucket, key, uid).await;
+                return Err(err);
+            }
+        }
+    }
+    let completed = complete_upload(
+        endpoints,
+        bucket,
+        key,
+        uid,
+        completed_parts,
+        &policy,
+        &coordinator,
+    )
+    .await;
+    if completed.is_err() {
+        // Aborting an upload that did complete only fails with NoSuchUpload.
+        abort_upload(&endpoints.client().1, bucket, key, uid).await;
+    }
+    completed
+}
+
+/// Uploads `parts`, as `(part number, offset, size)` in `file_name`, to the
+/// existing upload `uid`, then completes it with them and `kept`, the
+/// `(part number, ETag)` of the parts already stored. `on_part` is called
+/// with the number and size of each part uploaded.
+///
+/// Unlike the other uploads, a failure does not abort the upload, so that
+/// it can be resumed again.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_remaining_parts(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    uid: &str,
+    kept: &[(i32, String)],
+    parts: &[(i32, u64, u64)],
+    on_part: &(dyn Fn(i32, u64) + Sync),
+) -> Result<String, Error> {
+    let file = tokio::fs::File::open(file_name)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let endpoints = EndpointPool::single(client.clone());
+    let policy = RetryPolicy::default();
+    let coordinator = SlowDownCoordinator::new();
+    let mut completed_parts: Vec<CompletedPart> = kept
+        .iter()
+        .map(|(part_number, e_tag)| {
+            CompletedPart::builder()
+                .e_tag(format!("\"{}\"", e_tag))
+                .part_number(*part_number)
+                .build()
+        })
+        .collect();
+    for (part_number, offset, size) in parts {
+        let part = upload_part(
+            &endpoints,
+            &file,
+            PartTarget {
+                bucket,
+                key,
+                uid,
+                part_number: *part_number,
+                offset: *offset,
+                size: *size,
+            },
+            None,
+            &policy,
+            &coordinator,
+        )
+        .await?;
+        on_part(*part_number, *size);
+        completed_parts.push(part);
+    }
+    complete_upload(
+        &endpoints,
+        bucket,
+        key,
+        uid,
+        completed_parts,
+        &policy,
+        &coordinator,
+    )
+    .await
+}
+
+/// Same as `upload_multipart`, uploading all the parts concurrently, one task
+/// per part.
+///
+/// The tasks share a `SlowDownCoordinator`, so when the endpoint throttles one
+/// part all of them back off. The results are processed in the order the
+/// parts finished, so the error returned is the first one to happen.
+///
+/// The parts are spawned with `tokio::spawn` and nothing blocks a worker,
+/// so it must be called within a Tokio runtime, of either flavor: on a
+/// current-thread runtime the parts still overlap while they wait on the
+/// network.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_multipart_parallel(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    headers: Option<UploadHeaders>,
+    policy: &RetryPolicy,
+) -> Result<String, Error> {
+    upload_multipart_parallel_with_verbosity(
+        client,
+        bucket,
+        key,
+        file_name,
+        num_parts,
+        buffer_capacity,
+        headers,
+        policy,
+        VerbosityConfig::default(),
+    )
+    .await
+}
+
+/// Same as `upload_multipart_parallel`, printing a line per part as
+/// `verbosity` says, in the order the parts finish.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_multipart_parallel_with_verbosity(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    headers: Option<UploadHeaders>,
+    policy: &RetryPolicy,
+    verbosity: VerbosityConfig,
+) -> Result<String, Error> {
+    upload_multipart_parallel_with_hooks(
+        client,
+        bucket,
+        key,
+        file_name,
+        num_parts,
+        buffer_capacity,
+        headers,
+        policy,
+        DispatchHooks {
+            verbosity,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Same as `upload_multipart_parallel_with_verbosity`, stopping on
+/// `shutdown`.
+///
+/// Once a stop is requested no more parts are started, and the parts in
+/// flight get the grace period of `shutdown` to finish; the upload is then
+/// aborted and an error returned. When the grace period ends with parts
+/// still uploading, they are dropped, and as one may still be stored after
+/// the abort, the upload ID is printed to check for it.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_multipart_parallel_with_shutdown(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    headers: Option<UploadHeaders>,
+    policy: &RetryPolicy,
+    verbosity: VerbosityConfig,
+    shutdown: &Shutdown,
+) -> Result<String, Error> {
+    upload_multipart_parallel_with_hooks(
+        client,
+        bucket,
+        key,
+        file_name,
+        num_parts,
+        buffer_capacity,
+        headers,
+        policy,
+        DispatchHooks {
+            verbosity,
+            shutdown: Some(shutdown.clone()),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Options of `upload_multipart_parallel_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct ParallelUploadOptions {
+    pub buffer_capacity: Option<usize>,
+    pub headers: Option<UploadHeaders>,
+    pub policy: RetryPolicy,
+    pub verbosity: VerbosityConfig,
+    /// The parts uploading at the same time; all of them without a limit.
+    pub max_concurrent_parts: Option<usize>,
+}
+
+/// Same as `upload_multipart_parallel`, with the settings of `options`.
+pub async fn upload_multipart_parallel_with_options(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    num_parts: usize,
+    options: &ParallelUploadOptions,
+) -> Result<String, Error> {
+    upload_multipart_parallel_with_hooks(
+        client,
+        bucket,
+        key,
+        file_name,
+        num_parts,
+        options.buffer_capacity,
+        options.headers.clone(),
+        &options.policy,
+        DispatchHooks {
+            verbosity: options.verbosity,
+            permits: options
+                .max_concurrent_parts
+                .map(|parts| Arc::new(Semaphore::new(parts.max(1)))),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Default size from which `upload_auto` switches to a multipart upload.
+pub const AUTO_MULTIPART_THRESHOLD: u64 = 100 * 1024 * 1024;
+
+/// Settings of `upload_auto`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoUploadConfig {
+    /// Files of at least this size are uploaded in parts. Files over
+    /// `MAX_PUT_OBJECT_SIZE` always are.
+    pub multipart_threshold: u64,
+    /// Requested part size, adjusted by `plan_upload` to the part limits.
+    pub part_size: u64,
+    /// The parts uploading at the same time.
+    pub concurrency: usize,
+}
+
+impl Default for AutoUploadConfig {
+    fn default() -> Self {
+        Self {
+            multipart_threshold: AUTO_MULTIPART_THRESHOLD,
+            part_size: DEFAULT_PART_SIZE,
+            concurrency: 8,
+        }
+    }
+}
+
+/// Uploads `file_name` to bucket/key with a single `upload_chunk` below
+/// `config.multipart_threshold`, and with `upload_multipart_parallel` in
+/// parts of about `config.part_size` from it, so the caller need not
+/// choose. Returns the `etag` of the new object, without quotes.
+pub async fn upload_auto(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    config: AutoUploadConfig,
+) -> Result<String, Error> {
+    let size = tokio::fs::metadata(file_name)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+        .len();
+    check_object_size(size)?;
+    let plan = plan_upload(
+        size,
+        &UploadPlanOptions {
+            multipart_threshold: config.multipart_threshold.min(MAX_PUT_OBJECT_SIZE + 1),
+            part_size: Some(config.part_size),
+            num_parts: None,
+        },
+    );
+    let e_tag = match plan.strategy {
+        UploadStrategy::PutObject => {
+            upload_chunk(client, bucket, key, file_name, 0, size, None).await?
+        }
+        UploadStrategy::Multipart => {
+            let options = ParallelUploadOptions {
+                max_concurrent_parts: Some(config.concurrency.max(1)),
+                ..Default::default()
+            };
+            upload_multipart_parallel_with_options(
+                client,
+                bucket,
+                key,
+                file_name,
+                plan.num_parts,
+                &options,
+            )
+            .await?
+        }
+    };
+    Ok(e_tag.trim_matches('"').to_string())
+}
+
+/// A step of `upload_multipart_parallel`, recorded by a debug schedule.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PartEvent {
+    /// The task of the part was started.
+    Dispatched { part: i32 },
+    /// The part finished uploading, or failed.
+    Completed { part: i32, ok: bool },
+}
+
+/// Hooks into the dispatch loop of `upload_multipart_parallel`, with which a
+/// debug schedule makes the interleaving of the parts reproducible. The
+/// default starts every part at once, in order.
+#[derive(Debug, Default)]
+pub(crate) struct DispatchHooks {
+    /// The indexes of the parts in the order they are started.
+    pub order: Option<Vec<usize>>,
+    /// Limits the parts in flight; taken in dispatch order.
+    pub permits: Option<Arc<Semaphore>>,
+    /// A wait before uploading each of these parts, by part number.
+    pub delays: HashMap<i32, Duration>,
+    pub log: Option<Arc<Mutex<Vec<PartEvent>>>>,
+    /// Prints a line as each part finishes.
+    pub verbosity: VerbosityConfig,
+    /// Stops starting parts, then drops those in flight.
+    pub shutdown: Option<Shutdown>,
+}
+
+impl DispatchHooks {
+    fn record(&self, event: PartEvent) {
+        if let Some(log) = &self.log {
+            log.lock().unwrap().push(event);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn upload_multipart_parallel_with_hooks(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    file_name: &str,
+    num_parts: usize,
+    buffer_capacity: Option<usize>,
+    headers: Option<UploadHeaders>,
+    policy: &RetryPolicy,
+    hooks: DispatchHooks,
+) -> Result<String, Error> {
+    let window = SourceWindow::for_file(file_name, None, None)?;
+    check_object_size(window.length)?;
+    let file = tokio::fs::File::open(file_name)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let endpoints = EndpointPool::single(client.clone());
+    let coordinator = SlowDownCoordinator::new();
+    let uid = create_upload(&endpoints, bucket, key, headers, policy, &coordinator).await?;
+
+    let ranges = part_ranges(window, num_parts);
+    let total = ranges.len();
+    // An empty file has a single part whatever `num_parts` says, and nothing
+    // to order.
+    let order = hooks
+        .order
+        .clone()
+        .filter(|order| order.len() == ranges.len())
+        .unwrap_or_else(|| (0..ranges.len()).collect());
+    let hooks = Arc::new(hooks);
+    let finished = Arc::new(AtomicUsize::new(0));
+    let mut handles = Vec::new();
+    for i in order {
+        if hooks.shutdown.as_ref().map_or(false, Shutdown::is_stopping) {
+            break;
+        }
+        let (offset, size) = ranges[i];
+        let part_number = (i + 1) as i32;
+        let permit = match &hooks.permits {
+            Some(permits) => Some(
+                permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?,
+            ),
+            None => None,
+        };
+        hooks.record(PartEvent::Dispatched { part: part_number });
+        let endpoints = endpoints.clone();
+        let bucket = bucket.to_string();
+        let key = key.to_string();
+        let uid = uid.clone();
+        let policy = *policy;
+        let coordinator = coordinator.clone();
+        let hooks = hooks.clone();
+        let finished = finished.clone();
+        let file = file
+            .try_clone()
+            .await
+            .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        handles.push(tokio::spawn(async move {
+            if let Some(delay) = hooks.delays.get(&part_number) {
+                tokio::time::sleep(*delay).await;
+            }
+            let start = Instant::now();
+            let upload = with_request_id(upload_part(
+                &endpoints,
+                &file,
+                PartTarget {
+                    bucket: &bucket,
+                    key: &key,
+                    uid: &uid,
+                    part_number,
+                    offset,
+                    size,
+                },
+                buffer_capacity,
+                &policy,
+                &coordinator,
+            ));
+            let (part, request_id) = match &hooks.shutdown {
+                Some(shutdown) => tokio::select! {
+                    result = upload => result,
+                    _ = shutdown.aborted() => (
+                        Err(Error::Unhandled(Box::from(format!(
+                            "Part {} was dropped at the end of the grace period",
+                            part_number
+                        )))),
+                        None,
+                    ),
+                },
+                None => upload.await,
+            };
+            if part.is_ok() {
+                hooks.verbosity.report(
+                    "part",
+                    "uploaded",
+                    part_number as usize,
+                    total,
+                    size,
+                    start.elapsed(),
+                    request_id.as_deref(),
+                );
+            }
+            hooks.record(PartEvent::Completed {
+                part: part_number,
+                ok: part.is_ok(),
+            });
+            drop(permit);
+            (finished.fetch_add(1, Ordering::SeqCst), part)
+        }));
+    }
+    let mut results = Vec::new();
+    let mut failure = None;
+    for h in handles {
+        match h.await {
+            Ok(result) => results.push(result),
+            Err(err) => failure = failure.or_else(|| Some(Error::Unhandled(Box::new(err)))),
+        }
+    }
+    results.sort_by_key(|(finished, _)| *finished);
+    let mut completed_parts = Vec::new();
+    for (_, result) in results {
+        match result {
+            Ok(cp) => completed_parts.push(cp),
+            Err(err) => failure = failure.or(Some(err)),
+        }
+    }
+    if let Some(shutdown) = hooks.shutdown.as_ref().filter(|s| s.is_stopping()) {
+        abort_upload(client, bucket, key, &uid).await;
+        if shutdown.is_aborted() {
+            eprintln!(
+                "Parts of upload {} of {} were still in flight at the end of the grace period \
+                 and may be stored after the abort; if ListMultipartUploads still lists it, \
+                 abort it again",
+                uid, key
+            );
+        }
+        return Err(Error::Unhandled(Box::from(format!(
+            "The upload {} of {} was stopped after {} of {} parts, and aborted",
+            uid,
+            key,
+            completed_parts.len(),
+            total
+        ))));
+    }
+    if let Some(err) = failure {
+        abort_upload(client, bucket, key, &uid).await;
+        return Err(err);
+    }
+    complete_upload(
+        &endpoints,
+        bucket,
+        key,
+        &uid,
+        completed_parts,
+        policy,
+        &coordinator,
+    )
+    .await
+}
+
+/// A limit on the parts uploaded at the same time, shared by the multipart
+/// uploads of many files.
+///
+/// Each `upload_multipart_parallel` starts one task per part, so uploading
+/// hundreds of files that way opens thousands of connections. The uploads
+/// of a pool instead take a permit for each part, so no more than
+/// `total_concurrent_parts` parts are in flight across all files.
+#[derive(Debug, Clone)]
+pub struct SharedUploadPool {
+    pub semaphore: Arc<Semaphore>,
+}
+
+impl SharedUploadPool {
+    /// A pool of `total_concurrent_parts` permits, which must not be zero:
+    /// the uploads of an empty pool would wait forever.
+    pub fn new(total_concurrent_parts: usize) -> Self {
+        assert!(
+            total_concurrent_parts > 0,
+            "A shared upload pool needs at least one concurrent part"
+        );
+        Self {
+            semaphore: Arc::new(Semaphore::new(total_concurrent_parts)),
+        }
+    }
+
+    /// Starts the multipart upload of `file_name` to `bucket/key` in parts
+    /// of at least `part_size` bytes, and returns its task. The task's
+    /// result is the `etag` of the object, without quotes. A `part_size`
+    /// below `MIN_PART_SIZE` is raised to it, as smaller parts would be
+    /// rejected by Amazon S3.
+    ///
+    /// Parts are retried with the default `RetryPolicy`, and the throttling
+    /// of a part holds back the other parts of the same file. If a part
+    /// still fails the multipart upload is aborted.
+    pub fn upload_file(
+        &self,
+        client: &Client,
+        bucket: &str,
+        key: &str,
+        file_name: &str,
+        part_size: u64,
+    ) -> JoinHandle<Result<String, Error>> {
+        let semaphore = self.semaphore.clone();
+        let endpoints = EndpointPool::single(client.clone());
+        let bucket = bucket.to_string();
+        let key = key.to_string();
+        let file_name = file_name.to_string();
+        tokio::spawn(async move {
+            let window = SourceWindow::for_file(&file_name, None, None)?;
+            check_object_size(window.length)?;
+            let num_parts = (window.length / part_size.max(MIN_PART_SIZE))
+                .max(1)
+                .min(MAX_PARTS) as usize;
+            let file = tokio::fs::File::open(&file_name)
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+            let policy = RetryPolicy::default();
+            let coordinator = SlowDownCoordinator::new();
+            let uid = create_upload(&endpoints, &bucket, &key, None, &policy, &coordinator).await?;
+
+            let mut handles = Vec::new();
+            for (i, (offset, size)) in part_ranges(window, num_parts).into_iter().enumerate() {
+                // Waiting here also keeps this file from starting more
+                // part tasks than it can run.
+                let permit = semaphore
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                let endpoints = endpoints.clone();
+                let bucket = bucket.clone();
+                let key = key.clone();
+                let uid = uid.clone();
+                let coordinator = coordinator.clone();
+                // The clones share a position, which `upload_part` leaves
+                // alone: each part is read at its offset.
+                let file = file
+                    .try_clone()
+                    .await
+                    .map_err(|err| Error::Unhandled(Box::new(err)))?;
+                handles.push(tokio::spawn(async move {
+                    let part = upload_part(
+                        &endpoints,
+                        &file,
+                        PartTarget {
+                            bucket: &bucket,
+                            key: &key,
+                            uid: &uid,
+                            part_number: (i + 1) as i32,
+                            offset,
+                            size,
+                        },
+                        None,
+                        &policy,
+                        &coordinator,
+                    )
+                    .await;
+                    drop(permit);
+                    part
+                }));
+            }
+            let mut completed_parts = Vec::new();
+            let mut failure = None;
+            for h in handles {
+                match h.await {
+                    Ok(Ok(cp)) => completed_parts.push(cp),
+                    Ok(Err(err)) => failure = failure.or(Some(err)),
+                    Err(err) => failure = failure.or_else(|| Some(Error::Unhandled(Box::new(err)))),
+                }
+            }
+            if let Some(err) = failure {
+                abort_upload(&endpoints.client().1, &bucket, &key, &uid).await;
+                return Err(err);
+            }
+            complete_upload(
+                &endpoints,
+                &bucket,
+                &key,
+                &uid,
+                completed_parts,
+                &policy,
+                &coordinator,
+            )
+            .await
+        })
+    }
+}
+
+/// Initiates a multipart upload and returns its upload id.
+pub(crate) async fn create_upload(
+    endpoints: &EndpointPool,
+    bucket: &str,
+    key: &str,
+    headers: Option<UploadHeaders>,
+    policy: &RetryPolicy,
+    coordinator: &SlowDownCoordinator,
+) -> Result<String, Error> {
+    let headers = headers.unwrap_or_default();
+    let what = format!("creation of the upload of {}", key);
+    let u = endpoints
+        .retry(policy, coordinator, &what, |client| {
+            let request = client.create_multipart_upload().bucket(bucket).key(key);
+            headers.apply_to_create_multipart_upload(request).send()
+        })
+        .await?;
+    let uid = u.upload_id().ok_or(Error::NoSuchUpload(
+        aws_sdk_s3::error::NoSuchUpload::builder()
+            .message("No upload ID")
+            .build(),
+    ))?;
+    Ok(uid.to_string())
+}
+
+/// Completes a multipart upload, sending the (etag, part id) list along the
+/// request, and returns the `etag` of the object without quotes.
+pub(crate) async fn complete_upload(
+    endpoints: &EndpointPool,
+    bucket: &str,
+    key: &str,
+    uid: &str,
+    mut completed_parts: Vec<CompletedPart>,
+    policy: &RetryPolicy,
+    coordinator: &SlowDownCoordinator,
+) -> Result<String, Error> {
+    completed_parts.sort_by_key(|p| p.part_number);
+    let b = CompletedMultipartUpload::builder()
+        .set_parts(Some(completed_parts))
+        .build();
+    let what = format!("completion of the upload of {}", key);
+    let completed = endpoints
+        .retry(policy, coordinator, &what, |client| {
+            client
+                .complete_multipart_upload()
+                .multipart_upload(b.clone())
+                .upload_id(uid)
+                .bucket(bucket)
+                .key(key)
+                .send()
+        })
+        .await?;
+    Ok(completed.e_tag.unwrap_or_default().replace("\"", ""))
+}
+
+/// Where a part goes and which bytes of the file it holds.
+pub(crate) struct PartTarget<'a> {
+    pub bucket: &'a str,
+    pub key: &'a str,
+    pub uid: &'a str,
+    pub part_number: i32,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Uploads `target.size` bytes of `file` starting at `target.offset`,
+/// retrying according to `policy`.
+pub(crate) async fn upload_part(
+    endpoints: &EndpointPool,
+    file: &tokio::fs::File,
+    target: PartTarget<'_>,
+    buffer_capacity: Option<usize>,
+    policy: &RetryPolicy,
+    coordinator: &SlowDownCoordinator,
+) -> Result<CompletedPart, Error> {
+    upload_part_with_digests(
+        endpoints,
+        file,
+        target,
+        buffer_capacity,
+        policy,
+        coordinator,
+        None,
+    )
+    .await
+}
+
+/// Same as `upload_part`, hashing the bytes of each attempt into `digests`
+/// as they are sent.
+async fn upload_part_with_digests(
+    endpoints: &EndpointPool,
+    file: &tokio::fs::File,
+    target: PartTarget<'_>,
+    buffer_capacity: Option<usize>,
+    policy: &RetryPolicy,
+    coordinator: &SlowDownCoordinator,
+    digests: Option<&UploadDigests>,
+) -> Result<CompletedPart, Error> {
+    let PartTarget {
+        bucket,
+        key,
+        uid,
+        part_number,
+        offset,
+        size,
+    } = target;
+    let content_length = i64::try_from(size).map_err(|err| Error::Unhandled(Box::new(err)))?;
+    let what = format!("part {} of {}", part_number, key);
+    // The body is consumed by each attempt, so it is rebuilt from the file.
+    let up = endpoints
+        .retry(policy, coordinator, &what, move |client| async move {
+            let body = hashed_file_body(
+                file,
+                offset,
+                size,
+                buffer_capacity,
+                digests.map(|digests| (digests, part_number as u32)),
+            )
+            .await
+            .map_err(|err| SdkError::ConstructionFailure(Box::new(err)))?;
+            client
+                .upload_part()
+                .bucket(bucket)
+                .key(key)
+                .content_length(content_length)
+                .upload_id(uid)
+                .part_number(part_number)
+                .body(body)
+                .send()
+                .await
+        })
+        .await?;
+    Ok(CompletedPart::builder()
+        .set_e_tag(up.e_tag)
+        .part_number(part_number)
+        .build())
+}
+
+/// `headers`, with the Content-Type of `file_name` sniffed from the first
+/// `SNIFF_LEN` bytes of the object, at `offset` in `file`, if it has none.
+///
+/// The bytes are read at `offset`, leaving the position of `file` as it is.
+async fn with_sniffed_content_type(
+    file: &tokio::fs::File,
+    file_name: &str,
+    offset: u64,
+    size: u64,
+    mut headers: UploadHeaders,
+) -> std::io::Result<UploadHeaders> {
+    if headers.content_type.is_some() {
+        return Ok(headers);
+    }
+    let file = Arc::new(file.try_clone().await?.into_std().await);
+    let first_bytes = read_at(file, offset, size.min(SNIFF_LEN as u64) as usize).await?;
+    headers.content_type = Some(sniff_content_type(file_name, &first_bytes).to_string());
+    Ok(headers)
+}
+
+/// Reads `size` bytes of `file` from `offset` as a request body, streamed
+/// as it is read rather than held in memory.
+async fn file_body(
+    file: &tokio::fs::File,
+    offset: u64,
+    size: u64,
+    buffer_capacity: Option<usize>,
+) -> std::io::Result<ByteStream> {
+    hashed_file_body(file, offset, size, buffer_capacity, None).await
+}
+
+/// Same as `file_body`, hashing the bytes into `digests` as part `number`
+/// when given.
+async fn hashed_file_body(
+    file: &tokio::fs::File,
+    offset: u64,
+    size: u64,
+    buffer_capacity: Option<usize>,
+    digests: Option<(&UploadDigests, u32)>,
+) -> std::io::Result<ByteStream> {
+    let stream = file_stream(file, offset, size, buffer_capacity).await?;
+    Ok(match digests {
+        Some((digests, number)) => {
+            ByteStream::from(hyper::Body::wrap_stream(digests.reader(number, stream)))
+        }
+        None => ByteStream::from(hyper::Body::wrap_stream(stream)),
+    })
+}
+
+/// The chunks read by `file_stream` without a `buffer_capacity`.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// The largest chunk read by `file_stream`, so that a large
+/// `buffer_capacity` still reports its progress as it goes.
+const MAX_READ_CHUNK: usize = 2 * 1024 * 1024;
+
+/// The read of `size` bytes of `file` from `offset`, in chunks of
+/// `buffer_capacity` bytes.
+///
+/// The clones of a file share its position, so the parts of an upload,
+/// read at the same time from clones of one file, are read at their offset
+/// rather than seeked to: a seek of one part would move the others.
+pub(crate) async fn file_stream(
+    file: &tokio::fs::File,
+    offset: u64,
+    size: u64,
+    buffer_capacity: Option<usize>,
+) -> std::io::Result<impl Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static> {
+    let file = Arc::new(file.try_clone().await?.into_std().await);
+    let chunk = buffer_capacity
+        .unwrap_or(READ_CHUNK)
+        .max(1)
+        .min(MAX_READ_CHUNK) as u64;
+    let end = offset + size;
+    Ok(stream::try_unfold(offset, move |position| {
+        let file = file.clone();
+        async move {
+            if position >= end {
+                return Ok(None);
+            }
+            let data = read_at(file, position, (end - position).min(chunk) as usize).await?;
+            if data.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "The file ended {} bytes before the end of the part",
+                        end - position
+                    ),
+                ));
+            }
+            let next = position + data.len() as u64;
+            Ok(Some((data, next)))
+        }
+    }))
+}
+
+/// Reads up to `len` bytes of `file` at `offset`, without using or moving
+/// its position.
+async fn read_at(file: Arc<std::fs::File>, offset: u64, len: usize) -> std::io::Result<Bytes> {
+    tokio::task::spawn_blocking(move || {
+        let mut buffer = vec![0; len];
+        let n = read_at_blocking(&file, &mut buffer, offset)?;
+        buffer.truncate(n);
+        Ok(Bytes::from(buffer))
+    })
+    .await
+    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?
+}
+
+#[cfg(unix)]
+fn read_at_blocking(
+    file: &std::fs::File,
+    buffer: &mut [u8],
+    offset: u64,
+) -> std::io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_at_blocking(
+    file: &std::fs::File,
+    buffer: &mut [u8],
+    offset: u64,
+) -> std::io::Result<usize> {
+    use std::os::windows::fs::FileExt;
+    file.seek_read(buffer, offset)
+}
+
+/// Best-effort abort of a multipart upload; failures are only reported, since
+/// the caller is already handling another error.
+pub async fn abort_upload(client: &Client, bucket: &str, key: &str, uid: &str) {
+    if let Err(err) = client
+        .abort_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(uid)
+        .send()
+        .await
+    {
+        eprintln!("Error aborting upload {}: {}", uid, err);
+    }
+}
@@ -0,0 +1,298 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! The settings of `upload-file-multipart-tasks` as a TOML file, for runs
+//! too long to type as flags.
+//!
+//! Every argument of the command line has a key of the same name, and the
+//! file adds what the command line does not offer: retries, a limit on the
+//! parts in flight, encryption, tags, and an SNS notification. Arguments
+//! given on the command line win over the file. `DEFAULT_CONFIG_TEMPLATE`
+//! lists every key, commented out, and is what `write-default-config`
+//! writes.
+
+use crate::config::RetrySettings;
+use crate::retry::RetryPolicy;
+use crate::runtime::RuntimeFlavor;
+use crate::upload::UploadHeaders;
+use aws_sdk_s3::Error;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use validator::{Validate, ValidationError};
+
+/// Characters escaped in the tags of `x-amz-tagging`, a URL query.
+const TAG_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// The configuration file `write-default-config` writes: every key, with
+/// what it does, commented out.
+pub const DEFAULT_CONFIG_TEMPLATE: &str = r#"# Settings of upload-file-multipart-tasks. Uncomment the ones to use;
+# arguments given on the command line win over this file.
+
+# The profile in the .aws/credentials file.
+#profile = "default"
+
+# The endpoint URL.
+#url = "https://s3.us-east-1.amazonaws.com"
+
+# The name of the bucket, and the key of the uploaded object.
+#bucket = "doc-example-bucket"
+#key = "backups/data.bin"
+
+# The file to upload.
+#file_name = "data.bin"
+
+# The number of parts, from 1 to 10000.
+#num_parts = 8
+
+# The worker threads of the multi-threaded runtime.
+#num_threads = 4
+
+# "multi" for a pool of worker threads, or "current" to run on one thread.
+#runtime = "multi"
+
+# The prefix of the names of the worker threads, numbered from 0.
+#thread_name = "s3-upload"
+
+# The read buffer size of each part, in bytes, or auto_buffer = true to size
+# it from the part size and the available memory.
+#buffer_capacity = 65536
+#auto_buffer = false
+
+# The parts uploading at the same time. All of them without a limit.
+#concurrency = 4
+
+# The connections opened before the timed upload, by default one per part;
+# warm_key warms up with one-byte ranged GETs of that object instead of
+# HeadBucket; no_warm_up = true starts on cold connections.
+#warm_connections = 8
+#warm_key = "backups/warm-up"
+#no_warm_up = false
+
+# Upload even to a key ending in /, which the console shows as a folder.
+#allow_dir_marker = false
+
+# Print the size, time, rate, and request ID of each part.
+#verbose = false
+
+# Server-side encryption: "AES256", or "aws:kms" with an optional key.
+#sse = "aws:kms"
+#sse_kms_key_id = "arn:aws:kms:us-east-1:111122223333:key/1234abcd-12ab-34cd-56ef-1234567890ab"
+
+# Publish the bucket, key, ETag, and size to this SNS topic once uploaded.
+#notify_sns_topic_arn = "arn:aws:sns:us-east-1:111122223333:uploads"
+
+# Retries of each part.
+#[retry]
+#max_attempts = 4
+#base_delay_ms = 100
+#max_delay_ms = 20000
+#jitter_ms = 100
+
+# The tags of the object, at most 10.
+#[tags]
+#project = "migration"
+#team = "storage"
+"#;
+
+/// The settings of an upload, from the command line or a configuration
+/// file. Unset values come from the other source, then the defaults.
+#[derive(Debug, Clone, Default, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+#[validate(schema(function = "validate_encryption"))]
+pub struct UploadConfig {
+    #[validate(length(min = 1))]
+    pub profile: Option<String>,
+    #[validate(url)]
+    pub url: Option<String>,
+    #[validate(length(min = 1))]
+    pub bucket: Option<String>,
+    #[validate(length(min = 1))]
+    pub key: Option<String>,
+    #[validate(length(min = 1))]
+    pub file_name: Option<String>,
+    #[validate(range(min = 1, max = 10000))]
+    pub num_parts: Option<usize>,
+    #[validate(range(min = 1))]
+    pub num_threads: Option<usize>,
+    pub runtime: Option<RuntimeFlavor>,
+    pub thread_name: Option<String>,
+    #[validate(range(min = 1))]
+    pub buffer_capacity: Option<usize>,
+    pub auto_buffer: Option<bool>,
+    #[validate(range(min = 1))]
+    pub concurrency: Option<usize>,
+    pub warm_connections: Option<usize>,
+    pub warm_key: Option<String>,
+    pub no_warm_up: Option<bool>,
+    pub allow_dir_marker: Option<bool>,
+    pub verbose: Option<bool>,
+    pub sse: Option<String>,
+    pub sse_kms_key_id: Option<String>,
+    pub notify_sns_topic_arn: Option<String>,
+    #[serde(default)]
+    #[validate(custom = "validate_retry")]
+    pub retry: RetrySettings,
+    #[serde(default)]
+    #[validate(length(max = 10))]
+    pub tags: BTreeMap<String, String>,
+}
+
+fn invalid(code: &'static str, message: &'static str) -> ValidationError {
+    let mut err = ValidationError::new(code);
+    err.message = Some(Cow::from(message));
+    err
+}
+
+fn validate_retry(retry: &RetrySettings) -> Result<(), ValidationError> {
+    if retry.max_attempts == Some(0) {
+        return Err(invalid("max_attempts", "max_attempts must be at least 1"));
+    }
+    Ok(())
+}
+
+fn validate_encryption(config: &UploadConfig) -> Result<(), ValidationError> {
+    match config.sse.as_deref() {
+        None | Some("AES256") | Some("aws:kms") => {}
+        Some(_) => return Err(invalid("sse", "sse must be AES256 or aws:kms")),
+    }
+    if config.sse_kms_key_id.is_some() && config.sse.as_deref() != Some("aws:kms") {
+        return Err(invalid(
+            "sse_kms_key_id",
+            "sse_kms_key_id needs sse = \"aws:kms\"",
+        ));
+    }
+    if config.auto_buffer == Some(true) && config.buffer_capacity.is_some() {
+        return Err(invalid(
+            "buffer_capacity",
+            "buffer_capacity and auto_buffer cannot both be set",
+        ));
+    }
+    Ok(())
+}
+
+impl UploadConfig {
+    /// Parses and validates the TOML content of a configuration file.
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(content).map_err(|err| err.to_string())?;
+        config.validate().map_err(|err| err.to_string())?;
+        Ok(config)
+    }
+
+    /// Reads the configuration file at `path`.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content =
+            std::fs::read_to_string(path).map_err(|err| Error::Unhandled(Box::new(err)))?;
+        Self::from_toml(&content)
+            .map_err(|err| Error::Unhandled(Box::from(format!("{}: {}", path.display(), err))))
+    }
+
+    /// Each setting of `self`, or of `fallback` where `self` has none.
+    pub fn or(self, fallback: UploadConfig) -> UploadConfig {
+        let mut tags = fallback.tags;
+        tags.extend(self.tags);
+        UploadConfig {
+            profile: self.profile.or(fallback.profile),
+            url: self.url.or(fallback.url),
+            bucket: self.bucket.or(fallback.bucket),
+            key: self.key.or(fallback.key),
+            file_name: self.file_name.or(fallback.file_name),
+            num_parts: self.num_parts.or(fallback.num_parts),
+            num_threads: self.num_threads.or(fallback.num_threads),
+            runtime: self.runtime.or(fallback.runtime),
+            thread_name: self.thread_name.or(fallback.thread_name),
+            buffer_capacity: self.buffer_capacity.or(fallback.buffer_capacity),
+            auto_buffer: self.auto_buffer.or(fallback.auto_buffer),
+            concurrency: self.concurrency.or(fallback.concurrency),
+            warm_connections: self.warm_connections.or(fallback.warm_connections),
+            warm_key: self.warm_key.or(fallback.warm_key),
+            no_warm_up: self.no_warm_up.or(fallback.no_warm_up),
+            allow_dir_marker: self.allow_dir_marker.or(fallback.allow_dir_marker),
+            verbose: self.verbose.or(fallback.verbose),
+            sse: self.sse.or(fallback.sse),
+            sse_kms_key_id: self.sse_kms_key_id.or(fallback.sse_kms_key_id),
+            notify_sns_topic_arn: self.notify_sns_topic_arn.or(fallback.notify_sns_topic_arn),
+            retry: RetrySettings {
+                max_attempts: self.retry.max_attempts.or(fallback.retry.max_attempts),
+                base_delay_ms: self.retry.base_delay_ms.or(fallback.retry.base_delay_ms),
+                max_delay_ms: self.retry.max_delay_ms.or(fallback.retry.max_delay_ms),
+                jitter_ms: self.retry.jitter_ms.or(fallback.retry.jitter_ms),
+            },
+            tags,
+        }
+    }
+
+    /// The settings an upload cannot do without that are not set.
+    pub fn missing(&self) -> Vec<&'static str> {
+        let required = [
+            ("profile", self.profile.is_some()),
+            ("url", self.url.is_some()),
+            ("bucket", self.bucket.is_some()),
+            ("key", self.key.is_some()),
+            ("file_name", self.file_name.is_some()),
+            ("num_parts", self.num_parts.is_some()),
+            ("num_threads", self.num_threads.is_some()),
+        ];
+        required
+            .iter()
+            .filter(|(_, set)| !set)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// The default `RetryPolicy` with the `[retry]` settings.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry.apply(RetryPolicy::default())
+    }
+
+    /// The encryption and tags of the object, or `None` without either.
+    pub fn headers(&self) -> Option<UploadHeaders> {
+        if self.sse.is_none() && self.tags.is_empty() {
+            return None;
+        }
+        let tagging = self
+            .tags
+            .iter()
+            .map(|(key, value)| {
+                format!(
+                    "{}={}",
+                    utf8_percent_encode(key, TAG_ENCODE_SET),
+                    utf8_percent_encode(value, TAG_ENCODE_SET)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        Some(UploadHeaders {
+            server_side_encryption: self.sse.clone(),
+            ssekms_key_id: self.sse_kms_key_id.clone(),
+            tagging: Some(tagging).filter(|tagging| !tagging.is_empty()),
+            ..Default::default()
+        })
+    }
+}
+
+/// Writes `DEFAULT_CONFIG_TEMPLATE` to `path`, refusing to overwrite a file.
+pub fn write_default_config(path: &Path) -> Result<(), Error> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|err| {
+            Error::Unhandled(Box::from(format!(
+                "Cannot create {}: {}",
+                path.display(),
+                err
+            )))
+        })?;
+    file.write_all(DEFAULT_CONFIG_TEMPLATE.as_bytes())
+        .map_err(|err| Error::Unhandled(Box::new(err)))
+}
@@ -0,0 +1,221 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Copies an object to another bucket, server-side when possible.
+//!
+//! `CopyObject` is sent first, with the destination client: the bytes never
+//! leave S3. An object over the 5 GiB a copy allows is still copied
+//! server-side, in parts with `UploadPartCopy` as by `copy_prefix`. When the
+//! copy is refused because the destination cannot reach the source, such as
+//! a bucket of another account or another S3-compatible endpoint, the
+//! object is streamed instead: read with `GetObject` through the source
+//! client and written with a `MultipartWriter` through the destination
+//! client, one part in memory at a time. Other errors, such as a missing
+//! source key, are returned as they are.
+//!
+//! A streamed object keeps the Content-Type, the other standard headers, and
+//! the user-defined metadata of the source, but not its tags.
+
+use crate::copy_prefix::{copy_object_multipart, copy_source, CopyPrefixOptions};
+use crate::multipart_writer::MultipartWriter;
+use crate::upload::UploadHeaders;
+use aws_sdk_s3::error::CopyObjectError;
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use chrono::{TimeZone, Utc};
+
+/// `CopyObject` error codes after which the object is streamed instead.
+pub const STREAM_FALLBACK_CODES: &[&str] = &[
+    // The destination credentials cannot read the source.
+    "AccessDenied",
+    // The source bucket is not on the destination endpoint.
+    "NoSuchBucket",
+    "PermanentRedirect",
+    "AuthorizationHeaderMalformed",
+    // The endpoint does not copy at all.
+    "NotImplemented",
+];
+
+/// How `upload_from_s3` copied the object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CopyPath {
+    ServerSide,
+    /// Copied server-side in parts, the source being too large for
+    /// `CopyObject`.
+    ServerSideMultipart,
+    /// Streamed after `CopyObject` was refused with this code.
+    Streamed {
+        code: String,
+    },
+}
+
+/// Result of `upload_from_s3_with_path`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3Upload {
+    /// Without quotes.
+    pub e_tag: String,
+    pub path: CopyPath,
+}
+
+/// Whether `CopyObject` was refused because the source is over the 5 GiB of
+/// a copy. S3 reports it as an InvalidRequest, among others, so the message
+/// is checked too.
+fn too_large_to_copy(err: &SdkError<CopyObjectError>) -> bool {
+    match err {
+        SdkError::ServiceError { err, .. } => match err.code() {
+            Some("EntityTooLarge") => true,
+            Some("InvalidRequest") => err
+                .message()
+                .map(|message| message.contains("maximum allowable size"))
+                .unwrap_or(false),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether a `CopyObject` failure should fall back to streaming, and with
+/// which code.
+fn fallback_code(err: &SdkError<CopyObjectError>) -> Option<String> {
+    match err {
+        SdkError::ServiceError { err, .. } => err
+            .code()
+            .filter(|code| STREAM_FALLBACK_CODES.contains(code))
+            .map(|code| code.to_string()),
+        _ => None,
+    }
+}
+
+/// Copies `src_key` of `src_bucket` to `dst_key` of `dst_bucket`, server-side
+/// when the destination accepts it, otherwise by streaming it between the
+/// two clients. Returns the ETag of the new object, without quotes.
+pub async fn upload_from_s3(
+    src_client: &Client,
+    src_bucket: &str,
+    src_key: &str,
+    dst_client: &Client,
+    dst_bucket: &str,
+    dst_key: &str,
+) -> Result<String, Error> {
+    let upload = upload_from_s3_with_path(
+        src_client, src_bucket, src_key, dst_client, dst_bucket, dst_key,
+    )
+    .await?;
+    Ok(upload.e_tag)
+}
+
+/// Same as `upload_from_s3`, also reporting whether the object was copied
+/// or streamed.
+pub async fn upload_from_s3_with_path(
+    src_client: &Client,
+    src_bucket: &str,
+    src_key: &str,
+    dst_client: &Client,
+    dst_bucket: &str,
+    dst_key: &str,
+) -> Result<S3Upload, Error> {
+    let copied = dst_client
+        .copy_object()
+        .copy_source(copy_source(src_bucket, src_key))
+        .bucket(dst_bucket)
+        .key(dst_key)
+        .send()
+        .await;
+    let code = match copied {
+        Ok(resp) => {
+            let e_tag = resp
+                .copy_object_result()
+                .and_then(|r| r.e_tag())
+                .unwrap_or_default()
+                .trim_matches('"')
+                .to_string();
+            return Ok(S3Upload {
+                e_tag,
+                path: CopyPath::ServerSide,
+            });
+        }
+        Err(err) if too_large_to_copy(&err) => {
+            let size = dst_client
+                .head_object()
+                .bucket(src_bucket)
+                .key(src_key)
+                .send()
+                .await?
+                .content_length()
+                .max(0) as u64;
+            tracing::info!(
+                src_bucket,
+                src_key,
+                size,
+                "The object is too large for CopyObject, copying it in parts"
+            );
+            let e_tag = copy_object_multipart(
+                dst_client,
+                src_bucket,
+                src_key,
+                dst_bucket,
+                dst_key,
+                size,
+                None,
+                &CopyPrefixOptions::default(),
+            )
+            .await?;
+            return Ok(S3Upload {
+                e_tag,
+                path: CopyPath::ServerSideMultipart,
+            });
+        }
+        Err(err) => match fallback_code(&err) {
+            Some(code) => code,
+            None => return Err(err.into()),
+        },
+    };
+    tracing::info!(
+        %code,
+        src_bucket,
+        src_key,
+        "CopyObject was refused, streaming the object instead"
+    );
+
+    let resp = src_client
+        .get_object()
+        .bucket(src_bucket)
+        .key(src_key)
+        .send()
+        .await?;
+    let headers = UploadHeaders {
+        content_type: resp.content_type().map(|value| value.to_string()),
+        content_disposition: resp.content_disposition().map(|value| value.to_string()),
+        content_encoding: resp.content_encoding().map(|value| value.to_string()),
+        content_language: resp.content_language().map(|value| value.to_string()),
+        cache_control: resp.cache_control().map(|value| value.to_string()),
+        expires: resp.expires().map(|date| Utc.timestamp(date.secs(), 0)),
+        ..UploadHeaders::default()
+    };
+    let mut writer = MultipartWriter::new(dst_client, dst_bucket, dst_key).with_headers(headers);
+    if let Some(metadata) = resp.metadata() {
+        writer = writer.with_metadata(metadata.clone());
+    }
+    let mut body = resp.body;
+    loop {
+        let chunk = match body.try_next().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(err) => {
+                writer.abort().await?;
+                return Err(Error::Unhandled(Box::new(err)));
+            }
+        };
+        if let Err(err) = writer.write(&chunk).await {
+            writer.abort().await?;
+            return Err(err);
+        }
+    }
+    let finished = writer.finish().await?;
+    Ok(S3Upload {
+        e_tag: finished.e_tag,
+        path: CopyPath::Streamed { code },
+    })
+}
@@ -0,0 +1,385 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! How far an interrupted multipart upload got, to decide whether to resume
+//! or abort it.
+//!
+//! The parts listed by ListParts are matched against the layout of the local
+//! file: the part size is inferred from the first part, and every part but
+//! the last must have exactly that size. The uploads of this crate give the
+//! remainder to the last part, as `upload::part_ranges`; other tools, such
+//! as the AWS CLI, send it as an extra, smaller part. The listed parts tell
+//! which, and without a hint the layout of this crate is assumed. A part
+//! whose size does not fit the layout cannot be reused by a resume and is
+//! reported as mismatched.
+//!
+//! The ETag of a part is the MD5 of its bytes, unless the bucket encrypts
+//! with SSE-KMS or SSE-C. Recomputing the MD5 of a sample of the local parts
+//! tells whether the file changed since the upload started.
+
+use crate::units::{format_duration, format_size};
+use crate::upload_watch::{list_upload_parts, ListedPart};
+use aws_sdk_s3::{Client, Error};
+use md5::{Digest, Md5};
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// A listed part that fits the layout of the local file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MatchedPart {
+    pub part_number: i32,
+    /// Where the part starts in the local file.
+    pub offset: u64,
+    pub size: u64,
+    pub e_tag: String,
+}
+
+/// The listed parts of an upload matched against the local file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PartMatch {
+    /// Inferred from the first part; `None` when no part was listed.
+    pub part_size: Option<u64>,
+    /// The number of parts of the file at that size.
+    pub total_parts: Option<u64>,
+    pub matched: Vec<MatchedPart>,
+    /// Part numbers listed with a size that does not fit the layout, or
+    /// beyond the last part.
+    pub mismatched: Vec<i32>,
+    /// Part numbers of the layout still to upload: not listed, or listed
+    /// with the wrong size.
+    pub missing: Vec<i32>,
+}
+
+impl PartMatch {
+    pub fn bytes_matched(&self) -> u64 {
+        self.matched.iter().map(|part| part.size).sum()
+    }
+}
+
+/// The offset and size of part `part_number` of a file of `file_size`
+/// bytes in `total_parts` parts of `part_size` bytes, the last one taking
+/// what is left, or `None` past the last part.
+pub fn expected_part(
+    part_number: i32,
+    part_size: u64,
+    total_parts: u64,
+    file_size: u64,
+) -> Option<(u64, u64)> {
+    if part_number < 1 || part_number as u64 > total_parts {
+        return None;
+    }
+    let offset = (part_number as u64 - 1).checked_mul(part_size)?;
+    if offset > file_size {
+        return None;
+    }
+    if part_number as u64 == total_parts {
+        Some((offset, file_size - offset))
+    } else {
+        Some((offset, part_size))
+    }
+}
+
+/// The number of parts of `part_size` bytes a file of `file_size` bytes was
+/// split into: one more when the remainder was sent as its own part, which
+/// shows as a listed extra part, or a full-size part where the last one
+/// would otherwise be.
+fn total_parts(parts: &[&ListedPart], part_size: u64, file_size: u64) -> u64 {
+    let whole = (file_size / part_size).max(1);
+    if file_size % part_size == 0 || file_size < part_size {
+        return whole;
+    }
+    let listed = |n: u64| parts.iter().find(|part| part.part_number as u64 == n);
+    let extra_part =
+        listed(whole + 1).is_some() || listed(whole).map(|p| p.size) == Some(part_size);
+    if extra_part {
+        whole + 1
+    } else {
+        whole
+    }
+}
+
+/// Matches the listed `parts` against the layout of a file of `file_size`
+/// bytes, inferring the part size from the lowest-numbered part.
+///
+/// This is what a resume has to work out before it can reuse the parts
+/// already uploaded: only matched parts can be kept, and mismatched ones
+/// mean the upload was made with another layout or from another file.
+pub fn match_parts(parts: &[ListedPart], file_size: u64) -> PartMatch {
+    let mut parts: Vec<&ListedPart> = parts.iter().collect();
+    parts.sort_by_key(|part| part.part_number);
+    let part_size = match parts.first() {
+        Some(first) if first.size > 0 => first.size,
+        _ => {
+            return PartMatch {
+                mismatched: parts.iter().map(|part| part.part_number).collect(),
+                ..Default::default()
+            }
+        }
+    };
+    let total_parts = total_parts(&parts, part_size, file_size);
+
+    let mut result = PartMatch {
+        part_size: Some(part_size),
+        total_parts: Some(total_parts),
+        ..Default::default()
+    };
+    for part in &parts {
+        match expected_part(part.part_number, part_size, total_parts, file_size) {
+            Some((offset, size)) if size == part.size => result.matched.push(MatchedPart {
+                part_number: part.part_number,
+                offset,
+                size,
+                e_tag: part.e_tag.clone(),
+            }),
+            _ => result.mismatched.push(part.part_number),
+        }
+    }
+    result.missing = (1..=total_parts as i32)
+        .filter(|n| {
+            result
+                .matched
+                .binary_search_by_key(n, |part| part.part_number)
+                .is_err()
+        })
+        .collect();
+    result
+}
+
+/// Picks `sample` of `count` items, spread evenly and including the first
+/// and the last. Returns their indices in order.
+pub fn sample_indices(count: usize, sample: usize) -> Vec<usize> {
+    if sample >= count {
+        return (0..count).collect();
+    }
+    match sample {
+        0 => Vec::new(),
+        1 => vec![0],
+        _ => {
+            let mut indices: Vec<usize> = (0..sample)
+                .map(|i| i * (count - 1) / (sample - 1))
+                .collect();
+            indices.dedup();
+            indices
+        }
+    }
+}
+
+/// A completed part checked against the local file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PartCheck {
+    pub part_number: i32,
+    pub e_tag: String,
+    /// Lowercase hex MD5 of the local bytes of the part.
+    pub local_md5: String,
+    /// `None` when the ETag is not an MD5, as with SSE-KMS.
+    pub matches: Option<bool>,
+}
+
+pub(crate) fn is_md5(e_tag: &str) -> bool {
+    e_tag.len() == 32 && e_tag.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Lowercase hex MD5 of `size` bytes of the file at `path` from `offset`.
+pub async fn md5_range(path: &Path, offset: u64, size: u64) -> std::io::Result<String> {
+    let digest = md5_digest_range(path, offset, size).await?;
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// The MD5 of `size` bytes of the file at `path` from `offset`.
+pub(crate) async fn md5_digest_range(
+    path: &Path,
+    offset: u64,
+    size: u64,
+) -> std::io::Result<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let mut file = file.take(size);
+    let mut hasher = Md5::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Recomputes the MD5 of `sample` of the `matched` parts from the file at
+/// `path` and compares it with their ETags.
+pub async fn verify_parts(
+    path: &Path,
+    matched: &[MatchedPart],
+    sample: usize,
+) -> std::io::Result<Vec<PartCheck>> {
+    let mut checks = Vec::new();
+    for i in sample_indices(matched.len(), sample) {
+        let part = &matched[i];
+        let local_md5 = md5_range(path, part.offset, part.size).await?;
+        let matches = if is_md5(&part.e_tag) {
+            Some(part.e_tag.eq_ignore_ascii_case(&local_md5))
+        } else {
+            None
+        };
+        checks.push(PartCheck {
+            part_number: part.part_number,
+            e_tag: part.e_tag.clone(),
+            local_md5,
+            matches,
+        });
+    }
+    Ok(checks)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StatusOptions {
+    /// The expected transfer rate, to estimate the time left.
+    pub bytes_per_second: Option<u64>,
+    /// Check this many completed parts against the local file.
+    pub verify_sample: Option<usize>,
+}
+
+/// How far an upload got.
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadStatus {
+    pub upload_id: String,
+    pub file_size: u64,
+    #[serde(flatten)]
+    pub parts: PartMatch,
+    pub bytes_uploaded: u64,
+    pub percent: f64,
+    pub remaining_bytes: u64,
+    /// At `StatusOptions::bytes_per_second`.
+    pub eta_ms: Option<u64>,
+    /// With `StatusOptions::verify_sample`.
+    pub verified: Option<Vec<PartCheck>>,
+}
+
+impl UploadStatus {
+    pub fn new(upload_id: &str, file_size: u64, parts: PartMatch, options: &StatusOptions) -> Self {
+        let bytes_uploaded = parts.bytes_matched();
+        let remaining_bytes = file_size.saturating_sub(bytes_uploaded);
+        let percent = if file_size > 0 {
+            bytes_uploaded as f64 * 100.0 / file_size as f64
+        } else {
+            100.0
+        };
+        let eta_ms = options
+            .bytes_per_second
+            .filter(|rate| *rate > 0)
+            .map(|rate| (remaining_bytes as u128 * 1000 / rate as u128) as u64);
+        Self {
+            upload_id: upload_id.to_string(),
+            file_size,
+            parts,
+            bytes_uploaded,
+            percent,
+            remaining_bytes,
+            eta_ms,
+            verified: None,
+        }
+    }
+
+    /// Whether a checked part no longer matches the local file.
+    pub fn source_changed(&self) -> bool {
+        self.verified
+            .iter()
+            .flatten()
+            .any(|check| check.matches == Some(false))
+    }
+
+    /// The status as lines of text.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![format!(
+            "Upload {}: {} of {} ({:.1}%), {} left",
+            self.upload_id,
+            format_size(self.bytes_uploaded),
+            format_size(self.file_size),
+            self.percent,
+            format_size(self.remaining_bytes)
+        )];
+        if let (Some(part_size), Some(total)) = (self.parts.part_size, self.parts.total_parts) {
+            lines.push(format!(
+                "{} of {} parts of {}",
+                self.parts.matched.len(),
+                total,
+                format_size(part_size)
+            ));
+        }
+        if !self.parts.missing.is_empty() {
+            lines.push(format!("Missing parts: {}", numbers(&self.parts.missing)));
+        }
+        if !self.parts.mismatched.is_empty() {
+            lines.push(format!(
+                "Parts that do not fit the file: {}",
+                numbers(&self.parts.mismatched)
+            ));
+        }
+        if let Some(eta) = self.eta_ms {
+            lines.push(format!(
+                "About {} left",
+                format_duration(Duration::from_millis(eta))
+            ));
+        }
+        for check in self.verified.iter().flatten() {
+            let result = match check.matches {
+                Some(true) => "matches",
+                Some(false) => "DIFFERS",
+                None => "ETag is not an MD5",
+            };
+            lines.push(format!("Part {}: {}", check.part_number, result));
+        }
+        lines.join("\n")
+    }
+}
+
+fn numbers(parts: &[i32]) -> String {
+    parts
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Lists the parts of `upload_id` and matches them against the file at
+/// `path`. Fails when the upload no longer exists.
+pub async fn upload_status(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    path: &Path,
+    options: &StatusOptions,
+) -> Result<UploadStatus, Error> {
+    let file_size = tokio::fs::metadata(path)
+        .await
+        .map_err(|err| Error::Unhandled(Box::new(err)))?
+        .len();
+    let parts = list_upload_parts(client, bucket, key, upload_id)
+        .await?
+        .ok_or_else(|| {
+            Error::Unhandled(Box::from(format!(
+                "The upload {} of {} no longer exists",
+                upload_id, key
+            )))
+        })?;
+    let mut status = UploadStatus::new(
+        upload_id,
+        file_size,
+        match_parts(&parts, file_size),
+        options,
+    );
+    if let Some(sample) = options.verify_sample {
+        status.verified = Some(
+            verify_parts(path, &status.parts.matched, sample)
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?,
+        );
+    }
+    Ok(status)
+}
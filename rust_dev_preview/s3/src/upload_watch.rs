@@ -0,0 +1,349 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Watching the progress of a multipart upload made by another host.
+//!
+//! The parts uploaded so far are listed with ListParts on each poll, and
+//! the rate is estimated from the bytes added since the previous poll. Once
+//! ListParts reports NoSuchUpload the upload is over, either completed or
+//! aborted; HeadObject tells which.
+
+use crate::units::{format_duration, format_rate, format_size};
+use aws_sdk_s3::types::SdkError;
+use aws_sdk_s3::{Client, Error};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// The parts of an upload listed so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PartsProgress {
+    pub parts: u64,
+    pub bytes: u64,
+}
+
+/// A multipart upload in progress, as listed by ListMultipartUploads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InProgressUpload {
+    pub upload_id: String,
+    /// When the upload was created, in seconds since the Unix epoch.
+    pub initiated: Option<i64>,
+}
+
+/// Lists the uploads in progress for exactly `key`, following pagination.
+pub async fn list_uploads(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+) -> Result<Vec<InProgressUpload>, Error> {
+    let mut uploads = Vec::new();
+    let mut key_marker: Option<String> = None;
+    let mut upload_id_marker: Option<String> = None;
+    loop {
+        let resp = client
+            .list_multipart_uploads()
+            .bucket(bucket)
+            .prefix(key)
+            .set_key_marker(key_marker.take())
+            .set_upload_id_marker(upload_id_marker.take())
+            .send()
+            .await?;
+        for upload in resp.uploads().unwrap_or_default() {
+            // The prefix also matches longer keys.
+            if upload.key() != Some(key) {
+                continue;
+            }
+            if let Some(upload_id) = upload.upload_id() {
+                uploads.push(InProgressUpload {
+                    upload_id: upload_id.to_string(),
+                    initiated: upload.initiated().map(|t| t.secs()),
+                });
+            }
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        key_marker = resp.next_key_marker().map(|m| m.to_string());
+        upload_id_marker = resp.next_upload_id_marker().map(|m| m.to_string());
+    }
+    Ok(uploads)
+}
+
+/// Finds the upload to watch: `upload_id` if given, otherwise the newest
+/// upload in progress for `key`.
+///
+/// A given `upload_id` that is no longer listed is still returned, without
+/// its creation time, since it may have completed before the first poll.
+pub async fn find_upload(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: Option<&str>,
+) -> Result<InProgressUpload, Error> {
+    let uploads = list_uploads(client, bucket, key).await?;
+    match upload_id {
+        Some(upload_id) => Ok(uploads
+            .into_iter()
+            .find(|u| u.upload_id == upload_id)
+            .unwrap_or_else(|| InProgressUpload {
+                upload_id: upload_id.to_string(),
+                initiated: None,
+            })),
+        None => uploads
+            .into_iter()
+            .max_by_key(|u| u.initiated)
+            .ok_or_else(|| {
+                Error::Unhandled(Box::from(format!(
+                    "No multipart upload in progress for {}",
+                    key
+                )))
+            }),
+    }
+}
+
+/// A part of a multipart upload, as listed by ListParts.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListedPart {
+    pub part_number: i32,
+    pub size: u64,
+    /// Without the quotes.
+    pub e_tag: String,
+}
+
+/// Lists the parts of an upload in order, following pagination. Returns
+/// `None` once the upload no longer exists, including when it ends between
+/// two pages.
+pub async fn list_upload_parts(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+) -> Result<Option<Vec<ListedPart>>, Error> {
+    let mut parts = Vec::new();
+    let mut marker: Option<String> = None;
+    loop {
+        let resp = match client
+            .list_parts()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .set_part_number_marker(marker.take())
+            .send()
+            .await
+        {
+            Ok(resp) => resp,
+            Err(SdkError::ServiceError { err, .. }) if err.code() == Some("NoSuchUpload") => {
+                return Ok(None)
+            }
+            Err(err) => return Err(err.into()),
+        };
+        for part in resp.parts().unwrap_or_default() {
+            parts.push(ListedPart {
+                part_number: part.part_number(),
+                size: part.size().max(0) as u64,
+                e_tag: part.e_tag().unwrap_or_default().replace("\"", ""),
+            });
+        }
+        if !resp.is_truncated() {
+            break;
+        }
+        marker = resp.next_part_number_marker().map(|m| m.to_string());
+    }
+    Ok(Some(parts))
+}
+
+/// Counts the parts of an upload and their bytes. Returns `None` once the
+/// upload no longer exists.
+pub async fn list_all_parts(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+) -> Result<Option<PartsProgress>, Error> {
+    let parts = list_upload_parts(client, bucket, key, upload_id).await?;
+    Ok(parts.map(|parts| PartsProgress {
+        parts: parts.len() as u64,
+        bytes: parts.iter().map(|part| part.size).sum(),
+    }))
+}
+
+/// What a poll of the upload found.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum WatchEvent {
+    InProgress {
+        #[serde(flatten)]
+        progress: PartsProgress,
+        /// The rate since the previous poll.
+        bytes_per_second: Option<f64>,
+        /// The time left at that rate, when the total size is known.
+        eta_ms: Option<u64>,
+    },
+    Completed {
+        e_tag: String,
+    },
+    Aborted,
+}
+
+impl WatchEvent {
+    /// The event as one line of text.
+    pub fn to_text(&self) -> String {
+        match self {
+            WatchEvent::InProgress {
+                progress,
+                bytes_per_second,
+                eta_ms,
+            } => {
+                let mut text = format!("{} parts, {}", progress.parts, format_size(progress.bytes));
+                if let Some(rate) = bytes_per_second {
+                    text.push_str(&format!(", {} (since last poll)", format_rate(*rate)));
+                }
+                if let Some(eta) = eta_ms {
+                    text.push_str(&format!(
+                        ", {} left",
+                        format_duration(Duration::from_millis(*eta))
+                    ));
+                }
+                text
+            }
+            WatchEvent::Completed { e_tag } => format!("Completed, ETag {}", e_tag),
+            WatchEvent::Aborted => "Aborted".to_string(),
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        !matches!(self, WatchEvent::InProgress { .. })
+    }
+}
+
+/// The rate between two polls, and the time left at that rate to reach
+/// `total_size`, in milliseconds. No rate is estimated without a previous poll, and no time
+/// left while the rate is zero.
+pub fn estimate(
+    previous: Option<(PartsProgress, Duration)>,
+    current: PartsProgress,
+    elapsed: Duration,
+    total_size: Option<u64>,
+) -> (Option<f64>, Option<u64>) {
+    let rate = previous.and_then(|(before, at)| {
+        let seconds = elapsed.checked_sub(at)?.as_secs_f64();
+        (seconds > 0.0).then(|| current.bytes.saturating_sub(before.bytes) as f64 / seconds)
+    });
+    let eta = match (rate, total_size) {
+        (Some(rate), Some(total)) if rate > 0.0 => {
+            Some((total.saturating_sub(current.bytes) as f64 * 1000.0 / rate) as u64)
+        }
+        _ => None,
+    };
+    (rate, eta)
+}
+
+/// Tells how an upload that no longer exists ended: completed if `key`
+/// exists and was written since the upload was created, aborted otherwise.
+///
+/// A completed multipart upload's object is dated from the creation of the
+/// upload, so an object dated earlier is one the upload would have
+/// replaced. Without a creation time, any object counts.
+async fn ended(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    initiated: Option<i64>,
+) -> Result<WatchEvent, Error> {
+    match client.head_object().bucket(bucket).key(key).send().await {
+        Ok(head) => {
+            let modified = head.last_modified().map(|t| t.secs());
+            let newer = match (initiated, modified) {
+                (Some(initiated), Some(modified)) => modified >= initiated,
+                _ => true,
+            };
+            if newer {
+                Ok(WatchEvent::Completed {
+                    e_tag: head.e_tag().unwrap_or_default().replace("\"", ""),
+                })
+            } else {
+                Ok(WatchEvent::Aborted)
+            }
+        }
+        Err(SdkError::ServiceError { err, .. }) if err.is_not_found() => Ok(WatchEvent::Aborted),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchOptions {
+    pub interval: Duration,
+    /// Fail when the upload is still in progress after this long.
+    pub timeout: Option<Duration>,
+    /// The size of the file being uploaded, to estimate the time left.
+    pub total_size: Option<u64>,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            timeout: None,
+            total_size: None,
+        }
+    }
+}
+
+/// Polls `upload` of `bucket/key` until it is completed or aborted, and
+/// returns how it ended.
+pub async fn watch_upload(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload: &InProgressUpload,
+    options: &WatchOptions,
+) -> Result<WatchEvent, Error> {
+    watch_upload_with_hook(client, bucket, key, upload, options, |_| {}).await
+}
+
+/// As `watch_upload`, calling `on_poll` with the result of each poll,
+/// including the last one.
+pub async fn watch_upload_with_hook(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload: &InProgressUpload,
+    options: &WatchOptions,
+    mut on_poll: impl FnMut(&WatchEvent),
+) -> Result<WatchEvent, Error> {
+    let start = Instant::now();
+    let mut previous = None;
+    loop {
+        let event = match list_all_parts(client, bucket, key, &upload.upload_id).await? {
+            Some(progress) => {
+                let elapsed = start.elapsed();
+                let (bytes_per_second, eta_ms) =
+                    estimate(previous, progress, elapsed, options.total_size);
+                previous = Some((progress, elapsed));
+                WatchEvent::InProgress {
+                    progress,
+                    bytes_per_second,
+                    eta_ms,
+                }
+            }
+            None => ended(client, bucket, key, upload.initiated).await?,
+        };
+        on_poll(&event);
+        if event.is_over() {
+            return Ok(event);
+        }
+        if let Some(timeout) = options.timeout {
+            let elapsed = start.elapsed();
+            if elapsed + options.interval > timeout {
+                return Err(Error::Unhandled(Box::from(format!(
+                    "The upload {} of {} is still in progress after {}",
+                    upload.upload_id,
+                    key,
+                    format_duration(elapsed)
+                ))));
+            }
+        }
+        tokio::time::sleep(options.interval).await;
+    }
+}
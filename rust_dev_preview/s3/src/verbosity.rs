@@ -0,0 +1,174 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Per-part progress lines for `--verbose`.
+//!
+//! With a `VerbosityConfig` enabled, the multipart uploads print a line as
+//! each part is uploaded, the parallel download as each chunk is written,
+//! and `download_prefix_with_verbosity` as each object is, to stderr:
+//!
+//! ```text
+//! [part 3/10] uploaded 8.0 MiB in 0.4s (20.0 MiB/s) request ID 4442587FB7D0A2F9
+//! ```
+//!
+//! The SDK does not return the request ID of a successful response, so the
+//! `RecordRequestId` connector reads the `x-amz-request-id` header of each
+//! response into the slot of the request in flight, when one is set with
+//! `with_request_id`. Without that connector, no request IDs are printed.
+
+use crate::connect::https_adapter;
+use crate::units::{average_rate, format_duration, format_rate, format_size};
+use aws_sdk_s3::Client;
+use futures::future::BoxFuture;
+use hyper::service::Service;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+tokio::task_local! {
+    /// Where `RecordRequestId` writes the request ID of the response.
+    static REQUEST_ID: Arc<Mutex<Option<String>>>;
+}
+
+/// What `--verbose` prints.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VerbosityConfig {
+    /// Print a line per part or chunk.
+    pub enabled: bool,
+    /// Add the request ID of the part, for AWS Support.
+    pub show_request_ids: bool,
+}
+
+impl VerbosityConfig {
+    /// Everything on when `verbose` is set.
+    pub fn from_flag(verbose: bool) -> Self {
+        Self {
+            enabled: verbose,
+            show_request_ids: verbose,
+        }
+    }
+
+    /// Prints the line of a transfer of `bytes` in `elapsed`, if enabled.
+    #[allow(clippy::too_many_arguments)]
+    pub fn report(
+        &self,
+        unit: &str,
+        verb: &str,
+        index: usize,
+        total: usize,
+        bytes: u64,
+        elapsed: Duration,
+        request_id: Option<&str>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let request_id = if self.show_request_ids {
+            request_id
+        } else {
+            None
+        };
+        eprintln!(
+            "{}",
+            transfer_line(unit, verb, index, total, bytes, elapsed, request_id)
+        );
+    }
+}
+
+/// `[part 3/10] uploaded 8.0 MiB in 0.4s (20.0 MiB/s)`, followed by the
+/// request ID when there is one.
+pub fn transfer_line(
+    unit: &str,
+    verb: &str,
+    index: usize,
+    total: usize,
+    bytes: u64,
+    elapsed: Duration,
+    request_id: Option<&str>,
+) -> String {
+    let mut line = format!(
+        "[{} {}/{}] {} {} in {} ({})",
+        unit,
+        index,
+        total,
+        verb,
+        format_size(bytes),
+        format_duration(elapsed),
+        format_rate(average_rate(bytes, elapsed).unwrap_or_default())
+    );
+    if let Some(request_id) = request_id {
+        line.push_str(" request ID ");
+        line.push_str(request_id);
+    }
+    line
+}
+
+/// Runs `future`, returning its output with the request ID of the last
+/// response `RecordRequestId` saw while it ran, which is the attempt that
+/// succeeded.
+pub async fn with_request_id<F: Future>(future: F) -> (F::Output, Option<String>) {
+    let slot = Arc::new(Mutex::new(None));
+    let output = REQUEST_ID.scope(slot.clone(), future).await;
+    let request_id = slot.lock().unwrap().take();
+    (output, request_id)
+}
+
+/// A connector that saves the `x-amz-request-id` of each response for
+/// `with_request_id`. Outside of it, requests go through untouched.
+#[derive(Debug, Clone)]
+pub struct RecordRequestId<C> {
+    inner: C,
+}
+
+impl<C> RecordRequestId<C> {
+    pub fn new(inner: C) -> Self {
+        Self { inner }
+    }
+}
+
+impl<C, B, RB> Service<http::Request<B>> for RecordRequestId<C>
+where
+    C: Service<http::Request<B>, Response = http::Response<RB>> + Clone + Send + 'static,
+    C::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<C::Response, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        // As in `RateLimited`, the connector made ready serves this request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        // The slot of the caller, taken now: the response may be awaited
+        // from another task.
+        let slot = match REQUEST_ID.try_with(|slot| slot.clone()) {
+            Ok(slot) => slot,
+            Err(_) => return Box::pin(inner.call(request)),
+        };
+        Box::pin(async move {
+            let response = inner.call(request).await?;
+            if let Some(request_id) = response
+                .headers()
+                .get("x-amz-request-id")
+                .and_then(|value| value.to_str().ok())
+            {
+                *slot.lock().unwrap() = Some(request_id.to_string());
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// Creates a client that records the request IDs for `with_request_id`.
+pub fn request_id_client(config: aws_sdk_s3::Config) -> Client {
+    let adapter = https_adapter();
+    Client::from_conf_conn(config, RecordRequestId::new(adapter))
+}
@@ -0,0 +1,135 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! PutObject with a checksum that S3 verifies before storing the object.
+//!
+//! The checksum of the body is sent in an `x-amz-checksum-*` header. When
+//! the bytes S3 received do not match it, as after corruption on the way,
+//! S3 rejects the request with BadDigest or InvalidDigest instead of
+//! storing them, and the object is sent again.
+
+use aws_sdk_s3::types::{ByteStream, SdkError};
+use aws_sdk_s3::{Client, Error};
+use bytes::Bytes;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// How many times an object rejected for its checksum is sent again.
+pub const MAX_DIGEST_RETRIES: u32 = 3;
+
+/// The checksums S3 can verify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Crc32,
+    Crc32c,
+    Sha1,
+    Sha256,
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().replace('-', "").as_str() {
+            "crc32" => Ok(HashAlgorithm::Crc32),
+            "crc32c" => Ok(HashAlgorithm::Crc32c),
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => Err(format!(
+                "Unknown checksum algorithm {}, expected crc32, crc32c, sha1, or sha256",
+                value
+            )),
+        }
+    }
+}
+
+impl HashAlgorithm {
+    /// The header carrying the checksum.
+    pub fn header(self) -> &'static str {
+        match self {
+            HashAlgorithm::Crc32 => "x-amz-checksum-crc32",
+            HashAlgorithm::Crc32c => "x-amz-checksum-crc32c",
+            HashAlgorithm::Sha1 => "x-amz-checksum-sha1",
+            HashAlgorithm::Sha256 => "x-amz-checksum-sha256",
+        }
+    }
+
+    /// The base64 checksum of `data`, as S3 expects it in the header.
+    pub fn checksum(self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Crc32 => base64::encode(crc32fast::hash(data).to_be_bytes()),
+            HashAlgorithm::Crc32c => base64::encode(crc32c::crc32c(data).to_be_bytes()),
+            HashAlgorithm::Sha1 => base64::encode(Sha1::digest(data)),
+            HashAlgorithm::Sha256 => base64::encode(Sha256::digest(data)),
+        }
+    }
+}
+
+/// Stores `data` as `bucket/key` with its `algorithm` checksum, and returns
+/// the ETag S3 confirmed, without quotes.
+///
+/// A request rejected with BadDigest or InvalidDigest is logged and sent
+/// again, up to `MAX_DIGEST_RETRIES` times. Other errors are returned at
+/// once, as is a response whose checksum differs from the one sent.
+pub async fn put_object_verified(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    data: Bytes,
+    algorithm: HashAlgorithm,
+) -> Result<String, Error> {
+    let checksum = algorithm.checksum(&data);
+    let mut retries = 0;
+    loop {
+        let request = client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .body(ByteStream::from(data.clone()));
+        let request = match algorithm {
+            HashAlgorithm::Crc32 => request.checksum_crc32(&checksum),
+            HashAlgorithm::Crc32c => request.checksum_crc32c(&checksum),
+            HashAlgorithm::Sha1 => request.checksum_sha1(&checksum),
+            HashAlgorithm::Sha256 => request.checksum_sha256(&checksum),
+        };
+        match request.send().await {
+            Ok(resp) => {
+                let confirmed = match algorithm {
+                    HashAlgorithm::Crc32 => resp.checksum_crc32(),
+                    HashAlgorithm::Crc32c => resp.checksum_crc32c(),
+                    HashAlgorithm::Sha1 => resp.checksum_sha1(),
+                    HashAlgorithm::Sha256 => resp.checksum_sha256(),
+                };
+                if let Some(confirmed) = confirmed {
+                    if confirmed != checksum {
+                        return Err(Error::Unhandled(Box::from(format!(
+                            "S3 stored {} with {} {}, not {}",
+                            key,
+                            algorithm.header(),
+                            confirmed,
+                            checksum
+                        ))));
+                    }
+                }
+                return Ok(resp.e_tag().unwrap_or_default().replace("\"", ""));
+            }
+            Err(SdkError::ServiceError { err, .. })
+                if matches!(err.code(), Some("BadDigest") | Some("InvalidDigest"))
+                    && retries < MAX_DIGEST_RETRIES =>
+            {
+                retries += 1;
+                tracing::warn!(
+                    key,
+                    header = algorithm.header(),
+                    %checksum,
+                    code = err.code().unwrap_or_default(),
+                    retry = retries,
+                    "S3 rejected the checksum of the object, sending it again"
+                );
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Connection pool warm-up.
+//!
+//! The first requests of a run pay for DNS resolution, TCP connection and TLS
+//! handshakes. Issuing a few cheap concurrent requests with the same client
+//! before a transfer leaves that many established connections in the client's
+//! pool, so the timed transfer starts on warm connections.
+
+use aws_sdk_s3::{Client, Error};
+use std::time::{Duration, Instant};
+
+/// Issues `count` concurrent cheap requests and returns the time spent.
+///
+/// Uses `HeadBucket`, or a one-byte ranged `GetObject` on `key` when given
+/// (for credentials that can read objects but not the bucket). The requests go
+/// through `client`, so they use the same endpoint and connection settings as
+/// the transfer that follows.
+pub async fn warm_connections(
+    client: &Client,
+    bucket: &str,
+    key: Option<&str>,
+    count: usize,
+) -> Result<Duration, Error> {
+    let start = Instant::now();
+    let mut handles = Vec::with_capacity(count);
+    for _ in 0..count {
+        let client = client.clone();
+        let bucket = bucket.to_string();
+        let key = key.map(|k| k.to_string());
+        handles.push(tokio::spawn(async move {
+            warm_one(&client, &bucket, key.as_deref()).await
+        }));
+    }
+    for h in handles {
+        h.await.map_err(|err| Error::Unhandled(Box::new(err)))??;
+    }
+    Ok(start.elapsed())
+}
+
+async fn warm_one(client: &Client, bucket: &str, key: Option<&str>) -> Result<(), Error> {
+    match key {
+        Some(key) => {
+            let resp = client
+                .get_object()
+                .bucket(bucket)
+                .key(key)
+                .range("bytes=0-0")
+                .send()
+                .await?;
+            // The body must be drained for the connection to go back to the pool.
+            resp.body
+                .collect()
+                .await
+                .map_err(|err| Error::Unhandled(Box::new(err)))?;
+        }
+        None => {
+            client.head_bucket().bucket(bucket).send().await?;
+        }
+    }
+    Ok(())
+}
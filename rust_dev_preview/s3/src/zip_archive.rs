@@ -0,0 +1,238 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! ZIP archives of directory trees, generated and extracted on the fly.
+//!
+//! The archive is never written to local disk: on upload, entries are
+//! compressed one at a time into a pipe whose other end feeds a
+//! `MultipartWriter`, so memory use is bounded by the part size whatever the
+//! size of the tree. On download, the object body is read as a stream and each
+//! entry is written out as it arrives. Entries larger than 4 GB use the ZIP64
+//! extensions in both directions.
+
+use crate::memory_budget::MemoryBudget;
+use crate::multipart_writer::MultipartWriter;
+use crate::sync::walk_directory;
+use crate::units::format_size;
+use async_zip::base::read::stream::ZipFileReader;
+use async_zip::base::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use aws_sdk_s3::{Client, Error};
+use futures::TryStreamExt;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+use tokio_util::io::StreamReader;
+
+/// Capacity of the pipe between the ZIP writer and the upload.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+fn zip_error(err: async_zip::error::ZipError) -> Error {
+    Error::Unhandled(Box::new(err))
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::Unhandled(Box::new(err))
+}
+
+/// Uploads the files under `local_dir` to `bucket/key` as a ZIP archive.
+///
+/// Entries are named after the file paths relative to `local_dir`, with `/`
+/// separators, and compressed with Deflate. The central directory is written
+/// after the last entry. The archive is uploaded in 8 MB parts; if anything
+/// fails the multipart upload is aborted.
+pub async fn upload_as_zip(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    local_dir: &Path,
+) -> Result<(), Error> {
+    upload_as_zip_with_budget(client, bucket, key, local_dir, None).await
+}
+
+/// Same as `upload_as_zip`, reserving the part buffer from `budget` before
+/// it is allocated.
+pub async fn upload_as_zip_with_budget(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    local_dir: &Path,
+    budget: Option<&MemoryBudget>,
+) -> Result<(), Error> {
+    let files = walk_directory(local_dir, "").map_err(io_error)?;
+    let (zip_end, upload_end) = tokio::io::duplex(PIPE_CAPACITY);
+    let mut writer = MultipartWriter::new(client, bucket, key);
+    if let Some(budget) = budget {
+        writer = writer.with_memory_budget(budget);
+    }
+
+    // Each side owns its end of the pipe, so when one fails and drops it the
+    // other sees a closed pipe instead of waiting forever.
+    let archive = async move {
+        let mut zip_end = zip_end;
+        let mut zip = ZipFileWriter::with_tokio(&mut zip_end);
+        let mut uncompressed = 0;
+        for file in &files {
+            let builder = ZipEntryBuilder::new(file.key.clone().into(), Compression::Deflate);
+            let mut entry = zip.write_entry_stream(builder).await.map_err(zip_error)?;
+            let input = tokio::fs::File::open(&file.path).await.map_err(io_error)?;
+            uncompressed += futures::io::copy(input.compat(), &mut entry)
+                .await
+                .map_err(io_error)?;
+            entry.close().await.map_err(zip_error)?;
+        }
+        zip.close().await.map_err(zip_error)?;
+        zip_end.shutdown().await.map_err(io_error)?;
+        Ok::<(usize, u64), Error>((files.len(), uncompressed))
+    };
+    let upload = async {
+        let mut upload_end = upload_end;
+        let mut buffer = vec![0; PIPE_CAPACITY];
+        loop {
+            let n = upload_end.read(&mut buffer).await.map_err(io_error)?;
+            if n == 0 {
+                return Ok::<(), Error>(());
+            }
+            writer.write(&buffer[..n]).await?;
+        }
+    };
+    let (archived, uploaded) = tokio::join!(archive, upload);
+
+    let (file_count, uncompressed) = match archived.and_then(|a| uploaded.map(|_| a)) {
+        Ok(result) => result,
+        Err(err) => {
+            writer.abort().await?;
+            return Err(err);
+        }
+    };
+    let archive_size = writer.bytes_written();
+    writer.finish().await?;
+
+    println!(
+        "Uploaded {} files to s3://{}/{} as a {} ZIP archive ({} uncompressed)",
+        file_count,
+        bucket,
+        key,
+        format_size(archive_size),
+        format_size(uncompressed)
+    );
+    if archive_size > 0 {
+        println!(
+            "Compression ratio: {:.2}",
+            uncompressed as f64 / archive_size as f64
+        );
+    }
+    Ok(())
+}
+
+/// An entry that could not be extracted.
+#[derive(Debug, Clone)]
+pub struct FailedEntry {
+    pub name: String,
+    pub error: String,
+}
+
+/// Outcome of `download_and_extract_zip`.
+#[derive(Debug, Default, Clone)]
+pub struct ExtractionReport {
+    /// Number of files written.
+    pub files: usize,
+    /// Total size of the files written.
+    pub bytes: u64,
+    pub failed: Vec<FailedEntry>,
+}
+
+/// The path under `dest_dir` of the archive entry `name`, or `None` if the
+/// name is absolute or has a `..` component and would escape `dest_dir`.
+///
+/// Both `/` and `\` are treated as separators, since archives created on
+/// Windows can use either.
+pub fn entry_path(dest_dir: &Path, name: &str) -> Option<PathBuf> {
+    let relative = PathBuf::from(name.replace('\\', "/"));
+    let mut path = dest_dir.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Streams the ZIP archive `bucket/key` and extracts its entries under
+/// `dest_dir`, creating directories as needed.
+///
+/// Entries whose name would escape `dest_dir`, or that cannot be written, are
+/// skipped and listed in the report; a corrupt archive stops the extraction
+/// with an error.
+pub async fn download_and_extract_zip(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    dest_dir: &Path,
+) -> Result<ExtractionReport, Error> {
+    let resp = client.get_object().bucket(bucket).key(key).send().await?;
+    let body = StreamReader::new(
+        resp.body
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)),
+    );
+
+    let mut report = ExtractionReport::default();
+    let mut zip = ZipFileReader::with_tokio(body);
+    while let Some(mut next) = zip.next_with_entry().await.map_err(zip_error)? {
+        let entry = next.reader().entry();
+        let name = String::from_utf8_lossy(entry.filename().as_bytes()).into_owned();
+        let is_dir = entry.dir().map_err(zip_error)?;
+
+        let result = match entry_path(dest_dir, &name) {
+            None => Err(format!(
+                "refusing to extract outside of {}",
+                dest_dir.display()
+            )),
+            Some(path) if is_dir => tokio::fs::create_dir_all(&path)
+                .await
+                .map(|_| None)
+                .map_err(|err| err.to_string()),
+            Some(path) => extract_file(next.reader_mut(), &path).await.map(Some),
+        };
+        match result {
+            Ok(Some(bytes)) => {
+                report.files += 1;
+                report.bytes += bytes;
+            }
+            Ok(None) => {}
+            Err(error) => {
+                eprintln!("Error extracting {}: {}", name, error);
+                report.failed.push(FailedEntry { name, error });
+            }
+        }
+        // Reads whatever is left of the entry, so the next one can be found.
+        zip = next.skip().await.map_err(zip_error)?;
+    }
+    Ok(report)
+}
+
+async fn extract_file(
+    entry: &mut (impl futures::AsyncRead + Unpin),
+    path: &Path,
+) -> Result<u64, String> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    let file = tokio::fs::File::create(path)
+        .await
+        .map_err(|err| err.to_string())?;
+    let mut file = file.compat_write();
+    let bytes = futures::io::copy(entry, &mut file)
+        .await
+        .map_err(|err| err.to_string())?;
+    futures::AsyncWriteExt::flush(&mut file)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(bytes)
+}
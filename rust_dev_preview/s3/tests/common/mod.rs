@@ -0,0 +1,314 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+// Shared setup for the tests: a mock S3 server on a local port, and a local
+// MinIO server for the ignored tests, e.g.:
+//   docker run -p 9000:9000 minio/minio server /data
+//   S3_ENDPOINT_URL=http://localhost:9000 cargo test -- --ignored
+
+#![allow(dead_code)]
+
+use aws_sdk_s3::{Client, Credentials, Endpoint, Region, RetryConfig};
+use http::Uri;
+use hyper::service::{make_service_fn, service_fn, Service};
+use hyper::{Body, Method, Request, Response};
+use s3_service::impl_s3_ops;
+use s3_service::ops::{OpError, S3Ops};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+/// Creates a client for the endpoint in `S3_ENDPOINT_URL`.
+pub async fn minio_client() -> Client {
+    let url = std::env::var("S3_ENDPOINT_URL").unwrap_or_else(|_| "http://localhost:9000".into());
+    let shared_config = aws_config::from_env()
+        .region(Region::new("us-east-1"))
+        .load()
+        .await;
+    let uri = url.parse::<http::uri::Uri>().expect("Invalid URL");
+    let s3_conf = aws_sdk_s3::config::Builder::from(&shared_config)
+        .endpoint_resolver(Endpoint::immutable(uri))
+        .build();
+    Client::from_conf(s3_conf)
+}
+
+/// Creates a uniquely named bucket.
+pub async fn create_test_bucket(client: &Client) -> String {
+    let bucket = format!("doc-example-bucket-{}", Uuid::new_v4());
+    client.create_bucket().bucket(&bucket).send().await.unwrap();
+    bucket
+}
+
+/// Empties and deletes a bucket created by `create_test_bucket`.
+pub async fn delete_test_bucket(client: &Client, bucket: &str) {
+    s3_service::delete_objects(client, bucket).await.unwrap();
+    s3_service::delete_bucket(client, bucket).await.unwrap();
+}
+
+/// Starts a local server answering each request with `handler` and returns
+/// its port, for `client_for`.
+pub fn mock_s3<F, Fut>(handler: F) -> u16
+where
+    F: Fn(Request<Body>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Response<Body>> + Send + 'static,
+{
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handler = Arc::new(handler);
+    let make_service = make_service_fn(move |_| {
+        let handler = handler.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let response = handler(req);
+                async move { Ok::<_, Infallible>(response.await) }
+            }))
+        }
+    });
+    let server = hyper::Server::from_tcp(listener)
+        .unwrap()
+        .serve(make_service);
+    tokio::spawn(server);
+    port
+}
+
+/// The URL of the local server on `port`.
+pub fn url_for(port: u16) -> String {
+    format!("http://127.0.0.1:{}", port)
+}
+
+/// The configuration of a client of the local server on `port`, in
+/// us-east-1 with test credentials and without retries.
+pub fn config_for(port: u16) -> aws_sdk_s3::config::Builder {
+    aws_sdk_s3::Config::builder()
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("access", "secret", None, None, "test"))
+        .endpoint_resolver(Endpoint::immutable(url_for(port).parse().unwrap()))
+        .retry_config(RetryConfig::disabled())
+}
+
+/// A client of the local server on `port`, as configured by `config_for`.
+pub fn client_for(port: u16) -> Client {
+    Client::from_conf(config_for(port).build())
+}
+
+/// What `mock_uploads` received and stores.
+#[derive(Debug, Default)]
+pub struct Uploads {
+    /// The operation of each request, as `PutObject` or `UploadPart`.
+    pub requests: Vec<String>,
+    /// The objects stored, by key.
+    pub objects: HashMap<String, Vec<u8>>,
+    /// The parts of the multipart upload in progress, by number.
+    pub parts: BTreeMap<i32, Vec<u8>>,
+    /// A part number answered with a 500 InternalError.
+    pub fail_part: Option<i32>,
+}
+
+/// Starts a local server storing the objects of PutObject and of the
+/// multipart upload requests in `Uploads`, and returns a client of it.
+pub fn mock_uploads() -> (Client, Arc<Mutex<Uploads>>) {
+    let uploads = Arc::new(Mutex::new(Uploads::default()));
+    let store = uploads.clone();
+    let port = mock_s3(move |req: Request<Body>| {
+        let store = store.clone();
+        async move {
+            let method = req.method().clone();
+            let key = req.uri().path().trim_start_matches("/bucket/").to_string();
+            let query = req.uri().query().unwrap_or_default().to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let part_number = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("partNumber="))
+                .map(|n| n.parse::<i32>().unwrap());
+            let mut store = store.lock().unwrap();
+            let (operation, status, response) = match (&method, part_number) {
+                (&Method::PUT, Some(n)) if store.fail_part == Some(n) => (
+                    "UploadPart",
+                    500,
+                    "<Error><Code>InternalError</Code></Error>".to_string(),
+                ),
+                (&Method::PUT, Some(n)) => {
+                    store.parts.insert(n, body.to_vec());
+                    ("UploadPart", 200, String::new())
+                }
+                (&Method::PUT, None) => {
+                    store.objects.insert(key, body.to_vec());
+                    ("PutObject", 200, String::new())
+                }
+                (&Method::POST, _) if query.starts_with("uploads") => (
+                    "CreateMultipartUpload",
+                    200,
+                    "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                     </InitiateMultipartUploadResult>"
+                        .to_string(),
+                ),
+                (&Method::POST, _) => {
+                    let object = std::mem::take(&mut store.parts)
+                        .into_values()
+                        .flatten()
+                        .collect();
+                    store.objects.insert(key, object);
+                    (
+                        "CompleteMultipartUpload",
+                        200,
+                        "<CompleteMultipartUploadResult><ETag>\"etag\"</ETag>\
+                         </CompleteMultipartUploadResult>"
+                            .to_string(),
+                    )
+                }
+                (&Method::DELETE, _) => {
+                    store.parts.clear();
+                    ("AbortMultipartUpload", 204, String::new())
+                }
+                _ => ("Other", 405, String::new()),
+            };
+            store.requests.push(operation.to_string());
+            Response::builder()
+                .status(status)
+                .header("ETag", "\"etag\"")
+                .body(Body::from(response))
+                .unwrap()
+        }
+    });
+    (client_for(port), uploads)
+}
+
+/// A Hyper connector connecting to the local server on a port whatever the
+/// host of the request, so requests keep host names that do not resolve.
+#[derive(Debug, Clone, Copy)]
+pub struct LoopbackConnector(pub u16);
+
+impl Service<Uri> for LoopbackConnector {
+    type Response = TcpStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _uri: Uri) -> Self::Future {
+        Box::pin(TcpStream::connect(("127.0.0.1", self.0)))
+    }
+}
+
+/// In-memory `S3Ops` for tests. Operations named in `denied` fail with a
+/// 403 AccessDenied; every call is recorded in `calls`. Objects read are
+/// filled with zeros.
+#[derive(Debug, Default)]
+pub struct MockS3 {
+    pub denied: HashSet<&'static str>,
+    pub calls: Mutex<Vec<String>>,
+}
+
+impl MockS3 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `operation`, such as `"PutObject"`, fail with AccessDenied.
+    pub fn deny(mut self, operation: &'static str) -> Self {
+        self.denied.insert(operation);
+        self
+    }
+
+    /// The operations called so far, in order.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn call(&self, operation: &'static str) -> Result<(), OpError> {
+        self.calls.lock().unwrap().push(operation.to_string());
+        if self.denied.contains(operation) {
+            Err(OpError {
+                status: Some(403),
+                code: Some("AccessDenied".to_string()),
+                message: "Access Denied".to_string(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl_s3_ops! {
+    impl S3Ops for MockS3 {
+        async fn head_bucket(&self, _bucket: &str) -> Result<(), OpError> {
+            self.call("HeadBucket")
+        }
+
+        async fn create_multipart_upload(
+            &self,
+            _bucket: &str,
+            _key: &str,
+        ) -> Result<String, OpError> {
+            self.call("CreateMultipartUpload")?;
+            Ok("mock-upload-id".to_string())
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _upload_id: &str,
+        ) -> Result<(), OpError> {
+            self.call("AbortMultipartUpload")
+        }
+
+        async fn upload_part(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _upload_id: &str,
+            part_number: i32,
+            _body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            self.call("UploadPart")?;
+            Ok(format!("\"mock-etag-{}\"", part_number))
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _upload_id: &str,
+            _parts: Vec<(i32, String)>,
+        ) -> Result<String, OpError> {
+            self.call("CompleteMultipartUpload")?;
+            Ok("mock-etag".to_string())
+        }
+
+        async fn put_object(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            self.call("PutObject")?;
+            Ok("mock-etag".to_string())
+        }
+
+        async fn delete_object(&self, _bucket: &str, _key: &str) -> Result<(), OpError> {
+            self.call("DeleteObject")
+        }
+
+        async fn get_object_range(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>, OpError> {
+            self.call("GetObject")?;
+            Ok(vec![0; length as usize])
+        }
+    }
+}
@@ -0,0 +1,250 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use common::MockS3;
+use s3_service::adaptive::{
+    AdaptiveConcurrency, AdjustmentReason, AimdController, Concurrency, ConcurrencyEvent,
+};
+use s3_service::impl_s3_ops;
+use s3_service::ops::{OpError, S3Ops};
+use s3_service::scheduler::{upload_files, ScheduledFile, SchedulerOptions};
+use s3_service::shutdown::Shutdown;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const FILES: usize = 600;
+
+/// An endpoint that throttles PutObject requests beyond its capacity, which
+/// changes after a number of them.
+struct Capacity {
+    mock: MockS3,
+    capacity: AtomicUsize,
+    in_flight: AtomicUsize,
+    puts: AtomicUsize,
+    /// After this many requests, the capacity becomes the second value.
+    switch: (usize, usize),
+    concurrency: AdaptiveConcurrency,
+    /// The number of adjustments made when the capacity changed.
+    events_at_switch: Mutex<Option<usize>>,
+}
+
+impl Capacity {
+    fn new(capacity: usize, switch: (usize, usize), concurrency: AdaptiveConcurrency) -> Self {
+        Self {
+            mock: MockS3::new(),
+            capacity: AtomicUsize::new(capacity),
+            in_flight: AtomicUsize::new(0),
+            puts: AtomicUsize::new(0),
+            switch,
+            concurrency,
+            events_at_switch: Mutex::new(None),
+        }
+    }
+}
+
+impl_s3_ops! {
+    impl S3Ops for Capacity {
+        async fn head_bucket(&self, bucket: &str) -> Result<(), OpError> {
+            self.mock.head_bucket(bucket).await
+        }
+
+        async fn create_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> Result<String, OpError> {
+            self.mock.create_multipart_upload(bucket, key).await
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+        ) -> Result<(), OpError> {
+            self.mock
+                .abort_multipart_upload(bucket, key, upload_id)
+                .await
+        }
+
+        async fn upload_part(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            self.mock
+                .upload_part(bucket, key, upload_id, part_number, body)
+                .await
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String)>,
+        ) -> Result<String, OpError> {
+            self.mock
+                .complete_multipart_upload(bucket, key, upload_id, parts)
+                .await
+        }
+
+        async fn put_object(
+            &self,
+            _bucket: &str,
+            _key: &str,
+            _body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            let throttled = in_flight > self.capacity.load(Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            if self.puts.fetch_add(1, Ordering::SeqCst) + 1 == self.switch.0 {
+                self.capacity.store(self.switch.1, Ordering::SeqCst);
+                *self.events_at_switch.lock().unwrap() = Some(self.concurrency.events().len());
+            }
+            if throttled {
+                Err(OpError {
+                    status: Some(503),
+                    code: Some("SlowDown".to_string()),
+                    message: "Please reduce your request rate.".to_string(),
+                })
+            } else {
+                Ok("etag".to_string())
+            }
+        }
+
+        async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), OpError> {
+            self.mock.delete_object(bucket, key).await
+        }
+
+        async fn get_object_range(
+            &self,
+            bucket: &str,
+            key: &str,
+            offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>, OpError> {
+            self.mock
+                .get_object_range(bucket, key, offset, length)
+                .await
+        }
+    }
+}
+
+/// `FILES` small files, all read from the same local file.
+fn test_files() -> (std::path::PathBuf, Vec<ScheduledFile>) {
+    let path = std::env::temp_dir().join(format!("adaptive-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, b"data").unwrap();
+    let files = (0..FILES)
+        .map(|n| ScheduledFile {
+            path: path.clone(),
+            key: format!("file-{}", n),
+            size: 4,
+        })
+        .collect();
+    (path, files)
+}
+
+fn peak(events: &[ConcurrencyEvent]) -> usize {
+    events.iter().map(|event| event.to).max().unwrap_or(0)
+}
+
+#[test]
+fn test_parse_concurrency() {
+    assert_eq!(Ok(Concurrency::Auto), "auto".parse());
+    assert_eq!(Ok(Concurrency::Fixed(8)), "8".parse());
+    assert!("0".parse::<Concurrency>().is_err());
+    assert!("many".parse::<Concurrency>().is_err());
+}
+
+#[test]
+fn test_aimd_controller() {
+    // Starts from a saved level rather than from the minimum.
+    let mut controller = AimdController::new(12, 1, 64);
+    assert_eq!(12, controller.limit());
+    for _ in 0..12 {
+        controller.on_success();
+    }
+    assert_eq!(13, controller.limit());
+
+    let sent_at = controller.epoch();
+    controller.on_throttle(sent_at);
+    assert_eq!(6, controller.limit());
+    // The other requests of the same burst do not lower it again.
+    controller.on_throttle(sent_at);
+    assert_eq!(6, controller.limit());
+    controller.on_throttle(controller.epoch());
+    assert_eq!(3, controller.limit());
+
+    let reasons: Vec<_> = controller.events().iter().map(|e| e.reason).collect();
+    assert_eq!(
+        vec![
+            AdjustmentReason::Increase,
+            AdjustmentReason::Throttled,
+            AdjustmentReason::Throttled
+        ],
+        reasons
+    );
+    assert!(controller
+        .events()
+        .windows(2)
+        .all(|w| w[0].timestamp_ms <= w[1].timestamp_ms));
+
+    let mut bounded = AimdController::new(2, 2, 3);
+    bounded.on_throttle(bounded.epoch());
+    assert_eq!(2, bounded.limit());
+    for _ in 0..10 {
+        bounded.on_success();
+    }
+    assert_eq!(3, bounded.limit());
+}
+
+#[tokio::test]
+async fn test_reconverges_when_capacity_changes() {
+    let (path, files) = test_files();
+    let concurrency = AdaptiveConcurrency::new(AimdController::new(2, 1, 32));
+    // 16 requests at a time are accepted for the first half of the batch,
+    // then 4.
+    let endpoint = Capacity::new(16, (FILES / 2, 4), concurrency.clone());
+    let options = SchedulerOptions {
+        adaptive: Some(concurrency.clone()),
+        ..Default::default()
+    };
+
+    let summary = upload_files(&endpoint, "bucket", files, &options, &Shutdown::default()).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(FILES, summary.completed.len() + summary.pending.len());
+    assert!(summary
+        .failed()
+        .all(|file| file.error.as_deref().unwrap().contains("SlowDown")));
+
+    let events = concurrency.events();
+    let switch = endpoint.events_at_switch.lock().unwrap().unwrap();
+    let (before, after) = events.split_at(switch);
+    // The level climbs from 2 to the first capacity, and no further than
+    // one above it before it is throttled.
+    let first = peak(before);
+    assert!((12..=17).contains(&first), "{:?}", before);
+    assert!(before
+        .iter()
+        .any(|event| event.reason == AdjustmentReason::Throttled));
+
+    // Once the capacity drops, it is throttled back down and then stays
+    // around the new capacity.
+    let settled = after
+        .iter()
+        .position(|event| event.reason == AdjustmentReason::Throttled && event.to <= 4)
+        .expect("never went back down");
+    assert!(peak(&after[settled..]) <= 5, "{:?}", &after[settled..]);
+    assert!((2..=5).contains(&concurrency.limit()));
+}
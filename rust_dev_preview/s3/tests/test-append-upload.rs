@@ -0,0 +1,261 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use md5::{Digest, Md5};
+use s3_service::append_upload::{append_upload, AppendManifest, AppendOptions, AppendRun};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const OPTIONS: AppendOptions = AppendOptions {
+    part_size: 250,
+    min_part_size: 100,
+};
+
+/// The parts uploaded, by part number, the number of UploadPart requests,
+/// and the object assembled by the completion.
+#[derive(Default)]
+struct Upload {
+    parts: BTreeMap<i32, Vec<u8>>,
+    part_requests: usize,
+    object: Option<Vec<u8>>,
+}
+
+/// Starts a server holding one multipart upload `upload` of `key`: it stores
+/// the bytes of each part, with their MD5 as ETag, lists them, and assembles
+/// the object from the parts named by the completion.
+async fn mock_s3() -> (Client, Arc<Mutex<Upload>>) {
+    let upload = Arc::new(Mutex::new(Upload::default()));
+    let state = upload.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let state = state.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let mut upload = state.lock().unwrap();
+            let response = if method == Method::POST && query.starts_with("uploads") {
+                Response::builder().body(Body::from(
+                    "<InitiateMultipartUploadResult><Bucket>bucket</Bucket>\
+                     <Key>key</Key><UploadId>upload</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                ))
+            } else if method == Method::GET {
+                let parts: String = upload
+                    .parts
+                    .iter()
+                    .map(|(part_number, bytes)| {
+                        format!(
+                            "<Part><PartNumber>{}</PartNumber><ETag>\"{:x}\"</ETag>\
+                             <Size>{}</Size></Part>",
+                            part_number,
+                            Md5::digest(bytes),
+                            bytes.len()
+                        )
+                    })
+                    .collect();
+                Response::builder().body(Body::from(format!(
+                    "<ListPartsResult><Bucket>bucket</Bucket><Key>key</Key>\
+                     <UploadId>upload</UploadId><IsTruncated>false</IsTruncated>{}\
+                     </ListPartsResult>",
+                    parts
+                )))
+            } else if method == Method::PUT {
+                let part_number: i32 = query
+                    .split('&')
+                    .find_map(|p| p.strip_prefix("partNumber="))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let e_tag = format!("\"{:x}\"", Md5::digest(&body));
+                upload.parts.insert(part_number, body.to_vec());
+                upload.part_requests += 1;
+                Response::builder()
+                    .header("ETag", e_tag)
+                    .body(Body::empty())
+            } else {
+                let completion = String::from_utf8(body.to_vec()).unwrap();
+                let object: Vec<u8> = completion
+                    .split("<PartNumber>")
+                    .skip(1)
+                    .flat_map(|rest| {
+                        let part_number: i32 = rest[..rest.find('<').unwrap()].parse().unwrap();
+                        upload.parts[&part_number].clone()
+                    })
+                    .collect();
+                upload.object = Some(object);
+                Response::builder().body(Body::from(
+                    "<CompleteMultipartUploadResult><ETag>\"done-5\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                ))
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), upload)
+}
+
+/// An empty log file and the path of its manifest, not created yet.
+fn log_file() -> (PathBuf, PathBuf) {
+    let id = uuid::Uuid::new_v4();
+    let path = std::env::temp_dir().join(format!("append-{}.log", id));
+    std::fs::write(&path, b"").unwrap();
+    let manifest = std::env::temp_dir().join(format!("append-{}.json", id));
+    (path, manifest)
+}
+
+/// Appends `len` bytes to the file at `path`, continuing its pattern.
+fn grow(path: &Path, len: u64) {
+    let start = std::fs::metadata(path).unwrap().len();
+    let bytes: Vec<u8> = (start..start + len).map(|i| (i % 251) as u8).collect();
+    let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(&bytes).unwrap();
+}
+
+async fn run(
+    client: &Client,
+    path: &Path,
+    manifest: &Path,
+    finalize: bool,
+) -> Result<AppendRun, aws_sdk_s3::Error> {
+    append_upload(client, "bucket", "key", path, manifest, finalize, &OPTIONS).await
+}
+
+#[tokio::test]
+async fn test_grow_and_run_cycles_assemble_the_file() {
+    let (client, upload) = mock_s3().await;
+    let (path, manifest) = log_file();
+
+    grow(&path, 30);
+    let first = run(&client, &path, &manifest, false).await.unwrap();
+    assert_eq!("upload", first.upload_id);
+    assert!(first.parts_uploaded.is_empty());
+    assert_eq!(30, first.remainder);
+
+    grow(&path, 200);
+    let second = run(&client, &path, &manifest, false).await.unwrap();
+    assert_eq!(vec![1], second.parts_uploaded);
+    assert_eq!(200, second.bytes_read);
+    assert_eq!(0, second.remainder);
+
+    // 250 and 250, then 100: just enough for a part of its own.
+    grow(&path, 600);
+    let third = run(&client, &path, &manifest, false).await.unwrap();
+    assert_eq!(vec![2, 3, 4], third.parts_uploaded);
+
+    grow(&path, 40);
+    let fourth = run(&client, &path, &manifest, false).await.unwrap();
+    assert!(fourth.parts_uploaded.is_empty());
+    assert_eq!(40, fourth.remainder);
+    assert!(upload.lock().unwrap().object.is_none());
+
+    let last = run(&client, &path, &manifest, true).await.unwrap();
+    assert_eq!(vec![5], last.parts_uploaded);
+    assert_eq!(Some("done-5".to_string()), last.e_tag);
+
+    let upload = upload.lock().unwrap();
+    let sizes: Vec<usize> = upload.parts.values().map(Vec::len).collect();
+    assert_eq!(vec![230, 250, 250, 100, 40], sizes);
+    assert_eq!(
+        &std::fs::read(&path).unwrap(),
+        upload.object.as_ref().unwrap()
+    );
+    let saved = AppendManifest::load(&manifest).unwrap();
+    assert_eq!(Some("done-5".to_string()), saved.completed_e_tag);
+    assert_eq!(870, saved.uploaded);
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&manifest).unwrap();
+}
+
+#[tokio::test]
+async fn test_part_missing_from_the_manifest_is_recovered() {
+    let (client, upload) = mock_s3().await;
+    let (path, manifest) = log_file();
+    grow(&path, 50);
+    run(&client, &path, &manifest, false).await.unwrap();
+    let before = std::fs::read(&manifest).unwrap();
+
+    grow(&path, 250);
+    let uploaded = run(&client, &path, &manifest, false).await.unwrap();
+    assert_eq!(vec![1], uploaded.parts_uploaded);
+    // As if the run had stopped before saving the part.
+    std::fs::write(&manifest, before).unwrap();
+
+    let rerun = run(&client, &path, &manifest, false).await.unwrap();
+    assert_eq!(vec![1], rerun.parts_recovered);
+    assert!(rerun.parts_uploaded.is_empty());
+    assert_eq!(50, rerun.remainder);
+    assert_eq!(1, upload.lock().unwrap().part_requests);
+
+    run(&client, &path, &manifest, true).await.unwrap();
+    let upload = upload.lock().unwrap();
+    assert_eq!(2, upload.part_requests);
+    assert_eq!(
+        &std::fs::read(&path).unwrap(),
+        upload.object.as_ref().unwrap()
+    );
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&manifest).unwrap();
+}
+
+#[tokio::test]
+async fn test_replaced_part_is_uploaded_again() {
+    let (client, upload) = mock_s3().await;
+    let (path, manifest) = log_file();
+    grow(&path, 50);
+    run(&client, &path, &manifest, false).await.unwrap();
+    // A part 1 that does not hold the next bytes of the file.
+    upload.lock().unwrap().parts.insert(1, vec![0; 120]);
+
+    grow(&path, 100);
+    let rerun = run(&client, &path, &manifest, true).await.unwrap();
+
+    assert!(rerun.parts_recovered.is_empty());
+    assert_eq!(vec![1], rerun.parts_uploaded);
+    assert_eq!(
+        &std::fs::read(&path).unwrap(),
+        upload.lock().unwrap().object.as_ref().unwrap()
+    );
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&manifest).unwrap();
+}
+
+#[tokio::test]
+async fn test_truncated_file_and_completed_upload_are_refused() {
+    let (client, _) = mock_s3().await;
+    let (path, manifest) = log_file();
+    grow(&path, 80);
+    run(&client, &path, &manifest, false).await.unwrap();
+
+    std::fs::write(&path, b"rotated").unwrap();
+    let err = run(&client, &path, &manifest, false).await.unwrap_err();
+    assert!(err.to_string().contains("truncated or rotated"), "{}", err);
+
+    grow(&path, 73);
+    run(&client, &path, &manifest, true).await.unwrap();
+    let err = run(&client, &path, &manifest, false).await.unwrap_err();
+    assert!(err.to_string().contains("already completed"), "{}", err);
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&manifest).unwrap();
+}
+
+#[tokio::test]
+async fn test_empty_file_is_completed_with_an_empty_part() {
+    let (client, upload) = mock_s3().await;
+    let (path, manifest) = log_file();
+
+    let result = run(&client, &path, &manifest, true).await.unwrap();
+
+    assert_eq!(vec![1], result.parts_uploaded);
+    assert_eq!(Some(Vec::new()), upload.lock().unwrap().object);
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&manifest).unwrap();
+}
@@ -0,0 +1,141 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use s3_service::bandwidth::{ThrottledRead, TokenBucket};
+use s3_service::download::download_prefix_rate_limited;
+use s3_service::durable::FsyncOptions;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+
+const KIB: u64 = 1024;
+
+/// Starts a server listing `objects` and serving them.
+async fn mock_s3(objects: BTreeMap<String, Vec<u8>>) -> Client {
+    let objects = Arc::new(objects);
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let objects = objects.clone();
+        async move {
+            let key = req.uri().path().trim_start_matches("/bucket/").to_string();
+            if key == "/bucket" {
+                let contents: String = objects
+                    .iter()
+                    .map(|(key, body)| {
+                        format!(
+                            "<Contents><Key>{}</Key><Size>{}</Size></Contents>",
+                            key,
+                            body.len()
+                        )
+                    })
+                    .collect();
+                Response::new(Body::from(format!(
+                    "<ListBucketResult><Name>bucket</Name>\
+                     <IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                    contents
+                )))
+            } else {
+                let body = objects[&key].clone();
+                Response::builder()
+                    .header("Content-Length", body.len())
+                    .body(Body::from(body))
+                    .unwrap()
+            }
+        }
+    });
+
+    common::client_for(port)
+}
+
+/// `wait` in whole milliseconds, rounding away the float error.
+fn millis(wait: Duration) -> u64 {
+    (wait.as_secs_f64() * 1000.0).round() as u64
+}
+
+#[test]
+fn test_token_bucket_goes_into_debt() {
+    let mut bucket = TokenBucket::new(1000).unwrap();
+    let start = Instant::now();
+    let at = |ms: u64| start + Duration::from_millis(ms);
+
+    // A tenth of a second of tokens to start with.
+    assert_eq!(0, millis(bucket.take_at(100, at(0))));
+    assert_eq!(500, millis(bucket.take_at(500, at(0))));
+    // Half of the debt is paid back after 250 ms.
+    assert_eq!(350, millis(bucket.take_at(100, at(250))));
+    // Idle time does not store more than the capacity.
+    assert_eq!(0, millis(bucket.take_at(100, at(60_000))));
+    assert_eq!(1, millis(bucket.take_at(1, at(60_000))));
+    assert_eq!(801, bucket.taken());
+}
+
+#[test]
+fn test_token_bucket_needs_a_positive_rate() {
+    assert!(TokenBucket::new(0).is_err());
+    assert_eq!(1, TokenBucket::new(1).unwrap().bytes_per_second());
+}
+
+#[tokio::test]
+async fn test_throttled_read_is_paced() {
+    let data = vec![7u8; 50 * KIB as usize];
+    let bucket = Arc::new(Mutex::new(TokenBucket::new(100 * KIB).unwrap()));
+    let mut reader = ThrottledRead::new(&data[..], bucket.clone());
+
+    let start = Instant::now();
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).await.unwrap();
+
+    assert_eq!(data, read);
+    // 10 KiB of burst, then 40 KiB at 100 KiB/s.
+    assert!(start.elapsed() >= Duration::from_millis(380));
+    assert_eq!(50 * KIB, bucket.lock().unwrap().taken());
+}
+
+#[tokio::test]
+async fn test_prefix_download_shares_the_cap() {
+    let mut objects = BTreeMap::new();
+    for i in 0..3u8 {
+        objects.insert(format!("data/{}.bin", i), vec![i; 40 * KIB as usize]);
+    }
+    objects.insert("data/empty/".to_string(), Vec::new());
+    let client = mock_s3(objects.clone()).await;
+    let dir = std::env::temp_dir().join(format!("bandwidth-{}", uuid::Uuid::new_v4()));
+    let max_bps = 300 * KIB;
+
+    let summary = download_prefix_rate_limited(
+        &client,
+        "bucket",
+        "data/",
+        &dir,
+        max_bps,
+        3,
+        &FsyncOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(3, summary.files);
+    assert_eq!(1, summary.directories);
+    assert_eq!(120 * KIB, summary.bytes);
+    for i in 0..3u8 {
+        assert_eq!(
+            vec![i; 40 * KIB as usize],
+            std::fs::read(dir.join(format!("{}.bin", i))).unwrap()
+        );
+    }
+    assert!(dir.join("empty").is_dir());
+    // 30 KiB of burst, then 90 KiB at 300 KiB/s, whatever the concurrency.
+    assert!(summary.elapsed >= Duration::from_millis(290));
+    assert!(
+        summary.bytes_per_second() <= max_bps as f64 * 1.4,
+        "{}",
+        summary.bytes_per_second()
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
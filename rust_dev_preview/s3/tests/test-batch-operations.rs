@@ -0,0 +1,238 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::Client;
+use aws_sdk_s3control::model::JobStatus;
+use futures::StreamExt;
+use hyper::{Body, Method, Request, Response};
+use s3_service::batch_operations::{
+    create_batch_job_manifest, create_tagging_batch_job, manifest_csv, monitor_batch_job,
+    JobProgress,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[test]
+fn test_manifest_csv_encodes_keys() {
+    let keys = ["logs/2022/a.txt", "photos/my cat+dog.jpg", "data/é,1.csv"];
+    assert_eq!(
+        "bucket,logs/2022/a.txt\n\
+         bucket,photos/my%20cat%2Bdog.jpg\n\
+         bucket,data/%C3%A9%2C1.csv\n",
+        manifest_csv("bucket", &keys)
+    );
+    assert_eq!("", manifest_csv::<&str>("bucket", &[]));
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_create_batch_job_manifest() {
+    let client = common::minio_client().await;
+    let bucket = common::create_test_bucket(&client).await;
+    for key in ["input/b", "input/a", "other/c"].iter() {
+        client
+            .put_object()
+            .bucket(&bucket)
+            .key(*key)
+            .body(ByteStream::from_static(b"x"))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let key = create_batch_job_manifest(&client, &bucket, "input/", "input/manifest.csv")
+        .await
+        .unwrap();
+    // Listing again does not put the manifest in itself.
+    create_batch_job_manifest(&client, &bucket, "input/", "input/manifest.csv")
+        .await
+        .unwrap();
+    let body = client
+        .get_object()
+        .bucket(&bucket)
+        .key(&key)
+        .send()
+        .await
+        .unwrap()
+        .body
+        .collect()
+        .await
+        .unwrap()
+        .into_bytes();
+    assert_eq!("input/manifest.csv", key);
+    assert_eq!(
+        format!("{0},input/a\n{0},input/b\n", bucket),
+        String::from_utf8(body.to_vec()).unwrap()
+    );
+
+    assert!(
+        create_batch_job_manifest(&client, &bucket, "missing/", "m.csv")
+            .await
+            .is_err()
+    );
+    common::delete_test_bucket(&client, &bucket).await;
+}
+
+/// The CreateJob bodies received.
+type Received = Arc<Mutex<Vec<String>>>;
+
+/// Answers the HeadObject of the manifest, CreateJob, and DescribeJob with
+/// the next of `statuses`, as `(status, total, succeeded, failed)`.
+async fn mock_s3control(statuses: Vec<(&'static str, i64, i64, i64)>) -> (u16, Received) {
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+    let recorder = received.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        let statuses = statuses.clone();
+        async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let response = match (method, path.as_str()) {
+                (Method::HEAD, "/manifests/manifest.csv") => Response::builder()
+                    .header("ETag", "\"manifest-etag\"")
+                    .body(Body::empty()),
+                (Method::POST, "/v20180820/jobs") => {
+                    recorder
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8(body.to_vec()).unwrap());
+                    Response::builder().body(Body::from(
+                        "<CreateJobResult><JobId>job-1</JobId></CreateJobResult>",
+                    ))
+                }
+                (Method::GET, "/v20180820/jobs/job-1") => {
+                    let (status, total, succeeded, failed) =
+                        statuses.lock().unwrap().next().unwrap();
+                    Response::builder().body(Body::from(format!(
+                        "<DescribeJobResult><Job><JobId>job-1</JobId>\
+                         <Status>{}</Status><ProgressSummary>\
+                         <TotalNumberOfTasks>{}</TotalNumberOfTasks>\
+                         <NumberOfTasksSucceeded>{}</NumberOfTasksSucceeded>\
+                         <NumberOfTasksFailed>{}</NumberOfTasksFailed>\
+                         </ProgressSummary></Job></DescribeJobResult>",
+                        status, total, succeeded, failed
+                    )))
+                }
+                _ => Response::builder().status(404).body(Body::empty()),
+            };
+            response.unwrap()
+        }
+    });
+    (port, received)
+}
+
+fn s3control_client(port: u16) -> aws_sdk_s3control::Client {
+    let conf = aws_sdk_s3control::Config::builder()
+        .region(aws_sdk_s3control::Region::new("us-east-1"))
+        .credentials_provider(aws_sdk_s3control::Credentials::new(
+            "access", "secret", None, None, "test",
+        ))
+        .endpoint_resolver(aws_sdk_s3control::Endpoint::immutable(
+            common::url_for(port).parse().unwrap(),
+        ))
+        .retry_config(aws_sdk_s3control::RetryConfig::disabled())
+        .build();
+    aws_sdk_s3control::Client::from_conf(conf)
+}
+
+#[tokio::test]
+async fn test_create_tagging_batch_job() {
+    let (port, received) = mock_s3control(Vec::new()).await;
+    let tags: HashMap<String, String> = vec![
+        ("team".to_string(), "storage".to_string()),
+        ("env".to_string(), "prod".to_string()),
+    ]
+    .into_iter()
+    .collect();
+
+    let job_id = create_tagging_batch_job(
+        &s3control_client(port),
+        &common::client_for(port),
+        "111122223333",
+        "manifests",
+        "manifest.csv",
+        tags,
+        "arn:aws:iam::111122223333:role/batch",
+        "reports",
+    )
+    .await
+    .unwrap();
+
+    assert_eq!("job-1", job_id);
+    let received = received.lock().unwrap();
+    assert_eq!(1, received.len());
+    let body = &received[0];
+    assert!(body.contains("<S3PutObjectTagging>"), "{}", body);
+    assert!(body.contains("<Value>storage</Value>"), "{}", body);
+    // The tags are sent in the order of their keys.
+    assert!(body.find("<Key>env</Key>").unwrap() < body.find("<Key>team</Key>").unwrap());
+    assert!(body.contains("<ObjectArn>arn:aws:s3:::manifests/manifest.csv</ObjectArn>"));
+    assert!(body.contains("<ETag>manifest-etag</ETag>"), "{}", body);
+    assert!(
+        body.contains("<Bucket>arn:aws:s3:::reports</Bucket>"),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test]
+async fn test_monitor_batch_job_until_complete() {
+    let (port, _) = mock_s3control(vec![
+        ("Preparing", 0, 0, 0),
+        ("Active", 10, 3, 1),
+        ("Complete", 10, 9, 1),
+    ])
+    .await;
+
+    let progress: Vec<JobProgress> = monitor_batch_job(
+        &s3control_client(port),
+        "111122223333",
+        "job-1",
+        Duration::from_millis(1),
+    )
+    .map(Result::unwrap)
+    .collect()
+    .await;
+
+    assert_eq!(3, progress.len());
+    assert_eq!(JobStatus::Preparing, progress[0].status);
+    assert_eq!(0.0, progress[0].progress_percent);
+    assert_eq!(40.0, progress[1].progress_percent);
+    assert!(!progress[1].is_over());
+    assert_eq!(
+        JobProgress {
+            status: JobStatus::Complete,
+            total_tasks: 10,
+            succeeded: 9,
+            failed: 1,
+            progress_percent: 100.0,
+        },
+        progress[2]
+    );
+    assert!(progress[2].is_over());
+}
+
+#[tokio::test]
+async fn test_monitor_batch_job_ends_on_error() {
+    let (port, _) = mock_s3control(Vec::new()).await;
+
+    let progress: Vec<_> = monitor_batch_job(
+        &s3control_client(port),
+        "111122223333",
+        "missing-job",
+        Duration::from_millis(1),
+    )
+    .collect()
+    .await;
+
+    assert_eq!(1, progress.len());
+    assert!(progress[0].is_err());
+}
@@ -0,0 +1,207 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use s3_service::batch::{
+    expand_remote, is_batch_object, plan_batches, ArchiveEntry, ArchiveIndex, BatchOptions,
+};
+use s3_service::download::download_prefix;
+use s3_service::sync::{format_mtime, sync_directory, LocalFile, RemoteObject, SyncOptions};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const KIB: u64 = 1024;
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn file(key: &str, size: u64) -> LocalFile {
+    LocalFile {
+        path: PathBuf::from(key),
+        key: key.to_string(),
+        size,
+        mtime: at(1000),
+    }
+}
+
+/// A synthetic tree: many small files in `logs/`, a few in `conf/`, a lone
+/// small file in `bin/`, and large files everywhere.
+fn tree() -> Vec<LocalFile> {
+    let mut files = (0..10)
+        .map(|i| file(&format!("data/logs/{}.log", i), 4 * KIB))
+        .collect::<Vec<_>>();
+    files.push(file("data/logs/big.log", 2048 * KIB));
+    files.push(file("data/conf/a.toml", KIB));
+    files.push(file("data/conf/b.toml", 2 * KIB));
+    files.push(file("data/bin/tool", 512));
+    files.push(file("data/bin/big-tool", 4096 * KIB));
+    files.push(file("data/readme", 100));
+    files
+}
+
+#[test]
+fn test_plan_batches_mixed_sizes() {
+    let options = BatchOptions {
+        threshold: 64 * KIB,
+        max_archive_size: 16 * KIB,
+        stamp_content_hash: false,
+    };
+    let plan = plan_batches(tree(), &options);
+
+    // 10 × 4 KiB logs in 16 KiB archives: 4 + 4 + 2.
+    let archives = plan
+        .archives
+        .iter()
+        .map(|a| (a.directory.as_str(), a.files.len(), a.size))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            ("data/conf/", 2, 3 * KIB),
+            ("data/logs/", 4, 16 * KIB),
+            ("data/logs/", 4, 16 * KIB),
+            ("data/logs/", 2, 8 * KIB),
+        ],
+        archives
+    );
+    assert!(plan.archives.iter().all(|a| a.size <= 16 * KIB));
+
+    // Large files, and small files alone in their directory, are not packed.
+    let mut individual = plan
+        .individual
+        .iter()
+        .map(|f| f.key.as_str())
+        .collect::<Vec<_>>();
+    individual.sort_unstable();
+    assert_eq!(
+        vec![
+            "data/bin/big-tool",
+            "data/bin/tool",
+            "data/logs/big.log",
+            "data/readme"
+        ],
+        individual
+    );
+    assert_eq!(12, plan.packed_files());
+    assert_eq!(4, plan.requests_saved());
+}
+
+#[test]
+fn test_plan_batches_threshold_is_exclusive() {
+    let options = BatchOptions {
+        threshold: 4 * KIB,
+        max_archive_size: 64 * KIB,
+        stamp_content_hash: false,
+    };
+    let plan = plan_batches(tree(), &options);
+    // Only conf/ has two files under 4 KiB.
+    assert_eq!(1, plan.archives.len());
+    assert_eq!("data/conf/", plan.archives[0].directory);
+}
+
+#[test]
+fn test_expand_remote() {
+    let object = |key: &str, size: u64, mtime: u64| RemoteObject {
+        key: key.to_string(),
+        size,
+        last_modified: at(mtime),
+        source_mtime: Some(at(mtime)),
+        e_tag: None,
+    };
+    let archive = "data/.s3-batch-1.pack";
+    let mut remote = vec![
+        object(archive, 30, 2000),
+        object("data/.s3-batch-1.index.json", 200, 2000),
+        // Uploaded individually after it was packed.
+        object("data/b", 25, 3000),
+        object("data/c", 5, 1500),
+    ]
+    .into_iter()
+    .map(|o| (o.key.clone(), o))
+    .collect::<HashMap<_, _>>();
+    let entry = |key: &str, offset: u64, size: u64| ArchiveEntry {
+        key: key.to_string(),
+        offset,
+        size,
+        mtime: format_mtime(at(1800)),
+        sha256: None,
+    };
+    let index = ArchiveIndex {
+        archive_key: archive.to_string(),
+        entries: vec![entry("data/a", 0, 10), entry("data/b", 10, 20)],
+    };
+
+    assert!(is_batch_object(archive));
+    assert!(!is_batch_object("data/notes.pack"));
+    let packed = expand_remote(&mut remote, &[index]);
+
+    let mut keys = remote.keys().cloned().collect::<Vec<_>>();
+    keys.sort();
+    assert_eq!(vec!["data/a", "data/b", "data/c"], keys);
+    assert_eq!(10, remote["data/a"].size);
+    assert_eq!(Some(at(1800)), remote["data/a"].source_mtime);
+    assert_eq!(25, remote["data/b"].size);
+    assert_eq!(vec!["data/a"], packed.keys().collect::<Vec<_>>());
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_batched_sync_round_trip() {
+    let client = common::minio_client().await;
+    let bucket = common::create_test_bucket(&client).await;
+    let root = std::env::temp_dir().join(format!("batch-test-{}", uuid::Uuid::new_v4()));
+    let source = root.join("source");
+    std::fs::create_dir_all(source.join("small")).unwrap();
+    for i in 0..20 {
+        std::fs::write(
+            source.join(format!("small/{}.txt", i)),
+            format!("file {}", i),
+        )
+        .unwrap();
+    }
+    std::fs::write(source.join("large.bin"), vec![b'x'; 100 * KIB as usize]).unwrap();
+
+    let options = SyncOptions {
+        batch: Some(BatchOptions {
+            threshold: 16 * KIB,
+            max_archive_size: 64 * KIB,
+            stamp_content_hash: false,
+        }),
+        ..Default::default()
+    };
+    let summary = sync_directory(&client, &bucket, &source, "tree/", &options, false)
+        .await
+        .unwrap();
+    assert_eq!(21, summary.uploaded.len());
+    assert_eq!(1, summary.archives);
+    assert_eq!(20, summary.packed_files);
+
+    // The packed files are recognized on the next run.
+    let summary = sync_directory(&client, &bucket, &source, "tree/", &options, false)
+        .await
+        .unwrap();
+    assert!(summary.uploaded.is_empty());
+    assert_eq!(21, summary.identical.len());
+
+    let target = root.join("target");
+    let downloaded = download_prefix(&client, &bucket, "tree/", &target, true)
+        .await
+        .unwrap();
+    assert_eq!(21, downloaded.files);
+    assert_eq!(20, downloaded.unpacked_files);
+    for i in 0..20 {
+        let name = format!("small/{}.txt", i);
+        assert_eq!(
+            std::fs::read(source.join(&name)).unwrap(),
+            std::fs::read(target.join(&name)).unwrap()
+        );
+    }
+    assert!(target.join("large.bin").exists());
+
+    std::fs::remove_dir_all(&root).unwrap();
+    common::delete_test_bucket(&client, &bucket).await;
+}
@@ -0,0 +1,342 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use common::MockS3;
+use s3_service::bisync::{
+    conflict_key, execute_bisync, plan_bisync, walk_bisync_directory, BisyncAction, BisyncOptions,
+    BisyncState, ConflictPolicy, SyncedEntry, BISYNC_STATE_FILE,
+};
+use s3_service::sync::{format_mtime, LocalFile, RemoteObject};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn local(key: &str, size: u64, mtime: u64) -> LocalFile {
+    LocalFile {
+        path: PathBuf::from(key),
+        key: key.to_string(),
+        size,
+        mtime: at(mtime),
+    }
+}
+
+fn remote(key: &str, size: u64, mtime: u64, e_tag: &str) -> RemoteObject {
+    RemoteObject {
+        key: key.to_string(),
+        size,
+        last_modified: at(mtime),
+        source_mtime: None,
+        e_tag: Some(e_tag.to_string()),
+    }
+}
+
+fn synced(size: u64, mtime: u64, e_tag: &str) -> SyncedEntry {
+    SyncedEntry {
+        size,
+        mtime: format_mtime(at(mtime)),
+        e_tag: e_tag.to_string(),
+    }
+}
+
+fn options(conflict: ConflictPolicy) -> BisyncOptions {
+    BisyncOptions {
+        conflict,
+        ..Default::default()
+    }
+}
+
+/// What the plan does with each key, as text.
+fn outcomes(
+    local: Vec<LocalFile>,
+    remote: Vec<RemoteObject>,
+    state: &BisyncState,
+    conflict: ConflictPolicy,
+) -> Vec<(String, String)> {
+    let remote: HashMap<_, _> = remote.into_iter().map(|o| (o.key.clone(), o)).collect();
+    let plan = plan_bisync(local, &remote, state, &options(conflict));
+    let mut outcomes: Vec<(String, String)> = plan
+        .actions
+        .iter()
+        .map(|action| match action {
+            BisyncAction::Upload { file, reason } => {
+                (file.key.clone(), format!("upload, {}", reason))
+            }
+            BisyncAction::Download { object, reason } => {
+                (object.key.clone(), format!("download, {}", reason))
+            }
+            BisyncAction::KeepBoth {
+                file, renamed_key, ..
+            } => (file.key.clone(), format!("keep both as {}", renamed_key)),
+        })
+        .collect();
+    outcomes.extend(
+        plan.identical
+            .iter()
+            .map(|(file, _)| (file.key.clone(), "identical".to_string())),
+    );
+    outcomes.extend(
+        plan.conflicts
+            .iter()
+            .map(|key| (key.clone(), "conflict".to_string())),
+    );
+    outcomes.sort();
+    outcomes
+}
+
+fn expected(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    let mut pairs: Vec<_> = pairs
+        .iter()
+        .map(|(key, outcome)| (key.to_string(), outcome.to_string()))
+        .collect();
+    pairs.sort();
+    pairs
+}
+
+#[test]
+fn test_conflict_matrix() {
+    let mut state = BisyncState::default();
+    for key in &["same", "local-edit", "remote-edit", "both", "gone-remote"] {
+        state
+            .entries
+            .insert(key.to_string(), synced(10, 1000, "e1"));
+    }
+    let local_files = || {
+        vec![
+            local("same", 10, 1000),
+            local("local-edit", 12, 2000),
+            local("remote-edit", 10, 1000),
+            local("both", 12, 2000),
+            local("new-local", 5, 3000),
+            local("gone-remote", 10, 1000),
+        ]
+    };
+    let objects = || {
+        vec![
+            remote("same", 10, 1001, "e1"),
+            remote("local-edit", 10, 1001, "e1"),
+            remote("remote-edit", 20, 2500, "e2"),
+            remote("both", 20, 2500, "e2"),
+            remote("new-remote", 7, 3000, "e3"),
+            // A directory marker is not a file.
+            remote("dir/", 0, 3000, "e4"),
+        ]
+    };
+    let unconflicted = [
+        ("same", "identical"),
+        ("local-edit", "upload, changed locally"),
+        ("remote-edit", "download, changed remotely"),
+        ("new-local", "upload, missing remotely"),
+        ("new-remote", "download, missing locally"),
+        // Deletions are not synchronized.
+        ("gone-remote", "upload, missing remotely"),
+    ];
+
+    let cases = [
+        (ConflictPolicy::PreferLocal, "upload, changed on both sides"),
+        (
+            ConflictPolicy::PreferRemote,
+            "download, changed on both sides",
+        ),
+        (ConflictPolicy::Error, "conflict"),
+        (ConflictPolicy::Rename, "keep both as both.conflict-2000"),
+    ];
+    for (policy, both) in cases.iter() {
+        let mut want = unconflicted.to_vec();
+        want.push(("both", *both));
+        assert_eq!(
+            expected(&want),
+            outcomes(local_files(), objects(), &state, *policy),
+            "{:?}",
+            policy
+        );
+    }
+}
+
+#[test]
+fn test_without_state_differences_are_conflicts() {
+    let state = BisyncState::default();
+    let outcome = outcomes(
+        vec![
+            local("a", 10, 1000),
+            local("b", 10, 1000),
+            local("c", 10, 1000),
+        ],
+        vec![
+            remote("a", 10, 1001, "e1"),
+            remote("b", 11, 1001, "e1"),
+            remote("c", 10, 5000, "e1"),
+        ],
+        &state,
+        ConflictPolicy::Error,
+    );
+    assert_eq!(
+        expected(&[("a", "identical"), ("b", "conflict"), ("c", "conflict")]),
+        outcome
+    );
+}
+
+#[test]
+fn test_touched_file_within_window_is_unchanged() {
+    let mut state = BisyncState::default();
+    state
+        .entries
+        .insert("a".to_string(), synced(10, 1000, "e1"));
+    let outcome = outcomes(
+        vec![local("a", 10, 1001)],
+        vec![remote("a", 10, 9000, "e1")],
+        &state,
+        ConflictPolicy::Error,
+    );
+    assert_eq!(expected(&[("a", "identical")]), outcome);
+}
+
+#[test]
+fn test_conflict_key() {
+    assert_eq!(
+        "docs/notes.conflict-1650000000.txt",
+        conflict_key("docs/notes.txt", at(1_650_000_000))
+    );
+    assert_eq!(
+        "docs/archive.tar.conflict-5.gz",
+        conflict_key("docs/archive.tar.gz", at(5))
+    );
+    assert_eq!("docs/README.conflict-5", conflict_key("docs/README", at(5)));
+    assert_eq!("v1.0/.env.conflict-5", conflict_key("v1.0/.env", at(5)));
+}
+
+fn test_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bisync-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn test_execute_renames_conflicts_and_records_state() {
+    let dir = test_dir();
+    std::fs::write(dir.join("sub/both.txt"), b"local edit").unwrap();
+    std::fs::write(dir.join("up.txt"), b"upload me").unwrap();
+    let local = walk_bisync_directory(&dir, "data/").unwrap();
+    let mut objects: HashMap<_, _> = vec![
+        remote("data/sub/both.txt", 6, 2000, "remote-both"),
+        remote("data/down/new.bin", 4, 2000, "remote-new"),
+    ]
+    .into_iter()
+    .map(|o| (o.key.clone(), o))
+    .collect();
+    let options = options(ConflictPolicy::Rename);
+    let plan = plan_bisync(local, &objects, &BisyncState::default(), &options);
+    let renamed = match &plan.actions[1] {
+        BisyncAction::KeepBoth { renamed_key, .. } => renamed_key.clone(),
+        other => panic!("unexpected action {:?}", other),
+    };
+
+    let ops = MockS3::new();
+    let summary = execute_bisync(
+        &ops,
+        "bucket",
+        &dir,
+        "data/",
+        plan,
+        BisyncState::default(),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        vec![renamed.clone(), "data/up.txt".to_string()],
+        summary.uploaded
+    );
+    assert_eq!(
+        vec!["data/down/new.bin", "data/sub/both.txt"],
+        summary.downloaded
+    );
+    assert_eq!(
+        vec![("data/sub/both.txt".to_string(), renamed.clone())],
+        summary.renamed
+    );
+    assert!(summary.failed.is_empty());
+    let mut calls = ops.calls();
+    calls.sort();
+    assert_eq!(
+        vec!["GetObject", "GetObject", "PutObject", "PutObject"],
+        calls
+    );
+
+    // The local edit survives under its new name, and the object takes
+    // its place.
+    let renamed_path = dir.join(renamed.strip_prefix("data/").unwrap());
+    assert_eq!(
+        b"local edit".to_vec(),
+        std::fs::read(&renamed_path).unwrap()
+    );
+    assert_eq!(vec![0; 6], std::fs::read(dir.join("sub/both.txt")).unwrap());
+    assert_eq!(vec![0; 4], std::fs::read(dir.join("down/new.bin")).unwrap());
+
+    let state = BisyncState::load(&dir.join(BISYNC_STATE_FILE)).unwrap();
+    let keys: Vec<_> = state.entries.keys().cloned().collect();
+    let mut want = vec![
+        "data/down/new.bin".to_string(),
+        "data/sub/both.txt".to_string(),
+        "data/up.txt".to_string(),
+        renamed,
+    ];
+    want.sort();
+    assert_eq!(want, keys);
+    assert_eq!("remote-both", state.entries["data/sub/both.txt"].e_tag);
+    assert_eq!("mock-etag", state.entries["data/up.txt"].e_tag);
+
+    // The state file is not synchronized, and the next run has nothing to do.
+    let local = walk_bisync_directory(&dir, "data/").unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(local
+        .iter()
+        .all(|file| !file.key.ends_with(BISYNC_STATE_FILE)));
+    for file in &local {
+        let e_tag = &state.entries[&file.key].e_tag;
+        objects.insert(file.key.clone(), remote(&file.key, file.size, 9000, e_tag));
+    }
+    let plan = plan_bisync(local, &objects, &state, &options);
+    assert!(plan.actions.is_empty());
+    assert!(plan.conflicts.is_empty());
+    assert_eq!(4, plan.identical.len());
+}
+
+#[tokio::test]
+async fn test_execute_refuses_unresolved_conflicts() {
+    let dir = test_dir();
+    std::fs::write(dir.join("a.txt"), b"local").unwrap();
+    let local = walk_bisync_directory(&dir, "").unwrap();
+    let objects: HashMap<_, _> = vec![remote("a.txt", 100, 9000, "e1")]
+        .into_iter()
+        .map(|o| (o.key.clone(), o))
+        .collect();
+    let options = options(ConflictPolicy::Error);
+    let plan = plan_bisync(local, &objects, &BisyncState::default(), &options);
+    assert_eq!(vec!["a.txt"], plan.conflicts);
+
+    let ops = MockS3::new();
+    let result = execute_bisync(
+        &ops,
+        "bucket",
+        &dir,
+        "",
+        plan,
+        BisyncState::default(),
+        &options,
+    )
+    .await;
+    let state_written = dir.join(BISYNC_STATE_FILE).exists();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(result.unwrap_err().to_string().contains("a.txt"));
+    assert!(ops.calls().is_empty());
+    assert!(!state_written);
+}
@@ -0,0 +1,214 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::{Client, Region};
+use hyper::{Body, Method, Request, Response};
+use s3_service::bucket_arn::{check_arn_addressing, region_for_arn, BucketArn};
+use s3_service::download::download_auto_decompress;
+use s3_service::express::check_general_purpose_bucket;
+use s3_service::listing::{list_objects, KeyEncoding};
+use s3_service::upload::upload_chunk;
+use std::sync::{Arc, Mutex};
+
+const ACCESS_POINT: &str = "arn:aws:s3:us-west-2:123456789012:accesspoint/my-ap";
+const OUTPOST_ACCESS_POINT: &str =
+    "arn:aws:s3-outposts:us-west-2:123456789012:outpost/op-01ac5d28a6a232904/accesspoint/my-ap";
+
+#[test]
+fn test_plain_bucket_is_not_an_arn() {
+    assert_eq!(None, BucketArn::parse("doc-example-bucket").unwrap());
+}
+
+#[test]
+fn test_parse_access_point() {
+    let arn = BucketArn::parse(ACCESS_POINT).unwrap().unwrap();
+    assert_eq!(
+        BucketArn::AccessPoint {
+            arn: ACCESS_POINT.to_string(),
+            region: "us-west-2".to_string(),
+            account_id: "123456789012".to_string(),
+            name: "my-ap".to_string(),
+        },
+        arn
+    );
+    assert_eq!(ACCESS_POINT, arn.bucket());
+    assert_eq!(Some("us-west-2"), arn.region());
+
+    let colon = "arn:aws:s3:us-west-2:123456789012:accesspoint:my-ap";
+    let arn = BucketArn::parse(colon).unwrap().unwrap();
+    assert_eq!(colon, arn.bucket());
+}
+
+#[test]
+fn test_parse_outpost_access_point() {
+    let arn = BucketArn::parse(OUTPOST_ACCESS_POINT).unwrap().unwrap();
+    match &arn {
+        BucketArn::OutpostAccessPoint {
+            outpost_id, name, ..
+        } => {
+            assert_eq!("op-01ac5d28a6a232904", outpost_id);
+            assert_eq!("my-ap", name);
+        }
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(OUTPOST_ACCESS_POINT, arn.bucket());
+}
+
+#[test]
+fn test_bucket_arn_becomes_the_bucket_name() {
+    let arn = BucketArn::parse("arn:aws:s3:::doc-example-bucket")
+        .unwrap()
+        .unwrap();
+    assert_eq!("doc-example-bucket", arn.bucket());
+    assert_eq!(None, arn.region());
+    assert!(!arn.is_access_point());
+}
+
+#[test]
+fn test_invalid_arns() {
+    for bucket in &[
+        "arn:aws:s3",
+        "arn:aws:s3:us-west-2::accesspoint/my-ap",
+        "arn:aws:s3:us-west-2:123456789012:accesspoint/",
+        "arn:aws:s3:::",
+        "arn:aws:iam::123456789012:role/admin",
+        "arn:aws:s3-outposts:us-west-2:123456789012:outpost/op-1/bucket/my-bucket",
+    ] {
+        assert!(BucketArn::parse(bucket).is_err(), "{}", bucket);
+    }
+}
+
+#[test]
+fn test_region_from_arn() {
+    let arn = BucketArn::parse(ACCESS_POINT).unwrap().unwrap();
+    assert_eq!(
+        Some("us-west-2".to_string()),
+        region_for_arn(None, &arn).unwrap()
+    );
+    assert_eq!(
+        Some("us-west-2".to_string()),
+        region_for_arn(Some("us-west-2".to_string()), &arn).unwrap()
+    );
+    let err = region_for_arn(Some("eu-west-1".to_string()), &arn).unwrap_err();
+    assert!(err.to_string().contains("us-west-2"), "{}", err);
+
+    let bucket = BucketArn::parse("arn:aws:s3:::doc-example-bucket")
+        .unwrap()
+        .unwrap();
+    assert_eq!(None, region_for_arn(None, &bucket).unwrap());
+}
+
+#[test]
+fn test_path_style_endpoint_is_rejected() {
+    let arn = BucketArn::parse(ACCESS_POINT).unwrap().unwrap();
+    assert!(check_arn_addressing(&arn, &[]).is_ok());
+    let err = check_arn_addressing(&arn, &["http://localhost:9000".to_string()]).unwrap_err();
+    assert!(err.to_string().contains("path-style"), "{}", err);
+
+    let bucket = BucketArn::parse("arn:aws:s3:::doc-example-bucket")
+        .unwrap()
+        .unwrap();
+    assert!(check_arn_addressing(&bucket, &["http://localhost:9000".to_string()]).is_ok());
+}
+
+#[test]
+fn test_access_points_skip_bucket_name_checks() {
+    assert!(check_general_purpose_bucket(ACCESS_POINT).is_ok());
+    assert!(check_general_purpose_bucket(OUTPOST_ACCESS_POINT).is_ok());
+}
+
+/// Answers PutObject, GetObject, and ListObjectsV2, recording the decoded
+/// path of each request.
+async fn mock_s3() -> (Client, Arc<Mutex<Vec<String>>>) {
+    let paths = Arc::new(Mutex::new(Vec::new()));
+    let recorder = paths.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let paths = recorder.clone();
+        async move {
+            let path = percent_encoding::percent_decode_str(req.uri().path())
+                .decode_utf8()
+                .unwrap()
+                .to_string();
+            paths.lock().unwrap().push(path);
+            let listing = req.uri().query().unwrap_or("").contains("list-type=2");
+            let response = match *req.method() {
+                Method::PUT => Response::builder()
+                    .header("ETag", "\"etag\"")
+                    .body(Body::empty()),
+                Method::GET if listing => Response::builder().body(Body::from(
+                    "<ListBucketResult><IsTruncated>false</IsTruncated>\
+                     <Contents><Key>a.txt</Key><Size>5</Size></Contents>\
+                     </ListBucketResult>",
+                )),
+                _ => Response::builder().body(Body::from("hello")),
+            };
+            response.unwrap()
+        }
+    });
+
+    let conf = common::config_for(port)
+        .region(Region::new("us-west-2"))
+        .build();
+    (Client::from_conf(conf), paths)
+}
+
+// The requests below carry the ARN in their bucket field unchanged; routing
+// it to the access point is up to the SDK.
+
+#[tokio::test]
+async fn test_upload_carries_the_arn() {
+    let (client, paths) = mock_s3().await;
+    let file = std::env::temp_dir().join(format!("arn-upload-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&file, b"hello").unwrap();
+
+    upload_chunk(
+        &client,
+        ACCESS_POINT,
+        "dir/a.txt",
+        file.to_str().unwrap(),
+        0,
+        5,
+        None,
+    )
+    .await
+    .unwrap();
+
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(
+        vec![format!("/{}/dir/a.txt", ACCESS_POINT)],
+        *paths.lock().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_download_carries_the_arn() {
+    let (client, paths) = mock_s3().await;
+    let file = std::env::temp_dir().join(format!("arn-download-{}", uuid::Uuid::new_v4()));
+
+    download_auto_decompress(&client, OUTPOST_ACCESS_POINT, "dir/a.txt", &file)
+        .await
+        .unwrap();
+
+    assert_eq!(b"hello".to_vec(), std::fs::read(&file).unwrap());
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(
+        vec![format!("/{}/dir/a.txt", OUTPOST_ACCESS_POINT)],
+        *paths.lock().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_list_carries_the_arn() {
+    let (client, paths) = mock_s3().await;
+
+    let listing = list_objects(&client, ACCESS_POINT, "", KeyEncoding::None)
+        .await
+        .unwrap();
+
+    assert_eq!(1, listing.objects.len());
+    assert_eq!(vec![format!("/{}", ACCESS_POINT)], *paths.lock().unwrap());
+}
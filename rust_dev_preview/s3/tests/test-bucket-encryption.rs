@@ -0,0 +1,124 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use s3_service::bucket_encryption::{bucket_default_encryption, BucketEncryption};
+use s3_service::resume::{verify_for_encryption, ResumeVerify};
+
+/// Starts a server answering GetBucketEncryption with `status` and `body`.
+async fn mock_s3(status: u16, body: String) -> Client {
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let body = body.clone();
+        async move {
+            assert_eq!(Some("encryption"), req.uri().query());
+            Response::builder()
+                .status(status)
+                .body(Body::from(body))
+                .unwrap()
+        }
+    });
+
+    common::client_for(port)
+}
+
+fn configuration(algorithm: &str, key: &str) -> String {
+    format!(
+        "<ServerSideEncryptionConfiguration><Rule><ApplyServerSideEncryptionByDefault>\
+         <SSEAlgorithm>{}</SSEAlgorithm>{}</ApplyServerSideEncryptionByDefault>\
+         <BucketKeyEnabled>true</BucketKeyEnabled></Rule></ServerSideEncryptionConfiguration>",
+        algorithm, key
+    )
+}
+
+/// The encryption read, and the verification chosen for `content`.
+async fn check(status: u16, body: String) -> (BucketEncryption, ResumeVerify, Option<String>) {
+    let client = mock_s3(status, body).await;
+    let encryption = bucket_default_encryption(&client, "bucket").await.unwrap();
+    let (verify, reason) = verify_for_encryption(ResumeVerify::Content, "bucket", &encryption);
+    (encryption, verify, reason)
+}
+
+#[tokio::test]
+async fn test_no_default_encryption_keeps_md5_verification() {
+    let (encryption, verify, reason) = check(
+        404,
+        "<Error><Code>ServerSideEncryptionConfigurationNotFoundError</Code>\
+         <Message>The server side encryption configuration was not found</Message></Error>"
+            .to_string(),
+    )
+    .await;
+
+    assert_eq!(BucketEncryption::None, encryption);
+    assert_eq!(ResumeVerify::Content, verify);
+    assert_eq!(None, reason);
+}
+
+#[tokio::test]
+async fn test_sse_s3_keeps_md5_verification() {
+    let (encryption, verify, reason) = check(200, configuration("AES256", "")).await;
+
+    assert_eq!(BucketEncryption::SseS3, encryption);
+    assert_eq!(ResumeVerify::Content, verify);
+    assert_eq!(None, reason);
+}
+
+#[tokio::test]
+async fn test_sse_kms_disables_md5_verification() {
+    let key = "arn:aws:kms:us-east-1:111122223333:key/1234abcd";
+    let (encryption, verify, reason) = check(
+        200,
+        configuration(
+            "aws:kms",
+            &format!("<KMSMasterKeyID>{}</KMSMasterKeyID>", key),
+        ),
+    )
+    .await;
+
+    assert_eq!(
+        BucketEncryption::SseKms {
+            kms_key_id: Some(key.to_string())
+        },
+        encryption
+    );
+    assert_eq!(ResumeVerify::Mtime, verify);
+    let reason = reason.unwrap();
+    assert!(reason.contains("SSE-KMS"), "{}", reason);
+    assert!(reason.contains("SHA-256"), "{}", reason);
+    assert_eq!(
+        r#"{"default_encryption":"sse_kms","kms_key_id":"arn:aws:kms:us-east-1:111122223333:key/1234abcd"}"#,
+        serde_json::to_string(&encryption).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_access_denied_leaves_the_verification_alone() {
+    let (encryption, verify, reason) = check(
+        403,
+        "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>".to_string(),
+    )
+    .await;
+
+    assert_eq!(
+        BucketEncryption::Unknown {
+            reason: "AccessDenied".to_string()
+        },
+        encryption
+    );
+    assert_eq!(ResumeVerify::Content, verify);
+    assert_eq!(None, reason);
+}
+
+#[test]
+fn test_mtime_is_unchanged_under_sse_kms() {
+    let encryption = BucketEncryption::SseKms { kms_key_id: None };
+
+    assert_eq!(
+        (ResumeVerify::Mtime, None),
+        verify_for_encryption(ResumeVerify::Mtime, "bucket", &encryption)
+    );
+}
@@ -0,0 +1,193 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::bucket_tags::{
+    get_bucket_tags, merge_bucket_tags, remove_bucket_tags, replace_bucket_tags, MAX_BUCKET_TAGS,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// What the mock bucket received and the tags it holds.
+#[derive(Debug, Default)]
+struct Tagging {
+    /// The method of each request.
+    requests: Vec<Method>,
+    /// The tag set, or `None` when the bucket has none.
+    tags: Option<BTreeMap<String, String>>,
+}
+
+/// The text of each `<name>` element in `xml`.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    xml.split(open.as_str())
+        .skip(1)
+        .map(|rest| rest.split(close.as_str()).next().unwrap())
+        .collect()
+}
+
+/// Starts a server answering the bucket tagging operations from a tag set
+/// that starts as `tags`.
+fn mock_s3(tags: Option<&[(&str, &str)]>) -> (Client, Arc<Mutex<Tagging>>) {
+    let tagging = Arc::new(Mutex::new(Tagging {
+        requests: Vec::new(),
+        tags: tags.map(|tags| {
+            tags.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        }),
+    }));
+    let store = tagging.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let store = store.clone();
+        async move {
+            assert_eq!(Some("tagging"), req.uri().query());
+            let method = req.method().clone();
+            store.lock().unwrap().requests.push(method.clone());
+            let body = String::from_utf8(
+                hyper::body::to_bytes(req.into_body())
+                    .await
+                    .unwrap()
+                    .to_vec(),
+            )
+            .unwrap();
+            let mut store = store.lock().unwrap();
+            match method {
+                Method::GET => match &store.tags {
+                    Some(tags) => {
+                        let tag_set: String = tags
+                            .iter()
+                            .map(|(key, value)| {
+                                format!("<Tag><Key>{}</Key><Value>{}</Value></Tag>", key, value)
+                            })
+                            .collect();
+                        Response::new(Body::from(format!(
+                            "<Tagging><TagSet>{}</TagSet></Tagging>",
+                            tag_set
+                        )))
+                    }
+                    None => Response::builder()
+                        .status(404)
+                        .body(Body::from(
+                            "<Error><Code>NoSuchTagSet</Code>\
+                             <Message>The TagSet does not exist</Message></Error>",
+                        ))
+                        .unwrap(),
+                },
+                Method::PUT => {
+                    let keys = elements(&body, "Key");
+                    let values = elements(&body, "Value");
+                    store.tags = Some(
+                        keys.iter()
+                            .zip(values.iter())
+                            .map(|(key, value)| (key.to_string(), value.to_string()))
+                            .collect(),
+                    );
+                    Response::new(Body::empty())
+                }
+                Method::DELETE => {
+                    store.tags = None;
+                    Response::builder().status(204).body(Body::empty()).unwrap()
+                }
+                _ => panic!("Unexpected request: {}", method),
+            }
+        }
+    });
+
+    (common::client_for(port), tagging)
+}
+
+fn tags(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_replace_with_no_tags_deletes_the_tag_set() {
+    let (client, tagging) = mock_s3(Some(&[("team", "docs")]));
+
+    replace_bucket_tags(&client, "bucket", HashMap::new())
+        .await
+        .unwrap();
+
+    let tagging = tagging.lock().unwrap();
+    assert_eq!(vec![Method::DELETE], tagging.requests);
+    assert_eq!(None, tagging.tags);
+}
+
+#[tokio::test]
+async fn test_replace_puts_only_the_given_tags() {
+    let (client, tagging) = mock_s3(Some(&[("team", "docs")]));
+
+    replace_bucket_tags(&client, "bucket", tags(&[("stage", "beta")]))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        tags(&[("stage", "beta")]),
+        get_bucket_tags(&client, "bucket").await.unwrap()
+    );
+    assert_eq!(Method::PUT, tagging.lock().unwrap().requests[0]);
+}
+
+#[tokio::test]
+async fn test_merge_keeps_existing_tags() {
+    let (client, _) = mock_s3(Some(&[("team", "docs"), ("stage", "alpha")]));
+
+    merge_bucket_tags(&client, "bucket", tags(&[("stage", "beta")]))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        tags(&[("team", "docs"), ("stage", "beta")]),
+        get_bucket_tags(&client, "bucket").await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_merge_into_a_bucket_without_tags() {
+    let (client, _) = mock_s3(None);
+
+    merge_bucket_tags(&client, "bucket", tags(&[("team", "docs")]))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        tags(&[("team", "docs")]),
+        get_bucket_tags(&client, "bucket").await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_removing_the_last_tag_deletes_the_tag_set() {
+    let (client, tagging) = mock_s3(Some(&[("team", "docs")]));
+
+    remove_bucket_tags(&client, "bucket", &["team".to_string()])
+        .await
+        .unwrap();
+
+    let tagging = tagging.lock().unwrap();
+    assert_eq!(vec![Method::GET, Method::DELETE], tagging.requests);
+    assert_eq!(None, tagging.tags);
+}
+
+#[tokio::test]
+async fn test_too_many_tags_are_rejected_before_sending() {
+    let (client, tagging) = mock_s3(None);
+    let too_many = (0..=MAX_BUCKET_TAGS)
+        .map(|i| (format!("key{}", i), "value".to_string()))
+        .collect();
+
+    assert!(replace_bucket_tags(&client, "bucket", too_many)
+        .await
+        .is_err());
+    assert!(tagging.lock().unwrap().requests.is_empty());
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use common::MockS3;
+use s3_service::checkpoint::{Checkpoint, CheckpointEntry, CheckpointedUpload};
+use s3_service::scheduler::{upload_files_with_hook, ScheduledFile, SchedulerOptions};
+use s3_service::shutdown::Shutdown;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+fn test_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("checkpoint-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn file(dir: &Path, n: usize) -> ScheduledFile {
+    ScheduledFile {
+        path: dir.join(format!("file-{}", n)),
+        key: format!("file-{}", n),
+        size: 0,
+    }
+}
+
+fn read(path: &Path) -> Checkpoint {
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn test_writes_every_n_files() {
+    let dir = test_dir();
+    let path = dir.join("checkpoint.json");
+    let checkpoint = CheckpointedUpload::new(&path, 2, 0);
+    assert_eq!(0, checkpoint.load("bucket").unwrap());
+
+    checkpoint.record(Path::new("a"), "a", "etag-a");
+    assert!(!path.exists());
+    checkpoint.record(Path::new("b"), "b", "etag-b");
+    assert_eq!(2, read(&path).uploaded.len());
+    checkpoint.record(Path::new("c"), "c", "etag-c");
+    assert_eq!(2, read(&path).uploaded.len());
+
+    checkpoint.write().unwrap();
+    let written = read(&path);
+    assert_eq!("bucket", written.bucket);
+    assert_eq!(
+        CheckpointEntry {
+            local_path: PathBuf::from("c"),
+            key: "c".to_string(),
+            e_tag: "etag-c".to_string(),
+            // There is no file "c" to take the size and time of.
+            size: None,
+            mtime: None,
+        },
+        written.uploaded[2]
+    );
+    // Only the checkpoint itself is left, without its temporary file.
+    assert_eq!(1, std::fs::read_dir(&dir).unwrap().count());
+
+    checkpoint.remove().unwrap();
+    assert!(!path.exists());
+    checkpoint.remove().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_writes_after_the_interval() {
+    let dir = test_dir();
+    let path = dir.join("checkpoint.json");
+    let checkpoint = CheckpointedUpload::new(&path, 0, 1);
+    checkpoint.load("bucket").unwrap();
+
+    checkpoint.record(Path::new("a"), "a", "etag-a");
+    assert!(!path.exists());
+    std::thread::sleep(Duration::from_millis(1100));
+    checkpoint.record(Path::new("b"), "b", "etag-b");
+    assert_eq!(2, read(&path).uploaded.len());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_load_skips_uploaded_files_in_order() {
+    let dir = test_dir();
+    let path = dir.join("checkpoint.json");
+    let files: Vec<_> = (0..5).map(|n| file(&dir, n)).collect();
+    for file in &files {
+        std::fs::write(&file.path, b"").unwrap();
+    }
+    let first = CheckpointedUpload::new(&path, 100, 0);
+    first.load("bucket").unwrap();
+    first.record(&files[3].path, &files[3].key, "etag-3");
+    first.record(&files[0].path, &files[0].key, "etag-0");
+    // The same file to another key is still uploaded.
+    first.record(&files[1].path, "elsewhere", "etag-1");
+    first.write().unwrap();
+
+    let second = CheckpointedUpload::new(&path, 100, 0);
+    assert_eq!(3, second.load("bucket").unwrap());
+    let remaining = second.remaining(files.clone());
+    assert_eq!(
+        vec![files[1].clone(), files[2].clone(), files[4].clone()],
+        remaining
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_changed_files_are_uploaded_again() {
+    let dir = test_dir();
+    let path = dir.join("checkpoint.json");
+    let files: Vec<_> = (0..3).map(|n| file(&dir, n)).collect();
+    for file in &files {
+        std::fs::write(&file.path, b"data").unwrap();
+    }
+    let first = CheckpointedUpload::new(&path, 100, 0);
+    first.load("bucket").unwrap();
+    for file in &files {
+        first.record(&file.path, &file.key, "etag");
+    }
+    first.write().unwrap();
+    let uploaded = read(&path).uploaded;
+    assert_eq!(Some(4), uploaded[0].size);
+    assert!(uploaded[0].mtime.is_some());
+
+    // Same size, later modification time.
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&files[0].path)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() + Duration::from_secs(60))
+        .unwrap();
+    // Same modification time, another size.
+    let mtime = std::fs::metadata(&files[1].path)
+        .unwrap()
+        .modified()
+        .unwrap();
+    std::fs::write(&files[1].path, b"more data").unwrap();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&files[1].path)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+
+    let second = CheckpointedUpload::new(&path, 100, 0);
+    second.load("bucket").unwrap();
+    assert_eq!(
+        vec![files[0].clone(), files[1].clone()],
+        second.remaining(files.clone())
+    );
+
+    // A checkpoint written before sizes and times were recorded skips nothing.
+    std::fs::write(
+        &path,
+        format!(
+            "{{\"bucket\": \"bucket\", \"uploaded\": [{{\"local_path\": {:?}, \
+             \"key\": \"file-2\", \"e_tag\": \"etag\"}}]}}",
+            files[2].path
+        ),
+    )
+    .unwrap();
+    let old = CheckpointedUpload::new(&path, 100, 0);
+    assert_eq!(1, old.load("bucket").unwrap());
+    assert_eq!(files, old.remaining(files.clone()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_invalid_checkpoints_are_refused() {
+    let dir = test_dir();
+    let path = dir.join("checkpoint.json");
+    let checkpoint = CheckpointedUpload::new(&path, 1, 0);
+    checkpoint.load("bucket").unwrap();
+    checkpoint.record(Path::new("a"), "a", "etag-a");
+
+    let other = CheckpointedUpload::new(&path, 1, 0);
+    let err = other.load("other-bucket").unwrap_err();
+    assert!(err.to_string().contains("is for bucket bucket"), "{}", err);
+
+    std::fs::write(&path, "{\"bucket\": \"bucket\", \"uploa").unwrap();
+    let err = other.load("bucket").unwrap_err();
+    assert!(err.to_string().contains("Invalid checkpoint"), "{}", err);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_scheduler_records_uploaded_files() {
+    let dir = test_dir();
+    let files: Vec<_> = (0..3)
+        .map(|n| {
+            let file = file(&dir, n);
+            std::fs::write(&file.path, b"data").unwrap();
+            ScheduledFile { size: 4, ..file }
+        })
+        .collect();
+    let path = dir.join("checkpoint.json");
+    let checkpoint = CheckpointedUpload::new(&path, 2, 0);
+    checkpoint.load("bucket").unwrap();
+    let ops = MockS3::new();
+
+    let summary = upload_files_with_hook(
+        &ops,
+        "bucket",
+        files,
+        &SchedulerOptions::default(),
+        &Shutdown::new(Duration::from_secs(1)),
+        &|file, e_tag| checkpoint.record(&file.path, &file.key, e_tag),
+    )
+    .await;
+
+    assert_eq!(3, summary.completed.len());
+    assert_eq!(2, read(&path).uploaded.len());
+    let mut keys: Vec<_> = checkpoint.uploaded().into_iter().map(|e| e.key).collect();
+    keys.sort();
+    assert_eq!(vec!["file-0", "file-1", "file-2"], keys);
+    assert!(checkpoint
+        .uploaded()
+        .iter()
+        .all(|entry| entry.e_tag == "mock-etag"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,73 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use s3_service::cli::{
+    parse_duration, parse_endpoint_uri, parse_endpoint_url, parse_interval, parse_key_value,
+};
+use std::time::Duration;
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(Ok(Duration::from_secs(2)), parse_duration("2s"));
+    assert_eq!(Ok(Duration::from_millis(500)), parse_duration("500ms"));
+    assert_eq!(Ok(Duration::from_secs(90)), parse_duration("1.5m"));
+    assert_eq!(Ok(Duration::from_secs(3600)), parse_duration("1h"));
+    assert_eq!(Ok(Duration::from_secs(7)), parse_duration("7"));
+}
+
+#[test]
+fn test_parse_duration_rejects_what_is_not_a_duration() {
+    for value in ["inf", "NaN", "-1s", "", "5 fortnights"] {
+        assert!(parse_duration(value).is_err(), "{}", value);
+    }
+    // Past what a `Duration` holds.
+    assert_eq!(
+        Err("Duration out of range: 99999999999999999999999d".to_string()),
+        parse_duration("99999999999999999999999d")
+    );
+}
+
+#[test]
+fn test_parse_interval_rejects_zero() {
+    assert_eq!(Ok(Duration::from_millis(500)), parse_interval("500ms"));
+    for value in ["0", "0s", "0ms"] {
+        assert!(parse_interval(value).is_err(), "{}", value);
+    }
+}
+
+#[test]
+fn test_parse_key_value() {
+    let pair = |key: &str, value: &str| Ok((key.to_string(), value.to_string()));
+    assert_eq!(pair("team", "data"), parse_key_value("team=data"));
+    assert_eq!(pair("query", "a=b"), parse_key_value("query=a=b"));
+    assert_eq!(pair("empty", ""), parse_key_value("empty="));
+    for value in ["team", "=data", ""] {
+        assert!(parse_key_value(value).is_err(), "{}", value);
+    }
+}
+
+#[test]
+fn test_parse_endpoint_url() {
+    assert_eq!(
+        Ok("http://localhost:9000".to_string()),
+        parse_endpoint_url("http://localhost:9000")
+    );
+    let uri = parse_endpoint_uri("https://s3.us-east-1.amazonaws.com").unwrap();
+    assert_eq!(Some("s3.us-east-1.amazonaws.com"), uri.host());
+}
+
+#[test]
+fn test_parse_endpoint_url_rejects_what_is_not_an_endpoint() {
+    for value in [
+        "",
+        "localhost:9000",
+        "ftp://host",
+        "http://",
+        "not a url",
+        "/path",
+    ] {
+        assert!(parse_endpoint_url(value).is_err(), "{}", value);
+    }
+}
@@ -0,0 +1,116 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use s3_service::config::{is_hashed_name, save_tuning, TransferConfig, TuningSettings};
+use s3_service::retry::RetryPolicy;
+
+const CONFIG: &str = r#"
+[headers.default]
+cache_control = "max-age=300"
+content_language = "en-US"
+
+[headers.extensions.html]
+content_type = "text/html; charset=utf-8"
+cache_control = "no-cache"
+
+[headers.extensions.JS]
+content_type = "application/javascript"
+
+[headers.hashed]
+cache_control = "public, max-age=31536000, immutable"
+"#;
+
+#[test]
+fn test_headers_by_extension() {
+    let rules = TransferConfig::from_toml(CONFIG).unwrap().headers;
+
+    let html = rules.headers_for("site/index.html");
+    assert_eq!(Some("no-cache"), html.cache_control.as_deref());
+    assert_eq!(
+        Some("text/html; charset=utf-8"),
+        html.content_type.as_deref()
+    );
+    assert_eq!(Some("en-US"), html.content_language.as_deref());
+
+    // Extensions are case insensitive; a hashed name is immutable.
+    let js = rules.headers_for("site/static/main.5d41402abc4b2a76.JS");
+    assert_eq!(Some("application/javascript"), js.content_type.as_deref());
+    assert_eq!(
+        Some("public, max-age=31536000, immutable"),
+        js.cache_control.as_deref()
+    );
+    let js = rules.headers_for("site/static/main.js");
+    assert_eq!(Some("max-age=300"), js.cache_control.as_deref());
+
+    let other = rules.headers_for("README");
+    assert_eq!(Some("max-age=300"), other.cache_control.as_deref());
+    assert_eq!(None, other.content_type);
+}
+
+#[test]
+fn test_hashed_names() {
+    assert!(is_hashed_name("main.5d41402abc4b2a76.js"));
+    assert!(is_hashed_name("app-d41d8cd98f00b204e9800998ecf8427e.css"));
+    assert!(is_hashed_name("chunk_0123abcd0123abcd0123.min.js"));
+    assert!(is_hashed_name(
+        "vendor.da39a3ee5e6b4b0d3255bfef95601890afd80709.js"
+    ));
+    assert!(!is_hashed_name("main.js"));
+    // Not a hash length, or hex letters only.
+    assert!(!is_hashed_name("main.3f2a9c1b.js"));
+    assert!(!is_hashed_name("build-20220301120000123.js"));
+    assert!(!is_hashed_name("facade-deadbeefdeadbeef.css"));
+    // A hash must follow a delimiter, and the extension itself is not one.
+    assert!(!is_hashed_name("5d41402abc4b2a76.js"));
+    assert!(!is_hashed_name("archive.5d41402abc4b2a76"));
+}
+
+#[test]
+fn test_retry_settings() {
+    let config = TransferConfig::from_toml("[retry]\nmax_attempts = 6\njitter_ms = 250\n").unwrap();
+    let policy = config.retry.apply(RetryPolicy::default());
+    assert_eq!(6, policy.max_attempts);
+    assert_eq!(250, policy.jitter_ms);
+    assert_eq!(RetryPolicy::default().base_delay_ms, policy.base_delay_ms);
+
+    assert!(TransferConfig::from_toml("[retry]\nmax_attempts = 0\n").is_err());
+    assert!(TransferConfig::from_toml("[retry]\nattempts = 3\n").is_err());
+}
+
+#[test]
+fn test_invalid_config() {
+    let err =
+        TransferConfig::from_toml("[headers.default]\nexpires = \"next week\"\n").unwrap_err();
+    assert!(err.contains("headers.default"), "{}", err);
+    assert!(TransferConfig::from_toml("[headers.hashed]\ncache = \"no-cache\"\n").is_err());
+    assert!(TransferConfig::from_toml(
+        "[headers.extensions.css]\nexpires = \"Thu, 31 Jan 2030 00:00:00 GMT\"\n"
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_save_tuning() {
+    let path = std::env::temp_dir().join(format!("config-test-{}.toml", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        "[retry]\nmax_attempts = 6\n\n[tuning]\nconcurrency = 4\n",
+    )
+    .unwrap();
+
+    save_tuning(
+        &path,
+        &TuningSettings {
+            concurrency: Some(12),
+        },
+    )
+    .unwrap();
+
+    let config = TransferConfig::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(Some(12), config.tuning.concurrency);
+    assert_eq!(Some(6), config.retry.max_attempts);
+    assert!(TransferConfig::from_toml("[tuning]\nconcurrency = 0\n").is_err());
+}
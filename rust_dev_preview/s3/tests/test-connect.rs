@@ -0,0 +1,161 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::Client;
+use hyper::service::{service_fn, Service};
+use hyper::{Body, Request, Response};
+use s3_service::connect::{build_s3_client_with_max_age, BoundConnector};
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn test_bound_connector_uses_local_address() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let uri = format!("http://{}/", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let local_addr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let mut connector = BoundConnector { local_addr };
+
+    let (stream, accepted) = tokio::join!(connector.call(uri), listener.accept());
+    let stream = stream.unwrap();
+    let (_, peer) = accepted.unwrap();
+    assert_eq!(local_addr, stream.local_addr().unwrap().ip());
+    assert_eq!(stream.local_addr().unwrap(), peer);
+}
+
+#[tokio::test]
+async fn test_bound_connector_address_not_on_host() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let uri = format!("http://{}/", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    // TEST-NET-1 is never assigned to an interface.
+    let mut connector = BoundConnector {
+        local_addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+    };
+
+    let err = connector.call(uri).await.unwrap_err();
+    assert_eq!(std::io::ErrorKind::AddrNotAvailable, err.kind());
+}
+
+/// Starts a server answering PutObject after `delay`, and returns a client
+/// whose connections last `max_age`, the number of connections accepted,
+/// and the age of the oldest connection a request arrived on.
+async fn aging_mock(
+    max_age: Duration,
+    delay: Duration,
+) -> (Client, Arc<AtomicUsize>, Arc<Mutex<Duration>>) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    let oldest_use = Arc::new(Mutex::new(Duration::ZERO));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let accepted = connections.clone();
+    let recorder = oldest_use.clone();
+    let make_service = hyper::service::make_service_fn(move |_| {
+        accepted.fetch_add(1, Ordering::SeqCst);
+        let accepted_at = Instant::now();
+        let recorder = recorder.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let recorder = recorder.clone();
+                async move {
+                    let mut oldest = recorder.lock().unwrap();
+                    *oldest = (*oldest).max(accepted_at.elapsed());
+                    drop(oldest);
+                    hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    tokio::time::sleep(delay).await;
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .header("ETag", "\"etag\"")
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                }
+            }))
+        }
+    });
+    let server = hyper::Server::from_tcp(listener)
+        .unwrap()
+        .serve(make_service);
+    tokio::spawn(server);
+
+    let conf = common::config_for(port).build();
+    (
+        build_s3_client_with_max_age(conf, max_age),
+        connections,
+        oldest_use,
+    )
+}
+
+async fn put(client: &Client) {
+    client
+        .put_object()
+        .bucket("bucket")
+        .key("a.txt")
+        .body(ByteStream::from(b"hello".to_vec()))
+        .send()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_young_connections_are_reused() {
+    let (client, connections, _) = aging_mock(Duration::from_secs(60), Duration::ZERO).await;
+
+    for _ in 0..3 {
+        put(&client).await;
+    }
+
+    assert_eq!(1, connections.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_old_connections_are_replaced() {
+    let (client, connections, _) = aging_mock(Duration::from_millis(200), Duration::ZERO).await;
+
+    put(&client).await;
+    put(&client).await;
+    assert_eq!(1, connections.load(Ordering::SeqCst));
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    put(&client).await;
+    put(&client).await;
+    assert_eq!(2, connections.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_request_in_flight_outlives_the_age() {
+    let (client, connections, _) =
+        aging_mock(Duration::from_millis(100), Duration::from_millis(300)).await;
+
+    // The connection expires while the server holds the response.
+    put(&client).await;
+    put(&client).await;
+
+    assert_eq!(2, connections.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_connections_are_not_reused_past_their_age() {
+    let max_age = Duration::from_millis(200);
+    let (client, connections, oldest_use) = aging_mock(max_age, Duration::ZERO).await;
+
+    for _ in 0..10 {
+        put(&client).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    assert!(connections.load(Ordering::SeqCst) >= 3);
+    // A request sent before the age passes arrives a little after.
+    assert!(*oldest_use.lock().unwrap() < max_age + Duration::from_millis(100));
+}
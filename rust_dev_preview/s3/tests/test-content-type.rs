@@ -0,0 +1,113 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use s3_service::content_type::{
+    detect_content_type, magic_content_type, sniff_content_type, DEFAULT_CONTENT_TYPE, SNIFF_LEN,
+};
+
+#[test]
+fn test_detect_content_type_from_extension() {
+    assert_eq!(Some("image/png"), detect_content_type("photos/cat.PNG"));
+    assert_eq!(Some("application/gzip"), detect_content_type("logs.tar.gz"));
+    assert_eq!(
+        Some("text/html"),
+        detect_content_type("C:\\site\\index.html")
+    );
+    assert_eq!(None, detect_content_type("README"));
+    assert_eq!(None, detect_content_type("dir.d/README"));
+    assert_eq!(None, detect_content_type(".profile"));
+    assert_eq!(None, detect_content_type("export.dat"));
+}
+
+#[test]
+fn test_magic_numbers() {
+    let mut tar = vec![0u8; SNIFF_LEN];
+    tar[257..262].copy_from_slice(b"ustar");
+    let cases: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", "image/png"),
+        (b"\xff\xd8\xff\xe0\0\x10JFIF", "image/jpeg"),
+        (b"GIF89a\x01\0", "image/gif"),
+        (b"RIFF\x24\0\0\0WEBPVP8 ", "image/webp"),
+        (b"RIFF\x24\0\0\0WAVEfmt ", "audio/wav"),
+        (b"II*\0\x08\0\0\0", "image/tiff"),
+        (b"\0\0\0\x1cftypavif", "image/avif"),
+        (b"\0\0\0\x18ftypmp42", "video/mp4"),
+        (b"\x1a\x45\xdf\xa3\x01\0", "video/webm"),
+        (b"ID3\x04\0\0", "audio/mpeg"),
+        (b"OggS\0\x02", "audio/ogg"),
+        (b"fLaC\0\0\0\x22", "audio/flac"),
+        (b"%PDF-1.7\n", "application/pdf"),
+        (b"PK\x03\x04\x14\0", "application/zip"),
+        (b"\x1f\x8b\x08\0", "application/gzip"),
+        (b"BZh91AY", "application/x-bzip2"),
+        (b"\xfd7zXZ\0\0", "application/x-xz"),
+        (b"\x28\xb5\x2f\xfd\x24", "application/zstd"),
+        (b"7z\xbc\xaf\x27\x1c\0\x04", "application/x-7z-compressed"),
+        (b"Rar!\x1a\x07\x01\0", "application/vnd.rar"),
+        (&tar[..], "application/x-tar"),
+        (b"SQLite format 3\0", "application/vnd.sqlite3"),
+        (b"PAR1\x15\x04", "application/vnd.apache.parquet"),
+        (b"\x7fELF\x02\x01", "application/x-elf"),
+        (b"\0asm\x01\0\0\0", "application/wasm"),
+        (b"wOF2\0\x01\0\0", "font/woff2"),
+    ];
+    for (bytes, expected) in cases.iter().copied() {
+        assert_eq!(Some(expected), magic_content_type(bytes), "{:?}", bytes);
+    }
+    assert_eq!(None, magic_content_type(b"hello, world"));
+    assert_eq!(None, magic_content_type(b""));
+    // Too short for the signature.
+    assert_eq!(None, magic_content_type(b"\x89PN"));
+}
+
+#[test]
+fn test_sniff_without_extension() {
+    assert_eq!(
+        "image/png",
+        sniff_content_type("upload-1234", b"\x89PNG\r\n\x1a\n")
+    );
+    assert_eq!("application/pdf", sniff_content_type("scan", b"%PDF-1.4"));
+    assert_eq!(
+        DEFAULT_CONTENT_TYPE,
+        sniff_content_type("blob", b"\0\x01\x02")
+    );
+    assert_eq!(DEFAULT_CONTENT_TYPE, sniff_content_type("empty", b""));
+}
+
+#[test]
+fn test_magic_number_wins_over_misleading_extension() {
+    assert_eq!(
+        "image/jpeg",
+        sniff_content_type("photo.txt", b"\xff\xd8\xff\xe1")
+    );
+    assert_eq!("text/plain", sniff_content_type("notes.txt", b"hello"));
+}
+
+#[test]
+fn test_extension_refines_containers() {
+    assert_eq!(
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        sniff_content_type("report.docx", b"PK\x03\x04\x14\0")
+    );
+    assert_eq!(
+        "image/svg+xml",
+        sniff_content_type("logo.svg", b"<?xml version=\"1.0\"?><svg")
+    );
+    assert_eq!(
+        "application/zip",
+        sniff_content_type("archive", b"PK\x03\x04\x14\0")
+    );
+}
+
+#[test]
+fn test_only_the_first_bytes_are_sniffed() {
+    let mut bytes = vec![0u8; SNIFF_LEN];
+    bytes.extend_from_slice(b"ustar");
+    let mut tar = vec![0u8; 257];
+    tar.extend_from_slice(b"ustar");
+
+    assert_eq!(DEFAULT_CONTENT_TYPE, sniff_content_type("data", &bytes));
+    assert_eq!("application/x-tar", sniff_content_type("data", &tar));
+}
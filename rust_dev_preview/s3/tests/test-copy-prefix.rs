@@ -0,0 +1,150 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::types::ByteStream;
+use hyper::{Body, Method, Request, Response};
+use s3_service::copy_prefix::{copy_prefix, CopyPrefixOptions, COPY_SOURCE_ETAG};
+use std::sync::{Arc, Mutex};
+
+const MIB: usize = 1024 * 1024;
+
+#[tokio::test]
+async fn test_skip_existing_recognizes_a_multipart_copy() {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let recorder = requests.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let path = req.uri().path().to_string();
+            recorder
+                .lock()
+                .unwrap()
+                .push((req.method().clone(), path.clone()));
+            let response = match (req.method().clone(), path.as_str()) {
+                (Method::GET, "/source") => Response::builder().body(Body::from(
+                    "<ListBucketResult><Name>source</Name><IsTruncated>false</IsTruncated>\
+                     <Contents><Key>data/large</Key><Size>100</Size>\
+                     <ETag>&quot;source-large&quot;</ETag></Contents>\
+                     <Contents><Key>data/other</Key><Size>10</Size>\
+                     <ETag>&quot;source-other&quot;</ETag></Contents></ListBucketResult>",
+                )),
+                // Copied in parts by an earlier run.
+                (Method::HEAD, "/destination/copy/large") => Response::builder()
+                    .header("Content-Length", 100)
+                    .header("ETag", "\"parts-etag-2\"")
+                    .header(
+                        format!("x-amz-meta-{}", COPY_SOURCE_ETAG).as_str(),
+                        "\"source-large\"",
+                    )
+                    .body(Body::empty()),
+                // Copied in parts from another version of the source.
+                (Method::HEAD, "/destination/copy/other") => Response::builder()
+                    .header("Content-Length", 10)
+                    .header("ETag", "\"parts-etag-1\"")
+                    .header(
+                        format!("x-amz-meta-{}", COPY_SOURCE_ETAG).as_str(),
+                        "\"older\"",
+                    )
+                    .body(Body::empty()),
+                (Method::PUT, "/destination/copy/other") => Response::builder().body(Body::from(
+                    "<CopyObjectResult><ETag>&quot;source-other&quot;</ETag></CopyObjectResult>",
+                )),
+                (method, path) => panic!("Unexpected request: {} {}", method, path),
+            };
+            response.unwrap()
+        }
+    });
+    let client = common::client_for(port);
+    let options = CopyPrefixOptions {
+        skip_existing: true,
+        ..Default::default()
+    };
+
+    let summary = copy_prefix(&client, "source", "data/", "destination", "copy/", &options)
+        .await
+        .unwrap();
+
+    assert_eq!(vec!["data/large".to_string()], summary.skipped);
+    assert_eq!(vec!["data/other".to_string()], summary.copied);
+    assert!(summary.failed.is_empty());
+    assert!(!requests
+        .lock()
+        .unwrap()
+        .contains(&(Method::PUT, "/destination/copy/large".to_string())));
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_copy_prefix_mixed_sizes() {
+    let client = common::minio_client().await;
+    let source = common::create_test_bucket(&client).await;
+    let destination = common::create_test_bucket(&client).await;
+
+    // Two objects take the CopyObject path, one is large enough for the
+    // multipart flow with the lowered threshold below.
+    let sizes = [
+        ("data/small-1", 1024),
+        ("data/small-2", MIB),
+        ("data/large", 12 * MIB),
+    ];
+    for (key, size) in sizes.iter() {
+        client
+            .put_object()
+            .bucket(&source)
+            .key(*key)
+            .metadata("origin", "test")
+            .body(ByteStream::from(vec![b'x'; *size]))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let options = CopyPrefixOptions {
+        multipart_threshold: 6 * MIB as u64,
+        part_size: 5 * MIB as u64,
+        concurrency: 2,
+        ..Default::default()
+    };
+    let summary = copy_prefix(&client, &source, "data/", &destination, "copy/", &options)
+        .await
+        .unwrap();
+    assert_eq!(3, summary.copied.len());
+    assert!(summary.failed.is_empty());
+    assert_eq!((12 * MIB + MIB + 1024) as u64, summary.bytes_copied);
+
+    for (key, size) in sizes.iter() {
+        let head = client
+            .head_object()
+            .bucket(&destination)
+            .key(key.replace("data/", "copy/"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(*size as i64, head.content_length());
+        assert_eq!(
+            Some("test"),
+            head.metadata()
+                .and_then(|m| m.get("origin"))
+                .map(|v| v.as_str())
+        );
+    }
+
+    // A second run with --skip-existing skips them all: the multipart copy
+    // has an ETag of its own, but records the one of its source.
+    let options = CopyPrefixOptions {
+        skip_existing: true,
+        ..options
+    };
+    let summary = copy_prefix(&client, &source, "data/", &destination, "copy/", &options)
+        .await
+        .unwrap();
+    assert_eq!(3, summary.skipped.len());
+    assert!(summary.copied.is_empty());
+
+    common::delete_test_bucket(&client, &source).await;
+    common::delete_test_bucket(&client, &destination).await;
+}
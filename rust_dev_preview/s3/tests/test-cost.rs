@@ -0,0 +1,74 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use s3_service::cost::{
+    estimate_monthly_cost, estimate_monthly_cost_with, CostOptions, ObjectInfo,
+    FALLBACK_PRICING_REGION,
+};
+
+const GIB: u64 = 1 << 30;
+
+fn object(size_bytes: u64, storage_class: &str) -> ObjectInfo {
+    ObjectInfo {
+        key: "key".to_string(),
+        size_bytes,
+        storage_class: storage_class.to_string(),
+    }
+}
+
+fn close(expected: f64, actual: f64) -> bool {
+    (expected - actual).abs() < 1e-9
+}
+
+#[test]
+fn test_estimate_by_storage_class() {
+    let objects = vec![
+        object(100 * GIB, "STANDARD"),
+        object(1000 * GIB, "DEEP_ARCHIVE"),
+        // Billed as 128 KiB.
+        object(1024, "STANDARD_IA"),
+    ];
+    let estimate = estimate_monthly_cost(&objects, "us-east-1");
+    assert_eq!("us-east-1", estimate.pricing_region);
+    assert_eq!(3, estimate.by_storage_class.len());
+    assert!(close(2.3, estimate.by_storage_class["STANDARD"].storage));
+    assert!(close(
+        0.99,
+        estimate.by_storage_class["DEEP_ARCHIVE"].storage
+    ));
+    assert!(close(
+        128.0 / (1024.0 * 1024.0) * 0.0125,
+        estimate.by_storage_class["STANDARD_IA"].storage
+    ));
+    assert_eq!(0.0, estimate.data_transfer);
+    assert!(close(estimate.storage + estimate.requests, estimate.total));
+}
+
+#[test]
+fn test_requests_and_transfer() {
+    let objects = (0..1000)
+        .map(|_| object(GIB / 1000, "STANDARD"))
+        .collect::<Vec<_>>();
+    let options = CostOptions {
+        gets_per_object_per_month: 10.0,
+        puts_per_object_per_month: 1.0,
+        transfer_out_fraction: 0.5,
+    };
+    let estimate = estimate_monthly_cost_with(&objects, "us-west-2", &options);
+    // 10,000 GETs and 1,000 PUTs.
+    assert!(close(10.0 * 0.0004 + 0.005, estimate.requests));
+    assert!(close(
+        (GIB / 1000 * 1000) as f64 / GIB as f64 * 0.5 * 0.09,
+        estimate.data_transfer
+    ));
+}
+
+#[test]
+fn test_unknown_region_and_class() {
+    let estimate = estimate_monthly_cost(&[object(GIB, "OUTPOSTS")], "xx-nowhere-1");
+    assert_eq!(FALLBACK_PRICING_REGION, estimate.pricing_region);
+    assert_eq!(vec!["OUTPOSTS"], estimate.unknown_storage_classes);
+    assert!(close(0.023, estimate.storage));
+}
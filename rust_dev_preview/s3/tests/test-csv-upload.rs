@@ -0,0 +1,88 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use s3_service::csv_upload::{upload_csv_validated, ColumnType, CsvSchema};
+
+fn schema() -> CsvSchema {
+    CsvSchema::new()
+        .column("name", ColumnType::String)
+        .column("count", ColumnType::Integer)
+        .column("day", ColumnType::Date)
+}
+
+#[tokio::test]
+async fn test_invalid_rows_are_dropped() {
+    let (client, uploads) = common::mock_uploads();
+    let csv = "name,count,day\n\
+               apples,3,2021-06-01\n\
+               pears,many,2021-06-02\n\
+               \"plums, red\",,2021-06-03\n\
+               figs,1,June 4\n";
+
+    let report = upload_csv_validated(&client, "bucket", "fruit.csv", csv.as_bytes(), schema())
+        .await
+        .unwrap();
+
+    assert_eq!(4, report.total_rows);
+    assert_eq!(2, report.valid_rows);
+    assert_eq!(2, report.error_rows);
+    assert!(
+        report.sample_errors[0].starts_with("line 3: "),
+        "{:?}",
+        report
+    );
+    assert!(
+        report.sample_errors[1].starts_with("line 5: "),
+        "{:?}",
+        report
+    );
+    // The fields that need it are quoted again.
+    assert_eq!(
+        "name,count,day\napples,3,2021-06-01\n\"plums, red\",,2021-06-03\n".as_bytes(),
+        &uploads.lock().unwrap().objects["fruit.csv"][..]
+    );
+}
+
+#[tokio::test]
+async fn test_errors_name_the_line_a_row_starts_on() {
+    let (client, _) = common::mock_uploads();
+    // The quoted name spans lines 2 to 4.
+    let csv = "name,count,day\n\
+               \"a\nlong\nname\",1,2021-06-01\n\
+               short,x,2021-06-02\n\
+               wrong,number,of,fields\n";
+
+    let report = upload_csv_validated(&client, "bucket", "key.csv", csv.as_bytes(), schema())
+        .await
+        .unwrap();
+
+    assert_eq!(3, report.total_rows);
+    assert_eq!(1, report.valid_rows);
+    assert!(
+        report.sample_errors[0].starts_with("line 5: "),
+        "{:?}",
+        report
+    );
+    assert!(
+        report.sample_errors[1].starts_with("line 6: "),
+        "{:?}",
+        report
+    );
+}
+
+#[tokio::test]
+async fn test_header_must_match_the_schema() {
+    let (client, uploads) = common::mock_uploads();
+    let csv = "name,total,day\napples,3,2021-06-01\n";
+
+    let err = upload_csv_validated(&client, "bucket", "key.csv", csv.as_bytes(), schema())
+        .await
+        .unwrap_err();
+
+    assert!(err.to_string().contains("does not match"), "{}", err);
+    assert!(uploads.lock().unwrap().requests.is_empty());
+}
@@ -0,0 +1,190 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+#![cfg(feature = "debug-tools")]
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::debug_schedule::{
+    parse_delays, upload_multipart_parallel_with_schedule, DebugSchedule,
+};
+use s3_service::retry::RetryPolicy;
+use s3_service::upload::PartEvent;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Starts a server that answers multipart uploads and returns its client and
+/// the part numbers in the order their uploads arrived.
+async fn mock_server() -> (Client, Arc<Mutex<Vec<i32>>>) {
+    let parts = Arc::new(Mutex::new(Vec::new()));
+    let recorder = parts.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let response = if method == Method::PUT {
+                let part_number = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("partNumber="))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                recorder.lock().unwrap().push(part_number);
+                Response::builder()
+                    .header("ETag", "\"part-etag\"")
+                    .body(Body::empty())
+            } else if query.contains("uploads") {
+                Response::builder().body(Body::from(
+                    "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                ))
+            } else {
+                Response::builder().body(Body::from(
+                    "<CompleteMultipartUploadResult><ETag>\"complete-etag\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                ))
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), parts)
+}
+
+fn test_file(size: usize) -> String {
+    let path = std::env::temp_dir().join(format!("schedule-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![b'x'; size]).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn test_parse_schedule() {
+    let schedule: DebugSchedule = "seed=42".parse().unwrap();
+    assert_eq!(42, schedule.seed);
+    let schedule: DebugSchedule = "seed=7, concurrency=2".parse().unwrap();
+    assert_eq!((7, 2), (schedule.seed, schedule.concurrency));
+    assert!("concurrency=2".parse::<DebugSchedule>().is_err());
+    assert!("seed=x".parse::<DebugSchedule>().is_err());
+    assert!("seed=1,concurrency=0".parse::<DebugSchedule>().is_err());
+    assert!("seed=1,order=2".parse::<DebugSchedule>().is_err());
+}
+
+#[test]
+fn test_dispatch_order_is_seeded() {
+    let schedule: DebugSchedule = "seed=42".parse().unwrap();
+    let order = schedule.dispatch_order(20);
+    assert_eq!(order, schedule.dispatch_order(20));
+    let mut sorted = order.clone();
+    sorted.sort_unstable();
+    assert_eq!((0..20).collect::<Vec<_>>(), sorted);
+    let other: DebugSchedule = "seed=43".parse().unwrap();
+    assert_ne!(order, other.dispatch_order(20));
+}
+
+#[test]
+fn test_parse_delays() {
+    let delays = parse_delays(
+        r#"
+        [[part]]
+        number = 3
+        delay = "250ms"
+
+        [[part]]
+        number = 1
+        delay = "2s"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(Some(&Duration::from_millis(250)), delays.get(&3));
+    assert_eq!(Some(&Duration::from_secs(2)), delays.get(&1));
+    assert!(parse_delays("[[part]]\nnumber = 1\ndelay = \"soon\"").is_err());
+}
+
+#[tokio::test]
+async fn test_parts_run_in_seeded_order() {
+    let (client, parts) = mock_server().await;
+    let file = test_file(8000);
+    let schedule: DebugSchedule = "seed=42,concurrency=1".parse().unwrap();
+
+    let e_tag = upload_multipart_parallel_with_schedule(
+        &client,
+        "bucket",
+        "key",
+        &file,
+        8,
+        None,
+        &RetryPolicy::default(),
+        &schedule,
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!("complete-etag", e_tag);
+    let expected: Vec<i32> = schedule
+        .dispatch_order(8)
+        .into_iter()
+        .map(|i| (i + 1) as i32)
+        .collect();
+    assert_eq!(expected, *parts.lock().unwrap());
+    // One part at a time: each finishes before the next starts.
+    let events = schedule.events();
+    for (pair, part) in events.chunks(2).zip(&expected) {
+        assert_eq!(
+            vec![
+                PartEvent::Dispatched { part: *part },
+                PartEvent::Completed {
+                    part: *part,
+                    ok: true
+                }
+            ],
+            pair
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_delays_reorder_completions() {
+    let (client, _) = mock_server().await;
+    let file = test_file(2000);
+    let mut schedule: DebugSchedule = "seed=1,concurrency=2".parse().unwrap();
+    let order = schedule.dispatch_order(2);
+    let (first, second) = ((order[0] + 1) as i32, (order[1] + 1) as i32);
+    schedule.delays.insert(first, Duration::from_millis(200));
+
+    upload_multipart_parallel_with_schedule(
+        &client,
+        "bucket",
+        "key",
+        &file,
+        2,
+        None,
+        &RetryPolicy::default(),
+        &schedule,
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(
+        vec![
+            PartEvent::Dispatched { part: first },
+            PartEvent::Dispatched { part: second },
+            PartEvent::Completed {
+                part: second,
+                ok: true
+            },
+            PartEvent::Completed {
+                part: first,
+                ok: true
+            },
+        ],
+        schedule.events()
+    );
+}
@@ -0,0 +1,155 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::dir_marker::{
+    check_upload_key, dir_marker_key, is_dir_marker, make_dir_marker, DIRECTORY_CONTENT_TYPE,
+};
+use s3_service::download::download_prefix;
+use s3_service::listing::{list_objects, KeyEncoding};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// The body and Content-Type of each object.
+type Store = BTreeMap<String, (Vec<u8>, Option<String>)>;
+
+/// Starts a server keeping objects in memory, answering PutObject,
+/// GetObject, and ListObjectsV2.
+async fn mock_s3(store: Store) -> (Client, Arc<Mutex<Store>>) {
+    let store = Arc::new(Mutex::new(store));
+    let shared = store.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let store = shared.clone();
+        async move {
+            let method = req.method().clone();
+            let key = req.uri().path().trim_start_matches("/bucket/").to_string();
+            let content_type = req
+                .headers()
+                .get("Content-Type")
+                .map(|value| value.to_str().unwrap().to_string());
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let mut store = store.lock().unwrap();
+            let response = if method == Method::PUT {
+                store.insert(key, (body.to_vec(), content_type));
+                Response::builder()
+                    .header("ETag", "\"etag\"")
+                    .body(Body::empty())
+            } else if key == "/bucket" {
+                let contents: String = store
+                    .iter()
+                    .map(|(key, (body, _))| {
+                        format!(
+                            "<Contents><Key>{}</Key><Size>{}</Size></Contents>",
+                            key,
+                            body.len()
+                        )
+                    })
+                    .collect();
+                Response::builder().body(Body::from(format!(
+                    "<ListBucketResult><Name>bucket</Name>\
+                     <IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                    contents
+                )))
+            } else {
+                let (body, _) = store.get(&key).unwrap();
+                Response::builder()
+                    .header("Content-Length", body.len())
+                    .body(Body::from(body.clone()))
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), store)
+}
+
+/// A folder created in the console, with a file in it, and an empty one.
+fn console_folders() -> Store {
+    let mut store = Store::new();
+    store.insert("photos/".to_string(), (Vec::new(), None));
+    store.insert("photos/a.jpg".to_string(), (b"jpeg".to_vec(), None));
+    store.insert("photos/empty/".to_string(), (Vec::new(), None));
+    store
+}
+
+#[test]
+fn test_trailing_slash_needs_the_flag() {
+    assert!(check_upload_key("photos/a.jpg", false).is_ok());
+    let err = check_upload_key("photos/", false).unwrap_err();
+    assert!(err.to_string().contains("--allow-dir-marker"), "{}", err);
+    assert!(check_upload_key("photos/", true).is_ok());
+}
+
+#[test]
+fn test_marker_keys() {
+    assert!(is_dir_marker("photos/"));
+    assert!(!is_dir_marker("photos"));
+    assert_eq!("photos/", dir_marker_key("photos"));
+    assert_eq!("photos/2021/", dir_marker_key("photos/2021/"));
+}
+
+#[tokio::test]
+async fn test_make_dir_creates_an_empty_directory_object() {
+    let (client, store) = mock_s3(Store::new()).await;
+
+    let key = make_dir_marker(&client, "bucket", "photos/2021")
+        .await
+        .unwrap();
+
+    assert_eq!("photos/2021/", key);
+    let store = store.lock().unwrap();
+    assert_eq!(
+        Some(&(Vec::new(), Some(DIRECTORY_CONTENT_TYPE.to_string()))),
+        store.get("photos/2021/")
+    );
+}
+
+#[tokio::test]
+async fn test_make_dir_needs_a_name() {
+    let (client, store) = mock_s3(Store::new()).await;
+
+    assert!(make_dir_marker(&client, "bucket", "").await.is_err());
+    assert!(store.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_listing_shows_markers_as_empty_objects() {
+    let (client, _) = mock_s3(console_folders()).await;
+
+    let listing = list_objects(&client, "bucket", "photos/", KeyEncoding::None)
+        .await
+        .unwrap();
+
+    let listed: Vec<(&str, u64)> = listing
+        .objects
+        .iter()
+        .map(|object| (object.key.as_str(), object.size))
+        .collect();
+    assert_eq!(
+        vec![("photos/", 0), ("photos/a.jpg", 4), ("photos/empty/", 0)],
+        listed
+    );
+    assert_eq!("photos/\nphotos/a.jpg\nphotos/empty/\n", listing.to_text());
+}
+
+#[tokio::test]
+async fn test_prefix_download_creates_directories_for_markers() {
+    let (client, _) = mock_s3(console_folders()).await;
+    let dir = std::env::temp_dir().join(format!("dir-marker-{}", uuid::Uuid::new_v4()));
+
+    let summary = download_prefix(&client, "bucket", "photos/", &dir, false)
+        .await
+        .unwrap();
+
+    assert_eq!(1, summary.files);
+    assert_eq!(2, summary.directories);
+    assert_eq!(b"jpeg".to_vec(), std::fs::read(dir.join("a.jpg")).unwrap());
+    assert!(dir.join("empty").is_dir());
+    assert_eq!(2, std::fs::read_dir(&dir).unwrap().count());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
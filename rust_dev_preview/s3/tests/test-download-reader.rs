@@ -0,0 +1,135 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::download_reader::{download_multipart_reader, OrderedReadOptions};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+const PART_SIZE: u64 = 1000;
+
+fn object(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// The first byte of each range requested, and of each range answered, in
+/// the order they happened.
+#[derive(Debug, Default)]
+struct Log {
+    requested: Vec<usize>,
+    answered: Vec<usize>,
+}
+
+/// Starts a server holding `data` as `bucket/key`, answering HeadObject and
+/// ranged GetObject requests, and holding the range at offset 0 back for
+/// `first_delay`.
+async fn mock_s3(data: Vec<u8>, first_delay: Duration) -> (Client, Arc<Mutex<Log>>) {
+    let data = Arc::new(data);
+    let log = Arc::new(Mutex::new(Log::default()));
+    let recorder = log.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let data = data.clone();
+        let log = recorder.clone();
+        async move {
+            if req.method() == Method::HEAD {
+                return Response::builder()
+                    .header("Content-Length", data.len())
+                    .header("ETag", "\"object-etag\"")
+                    .body(Body::empty())
+                    .unwrap();
+            }
+            let range = req.headers()["range"].to_str().unwrap().to_string();
+            let bounds = range.trim_start_matches("bytes=");
+            let (first, last) = bounds.split_at(bounds.find('-').unwrap());
+            let first: usize = first.parse().unwrap();
+            let last: usize = last[1..].parse().unwrap();
+            log.lock().unwrap().requested.push(first);
+            if first == 0 {
+                tokio::time::sleep(first_delay).await;
+            }
+            log.lock().unwrap().answered.push(first);
+            Response::builder()
+                .status(206)
+                .body(Body::from(data[first..=last].to_vec()))
+                .unwrap()
+        }
+    });
+
+    (common::client_for(port), log)
+}
+
+fn options(concurrency: usize, max_buffered_parts: usize) -> OrderedReadOptions {
+    OrderedReadOptions {
+        part_size: PART_SIZE,
+        concurrency,
+        max_buffered_parts,
+    }
+}
+
+#[tokio::test]
+async fn test_reads_the_object_in_order() {
+    let data = object(7 * PART_SIZE as usize + 123);
+    let (client, log) = mock_s3(data.clone(), Duration::ZERO).await;
+
+    let mut reader = download_multipart_reader(&client, "bucket", "key", &options(3, 4));
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).await.unwrap();
+
+    assert_eq!(data, read);
+    assert_eq!(8, log.lock().unwrap().requested.len());
+}
+
+#[tokio::test]
+async fn test_slowest_first_range_is_still_read_first() {
+    let data = object(6 * PART_SIZE as usize);
+    let (client, log) = mock_s3(data.clone(), Duration::from_millis(300)).await;
+
+    let mut reader = download_multipart_reader(&client, "bucket", "key", &options(4, 4));
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).await.unwrap();
+
+    assert_eq!(data, read);
+    let log = log.lock().unwrap();
+    // The other three ranges of the window were answered while the first one
+    // was held back, and nothing past the window was requested until it was.
+    assert_eq!(0, log.answered[3], "{:?}", log);
+    assert_eq!(vec![4000, 5000], log.answered[4..].to_vec(), "{:?}", log);
+}
+
+#[tokio::test]
+async fn test_full_window_stops_the_fetchers() {
+    let data = object(10 * PART_SIZE as usize);
+    let (client, log) = mock_s3(data.clone(), Duration::ZERO).await;
+
+    let mut reader = download_multipart_reader(&client, "bucket", "key", &options(2, 3));
+    let mut first = vec![0; PART_SIZE as usize];
+    reader.read_exact(&mut first).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // The first range was read, and at most a window of three is ahead.
+    let requested = log.lock().unwrap().requested.len();
+    assert!(requested <= 4, "{} ranges requested", requested);
+    assert_eq!(&data[..PART_SIZE as usize], &first[..]);
+
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).await.unwrap();
+    assert_eq!(&data[PART_SIZE as usize..], &rest[..]);
+}
+
+#[tokio::test]
+async fn test_empty_object() {
+    let (client, log) = mock_s3(Vec::new(), Duration::ZERO).await;
+
+    let mut reader = download_multipart_reader(&client, "bucket", "key", &options(2, 2));
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).await.unwrap();
+
+    assert!(read.is_empty());
+    assert!(log.lock().unwrap().requested.is_empty());
+}
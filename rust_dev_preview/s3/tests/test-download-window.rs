@@ -0,0 +1,198 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::download::download_into_window_with_split;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn object(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// How the mock answers GetObject.
+#[derive(Clone, Copy)]
+struct Behavior {
+    /// Answer 200 with the whole object, whatever the Range.
+    ignore_range: bool,
+    /// The time to the first byte of each response.
+    latency: Duration,
+}
+
+impl Default for Behavior {
+    fn default() -> Self {
+        Self {
+            ignore_range: false,
+            latency: Duration::ZERO,
+        }
+    }
+}
+
+/// The GetObject requests the mock received.
+#[derive(Default)]
+struct Gets {
+    count: AtomicUsize,
+    in_flight: AtomicUsize,
+    /// The most requests answered at the same time.
+    max_in_flight: AtomicUsize,
+}
+
+/// Starts a server holding `data` as `bucket/key`, answering HeadObject and
+/// GetObject as `behavior` says, and counting the GetObject requests.
+async fn mock_s3(data: Vec<u8>, behavior: Behavior) -> (Client, Arc<Gets>) {
+    let data = Arc::new(data);
+    let gets = Arc::new(Gets::default());
+    let counter = gets.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let data = data.clone();
+        let counter = counter.clone();
+        async move {
+            if req.method() == Method::HEAD {
+                return Response::builder()
+                    .header("Content-Length", data.len())
+                    .header("ETag", "\"object-etag\"")
+                    .body(Body::empty())
+                    .unwrap();
+            }
+            counter.count.fetch_add(1, Ordering::SeqCst);
+            let in_flight = counter.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            counter.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            let range = req
+                .headers()
+                .get("range")
+                .map(|value| value.to_str().unwrap().to_string());
+            let (status, body) = match range {
+                Some(range) if !behavior.ignore_range => {
+                    let bounds = range.trim_start_matches("bytes=");
+                    let (first, last) = bounds.split_at(bounds.find('-').unwrap());
+                    let first: usize = first.parse().unwrap();
+                    let last: usize = last[1..].parse().unwrap();
+                    (206, data[first..=last].to_vec())
+                }
+                _ => (200, data.to_vec()),
+            };
+            tokio::time::sleep(behavior.latency).await;
+            counter.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Response::builder()
+                .status(status)
+                .body(Body::from(body))
+                .unwrap()
+        }
+    });
+
+    (common::client_for(port), gets)
+}
+
+/// A file of `len` bytes of `fill`.
+fn test_file(len: usize, fill: u8) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("download-window-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![fill; len]).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn test_split_matches_a_single_request() {
+    let data = object(10_000);
+    let (client, gets) = mock_s3(data.clone(), Behavior::default()).await;
+    let single = test_file(12_000, b'x');
+    let split = test_file(12_000, b'x');
+
+    let expected = download_into_window_with_split(&client, "bucket", "key", &single, 1000, 1)
+        .await
+        .unwrap();
+    assert_eq!(1, gets.count.load(Ordering::SeqCst));
+    let result = download_into_window_with_split(&client, "bucket", "key", &split, 1000, 4)
+        .await
+        .unwrap();
+
+    assert_eq!(5, gets.count.load(Ordering::SeqCst));
+    assert_eq!(expected.sha256, result.sha256);
+    assert_eq!(1000, result.window.offset);
+    assert_eq!(10_000, result.window.length);
+    let written = std::fs::read(&split).unwrap();
+    assert_eq!(std::fs::read(&single).unwrap(), written);
+    assert_eq!(&data[..], &written[1000..11_000]);
+    assert!(written[..1000].iter().all(|byte| *byte == b'x'));
+    assert!(written[11_000..].iter().all(|byte| *byte == b'x'));
+    std::fs::remove_file(&single).unwrap();
+    std::fs::remove_file(&split).unwrap();
+}
+
+#[tokio::test]
+async fn test_object_smaller_than_the_split() {
+    let data = object(5);
+    let (client, gets) = mock_s3(data.clone(), Behavior::default()).await;
+    let path = test_file(5, b'x');
+
+    download_into_window_with_split(&client, "bucket", "key", &path, 0, 8)
+        .await
+        .unwrap();
+
+    assert_eq!(5, gets.count.load(Ordering::SeqCst));
+    assert_eq!(data, std::fs::read(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_empty_object_sends_no_get() {
+    let (client, gets) = mock_s3(Vec::new(), Behavior::default()).await;
+    let path = test_file(10, b'x');
+
+    let result = download_into_window_with_split(&client, "bucket", "key", &path, 0, 4)
+        .await
+        .unwrap();
+
+    assert_eq!(0, gets.count.load(Ordering::SeqCst));
+    assert_eq!(0, result.window.length);
+    assert_eq!(vec![b'x'; 10], std::fs::read(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_ignored_range_fails_without_writing() {
+    let behavior = Behavior {
+        ignore_range: true,
+        ..Behavior::default()
+    };
+    let (client, _) = mock_s3(object(10_000), behavior).await;
+    let path = test_file(10_000, b'x');
+
+    let err = download_into_window_with_split(&client, "bucket", "key", &path, 0, 4)
+        .await
+        .unwrap_err();
+
+    assert!(err.to_string().contains("ignores Range"), "{}", err);
+    assert_eq!(vec![b'x'; 10_000], std::fs::read(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_split_overlaps_its_requests() {
+    // Long enough for the ranges to be fetched while the first is pending.
+    let behavior = Behavior {
+        latency: Duration::from_millis(50),
+        ..Behavior::default()
+    };
+    let (client, gets) = mock_s3(object(100_000), behavior).await;
+    let path = test_file(100_000, b'x');
+
+    download_into_window_with_split(&client, "bucket", "key", &path, 0, 1)
+        .await
+        .unwrap();
+    assert_eq!(1, gets.max_in_flight.load(Ordering::SeqCst));
+
+    download_into_window_with_split(&client, "bucket", "key", &path, 0, 8)
+        .await
+        .unwrap();
+    assert_eq!(9, gets.count.load(Ordering::SeqCst));
+    assert!(gets.max_in_flight.load(Ordering::SeqCst) > 1);
+    assert!(gets.max_in_flight.load(Ordering::SeqCst) <= 8);
+    std::fs::remove_file(&path).unwrap();
+}
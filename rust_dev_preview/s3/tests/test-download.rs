@@ -0,0 +1,96 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use async_compression::tokio::write::GzipEncoder;
+use aws_sdk_s3::types::ByteStream;
+use hyper::{Body, Request, Response};
+use s3_service::download::download_auto_decompress;
+use tokio::io::AsyncWriteExt;
+
+async fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzipEncoder::new(Vec::new());
+    encoder.write_all(data).await.unwrap();
+    encoder.shutdown().await.unwrap();
+    encoder.into_inner()
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_download_gzip_round_trip() {
+    let client = common::minio_client().await;
+    let bucket = common::create_test_bucket(&client).await;
+    let dir = std::env::temp_dir().join(format!("download-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let data = "id,name\n".to_string() + &"1,example\n".repeat(10_000);
+    let compressed = gzip(data.as_bytes()).await;
+
+    // Detected by extension: the .gz suffix is dropped from the file name.
+    client
+        .put_object()
+        .bucket(&bucket)
+        .key("data.csv.gz")
+        .body(ByteStream::from(compressed.clone()))
+        .send()
+        .await
+        .unwrap();
+    let result =
+        download_auto_decompress(&client, &bucket, "data.csv.gz", &dir.join("data.csv.gz"))
+            .await
+            .unwrap();
+    assert_eq!(dir.join("data.csv"), result.path);
+    assert_eq!(compressed.len() as u64, result.compressed_bytes);
+    assert_eq!(data.len() as u64, result.decompressed_bytes);
+    assert!(result.compression_ratio > 1.0);
+    assert_eq!(data, std::fs::read_to_string(&result.path).unwrap());
+
+    // Detected by Content-Encoding: the file name is kept.
+    client
+        .put_object()
+        .bucket(&bucket)
+        .key("encoded.csv")
+        .content_encoding("gzip")
+        .body(ByteStream::from(compressed))
+        .send()
+        .await
+        .unwrap();
+    let result =
+        download_auto_decompress(&client, &bucket, "encoded.csv", &dir.join("encoded.csv"))
+            .await
+            .unwrap();
+    assert_eq!(dir.join("encoded.csv"), result.path);
+    assert_eq!(data, std::fs::read_to_string(&result.path).unwrap());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    common::delete_test_bucket(&client, &bucket).await;
+}
+
+#[tokio::test]
+async fn test_download_strips_only_one_gz_suffix() {
+    let data = "id,name\n1,example\n";
+    let compressed = gzip(data.as_bytes()).await;
+    let port = common::mock_s3(move |_req: Request<Body>| {
+        let compressed = compressed.clone();
+        async move { Response::new(Body::from(compressed)) }
+    });
+    let client = common::client_for(port);
+    let dir = std::env::temp_dir().join(format!("download-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let result = download_auto_decompress(
+        &client,
+        "bucket",
+        "data.csv.gz.gz",
+        &dir.join("data.csv.gz.gz"),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(dir.join("data.csv.gz"), result.path);
+    assert_eq!(data, std::fs::read_to_string(&result.path).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,122 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use futures::future::BoxFuture;
+use s3_service::durable::{part_path, write_file, FsyncOptions, SyncFile, SyncingWriter};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+
+/// A file that records the calls made on it.
+#[derive(Default)]
+struct MockFile {
+    data: Vec<u8>,
+    calls: Vec<&'static str>,
+}
+
+impl AsyncWrite for MockFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.data.extend_from_slice(buf);
+        self.calls.push("write");
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.calls.push("flush");
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl SyncFile for MockFile {
+    fn sync_all(&mut self) -> BoxFuture<'_, std::io::Result<()>> {
+        self.calls.push("fsync");
+        Box::pin(async { Ok(()) })
+    }
+}
+
+async fn write_chunks(options: FsyncOptions) -> (MockFile, u64) {
+    let mut writer = SyncingWriter::new(MockFile::default(), options);
+    for chunk in [b"abcde", b"fghij", b"klmno", b"pqrst", b"uvwxy"].iter() {
+        writer.write_all(*chunk).await.unwrap();
+    }
+    let (file, stats) = writer.finish().await.unwrap();
+    assert_eq!(b"abcdefghijklmnopqrstuvwxy".to_vec(), file.data);
+    (file, stats.syncs)
+}
+
+#[tokio::test]
+async fn test_no_fsync_by_default() {
+    let (file, syncs) = write_chunks(FsyncOptions::default()).await;
+    assert_eq!(0, syncs);
+    assert!(!file.calls.contains(&"fsync"));
+    assert_eq!(Some(&"flush"), file.calls.last());
+}
+
+#[tokio::test]
+async fn test_fsync_at_the_end() {
+    let (file, syncs) = write_chunks(FsyncOptions {
+        enabled: true,
+        interval: None,
+    })
+    .await;
+    assert_eq!(1, syncs);
+    // The data is flushed to the file before it is synced.
+    assert_eq!(["flush", "fsync"], file.calls[file.calls.len() - 2..]);
+    assert_eq!(1, file.calls.iter().filter(|c| **c == "fsync").count());
+}
+
+#[tokio::test]
+async fn test_fsync_interval() {
+    let (file, syncs) = write_chunks(FsyncOptions {
+        enabled: true,
+        interval: Some(10),
+    })
+    .await;
+    // After 10 and 20 bytes, and at the end.
+    assert_eq!(3, syncs);
+    let calls = file
+        .calls
+        .iter()
+        .filter(|c| **c != "flush")
+        .copied()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec!["write", "write", "fsync", "write", "write", "fsync", "write", "fsync"],
+        calls
+    );
+}
+
+#[tokio::test]
+async fn test_write_file_renames_part_file() {
+    let dir = std::env::temp_dir().join(format!("durable-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("file.bin");
+    assert_eq!(dir.join("file.bin.part"), part_path(&path));
+
+    let options = FsyncOptions {
+        enabled: true,
+        interval: None,
+    };
+    let (bytes, stats) = write_file(&mut &b"content"[..], &path, &options)
+        .await
+        .unwrap();
+    let content = std::fs::read(&path).unwrap();
+    let part_exists = part_path(&path).exists();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(7, bytes);
+    assert_eq!(b"content".to_vec(), content);
+    assert!(!part_exists);
+    // The file and, on Unix, its directory.
+    assert_eq!(if cfg!(unix) { 2 } else { 1 }, stats.syncs);
+}
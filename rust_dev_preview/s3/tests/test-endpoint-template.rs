@@ -0,0 +1,276 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_endpoint::ResolveAwsEndpoint;
+use aws_sdk_s3::{Client, Credentials, Region, RetryConfig};
+use aws_smithy_client::hyper_ext;
+use aws_types::credentials::SharedCredentialsProvider;
+use common::LoopbackConnector;
+use hyper::{Body, Method, Request, Response};
+use s3_service::download::download_prefix;
+use s3_service::endpoint_template::{
+    EndpointTemplate, TemplateResolver, TemplateStyle, VirtualHosted,
+};
+use s3_service::upload::upload_chunk;
+use std::sync::{Arc, Mutex};
+
+/// The method, Host header, path and query, and Authorization header of
+/// each request.
+type Captured = Arc<Mutex<Vec<(Method, String, String, String)>>>;
+
+const LISTING: &str = "<ListBucketResult><IsTruncated>false</IsTruncated>\
+    <Contents><Key>a.txt</Key><LastModified>2022-03-01T12:00:00.000Z</LastModified>\
+    <ETag>\"etag\"</ETag><Size>5</Size></Contents></ListBucketResult>";
+
+/// Starts a server that answers PutObject, ListObjectsV2 with one object,
+/// and GetObject, and returns its port.
+fn gateway(captured: Captured) -> u16 {
+    common::mock_s3(move |req: Request<Body>| {
+        let captured = captured.clone();
+        async move {
+            let method = req.method().clone();
+            let host = req
+                .headers()
+                .get("host")
+                .and_then(|host| host.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            let path = req.uri().path_and_query().unwrap().to_string();
+            let authorization = req
+                .headers()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            captured
+                .lock()
+                .unwrap()
+                .push((method.clone(), host, path.clone(), authorization));
+            hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let body = if method == Method::GET && path.contains("list-type=2") {
+                LISTING
+            } else if method == Method::GET {
+                "hello"
+            } else {
+                ""
+            };
+            Response::builder()
+                .header("ETag", "\"etag\"")
+                .body(Body::from(body))
+                .unwrap()
+        }
+    })
+}
+
+fn config(template: &EndpointTemplate) -> aws_sdk_s3::Config {
+    aws_sdk_s3::Config::builder()
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("access", "secret", None, None, "test"))
+        .endpoint_resolver(TemplateResolver::new(template.clone()))
+        .retry_config(RetryConfig::disabled())
+        .build()
+}
+
+/// A client sending the requests of a host template to the server on
+/// `port`, whatever the host name.
+fn virtual_hosted_client(template: &EndpointTemplate, port: u16) -> Client {
+    let connector = VirtualHosted::new(
+        hyper_ext::Adapter::builder().build(LoopbackConnector(port)),
+        Some(template.clone()),
+        Some(SharedCredentialsProvider::new(Credentials::new(
+            "access", "secret", None, None, "test",
+        ))),
+    );
+    Client::from_conf_conn(config(template), connector)
+}
+
+/// Uploads, lists, and downloads `a.txt` in `bucket`.
+async fn round_trip(client: &Client, bucket: &str) {
+    let file = std::env::temp_dir().join(format!("template-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&file, b"hello").unwrap();
+    upload_chunk(client, bucket, "a.txt", file.to_str().unwrap(), 0, 5, None)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("template-{}", uuid::Uuid::new_v4()));
+    let summary = download_prefix(client, bucket, "", &dir, false)
+        .await
+        .unwrap();
+    assert_eq!(summary.files, 1);
+    assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"hello");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn parses_both_styles() {
+    let host = EndpointTemplate::parse("https://{bucket}.tenant-a.gw.internal").unwrap();
+    assert_eq!(host.style(), TemplateStyle::Host);
+    let path = EndpointTemplate::parse("https://gw.internal/tenant-a/{bucket}").unwrap();
+    assert_eq!(path.style(), TemplateStyle::Path);
+    let regional = EndpointTemplate::parse("https://gw.{region}.internal/{bucket}/").unwrap();
+    assert_eq!(regional.style(), TemplateStyle::Path);
+}
+
+#[test]
+fn rejects_invalid_templates() {
+    for (template, message) in [
+        (
+            "https://{bucket}.{tenant}.gw.internal",
+            "Unknown placeholder {tenant}",
+        ),
+        ("https://{bucket.gw.internal", "Unbalanced {"),
+        ("https://bucket}.gw.internal", "Unbalanced }"),
+        ("https://gw.internal/{bu{cket}", "Unbalanced {"),
+        ("https://gw.internal/tenant-a", "exactly once"),
+        ("https://{bucket}.gw.internal/{bucket}", "exactly once"),
+        ("https://gw.internal/{bucket}/objects", "last segment"),
+        ("https://gw.internal/x{bucket}", "last segment"),
+        ("gw.internal/{bucket}", "must start with"),
+        ("https://gw.internal/{bucket}?tenant=a", "query"),
+    ] {
+        let err = EndpointTemplate::parse(template).unwrap_err();
+        assert!(err.contains(message), "{}: {}", template, err);
+    }
+}
+
+#[test]
+fn fills_in_the_bucket_and_region() {
+    let host = EndpointTemplate::parse("https://{bucket}.{region}.gw.internal").unwrap();
+    assert_eq!(
+        host.bucket_url("photos", "eu-west-1"),
+        "https://photos.eu-west-1.gw.internal"
+    );
+    assert_eq!(
+        host.endpoint_for("photos", "eu-west-1")
+            .unwrap()
+            .to_string(),
+        "https://photos.eu-west-1.gw.internal/"
+    );
+
+    let path = EndpointTemplate::parse("https://gw.internal/tenant-a/{bucket}").unwrap();
+    assert_eq!(
+        path.bucket_url("photos", "eu-west-1"),
+        "https://gw.internal/tenant-a/photos"
+    );
+    // The SDK appends the bucket.
+    assert_eq!(
+        path.endpoint_for("photos", "eu-west-1")
+            .unwrap()
+            .to_string(),
+        "https://gw.internal/tenant-a"
+    );
+}
+
+#[test]
+fn host_templates_need_a_dns_bucket() {
+    let host = EndpointTemplate::parse("https://{bucket}.tenant-a.gw.internal").unwrap();
+    assert!(host.endpoint_for("My_Photos", "us-east-1").is_err());
+    assert!(host.endpoint_for("photos.2022", "us-east-1").is_ok());
+}
+
+#[test]
+fn resolves_for_the_client_region() {
+    let template = EndpointTemplate::parse("https://{bucket}.{region}.gw.internal").unwrap();
+    let resolver = TemplateResolver::new(template);
+    assert!(resolver.resolve_endpoint(&Region::new("eu-west-1")).is_ok());
+}
+
+#[tokio::test]
+async fn path_template_requests() {
+    let captured = Captured::default();
+    let port = gateway(captured.clone());
+    let template =
+        EndpointTemplate::parse(&format!("http://127.0.0.1:{}/tenant-a/{{bucket}}", port)).unwrap();
+    let client = Client::from_conf(config(&template));
+
+    round_trip(&client, "photos").await;
+    round_trip(&client, "videos").await;
+
+    let requests = captured.lock().unwrap().clone();
+    assert_eq!(requests.len(), 6, "{:?}", requests);
+    assert_eq!(requests[0].0, Method::PUT);
+    assert_eq!(requests[0].2, "/tenant-a/photos/a.txt");
+    assert_eq!(requests[1].0, Method::GET);
+    assert!(
+        requests[1].2.starts_with("/tenant-a/photos?list-type=2"),
+        "{}",
+        requests[1].2
+    );
+    assert_eq!(requests[2].0, Method::GET);
+    assert!(
+        requests[2].2.starts_with("/tenant-a/photos/a.txt"),
+        "{}",
+        requests[2].2
+    );
+    assert_eq!(requests[3].2, "/tenant-a/videos/a.txt");
+    for (_, host, _, _) in &requests {
+        assert_eq!(host, &format!("127.0.0.1:{}", port));
+    }
+}
+
+#[tokio::test]
+async fn host_template_requests() {
+    let captured = Captured::default();
+    let port = gateway(captured.clone());
+    let template =
+        EndpointTemplate::parse(&format!("http://{{bucket}}.tenant-a.gw.internal:{}", port))
+            .unwrap();
+    let client = virtual_hosted_client(&template, port);
+
+    // The same client serves any bucket.
+    round_trip(&client, "photos").await;
+    round_trip(&client, "videos").await;
+
+    let requests = captured.lock().unwrap().clone();
+    assert_eq!(requests.len(), 6, "{:?}", requests);
+    for (bucket, requests) in ["photos", "videos"].iter().zip(requests.chunks(3)) {
+        for (_, host, _, authorization) in requests {
+            assert_eq!(host, &format!("{}.tenant-a.gw.internal:{}", bucket, port));
+            // Signed again for the host and path sent.
+            assert!(
+                authorization.contains("Credential=access/")
+                    && authorization.contains("/us-east-1/s3/aws4_request")
+                    && authorization.contains("SignedHeaders=host;"),
+                "{}",
+                authorization
+            );
+        }
+        // The bucket is only in the host name.
+        assert_eq!(requests[0].0, Method::PUT);
+        assert!(requests[0].2.starts_with("/a.txt"), "{}", requests[0].2);
+        assert!(
+            requests[1].2.starts_with("/?list-type=2"),
+            "{}",
+            requests[1].2
+        );
+        assert!(requests[2].2.starts_with("/a.txt"), "{}", requests[2].2);
+    }
+}
+
+#[tokio::test]
+async fn host_template_requests_need_a_dns_bucket() {
+    let captured = Captured::default();
+    let port = gateway(captured.clone());
+    let template =
+        EndpointTemplate::parse(&format!("http://{{bucket}}.tenant-a.gw.internal:{}", port))
+            .unwrap();
+    let client = virtual_hosted_client(&template, port);
+
+    let err = client
+        .list_objects_v2()
+        .bucket("My_Photos")
+        .send()
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{:?}", err).contains("cannot be part of the host name"),
+        "{:?}",
+        err
+    );
+    assert!(captured.lock().unwrap().is_empty());
+}
@@ -0,0 +1,170 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use aws_sdk_s3::error::NoSuchBucket;
+use s3_service::error_hints::{
+    error_class, explain, explain_code, explain_text, RenderedError, CATALOGUE,
+};
+use s3_service::expected_sha256::Sha256Mismatch;
+use s3_service::ops::OpError;
+use std::path::PathBuf;
+
+/// The code of the catalogue entry matching `text`.
+fn code_of(text: &str) -> Option<&'static str> {
+    explain_text(text).map(|entry| entry.code)
+}
+
+/// How an unhandled service error prints.
+fn service_error(code: &str) -> String {
+    format!(
+        "ServiceError {{ err: PutObjectError {{ kind: Unhandled(Error {{ code: Some({:?}), \
+         message: Some(\"...\"), request_id: Some(\"4442587FB7D0A2F9\") }}) }} }}",
+        code
+    )
+}
+
+#[test]
+fn test_no_such_bucket() {
+    let err = aws_sdk_s3::Error::NoSuchBucket(
+        NoSuchBucket::builder()
+            .message("The specified bucket does not exist")
+            .build(),
+    );
+    assert_eq!(Some("NoSuchBucket"), explain(&err).map(|entry| entry.code));
+}
+
+#[test]
+fn test_access_denied() {
+    let err = OpError {
+        status: Some(403),
+        code: Some("AccessDenied".to_string()),
+        message: "Access Denied".to_string(),
+    };
+    assert_eq!(Some("AccessDenied"), explain(&err).map(|entry| entry.code));
+}
+
+#[test]
+fn test_expired_token() {
+    assert_eq!(
+        Some("ExpiredToken"),
+        code_of(&service_error("ExpiredToken"))
+    );
+    assert_eq!(
+        Some("ExpiredToken"),
+        code_of("ExpiredTokenException: The security token included in the request is expired")
+    );
+}
+
+#[test]
+fn test_signature_does_not_match() {
+    assert_eq!(
+        Some("SignatureDoesNotMatch"),
+        code_of(&service_error("SignatureDoesNotMatch"))
+    );
+}
+
+#[test]
+fn test_request_time_too_skewed() {
+    let entry = explain_text(&service_error("RequestTimeTooSkewed")).unwrap();
+    assert!(entry.hint.contains("15 minutes"));
+}
+
+#[test]
+fn test_entity_too_small() {
+    let entry = explain_text(&service_error("EntityTooSmall")).unwrap();
+    assert!(entry.hint.contains("--part-size"));
+}
+
+#[test]
+fn test_entity_too_large() {
+    let entry = explain_text(&service_error("EntityTooLarge")).unwrap();
+    assert_eq!("EntityTooLarge", entry.code);
+    assert!(entry.hint.contains("--auto-split-parts"));
+    assert_eq!(None, code_of(&service_error("EntityTooLargeError")));
+}
+
+#[test]
+fn test_invalid_part() {
+    assert_eq!(Some("InvalidPart"), code_of(&service_error("InvalidPart")));
+    // Only whole words match.
+    assert_eq!(None, code_of(&service_error("InvalidPartOrder")));
+}
+
+#[test]
+fn test_sha256_mismatch() {
+    let err = Sha256Mismatch {
+        path: PathBuf::from("build/app.tar"),
+        expected: "a".repeat(64),
+        actual: "b".repeat(64),
+    };
+    assert_eq!(
+        Some("Sha256Mismatch"),
+        explain(&err).map(|entry| entry.code)
+    );
+    assert_eq!("Sha256Mismatch", error_class(&err));
+}
+
+#[test]
+fn test_no_such_upload() {
+    assert_eq!(
+        Some("NoSuchUpload"),
+        code_of(&service_error("NoSuchUpload"))
+    );
+}
+
+#[test]
+fn test_slow_down() {
+    assert_eq!(Some("SlowDown"), code_of(&service_error("SlowDown")));
+}
+
+#[test]
+fn test_connection_refused() {
+    let err = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+    assert_eq!(
+        Some("ConnectionRefused"),
+        explain(&err).map(|entry| entry.code)
+    );
+    assert_eq!(
+        Some("ConnectionRefused"),
+        code_of("DispatchFailure(ConnectorError { err: hyper::Error(Connect, ConnectError(\"tcp connect error\", Os { code: 111, kind: ConnectionRefused, message: \"Connection refused\" })) })")
+    );
+}
+
+#[test]
+fn test_dns_failure() {
+    assert_eq!(
+        Some("DnsFailure"),
+        code_of("ConnectError(\"dns error\", Custom { kind: Other, error: \"failed to lookup address information: Name or service not known\" })")
+    );
+}
+
+#[test]
+fn test_catalogue_codes() {
+    for entry in CATALOGUE {
+        assert_eq!(Some(entry), explain_code(entry.code));
+    }
+    assert_eq!(None, explain_code("NoSuchKey"));
+}
+
+#[test]
+fn test_rendered_error() {
+    let err = aws_sdk_s3::Error::Unhandled(Box::from(service_error("SlowDown")));
+    let rendered = RenderedError::new(&err, false);
+    assert_eq!(Some("SlowDown"), rendered.code);
+    assert!(rendered.to_text().contains("Hint: Lower the concurrency"));
+    assert!(rendered.details.is_none());
+
+    let json = serde_json::to_value(&rendered).unwrap();
+    assert_eq!("SlowDown", json["code"]);
+    assert!(json["hint"].as_str().unwrap().contains("concurrency"));
+    assert!(json.get("details").is_none());
+
+    // Unknown errors keep their message; -v adds the full error.
+    let err = aws_sdk_s3::Error::Unhandled(Box::from("something else"));
+    let rendered = RenderedError::new(&err, true);
+    assert_eq!(None, rendered.code);
+    assert!(rendered.to_text().starts_with("Error: something else"));
+    assert!(rendered.details.is_some());
+}
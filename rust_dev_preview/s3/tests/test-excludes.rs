@@ -0,0 +1,118 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use common::MockS3;
+use s3_service::excludes::{
+    build_excludes, upload_directory_with_excludes, walk_directory_with_excludes,
+};
+use std::path::{Path, PathBuf};
+
+/// Creates a directory with the files at `paths`, relative to it.
+fn tree(paths: &[&str]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("excludes-test-{}", uuid::Uuid::new_v4()));
+    for path in paths {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"x").unwrap();
+    }
+    dir
+}
+
+fn keys(dir: &Path, patterns: &[&str], exclude_files: &[PathBuf]) -> Vec<String> {
+    let excludes = build_excludes(dir, patterns, exclude_files).unwrap();
+    walk_directory_with_excludes(dir, "up/", &excludes)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.key)
+        .collect()
+}
+
+const FILES: &[&str] = &[
+    "a.txt",
+    "b.tmp",
+    ".DS_Store",
+    "docs/.DS_Store",
+    "docs/guide.md",
+    "logs/app.log",
+    "keep.log",
+    "node_modules/pkg/index.js",
+    "src/node_modules/lib.js",
+];
+
+#[test]
+fn test_patterns() {
+    let dir = tree(FILES);
+    assert_eq!(
+        vec![
+            "up/a.txt",
+            "up/docs/guide.md",
+            "up/keep.log",
+            "up/logs/app.log"
+        ],
+        keys(&dir, &["*.tmp", ".DS_Store", "node_modules/"], &[])
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_upload_ignore_file_and_negation() {
+    let dir = tree(FILES);
+    std::fs::write(dir.join(".uploadignore"), "*.log\n!keep.log\n").unwrap();
+    assert_eq!(
+        vec![
+            "up/.DS_Store",
+            "up/a.txt",
+            "up/docs/.DS_Store",
+            "up/docs/guide.md",
+            "up/keep.log",
+        ],
+        keys(&dir, &["*.tmp", "node_modules/"], &[])
+    );
+    // The command line comes last and wins.
+    assert!(!keys(&dir, &["keep.log"], &[]).contains(&"up/keep.log".to_string()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_exclude_from() {
+    let dir = tree(FILES);
+    let patterns = std::env::temp_dir().join(format!("excludes-{}", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &patterns,
+        "# build output\n*.tmp\n/logs/\nnode_modules/\n.DS_Store\n",
+    )
+    .unwrap();
+    assert_eq!(
+        vec!["up/a.txt", "up/docs/guide.md", "up/keep.log"],
+        keys(&dir, &[], &[patterns.clone()])
+    );
+    std::fs::remove_file(&patterns).unwrap();
+    assert!(build_excludes(&dir, &[], &[patterns]).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_upload_directory_with_excludes() {
+    let dir = tree(FILES);
+    let mock = MockS3::new();
+    let summary = upload_directory_with_excludes(
+        &mock,
+        "bucket",
+        &dir,
+        "up/",
+        &["*.tmp", "*.log", ".DS_Store", "node_modules/"],
+        2,
+    )
+    .await
+    .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let mut completed = summary.completed;
+    completed.sort();
+    assert_eq!(vec!["up/a.txt", "up/docs/guide.md"], completed);
+    assert!(summary.pending.is_empty());
+}
@@ -0,0 +1,302 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use common::MockS3;
+use s3_service::expected_sha256::parse_sha256;
+use s3_service::impl_s3_ops;
+use s3_service::ops::{OpError, S3Ops};
+use s3_service::scheduler::{upload_files, ScheduledFile, SchedulerOptions};
+use s3_service::shutdown::Shutdown;
+use s3_service::upload::{UploadPlanOptions, MIN_PART_SIZE};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// `MockS3` recording the checksums sent with the `_sha256` methods.
+#[derive(Default)]
+struct Recording {
+    mock: MockS3,
+    /// `(operation, checksum)`, in order.
+    checksums: Mutex<Vec<(&'static str, String)>>,
+}
+
+impl Recording {
+    fn record(&self, operation: &'static str, checksum: &str) {
+        self.checksums
+            .lock()
+            .unwrap()
+            .push((operation, checksum.to_string()));
+    }
+
+    fn checksums(&self) -> Vec<(&'static str, String)> {
+        self.checksums.lock().unwrap().clone()
+    }
+}
+
+impl_s3_ops! {
+    impl S3Ops for Recording {
+        async fn head_bucket(&self, bucket: &str) -> Result<(), OpError> {
+            self.mock.head_bucket(bucket).await
+        }
+
+        async fn create_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> Result<String, OpError> {
+            self.mock.create_multipart_upload(bucket, key).await
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+        ) -> Result<(), OpError> {
+            self.mock
+                .abort_multipart_upload(bucket, key, upload_id)
+                .await
+        }
+
+        async fn upload_part(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            self.mock
+                .upload_part(bucket, key, upload_id, part_number, body)
+                .await
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String)>,
+        ) -> Result<String, OpError> {
+            self.mock
+                .complete_multipart_upload(bucket, key, upload_id, parts)
+                .await
+        }
+
+        async fn put_object(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            self.mock.put_object(bucket, key, body).await
+        }
+
+        async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), OpError> {
+            self.mock.delete_object(bucket, key).await
+        }
+
+        async fn get_object_range(
+            &self,
+            bucket: &str,
+            key: &str,
+            offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>, OpError> {
+            self.mock
+                .get_object_range(bucket, key, offset, length)
+                .await
+        }
+
+        async fn upload_part_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+            checksum: String,
+        ) -> Result<String, OpError> {
+            self.record("UploadPart", &checksum);
+            self.upload_part(bucket, key, upload_id, part_number, body)
+                .await
+        }
+
+        async fn complete_multipart_upload_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String, String)>,
+        ) -> Result<String, OpError> {
+            for (_, _, checksum) in &parts {
+                self.record("CompleteMultipartUpload", checksum);
+            }
+            let parts = parts
+                .into_iter()
+                .map(|(part_number, e_tag, _)| (part_number, e_tag))
+                .collect();
+            self.complete_multipart_upload(bucket, key, upload_id, parts)
+                .await
+        }
+
+        async fn put_object_sha256(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: Vec<u8>,
+            checksum: String,
+        ) -> Result<String, OpError> {
+            self.record("PutObject", &checksum);
+            self.put_object(bucket, key, body).await
+        }
+    }
+}
+
+/// Writes a file of `size` bytes counting up from zero, keyed `file`.
+fn test_file(size: usize) -> (PathBuf, ScheduledFile, Vec<u8>) {
+    let dir = std::env::temp_dir().join(format!("expected-sha256-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("file");
+    let content: Vec<u8> = (0..size).map(|n| n as u8).collect();
+    std::fs::write(&path, &content).unwrap();
+    let file = ScheduledFile {
+        path,
+        key: "file".to_string(),
+        size: size as u64,
+    };
+    (dir, file, content)
+}
+
+fn hex_sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn options(file: &ScheduledFile, expected: String, checksum_sha256: bool) -> SchedulerOptions {
+    let mut expected_sha256 = HashMap::new();
+    expected_sha256.insert(file.path.clone(), expected);
+    SchedulerOptions {
+        concurrency: 1,
+        plan: UploadPlanOptions {
+            multipart_threshold: MIN_PART_SIZE,
+            part_size: None,
+            num_parts: Some(2),
+        },
+        expected_sha256,
+        checksum_sha256,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_parse_sha256() {
+    let digest = hex_sha256(b"artifact");
+    assert_eq!(Ok(digest.clone()), parse_sha256(&digest.to_uppercase()));
+    assert!(parse_sha256(&digest[1..]).is_err());
+    assert!(parse_sha256(&format!("{}g", &digest[1..])).is_err());
+}
+
+#[tokio::test]
+async fn test_put_object_matches_and_mismatches() {
+    let (dir, file, content) = test_file(100);
+    let shutdown = Shutdown::new(Duration::from_secs(60));
+
+    let ops = Recording::default();
+    let matching = options(&file, hex_sha256(&content), false);
+    let summary = upload_files(&ops, "bucket", vec![file.clone()], &matching, &shutdown).await;
+    assert_eq!(vec!["file"], summary.completed);
+    assert_eq!(vec!["PutObject"], ops.mock.calls());
+    assert!(ops.checksums().is_empty());
+
+    let ops = Recording::default();
+    let other = options(&file, hex_sha256(b"another build"), false);
+    let summary = upload_files(&ops, "bucket", vec![file], &other, &shutdown).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(summary.completed.is_empty());
+    assert!(ops.mock.calls().is_empty());
+    assert_eq!(
+        Some("Sha256Mismatch"),
+        summary.pending[0].error_class.as_deref()
+    );
+    let error = summary.pending[0].error.as_deref().unwrap();
+    assert!(error.contains(&hex_sha256(&content)), "{}", error);
+}
+
+#[tokio::test]
+async fn test_multipart_mismatch_is_aborted() {
+    let size = 2 * MIN_PART_SIZE as usize;
+    let (dir, file, content) = test_file(size);
+    let shutdown = Shutdown::new(Duration::from_secs(60));
+
+    let ops = Recording::default();
+    let matching = options(&file, hex_sha256(&content), false);
+    let summary = upload_files(&ops, "bucket", vec![file.clone()], &matching, &shutdown).await;
+    assert_eq!(vec!["file"], summary.completed);
+    assert_eq!(
+        vec![
+            "CreateMultipartUpload",
+            "UploadPart",
+            "UploadPart",
+            "CompleteMultipartUpload"
+        ],
+        ops.mock.calls()
+    );
+
+    let ops = Recording::default();
+    let other = options(&file, hex_sha256(b"another build"), false);
+    let summary = upload_files(&ops, "bucket", vec![file], &other, &shutdown).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(summary.completed.is_empty());
+    assert_eq!(vec!["file"], summary.aborted_uploads);
+    assert_eq!(
+        vec![
+            "CreateMultipartUpload",
+            "UploadPart",
+            "UploadPart",
+            "AbortMultipartUpload"
+        ],
+        ops.mock.calls()
+    );
+    assert_eq!(
+        Some("Sha256Mismatch"),
+        summary.pending[0].error_class.as_deref()
+    );
+}
+
+#[tokio::test]
+async fn test_checksums_come_from_the_same_reads() {
+    let shutdown = Shutdown::new(Duration::from_secs(60));
+    let checksum = |bytes: &[u8]| base64::encode(Sha256::digest(bytes));
+
+    let (dir, file, content) = test_file(100);
+    let ops = Recording::default();
+    let with_checksum = options(&file, hex_sha256(&content), true);
+    let summary = upload_files(&ops, "bucket", vec![file], &with_checksum, &shutdown).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(vec!["file"], summary.completed);
+    assert_eq!(vec![("PutObject", checksum(&content))], ops.checksums());
+
+    let size = 2 * MIN_PART_SIZE as usize;
+    let (dir, file, content) = test_file(size);
+    let ops = Recording::default();
+    let with_checksum = options(&file, hex_sha256(&content), true);
+    let summary = upload_files(&ops, "bucket", vec![file], &with_checksum, &shutdown).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(vec!["file"], summary.completed);
+    let (first, second) = content.split_at(MIN_PART_SIZE as usize);
+    assert_eq!(
+        vec![
+            ("UploadPart", checksum(first)),
+            ("UploadPart", checksum(second)),
+            ("CompleteMultipartUpload", checksum(first)),
+            ("CompleteMultipartUpload", checksum(second)),
+        ],
+        ops.checksums()
+    );
+}
@@ -0,0 +1,263 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use chrono::{TimeZone, Utc};
+use hyper::{Body, Method, Request, Response};
+use s3_service::expiry::{
+    delete_expired_tagged_objects, delete_objects_older_than, find_objects_older_than,
+    parse_expiration_header,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The objects of the mock bucket: key, tagged `retention=temp`, and the
+/// `x-amz-expiration` and `Expires` headers. The HeadObject of
+/// `head-denied` fails.
+const OBJECTS: &[(&str, bool, Option<&str>, Option<&str>)] = &[
+    (
+        "lifecycle-expired",
+        true,
+        Some("expiry-date=\"Fri, 23 Dec 2012 00:00:00 GMT\", rule-id=\"rule\""),
+        None,
+    ),
+    (
+        "expires-future",
+        true,
+        None,
+        Some("Thu, 31 Dec 2099 00:00:00 GMT"),
+    ),
+    (
+        "untagged-expired",
+        false,
+        None,
+        Some("Fri, 23 Dec 2012 00:00:00 GMT"),
+    ),
+    (
+        "expires-past",
+        true,
+        None,
+        Some("Fri, 23 Dec 2012 00:00:00 GMT"),
+    ),
+    ("no-expiry", true, None, None),
+    (
+        "head-denied",
+        true,
+        Some("expiry-date=\"Fri, 23 Dec 2012 00:00:00 GMT\", rule-id=\"rule\""),
+        None,
+    ),
+];
+
+fn object(
+    path: &str,
+) -> &'static (
+    &'static str,
+    bool,
+    Option<&'static str>,
+    Option<&'static str>,
+) {
+    let key = path.trim_start_matches("/bucket/");
+    OBJECTS.iter().find(|(k, ..)| *k == key).unwrap()
+}
+
+/// Starts a mock bucket and returns its client and the bodies of the
+/// DeleteObjects requests.
+async fn mock_server() -> (Client, Arc<Mutex<Vec<String>>>) {
+    let deletes = Arc::new(Mutex::new(Vec::new()));
+    let recorder = deletes.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let response = if method == Method::POST {
+                recorder
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(body.to_vec()).unwrap());
+                Response::builder().body(Body::from("<DeleteResult></DeleteResult>"))
+            } else if method == Method::HEAD && path == "/bucket/head-denied" {
+                Response::builder().status(403).body(Body::empty())
+            } else if method == Method::HEAD {
+                let (_, _, expiration, expires) = object(&path);
+                let mut response = Response::builder();
+                if let Some(expiration) = expiration {
+                    response = response.header("x-amz-expiration", *expiration);
+                }
+                if let Some(expires) = expires {
+                    response = response.header("Expires", *expires);
+                }
+                response.body(Body::empty())
+            } else if query.contains("tagging") {
+                let (_, tagged, ..) = object(&path);
+                let tags = if *tagged {
+                    "<Tag><Key>retention</Key><Value>temp</Value></Tag>"
+                } else {
+                    "<Tag><Key>retention</Key><Value>keep</Value></Tag>"
+                };
+                Response::builder().body(Body::from(format!(
+                    "<Tagging><TagSet>{}</TagSet></Tagging>",
+                    tags
+                )))
+            } else {
+                let contents: String = OBJECTS
+                    .iter()
+                    .map(|(key, ..)| {
+                        format!("<Contents><Key>{}</Key><Size>1</Size></Contents>", key)
+                    })
+                    .collect();
+                Response::builder().body(Body::from(format!(
+                    "<ListBucketResult><Name>bucket</Name>\
+                     <IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                    contents
+                )))
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), deletes)
+}
+
+#[test]
+fn test_parse_expiration_header() {
+    assert_eq!(
+        Some(Utc.ymd(2012, 12, 23).and_hms(0, 0, 0)),
+        parse_expiration_header(
+            "expiry-date=\"Fri, 23 Dec 2012 00:00:00 GMT\", rule-id=\"picture-deletion-rule\""
+        )
+    );
+    assert_eq!(None, parse_expiration_header("rule-id=\"rule\""));
+}
+
+#[tokio::test]
+async fn test_deletes_only_expired_tagged_objects() {
+    let (client, deletes) = mock_server().await;
+    let summary = delete_expired_tagged_objects(&client, "bucket", "retention", "temp")
+        .await
+        .unwrap();
+    assert_eq!(1, summary.deleted);
+    // The object that cannot be checked is reported, and the others go on.
+    assert_eq!(1, summary.failed.len());
+    assert_eq!("head-denied", summary.failed[0].0);
+
+    let deletes = deletes.lock().unwrap();
+    assert_eq!(1, deletes.len());
+    assert!(deletes[0].contains("<Key>lifecycle-expired</Key>"));
+    // A past `Expires` header only means the cached copies are stale.
+    for kept in &[
+        "expires-past",
+        "expires-future",
+        "untagged-expired",
+        "no-expiry",
+        "head-denied",
+    ] {
+        assert!(!deletes[0].contains(&format!("<Key>{}</Key>", kept)));
+    }
+}
+
+/// How many objects of the dated bucket are older than 30 days: more than
+/// one DeleteObjects request takes.
+const OLD_OBJECTS: usize = 1200;
+
+fn listed(key: &str, days_ago: i64, size: u64) -> String {
+    let modified = Utc::now() - chrono::Duration::days(days_ago);
+    format!(
+        "<Contents><Key>{}</Key><LastModified>{}</LastModified><Size>{}</Size></Contents>",
+        key,
+        modified.format("%Y-%m-%dT%H:%M:%S.000Z"),
+        size
+    )
+}
+
+/// Starts a mock bucket listing, in two pages, `OLD_OBJECTS` objects
+/// modified 40 days ago, one modified a day ago, and one modified in the
+/// future. Returns its client, the query of each listing, and the bodies of
+/// the DeleteObjects requests.
+async fn mock_dated_bucket() -> (Client, Arc<Mutex<Vec<String>>>, Arc<Mutex<Vec<String>>>) {
+    let listings = Arc::new(Mutex::new(Vec::new()));
+    let deletes = Arc::new(Mutex::new(Vec::new()));
+    let (listed_queries, recorder) = (listings.clone(), deletes.clone());
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let (listings, recorder) = (listed_queries.clone(), recorder.clone());
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let response = if method == Method::POST {
+                recorder
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(body.to_vec()).unwrap());
+                "<DeleteResult></DeleteResult>".to_string()
+            } else if query.contains("continuation-token") {
+                listings.lock().unwrap().push(query);
+                format!(
+                    "<ListBucketResult><Name>bucket</Name>\
+                     <IsTruncated>false</IsTruncated>{}{}</ListBucketResult>",
+                    listed("logs/recent", 1, 7),
+                    listed("logs/future", -1, 11)
+                )
+            } else {
+                listings.lock().unwrap().push(query);
+                let contents: String = (0..OLD_OBJECTS)
+                    .map(|i| listed(&format!("logs/old-{}", i), 40, 10))
+                    .collect();
+                format!(
+                    "<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated>\
+                     <NextContinuationToken>page-2</NextContinuationToken>{}\
+                     </ListBucketResult>",
+                    contents
+                )
+            };
+            Response::new(Body::from(response))
+        }
+    });
+
+    (common::client_for(port), listings, deletes)
+}
+
+const THIRTY_DAYS: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[tokio::test]
+async fn test_find_objects_older_than() {
+    let (client, listings, deletes) = mock_dated_bucket().await;
+
+    let aged = find_objects_older_than(&client, "bucket", Some("logs/"), THIRTY_DAYS)
+        .await
+        .unwrap();
+
+    assert_eq!(OLD_OBJECTS, aged.keys.len());
+    assert!(aged.keys.iter().all(|key| key.starts_with("logs/old-")));
+    assert_eq!(OLD_OBJECTS as u64 * 10, aged.bytes);
+    let listings = listings.lock().unwrap();
+    assert_eq!(2, listings.len());
+    assert!(listings.iter().all(|query| query.contains("prefix=logs")));
+    assert!(deletes.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_delete_objects_older_than_in_batches() {
+    let (client, _, deletes) = mock_dated_bucket().await;
+
+    let deleted = delete_objects_older_than(&client, "bucket", Some("logs/"), THIRTY_DAYS)
+        .await
+        .unwrap();
+
+    assert_eq!(OLD_OBJECTS as u64, deleted);
+    let deletes = deletes.lock().unwrap();
+    assert_eq!(2, deletes.len());
+    assert_eq!(1000, deletes[0].matches("<Key>").count());
+    assert_eq!(OLD_OBJECTS - 1000, deletes[1].matches("<Key>").count());
+    for kept in &["logs/recent", "logs/future"] {
+        assert!(deletes
+            .iter()
+            .all(|body| !body.contains(&format!("<Key>{}</Key>", kept))));
+    }
+}
@@ -0,0 +1,263 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::{Client, Credentials, Region, RetryConfig};
+use aws_smithy_client::hyper_ext;
+use aws_types::credentials::SharedCredentialsProvider;
+use chrono::Utc;
+use common::LoopbackConnector;
+use hyper::{Body, Method, Request, Response};
+use s3_service::express::{
+    check_general_purpose_bucket, parse_session, upload_chunk_express, upload_multipart_express,
+    DirectoryBucket, ExpressConnector,
+};
+use std::sync::{Arc, Mutex};
+
+const BUCKET: &str = "logs--usw2-az1--x-s3";
+const ZONAL_HOST: &str = "logs--usw2-az1--x-s3.s3express-usw2-az1.us-west-2.amazonaws.com";
+
+/// What the server saw of a request.
+#[derive(Debug, Clone)]
+struct Seen {
+    method: Method,
+    host: String,
+    path: String,
+    authorization: String,
+    session_token: Option<String>,
+    security_token: bool,
+}
+
+type Captured = Arc<Mutex<Vec<Seen>>>;
+
+/// Starts a server that answers CreateSession with sessions valid for
+/// `session_seconds`, and PutObject and the multipart upload requests, and
+/// returns its port.
+fn zonal_endpoint(captured: Captured, session_seconds: i64) -> u16 {
+    common::mock_s3(move |req: Request<Body>| {
+        let captured = captured.clone();
+        async move {
+            let header = |name: &str| {
+                req.headers()
+                    .get(name)
+                    .map(|value| value.to_str().unwrap().to_string())
+            };
+            let seen = Seen {
+                method: req.method().clone(),
+                host: header("host").unwrap_or_default(),
+                path: req.uri().path_and_query().unwrap().to_string(),
+                authorization: header("authorization").unwrap_or_default(),
+                session_token: header("x-amz-s3session-token"),
+                security_token: header("x-amz-security-token").is_some(),
+            };
+            captured.lock().unwrap().push(seen.clone());
+            hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let body = if seen.path == "/?session" {
+                let expiration = Utc::now() + chrono::Duration::seconds(session_seconds);
+                format!(
+                    "<CreateSessionResult><Credentials>\
+                     <SessionToken>session-token</SessionToken>\
+                     <SecretAccessKey>session-secret</SecretAccessKey>\
+                     <AccessKeyId>SESSIONKEY</AccessKeyId>\
+                     <Expiration>{}</Expiration>\
+                     </Credentials></CreateSessionResult>",
+                    expiration.to_rfc3339()
+                )
+            } else if seen.method == Method::POST && seen.path.contains("uploads") {
+                "<InitiateMultipartUploadResult><Bucket>b</Bucket><Key>k</Key>\
+                 <UploadId>upload-1</UploadId></InitiateMultipartUploadResult>"
+                    .to_string()
+            } else if seen.method == Method::POST {
+                "<CompleteMultipartUploadResult><ETag>\"etag\"</ETag>\
+                 </CompleteMultipartUploadResult>"
+                    .to_string()
+            } else {
+                String::new()
+            };
+            Response::builder()
+                .header("ETag", "\"etag\"")
+                .body(Body::from(body))
+                .unwrap()
+        }
+    })
+}
+
+fn express_client(port: u16) -> Client {
+    let credentials = Credentials::new("access", "secret", None, None, "test");
+    let conf = aws_sdk_s3::Config::builder()
+        .region(Region::new("us-west-2"))
+        .credentials_provider(credentials.clone())
+        .retry_config(RetryConfig::disabled())
+        .build();
+    let connector = ExpressConnector::new(
+        hyper_ext::Adapter::builder().build(LoopbackConnector(port)),
+        Some(SharedCredentialsProvider::new(credentials)),
+        "us-west-2",
+        "usw2-az1",
+    );
+    Client::from_conf_conn(conf, connector)
+}
+
+fn test_file(content: &[u8]) -> String {
+    let path = std::env::temp_dir().join(format!("express-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, content).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_parse_directory_bucket() {
+    let bucket = DirectoryBucket::parse("logs--usw2-az1--x-s3").unwrap();
+    assert_eq!("logs", bucket.base_name);
+    assert_eq!("usw2-az1", bucket.az_id);
+    assert_eq!(
+        "https://logs--usw2-az1--x-s3.s3express-usw2-az1.us-west-2.amazonaws.com",
+        bucket.endpoint("us-west-2")
+    );
+
+    // The base name may itself contain double dashes.
+    let bucket = DirectoryBucket::parse("my--logs--use1-az4--x-s3").unwrap();
+    assert_eq!("my--logs", bucket.base_name);
+    assert_eq!("use1-az4", bucket.az_id);
+
+    assert_eq!(None, DirectoryBucket::parse("logs"));
+    assert_eq!(None, DirectoryBucket::parse("logs--x-s3"));
+    assert_eq!(None, DirectoryBucket::parse("--usw2-az1--x-s3"));
+    assert_eq!(None, DirectoryBucket::parse("logs--not-a-zone--x-s3"));
+}
+
+#[test]
+fn test_directory_buckets_are_refused() {
+    assert!(check_general_purpose_bucket("logs").is_ok());
+    let err = check_general_purpose_bucket("logs--usw2-az1--x-s3").unwrap_err();
+    assert!(
+        err.to_string().contains("directory bucket in usw2-az1"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_parse_session() {
+    let session = parse_session(
+        "<CreateSessionResult><Credentials><SessionToken>token</SessionToken>\
+         <SecretAccessKey>secret</SecretAccessKey><AccessKeyId>AKID</AccessKeyId>\
+         <Expiration>2023-11-28T12:05:00Z</Expiration></Credentials></CreateSessionResult>",
+    )
+    .unwrap();
+    assert_eq!(session.access_key_id(), "AKID");
+    assert_eq!(session.secret_access_key(), "secret");
+    assert_eq!(session.session_token(), Some("token"));
+    assert!(session.expiry().is_some());
+
+    let err = parse_session("<CreateSessionResult></CreateSessionResult>").unwrap_err();
+    assert!(err.contains("AccessKeyId"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_uploads_go_to_the_zonal_endpoint_with_one_session() {
+    let captured = Captured::default();
+    let client = express_client(zonal_endpoint(captured.clone(), 300));
+    let file = test_file(b"hello");
+
+    for key in ["a.txt", "b.txt"] {
+        let etag = upload_chunk_express(&client, BUCKET, key, &file, 0, 5, None)
+            .await
+            .unwrap();
+        assert_eq!(etag, "etag");
+    }
+    std::fs::remove_file(&file).unwrap();
+
+    let requests = captured.lock().unwrap().clone();
+    assert_eq!(requests.len(), 3, "{:#?}", requests);
+    for seen in &requests {
+        assert_eq!(seen.host, ZONAL_HOST);
+        assert!(!seen.security_token, "{:?}", seen);
+    }
+
+    // The session is created with the credentials of the client.
+    let create = &requests[0];
+    assert_eq!(create.method, Method::GET);
+    assert_eq!(create.path, "/?session");
+    assert!(
+        create.authorization.contains("Credential=access/")
+            && create
+                .authorization
+                .contains("/us-west-2/s3express/aws4_request"),
+        "{}",
+        create.authorization
+    );
+
+    // The uploads are addressed by key and signed with the session.
+    for (seen, key) in requests[1..].iter().zip(["/a.txt", "/b.txt"]) {
+        assert_eq!(seen.method, Method::PUT);
+        assert!(seen.path.starts_with(key), "{}", seen.path);
+        assert_eq!(seen.session_token.as_deref(), Some("session-token"));
+        assert!(
+            seen.authorization.contains("Credential=SESSIONKEY/")
+                && seen
+                    .authorization
+                    .contains("/us-west-2/s3express/aws4_request")
+                && seen.authorization.contains("x-amz-s3session-token"),
+            "{}",
+            seen.authorization
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_sessions_about_to_expire_are_replaced() {
+    let captured = Captured::default();
+    // Less than a minute is left of every session.
+    let client = express_client(zonal_endpoint(captured.clone(), 30));
+    let file = test_file(b"hello world");
+
+    let etag = upload_multipart_express(&client, BUCKET, "big.bin", &file, 2, None)
+        .await
+        .unwrap();
+    assert_eq!(etag, "etag");
+    std::fs::remove_file(&file).unwrap();
+
+    let requests = captured.lock().unwrap().clone();
+    let methods: Vec<(&Method, bool)> = requests
+        .iter()
+        .map(|seen| (&seen.method, seen.path == "/?session"))
+        .collect();
+    // Create, two parts, and complete, each after a new session.
+    assert_eq!(
+        methods,
+        vec![
+            (&Method::GET, true),
+            (&Method::POST, false),
+            (&Method::GET, true),
+            (&Method::PUT, false),
+            (&Method::GET, true),
+            (&Method::PUT, false),
+            (&Method::GET, true),
+            (&Method::POST, false),
+        ],
+        "{:#?}",
+        requests
+    );
+    for seen in requests.iter().filter(|seen| seen.path != "/?session") {
+        assert_eq!(seen.host, ZONAL_HOST);
+        assert!(seen.path.starts_with("/big.bin?"), "{}", seen.path);
+    }
+}
+
+#[tokio::test]
+async fn test_express_uploads_need_a_directory_bucket() {
+    let captured = Captured::default();
+    let client = express_client(zonal_endpoint(captured.clone(), 300));
+    let err = upload_chunk_express(&client, "logs", "a.txt", "unused", 0, 5, None)
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("not an S3 Express One Zone"),
+        "{}",
+        err
+    );
+    assert!(captured.lock().unwrap().is_empty());
+}
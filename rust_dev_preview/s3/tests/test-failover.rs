@@ -0,0 +1,239 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+// Two mock gateway nodes in front of the same "cluster" state. A node can be
+// killed, which drops its listener and every open connection, like a gateway
+// going down in the middle of a transfer.
+
+use aws_sdk_s3::{Client, Credentials, Endpoint, Region, RetryConfig};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use s3_service::failover::EndpointPool;
+use s3_service::upload::upload_multipart_with_endpoints;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+#[derive(Default)]
+struct Cluster {
+    /// Part number to size of the stored parts.
+    parts: Mutex<BTreeMap<u32, usize>>,
+    /// The node, method, and query string of each request received.
+    requests: Mutex<Vec<(usize, Method, String)>>,
+}
+
+#[derive(Clone, Default)]
+struct Node {
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Node {
+    fn kill(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+/// How a node behaves.
+#[derive(Clone, Copy)]
+enum Fault {
+    None,
+    /// Dies when it receives this part number.
+    DieOnPart(u32),
+    /// Answers every request with 403 AccessDenied.
+    Deny,
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').find_map(|pair| {
+        let mut kv = pair.splitn(2, '=');
+        (kv.next() == Some(name)).then(|| kv.next().unwrap_or(""))
+    })
+}
+
+async fn handle(
+    id: usize,
+    node: Node,
+    fault: Fault,
+    cluster: Arc<Cluster>,
+    req: Request<Body>,
+) -> Result<Response<Body>, std::io::Error> {
+    let query = req.uri().query().unwrap_or("").to_string();
+    let method = req.method().clone();
+    let part_number = query_param(&query, "partNumber").and_then(|n| n.parse::<u32>().ok());
+    cluster
+        .requests
+        .lock()
+        .unwrap()
+        .push((id, method.clone(), query.clone()));
+
+    if let Fault::Deny = fault {
+        return Ok(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(
+                "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>",
+            ))
+            .unwrap());
+    }
+    if let (Fault::DieOnPart(n), Some(part)) = (fault, part_number) {
+        if part == n {
+            node.kill();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "node killed",
+            ));
+        }
+    }
+
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let response = match (method, part_number) {
+        (Method::POST, _) if query_param(&query, "uploads").is_some() => Response::new(Body::from(
+            "<InitiateMultipartUploadResult><Bucket>bucket</Bucket><Key>key</Key>\
+             <UploadId>upload-1</UploadId></InitiateMultipartUploadResult>",
+        )),
+        (Method::PUT, Some(part)) => {
+            cluster.parts.lock().unwrap().insert(part, body.len());
+            Response::builder()
+                .header("ETag", format!("\"etag-{}\"", part))
+                .body(Body::empty())
+                .unwrap()
+        }
+        (Method::POST, _) if query_param(&query, "uploadId").is_some() => {
+            Response::new(Body::from(
+                "<CompleteMultipartUploadResult><Bucket>bucket</Bucket><Key>key</Key>\
+                 <ETag>\"complete-etag\"</ETag></CompleteMultipartUploadResult>",
+            ))
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    };
+    Ok(response)
+}
+
+/// Starts a node and returns its URL and handle.
+async fn start_node(id: usize, fault: Fault, cluster: Arc<Cluster>) -> (String, Node) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let node = Node::default();
+    let accept_node = node.clone();
+    let accept = tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let node = accept_node.clone();
+            let cluster = cluster.clone();
+            let service =
+                service_fn(move |req| handle(id, node.clone(), fault, cluster.clone(), req));
+            let connection = tokio::spawn(async move {
+                let _ = Http::new().serve_connection(stream, service).await;
+            });
+            accept_node.tasks.lock().unwrap().push(connection);
+        }
+    });
+    node.tasks.lock().unwrap().push(accept);
+    (url, node)
+}
+
+fn client_for(url: &str) -> Client {
+    let conf = aws_sdk_s3::Config::builder()
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("access", "secret", None, None, "test"))
+        .endpoint_resolver(Endpoint::immutable(url.parse().unwrap()))
+        // Leave the retries to the upload, so they go through the pool.
+        .retry_config(RetryConfig::disabled())
+        .build();
+    Client::from_conf(conf)
+}
+
+fn test_file(size: usize) -> String {
+    let path = std::env::temp_dir().join(format!("failover-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![b'x'; size]).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[tokio::test]
+async fn test_failover_mid_transfer() {
+    let cluster = Arc::new(Cluster::default());
+    let (first, _first_node) = start_node(0, Fault::DieOnPart(3), cluster.clone()).await;
+    let (second, _second_node) = start_node(1, Fault::None, cluster.clone()).await;
+    let endpoints = EndpointPool::new(
+        vec![
+            (first.clone(), client_for(&first)),
+            (second.clone(), client_for(&second)),
+        ],
+        None,
+    );
+    let file = test_file(4000);
+
+    let e_tag = upload_multipart_with_endpoints(&endpoints, "bucket", "key", &file, 4, None, None)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!("complete-etag", e_tag);
+    assert_eq!(1, endpoints.failovers());
+    assert_eq!(second, endpoints.current_endpoint());
+    let parts = cluster.parts.lock().unwrap().clone();
+    assert_eq!(vec![1, 2, 3, 4], parts.keys().copied().collect::<Vec<_>>());
+    assert_eq!(4000, parts.values().sum::<usize>());
+
+    // Parts 1 and 2 went to the first node, part 3 was retried on the second
+    // one, which also received the rest of the upload.
+    let requests = cluster.requests.lock().unwrap().clone();
+    let nodes_of = |name: &str, value: &str| {
+        requests
+            .iter()
+            .filter(|(_, _, query)| query_param(query, name) == Some(value))
+            .map(|(node, method, _)| (*node, method.clone()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec![(0, Method::POST)], nodes_of("uploads", ""));
+    assert_eq!(vec![(0, Method::PUT)], nodes_of("partNumber", "1"));
+    assert_eq!(
+        vec![(0, Method::PUT), (1, Method::PUT)],
+        nodes_of("partNumber", "3")
+    );
+    assert_eq!(vec![(1, Method::PUT)], nodes_of("partNumber", "4"));
+    // The completion is the only POST with an upload id.
+    assert_eq!(
+        vec![(1, Method::POST)],
+        nodes_of("uploadId", "upload-1")
+            .into_iter()
+            .filter(|(_, method)| *method == Method::POST)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_error_response_does_not_fail_over() {
+    let cluster = Arc::new(Cluster::default());
+    let (first, _first_node) = start_node(0, Fault::Deny, cluster.clone()).await;
+    let (second, _second_node) = start_node(1, Fault::None, cluster.clone()).await;
+    let endpoints = EndpointPool::new(
+        vec![
+            (first.clone(), client_for(&first)),
+            (second.clone(), client_for(&second)),
+        ],
+        None,
+    );
+    let file = test_file(1000);
+
+    let result =
+        upload_multipart_with_endpoints(&endpoints, "bucket", "key", &file, 2, None, None).await;
+    std::fs::remove_file(&file).unwrap();
+
+    assert!(result.is_err());
+    assert_eq!(0, endpoints.failovers());
+    assert_eq!(first, endpoints.current_endpoint());
+    assert!(cluster
+        .requests
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|(node, _, _)| *node == 0));
+}
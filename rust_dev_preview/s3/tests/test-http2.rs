@@ -0,0 +1,137 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use s3_service::http2::{
+    build_s3_client_counted, build_s3_client_http2, time_sequential_uploads, ConnectionCounter,
+    HttpVersion, SequentialTimings,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The requests in flight at the mock, and the most there were at once.
+#[derive(Default)]
+struct InFlight {
+    now: AtomicUsize,
+    most: AtomicUsize,
+}
+
+/// Starts an HTTP/1.1 server answering PutObject after `delay`, and returns
+/// the configuration of a client for it.
+async fn mock_s3(delay: Duration) -> (aws_sdk_s3::Config, Arc<InFlight>) {
+    let in_flight = Arc::new(InFlight::default());
+    let counter = in_flight.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let in_flight = counter.clone();
+        async move {
+            let now = in_flight.now.fetch_add(1, Ordering::SeqCst) + 1;
+            in_flight.most.fetch_max(now, Ordering::SeqCst);
+            hyper::body::to_bytes(req.into_body()).await.unwrap();
+            tokio::time::sleep(delay).await;
+            in_flight.now.fetch_sub(1, Ordering::SeqCst);
+            Response::builder()
+                .header("ETag", "\"etag\"")
+                .body(Body::empty())
+                .unwrap()
+        }
+    });
+
+    let conf = common::config_for(port).build();
+    (conf, in_flight)
+}
+
+async fn put(client: &Client, key: &str) {
+    client
+        .put_object()
+        .bucket("bucket")
+        .key(key)
+        .body(ByteStream::from(b"hello".to_vec()))
+        .send()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_sequential_uploads_reuse_one_connection() {
+    for version in [
+        HttpVersion::Http1,
+        HttpVersion::Http2 {
+            max_concurrent_streams: 4,
+        },
+    ]
+    .iter()
+    .copied()
+    {
+        let (conf, _) = mock_s3(Duration::ZERO).await;
+        let counter = ConnectionCounter::new();
+        let client = build_s3_client_counted(conf, version, &counter);
+
+        let timings = time_sequential_uploads(&client, "bucket", "small/", 5, 16)
+            .await
+            .unwrap();
+
+        assert_eq!(5, timings.uploads);
+        assert!(timings.first <= timings.total);
+        assert_eq!(1, counter.opened(), "{:?}", version);
+    }
+}
+
+#[tokio::test]
+async fn test_http2_client_falls_back_to_http1() {
+    // The mock only speaks HTTP/1.1, as a plain `http` endpoint always is
+    // reached.
+    let (conf, _) = mock_s3(Duration::ZERO).await;
+    let client = build_s3_client_http2(conf, 100);
+
+    put(&client, "a.txt").await;
+}
+
+#[tokio::test]
+async fn test_streams_are_capped() {
+    let (conf, in_flight) = mock_s3(Duration::from_millis(100)).await;
+    let client = build_s3_client_counted(
+        conf,
+        HttpVersion::Http2 {
+            max_concurrent_streams: 2,
+        },
+        &ConnectionCounter::new(),
+    );
+
+    let uploads: Vec<_> = (0..6)
+        .map(|n| {
+            let client = client.clone();
+            tokio::spawn(async move { put(&client, &format!("{}.txt", n)).await })
+        })
+        .collect();
+    for upload in uploads {
+        upload.await.unwrap();
+    }
+
+    assert_eq!(2, in_flight.most.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_setup_overhead() {
+    let timings = SequentialTimings {
+        uploads: 5,
+        first: Duration::from_millis(50),
+        total: Duration::from_millis(90),
+    };
+    assert_eq!(Duration::from_millis(10), timings.mean_reused());
+    assert_eq!(Duration::from_millis(40), timings.setup_overhead());
+
+    let single = SequentialTimings {
+        uploads: 1,
+        first: Duration::from_millis(50),
+        total: Duration::from_millis(50),
+    };
+    assert_eq!(Duration::ZERO, single.mean_reused());
+    assert_eq!(Duration::from_millis(50), single.setup_overhead());
+}
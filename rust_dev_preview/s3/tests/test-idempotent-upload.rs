@@ -0,0 +1,216 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use md5::{Digest, Md5};
+use s3_service::idempotent_upload::{
+    local_etag, multipart_etag_parts, upload_idempotent, IdempotentUploadResult, PartLayout,
+};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const HELLO_MD5: &str = "5d41402abc4b2a76b9719d911017c592";
+
+/// The requests received: `head`, `head-part`, or `put`.
+type Captured = Arc<Mutex<Vec<&'static str>>>;
+
+/// The object the server holds.
+#[derive(Clone)]
+struct Existing {
+    size: usize,
+    e_tag: String,
+    /// The size of part 1, for a multipart object.
+    first_part: Option<usize>,
+}
+
+/// Starts a server answering HeadObject with `existing`, or 404 without
+/// it, and PutObject.
+fn mock_server(existing: Option<Existing>) -> (Client, Captured) {
+    let captured = Captured::default();
+    let recorder = captured.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        let existing = existing.clone();
+        async move {
+            let part = req.uri().query().unwrap_or("").contains("partNumber=1");
+            let response = match (req.method(), existing) {
+                (&Method::HEAD, Some(existing)) => {
+                    recorder
+                        .lock()
+                        .unwrap()
+                        .push(if part { "head-part" } else { "head" });
+                    let size = match (part, existing.first_part) {
+                        (true, Some(first_part)) => first_part,
+                        _ => existing.size,
+                    };
+                    Response::builder()
+                        .header("Content-Length", size)
+                        .header("ETag", format!("\"{}\"", existing.e_tag))
+                        .body(Body::empty())
+                }
+                (&Method::HEAD, None) => {
+                    recorder.lock().unwrap().push("head");
+                    Response::builder().status(404).body(Body::empty())
+                }
+                _ => {
+                    recorder.lock().unwrap().push("put");
+                    Response::builder()
+                        .header("ETag", "\"put-etag\"")
+                        .body(Body::empty())
+                }
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), captured)
+}
+
+fn test_file(content: &[u8]) -> String {
+    let path = std::env::temp_dir().join(format!("idempotent-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, content).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+/// The multipart ETag of `content` in parts of `part_size` bytes.
+fn multipart_etag(content: &[u8], part_size: usize) -> String {
+    let chunks: Vec<&[u8]> = content.chunks(part_size).collect();
+    let mut hasher = Md5::new();
+    for chunk in &chunks {
+        hasher.update(Md5::digest(chunk));
+    }
+    format!("{:x}-{}", hasher.finalize(), chunks.len())
+}
+
+#[test]
+fn test_multipart_etag_parts() {
+    assert_eq!(Some(3), multipart_etag_parts(&format!("{}-3", HELLO_MD5)));
+    assert_eq!(
+        Some(12),
+        multipart_etag_parts(&format!("\"{}-12\"", HELLO_MD5))
+    );
+    assert_eq!(None, multipart_etag_parts(HELLO_MD5));
+    assert_eq!(None, multipart_etag_parts(&format!("{}-0", HELLO_MD5)));
+    assert_eq!(None, multipart_etag_parts("not-an-md5-3"));
+}
+
+#[tokio::test]
+async fn test_local_etag() {
+    let content: Vec<u8> = (0..25u8).collect();
+    let file = test_file(&content);
+    let path = Path::new(&file);
+
+    let single = local_etag(path, 25, None).await.unwrap();
+    assert_eq!(format!("{:x}", Md5::digest(&content)), single);
+
+    // Parts of 10 bytes, the last one with the rest.
+    let layout = PartLayout {
+        num_parts: 3,
+        part_size: 10,
+    };
+    assert_eq!(
+        multipart_etag(&content, 10),
+        local_etag(path, 25, Some(layout)).await.unwrap()
+    );
+
+    // 3 parts of 20 bytes are more than the file.
+    let layout = PartLayout {
+        num_parts: 3,
+        part_size: 20,
+    };
+    let err = local_etag(path, 25, Some(layout)).await.unwrap_err();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(std::io::ErrorKind::InvalidInput, err.kind());
+}
+
+#[tokio::test]
+async fn test_already_uploaded() {
+    let (client, captured) = mock_server(Some(Existing {
+        size: 5,
+        e_tag: HELLO_MD5.to_string(),
+        first_part: None,
+    }));
+    let file = test_file(b"hello");
+
+    let result = upload_idempotent(&client, "bucket", "key", &file)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(
+        IdempotentUploadResult::AlreadyUploaded(HELLO_MD5.to_string()),
+        result
+    );
+    assert_eq!(vec!["head"], *captured.lock().unwrap());
+}
+
+#[tokio::test]
+async fn test_already_uploaded_in_parts() {
+    let content: Vec<u8> = (0..25u8).collect();
+    let e_tag = multipart_etag(&content, 10);
+    let (client, captured) = mock_server(Some(Existing {
+        size: 25,
+        e_tag: e_tag.clone(),
+        first_part: Some(10),
+    }));
+    let file = test_file(&content);
+
+    let result = upload_idempotent(&client, "bucket", "key", &file)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(IdempotentUploadResult::AlreadyUploaded(e_tag), result);
+    assert_eq!(vec!["head", "head-part"], *captured.lock().unwrap());
+}
+
+#[tokio::test]
+async fn test_uploads_on_mismatch() {
+    // Same size, other content.
+    let (client, captured) = mock_server(Some(Existing {
+        size: 5,
+        e_tag: format!("{:x}", Md5::digest(b"world")),
+        first_part: None,
+    }));
+    let file = test_file(b"hello");
+
+    let result = upload_idempotent(&client, "bucket", "key", &file)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(
+        IdempotentUploadResult::Uploaded("put-etag".to_string()),
+        result
+    );
+    assert_eq!(vec!["head", "put"], *captured.lock().unwrap());
+}
+
+#[tokio::test]
+async fn test_uploads_when_missing_or_not_md5() {
+    let (client, captured) = mock_server(None);
+    let file = test_file(b"hello");
+    let result = upload_idempotent(&client, "bucket", "key", &file)
+        .await
+        .unwrap();
+    assert_eq!("put-etag", result.e_tag());
+    assert_eq!(vec!["head", "put"], *captured.lock().unwrap());
+
+    // An SSE-KMS ETag cannot be compared, so the file is uploaded.
+    let (client, captured) = mock_server(Some(Existing {
+        size: 5,
+        e_tag: "a1b2c3d4e5f60718293a4b5c6d7e8f90aabbccdd".to_string(),
+        first_part: None,
+    }));
+    let result = upload_idempotent(&client, "bucket", "key", &file)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert!(matches!(result, IdempotentUploadResult::Uploaded(_)));
+    assert_eq!(vec!["head", "put"], *captured.lock().unwrap());
+}
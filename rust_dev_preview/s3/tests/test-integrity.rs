@@ -0,0 +1,298 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use futures::StreamExt;
+use hyper::{Body, Method, Request, Response};
+use s3_service::failover::EndpointPool;
+use s3_service::integrity::{
+    get_integrity_manifest, manifest_key, put_integrity_manifest, IntegrityManifest, UploadDigests,
+    INTEGRITY_SCHEMA_VERSION,
+};
+use s3_service::manifest::sha256_file;
+use s3_service::upload::{upload_chunk_with_digests, SourceWindow};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+/// Starts a server storing objects in memory, by path.
+async fn mock_bucket() -> (Client, Objects) {
+    let objects = Objects::default();
+    let store = objects.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let store = store.clone();
+        async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let if_none_match = req.headers().contains_key("if-none-match");
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let mut objects = store.lock().unwrap();
+            let response = match (method, objects.get(&path)) {
+                (Method::PUT, Some(_)) if if_none_match => {
+                    Response::builder().status(412).body(Body::from(
+                        "<Error><Code>PreconditionFailed</Code>\
+                         <Message>At least one of the pre-conditions you specified \
+                         did not hold</Message></Error>",
+                    ))
+                }
+                (Method::PUT, _) => {
+                    objects.insert(path, body.to_vec());
+                    Response::builder()
+                        .header("ETag", "\"etag\"")
+                        .body(Body::empty())
+                }
+                (Method::HEAD, Some(content)) => Response::builder()
+                    .header("Content-Length", content.len())
+                    .body(Body::empty()),
+                (Method::GET, Some(content)) => {
+                    Response::builder().body(Body::from(content.clone()))
+                }
+                (Method::HEAD, None) => Response::builder().status(404).body(Body::empty()),
+                _ => Response::builder().status(404).body(Body::from(
+                    "<Error><Code>NoSuchKey</Code>\
+                     <Message>The specified key does not exist.</Message></Error>",
+                )),
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), objects)
+}
+
+fn test_file(content: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("integrity-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+fn content() -> Vec<u8> {
+    (0..3000u32).map(|i| (i % 251) as u8).collect()
+}
+
+async fn manifest_for(path: &Path, num_parts: usize) -> IntegrityManifest {
+    let window = SourceWindow::for_file(&path.to_string_lossy(), None, None).unwrap();
+    IntegrityManifest::for_upload("data/file.bin", path, window, num_parts)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_manifest_describes_parts() {
+    let path = test_file(&content());
+    let manifest = manifest_for(&path, 3).await;
+
+    assert_eq!(INTEGRITY_SCHEMA_VERSION, manifest.schema_version);
+    assert_eq!(3000, manifest.size);
+    assert_eq!(sha256_file(&path).await.unwrap(), manifest.sha256);
+    let layout: Vec<(u32, u64, u64)> = manifest
+        .parts
+        .iter()
+        .map(|part| (part.number, part.offset, part.size))
+        .collect();
+    assert_eq!(vec![(1, 0, 1000), (2, 1000, 1000), (3, 2000, 1000)], layout);
+    assert!(manifest
+        .check_file(&path, SourceWindow::whole(3000))
+        .await
+        .unwrap()
+        .is_ok());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_window_offsets_are_relative() {
+    let path = test_file(&content());
+    let window = SourceWindow::for_file(&path.to_string_lossy(), Some(1000), Some(1500)).unwrap();
+    let manifest = IntegrityManifest::for_upload("key", &path, window, 2)
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![0, 750],
+        manifest.parts.iter().map(|p| p.offset).collect::<Vec<_>>()
+    );
+    assert!(manifest.check_file(&path, window).await.unwrap().is_ok());
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Reads `chunks` through the reader of part `number`, up to `read` of them.
+async fn read_part(digests: &UploadDigests, number: u32, chunks: &[&[u8]], read: usize) {
+    let stream = futures::stream::iter(
+        chunks
+            .iter()
+            .map(|chunk| Ok::<_, Infallible>(chunk.to_vec()))
+            .collect::<Vec<_>>(),
+    );
+    let reader = digests.reader(number, stream);
+    futures::pin_mut!(reader);
+    for _ in 0..read {
+        reader.next().await;
+    }
+}
+
+#[tokio::test]
+async fn test_upload_digests_keep_the_last_complete_read() {
+    let content = content();
+    let digests = UploadDigests::new();
+    read_part(&digests, 1, &[&content[..400], &content[400..1000]], 3).await;
+    // A failed attempt at part 2 stops reading before the end.
+    read_part(
+        &digests,
+        2,
+        &[&content[1000..1500], &content[1500..2000]],
+        1,
+    )
+    .await;
+    read_part(&digests, 2, &[&content[1000..2000]], 2).await;
+    read_part(&digests, 3, &[&content[2000..]], 2).await;
+
+    let path = test_file(&content);
+    let expected = manifest_for(&path, 3).await;
+    std::fs::remove_file(&path).unwrap();
+    let manifest = digests.manifest("data/file.bin", 3000).unwrap();
+    assert_eq!(expected.parts, manifest.parts);
+    assert_eq!(expected.sha256, manifest.sha256);
+
+    // An upload started again in one part replaces the three.
+    read_part(&digests, 1, &[&content[..]], 2).await;
+    let manifest = digests.manifest("data/file.bin", 3000).unwrap();
+    assert_eq!(1, manifest.parts.len());
+    assert_eq!(expected.sha256, manifest.sha256);
+}
+
+#[tokio::test]
+async fn test_upload_digests_need_every_part() {
+    let content = content();
+    let digests = UploadDigests::new();
+    read_part(&digests, 1, &[&content[..1000]], 2).await;
+    read_part(&digests, 3, &[&content[2000..]], 2).await;
+    assert!(digests.manifest("key", 3000).is_err());
+
+    let digests = UploadDigests::new();
+    read_part(&digests, 1, &[&content[..1000]], 2).await;
+    let err = digests.manifest("key", 3000).unwrap_err();
+    assert!(err.contains("read 1000 of its 3000 bytes"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_upload_is_hashed_as_it_is_sent() {
+    let (client, objects) = mock_bucket().await;
+    let path = test_file(&content());
+    let digests = UploadDigests::new();
+    upload_chunk_with_digests(
+        &EndpointPool::single(client),
+        "bucket",
+        "data/file.bin",
+        &path.to_string_lossy(),
+        1000,
+        1500,
+        None,
+        Some(&digests),
+    )
+    .await
+    .unwrap();
+    let manifest = digests.manifest("data/file.bin", 1500).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let sent = objects.lock().unwrap()["/bucket/data/file.bin"].clone();
+    assert_eq!(content()[1000..2500].to_vec(), sent);
+    assert_eq!(1, manifest.parts.len());
+    assert_eq!(0, manifest.parts[0].offset);
+    assert_eq!(1500, manifest.parts[0].size);
+    assert_eq!(format!("{:x}", Sha256::digest(&sent)), manifest.sha256);
+}
+
+#[test]
+fn test_newer_schema_is_rejected() {
+    let json = serde_json::json!({
+        "schema_version": INTEGRITY_SCHEMA_VERSION + 1,
+        "key": "key",
+    });
+    let err = IntegrityManifest::from_json(json.to_string().as_bytes()).unwrap_err();
+    assert!(err.contains("newer"));
+}
+
+#[tokio::test]
+async fn test_round_trip_detects_tampering() {
+    let (client, _) = mock_bucket().await;
+    let path = test_file(&content());
+    let manifest = manifest_for(&path, 3).await;
+    let key = put_integrity_manifest(&client, "bucket", &manifest, false)
+        .await
+        .unwrap();
+    assert_eq!("data/file.bin.integrity.json", key);
+
+    let fetched = get_integrity_manifest(&client, "bucket", "data/file.bin")
+        .await
+        .unwrap();
+    assert_eq!(manifest, fetched);
+
+    // One byte of the second part changes after the upload.
+    let mut tampered = content();
+    tampered[1500] ^= 0xff;
+    std::fs::write(&path, &tampered).unwrap();
+    let check = fetched
+        .check_file(&path, SourceWindow::whole(3000))
+        .await
+        .unwrap();
+    assert!(!check.is_ok());
+    assert_eq!(vec![2], check.mismatched_parts);
+    assert_ne!(Some(&manifest.sha256), check.actual_sha256.as_ref());
+
+    // A truncated download fails on its size alone.
+    std::fs::write(&path, &tampered[..2999]).unwrap();
+    let check = fetched
+        .check_file(&path, SourceWindow::whole(2999))
+        .await
+        .unwrap();
+    assert!(!check.size_matches);
+    assert!(!check.is_ok());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_existing_manifest_is_not_replaced() {
+    let (client, objects) = mock_bucket().await;
+    let path = test_file(&content());
+    let manifest = manifest_for(&path, 1).await;
+    std::fs::remove_file(&path).unwrap();
+    let stored = format!("/bucket/{}", manifest_key("data/file.bin"));
+    objects
+        .lock()
+        .unwrap()
+        .insert(stored.clone(), b"{}".to_vec());
+
+    let err = put_integrity_manifest(&client, "bucket", &manifest, false)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("already exists"));
+    assert_eq!(b"{}".to_vec(), objects.lock().unwrap()[&stored]);
+
+    put_integrity_manifest(&client, "bucket", &manifest, true)
+        .await
+        .unwrap();
+    assert_ne!(b"{}".to_vec(), objects.lock().unwrap()[&stored]);
+}
+
+#[tokio::test]
+async fn test_manifest_of_another_key_is_rejected() {
+    let (client, objects) = mock_bucket().await;
+    let path = test_file(&content());
+    let manifest = manifest_for(&path, 1).await;
+    std::fs::remove_file(&path).unwrap();
+    objects.lock().unwrap().insert(
+        format!("/bucket/{}", manifest_key("other")),
+        serde_json::to_vec(&manifest).unwrap(),
+    );
+    let err = get_integrity_manifest(&client, "bucket", "other")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("describes data/file.bin"));
+}
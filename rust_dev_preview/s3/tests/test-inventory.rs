@@ -0,0 +1,266 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::model::BucketLocationConstraint;
+use aws_sdk_s3::{Client, Credentials, Endpoint, Region, RetryConfig};
+use aws_types::credentials::SharedCredentialsProvider;
+use hyper::{Body, Method, Request, Response};
+use s3_service::inventory::{
+    aggregate_inventory_with_options, bucket_region, Totals, REPORT_PREFIX,
+};
+use std::sync::{Arc, Mutex};
+
+/// Account 111111111111 has two buckets, account 222222222222 does not
+/// trust the caller, and of the two buckets of account 333333333333, one
+/// denies access and the other denies its second page.
+fn respond(
+    method: &Method,
+    path: &str,
+    query: &str,
+    authorization: &str,
+    body: &str,
+) -> (u16, String) {
+    let access_denied = "<Error><Code>AccessDenied</Code><Message>Access Denied</Message></Error>";
+    let objects = |region: &str, contents: &[(&str, u64, &str)]| {
+        if !authorization.contains(&format!("/{}/s3/", region)) {
+            return (
+                400,
+                "<Error><Code>AuthorizationHeaderMalformed</Code></Error>".to_string(),
+            );
+        }
+        let contents: String = contents
+            .iter()
+            .map(|(key, size, class)| {
+                format!(
+                    "<Contents><Key>{}</Key><Size>{}</Size>\
+                     <StorageClass>{}</StorageClass></Contents>",
+                    key, size, class
+                )
+            })
+            .collect();
+        (
+            200,
+            format!(
+                "<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                contents
+            ),
+        )
+    };
+    let location = |constraint: &str| {
+        (
+            200,
+            format!("<LocationConstraint>{}</LocationConstraint>", constraint),
+        )
+    };
+    let account = if authorization.contains("Credential=AKID111111111111/") {
+        "111111111111"
+    } else if authorization.contains("Credential=AKID333333333333/") {
+        "333333333333"
+    } else {
+        "caller"
+    };
+    match (method, path, account) {
+        (&Method::POST, "/", _) => {
+            for trusting in &["111111111111", "333333333333"] {
+                if body.contains(&format!(
+                    "iam%3A%3A{}%3Arole%2FS3InventoryReadOnly",
+                    trusting
+                )) {
+                    return (
+                        200,
+                        format!(
+                            "<AssumeRoleResponse><AssumeRoleResult><Credentials>\
+                             <AccessKeyId>AKID{}</AccessKeyId>\
+                             <SecretAccessKey>secret</SecretAccessKey>\
+                             <SessionToken>token</SessionToken>\
+                             <Expiration>2030-01-01T00:00:00Z</Expiration>\
+                             </Credentials></AssumeRoleResult></AssumeRoleResponse>",
+                            trusting
+                        ),
+                    );
+                }
+            }
+            (
+                403,
+                "<ErrorResponse><Error><Type>Sender</Type><Code>AccessDenied</Code>\
+                 <Message>Not authorized to perform sts:AssumeRole</Message>\
+                 </Error></ErrorResponse>"
+                    .to_string(),
+            )
+        }
+        (&Method::GET, "/", "111111111111") => (
+            200,
+            "<ListAllMyBucketsResult><Buckets><Bucket><Name>logs</Name></Bucket>\
+             <Bucket><Name>archive</Name></Bucket></Buckets></ListAllMyBucketsResult>"
+                .to_string(),
+        ),
+        (&Method::GET, "/", "333333333333") => (
+            200,
+            "<ListAllMyBucketsResult><Buckets><Bucket><Name>private</Name></Bucket>\
+             <Bucket><Name>partial</Name></Bucket></Buckets></ListAllMyBucketsResult>"
+                .to_string(),
+        ),
+        (&Method::GET, "/partial", "333333333333") if query.contains("location") => location(""),
+        (&Method::GET, "/partial", "333333333333") if !query.contains("continuation-token") => (
+            200,
+            "<ListBucketResult><IsTruncated>true</IsTruncated>\
+             <NextContinuationToken>page-2</NextContinuationToken>\
+             <Contents><Key>d</Key><Size>7</Size><StorageClass>STANDARD</StorageClass></Contents>\
+             </ListBucketResult>"
+                .to_string(),
+        ),
+        (&Method::GET, "/logs", "111111111111") if query.contains("location") => location(""),
+        (&Method::GET, "/archive", "111111111111") if query.contains("location") => location("EU"),
+        (&Method::GET, "/logs", "111111111111") => objects(
+            "us-east-1",
+            &[("a", 100, "STANDARD"), ("b", 1000, "GLACIER")],
+        ),
+        (&Method::GET, "/archive", "111111111111") => {
+            objects("eu-west-1", &[("c", 50, "STANDARD_IA")])
+        }
+        (&Method::PUT, _, "caller") if path.starts_with("/dest/") => (200, String::new()),
+        _ => (403, access_denied.to_string()),
+    }
+}
+
+/// The key and body of the objects written.
+type Puts = Arc<Mutex<Vec<(String, String)>>>;
+
+async fn mock_endpoint() -> (u16, Puts) {
+    let puts: Puts = Arc::new(Mutex::new(Vec::new()));
+    let recorder = puts.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let query = req.uri().query().unwrap_or_default().to_string();
+            let authorization = req
+                .headers()
+                .get("authorization")
+                .map(|v| v.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            let (status, response) = respond(&method, &path, &query, &authorization, &body);
+            if method == Method::PUT && status == 200 {
+                recorder.lock().unwrap().push((path, body));
+            }
+            Response::builder()
+                .status(status)
+                .body(Body::from(response))
+                .unwrap()
+        }
+    });
+    (port, puts)
+}
+
+fn s3_client(port: u16, credentials: SharedCredentialsProvider, region: String) -> Client {
+    let conf = common::config_for(port)
+        .region(Region::new(region))
+        .set_credentials_provider(Some(credentials))
+        .build();
+    Client::from_conf(conf)
+}
+
+fn sts_client(port: u16) -> aws_sdk_sts::Client {
+    let conf = aws_sdk_sts::Config::builder()
+        .region(aws_sdk_sts::Region::new("us-east-1"))
+        .credentials_provider(aws_sdk_sts::Credentials::new(
+            "access", "secret", None, None, "test",
+        ))
+        .endpoint_resolver(aws_sdk_sts::Endpoint::immutable(
+            common::url_for(port).parse().unwrap(),
+        ))
+        .retry_config(aws_sdk_sts::RetryConfig::disabled())
+        .build();
+    aws_sdk_sts::Client::from_conf(conf)
+}
+
+#[test]
+fn test_bucket_region() {
+    assert_eq!("us-east-1", bucket_region(None));
+    assert_eq!(
+        "eu-west-1",
+        bucket_region(Some(&BucketLocationConstraint::from("EU")))
+    );
+    assert_eq!(
+        "ap-south-1",
+        bucket_region(Some(&BucketLocationConstraint::from("ap-south-1")))
+    );
+}
+
+#[tokio::test]
+async fn test_aggregate_inventory() {
+    let (port, puts) = mock_endpoint().await;
+    let caller =
+        SharedCredentialsProvider::new(Credentials::new("access", "secret", None, None, "test"));
+    let client_for = |credentials, region| s3_client(port, credentials, region);
+
+    let report = aggregate_inventory_with_options(
+        &sts_client(port),
+        &s3_client(port, caller, "us-east-1".to_string()),
+        &["111111111111", "222222222222", "333333333333"],
+        "dest",
+        "S3InventoryReadOnly",
+        &client_for,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(3, report.total_objects);
+    assert_eq!(1150, report.total_bytes);
+    assert_eq!(
+        Some(&Totals {
+            objects: 1,
+            bytes: 1000
+        }),
+        report.storage_class_breakdown.get("GLACIER")
+    );
+    assert_eq!(3, report.storage_class_breakdown.len());
+    assert_eq!(
+        Some(&Totals {
+            objects: 2,
+            bytes: 1100
+        }),
+        report.region_breakdown.get("us-east-1")
+    );
+    assert_eq!(
+        Some(&Totals {
+            objects: 1,
+            bytes: 50
+        }),
+        report.region_breakdown.get("eu-west-1")
+    );
+
+    // The denied account and buckets are reported, not fatal, and the
+    // object of the first page of the partial bucket is not counted.
+    assert_eq!(3, report.accounts.len());
+    assert_eq!(2, report.accounts[0].buckets);
+    assert!(report.accounts[0].access_denied.is_empty());
+    let denied: Vec<_> = report
+        .denied_accounts()
+        .map(|account| account.account_id.as_str())
+        .collect();
+    assert_eq!(vec!["222222222222", "333333333333"], denied);
+    assert!(report.accounts[2].access_denied[0].starts_with("private: "));
+    assert!(report.accounts[2].access_denied[1].starts_with("partial: "));
+    assert_eq!(0, report.accounts[2].total_objects);
+
+    let puts = puts.lock().unwrap();
+    assert_eq!(1, puts.len());
+    let (path, body) = &puts[0];
+    assert_eq!(format!("/dest/{}", report.key), *path);
+    assert!(report.key.starts_with(REPORT_PREFIX));
+    let uploaded: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(3, uploaded["total_objects"]);
+    assert_eq!(
+        1000,
+        uploaded["storage_class_breakdown"]["GLACIER"]["bytes"]
+    );
+    assert_eq!(1150, uploaded["accounts"][0]["total_bytes"]);
+}
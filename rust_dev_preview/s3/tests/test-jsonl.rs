@@ -0,0 +1,85 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use s3_service::jsonl::{upload_jsonl, UploadStats};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+#[tokio::test]
+async fn test_one_line_per_record() {
+    let (client, uploads) = common::mock_uploads();
+    let records = vec![
+        json!({"id": 1, "name": "first"}),
+        // Newlines in strings are escaped, so the record stays on one line.
+        json!({"id": 2, "name": "two\nlines"}),
+    ];
+
+    let stats = upload_jsonl(
+        &client,
+        "bucket",
+        "records.jsonl",
+        futures::stream::iter(records),
+    )
+    .await
+    .unwrap();
+
+    let expected = "{\"id\":1,\"name\":\"first\"}\n{\"id\":2,\"name\":\"two\\nlines\"}\n";
+    assert_eq!(
+        UploadStats {
+            record_count: 2,
+            total_bytes: expected.len() as u64,
+            part_count: 1,
+        },
+        stats
+    );
+    assert_eq!(
+        expected.as_bytes(),
+        &uploads.lock().unwrap().objects["records.jsonl"][..]
+    );
+}
+
+#[tokio::test]
+async fn test_empty_stream_uploads_an_empty_object() {
+    let (client, uploads) = common::mock_uploads();
+
+    let stats = upload_jsonl(
+        &client,
+        "bucket",
+        "empty.jsonl",
+        futures::stream::iter(Vec::<u32>::new()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(0, stats.record_count);
+    assert!(uploads.lock().unwrap().objects["empty.jsonl"].is_empty());
+}
+
+/// A record JSON cannot represent: its map has keys that are not strings.
+#[derive(Serialize)]
+struct Unrepresentable(BTreeMap<Vec<u8>, u32>);
+
+#[tokio::test]
+async fn test_serialization_error_uploads_nothing() {
+    let (client, uploads) = common::mock_uploads();
+    let mut map = BTreeMap::new();
+    map.insert(vec![1, 2], 3);
+
+    let result = upload_jsonl(
+        &client,
+        "bucket",
+        "bad.jsonl",
+        futures::stream::iter(vec![Unrepresentable(map)]),
+    )
+    .await;
+
+    assert!(result.is_err());
+    let uploads = uploads.lock().unwrap();
+    assert!(uploads.requests.is_empty());
+    assert!(uploads.objects.is_empty());
+}
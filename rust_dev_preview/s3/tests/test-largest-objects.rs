@@ -0,0 +1,151 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use s3_service::largest_objects::{list_largest_objects, render_largest_table};
+use std::sync::{Arc, Mutex};
+
+/// The objects of the bucket: key, size, and storage class.
+const OBJECTS: [(&str, u64, &str); 7] = [
+    ("backups/db.dump", 9000, "GLACIER"),
+    ("logs/a", 100, "STANDARD"),
+    ("logs/b", 2000, "STANDARD"),
+    ("logs/c", 2000, "STANDARD_IA"),
+    ("logs/d", 50, "STANDARD"),
+    ("logs/e", 3000, "STANDARD"),
+    ("logs/f", 5, "STANDARD"),
+];
+
+/// Starts a server listing the `OBJECTS` under the requested prefix in pages
+/// of two, recording the query of each request.
+async fn mock_s3() -> (Client, Arc<Mutex<Vec<String>>>) {
+    let queries = Arc::new(Mutex::new(Vec::new()));
+    let recorder = queries.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let query = req.uri().query().unwrap_or("").to_string();
+            recorder.lock().unwrap().push(query.clone());
+            let prefix = query
+                .split('&')
+                .find_map(|p| p.strip_prefix("prefix="))
+                .map(|prefix| prefix.replace("%2F", "/"))
+                .unwrap_or_default();
+            let page: usize = query
+                .split('&')
+                .find_map(|p| p.strip_prefix("continuation-token="))
+                .map(|token| token.parse().unwrap())
+                .unwrap_or(0);
+            let listed: Vec<_> = OBJECTS
+                .iter()
+                .filter(|(key, _, _)| key.starts_with(&prefix))
+                .collect();
+            let contents: String = listed
+                .iter()
+                .skip(page * 2)
+                .take(2)
+                .map(|(key, size, class)| {
+                    format!(
+                        "<Contents><Key>{}</Key><Size>{}</Size>\
+                         <LastModified>2022-03-01T12:00:00.000Z</LastModified>\
+                         <StorageClass>{}</StorageClass></Contents>",
+                        key, size, class
+                    )
+                })
+                .collect();
+            let more = (page + 1) * 2 < listed.len();
+            let next = if more {
+                format!(
+                    "<NextContinuationToken>{}</NextContinuationToken>",
+                    page + 1
+                )
+            } else {
+                String::new()
+            };
+            Response::builder()
+                .body(Body::from(format!(
+                    "<ListBucketResult><Name>bucket</Name>\
+                         <IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>",
+                    more, next, contents
+                )))
+                .unwrap()
+        }
+    });
+
+    (common::client_for(port), queries)
+}
+
+fn keys(objects: &[aws_sdk_s3::model::Object]) -> Vec<&str> {
+    objects
+        .iter()
+        .map(|object| object.key().unwrap_or_default())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_keeps_the_largest_across_pages() {
+    let (client, queries) = mock_s3().await;
+
+    let largest = list_largest_objects(&client, "bucket", None, 3)
+        .await
+        .unwrap();
+
+    assert_eq!(vec!["backups/db.dump", "logs/e", "logs/b"], keys(&largest));
+    assert_eq!(4, queries.lock().unwrap().len());
+    assert!(queries
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|q| !q.contains("prefix=")));
+}
+
+#[tokio::test]
+async fn test_prefix_ties_and_fewer_objects_than_top() {
+    let (client, queries) = mock_s3().await;
+
+    // Of the objects of the same size, the first by key come first.
+    let largest = list_largest_objects(&client, "bucket", Some("logs/"), 10)
+        .await
+        .unwrap();
+    assert_eq!(
+        vec!["logs/e", "logs/b", "logs/c", "logs/a", "logs/d", "logs/f"],
+        keys(&largest)
+    );
+    assert!(queries.lock().unwrap()[0].contains("prefix=logs"));
+
+    let none = list_largest_objects(&client, "bucket", None, 0)
+        .await
+        .unwrap();
+    assert!(none.is_empty());
+}
+
+#[tokio::test]
+async fn test_huge_top_lists_every_object() {
+    let (client, _) = mock_s3().await;
+
+    let largest = list_largest_objects(&client, "bucket", Some("logs/"), usize::MAX)
+        .await
+        .unwrap();
+
+    assert_eq!(6, largest.len());
+}
+
+#[tokio::test]
+async fn test_render_largest_table() {
+    let (client, _) = mock_s3().await;
+    let largest = list_largest_objects(&client, "bucket", None, 2)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        "Key                    Size  Storage class        Last modified\n\
+         backups/db.dump     8.8 KiB  GLACIER              2022-03-01T12:00:00Z\n\
+         logs/e              2.9 KiB  STANDARD             2022-03-01T12:00:00Z",
+        render_largest_table(&largest)
+    );
+}
@@ -0,0 +1,302 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::Client;
+use csv_async::AsyncReaderBuilder;
+use futures::StreamExt;
+use hyper::{Body, Method, Request, Response};
+use percent_encoding::percent_decode_str;
+use s3_service::listing::{
+    decode_key, display_key, list_objects, KeyEncoding, ListedObject, Listing,
+};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Keys that break line-based and naive CSV consumers.
+const NASTY_KEYS: &[&str] = &[
+    "line\nbreak.txt",
+    "carriage\rreturn.txt",
+    "tab\tand,comma.csv",
+    "quote\"d \"key\".txt",
+    "bell\u{7}and\u{1b}escape",
+    "space and+plus%20literal",
+    "unicode/é/漢字/🦀.txt",
+    "trailing space ",
+];
+
+/// Listed per page, to go through continuation tokens.
+const PAGE_SIZE: usize = 3;
+
+/// As S3 encodes keys with `encoding-type=url`.
+fn form_encode(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            b' ' => "+".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn query_value(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let mut split = pair.splitn(2, '=');
+        (split.next() == Some(name)).then(|| {
+            percent_decode_str(split.next().unwrap_or(""))
+                .decode_utf8_lossy()
+                .into_owned()
+        })
+    })
+}
+
+fn list_response(objects: &BTreeMap<String, Vec<u8>>, query: &str) -> String {
+    let url = query_value(query, "encoding-type").as_deref() == Some("url");
+    let start: usize = query_value(query, "continuation-token")
+        .map(|token| token.parse().unwrap())
+        .unwrap_or(0);
+    let page: Vec<_> = objects.iter().skip(start).take(PAGE_SIZE).collect();
+    let truncated = start + page.len() < objects.len();
+    let contents: String = page
+        .iter()
+        .map(|(key, data)| {
+            let key = if url {
+                form_encode(key)
+            } else {
+                xml_escape(key)
+            };
+            format!(
+                "<Contents><Key>{}</Key><LastModified>2022-03-01T12:00:00.000Z</LastModified>\
+                 <ETag>\"etag-{}\"</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass>\
+                 </Contents>",
+                key,
+                data.len(),
+                data.len()
+            )
+        })
+        .collect();
+    let next = if truncated {
+        format!(
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            start + PAGE_SIZE
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        "<ListBucketResult><Name>bucket</Name><KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys>\
+         <IsTruncated>{}</IsTruncated>{}{}{}</ListBucketResult>",
+        page.len(),
+        PAGE_SIZE,
+        truncated,
+        if url {
+            "<EncodingType>url</EncodingType>"
+        } else {
+            ""
+        },
+        next,
+        contents
+    )
+}
+
+/// Starts a server storing the objects put, listing them, and serving them.
+async fn mock_s3() -> Client {
+    let objects = Arc::new(Mutex::new(BTreeMap::<String, Vec<u8>>::new()));
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let objects = objects.clone();
+        async move {
+            let path = req.uri().path().to_string();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let key = percent_decode_str(path.trim_start_matches("/bucket"))
+                .decode_utf8()
+                .unwrap()
+                .trim_start_matches('/')
+                .to_string();
+            let response = match *req.method() {
+                Method::PUT => {
+                    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    objects.lock().unwrap().insert(key, body.to_vec());
+                    Response::builder()
+                        .header("ETag", "\"etag\"")
+                        .body(Body::empty())
+                }
+                Method::GET if key.is_empty() => Response::builder()
+                    .body(Body::from(list_response(&objects.lock().unwrap(), &query))),
+                Method::GET => match objects.lock().unwrap().get(&key) {
+                    Some(data) => Response::builder().body(Body::from(data.clone())),
+                    None => Response::builder()
+                        .status(404)
+                        .body(Body::from("<Error><Code>NoSuchKey</Code></Error>")),
+                },
+                _ => Response::builder().status(405).body(Body::empty()),
+            };
+            response.unwrap()
+        }
+    });
+
+    common::client_for(port)
+}
+
+async fn upload_nasty_keys(client: &Client) {
+    for key in NASTY_KEYS {
+        client
+            .put_object()
+            .bucket("bucket")
+            .key(*key)
+            .body(ByteStream::from(key.as_bytes().to_vec()))
+            .send()
+            .await
+            .unwrap();
+    }
+}
+
+fn sorted(keys: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut keys: Vec<_> = keys.into_iter().collect();
+    keys.sort();
+    keys
+}
+
+#[test]
+fn test_decode_and_display_key() {
+    assert_eq!("a b+c\n", decode_key("a+b%2Bc%0A"));
+    assert_eq!("漢字", decode_key("%E6%BC%A2%E5%AD%97"));
+    assert_eq!(
+        "line\\nbreak",
+        display_key("line\nbreak", KeyEncoding::None)
+    );
+    assert_eq!("bell\\u{7}", display_key("bell%07", KeyEncoding::Url));
+    assert_eq!("a+b", display_key("a+b", KeyEncoding::None));
+}
+
+#[tokio::test]
+async fn test_nasty_keys_round_trip() {
+    let client = mock_s3().await;
+    upload_nasty_keys(&client).await;
+
+    let listing = list_objects(&client, "bucket", "", KeyEncoding::Url)
+        .await
+        .unwrap();
+
+    assert_eq!(NASTY_KEYS.len(), listing.objects.len());
+    // The encoded keys are plain ASCII without control characters.
+    for object in &listing.objects {
+        assert!(
+            object.key.chars().all(|c| c.is_ascii_graphic()),
+            "{:?}",
+            object.key
+        );
+    }
+    let decoded = sorted(listing.objects.iter().map(|o| decode_key(&o.key)));
+    assert_eq!(sorted(NASTY_KEYS.iter().map(|k| k.to_string())), decoded);
+
+    // Each decoded key downloads the object uploaded under it.
+    for key in decoded {
+        let resp = client
+            .get_object()
+            .bucket("bucket")
+            .key(&key)
+            .send()
+            .await
+            .unwrap();
+        let body = resp.body.collect().await.unwrap().into_bytes();
+        assert_eq!(key.as_bytes(), &body[..]);
+    }
+
+    // One line per key in the text output.
+    assert_eq!(NASTY_KEYS.len(), listing.to_text().lines().count());
+}
+
+#[tokio::test]
+async fn test_json_output_keeps_encoded_keys() {
+    let client = mock_s3().await;
+    upload_nasty_keys(&client).await;
+    let listing = list_objects(&client, "bucket", "", KeyEncoding::Url)
+        .await
+        .unwrap();
+
+    let json: serde_json::Value = serde_json::from_str(&listing.to_json()).unwrap();
+
+    assert_eq!("url", json["encoding_type"]);
+    let objects = json["objects"].as_array().unwrap();
+    let keys: Vec<String> = objects
+        .iter()
+        .map(|o| o["key"].as_str().unwrap().to_string())
+        .collect();
+    let listed: Vec<String> = listing.objects.iter().map(|o| o.key.clone()).collect();
+    assert_eq!(listed, keys);
+    assert_eq!(
+        sorted(NASTY_KEYS.iter().map(|k| k.to_string())),
+        sorted(keys.iter().map(|k| decode_key(k)))
+    );
+    assert_eq!("2022-03-01T12:00:00Z", objects[0]["last_modified"]);
+}
+
+#[tokio::test]
+async fn test_csv_output_quotes_raw_keys() {
+    // Raw keys, as listed without encoding, are the hardest case for CSV.
+    let listing = Listing {
+        encoding_type: None,
+        objects: NASTY_KEYS
+            .iter()
+            .enumerate()
+            .map(|(i, key)| ListedObject {
+                key: key.to_string(),
+                size: i as u64,
+                last_modified: Some("2022-03-01T12:00:00Z".to_string()),
+                e_tag: Some(format!("etag-{}", i)),
+            })
+            .collect(),
+    };
+
+    let csv = listing.to_csv().await.unwrap();
+
+    let mut reader = AsyncReaderBuilder::new()
+        .has_headers(true)
+        .create_reader(csv.as_bytes());
+    assert_eq!(
+        vec!["key", "size", "last_modified", "e_tag"],
+        reader.headers().await.unwrap().iter().collect::<Vec<_>>()
+    );
+    let records: Vec<_> = reader.records().map(|r| r.unwrap()).collect().await;
+    assert_eq!(NASTY_KEYS.len(), records.len());
+    for (i, record) in records.iter().enumerate() {
+        assert_eq!(NASTY_KEYS[i], &record[0]);
+        assert_eq!(i.to_string(), &record[1]);
+        assert_eq!("2022-03-01T12:00:00Z", &record[2]);
+        assert_eq!(format!("etag-{}", i), &record[3]);
+    }
+}
+
+#[tokio::test]
+async fn test_listing_without_encoding() {
+    let client = mock_s3().await;
+    client
+        .put_object()
+        .bucket("bucket")
+        .key("a & b <c>.txt")
+        .body(ByteStream::from_static(b"data"))
+        .send()
+        .await
+        .unwrap();
+
+    let listing = list_objects(&client, "bucket", "", KeyEncoding::None)
+        .await
+        .unwrap();
+
+    assert_eq!(None, listing.encoding_type);
+    assert_eq!("a & b <c>.txt", listing.objects[0].key);
+    assert_eq!(4, listing.objects[0].size);
+    assert_eq!(Some("etag-4".to_string()), listing.objects[0].e_tag);
+}
@@ -0,0 +1,116 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::types::ByteStream;
+use s3_service::manifest::{
+    download_and_verify, generate_manifest, sha256_file, sha256_window, Manifest,
+};
+use s3_service::upload::SourceWindow;
+use std::path::PathBuf;
+
+const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn test_generate_manifest() {
+    let dir = temp_dir("manifest-test");
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("b.txt"), "abc").unwrap();
+    std::fs::write(dir.join("sub/a.txt"), "").unwrap();
+
+    let manifest = generate_manifest(&dir, "backup/").await.unwrap();
+    let path = dir.join("manifest.json");
+    manifest.save(&path).unwrap();
+    assert_eq!(manifest, Manifest::load(&path).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let keys = manifest
+        .objects
+        .iter()
+        .map(|entry| entry.key.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["backup/b.txt", "backup/sub/a.txt"], keys);
+    assert_eq!(3, manifest.objects[0].size);
+    assert_eq!(ABC_SHA256, manifest.objects[0].sha256);
+    assert_eq!(
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        manifest.objects[1].sha256
+    );
+}
+
+#[tokio::test]
+async fn test_sha256_window() {
+    let dir = temp_dir("manifest-window");
+    let path = dir.join("file");
+    std::fs::write(&path, "xxabcyy").unwrap();
+    let abc = SourceWindow::resolve(7, Some(2), Some(3)).unwrap();
+    let window_sha256 = sha256_window(&path, abc).await.unwrap();
+    let whole = sha256_window(&path, SourceWindow::whole(7)).await.unwrap();
+    let file_sha256 = sha256_file(&path).await.unwrap();
+    let empty = sha256_window(&path, SourceWindow::resolve(7, Some(7), None).unwrap())
+        .await
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(ABC_SHA256, window_sha256);
+    assert_eq!(file_sha256, whole);
+    assert_eq!(
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        empty
+    );
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_download_and_verify() {
+    let client = common::minio_client().await;
+    let bucket = common::create_test_bucket(&client).await;
+    let source = temp_dir("manifest-source");
+    std::fs::write(source.join("good.txt"), "abc").unwrap();
+    std::fs::write(source.join("tampered.txt"), "abc").unwrap();
+    std::fs::write(source.join("lost.txt"), "abc").unwrap();
+    let manifest = generate_manifest(&source, "backup/").await.unwrap();
+    let manifest_path = source.join("manifest.json");
+    manifest.save(&manifest_path).unwrap();
+
+    for (key, content) in [("backup/good.txt", "abc"), ("backup/tampered.txt", "abd")] {
+        client
+            .put_object()
+            .bucket(&bucket)
+            .key(key)
+            .body(ByteStream::from(content.as_bytes().to_vec()))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let dest = temp_dir("manifest-dest");
+    let report = download_and_verify(&client, &bucket, &manifest_path, &dest)
+        .await
+        .unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(vec!["backup/good.txt"], report.verified);
+    assert_eq!(vec!["backup/lost.txt"], report.missing);
+    assert_eq!(1, report.mismatched.len());
+    let (key, expected, actual) = &report.mismatched[0];
+    assert_eq!("backup/tampered.txt", key);
+    assert_eq!(ABC_SHA256, expected);
+    assert_ne!(expected, actual);
+    assert_eq!(
+        "abc",
+        std::fs::read_to_string(dest.join("good.txt")).unwrap()
+    );
+
+    std::fs::remove_dir_all(&source).unwrap();
+    std::fs::remove_dir_all(&dest).unwrap();
+    common::delete_test_bucket(&client, &bucket).await;
+}
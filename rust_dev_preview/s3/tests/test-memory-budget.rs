@@ -0,0 +1,160 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use s3_service::memory_budget::{MemoryBudget, MemoryStats};
+use std::time::Duration;
+use tokio::time::timeout;
+
+const MIB: u64 = 1024 * 1024;
+
+#[test]
+fn test_reservations_are_counted_until_dropped() {
+    let budget = MemoryBudget::new(100);
+
+    let first = budget.try_acquire(60).unwrap();
+    let second = budget.try_acquire(40).unwrap();
+    assert_eq!(100, budget.used());
+    assert!(budget.try_acquire(1).is_none());
+
+    drop(first);
+    assert_eq!(40, budget.used());
+    drop(second);
+    assert_eq!(0, budget.used());
+    assert_eq!(
+        MemoryStats {
+            limit: 100,
+            peak: 100,
+            waits: 0
+        },
+        budget.stats()
+    );
+}
+
+#[test]
+fn test_oversized_reservation_is_granted_alone() {
+    let budget = MemoryBudget::new(100);
+
+    let oversized = budget.try_acquire(500).unwrap();
+    assert!(budget.try_acquire(1).is_none());
+    drop(oversized);
+
+    let small = budget.try_acquire(10).unwrap();
+    assert!(budget.try_acquire(500).is_none());
+    drop(small);
+}
+
+#[tokio::test]
+async fn test_acquire_waits_for_a_release() {
+    let budget = MemoryBudget::new(100);
+    let first = budget.acquire(80).await;
+
+    let mut waiting = {
+        let budget = budget.clone();
+        tokio::spawn(async move { budget.acquire(50).await })
+    };
+    assert!(timeout(Duration::from_millis(50), &mut waiting)
+        .await
+        .is_err());
+    assert_eq!(80, budget.used());
+
+    drop(first);
+    let second = timeout(Duration::from_secs(5), waiting)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(50, second.bytes());
+    assert_eq!(50, budget.used());
+    assert_eq!(1, budget.stats().waits);
+}
+
+#[tokio::test]
+async fn test_cancelled_acquire_reserves_nothing() {
+    let budget = MemoryBudget::new(100);
+    let held = budget.acquire(100).await;
+
+    assert!(timeout(Duration::from_millis(50), budget.acquire(30))
+        .await
+        .is_err());
+    assert_eq!(100, budget.used());
+
+    drop(held);
+    assert_eq!(0, budget.used());
+    let reservation = timeout(Duration::from_secs(5), budget.acquire(100))
+        .await
+        .unwrap();
+    assert_eq!(100, budget.used());
+    drop(reservation);
+}
+
+#[tokio::test]
+async fn test_waiters_are_served_in_order() {
+    let budget = MemoryBudget::new(100);
+    let held = budget.acquire(60).await;
+
+    let big = {
+        let budget = budget.clone();
+        tokio::spawn(async move { budget.acquire(80).await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // 30 bytes fit beside the 60 held, but the 80 asked first.
+    assert!(budget.try_acquire(30).is_none());
+    let mut small = {
+        let budget = budget.clone();
+        tokio::spawn(async move { budget.acquire(30).await })
+    };
+    assert!(timeout(Duration::from_millis(50), &mut small)
+        .await
+        .is_err());
+
+    drop(held);
+    let big = timeout(Duration::from_secs(5), big).await.unwrap().unwrap();
+    assert_eq!(80, budget.used());
+    assert!(timeout(Duration::from_millis(50), &mut small)
+        .await
+        .is_err());
+
+    drop(big);
+    let small = timeout(Duration::from_secs(5), small)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(30, small.bytes());
+    assert_eq!(2, budget.stats().waits);
+}
+
+#[test]
+fn test_fit_warning_suggests_values_that_fit() {
+    let budget = MemoryBudget::new(64 * MIB);
+
+    assert_eq!(None, budget.fit_warning(8, 8 * MIB));
+    let warning = budget.fit_warning(16, 8 * MIB).unwrap();
+
+    assert!(warning.contains("need 128.0 MiB"), "{}", warning);
+    assert!(
+        warning.contains("a concurrency of at most 8"),
+        "{}",
+        warning
+    );
+    assert!(
+        warning.contains("a part size of at most 4.0 MiB"),
+        "{}",
+        warning
+    );
+    assert!(warning.contains("--memory-limit"), "{}", warning);
+}
+
+#[test]
+fn test_fit_warning_without_a_fitting_concurrency() {
+    let budget = MemoryBudget::new(4 * MIB);
+
+    let warning = budget.fit_warning(2, 8 * MIB).unwrap();
+
+    assert!(!warning.contains("concurrency of at most"), "{}", warning);
+    assert!(
+        warning.contains("a part size of at most 2.0 MiB"),
+        "{}",
+        warning
+    );
+}
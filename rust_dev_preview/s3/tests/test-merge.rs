@@ -0,0 +1,300 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::merge::{copy_ranges, merge_objects_with_options, MergeOptions};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A part received by the mock.
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Upload(Vec<u8>),
+    Copy { key: String, first: u64, last: u64 },
+}
+
+#[derive(Debug, Default)]
+struct Received {
+    parts: Vec<(i32, Part)>,
+    created: bool,
+    completed: Option<String>,
+    aborted: bool,
+}
+
+type Log = Arc<Mutex<Received>>;
+
+fn query_value<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').find_map(|pair| {
+        let mut split = pair.splitn(2, '=');
+        (split.next() == Some(name)).then(|| split.next().unwrap_or(""))
+    })
+}
+
+fn parse_range(range: &str) -> (u64, u64) {
+    let range = range.trim_start_matches("bytes=");
+    let (first, last) = range.split_at(range.find('-').unwrap());
+    (first.parse().unwrap(), last[1..].parse().unwrap())
+}
+
+/// Starts a server holding `objects` in `bucket` and recording the
+/// multipart upload of the merge.
+async fn mock_s3(objects: Vec<(&str, Vec<u8>)>) -> (Client, Log) {
+    let objects: Arc<HashMap<String, Vec<u8>>> = Arc::new(
+        objects
+            .into_iter()
+            .map(|(key, data)| (format!("/bucket/{}", key), data))
+            .collect(),
+    );
+    let log = Log::default();
+    let received = log.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let log = received.clone();
+        let objects = objects.clone();
+        async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let response = match method {
+                Method::HEAD => match objects.get(&path) {
+                    Some(data) => Response::builder()
+                        .header("Content-Length", data.len())
+                        .body(Body::empty()),
+                    None => Response::builder().status(404).body(Body::empty()),
+                },
+                Method::GET => {
+                    let data = &objects[&path];
+                    let range = req.headers()["range"].to_str().unwrap();
+                    let (first, last) = parse_range(range);
+                    Response::builder()
+                        .status(206)
+                        .body(Body::from(data[first as usize..=last as usize].to_vec()))
+                }
+                Method::POST if query_value(&query, "uploads").is_some() => {
+                    log.lock().unwrap().created = true;
+                    Response::builder().body(Body::from(
+                        "<InitiateMultipartUploadResult><Bucket>bucket</Bucket>\
+                         <Key>merged</Key><UploadId>upload</UploadId>\
+                         </InitiateMultipartUploadResult>",
+                    ))
+                }
+                Method::POST => {
+                    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    log.lock().unwrap().completed = Some(String::from_utf8(body.to_vec()).unwrap());
+                    Response::builder().body(Body::from(
+                        "<CompleteMultipartUploadResult><Bucket>bucket</Bucket>\
+                         <Key>merged</Key><ETag>\"merged-etag\"</ETag>\
+                         </CompleteMultipartUploadResult>",
+                    ))
+                }
+                Method::PUT => {
+                    let part_number: i32 =
+                        query_value(&query, "partNumber").unwrap().parse().unwrap();
+                    let copy = req
+                        .headers()
+                        .get("x-amz-copy-source")
+                        .map(|v| v.to_str().unwrap().to_string());
+                    match copy {
+                        Some(source) => {
+                            let range = req.headers()["x-amz-copy-source-range"].to_str().unwrap();
+                            let (first, last) = parse_range(range);
+                            let key = source.trim_start_matches("bucket/").to_string();
+                            log.lock()
+                                .unwrap()
+                                .parts
+                                .push((part_number, Part::Copy { key, first, last }));
+                            Response::builder().body(Body::from(format!(
+                                "<CopyPartResult><ETag>\"copy-{}\"</ETag></CopyPartResult>",
+                                part_number
+                            )))
+                        }
+                        None => {
+                            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                            log.lock()
+                                .unwrap()
+                                .parts
+                                .push((part_number, Part::Upload(body.to_vec())));
+                            Response::builder()
+                                .header("ETag", format!("\"part-{}\"", part_number))
+                                .body(Body::empty())
+                        }
+                    }
+                }
+                Method::DELETE => {
+                    log.lock().unwrap().aborted = true;
+                    Response::builder().status(204).body(Body::empty())
+                }
+                _ => Response::builder().status(405).body(Body::empty()),
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), log)
+}
+
+fn bytes(fill: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| fill.wrapping_add(i as u8)).collect()
+}
+
+/// The merged object, rebuilt from the parts received in order.
+fn assemble(parts: &[(i32, Part)], sources: &HashMap<&str, Vec<u8>>) -> Vec<u8> {
+    let mut parts = parts.to_vec();
+    parts.sort_by_key(|(n, _)| *n);
+    let mut merged = Vec::new();
+    for (_, part) in parts {
+        match part {
+            Part::Upload(data) => merged.extend(data),
+            Part::Copy { key, first, last } => {
+                merged.extend(&sources[key.as_str()][first as usize..=last as usize])
+            }
+        }
+    }
+    merged
+}
+
+#[test]
+fn test_copy_ranges() {
+    assert_eq!(vec![(3, 24)], copy_ranges(3, 22, 100));
+    assert_eq!(vec![(0, 8), (9, 16), (17, 24)], copy_ranges(0, 25, 10));
+    assert_eq!(vec![(0, 9), (10, 19)], copy_ranges(0, 20, 10));
+    assert!(copy_ranges(0, 0, 10).is_empty());
+}
+
+#[tokio::test]
+async fn test_small_sources_are_buffered_and_large_ones_copied() {
+    let sources = vec![
+        ("a", bytes(0, 4)),
+        ("b", bytes(50, 3)),
+        ("c", bytes(100, 25)),
+        ("empty", Vec::new()),
+        ("d", bytes(150, 12)),
+        ("e", bytes(200, 2)),
+    ];
+    let expected: Vec<u8> = sources.iter().flat_map(|(_, data)| data.clone()).collect();
+    let by_key: HashMap<_, _> = sources.iter().cloned().collect();
+    let (client, log) = mock_s3(sources).await;
+    let options = MergeOptions {
+        min_part_size: 10,
+        ..Default::default()
+    };
+
+    let e_tag = merge_objects_with_options(
+        &client,
+        "bucket",
+        &["a", "b", "c", "empty", "d", "e"],
+        "merged",
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!("merged-etag", e_tag);
+    let log = log.lock().unwrap();
+    // a, b, and the start of c make the first part; the rest of c and d are
+    // copied; e is the small last part.
+    assert_eq!(
+        vec![
+            (1, Part::Upload(expected[..10].to_vec())),
+            (
+                2,
+                Part::Copy {
+                    key: "c".to_string(),
+                    first: 3,
+                    last: 24
+                }
+            ),
+            (
+                3,
+                Part::Copy {
+                    key: "d".to_string(),
+                    first: 0,
+                    last: 11
+                }
+            ),
+            (4, Part::Upload(bytes(200, 2))),
+        ],
+        log.parts
+    );
+    assert_eq!(expected, assemble(&log.parts, &by_key));
+    let completed = log.completed.as_ref().unwrap();
+    for n in 1..=4 {
+        assert!(completed.contains(&format!("<PartNumber>{}</PartNumber>", n)));
+    }
+    assert!(!log.aborted);
+}
+
+#[tokio::test]
+async fn test_large_source_is_copied_in_ranges() {
+    let sources = vec![("a", bytes(0, 3)), ("big", bytes(10, 30))];
+    let expected: Vec<u8> = sources.iter().flat_map(|(_, data)| data.clone()).collect();
+    let by_key: HashMap<_, _> = sources.iter().cloned().collect();
+    let (client, log) = mock_s3(sources).await;
+    let options = MergeOptions {
+        min_part_size: 5,
+        max_part_size: 10,
+    };
+
+    merge_objects_with_options(&client, "bucket", &["a", "big"], "merged", &options)
+        .await
+        .unwrap();
+
+    let log = log.lock().unwrap();
+    assert_eq!(expected, assemble(&log.parts, &by_key));
+    // The two bytes topping up the first part, then 28 bytes in three
+    // copies of at most 10.
+    let sizes: Vec<_> = log
+        .parts
+        .iter()
+        .map(|(_, part)| match part {
+            Part::Upload(data) => data.len() as u64,
+            Part::Copy { first, last, .. } => last - first + 1,
+        })
+        .collect();
+    assert_eq!(vec![5, 10, 9, 9], sizes);
+}
+
+#[tokio::test]
+async fn test_small_sources_make_one_part() {
+    let sources = vec![("a", bytes(0, 3)), ("b", bytes(10, 4))];
+    let (client, log) = mock_s3(sources).await;
+
+    let e_tag = merge_objects_with_options(
+        &client,
+        "bucket",
+        &["a", "b"],
+        "merged",
+        &MergeOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!("merged-etag", e_tag);
+    let log = log.lock().unwrap();
+    let mut expected = bytes(0, 3);
+    expected.extend(bytes(10, 4));
+    assert_eq!(vec![(1, Part::Upload(expected))], log.parts);
+}
+
+#[tokio::test]
+async fn test_missing_source_fails_before_the_upload() {
+    let (client, log) = mock_s3(vec![("a", bytes(0, 3))]).await;
+
+    let result = merge_objects_with_options(
+        &client,
+        "bucket",
+        &["a", "missing"],
+        "merged",
+        &MergeOptions::default(),
+    )
+    .await;
+
+    assert!(result.is_err());
+    let log = log.lock().unwrap();
+    assert!(!log.created);
+    assert!(log.parts.is_empty());
+}
@@ -0,0 +1,94 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use s3_service::multipart_writer::{MultipartWriter, MIN_PART_SIZE};
+
+/// `len` bytes that differ from one part to the next.
+fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i / 1000 % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn test_small_objects_are_put_whole() {
+    let (client, uploads) = common::mock_uploads();
+    let mut writer = MultipartWriter::new(&client, "bucket", "small");
+
+    writer.write(b"hello ").await.unwrap();
+    writer.write(b"world").await.unwrap();
+    assert_eq!(11, writer.bytes_written());
+    let finished = writer.finish().await.unwrap();
+
+    assert_eq!(1, finished.part_count);
+    assert_eq!("etag", finished.e_tag);
+    let uploads = uploads.lock().unwrap();
+    assert_eq!(vec!["PutObject"], uploads.requests);
+    assert_eq!(b"hello world".to_vec(), uploads.objects["small"]);
+}
+
+#[tokio::test]
+async fn test_full_parts_are_uploaded_as_they_fill() {
+    let (client, uploads) = common::mock_uploads();
+    let object = data(2 * MIN_PART_SIZE + 100);
+    let mut writer = MultipartWriter::new(&client, "bucket", "large").with_part_size(MIN_PART_SIZE);
+
+    for chunk in object.chunks(1 << 20) {
+        writer.write(chunk).await.unwrap();
+    }
+    assert_eq!(2, writer.parts_uploaded());
+    let finished = writer.finish().await.unwrap();
+
+    assert_eq!(3, finished.part_count);
+    let uploads = uploads.lock().unwrap();
+    assert_eq!(
+        vec![
+            "CreateMultipartUpload",
+            "UploadPart",
+            "UploadPart",
+            "UploadPart",
+            "CompleteMultipartUpload"
+        ],
+        uploads.requests
+    );
+    assert_eq!(object, uploads.objects["large"]);
+}
+
+#[tokio::test]
+async fn test_part_size_is_raised_to_the_minimum() {
+    let (client, uploads) = common::mock_uploads();
+    let object = data(MIN_PART_SIZE + 1);
+    let mut writer = MultipartWriter::new(&client, "bucket", "key").with_part_size(1024);
+
+    writer.write(&object).await.unwrap();
+    let finished = writer.finish().await.unwrap();
+
+    assert_eq!(2, finished.part_count);
+    assert_eq!(object, uploads.lock().unwrap().objects["key"]);
+}
+
+#[tokio::test]
+async fn test_failed_part_and_abort() {
+    let (client, uploads) = common::mock_uploads();
+    uploads.lock().unwrap().fail_part = Some(2);
+    let mut writer = MultipartWriter::new(&client, "bucket", "key").with_part_size(MIN_PART_SIZE);
+
+    assert!(writer.write(&data(MIN_PART_SIZE)).await.is_ok());
+    assert!(writer.write(&data(MIN_PART_SIZE)).await.is_err());
+    writer.abort().await.unwrap();
+
+    let uploads = uploads.lock().unwrap();
+    assert_eq!(
+        vec![
+            "CreateMultipartUpload",
+            "UploadPart",
+            "UploadPart",
+            "AbortMultipartUpload"
+        ],
+        uploads.requests
+    );
+    assert!(uploads.objects.is_empty());
+    assert!(uploads.parts.is_empty());
+}
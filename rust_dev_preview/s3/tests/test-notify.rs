@@ -0,0 +1,149 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_sns::{Client, Credentials, Endpoint, Region, RetryConfig};
+use hyper::{Body, Request, Response};
+use s3_service::notify::{notify_sns_with_policy, UploadPayload};
+use s3_service::retry::RetryPolicy;
+use std::sync::{Arc, Mutex};
+
+const TOPIC_ARN: &str = "arn:aws:sns:us-east-1:123456789012:uploads";
+
+/// Starts an SNS endpoint that answers Publish with the error code of each
+/// entry of `failures` in turn, then with a MessageId. Returns the client
+/// and the request bodies received.
+async fn mock_sns(failures: Vec<&'static str>) -> (Client, Arc<Mutex<Vec<String>>>) {
+    let failures = Arc::new(Mutex::new(failures.into_iter()));
+    let bodies = Arc::new(Mutex::new(Vec::new()));
+    let recorder = bodies.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let failures = failures.clone();
+        let bodies = recorder.clone();
+        async move {
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            bodies
+                .lock()
+                .unwrap()
+                .push(String::from_utf8(body.to_vec()).unwrap());
+            let response = match failures.lock().unwrap().next() {
+                Some(code) => Response::builder().status(400).body(Body::from(format!(
+                    "<ErrorResponse><Error><Type>Sender</Type><Code>{}</Code>\
+                     <Message>failed</Message></Error><RequestId>r</RequestId>\
+                     </ErrorResponse>",
+                    code
+                ))),
+                None => Response::builder().body(Body::from(
+                    "<PublishResponse><PublishResult><MessageId>message-1</MessageId>\
+                     </PublishResult><ResponseMetadata><RequestId>r</RequestId>\
+                     </ResponseMetadata></PublishResponse>",
+                )),
+            };
+            response.unwrap()
+        }
+    });
+    (sns_client(&common::url_for(port)), bodies)
+}
+
+fn sns_client(url: &str) -> Client {
+    let conf = aws_sdk_sns::Config::builder()
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("access", "secret", None, None, "test"))
+        .endpoint_resolver(Endpoint::immutable(url.parse().unwrap()))
+        .retry_config(RetryConfig::disabled())
+        .build();
+    Client::from_conf(conf)
+}
+
+fn payload() -> UploadPayload {
+    UploadPayload {
+        bucket: "bucket".to_string(),
+        key: "reports/2021.csv".to_string(),
+        e_tag: "etag-1".to_string(),
+        size: 1234,
+    }
+}
+
+fn policy() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 3,
+        base_delay_ms: 1,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_publishes_the_payload() {
+    let (client, bodies) = mock_sns(vec![]).await;
+
+    let message_id = notify_sns_with_policy(&client, TOPIC_ARN, &payload(), &policy())
+        .await
+        .unwrap();
+
+    assert_eq!("message-1", message_id);
+    let bodies = bodies.lock().unwrap();
+    assert_eq!(1, bodies.len());
+    assert!(bodies[0].contains("Action=Publish"), "{}", bodies[0]);
+    assert!(bodies[0].contains("uploads"), "{}", bodies[0]);
+    // The JSON message, form-encoded.
+    for field in &["e_tag", "etag-1", "size", "1234"] {
+        assert!(bodies[0].contains(field), "{}", bodies[0]);
+    }
+}
+
+#[tokio::test]
+async fn test_retries_disabled_kms_key_and_endpoint() {
+    let (client, bodies) = mock_sns(vec!["KMSDisabled", "EndpointDisabled"]).await;
+
+    let message_id = notify_sns_with_policy(&client, TOPIC_ARN, &payload(), &policy())
+        .await
+        .unwrap();
+
+    assert_eq!("message-1", message_id);
+    assert_eq!(3, bodies.lock().unwrap().len());
+}
+
+#[tokio::test]
+async fn test_gives_up_after_three_attempts() {
+    let (client, bodies) = mock_sns(vec!["KMSDisabled"; 3]).await;
+
+    let err = notify_sns_with_policy(&client, TOPIC_ARN, &payload(), &policy())
+        .await
+        .unwrap_err();
+
+    assert!(format!("{:?}", err).contains("KMSDisabled"), "{:?}", err);
+    assert_eq!(3, bodies.lock().unwrap().len());
+}
+
+#[tokio::test]
+async fn test_other_errors_are_not_retried() {
+    let (client, bodies) = mock_sns(vec!["AuthorizationError"]).await;
+
+    let err = notify_sns_with_policy(&client, TOPIC_ARN, &payload(), &policy()).await;
+
+    assert!(err.is_err());
+    assert_eq!(1, bodies.lock().unwrap().len());
+}
+
+#[tokio::test]
+async fn test_retries_connection_errors() {
+    // Nothing listens on this port once the listener is dropped.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let client = sns_client(&url);
+
+    let start = std::time::Instant::now();
+    let policy = RetryPolicy {
+        base_delay_ms: 100,
+        ..policy()
+    };
+    let err = notify_sns_with_policy(&client, TOPIC_ARN, &payload(), &policy).await;
+
+    assert!(err.is_err());
+    // Two backoffs, of 100 and 200 ms, before the second and third attempts.
+    assert!(start.elapsed() >= std::time::Duration::from_millis(300));
+}
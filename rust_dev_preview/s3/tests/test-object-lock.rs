@@ -0,0 +1,159 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::model::ObjectLockRetentionMode;
+use aws_sdk_s3::Client;
+use chrono::{TimeZone, Utc};
+use hyper::{Body, Method, Request, Response};
+use s3_service::object_lock::{
+    compliance_date, enforce_compliance_lock, enforce_compliance_lock_with_options,
+    parse_retain_until,
+};
+use std::sync::{Arc, Mutex};
+
+/// The objects of the mock bucket and their retention, if any.
+const OBJECTS: &[(&str, Option<(&str, &str)>)] = &[
+    ("unlocked", None),
+    ("governance", Some(("GOVERNANCE", "2025-01-01T00:00:00Z"))),
+    (
+        "compliance-later",
+        Some(("COMPLIANCE", "2040-01-01T00:00:00Z")),
+    ),
+    (
+        "compliance-earlier",
+        Some(("COMPLIANCE", "2025-01-01T00:00:00Z")),
+    ),
+];
+
+/// A PutObjectRetention received: the key, whether it bypassed governance
+/// mode, and the body.
+type Put = (String, bool, String);
+
+/// Starts a mock bucket with `OBJECTS` and returns its client and the
+/// PutObjectRetention requests.
+async fn mock_s3() -> (Client, Arc<Mutex<Vec<Put>>>) {
+    let puts = Arc::new(Mutex::new(Vec::new()));
+    let recorder = puts.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let method = req.method().clone();
+            let key = req.uri().path().trim_start_matches("/bucket/").to_string();
+            let bypass = req
+                .headers()
+                .get("x-amz-bypass-governance-retention")
+                .map(|v| v == "true")
+                .unwrap_or(false);
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let response = if method == Method::PUT {
+                recorder.lock().unwrap().push((
+                    key,
+                    bypass,
+                    String::from_utf8(body.to_vec()).unwrap(),
+                ));
+                Response::builder().body(Body::empty())
+            } else if key == "/bucket" {
+                let contents: String = OBJECTS
+                    .iter()
+                    .map(|(key, _)| {
+                        format!("<Contents><Key>{}</Key><Size>1</Size></Contents>", key)
+                    })
+                    .collect();
+                Response::builder().body(Body::from(format!(
+                    "<ListBucketResult><Name>bucket</Name>\
+                     <IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                    contents
+                )))
+            } else {
+                match OBJECTS.iter().find(|(k, _)| *k == key).unwrap().1 {
+                    None => Response::builder().status(404).body(Body::from(
+                        "<Error><Code>NoSuchObjectLockConfiguration</Code></Error>",
+                    )),
+                    Some((mode, until)) => Response::builder().body(Body::from(format!(
+                        "<Retention><Mode>{}</Mode>\
+                         <RetainUntilDate>{}</RetainUntilDate></Retention>",
+                        mode, until
+                    ))),
+                }
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), puts)
+}
+
+fn day(year: i32) -> chrono::DateTime<Utc> {
+    Utc.ymd(year, 1, 1).and_hms(0, 0, 0)
+}
+
+#[test]
+fn test_parse_retain_until() {
+    assert_eq!(Ok(day(2030)), parse_retain_until("2030-01-01"));
+    assert_eq!(Ok(day(2030)), parse_retain_until("2030-01-01T00:00:00Z"));
+    assert_eq!(
+        Ok(day(2030)),
+        parse_retain_until("2030-01-01T02:00:00+02:00")
+    );
+    assert!(parse_retain_until("next year").is_err());
+}
+
+#[test]
+fn test_compliance_date() {
+    let compliance = ObjectLockRetentionMode::Compliance;
+    let governance = ObjectLockRetentionMode::Governance;
+    assert_eq!(Some(day(2030)), compliance_date(None, None, day(2030)));
+    assert_eq!(
+        None,
+        compliance_date(Some(&compliance), Some(day(2030)), day(2030))
+    );
+    assert_eq!(
+        Some(day(2030)),
+        compliance_date(Some(&compliance), Some(day(2029)), day(2030))
+    );
+    // Upgrading governance mode never shortens the retention.
+    assert_eq!(
+        Some(day(2031)),
+        compliance_date(Some(&governance), Some(day(2031)), day(2030))
+    );
+    assert_eq!(
+        Some(day(2030)),
+        compliance_date(Some(&governance), Some(day(2029)), day(2030))
+    );
+}
+
+#[tokio::test]
+async fn test_enforce_compliance_lock() {
+    let (client, puts) = mock_s3().await;
+
+    let updated = enforce_compliance_lock(&client, "bucket", "", day(2030))
+        .await
+        .unwrap();
+
+    assert_eq!(3, updated);
+    let mut puts = puts.lock().unwrap().clone();
+    puts.sort();
+    let keys: Vec<_> = puts.iter().map(|(key, ..)| key.as_str()).collect();
+    assert_eq!(vec!["compliance-earlier", "governance", "unlocked"], keys);
+    for (key, bypass, body) in &puts {
+        assert!(body.contains("<Mode>COMPLIANCE</Mode>"), "{}", body);
+        assert!(body.contains("2030-01-01T00:00:00"), "{}", body);
+        assert_eq!(key == "governance", *bypass);
+    }
+}
+
+#[tokio::test]
+async fn test_dry_run_changes_nothing() {
+    let (client, puts) = mock_s3().await;
+
+    let updated = enforce_compliance_lock_with_options(&client, "bucket", "", day(2030), true)
+        .await
+        .unwrap();
+
+    assert_eq!(3, updated);
+    assert!(puts.lock().unwrap().is_empty());
+}
@@ -0,0 +1,180 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use s3_service::ownership::{
+    objects_by_owner_with_concurrency, owner_totals, render_owner_table, OwnerTotals,
+    ACCESS_DENIED_OWNER,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The owner of objects whose ACL the caller may not read.
+const DENIED: &str = "denied";
+
+/// The objects of the bucket: key, size, and owner, or `None` for an object
+/// deleted after it is listed.
+const OBJECTS: [(&str, u64, Option<&str>); 7] = [
+    ("logs/a", 100, Some("owner-a")),
+    ("logs/b", 200, Some("owner-b")),
+    ("logs/c", 300, Some("owner-a")),
+    ("logs/d", 50, None),
+    ("logs/e", 1000, Some("owner-b")),
+    ("logs/f", 5, Some("owner-a")),
+    ("logs/g", 9, Some(DENIED)),
+];
+
+/// Starts a server listing `OBJECTS` in pages of two and answering
+/// GetObjectAcl after a short delay, recording the most ACL requests in
+/// flight at once.
+async fn mock_s3() -> (Client, Arc<AtomicUsize>) {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+    let (counter, peak) = (in_flight, most.clone());
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let (counter, peak) = (counter.clone(), peak.clone());
+        async move {
+            let query = req.uri().query().unwrap_or("").to_string();
+            if query.split('&').any(|p| p == "acl") {
+                let now = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                counter.fetch_sub(1, Ordering::SeqCst);
+                let key = req.uri().path().trim_start_matches("/bucket/");
+                let owner = OBJECTS.iter().find(|o| o.0 == key).unwrap().2;
+                let response = match owner {
+                    Some(DENIED) => Response::builder().status(403).body(Body::from(
+                        "<Error><Code>AccessDenied</Code><Message>denied</Message></Error>",
+                    )),
+                    Some(owner) => Response::builder().body(Body::from(format!(
+                        "<AccessControlPolicy><Owner><ID>{}</ID></Owner>\
+                         <AccessControlList></AccessControlList></AccessControlPolicy>",
+                        owner
+                    ))),
+                    None => Response::builder().status(404).body(Body::from(
+                        "<Error><Code>NoSuchKey</Code><Message>gone</Message></Error>",
+                    )),
+                };
+                return response.unwrap();
+            }
+            let page: usize = query
+                .split('&')
+                .find_map(|p| p.strip_prefix("continuation-token="))
+                .map(|token| token.parse().unwrap())
+                .unwrap_or(0);
+            let contents: String = OBJECTS
+                .iter()
+                .skip(page * 2)
+                .take(2)
+                .map(|(key, size, _)| {
+                    format!(
+                        "<Contents><Key>{}</Key><Size>{}</Size></Contents>",
+                        key, size
+                    )
+                })
+                .collect();
+            let more = (page + 1) * 2 < OBJECTS.len();
+            let next = if more {
+                format!(
+                    "<NextContinuationToken>{}</NextContinuationToken>",
+                    page + 1
+                )
+            } else {
+                String::new()
+            };
+            Response::builder()
+                .body(Body::from(format!(
+                    "<ListBucketResult><Name>bucket</Name>\
+                         <IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>",
+                    more, next, contents
+                )))
+                .unwrap()
+        }
+    });
+
+    (common::client_for(port), most)
+}
+
+#[tokio::test]
+async fn test_objects_are_grouped_by_owner() {
+    let (client, most) = mock_s3().await;
+
+    let owners = objects_by_owner_with_concurrency(&client, "bucket", 2)
+        .await
+        .unwrap();
+
+    assert_eq!(3, owners.len());
+    let mut keys: Vec<&str> = owners["owner-a"]
+        .iter()
+        .map(|object| object.key().unwrap())
+        .collect();
+    keys.sort_unstable();
+    assert_eq!(vec!["logs/a", "logs/c", "logs/f"], keys);
+    assert_eq!(2, owners["owner-b"].len());
+    assert!(most.load(Ordering::SeqCst) <= 2);
+
+    let totals = owner_totals(&owners);
+    assert_eq!(
+        vec![
+            OwnerTotals {
+                owner_id: "owner-b".to_string(),
+                objects: 2,
+                bytes: 1200,
+            },
+            OwnerTotals {
+                owner_id: "owner-a".to_string(),
+                objects: 3,
+                bytes: 405,
+            },
+            OwnerTotals {
+                owner_id: ACCESS_DENIED_OWNER.to_string(),
+                objects: 1,
+                bytes: 9,
+            },
+        ],
+        totals
+    );
+}
+
+#[test]
+fn test_render_owner_table() {
+    let totals = vec![
+        OwnerTotals {
+            owner_id: "79a59df900b949e55d96a1e698fbaced".to_string(),
+            objects: 12,
+            bytes: 4096,
+        },
+        OwnerTotals {
+            owner_id: "other".to_string(),
+            objects: 1,
+            bytes: 7,
+        },
+    ];
+
+    let table = render_owner_table(&totals);
+
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!("2 owners", lines[0]);
+    assert_eq!(
+        "Owner                                Objects              Size",
+        lines[2]
+    );
+    assert_eq!(
+        "79a59df900b949e55d96a1e698fbaced          12           4.0 KiB",
+        lines[3]
+    );
+    assert_eq!(
+        "other                                      1               7 B",
+        lines[4]
+    );
+    assert_eq!(
+        "1 owner",
+        render_owner_table(&totals[1..]).lines().next().unwrap()
+    );
+}
@@ -0,0 +1,646 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::durable::{part_path, FsyncOptions};
+use s3_service::integrity::IntegrityManifest;
+use s3_service::parallel_download::{
+    download_parallel, download_parallel_with_events, download_ranges, is_object_changed,
+    state_path, DownloadState, ObjectPin, ParallelDownloadOptions, WriteVerify,
+};
+use s3_service::progress::{ProgressEvent, TransferTotals};
+use s3_service::upload::SourceWindow;
+use std::collections::HashMap;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Fills the regions of the target the object must not touch.
+const UNTOUCHED: u8 = 0xAA;
+
+fn object(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn test_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("parallel-download-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Starts a server holding `objects`, answering HeadObject and ranged or
+/// whole GetObject requests, and recording the ranges read.
+async fn mock_s3(objects: Vec<(&str, Vec<u8>)>) -> (Client, Arc<Mutex<Vec<String>>>) {
+    let objects: Arc<HashMap<String, Vec<u8>>> = Arc::new(
+        objects
+            .into_iter()
+            .map(|(key, data)| (format!("/bucket/{}", key), data))
+            .collect(),
+    );
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+    let received = ranges.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let objects = objects.clone();
+        let ranges = received.clone();
+        async move {
+            let data = match objects.get(req.uri().path()) {
+                Some(data) => data,
+                None => {
+                    return Response::builder()
+                        .status(404)
+                        .body(Body::from("<Error><Code>NoSuchKey</Code></Error>"))
+                        .unwrap()
+                }
+            };
+            let response = match (req.method(), req.headers().get("range")) {
+                (&Method::HEAD, _) => Response::builder()
+                    .header("Content-Length", data.len())
+                    .header("ETag", "\"object-etag\"")
+                    .body(Body::empty()),
+                (_, Some(range)) => {
+                    let range = range.to_str().unwrap().to_string();
+                    let bounds = range.trim_start_matches("bytes=");
+                    let (first, last) = bounds.split_at(bounds.find('-').unwrap());
+                    let first: usize = first.parse().unwrap();
+                    let last: usize = last[1..].parse().unwrap();
+                    ranges.lock().unwrap().push(range);
+                    Response::builder()
+                        .status(206)
+                        .body(Body::from(data[first..=last].to_vec()))
+                }
+                (_, None) => Response::builder().body(Body::from(data.clone())),
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), ranges)
+}
+
+/// An object overwritten with `objects[1]` after `swap_after` ranged GETs,
+/// with the ETag `"gen-N"`, and in a versioned bucket the version `vN`.
+#[derive(Debug, Default)]
+struct Overwritten {
+    objects: Vec<Vec<u8>>,
+    current: usize,
+    versioned: bool,
+    swap_after: Option<usize>,
+    /// The ranged GET answered with a 500, counting from 1.
+    fail_at: Option<usize>,
+    /// The query and If-Match header of each ranged GET.
+    gets: Vec<(String, Option<String>)>,
+}
+
+impl Overwritten {
+    fn respond(&mut self, req: &Request<Body>) -> Response<Body> {
+        let e_tag = |generation: usize| format!("\"gen-{}\"", generation + 1);
+        if req.method() == Method::HEAD {
+            let mut response = Response::builder()
+                .header("Content-Length", self.objects[self.current].len())
+                .header("ETag", e_tag(self.current));
+            if self.versioned {
+                response = response.header("x-amz-version-id", format!("v{}", self.current + 1));
+            }
+            return response.body(Body::empty()).unwrap();
+        }
+        let query = req.uri().query().unwrap_or_default().to_string();
+        let if_match = req
+            .headers()
+            .get("if-match")
+            .map(|v| v.to_str().unwrap().to_string());
+        self.gets.push((query.clone(), if_match.clone()));
+        if self.swap_after == Some(self.gets.len() - 1) {
+            self.current = 1;
+        }
+        if self.fail_at == Some(self.gets.len()) {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("<Error><Code>InternalError</Code></Error>"))
+                .unwrap();
+        }
+        let version = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("versionId=v"));
+        let generation = match version {
+            Some(version) => version.parse::<usize>().unwrap() - 1,
+            None => self.current,
+        };
+        if if_match.map_or(false, |if_match| if_match != e_tag(generation)) {
+            return Response::builder()
+                .status(412)
+                .body(Body::from("<Error><Code>PreconditionFailed</Code></Error>"))
+                .unwrap();
+        }
+        let range = req.headers()["range"].to_str().unwrap();
+        let bounds = range.trim_start_matches("bytes=");
+        let (first, last) = bounds.split_at(bounds.find('-').unwrap());
+        let first: usize = first.parse().unwrap();
+        let last: usize = last[1..].parse().unwrap();
+        Response::builder()
+            .status(206)
+            .body(Body::from(self.objects[generation][first..=last].to_vec()))
+            .unwrap()
+    }
+}
+
+async fn mock_overwritten(mock: Overwritten) -> (Client, Arc<Mutex<Overwritten>>) {
+    let mock = Arc::new(Mutex::new(mock));
+    let shared = mock.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let response = shared.lock().unwrap().respond(&req);
+        async move { response }
+    });
+
+    (common::client_for(port), mock)
+}
+
+/// Two generations of an object of `len` bytes that differ in every byte.
+fn generations(len: usize) -> Vec<Vec<u8>> {
+    let first = object(len);
+    let second = first.iter().map(|b| !b).collect();
+    vec![first, second]
+}
+
+#[test]
+fn test_download_ranges() {
+    assert_eq!(vec![(0, 4), (4, 4), (8, 2)], download_ranges(10, 4, None));
+    assert_eq!(vec![(0, 8), (8, 2)], download_ranges(10, 5, Some(4)));
+    assert_eq!(vec![(0, 10)], download_ranges(10, 100, Some(4)));
+    assert!(download_ranges(0, 4, None).is_empty());
+}
+
+#[test]
+fn test_parse_verify() {
+    assert_eq!(Ok(WriteVerify::None), "none".parse());
+    assert_eq!(Ok(WriteVerify::Sample(8)), "sample".parse());
+    assert_eq!(Ok(WriteVerify::Sample(3)), "sample:3".parse());
+    assert_eq!(Ok(WriteVerify::Checksum), "checksum".parse());
+    assert!("sample:x".parse::<WriteVerify>().is_err());
+    assert!("all".parse::<WriteVerify>().is_err());
+}
+
+#[tokio::test]
+async fn test_write_in_place_into_sparse_file() {
+    let data = object(10_000);
+    let (client, ranges) = mock_s3(vec![("image", data.clone())]).await;
+    let dir = test_dir();
+    let path = dir.join("disk.img");
+    // A sparse file larger than the object, with a marker past it.
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.set_len(1 << 20).unwrap();
+    file.seek(SeekFrom::Start(20_000)).unwrap();
+    file.write_all(&[UNTOUCHED; 100]).unwrap();
+    drop(file);
+    let before = std::fs::read(&path).unwrap();
+
+    let options = ParallelDownloadOptions {
+        part_size: 3_000,
+        concurrency: 3,
+        write_in_place: true,
+        truncate: false,
+        verify: WriteVerify::Sample(2),
+        ..Default::default()
+    };
+    let result = download_parallel(&client, "bucket", "image", &path, &options)
+        .await
+        .unwrap();
+
+    assert!(result.is_ok());
+    assert_eq!(10_000, result.size);
+    assert_eq!(Some(1 << 20), result.target_size);
+    assert_eq!(4, result.parts);
+    assert_eq!(vec![1, 4], result.sampled_parts);
+    assert_eq!(4, ranges.lock().unwrap().len());
+    let after = std::fs::read(&path).unwrap();
+    assert_eq!(1 << 20, after.len());
+    assert_eq!(&data[..], &after[..10_000]);
+    assert_eq!(&before[10_000..], &after[10_000..]);
+    // No part file was made.
+    assert_eq!(1, std::fs::read_dir(&dir).unwrap().count());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_target_too_small_is_refused() {
+    let (client, ranges) = mock_s3(vec![("image", object(1_000))]).await;
+    let dir = test_dir();
+    let path = dir.join("disk.img");
+    std::fs::write(&path, vec![UNTOUCHED; 999]).unwrap();
+    let options = ParallelDownloadOptions {
+        write_in_place: true,
+        truncate: false,
+        ..Default::default()
+    };
+
+    let err = download_parallel(&client, "bucket", "image", &path, &options)
+        .await
+        .unwrap_err();
+
+    assert!(
+        err.to_string().contains("less than the 1000 bytes"),
+        "{}",
+        err
+    );
+    assert!(ranges.lock().unwrap().is_empty());
+    assert_eq!(vec![UNTOUCHED; 999], std::fs::read(&path).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_no_truncate_needs_write_in_place() {
+    let (client, _) = mock_s3(vec![("image", object(10))]).await;
+    let options = ParallelDownloadOptions {
+        truncate: false,
+        ..Default::default()
+    };
+    let result = download_parallel(
+        &client,
+        "bucket",
+        "image",
+        &PathBuf::from("image"),
+        &options,
+    )
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_default_download_goes_through_part_file() {
+    let data = object(5_000);
+    let (client, _) = mock_s3(vec![("object", data.clone())]).await;
+    let dir = test_dir();
+    let path = dir.join("object");
+    std::fs::write(&path, vec![UNTOUCHED; 8_000]).unwrap();
+    let options = ParallelDownloadOptions {
+        part_size: 1_024,
+        ..Default::default()
+    };
+
+    let result = download_parallel(&client, "bucket", "object", &path, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(None, result.target_size);
+    assert_eq!(data, std::fs::read(&path).unwrap());
+    assert_eq!(1, std::fs::read_dir(&dir).unwrap().count());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_fsync_interval_syncs_during_the_download() {
+    let data = object(5_000);
+    let (client, _) = mock_s3(vec![("object", data.clone())]).await;
+    let dir = test_dir();
+    let path = dir.join("object");
+    let options = ParallelDownloadOptions {
+        part_size: 1_000,
+        concurrency: 1,
+        fsync: FsyncOptions {
+            enabled: true,
+            interval: Some(3_000),
+        },
+        ..Default::default()
+    };
+
+    let result = download_parallel(&client, "bucket", "object", &path, &options)
+        .await
+        .unwrap();
+
+    // Once after the third range, then the whole file once all the ranges
+    // are written, then its directory after the rename.
+    assert_eq!(3, result.fsync.syncs);
+    assert_eq!(data, std::fs::read(&path).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_aligned_writes_with_unaligned_tail() {
+    // Not a multiple of the alignment, so the tail is written buffered.
+    let data = object(3 * 4096 + 100);
+    let (client, _) = mock_s3(vec![("image", data.clone())]).await;
+    let dir = test_dir();
+    let path = dir.join("disk.img");
+    std::fs::write(&path, vec![UNTOUCHED; 5 * 4096]).unwrap();
+    let options = ParallelDownloadOptions {
+        part_size: 5_000,
+        write_in_place: true,
+        truncate: false,
+        alignment: Some(4096),
+        ..Default::default()
+    };
+
+    let result = download_parallel(&client, "bucket", "image", &path, &options)
+        .await
+        .unwrap();
+
+    // O_DIRECT is used where the file system accepts it; tmpfs does not.
+    assert!(result.direct_io || result.fallback.is_some());
+    assert_eq!(2, result.parts);
+    let after = std::fs::read(&path).unwrap();
+    assert_eq!(&data[..], &after[..data.len()]);
+    assert!(after[data.len()..].iter().all(|b| *b == UNTOUCHED));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_verify_against_integrity_manifest() {
+    let data = object(7_000);
+    let dir = test_dir();
+    let source = dir.join("source");
+    std::fs::write(&source, &data).unwrap();
+    let manifest = IntegrityManifest::for_upload("image", &source, SourceWindow::whole(7_000), 2)
+        .await
+        .unwrap();
+    let manifest = serde_json::to_vec(&manifest).unwrap();
+    let (client, _) = mock_s3(vec![
+        ("image", data.clone()),
+        ("image.integrity.json", manifest),
+    ])
+    .await;
+    let path = dir.join("disk.img");
+    std::fs::write(&path, vec![UNTOUCHED; 10_000]).unwrap();
+    let options = ParallelDownloadOptions {
+        part_size: 2_000,
+        write_in_place: true,
+        truncate: false,
+        verify: WriteVerify::Checksum,
+        ..Default::default()
+    };
+
+    let result = download_parallel(&client, "bucket", "image", &path, &options)
+        .await
+        .unwrap();
+
+    assert!(result.is_ok());
+    assert!(result.integrity.unwrap().is_ok());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_null_version_does_not_pin() {
+    assert_eq!(None, ObjectPin::new(Some("\"e\""), Some("null")).version_id);
+    assert_eq!(
+        Some("v1".to_string()),
+        ObjectPin::new(Some("\"e\""), Some("v1")).version_id
+    );
+}
+
+#[tokio::test]
+async fn test_overwrite_between_ranges_is_detected() {
+    let (client, mock) = mock_overwritten(Overwritten {
+        objects: generations(5_000),
+        swap_after: Some(2),
+        ..Default::default()
+    })
+    .await;
+    let dir = test_dir();
+    let path = dir.join("object");
+    let options = ParallelDownloadOptions {
+        part_size: 1_000,
+        concurrency: 1,
+        ..Default::default()
+    };
+
+    let err = download_parallel(&client, "bucket", "object", &path, &options)
+        .await
+        .unwrap_err();
+
+    assert!(is_object_changed(&err), "{}", err);
+    assert!(
+        err.to_string().contains(
+            "object was overwritten during the download: it no longer has the ETag \"gen-1\""
+        ),
+        "{}",
+        err
+    );
+    // Every range asked for the first object; the third one was refused.
+    let gets = &mock.lock().unwrap().gets;
+    assert_eq!(3, gets.len());
+    assert!(gets
+        .iter()
+        .all(|(_, if_match)| if_match.as_deref() == Some("\"gen-1\"")));
+    assert_eq!(0, std::fs::read_dir(&dir).unwrap().count());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_retry_on_change_downloads_new_object() {
+    let objects = generations(5_000);
+    let (client, _) = mock_overwritten(Overwritten {
+        objects: objects.clone(),
+        swap_after: Some(2),
+        ..Default::default()
+    })
+    .await;
+    let dir = test_dir();
+    let path = dir.join("object");
+    let options = ParallelDownloadOptions {
+        part_size: 1_000,
+        concurrency: 1,
+        retry_on_change: true,
+        ..Default::default()
+    };
+
+    let result = download_parallel(&client, "bucket", "object", &path, &options)
+        .await
+        .unwrap();
+
+    assert!(result.restarted_on_change);
+    assert_eq!(objects[1], std::fs::read(&path).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_versioned_download_reads_its_version() {
+    let objects = generations(5_000);
+    let (client, mock) = mock_overwritten(Overwritten {
+        objects: objects.clone(),
+        versioned: true,
+        swap_after: Some(2),
+        ..Default::default()
+    })
+    .await;
+    let dir = test_dir();
+    let path = dir.join("object");
+    let options = ParallelDownloadOptions {
+        part_size: 1_000,
+        concurrency: 1,
+        ..Default::default()
+    };
+
+    let result = download_parallel(&client, "bucket", "object", &path, &options)
+        .await
+        .unwrap();
+
+    // The overwrite made a new version; the download kept reading the first.
+    assert!(!result.restarted_on_change);
+    assert_eq!(objects[0], std::fs::read(&path).unwrap());
+    let gets = &mock.lock().unwrap().gets;
+    assert_eq!(5, gets.len());
+    assert!(gets
+        .iter()
+        .all(|(query, if_match)| query.contains("versionId=v1") && if_match.is_none()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_resume_fetches_missing_ranges() {
+    let objects = generations(5_000);
+    let (client, mock) = mock_overwritten(Overwritten {
+        objects: objects.clone(),
+        fail_at: Some(3),
+        ..Default::default()
+    })
+    .await;
+    let dir = test_dir();
+    let path = dir.join("object");
+    let options = ParallelDownloadOptions {
+        part_size: 1_000,
+        concurrency: 1,
+        resume: true,
+        ..Default::default()
+    };
+
+    assert!(
+        download_parallel(&client, "bucket", "object", &path, &options)
+            .await
+            .is_err()
+    );
+    let state = DownloadState::load(&state_path(&part_path(&path))).unwrap();
+    assert_eq!(Some("\"gen-1\"".to_string()), state.pin.e_tag);
+    assert_eq!(
+        vec![0, 1],
+        state.completed.keys().copied().collect::<Vec<_>>()
+    );
+
+    let events = Mutex::new(Vec::new());
+    let result =
+        download_parallel_with_events(&client, "bucket", "object", &path, &options, &|event| {
+            events.lock().unwrap().push(event.clone())
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(2, result.resumed_parts);
+    assert_eq!(
+        TransferTotals {
+            total_bytes: 5_000,
+            resumed_bytes: 2_000,
+            transferred_bytes: 3_000,
+        },
+        result.totals
+    );
+    let events = events.into_inner().unwrap();
+    assert_eq!(
+        ProgressEvent::Started {
+            totals: TransferTotals::resumed(5_000, 2_000)
+        },
+        events[0]
+    );
+    let parts: Vec<(i32, u64, u64)> = events
+        .iter()
+        .filter_map(|event| match event {
+            ProgressEvent::Part {
+                part_number,
+                bytes,
+                totals,
+                ..
+            } => Some((*part_number, *bytes, totals.completed_bytes())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        vec![(3, 1_000, 3_000), (4, 1_000, 4_000), (5, 1_000, 5_000)],
+        parts
+    );
+    assert!(matches!(
+        events.last(),
+        Some(ProgressEvent::Finished { totals, .. }) if *totals == result.totals
+    ));
+    // Two ranges, the failed one, then the three that were missing.
+    assert_eq!(6, mock.lock().unwrap().gets.len());
+    assert_eq!(objects[0], std::fs::read(&path).unwrap());
+    assert_eq!(1, std::fs::read_dir(&dir).unwrap().count());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_resume_fetches_ranges_that_no_longer_match_again() {
+    let objects = generations(5_000);
+    let (client, mock) = mock_overwritten(Overwritten {
+        objects: objects.clone(),
+        fail_at: Some(3),
+        ..Default::default()
+    })
+    .await;
+    let dir = test_dir();
+    let path = dir.join("object");
+    let options = ParallelDownloadOptions {
+        part_size: 1_000,
+        concurrency: 1,
+        resume: true,
+        ..Default::default()
+    };
+    assert!(
+        download_parallel(&client, "bucket", "object", &path, &options)
+            .await
+            .is_err()
+    );
+    // The second range recorded did not survive, as after a crash.
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(part_path(&path))
+        .unwrap();
+    file.seek(SeekFrom::Start(1_500)).unwrap();
+    file.write_all(&[0; 10]).unwrap();
+    drop(file);
+
+    let result = download_parallel(&client, "bucket", "object", &path, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(1, result.resumed_parts);
+    // Two ranges, the failed one, then the corrupted one and the three
+    // that were missing.
+    assert_eq!(7, mock.lock().unwrap().gets.len());
+    assert_eq!(objects[0], std::fs::read(&path).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_resume_refuses_changed_object() {
+    let (client, mock) = mock_overwritten(Overwritten {
+        objects: generations(5_000),
+        fail_at: Some(3),
+        ..Default::default()
+    })
+    .await;
+    let dir = test_dir();
+    let path = dir.join("object");
+    let options = ParallelDownloadOptions {
+        part_size: 1_000,
+        concurrency: 1,
+        resume: true,
+        ..Default::default()
+    };
+    assert!(
+        download_parallel(&client, "bucket", "object", &path, &options)
+            .await
+            .is_err()
+    );
+    mock.lock().unwrap().current = 1;
+
+    let err = download_parallel(&client, "bucket", "object", &path, &options)
+        .await
+        .unwrap_err();
+
+    assert!(is_object_changed(&err), "{}", err);
+    // The ranges of the old object are gone, so the next run starts over.
+    assert_eq!(0, std::fs::read_dir(&dir).unwrap().count());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,276 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use percent_encoding::percent_decode_str;
+use s3_service::parallel_list::{
+    character_boundaries, list_remote_parallel, pick_boundaries, shard_ranges, KeyRange,
+};
+use s3_service::sync::list_remote;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Entries listed per page, to go through continuation tokens.
+const PAGE_SIZE: usize = 5;
+
+fn query_value(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let mut split = pair.splitn(2, '=');
+        (split.next() == Some(name)).then(|| {
+            percent_decode_str(split.next().unwrap_or(""))
+                .decode_utf8_lossy()
+                .into_owned()
+        })
+    })
+}
+
+/// A page of ListObjectsV2 over `keys`, honoring the prefix, start-after
+/// (unless `ignore_start_after`), delimiter, and continuation token.
+fn list_response(keys: &BTreeSet<String>, query: &str, ignore_start_after: bool) -> String {
+    let prefix = query_value(query, "prefix").unwrap_or_default();
+    let start_after = query_value(query, "start-after").filter(|_| !ignore_start_after);
+    let delimiter = query_value(query, "delimiter");
+    let start: usize = query_value(query, "continuation-token")
+        .map(|token| token.parse().unwrap())
+        .unwrap_or(0);
+    // (is a common prefix, name), in order and without duplicates.
+    let mut entries: Vec<(bool, String)> = Vec::new();
+    for key in keys {
+        if !key.starts_with(&prefix)
+            || start_after
+                .as_deref()
+                .map_or(false, |after| key.as_str() <= after)
+        {
+            continue;
+        }
+        let common = delimiter.as_deref().and_then(|delimiter| {
+            key[prefix.len()..]
+                .find(delimiter)
+                .map(|at| key[..prefix.len() + at + delimiter.len()].to_string())
+        });
+        let entry = match common {
+            Some(common) => (true, common),
+            None => (false, key.clone()),
+        };
+        if entries.last() != Some(&entry) {
+            entries.push(entry);
+        }
+    }
+    let page: Vec<_> = entries.iter().skip(start).take(PAGE_SIZE).collect();
+    let truncated = start + page.len() < entries.len();
+    let body: String = page
+        .iter()
+        .map(|(common, name)| {
+            if *common {
+                format!("<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", name)
+            } else {
+                format!(
+                    "<Contents><Key>{}</Key><LastModified>2022-03-01T12:00:00.000Z</LastModified>\
+                     <ETag>\"etag\"</ETag><Size>{}</Size></Contents>",
+                    name,
+                    name.len()
+                )
+            }
+        })
+        .collect();
+    let next = if truncated {
+        format!(
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            start + PAGE_SIZE
+        )
+    } else {
+        String::new()
+    };
+    format!(
+        "<ListBucketResult><Name>bucket</Name><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>",
+        truncated, next, body
+    )
+}
+
+/// Starts a server listing `keys`, and counts the requests.
+async fn mock_s3(keys: BTreeSet<String>, ignore_start_after: bool) -> (Client, Arc<AtomicUsize>) {
+    let keys = Arc::new(keys);
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let keys = keys.clone();
+        let counter = counter.clone();
+        async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let query = req.uri().query().unwrap_or("").to_string();
+            Response::new(Body::from(list_response(&keys, &query, ignore_start_after)))
+        }
+    });
+
+    (common::client_for(port), requests)
+}
+
+/// Keys under `data/` in one directory a year, and a few outside of it.
+fn by_year() -> BTreeSet<String> {
+    let mut keys = BTreeSet::new();
+    for year in 2019..2023 {
+        keys.insert(format!("data/{}/", year));
+        for i in 0..10 {
+            keys.insert(format!("data/{}/part-{:02}.csv", year, i));
+        }
+    }
+    keys.insert("data/README".to_string());
+    keys.insert("dat".to_string());
+    keys.insert("data0".to_string());
+    keys.insert("other/data/1".to_string());
+    keys
+}
+
+/// Keys under `flat/` with no delimiter, starting with many characters.
+fn flat() -> BTreeSet<String> {
+    let characters = "09AZ_az-~.";
+    let mut keys = BTreeSet::new();
+    for first in characters.chars() {
+        for i in 0..7 {
+            keys.insert(format!("flat/{}{:03}", first, i));
+        }
+    }
+    keys.insert("flat/".to_string());
+    keys.insert("flat/a".to_string());
+    keys.insert("flat/é".to_string());
+    keys
+}
+
+fn under(keys: &BTreeSet<String>, prefix: &str) -> BTreeSet<String> {
+    keys.iter()
+        .filter(|key| key.starts_with(prefix))
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn test_shard_ranges_cover_the_keyspace() {
+    let boundaries = vec!["b".to_string(), "d".to_string()];
+    let ranges = shard_ranges(&boundaries);
+
+    assert_eq!(
+        vec![
+            KeyRange {
+                start_after: None,
+                end: Some("b".to_string())
+            },
+            KeyRange {
+                start_after: Some("b".to_string()),
+                end: Some("d".to_string())
+            },
+            KeyRange {
+                start_after: Some("d".to_string()),
+                end: None
+            },
+        ],
+        ranges
+    );
+    for key in ["", "a", "b", "b0", "c", "d", "d/", "z"] {
+        let containing = ranges.iter().filter(|range| range.contains(key)).count();
+        assert_eq!(1, containing, "{}", key);
+    }
+    assert_eq!(1, shard_ranges(&[]).len());
+}
+
+#[test]
+fn test_pick_boundaries() {
+    let candidates: Vec<String> = ["d/", "a/", "c/", "b/", "a/"]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+
+    assert_eq!(vec!["c/".to_string()], pick_boundaries(&candidates, 2));
+    assert_eq!(
+        vec!["b/".to_string(), "c/".to_string(), "d/".to_string()],
+        pick_boundaries(&candidates, 4)
+    );
+    // More shards than candidates.
+    assert_eq!(4, pick_boundaries(&candidates, 10).len());
+    assert!(pick_boundaries(&candidates, 1).is_empty());
+    assert_eq!(vec!["p/V".to_string()], character_boundaries("p/", 2));
+    assert_eq!(63, character_boundaries("p/", 1000).len());
+}
+
+async fn check_listing(keys: BTreeSet<String>, prefix: &str, shards: usize) {
+    let (client, _) = mock_s3(keys.clone(), false).await;
+    let sequential = list_remote(&client, "bucket", prefix).await.unwrap();
+    let (parallel, stats) = list_remote_parallel(&client, "bucket", prefix, shards)
+        .await
+        .unwrap();
+
+    let expected = under(&keys, prefix);
+    let listed: BTreeSet<String> = parallel.keys().cloned().collect();
+    assert_eq!(expected, listed);
+    assert_eq!(sequential, parallel);
+    assert_eq!(expected.len(), stats.keys);
+    assert_eq!(0, stats.duplicates);
+    assert!(stats.shards <= shards.max(1));
+}
+
+#[tokio::test]
+async fn test_parallel_list_splits_at_common_prefixes() {
+    check_listing(by_year(), "data/", 3).await;
+    check_listing(by_year(), "data/", 8).await;
+    check_listing(by_year(), "data", 2).await;
+}
+
+#[tokio::test]
+async fn test_parallel_list_splits_flat_keys_by_character() {
+    check_listing(flat(), "flat/", 4).await;
+    check_listing(flat(), "flat/", 16).await;
+    check_listing(flat(), "", 5).await;
+}
+
+#[tokio::test]
+async fn test_parallel_list_with_one_shard_lists_sequentially() {
+    let (client, requests) = mock_s3(flat(), false).await;
+
+    let (objects, stats) = list_remote_parallel(&client, "bucket", "flat/", 1)
+        .await
+        .unwrap();
+
+    assert_eq!(flat().len(), objects.len());
+    assert_eq!(1, stats.shards);
+    // No delimiter pass.
+    assert_eq!(
+        (flat().len() + PAGE_SIZE - 1) / PAGE_SIZE,
+        requests.load(Ordering::SeqCst)
+    );
+    assert_eq!(stats.pages, requests.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_parallel_list_of_an_empty_prefix() {
+    let (client, _) = mock_s3(flat(), false).await;
+
+    let (objects, stats) = list_remote_parallel(&client, "bucket", "missing/", 4)
+        .await
+        .unwrap();
+
+    assert!(objects.is_empty());
+    assert_eq!(0, stats.keys);
+}
+
+#[tokio::test]
+async fn test_parallel_list_without_start_after_support() {
+    let keys = flat();
+    let (client, _) = mock_s3(keys.clone(), true).await;
+
+    let (objects, stats) = list_remote_parallel(&client, "bucket", "flat/", 4)
+        .await
+        .unwrap();
+
+    // Each shard lists from the start, and keeps only its own keys.
+    let listed: HashSet<String> = objects.keys().cloned().collect();
+    assert_eq!(
+        under(&keys, "flat/").into_iter().collect::<HashSet<_>>(),
+        listed
+    );
+    assert_eq!(0, stats.duplicates);
+}
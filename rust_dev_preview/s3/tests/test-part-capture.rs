@@ -0,0 +1,158 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::{Client, Credentials, RetryConfig};
+use hyper::{Body, Request, Response};
+use s3_service::part_capture::{part_capture_client, redact_uri, PartCapture};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+const ACCESS_KEY_ID: &str = "AKIDSECRETMARKER";
+const SESSION_TOKEN: &str = "TOKEN-SECRET-MARKER";
+/// Base64 of the 32 bytes of "SSE-C-SECRET-MARKER-0123456789ab".
+const SSE_C_KEY: &str = "U1NFLUMtU0VDUkVULU1BUktFUi0wMTIzNDU2Nzg5YWI=";
+
+/// The Authorization headers received.
+type Received = Arc<Mutex<Vec<String>>>;
+
+/// Accepts UploadPart, failing the first attempt at part 2 with a 500.
+async fn mock_endpoint() -> (u16, Received) {
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let part_2_attempts = Arc::new(AtomicUsize::new(0));
+    let recorder = received.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        let part_2_attempts = part_2_attempts.clone();
+        async move {
+            let authorization = req
+                .headers()
+                .get("authorization")
+                .map(|v| v.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            recorder.lock().unwrap().push(authorization);
+            let query = req.uri().query().unwrap_or("").to_string();
+            let part = query
+                .split('&')
+                .find_map(|p| p.strip_prefix("partNumber="))
+                .unwrap_or_default()
+                .to_string();
+            hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let response = if part == "2" && part_2_attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                Response::builder()
+                    .status(500)
+                    .body(Body::from("<Error><Code>InternalError</Code></Error>"))
+            } else {
+                Response::builder()
+                    .header("ETag", format!("\"etag-{}\"", part))
+                    .header("x-amz-request-id", format!("request-{}", part))
+                    .body(Body::empty())
+            };
+            response.unwrap()
+        }
+    });
+    (port, received)
+}
+
+fn client(port: u16, capture: &PartCapture) -> Client {
+    let config = common::config_for(port)
+        .credentials_provider(Credentials::new(
+            ACCESS_KEY_ID,
+            "secret",
+            Some(SESSION_TOKEN.to_string()),
+            None,
+            "test",
+        ))
+        .retry_config(RetryConfig::new().with_max_attempts(3))
+        .build();
+    part_capture_client(config, capture)
+}
+
+/// Uploads parts 1 to 3 with an SSE-C key.
+async fn upload_parts(client: &Client) {
+    for part_number in 1..=3 {
+        client
+            .upload_part()
+            .bucket("bucket")
+            .key("key")
+            .upload_id("upload")
+            .part_number(part_number)
+            .sse_customer_algorithm("AES256")
+            .sse_customer_key(SSE_C_KEY)
+            .sse_customer_key_md5("bWQ1")
+            .body(b"part data".to_vec().into())
+            .send()
+            .await
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_redact_uri() {
+    let uri: http::Uri = "https://host/bucket/key?partNumber=1&X-Amz-Signature=abcd&\
+                          X-Amz-Credential=AKID%2F20220301&uploadId=u"
+        .parse()
+        .unwrap();
+
+    assert_eq!(
+        "/bucket/key?partNumber=1&X-Amz-Signature=<redacted>&\
+         X-Amz-Credential=<redacted>&uploadId=u",
+        redact_uri(&uri)
+    );
+}
+
+#[tokio::test]
+async fn test_captures_each_attempt_of_one_part() {
+    let (port, _) = mock_endpoint().await;
+    let capture = PartCapture::in_memory(2);
+
+    upload_parts(&client(port, &capture)).await;
+
+    let attempts = capture.attempts();
+    assert_eq!(2, attempts.len(), "{:?}", attempts);
+    assert_eq!((1, Some(500)), (attempts[0].attempt, attempts[0].status));
+    assert!(attempts[0]
+        .error
+        .as_deref()
+        .unwrap()
+        .contains("InternalError"));
+    assert_eq!((2, Some(200)), (attempts[1].attempt, attempts[1].status));
+    assert_eq!(None, attempts[1].error);
+    for attempt in &attempts {
+        assert_eq!("PUT", attempt.method);
+        assert!(attempt.uri.contains("partNumber=2"), "{}", attempt.uri);
+        assert!(attempt.first_byte_ms.unwrap() <= attempt.last_byte_ms.unwrap());
+    }
+    assert!(attempts[1]
+        .response_headers
+        .contains(&("x-amz-request-id".to_string(), "request-2".to_string())));
+}
+
+#[tokio::test]
+async fn test_capture_file_has_no_secrets() {
+    let (port, received) = mock_endpoint().await;
+    let path = std::env::temp_dir().join(format!("capture-{}.json", uuid::Uuid::new_v4()));
+    let capture = PartCapture::new(2, &path);
+
+    upload_parts(&client(port, &capture)).await;
+    let output = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(output.contains("\"part_number\": 2"), "{}", output);
+    assert!(
+        output.contains("x-amz-server-side-encryption-customer-algorithm"),
+        "{}",
+        output
+    );
+    assert!(output.contains("<redacted>"), "{}", output);
+    for secret in &[ACCESS_KEY_ID, SESSION_TOKEN, SSE_C_KEY, "SECRET-MARKER"] {
+        assert!(!output.contains(secret), "{} in {}", secret, output);
+    }
+    for authorization in received.lock().unwrap().iter() {
+        let signature = authorization.rsplit("Signature=").next().unwrap();
+        assert!(!output.contains(signature), "{}", output);
+    }
+}
@@ -0,0 +1,280 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::{Client, Error};
+use hyper::{Body, Method, Request, Response};
+use s3_service::config::{save_part_size_cap, TransferConfig};
+use s3_service::failover::EndpointPool;
+use s3_service::part_size_cap::{
+    is_entity_too_large, next_part_cap, plan_upload_capped, upload_multipart_window_split,
+};
+use s3_service::staged_upload::{upload_parts_range, PartsRangeOptions};
+use s3_service::upload::{SourceWindow, UploadPlanOptions, UploadStrategy, MIN_PART_SIZE};
+use s3_service::verbosity::VerbosityConfig;
+use std::sync::{Arc, Mutex};
+
+const MIB: u64 = 1024 * 1024;
+
+/// The requests received: `create`, `part`, `rejected`, `abort`, or
+/// `complete`, with the size of the body.
+type Captured = Arc<Mutex<Vec<(&'static str, usize)>>>;
+
+/// Starts a server answering multipart uploads that rejects parts over
+/// `max_part_size` bytes with EntityTooLarge.
+fn mock_server(max_part_size: usize) -> (Client, Captured) {
+    let captured = Captured::default();
+    let recorder = captured.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let (request, status, response) = if method == Method::PUT {
+                if body.len() > max_part_size {
+                    (
+                        "rejected",
+                        400,
+                        "<Error><Code>EntityTooLarge</Code>\
+                         <Message>Your proposed upload exceeds the maximum allowed size\
+                         </Message></Error>",
+                    )
+                } else {
+                    ("part", 200, "")
+                }
+            } else if method == Method::DELETE {
+                ("abort", 204, "")
+            } else if query.contains("uploads") {
+                (
+                    "create",
+                    200,
+                    "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                )
+            } else {
+                (
+                    "complete",
+                    200,
+                    "<CompleteMultipartUploadResult><ETag>\"complete-etag\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                )
+            };
+            recorder.lock().unwrap().push((request, body.len()));
+            Response::builder()
+                .status(status)
+                .header("ETag", "\"part-etag\"")
+                .body(Body::from(response))
+                .unwrap()
+        }
+    });
+
+    (common::client_for(port), captured)
+}
+
+fn test_file(size: u64) -> String {
+    let path = std::env::temp_dir().join(format!("part-cap-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![b'x'; size as usize]).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn plan_options(part_size: u64) -> UploadPlanOptions {
+    UploadPlanOptions {
+        multipart_threshold: MIN_PART_SIZE,
+        part_size: Some(part_size),
+        num_parts: None,
+    }
+}
+
+#[test]
+fn test_is_entity_too_large_matches_the_error_code() {
+    let error = |code: &str, message: &str| {
+        Error::Unhandled(Box::new(
+            aws_smithy_types::Error::builder()
+                .code(code)
+                .message(message)
+                .build(),
+        ))
+    };
+
+    assert!(is_entity_too_large(&error(
+        "EntityTooLarge",
+        "Your proposed upload exceeds the maximum allowed size"
+    )));
+    assert!(!is_entity_too_large(&error(
+        "InvalidArgument",
+        "EntityTooLarge is not the reason"
+    )));
+    assert!(!is_entity_too_large(&Error::Unhandled(Box::from(
+        "EntityTooLarge"
+    ))));
+}
+
+#[test]
+fn test_next_part_cap() {
+    assert_eq!(Some(8 * MIB), next_part_cap(16 * MIB));
+    assert_eq!(Some(MIN_PART_SIZE), next_part_cap(MIN_PART_SIZE + 1));
+    assert_eq!(None, next_part_cap(MIN_PART_SIZE));
+}
+
+#[test]
+fn test_plan_upload_capped() {
+    let options = plan_options(64 * MIB);
+    let plan = plan_upload_capped(100 * MIB, &options, Some(10 * MIB));
+    assert_eq!(UploadStrategy::Multipart, plan.strategy);
+    assert!(plan.num_parts >= 10);
+    assert!(plan.part_size <= 10 * MIB);
+    assert!(plan.last_part_size <= 10 * MIB);
+
+    // A cap the plan is already within changes nothing.
+    let uncapped = plan_upload_capped(100 * MIB, &options, None);
+    assert_eq!(
+        uncapped,
+        plan_upload_capped(100 * MIB, &options, Some(200 * MIB))
+    );
+
+    // A cap below the minimum part size is raised to it.
+    let plan = plan_upload_capped(20 * MIB, &options, Some(MIB));
+    assert_eq!(4, plan.num_parts);
+    assert_eq!(MIN_PART_SIZE, plan.last_part_size);
+
+    // A single PutObject is not capped.
+    let plan = plan_upload_capped(4 * MIB, &options, Some(MIN_PART_SIZE));
+    assert_eq!(UploadStrategy::PutObject, plan.strategy);
+}
+
+#[tokio::test]
+async fn test_split_until_accepted() {
+    let (client, captured) = mock_server(8 * MIB as usize);
+    let file = test_file(24 * MIB);
+    let window = SourceWindow::for_file(&file, None, None).unwrap();
+
+    let split = upload_multipart_window_split(
+        &EndpointPool::single(client),
+        "bucket",
+        "key",
+        &file,
+        window,
+        &plan_options(24 * MIB),
+        None,
+        None,
+        VerbosityConfig::from_flag(false),
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!("complete-etag", split.e_tag);
+    assert!(split.replans > 0);
+    let cap = split.cap.unwrap();
+    assert!(cap <= 8 * MIB && cap >= MIN_PART_SIZE);
+    assert!(split.plan.last_part_size <= cap);
+
+    let requests = captured.lock().unwrap();
+    let count = |name| requests.iter().filter(|(r, _)| *r == name).count();
+    assert_eq!(split.replans as usize, count("abort"));
+    assert_eq!(split.replans as usize + 1, count("create"));
+    assert_eq!(1, count("complete"));
+    assert_eq!(split.plan.num_parts, count("part"));
+}
+
+#[tokio::test]
+async fn test_split_starts_within_known_cap() {
+    let (client, captured) = mock_server(8 * MIB as usize);
+    let file = test_file(24 * MIB);
+    let window = SourceWindow::for_file(&file, None, None).unwrap();
+
+    let split = upload_multipart_window_split(
+        &EndpointPool::single(client),
+        "bucket",
+        "key",
+        &file,
+        window,
+        &plan_options(24 * MIB),
+        Some(6 * MIB),
+        None,
+        VerbosityConfig::from_flag(false),
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(0, split.replans);
+    assert_eq!(Some(6 * MIB), split.cap);
+    let requests = captured.lock().unwrap();
+    assert!(requests.iter().all(|(request, _)| *request != "rejected"));
+}
+
+#[tokio::test]
+async fn test_split_gives_up_at_min_part_size() {
+    let (client, _) = mock_server(MIB as usize);
+    let file = test_file(12 * MIB);
+    let window = SourceWindow::for_file(&file, None, None).unwrap();
+
+    let result = upload_multipart_window_split(
+        &EndpointPool::single(client),
+        "bucket",
+        "key",
+        &file,
+        window,
+        &plan_options(12 * MIB),
+        None,
+        None,
+        VerbosityConfig::from_flag(false),
+    )
+    .await;
+    std::fs::remove_file(&file).unwrap();
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("cannot be smaller"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_staged_part_too_large() {
+    let (client, _) = mock_server(MIB as usize);
+    let file = test_file(12 * MIB);
+    let window = SourceWindow::for_file(&file, None, None).unwrap();
+
+    let result = upload_parts_range(
+        &client,
+        "bucket",
+        "key",
+        "upload-1",
+        &file,
+        window,
+        &PartsRangeOptions {
+            part_size: 12 * MIB,
+            collision_check: false,
+            ..Default::default()
+        },
+    )
+    .await;
+    std::fs::remove_file(&file).unwrap();
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("are not split automatically"), "{}", err);
+    assert!(err.contains(&format!("{} bytes", 6 * MIB)), "{}", err);
+}
+
+#[test]
+fn test_save_part_size_cap() {
+    let path = std::env::temp_dir().join(format!("config-test-{}.toml", uuid::Uuid::new_v4()));
+    std::fs::write(&path, "[tuning]\nconcurrency = 4\n").unwrap();
+
+    save_part_size_cap(&path, "https://minio.internal:9000", 64 * MIB).unwrap();
+    save_part_size_cap(&path, "amazon-s3", 128 * MIB).unwrap();
+    save_part_size_cap(&path, "https://minio.internal:9000", 32 * MIB).unwrap();
+
+    let config = TransferConfig::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        Some(&(32 * MIB)),
+        config.part_size_caps.get("https://minio.internal:9000")
+    );
+    assert_eq!(Some(&(128 * MIB)), config.part_size_caps.get("amazon-s3"));
+    assert_eq!(Some(4), config.tuning.concurrency);
+    assert!(TransferConfig::from_toml("[part_size_caps]\n\"amazon-s3\" = 1024\n").is_err());
+}
@@ -0,0 +1,373 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use hyper::{Body, Method, Request, Response};
+use s3_service::notify::UploadPayload;
+use s3_service::pipeline::{
+    consume_queue, parse_notification, parse_visibility_timeout, queue_policy, setup_pipeline,
+    ConsumerOptions,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TOPIC_ARN: &str = "arn:aws:sns:us-east-1:123456789012:uploads";
+const QUEUE_ARN: &str = "arn:aws:sqs:us-east-1:123456789012:uploads";
+const QUEUE_URL: &str = "https://sqs.us-east-1.amazonaws.com/123456789012/uploads";
+
+/// The requests received: the SNS or SQS action and its decoded form body,
+/// or `GET` and the path of an S3 request.
+type Captured = Arc<Mutex<Vec<(String, String)>>>;
+
+fn query_response(action: &str, result: &str) -> String {
+    format!(
+        "<{0}Response><{0}Result>{1}</{0}Result>\
+         <ResponseMetadata><RequestId>r</RequestId></ResponseMetadata></{0}Response>",
+        action, result
+    )
+}
+
+fn message(id: &str, body: &str) -> String {
+    format!(
+        "<Message><MessageId>{0}</MessageId><ReceiptHandle>receipt-{0}</ReceiptHandle>\
+         <MD5OfBody>ignored</MD5OfBody><Body>{1}</Body></Message>",
+        id,
+        body.replace('&', "&amp;").replace('<', "&lt;")
+    )
+}
+
+/// Starts a server answering the SNS and SQS actions of the pipeline, with
+/// `messages` on the first ReceiveMessage and none afterwards, and S3
+/// GetObject: `slow` after a delay, `missing` with NoSuchKey, and any other
+/// key with `hello`. DeleteMessage fails for the message `undeletable`.
+fn mock_server(messages: Vec<String>) -> (u16, Captured) {
+    let captured = Captured::default();
+    let messages = Arc::new(Mutex::new(Some(messages)));
+    let recorder = captured.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        let messages = messages.clone();
+        async move {
+            if req.method() == Method::GET {
+                let path = req.uri().path().to_string();
+                recorder
+                    .lock()
+                    .unwrap()
+                    .push(("GET".to_string(), path.clone()));
+                let response = if path.ends_with("/missing") {
+                    Response::builder().status(404).body(Body::from(
+                        "<Error><Code>NoSuchKey</Code><Message>missing</Message></Error>",
+                    ))
+                } else {
+                    if path.ends_with("/slow") {
+                        tokio::time::sleep(Duration::from_millis(1500)).await;
+                    }
+                    Response::builder().body(Body::from("hello"))
+                };
+                return response.unwrap();
+            }
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let form = percent_encoding::percent_decode(&body)
+                .decode_utf8_lossy()
+                .replace('+', " ");
+            let action = form
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("Action="))
+                .unwrap_or_default()
+                .to_string();
+            recorder
+                .lock()
+                .unwrap()
+                .push((action.clone(), form.clone()));
+            if action == "DeleteMessage" && form.contains("ReceiptHandle=receipt-undeletable") {
+                return Response::builder()
+                    .status(400)
+                    .body(Body::from(
+                        "<ErrorResponse><Error><Type>Sender</Type>\
+                         <Code>ReceiptHandleIsInvalid</Code><Message>expired</Message></Error>\
+                         <RequestId>r</RequestId></ErrorResponse>",
+                    ))
+                    .unwrap();
+            }
+            let result = match action.as_str() {
+                "CreateTopic" => format!("<TopicArn>{}</TopicArn>", TOPIC_ARN),
+                "CreateQueue" => format!("<QueueUrl>{}</QueueUrl>", QUEUE_URL),
+                "GetQueueAttributes" => format!(
+                    "<Attribute><Name>QueueArn</Name><Value>{}</Value></Attribute>",
+                    QUEUE_ARN
+                ),
+                "Subscribe" => format!(
+                    "<SubscriptionArn>{}:subscription-1</SubscriptionArn>",
+                    TOPIC_ARN
+                ),
+                "ReceiveMessage" => messages.lock().unwrap().take().unwrap_or_default().concat(),
+                _ => String::new(),
+            };
+            Response::builder()
+                .body(Body::from(query_response(&action, &result)))
+                .unwrap()
+        }
+    });
+    (port, captured)
+}
+
+fn sqs_client(port: u16) -> aws_sdk_sqs::Client {
+    use aws_sdk_sqs::{Credentials, Endpoint, Region, RetryConfig};
+    let conf = aws_sdk_sqs::Config::builder()
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("access", "secret", None, None, "test"))
+        .endpoint_resolver(Endpoint::immutable(common::url_for(port).parse().unwrap()))
+        .retry_config(RetryConfig::disabled())
+        .build();
+    aws_sdk_sqs::Client::from_conf(conf)
+}
+
+fn sns_client(port: u16) -> aws_sdk_sns::Client {
+    use aws_sdk_sns::{Credentials, Endpoint, Region, RetryConfig};
+    let conf = aws_sdk_sns::Config::builder()
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("access", "secret", None, None, "test"))
+        .endpoint_resolver(Endpoint::immutable(common::url_for(port).parse().unwrap()))
+        .retry_config(RetryConfig::disabled())
+        .build();
+    aws_sdk_sns::Client::from_conf(conf)
+}
+
+fn payload(key: &str) -> String {
+    serde_json::to_string(&UploadPayload {
+        bucket: "bucket".to_string(),
+        key: key.to_string(),
+        e_tag: "etag-1".to_string(),
+        size: 5,
+    })
+    .unwrap()
+}
+
+fn requests_of<'a>(captured: &'a [(String, String)], action: &str) -> Vec<&'a str> {
+    captured
+        .iter()
+        .filter(|(name, _)| name == action)
+        .map(|(_, form)| form.as_str())
+        .collect()
+}
+
+#[test]
+fn test_parse_notification() {
+    let raw = parse_notification(&payload("reports/a.csv")).unwrap();
+    assert_eq!("reports/a.csv", raw.key);
+
+    let envelope = serde_json::json!({
+        "Type": "Notification",
+        "MessageId": "message-1",
+        "TopicArn": TOPIC_ARN,
+        "Message": payload("reports/b.csv"),
+    });
+    let wrapped = parse_notification(&envelope.to_string()).unwrap();
+    assert_eq!("reports/b.csv", wrapped.key);
+    assert_eq!(5, wrapped.size);
+
+    let confirmation = serde_json::json!({
+        "Type": "SubscriptionConfirmation",
+        "Message": "You have chosen to subscribe",
+    });
+    assert!(parse_notification(&confirmation.to_string()).is_err());
+    assert!(parse_notification("not json").is_err());
+}
+
+#[test]
+fn test_queue_policy() {
+    let policy: serde_json::Value =
+        serde_json::from_str(&queue_policy(QUEUE_ARN, TOPIC_ARN)).unwrap();
+    let statement = &policy["Statement"][0];
+    assert_eq!("sqs:SendMessage", statement["Action"]);
+    assert_eq!(QUEUE_ARN, statement["Resource"]);
+    assert_eq!(
+        TOPIC_ARN,
+        statement["Condition"]["ArnEquals"]["aws:SourceArn"]
+    );
+}
+
+#[tokio::test]
+async fn test_setup_pipeline() {
+    let (port, captured) = mock_server(Vec::new());
+    let resources = setup_pipeline(
+        &sns_client(port),
+        &sqs_client(port),
+        "uploads",
+        "uploads",
+        Duration::from_secs(90),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(TOPIC_ARN, resources.topic_arn);
+    assert_eq!(QUEUE_URL, resources.queue_url);
+    assert_eq!(QUEUE_ARN, resources.queue_arn);
+    let captured = captured.lock().unwrap();
+    let create_queue = requests_of(&captured, "CreateQueue");
+    assert!(create_queue[0].contains("VisibilityTimeout"));
+    assert!(create_queue[0].contains("90"));
+    let policy = requests_of(&captured, "SetQueueAttributes");
+    assert!(policy[0].contains(TOPIC_ARN));
+    let subscribe = requests_of(&captured, "Subscribe");
+    assert!(subscribe[0].contains("Protocol=sqs"));
+    assert!(subscribe[0].contains(&format!("Endpoint={}", QUEUE_ARN)));
+    assert!(subscribe[0].contains("RawMessageDelivery"));
+}
+
+#[tokio::test]
+async fn test_consume_queue() {
+    let envelope = serde_json::json!({
+        "Type": "Notification",
+        "Message": payload("wrapped"),
+    });
+    let (port, captured) = mock_server(vec![
+        message("m1", &payload("raw")),
+        message("m2", &envelope.to_string()),
+        message("m3", "not a notification"),
+        message("m4", &payload("missing")),
+        message("m5", &payload("slow")),
+    ]);
+    let options = ConsumerOptions {
+        visibility_timeout: Duration::from_secs(2),
+        wait_time: Duration::from_secs(0),
+        max_empty_receives: Some(1),
+        ..Default::default()
+    };
+    let mut seen = Vec::new();
+    let summary = consume_queue(
+        &common::client_for(port),
+        &sqs_client(port),
+        QUEUE_URL,
+        &options,
+        |object| seen.push(object.key.clone()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(5, summary.received);
+    let mut processed: Vec<_> = summary
+        .processed
+        .iter()
+        .map(|object| object.key.as_str())
+        .collect();
+    processed.sort_unstable();
+    assert_eq!(vec!["raw", "slow", "wrapped"], processed);
+    assert_eq!(3, seen.len());
+    let hello = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    assert!(summary
+        .processed
+        .iter()
+        .all(|object| object.sha256 == hello && object.size == 5));
+    assert_eq!(vec!["m3".to_string()], summary.skipped);
+    assert_eq!(1, summary.failed.len());
+    assert_eq!("m4", summary.failed[0].0);
+
+    // The slow object outlived half the visibility timeout.
+    let slow = summary
+        .processed
+        .iter()
+        .find(|object| object.key == "slow")
+        .unwrap();
+    assert!(slow.extensions >= 1);
+
+    let captured = captured.lock().unwrap();
+    let extended = requests_of(&captured, "ChangeMessageVisibility");
+    assert!(extended
+        .iter()
+        .all(|form| form.contains("ReceiptHandle=receipt-m5")));
+    // The failed message stays in the queue.
+    let mut deleted: Vec<_> = requests_of(&captured, "DeleteMessage")
+        .iter()
+        .map(|form| {
+            form.split('&')
+                .find_map(|pair| pair.strip_prefix("ReceiptHandle="))
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    deleted.sort();
+    assert_eq!(
+        vec!["receipt-m1", "receipt-m2", "receipt-m3", "receipt-m5"],
+        deleted
+    );
+}
+
+#[tokio::test]
+async fn test_failed_delete_is_recorded() {
+    let (port, captured) = mock_server(vec![
+        message("undeletable", &payload("first")),
+        message("m2", &payload("second")),
+    ]);
+    let options = ConsumerOptions {
+        visibility_timeout: Duration::from_secs(2),
+        wait_time: Duration::from_secs(0),
+        max_empty_receives: Some(1),
+        ..Default::default()
+    };
+
+    let summary = consume_queue(
+        &common::client_for(port),
+        &sqs_client(port),
+        QUEUE_URL,
+        &options,
+        |_| {},
+    )
+    .await
+    .unwrap();
+
+    // Both objects were processed, and the consumer went on to receive again.
+    assert_eq!(2, summary.processed.len());
+    assert_eq!(1, summary.undeleted.len());
+    assert_eq!("undeletable", summary.undeleted[0].0);
+    assert!(
+        summary.undeleted[0].1.contains("ReceiptHandleIsInvalid"),
+        "{}",
+        summary.undeleted[0].1
+    );
+    let captured = captured.lock().unwrap();
+    assert_eq!(2, requests_of(&captured, "DeleteMessage").len());
+    assert_eq!(2, requests_of(&captured, "ReceiveMessage").len());
+}
+
+#[tokio::test]
+async fn test_visibility_timeout_bounds() {
+    assert!(parse_visibility_timeout("1s").is_err());
+    assert!(parse_visibility_timeout("500ms").is_err());
+    assert_eq!(Ok(Duration::from_secs(2)), parse_visibility_timeout("2s"));
+    assert_eq!(
+        Ok(Duration::from_secs(12 * 3600)),
+        parse_visibility_timeout("12h")
+    );
+    let err = parse_visibility_timeout("13h").unwrap_err();
+    assert!(err.contains("between 2 s and 43200 s"), "{}", err);
+
+    // The library refuses it too, before any request.
+    let (port, captured) = mock_server(Vec::new());
+    let options = ConsumerOptions {
+        visibility_timeout: Duration::from_secs(1),
+        ..Default::default()
+    };
+    assert!(consume_queue(
+        &common::client_for(port),
+        &sqs_client(port),
+        QUEUE_URL,
+        &options,
+        |_| {}
+    )
+    .await
+    .is_err());
+    assert!(setup_pipeline(
+        &sns_client(port),
+        &sqs_client(port),
+        "uploads",
+        "uploads",
+        Duration::from_secs(13 * 3600)
+    )
+    .await
+    .is_err());
+    assert!(captured.lock().unwrap().is_empty());
+}
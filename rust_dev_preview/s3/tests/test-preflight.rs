@@ -0,0 +1,111 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use common::MockS3;
+use s3_service::preflight::{run_preflight, PreflightMode, PreflightOptions};
+
+const ALL_CHECKS: PreflightOptions = PreflightOptions {
+    sibling_key: true,
+    put_object: true,
+};
+
+async fn missing(mock: &MockS3) -> Vec<(String, Option<String>)> {
+    let report = run_preflight(mock, "bucket", "key", &ALL_CHECKS).await;
+    assert!(!report.passed);
+    report
+        .checks
+        .into_iter()
+        .filter(|c| !c.passed)
+        .map(|c| (c.operation, c.missing_permission))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_preflight_passes() {
+    let mock = MockS3::new();
+    let report = run_preflight(&mock, "bucket", "key", &ALL_CHECKS).await;
+    assert!(report.passed);
+    assert_eq!(
+        vec![
+            "HeadBucket",
+            "CreateMultipartUpload",
+            "AbortMultipartUpload",
+            "PutObject",
+            "DeleteObject"
+        ],
+        mock.calls()
+    );
+
+    // Without --preflight-put, no object is written.
+    let mock = MockS3::new();
+    let report = run_preflight(&mock, "bucket", "key", &PreflightOptions::default()).await;
+    assert!(report.passed);
+    assert_eq!(3, mock.calls().len());
+}
+
+#[tokio::test]
+async fn test_preflight_missing_list_bucket() {
+    let mock = MockS3::new().deny("HeadBucket");
+    assert_eq!(
+        vec![("HeadBucket".to_string(), Some("s3:ListBucket".to_string()))],
+        missing(&mock).await
+    );
+    // Nothing else is attempted.
+    assert_eq!(vec!["HeadBucket"], mock.calls());
+}
+
+#[tokio::test]
+async fn test_preflight_missing_put_object() {
+    let mock = MockS3::new().deny("CreateMultipartUpload");
+    assert_eq!(
+        vec![(
+            "CreateMultipartUpload".to_string(),
+            Some("s3:PutObject".to_string())
+        )],
+        missing(&mock).await
+    );
+
+    let mock = MockS3::new().deny("PutObject");
+    assert_eq!(
+        vec![("PutObject".to_string(), Some("s3:PutObject".to_string()))],
+        missing(&mock).await
+    );
+    assert!(!mock.calls().contains(&"DeleteObject".to_string()));
+}
+
+#[tokio::test]
+async fn test_preflight_missing_abort() {
+    let mock = MockS3::new().deny("AbortMultipartUpload");
+    assert_eq!(
+        vec![(
+            "AbortMultipartUpload".to_string(),
+            Some("s3:AbortMultipartUpload".to_string())
+        )],
+        missing(&mock).await
+    );
+    assert!(!mock.calls().contains(&"PutObject".to_string()));
+}
+
+#[tokio::test]
+async fn test_preflight_missing_delete() {
+    let mock = MockS3::new().deny("DeleteObject");
+    assert_eq!(
+        vec![(
+            "DeleteObject".to_string(),
+            Some("s3:DeleteObject".to_string())
+        )],
+        missing(&mock).await
+    );
+}
+
+#[test]
+fn test_preflight_auto_threshold() {
+    assert!(!PreflightMode::Auto.should_run(99, 100));
+    assert!(PreflightMode::Auto.should_run(100, 100));
+    assert!(PreflightMode::Always.should_run(0, 100));
+    assert!(!PreflightMode::Off.should_run(1000, 100));
+}
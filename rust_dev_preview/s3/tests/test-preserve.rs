@@ -0,0 +1,361 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+#![cfg(unix)]
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::download::download_prefix_with_options;
+use s3_service::durable::FsyncOptions;
+use s3_service::preserve::{apply, FileMetadata, RestoreOptions, SYMLINK_TARGET_METADATA};
+use s3_service::sync::{format_mtime, sync_directory, SyncOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The body and the `x-amz-meta-*` entries of each object.
+type Store = BTreeMap<String, (Vec<u8>, HashMap<String, String>)>;
+
+/// Starts a server keeping objects and their metadata in memory. Every
+/// object is listed as last modified now.
+async fn mock_s3() -> (Client, Arc<Mutex<Store>>) {
+    let store = Arc::new(Mutex::new(Store::new()));
+    let shared = store.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let store = shared.clone();
+        async move {
+            let method = req.method().clone();
+            let key = req.uri().path().trim_start_matches("/bucket/").to_string();
+            let metadata: HashMap<String, String> = req
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    let name = name.as_str().strip_prefix("x-amz-meta-")?;
+                    Some((name.to_string(), value.to_str().unwrap().to_string()))
+                })
+                .collect();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let mut store = store.lock().unwrap();
+            let response = if method == Method::PUT {
+                store.insert(key, (body.to_vec(), metadata));
+                Response::builder()
+                    .header("ETag", "\"etag\"")
+                    .body(Body::empty())
+            } else if key == "/bucket" {
+                let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S.000Z");
+                let contents: String = store
+                    .iter()
+                    .map(|(key, (body, _))| {
+                        format!(
+                            "<Contents><Key>{}</Key><Size>{}</Size>\
+                             <LastModified>{}</LastModified></Contents>",
+                            key,
+                            body.len(),
+                            now
+                        )
+                    })
+                    .collect();
+                Response::builder().body(Body::from(format!(
+                    "<ListBucketResult><Name>bucket</Name>\
+                     <IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                    contents
+                )))
+            } else {
+                let (body, metadata) = store.get(&key).unwrap();
+                let mut response = Response::builder()
+                    .header("Content-Length", body.len())
+                    .header("ETag", "\"etag\"");
+                for (name, value) in metadata {
+                    response = response.header(format!("x-amz-meta-{}", name), value);
+                }
+                if method == Method::HEAD {
+                    response.body(Body::empty())
+                } else {
+                    response.body(Body::from(body.clone()))
+                }
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), store)
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("preserve-{}-{}", name, uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes `path` with `mode` and a modification time of `secs` after the
+/// epoch, plus 250 ms.
+fn write(path: &Path, content: &[u8], mode: u32, secs: u64) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+    set_mtime(path, secs);
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+}
+
+fn set_mtime(path: &Path, secs: u64) {
+    std::fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(250))
+        .unwrap();
+}
+
+fn mode(path: &Path) -> u32 {
+    std::fs::symlink_metadata(path)
+        .unwrap()
+        .permissions()
+        .mode()
+        & 0o7777
+}
+
+fn mtime(path: &Path) -> String {
+    format_mtime(std::fs::symlink_metadata(path).unwrap().modified().unwrap())
+}
+
+/// A directory with files of different modes and times, and two links.
+fn source_tree() -> PathBuf {
+    let dir = temp_dir("source");
+    write(&dir.join("script.sh"), b"#!/bin/sh\n", 0o755, 1_577_880_000);
+    write(&dir.join("secret.txt"), b"secret", 0o600, 1_546_344_000);
+    write(
+        &dir.join("sub/readonly.txt"),
+        b"read only",
+        0o444,
+        1_600_000_000,
+    );
+    std::os::unix::fs::symlink("script.sh", dir.join("link")).unwrap();
+    std::os::unix::fs::symlink("../secret.txt", dir.join("sub/up")).unwrap();
+    dir
+}
+
+fn preserving() -> SyncOptions {
+    SyncOptions {
+        preserve: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_metadata_round_trip() {
+    let metadata = FileMetadata {
+        mode: Some(0o4755),
+        mtime: Some(UNIX_EPOCH + Duration::from_millis(1_600_000_000_123)),
+        uid: Some(1000),
+        gid: Some(100),
+        symlink_target: Some(PathBuf::from("../dir with spaces/café%")),
+    };
+
+    let entries = metadata.to_metadata();
+
+    assert_eq!("4755", entries["file-mode"]);
+    assert_eq!("1600000000.123", entries["file-mtime"]);
+    let target = &entries[SYMLINK_TARGET_METADATA];
+    assert!(target.is_ascii() && !target.contains("é"), "{}", target);
+    assert_eq!(metadata, FileMetadata::from_metadata(&entries));
+    assert_eq!(
+        FileMetadata::default(),
+        FileMetadata::from_metadata(&HashMap::new())
+    );
+}
+
+#[tokio::test]
+async fn test_round_trip_preserves_modes_mtimes_and_symlinks() {
+    let (client, store) = mock_s3().await;
+    let source = source_tree();
+
+    let summary = sync_directory(&client, "bucket", &source, "backup/", &preserving(), false)
+        .await
+        .unwrap();
+    assert_eq!(5, summary.uploaded.len(), "{:?}", summary.failed);
+    {
+        let store = store.lock().unwrap();
+        let (body, metadata) = &store["backup/link"];
+        assert!(body.is_empty());
+        assert_eq!("script.sh", metadata[SYMLINK_TARGET_METADATA]);
+        assert_eq!("755", store["backup/script.sh"].1["file-mode"]);
+    }
+
+    let dest = temp_dir("dest");
+    let restored = download_prefix_with_options(
+        &client,
+        "bucket",
+        "backup/",
+        &dest,
+        false,
+        &FsyncOptions::default(),
+        Some(&RestoreOptions::default()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(3, restored.files);
+    assert_eq!(2, restored.symlinks);
+    for (file, expected_mode) in &[
+        ("script.sh", 0o755),
+        ("secret.txt", 0o600),
+        ("sub/readonly.txt", 0o444),
+    ] {
+        assert_eq!(*expected_mode, mode(&dest.join(file)), "{}", file);
+        assert_eq!(
+            mtime(&source.join(file)),
+            mtime(&dest.join(file)),
+            "{}",
+            file
+        );
+    }
+    assert_eq!(
+        Path::new("script.sh"),
+        std::fs::read_link(dest.join("link")).unwrap()
+    );
+    assert_eq!(
+        Path::new("../secret.txt"),
+        std::fs::read_link(dest.join("sub/up")).unwrap()
+    );
+    assert_eq!(mtime(&source.join("link")), mtime(&dest.join("link")));
+    assert_eq!(b"secret", &std::fs::read(dest.join("sub/up")).unwrap()[..]);
+
+    std::fs::remove_dir_all(&source).unwrap();
+    std::fs::remove_dir_all(&dest).unwrap();
+}
+
+#[tokio::test]
+async fn test_without_preserve_links_are_skipped_and_modes_default() {
+    let (client, store) = mock_s3().await;
+    let source = source_tree();
+
+    sync_directory(
+        &client,
+        "bucket",
+        &source,
+        "",
+        &SyncOptions::default(),
+        false,
+    )
+    .await
+    .unwrap();
+
+    let store = store.lock().unwrap();
+    let keys: Vec<_> = store.keys().map(|key| key.as_str()).collect();
+    assert_eq!(vec!["script.sh", "secret.txt", "sub/readonly.txt"], keys);
+    assert!(!store["script.sh"].1.contains_key("file-mode"));
+    std::fs::remove_dir_all(&source).unwrap();
+}
+
+#[tokio::test]
+async fn test_use_preserved_mtime_catches_backdated_change() {
+    let (client, _) = mock_s3().await;
+    let source = source_tree();
+    sync_directory(&client, "bucket", &source, "backup/", &preserving(), false)
+        .await
+        .unwrap();
+
+    // Edited after the upload, but with a time between the preserved one
+    // and the upload, as a restored file would be.
+    let secret = source.join("secret.txt");
+    std::fs::write(&secret, b"SECRET").unwrap();
+    set_mtime(&secret, 1_609_459_200);
+
+    let by_upload_time = sync_directory(&client, "bucket", &source, "backup/", &preserving(), true)
+        .await
+        .unwrap();
+    let by_preserved_mtime = sync_directory(
+        &client,
+        "bucket",
+        &source,
+        "backup/",
+        &SyncOptions {
+            use_preserved_mtime: true,
+            ..preserving()
+        },
+        true,
+    )
+    .await
+    .unwrap();
+
+    assert!(by_upload_time.uploaded.is_empty());
+    assert_eq!(vec!["backup/secret.txt"], by_preserved_mtime.uploaded);
+    assert_eq!(4, by_preserved_mtime.identical.len());
+    std::fs::remove_dir_all(&source).unwrap();
+}
+
+#[test]
+fn test_numeric_owner_needs_root() {
+    let dir = temp_dir("owner");
+    let path = dir.join("file");
+    std::fs::write(&path, b"owned").unwrap();
+    let metadata = FileMetadata {
+        uid: Some(1234),
+        gid: Some(5678),
+        ..Default::default()
+    };
+
+    let ignored = apply(&path, &metadata, &RestoreOptions::default()).unwrap();
+    let warnings = apply(
+        &path,
+        &metadata,
+        &RestoreOptions {
+            numeric_owner: true,
+        },
+    )
+    .unwrap();
+
+    assert!(ignored.is_empty());
+    if unsafe { libc::geteuid() } == 0 {
+        use std::os::unix::fs::MetadataExt;
+        let owner = std::fs::metadata(&path).unwrap();
+        assert_eq!((1234, 5678), (owner.uid(), owner.gid()));
+        assert!(warnings.is_empty());
+    } else {
+        assert_eq!(1, warnings.len());
+        assert!(warnings[0].contains("root"));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_restored_links_do_not_redirect_files() {
+    let (client, store) = mock_s3().await;
+    let outside = temp_dir("outside");
+    {
+        let mut store = store.lock().unwrap();
+        let mut link = HashMap::new();
+        link.insert(
+            SYMLINK_TARGET_METADATA.to_string(),
+            outside.to_string_lossy().into_owned(),
+        );
+        // A link to a directory outside, and a file below the link.
+        store.insert("dir".to_string(), (Vec::new(), link));
+        store.insert("dir/file".to_string(), (b"data".to_vec(), HashMap::new()));
+    }
+    let dest = temp_dir("dest");
+
+    let result = download_prefix_with_options(
+        &client,
+        "bucket",
+        "",
+        &dest,
+        false,
+        &FsyncOptions::default(),
+        Some(&RestoreOptions::default()),
+    )
+    .await;
+
+    // Whatever the listing order, the file is written first, and the link
+    // then fails on the directory in its way instead of replacing it.
+    assert!(result.is_err());
+    assert!(!outside.join("file").exists());
+    assert_eq!(b"data", &std::fs::read(dest.join("dir/file")).unwrap()[..]);
+    std::fs::remove_dir_all(&outside).unwrap();
+    std::fs::remove_dir_all(&dest).unwrap();
+}
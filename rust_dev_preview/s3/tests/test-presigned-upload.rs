@@ -0,0 +1,144 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use hyper::{Body, Request, Response};
+use s3_service::presigned_upload::upload_via_presigned_url_with_policy;
+use s3_service::retry::RetryPolicy;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const SIGNATURE: &str = "X-Amz-Signature=0123abcd";
+
+/// The path, Content-Length, and body of the requests received.
+type Received = Arc<Mutex<Vec<(String, String, Vec<u8>)>>>;
+
+/// `/bucket/flaky` fails with a 503 the first time, `/bucket/denied` always
+/// with a 403; the rest succeed.
+async fn mock_endpoint() -> (String, Received) {
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let recorder = received.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let path = req.uri().path().to_string();
+            let content_length = req
+                .headers()
+                .get("content-length")
+                .map(|v| v.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let attempts = {
+                let mut received = recorder.lock().unwrap();
+                received.push((path.clone(), content_length, body.to_vec()));
+                received.iter().filter(|(p, _, _)| *p == path).count()
+            };
+            let response = match path.as_str() {
+                "/bucket/flaky" if attempts == 1 => Response::builder()
+                    .status(503)
+                    .body(Body::from("<Error><Code>SlowDown</Code></Error>")),
+                "/bucket/denied" => Response::builder()
+                    .status(403)
+                    .body(Body::from("<Error><Code>AccessDenied</Code></Error>")),
+                _ => Response::builder()
+                    .header("ETag", "\"etag-1\"")
+                    .body(Body::empty()),
+            };
+            response.unwrap()
+        }
+    });
+    (common::url_for(port), received)
+}
+
+fn policy() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 3,
+        base_delay_ms: 1,
+        ..Default::default()
+    }
+}
+
+fn write_file() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("presigned-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, b"0123456789abcdefghij").unwrap();
+    path
+}
+
+#[tokio::test]
+async fn test_uploads_chunk_and_retries_5xx() {
+    let (url, received) = mock_endpoint().await;
+    let path = write_file();
+
+    let etag = upload_via_presigned_url_with_policy(
+        &reqwest::Client::new(),
+        &policy(),
+        &format!("{}/bucket/flaky?{}", url, SIGNATURE),
+        path.to_str().unwrap(),
+        5,
+        10,
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!("etag-1", etag);
+    let received = received.lock().unwrap();
+    assert_eq!(2, received.len());
+    for (_, content_length, body) in received.iter() {
+        assert_eq!("10", content_length);
+        assert_eq!(b"56789abcde".to_vec(), *body);
+    }
+}
+
+#[tokio::test]
+async fn test_does_not_retry_4xx() {
+    let (url, received) = mock_endpoint().await;
+    let path = write_file();
+
+    let err = upload_via_presigned_url_with_policy(
+        &reqwest::Client::new(),
+        &policy(),
+        &format!("{}/bucket/denied?{}", url, SIGNATURE),
+        path.to_str().unwrap(),
+        0,
+        20,
+    )
+    .await
+    .unwrap_err()
+    .to_string();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(1, received.lock().unwrap().len());
+    assert!(err.contains("403"), "{}", err);
+    assert!(err.contains("AccessDenied"), "{}", err);
+    assert!(!err.contains(SIGNATURE), "{}", err);
+}
+
+#[tokio::test]
+async fn test_retries_connection_errors() {
+    // Nothing listens on a port that was bound and released.
+    let url = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    };
+    let path = write_file();
+
+    let err = upload_via_presigned_url_with_policy(
+        &reqwest::Client::new(),
+        &policy(),
+        &format!("{}/bucket/key?{}", url, SIGNATURE),
+        path.to_str().unwrap(),
+        0,
+        20,
+    )
+    .await
+    .unwrap_err()
+    .to_string();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(err.contains("/bucket/key failed"), "{}", err);
+    assert!(!err.contains(SIGNATURE), "{}", err);
+}
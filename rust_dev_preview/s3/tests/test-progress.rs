@@ -0,0 +1,230 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use futures::StreamExt;
+use hyper::{Body, Request, Response};
+use s3_service::progress::{
+    progress_line, progress_reader, ProgressEvent, ProgressTracker, RollingThroughput,
+    TransferTotals,
+};
+use s3_service::upload::upload_chunk_with_progress;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Starts a server answering PutObject, or failing it with 500 when `fail`
+/// is set, and recording the size of each body received.
+async fn mock_s3(fail: bool) -> (Client, Arc<Mutex<Vec<usize>>>) {
+    let sizes = Arc::new(Mutex::new(Vec::new()));
+    let received = sizes.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let sizes = received.clone();
+        async move {
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            sizes.lock().unwrap().push(body.len());
+            let response = if fail {
+                Response::builder()
+                    .status(500)
+                    .body(Body::from("<Error><Code>InternalError</Code></Error>"))
+            } else {
+                Response::builder()
+                    .header("ETag", "\"etag\"")
+                    .body(Body::empty())
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), sizes)
+}
+
+fn test_file(size: usize) -> String {
+    let path = std::env::temp_dir().join(format!("progress-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![b'x'; size]).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn test_rolling_throughput() {
+    let start = Instant::now();
+    let at = |secs: u64| start + Duration::from_secs(secs);
+    let mut throughput = RollingThroughput::new(Duration::from_secs(5));
+    throughput.record(at(0), 0);
+    assert_eq!(None, throughput.bytes_per_sec());
+
+    // 100 bytes per second for 10 seconds, then 10 per second.
+    for secs in 1..=10 {
+        throughput.record(at(secs), secs * 100);
+    }
+    assert_eq!(Some(100.0), throughput.bytes_per_sec());
+    for secs in 11..=15 {
+        throughput.record(at(secs), 1000 + (secs - 10) * 10);
+    }
+    // Only the last 5 seconds count.
+    assert_eq!(Some(10.0), throughput.bytes_per_sec());
+
+    // A stall shows as zero within one window.
+    for secs in 16..=20 {
+        throughput.record(at(secs), 1050);
+    }
+    assert_eq!(Some(0.0), throughput.bytes_per_sec());
+}
+
+#[test]
+fn test_progress_line() {
+    assert_eq!(
+        "key: 512 B of 2.0 KiB (25.0%), 1.5 MiB/s (10s avg)",
+        progress_line("key", 512, 2048, Some(1.5 * 1024.0 * 1024.0))
+    );
+    assert_eq!(
+        "key: 0 B of 0 B (100.0%), -- MiB/s (10s avg)",
+        progress_line("key", 0, 0, None)
+    );
+}
+
+#[tokio::test]
+async fn test_progress_reader_counts_bytes() {
+    let written = Arc::new(AtomicU64::new(0));
+    let chunks = vec![
+        Ok::<_, std::io::Error>(vec![0u8; 10]),
+        Ok(vec![0u8; 32]),
+        Err(std::io::Error::new(std::io::ErrorKind::Other, "read error")),
+    ];
+
+    let read: Vec<_> = progress_reader(futures::stream::iter(chunks), written.clone())
+        .collect()
+        .await;
+
+    assert_eq!(3, read.len());
+    assert_eq!(42, written.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_upload_chunk_with_progress() {
+    let (client, sizes) = mock_s3(false).await;
+    let file = test_file(100_000);
+
+    let etag = upload_chunk_with_progress(
+        &client,
+        "bucket",
+        "key",
+        &file,
+        1_000,
+        50_000,
+        Duration::from_millis(1),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!("etag", etag);
+    assert_eq!(vec![50_000], *sizes.lock().unwrap());
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[tokio::test]
+async fn test_failed_upload_stops_progress() {
+    let (client, _) = mock_s3(true).await;
+    let file = test_file(1_000);
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(10),
+        upload_chunk_with_progress(
+            &client,
+            "bucket",
+            "key",
+            &file,
+            0,
+            1_000,
+            Duration::from_millis(1),
+        ),
+    )
+    .await
+    .expect("the progress task kept the upload from returning");
+
+    assert!(result.is_err());
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn test_resumed_transfer_events() {
+    let start = Instant::now();
+    let mut tracker = ProgressTracker::starting_at(1000, 600, start);
+
+    let started = tracker.started();
+    assert_eq!(
+        ProgressEvent::Started {
+            totals: TransferTotals::resumed(1000, 600)
+        },
+        started
+    );
+    assert_eq!("Resuming at 600 B of 1000 B (60.0%)", started.to_text());
+
+    // The rate counts the 100 bytes of this run, not the 600 resumed.
+    let event = tracker.part_at(7, 100, start + Duration::from_secs(1));
+    match event {
+        ProgressEvent::Part {
+            part_number,
+            totals,
+            bytes_per_second,
+            eta_ms,
+            ..
+        } => {
+            assert_eq!(7, part_number);
+            assert_eq!(700, totals.completed_bytes());
+            assert_eq!(70.0, totals.percent());
+            assert_eq!(Some(100.0), bytes_per_second);
+            assert_eq!(Some(3000), eta_ms);
+        }
+        other => panic!("{:?}", other),
+    }
+    tracker.part_at(8, 300, start + Duration::from_secs(2));
+
+    let finished = tracker.finished_at(start + Duration::from_secs(2));
+    assert_eq!(
+        ProgressEvent::Finished {
+            totals: TransferTotals {
+                total_bytes: 1000,
+                resumed_bytes: 600,
+                transferred_bytes: 400,
+            },
+            elapsed_ms: 2000,
+            bytes_per_second: Some(200.0),
+        },
+        finished
+    );
+    assert_eq!(
+        "Finished in 2.0s, 0.0 MiB/s (overall avg): transferred this run: 400 B; \
+         total object: 1000 B of 1000 B (100.0%), 600 B resumed",
+        finished.to_text()
+    );
+}
+
+#[test]
+fn test_fresh_transfer_starts_at_zero() {
+    let tracker = ProgressTracker::new(1000, 0);
+    assert_eq!("Starting 1000 B", tracker.started().to_text());
+    assert_eq!(0.0, tracker.totals().percent());
+    assert_eq!(None, tracker.totals().eta(None));
+}
+
+#[test]
+fn test_transfer_totals_json() {
+    let totals = TransferTotals {
+        total_bytes: 1000,
+        resumed_bytes: 600,
+        transferred_bytes: 400,
+    };
+    assert_eq!(
+        serde_json::json!({
+            "total_bytes": 1000,
+            "resumed_bytes": 600,
+            "transferred_bytes": 400,
+        }),
+        serde_json::to_value(&totals).unwrap()
+    );
+}
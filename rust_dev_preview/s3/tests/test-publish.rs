@@ -0,0 +1,345 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::{Client, Error};
+use hyper::{Body, Method, Request, Response};
+use s3_service::publish::{publish_via_temp, publish_via_temp_with_hook, PublishConditions};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+async fn keys(client: &Client, bucket: &str) -> Vec<String> {
+    let resp = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .send()
+        .await
+        .unwrap();
+    resp.contents()
+        .unwrap_or_default()
+        .iter()
+        .map(|o| o.key().unwrap_or_default().to_string())
+        .collect()
+}
+
+async fn body(client: &Client, bucket: &str, key: &str) -> Vec<u8> {
+    let resp = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .unwrap();
+    resp.body.collect().await.unwrap().into_bytes().to_vec()
+}
+
+fn temp_file(contents: &[u8]) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("publish-{}", uuid::Uuid::new_v4()));
+    std::fs::File::create(&path)
+        .unwrap()
+        .write_all(contents)
+        .unwrap();
+    path
+}
+
+/// What the mock bucket received and holds.
+#[derive(Debug, Default)]
+struct Bucket {
+    /// The method and key of each request, with the If-Match and
+    /// If-None-Match headers of the copies.
+    requests: Vec<(Method, String, Option<String>, Option<String>)>,
+    objects: HashMap<String, Vec<u8>>,
+    parts: Vec<u8>,
+}
+
+fn header(req: &Request<Body>, name: &str) -> Option<String> {
+    req.headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap().to_string())
+}
+
+/// Starts a server holding `objects`, answering the upload of the temporary
+/// object and a copy that checks If-Match and If-None-Match as S3 does.
+fn mock_bucket(objects: &[(&str, &[u8])]) -> (Client, Arc<Mutex<Bucket>>) {
+    let bucket = Arc::new(Mutex::new(Bucket {
+        objects: objects
+            .iter()
+            .map(|(key, body)| (key.to_string(), body.to_vec()))
+            .collect(),
+        ..Default::default()
+    }));
+    let store = bucket.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let store = store.clone();
+        async move {
+            let method = req.method().clone();
+            let key = req.uri().path().trim_start_matches("/bucket/").to_string();
+            let query = req.uri().query().unwrap_or_default().to_string();
+            let copy_source = header(&req, "x-amz-copy-source");
+            let if_match = header(&req, "If-Match");
+            let if_none_match = header(&req, "If-None-Match");
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let mut store = store.lock().unwrap();
+            store.requests.push((
+                method.clone(),
+                key.clone(),
+                if_match.clone(),
+                if_none_match.clone(),
+            ));
+            let response = Response::builder().header("ETag", "\"etag\"");
+            match method {
+                Method::POST if query.starts_with("uploads") => response.body(Body::from(
+                    "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                )),
+                Method::POST => {
+                    let object = std::mem::take(&mut store.parts);
+                    store.objects.insert(key, object);
+                    response.body(Body::from(
+                        "<CompleteMultipartUploadResult><ETag>\"etag\"</ETag>\
+                         </CompleteMultipartUploadResult>",
+                    ))
+                }
+                Method::PUT if copy_source.is_none() => {
+                    store.parts.extend_from_slice(&body);
+                    response.body(Body::empty())
+                }
+                Method::PUT => {
+                    let exists = store.objects.contains_key(&key);
+                    let refused = (if_none_match.is_some() && exists)
+                        || (if_match.is_some() && if_match.as_deref() != Some("\"etag\""));
+                    if refused {
+                        return Response::builder()
+                            .status(412)
+                            .body(Body::from("<Error><Code>PreconditionFailed</Code></Error>"))
+                            .unwrap();
+                    }
+                    let source = copy_source.unwrap();
+                    let source = source.trim_start_matches("bucket/");
+                    let object = store.objects[source].clone();
+                    store.objects.insert(key, object);
+                    response.body(Body::from(
+                        "<CopyObjectResult><ETag>\"etag\"</ETag></CopyObjectResult>",
+                    ))
+                }
+                Method::HEAD => response
+                    .header("Content-Length", store.objects[&key].len())
+                    .body(Body::empty()),
+                Method::DELETE => {
+                    store.objects.remove(&key);
+                    response.status(204).body(Body::empty())
+                }
+                _ => panic!("Unexpected request: {}", method),
+            }
+            .unwrap()
+        }
+    });
+    (common::client_for(port), bucket)
+}
+
+#[test]
+fn test_conditions_are_sent_as_headers() {
+    let headers = PublishConditions {
+        if_match: Some("0123".into()),
+        if_none_match: Some("*".into()),
+    }
+    .headers()
+    .unwrap();
+
+    assert_eq!("\"0123\"", headers[http::header::IF_MATCH]);
+    assert_eq!("*", headers[http::header::IF_NONE_MATCH]);
+    assert!(PublishConditions::default().headers().unwrap().is_empty());
+}
+
+#[test]
+fn test_if_none_match_only_accepts_any_object() {
+    let conditions = PublishConditions {
+        if_none_match: Some("0123".into()),
+        ..Default::default()
+    };
+
+    assert!(conditions.headers().is_err());
+}
+
+#[tokio::test]
+async fn test_copy_refused_by_its_condition_keeps_the_object() {
+    let (client, bucket) = mock_bucket(&[("artifact", b"previous")]);
+    let file = temp_file(b"new version");
+
+    let err = publish_via_temp(
+        &client,
+        "bucket",
+        "artifact",
+        file.to_str().unwrap(),
+        None,
+        1,
+        None,
+        None,
+        &PublishConditions {
+            if_none_match: Some("*".into()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap_err();
+
+    assert!(err.to_string().contains("Precondition failed"), "{}", err);
+    let bucket = bucket.lock().unwrap();
+    assert_eq!(
+        vec![("artifact".to_string(), b"previous".to_vec())],
+        bucket.objects.clone().into_iter().collect::<Vec<_>>()
+    );
+    // The condition travels with the copy: nothing reads the final key first.
+    let on_final: Vec<_> = bucket
+        .requests
+        .iter()
+        .filter(|(_, key, _, _)| key == "artifact")
+        .collect();
+    assert_eq!(1, on_final.len());
+    assert_eq!(Method::PUT, on_final[0].0);
+    assert_eq!(Some("*"), on_final[0].3.as_deref());
+    std::fs::remove_file(file).unwrap();
+}
+
+#[tokio::test]
+async fn test_copy_with_a_matching_etag_replaces_the_object() {
+    let (client, bucket) = mock_bucket(&[("artifact", b"previous")]);
+    let file = temp_file(b"new version");
+
+    publish_via_temp(
+        &client,
+        "bucket",
+        "artifact",
+        file.to_str().unwrap(),
+        None,
+        1,
+        None,
+        None,
+        &PublishConditions {
+            if_match: Some("etag".into()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let bucket = bucket.lock().unwrap();
+    assert_eq!(b"new version".to_vec(), bucket.objects["artifact"]);
+    assert_eq!(1, bucket.objects.len());
+    std::fs::remove_file(file).unwrap();
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_failure_between_upload_and_copy_keeps_previous_object() {
+    let client = common::minio_client().await;
+    let bucket = common::create_test_bucket(&client).await;
+    client
+        .put_object()
+        .bucket(&bucket)
+        .key("artifact")
+        .body(ByteStream::from_static(b"previous"))
+        .send()
+        .await
+        .unwrap();
+    let file = temp_file(b"new version");
+
+    let result = publish_via_temp_with_hook(
+        &client,
+        &bucket,
+        "artifact",
+        file.to_str().unwrap(),
+        None,
+        1,
+        None,
+        None,
+        &PublishConditions::default(),
+        |_| Err(Error::Unhandled(Box::from("injected failure"))),
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert_eq!(vec!["artifact".to_string()], keys(&client, &bucket).await);
+    assert_eq!(
+        b"previous".to_vec(),
+        body(&client, &bucket, "artifact").await
+    );
+
+    std::fs::remove_file(file).unwrap();
+    common::delete_test_bucket(&client, &bucket).await;
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_publish_replaces_object_and_honors_if_match() {
+    let client = common::minio_client().await;
+    let bucket = common::create_test_bucket(&client).await;
+    let file = temp_file(b"first");
+
+    let first = publish_via_temp(
+        &client,
+        &bucket,
+        "artifact",
+        file.to_str().unwrap(),
+        None,
+        1,
+        None,
+        None,
+        &PublishConditions {
+            if_none_match: Some("*".into()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(b"first".to_vec(), body(&client, &bucket, "artifact").await);
+
+    // A stale ETag must not replace the object.
+    std::fs::write(&file, b"second").unwrap();
+    let stale = PublishConditions {
+        if_match: Some("0123456789abcdef".into()),
+        ..Default::default()
+    };
+    let result = publish_via_temp(
+        &client,
+        &bucket,
+        "artifact",
+        file.to_str().unwrap(),
+        None,
+        1,
+        None,
+        None,
+        &stale,
+    )
+    .await;
+    assert!(result.is_err());
+    assert_eq!(b"first".to_vec(), body(&client, &bucket, "artifact").await);
+
+    let current = PublishConditions {
+        if_match: Some(first.e_tag),
+        ..Default::default()
+    };
+    publish_via_temp(
+        &client,
+        &bucket,
+        "artifact",
+        file.to_str().unwrap(),
+        None,
+        1,
+        None,
+        None,
+        &current,
+    )
+    .await
+    .unwrap();
+    assert_eq!(b"second".to_vec(), body(&client, &bucket, "artifact").await);
+    assert_eq!(vec!["artifact".to_string()], keys(&client, &bucket).await);
+
+    std::fs::remove_file(file).unwrap();
+    common::delete_test_bucket(&client, &bucket).await;
+}
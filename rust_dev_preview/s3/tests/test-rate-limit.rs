@@ -0,0 +1,134 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use s3_service::rate_limit::{rate_limited_client, RequestLimiter};
+use s3_service::retry::{retry_sdk, RetryPolicy, SlowDownCoordinator};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// When each request reached the mock.
+type Arrivals = Arc<Mutex<Vec<Instant>>>;
+
+/// Starts a server answering the first `failures` requests with a 500 and
+/// the others with a 200, and returns a client paced by `limiter`.
+async fn counting_mock(limiter: &RequestLimiter, failures: usize) -> (Client, Arrivals) {
+    let arrivals = Arrivals::default();
+    let log = arrivals.clone();
+    let port = common::mock_s3(move |_req: Request<Body>| {
+        let log = log.clone();
+        async move {
+            let mut log = log.lock().unwrap();
+            log.push(Instant::now());
+            let status = if log.len() <= failures { 500 } else { 200 };
+            Response::builder()
+                .status(status)
+                .header("Content-Length", 0)
+                .body(Body::empty())
+                .unwrap()
+        }
+    });
+
+    let conf = common::config_for(port).build();
+    (rate_limited_client(conf, limiter), arrivals)
+}
+
+#[test]
+fn test_rate_must_be_positive() {
+    assert!(RequestLimiter::new(0.0).is_err());
+    assert!(RequestLimiter::new(-1.0).is_err());
+    assert!(RequestLimiter::new(f64::NAN).is_err());
+    assert_eq!(4.0, RequestLimiter::new(4.0).unwrap().requests_per_second());
+}
+
+#[tokio::test]
+async fn test_concurrent_requests_are_capped() {
+    let limiter = RequestLimiter::new(5.0).unwrap();
+    let (client, arrivals) = counting_mock(&limiter, 0).await;
+    let start = Instant::now();
+    let run = Duration::from_secs(3);
+
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                while start.elapsed() < run {
+                    client
+                        .head_object()
+                        .bucket("bucket")
+                        .key("key")
+                        .send()
+                        .await
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    tokio::time::sleep(run).await;
+    for task in tasks {
+        task.abort();
+    }
+
+    // One request at the start, then one every 200 ms.
+    let in_run = arrivals
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|at| at.duration_since(start) < run)
+        .count();
+    assert!((14..=16).contains(&in_run), "{} requests in 3 s", in_run);
+    let stats = limiter.stats();
+    assert!(
+        stats.requests_per_second <= 5.0 * 1.2,
+        "achieved {}",
+        stats.requests_per_second
+    );
+    assert!(stats.waited_ms > 0);
+}
+
+#[tokio::test]
+async fn test_retries_take_a_turn() {
+    let limiter = RequestLimiter::new(10.0).unwrap();
+    let (client, arrivals) = counting_mock(&limiter, 2).await;
+    let policy = RetryPolicy {
+        max_attempts: 4,
+        base_delay_ms: 0,
+        ..Default::default()
+    };
+    let start = Instant::now();
+
+    retry_sdk(&policy, &SlowDownCoordinator::new(), "HeadObject", || {
+        client.head_object().bucket("bucket").key("key").send()
+    })
+    .await
+    .unwrap();
+
+    let arrivals = arrivals.lock().unwrap();
+    assert_eq!(3, arrivals.len());
+    assert_eq!(3, limiter.stats().requests);
+    // The two retries each waited for their turn, without any backoff.
+    assert!(start.elapsed() >= Duration::from_millis(190));
+    assert!(arrivals[2].duration_since(arrivals[1]) >= Duration::from_millis(90));
+}
+
+#[tokio::test]
+async fn test_slow_down_pause_and_limit_do_not_add_up() {
+    let limiter = RequestLimiter::new(4.0).unwrap();
+    let coordinator = SlowDownCoordinator::new();
+    limiter.acquire().await;
+    let start = Instant::now();
+    coordinator.slow_down(Duration::from_millis(400));
+
+    coordinator.wait().await;
+    limiter.acquire().await;
+
+    // The 250 ms turn passed during the 400 ms pause.
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(400));
+    assert!(elapsed < Duration::from_millis(600), "{:?}", elapsed);
+}
@@ -0,0 +1,214 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::reencrypt::{is_same_key, reencrypt_prefix};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+const OLD_KEY: &str = "arn:aws:kms:us-east-1:111122223333:key/old-key";
+const NEW_KEY: &str = "arn:aws:kms:us-east-1:111122223333:key/new-key";
+
+#[derive(Clone)]
+struct StoredObject {
+    /// `None` for SSE-S3.
+    kms_key: Option<String>,
+    storage_class: Option<&'static str>,
+    e_tag: &'static str,
+    /// Overwritten between HeadObject and CopyObject.
+    changed: bool,
+}
+
+/// A copy request, as received.
+#[derive(Debug)]
+struct Copy {
+    key: String,
+    source: String,
+    if_match: Option<String>,
+    metadata_directive: Option<String>,
+    storage_class: Option<String>,
+    kms_key: Option<String>,
+}
+
+fn header(req: &Request<Body>, name: &str) -> Option<String> {
+    req.headers()
+        .get(name)
+        .map(|v| v.to_str().unwrap().to_string())
+}
+
+/// Starts a server answering ListObjectsV2, HeadObject, and CopyObject, and
+/// recording the copies. A copy whose `x-amz-copy-source-if-match` does not
+/// match the stored ETag, or of an object marked as changed, fails with 412.
+async fn mock_s3(objects: BTreeMap<String, StoredObject>) -> (Client, Arc<Mutex<Vec<Copy>>>) {
+    let objects = Arc::new(Mutex::new(objects));
+    let copies = Arc::new(Mutex::new(Vec::new()));
+    let received = copies.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let objects = objects.clone();
+        let copies = received.clone();
+        async move {
+            let key = req
+                .uri()
+                .path()
+                .trim_start_matches("/bucket")
+                .trim_start_matches('/')
+                .to_string();
+            let mut objects = objects.lock().unwrap();
+            let response = match *req.method() {
+                Method::GET => {
+                    let contents: String = objects
+                        .keys()
+                        .map(|key| format!("<Contents><Key>{}</Key><Size>4</Size></Contents>", key))
+                        .collect();
+                    Response::builder().body(Body::from(format!(
+                        "<ListBucketResult><Name>bucket</Name>\
+                         <IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                        contents
+                    )))
+                }
+                Method::HEAD => {
+                    let object = &objects[&key];
+                    let mut response = Response::builder()
+                        .header("Content-Length", 4)
+                        .header("ETag", object.e_tag);
+                    response = match &object.kms_key {
+                        Some(kms_key) => response
+                            .header("x-amz-server-side-encryption", "aws:kms")
+                            .header(
+                                "x-amz-server-side-encryption-aws-kms-key-id",
+                                kms_key.as_str(),
+                            ),
+                        None => response.header("x-amz-server-side-encryption", "AES256"),
+                    };
+                    if let Some(class) = object.storage_class {
+                        response = response.header("x-amz-storage-class", class);
+                    }
+                    response.body(Body::empty())
+                }
+                Method::PUT => {
+                    let copy = Copy {
+                        key: key.clone(),
+                        source: header(&req, "x-amz-copy-source").unwrap(),
+                        if_match: header(&req, "x-amz-copy-source-if-match"),
+                        metadata_directive: header(&req, "x-amz-metadata-directive"),
+                        storage_class: header(&req, "x-amz-storage-class"),
+                        kms_key: header(&req, "x-amz-server-side-encryption-aws-kms-key-id"),
+                    };
+                    let object = objects.get_mut(&key).unwrap();
+                    if copy.if_match.as_deref() == Some(object.e_tag) && !object.changed {
+                        object.kms_key = copy.kms_key.clone();
+                        Response::builder().body(Body::from(
+                            "<CopyObjectResult><ETag>\"copied\"</ETag></CopyObjectResult>",
+                        ))
+                    } else {
+                        Response::builder()
+                            .status(412)
+                            .body(Body::from("<Error><Code>PreconditionFailed</Code></Error>"))
+                    };
+                    copies.lock().unwrap().push(copy)
+                }
+                _ => Response::builder().status(405).body(Body::empty()),
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), copies)
+}
+
+fn stored(kms_key: Option<&str>, storage_class: Option<&'static str>) -> StoredObject {
+    StoredObject {
+        kms_key: kms_key.map(|k| k.to_string()),
+        storage_class,
+        e_tag: "\"etag\"",
+        changed: false,
+    }
+}
+
+#[test]
+fn test_is_same_key() {
+    assert!(is_same_key(OLD_KEY, OLD_KEY));
+    assert!(is_same_key(OLD_KEY, "old-key"));
+    assert!(!is_same_key(OLD_KEY, "key"));
+    assert!(!is_same_key(OLD_KEY, NEW_KEY));
+    assert!(!is_same_key(OLD_KEY, "alias/old"));
+}
+
+#[tokio::test]
+async fn test_reencrypt_prefix() {
+    let objects = vec![
+        ("data/old", stored(Some(OLD_KEY), None)),
+        ("data/old-ia", stored(Some(OLD_KEY), Some("STANDARD_IA"))),
+        ("data/new", stored(Some(NEW_KEY), None)),
+        ("data/sse-s3", stored(None, None)),
+    ]
+    .into_iter()
+    .map(|(key, object)| (key.to_string(), object))
+    .collect();
+    let (client, copies) = mock_s3(objects).await;
+
+    let report = reencrypt_prefix(&client, "bucket", "data/", "old-key", NEW_KEY, 2)
+        .await
+        .unwrap();
+
+    assert_eq!(vec!["data/old", "data/old-ia"], report.reencrypted);
+    assert_eq!(vec!["data/new", "data/sse-s3"], report.skipped);
+    assert!(report.errors.is_empty());
+
+    let mut copies = copies.lock().unwrap();
+    copies.sort_by(|a, b| a.key.cmp(&b.key));
+    assert_eq!(2, copies.len());
+    for copy in copies.iter() {
+        assert_eq!(format!("bucket/{}", copy.key), copy.source);
+        assert_eq!(Some("\"etag\""), copy.if_match.as_deref());
+        assert_eq!(Some("COPY"), copy.metadata_directive.as_deref());
+        assert_eq!(Some(NEW_KEY), copy.kms_key.as_deref());
+    }
+    assert_eq!(None, copies[0].storage_class);
+    assert_eq!(Some("STANDARD_IA"), copies[1].storage_class.as_deref());
+}
+
+#[tokio::test]
+async fn test_overwritten_object_is_an_error() {
+    let mut objects = BTreeMap::new();
+    objects.insert(
+        "data/a".to_string(),
+        StoredObject {
+            changed: true,
+            ..stored(Some(OLD_KEY), None)
+        },
+    );
+    let (client, _) = mock_s3(objects).await;
+
+    let report = reencrypt_prefix(&client, "bucket", "data/", OLD_KEY, NEW_KEY, 1)
+        .await
+        .unwrap();
+
+    assert!(report.reencrypted.is_empty());
+    assert_eq!(1, report.errors.len());
+    assert_eq!("data/a", report.errors[0].0);
+}
+
+#[tokio::test]
+async fn test_second_run_skips_reencrypted_objects() {
+    let mut objects = BTreeMap::new();
+    objects.insert("data/a".to_string(), stored(Some(OLD_KEY), None));
+    let (client, copies) = mock_s3(objects).await;
+
+    let first = reencrypt_prefix(&client, "bucket", "data/", OLD_KEY, NEW_KEY, 1)
+        .await
+        .unwrap();
+    let second = reencrypt_prefix(&client, "bucket", "data/", OLD_KEY, NEW_KEY, 1)
+        .await
+        .unwrap();
+
+    assert_eq!(vec!["data/a"], first.reencrypted);
+    assert!(second.reencrypted.is_empty());
+    assert_eq!(vec!["data/a"], second.skipped);
+    assert_eq!(1, copies.lock().unwrap().len());
+}
@@ -0,0 +1,197 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::{Client, Error};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use s3_service::region_fallback::{is_region_unavailable, upload_with_fallback, UploadLocation};
+use std::sync::{Arc, Mutex};
+
+/// How a mock Region answers.
+#[derive(Clone, Copy)]
+enum Health {
+    Up,
+    /// 503 ServiceUnavailable from the first part on.
+    Unavailable,
+    /// 403 AccessDenied to every request.
+    Deny,
+}
+
+type Requests = Arc<Mutex<Vec<(Method, String)>>>;
+
+/// Starts a mock Region and returns its client and the method and query of
+/// each request it received.
+async fn region(health: Health) -> (Client, Requests) {
+    let requests = Requests::default();
+    let recorder = requests.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            recorder
+                .lock()
+                .unwrap()
+                .push((method.clone(), query.clone()));
+            hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let creating = method == Method::POST && query.contains("uploads");
+            let response = match health {
+                Health::Deny => Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(Body::from("<Error><Code>AccessDenied</Code></Error>")),
+                Health::Unavailable if !creating && method != Method::DELETE => Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::from(
+                        "<Error><Code>ServiceUnavailable</Code>\
+                             <Message>Please reduce your request rate.</Message></Error>",
+                    )),
+                _ if creating => Response::builder().body(Body::from(
+                    "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                )),
+                _ if method == Method::POST => Response::builder().body(Body::from(
+                    "<CompleteMultipartUploadResult><ETag>\"complete-etag\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                )),
+                _ if method == Method::DELETE => {
+                    Response::builder().status(204).body(Body::empty())
+                }
+                _ => Response::builder()
+                    .header("ETag", "\"part-etag\"")
+                    .body(Body::empty()),
+            };
+            response.unwrap()
+        }
+    });
+    (common::client_for(port), requests)
+}
+
+fn test_file(size: usize) -> String {
+    let path = std::env::temp_dir().join(format!("fallback-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![b'x'; size]).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn methods(requests: &Requests) -> Vec<Method> {
+    requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(method, _)| method.clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_primary_up() {
+    let (primary, _) = region(Health::Up).await;
+    let (fallback, fallback_requests) = region(Health::Up).await;
+    let file = test_file(1000);
+
+    let location = upload_with_fallback(&primary, &fallback, "bucket", "key", &file, 2)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(
+        UploadLocation::Primary("complete-etag".to_string()),
+        location
+    );
+    assert!(fallback_requests.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_service_unavailable_aborts_and_falls_back() {
+    let (primary, primary_requests) = region(Health::Unavailable).await;
+    let (fallback, fallback_requests) = region(Health::Up).await;
+    let file = test_file(1000);
+
+    let location = upload_with_fallback(&primary, &fallback, "bucket", "key", &file, 2)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(
+        UploadLocation::Fallback("complete-etag".to_string()),
+        location
+    );
+    assert_eq!("complete-etag", location.e_tag());
+    // The upload started in the primary Region was aborted.
+    assert_eq!(Some(&Method::DELETE), methods(&primary_requests).last());
+    assert_eq!(
+        vec![Method::POST, Method::PUT, Method::PUT, Method::POST],
+        methods(&fallback_requests)
+    );
+}
+
+#[tokio::test]
+async fn test_unreachable_primary_falls_back() {
+    // Nothing listens on this port once the listener is dropped.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let primary = common::client_for(port);
+    let (fallback, _) = region(Health::Up).await;
+    let file = test_file(1000);
+
+    let location = upload_with_fallback(&primary, &fallback, "bucket", "key", &file, 1)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(
+        UploadLocation::Fallback("complete-etag".to_string()),
+        location
+    );
+}
+
+#[tokio::test]
+async fn test_access_denied_does_not_fall_back() {
+    let (primary, _) = region(Health::Deny).await;
+    let (fallback, fallback_requests) = region(Health::Up).await;
+    let file = test_file(1000);
+
+    let err = upload_with_fallback(&primary, &fallback, "bucket", "key", &file, 2)
+        .await
+        .unwrap_err();
+    std::fs::remove_file(&file).unwrap();
+
+    assert!(!is_region_unavailable(&err));
+    assert!(fallback_requests.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_only_the_error_code_marks_the_region_unavailable() {
+    let error = |code: &str, message: &str| {
+        Error::Unhandled(Box::new(
+            aws_smithy_types::Error::builder()
+                .code(code)
+                .message(message)
+                .build(),
+        ))
+    };
+
+    assert!(is_region_unavailable(&error(
+        "ServiceUnavailable",
+        "Please reduce your request rate."
+    )));
+    assert!(!is_region_unavailable(&error(
+        "AccessDenied",
+        "ServiceUnavailable is not the reason"
+    )));
+    assert!(!is_region_unavailable(&error(
+        "SlowDown",
+        "Please reduce your request rate."
+    )));
+}
+
+#[test]
+fn test_location_json() {
+    let location = UploadLocation::Fallback("etag".to_string());
+    assert_eq!(
+        serde_json::json!({"region": "fallback", "e_tag": "etag"}),
+        serde_json::to_value(&location).unwrap()
+    );
+}
@@ -0,0 +1,218 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::download::{download_prefix, download_prefix_rate_limited};
+use s3_service::durable::FsyncOptions;
+use s3_service::sync::{sync_directory, SyncOptions, MAX_REPLANS};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The mock bucket, and the changes it makes between the planning and the
+/// transfers.
+#[derive(Debug, Default)]
+struct Bucket {
+    /// Key, size, and ETag of the objects listed.
+    listed: Vec<(&'static str, usize, &'static str)>,
+    /// Appended to on each ListObjectsV2, after the directory was walked.
+    append_on_list: Option<PathBuf>,
+    /// Appended to on each PutObject, as a file written during its upload.
+    append_on_put: Option<PathBuf>,
+    /// The body of GetObject; without it, each GetObject returns a longer
+    /// body with a new ETag.
+    body: Option<&'static str>,
+    /// The size of the body of each PutObject.
+    puts: Vec<usize>,
+    gets: usize,
+}
+
+type Shared = Arc<Mutex<Bucket>>;
+
+fn append(path: &Path) {
+    let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(b" more").unwrap();
+}
+
+fn handle(bucket: &Shared, method: &Method, query: &str, body_len: usize) -> Response<Body> {
+    let mut bucket = bucket.lock().unwrap();
+    let (body, e_tag) = if query.contains("list-type=2") {
+        if let Some(path) = &bucket.append_on_list {
+            append(path);
+        }
+        let contents: String = bucket
+            .listed
+            .iter()
+            .map(|(key, size, e_tag)| {
+                format!(
+                    "<Contents><Key>{}</Key><LastModified>2022-03-01T12:00:00.000Z\
+                     </LastModified><ETag>\"{}\"</ETag><Size>{}</Size></Contents>",
+                    key, e_tag, size
+                )
+            })
+            .collect();
+        (
+            format!(
+                "<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                contents
+            ),
+            String::new(),
+        )
+    } else if *method == Method::PUT {
+        bucket.puts.push(body_len);
+        if let Some(path) = &bucket.append_on_put {
+            append(path);
+        }
+        (String::new(), "put".to_string())
+    } else {
+        bucket.gets += 1;
+        match bucket.body {
+            Some(body) => (body.to_string(), "v2".to_string()),
+            None => ("x".repeat(bucket.gets), format!("v{}", bucket.gets + 1)),
+        }
+    };
+    Response::builder()
+        .header("ETag", format!("\"{}\"", e_tag))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn mock_bucket(bucket: Bucket) -> (Client, Shared) {
+    let shared = Arc::new(Mutex::new(bucket));
+    let state = shared.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let state = state.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            handle(&state, &method, &query, body.len())
+        }
+    });
+
+    (common::client_for(port), shared)
+}
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("replan-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn upload_of_a_file_changed_after_planning_sends_it_as_it_is_now() {
+    let dir = temp_dir();
+    let path = dir.join("log.txt");
+    std::fs::write(&path, b"first").unwrap();
+    let (client, bucket) = mock_bucket(Bucket {
+        append_on_list: Some(path.clone()),
+        ..Default::default()
+    });
+
+    let summary = sync_directory(&client, "bucket", &dir, "", &SyncOptions::default(), false)
+        .await
+        .unwrap();
+
+    assert_eq!(summary.uploaded, ["log.txt"]);
+    assert!(summary.unstable.is_empty());
+    assert_eq!(bucket.lock().unwrap().puts, ["first more".len()]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn upload_of_a_file_that_keeps_changing_is_unstable() {
+    let dir = temp_dir();
+    let path = dir.join("log.txt");
+    std::fs::write(&path, b"first").unwrap();
+    std::fs::write(dir.join("stable.txt"), b"stable").unwrap();
+    let (client, bucket) = mock_bucket(Bucket {
+        append_on_put: Some(path.clone()),
+        ..Default::default()
+    });
+    let options = SyncOptions {
+        max_replans: 2,
+        concurrency: 1,
+        ..Default::default()
+    };
+
+    let summary = sync_directory(&client, "bucket", &dir, "", &options, false)
+        .await
+        .unwrap();
+
+    assert_eq!(summary.unstable, ["log.txt"]);
+    assert_eq!(summary.uploaded, ["stable.txt"]);
+    assert!(summary.failed.is_empty());
+    // The file and the stable one, then the file after each of 2 replans.
+    assert_eq!(bucket.lock().unwrap().puts.len(), 4);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn download_of_an_object_replaced_after_listing_is_planned_again() {
+    let dir = temp_dir();
+    let (client, bucket) = mock_bucket(Bucket {
+        listed: vec![("a.txt", 5, "v1")],
+        body: Some("hello world"),
+        ..Default::default()
+    });
+
+    let summary = download_prefix(&client, "bucket", "", &dir, false)
+        .await
+        .unwrap();
+
+    assert_eq!(summary.files, 1);
+    assert!(summary.unstable.is_empty());
+    assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"hello world");
+    // The response to the first GetObject had a new size and ETag.
+    assert_eq!(bucket.lock().unwrap().gets, 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn download_of_an_object_that_keeps_changing_is_unstable() {
+    let dir = temp_dir();
+    let (client, bucket) = mock_bucket(Bucket {
+        listed: vec![("a.txt", 5, "v1")],
+        ..Default::default()
+    });
+
+    let summary = download_prefix(&client, "bucket", "", &dir, false)
+        .await
+        .unwrap();
+
+    assert_eq!(summary.files, 0);
+    assert_eq!(summary.unstable, ["a.txt"]);
+    assert!(!dir.join("a.txt").exists());
+    assert_eq!(bucket.lock().unwrap().gets, MAX_REPLANS as usize + 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn rate_limited_download_reports_unstable_objects() {
+    let dir = temp_dir();
+    let (client, _) = mock_bucket(Bucket {
+        listed: vec![("a.txt", 5, "v1")],
+        ..Default::default()
+    });
+
+    let summary = download_prefix_rate_limited(
+        &client,
+        "bucket",
+        "",
+        &dir,
+        1 << 20,
+        2,
+        &FsyncOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(summary.files, 0);
+    assert_eq!(summary.unstable, ["a.txt"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
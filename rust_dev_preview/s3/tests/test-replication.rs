@@ -0,0 +1,116 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use s3_service::replication::{
+    render_dashboard, replication_destinations, DestinationRow, ReplicationMetrics,
+};
+
+const RED: &str = "\x1b[1;31m";
+
+/// Starts a server answering GetBucketReplication with `rules`.
+async fn mock_s3(rules: &'static str) -> Client {
+    let port = common::mock_s3(move |_: Request<Body>| async move {
+        Response::new(Body::from(format!(
+            "<ReplicationConfiguration><Role>arn:aws:iam::123456789012:role/replication</Role>\
+             {}</ReplicationConfiguration>",
+            rules
+        )))
+    });
+
+    common::client_for(port)
+}
+
+fn row(destination: &str, latency: f64, bytes: f64) -> DestinationRow {
+    DestinationRow {
+        destination: destination.to_string(),
+        metrics: Ok(ReplicationMetrics {
+            latency_seconds: Some(latency),
+            bytes_pending: Some(bytes),
+            operations_pending: Some(3.0),
+        }),
+    }
+}
+
+#[tokio::test]
+async fn test_destinations_of_all_rules() {
+    let client = mock_s3(
+        "<Rule><ID>logs</ID><Status>Enabled</Status>\
+         <Destination><Bucket>arn:aws:s3:::replica-east</Bucket></Destination></Rule>\
+         <Rule><ID>images</ID><Status>Enabled</Status>\
+         <Destination><Bucket>arn:aws-cn:s3:::replica-china</Bucket></Destination></Rule>\
+         <Rule><ID>backups</ID><Status>Enabled</Status>\
+         <Destination><Bucket>arn:aws:s3:::replica-east</Bucket></Destination></Rule>",
+    )
+    .await;
+
+    let destinations = replication_destinations(&client, "source").await.unwrap();
+
+    assert_eq!(vec!["replica-east", "replica-china"], destinations);
+}
+
+#[test]
+fn test_healthy_replication_has_no_warning() {
+    let frame = render_dashboard(
+        "source",
+        "2021-11-02 10:00:00",
+        &[row("replica", 42.0, 5e6)],
+    );
+
+    assert!(frame.contains("Replication from source at 2021-11-02 10:00:00"));
+    assert!(frame.contains("42.0s"));
+    assert!(frame.contains("4.8 MiB"));
+    assert!(!frame.contains(RED), "{:?}", frame);
+    // Every line clears what an earlier, longer frame left on it.
+    assert!(frame.lines().all(|line| line.ends_with("\x1b[K")));
+}
+
+#[test]
+fn test_lag_and_backlog_are_highlighted() {
+    let frame = render_dashboard(
+        "source",
+        "2021-11-02 10:00:00",
+        &[
+            row("lagging", 301.0, 0.0),
+            row("backlogged", 10.0, 2.5e9),
+            row("healthy", 300.0, 1e9),
+        ],
+    );
+
+    let line = |name: &str| frame.lines().find(|line| line.starts_with(name)).unwrap();
+    assert!(line("lagging").contains(&format!("{}   5m 1.0s", RED)));
+    assert!(!line("lagging").contains("B\x1b[0m"));
+    assert!(line("backlogged").contains(&format!("{}       2.3 GiB", RED)));
+    assert!(!line("healthy").contains(RED));
+}
+
+#[test]
+fn test_errors_and_missing_data() {
+    let frame = render_dashboard(
+        "source",
+        "2021-11-02 10:00:00",
+        &[
+            DestinationRow {
+                destination: "denied".to_string(),
+                metrics: Err("AccessDenied".to_string()),
+            },
+            DestinationRow {
+                destination: "quiet".to_string(),
+                metrics: Ok(ReplicationMetrics::default()),
+            },
+        ],
+    );
+
+    assert!(frame.contains("Error getting metrics: AccessDenied"));
+    let quiet = frame
+        .lines()
+        .find(|line| line.starts_with("quiet"))
+        .unwrap();
+    assert_eq!(3, quiet.matches('-').count());
+    assert!(render_dashboard("source", "now", &[]).contains("No replication rules"));
+}
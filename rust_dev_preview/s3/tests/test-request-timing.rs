@@ -0,0 +1,170 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::types::ByteStream;
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use s3_service::request_timing::{timed_client, Percentiles, RequestTimings};
+use std::time::Duration;
+
+/// Starts a server that answers every request after `delay`, and returns a
+/// client timing its part requests into `timings`.
+async fn delaying_mock(delay: Duration, timings: &RequestTimings) -> Client {
+    let port = common::mock_s3(move |req: Request<Body>| async move {
+        hyper::body::to_bytes(req.into_body()).await.unwrap();
+        tokio::time::sleep(delay).await;
+        Response::builder()
+            .header("ETag", "\"part-etag\"")
+            .body(Body::empty())
+            .unwrap()
+    });
+
+    let conf = common::config_for(port).build();
+    timed_client(conf, timings)
+}
+
+async fn upload_part(client: &Client, part_number: i32) {
+    client
+        .upload_part()
+        .bucket("bucket")
+        .key("key")
+        .upload_id("upload-1")
+        .part_number(part_number)
+        .body(ByteStream::from(vec![b'x'; 1000]))
+        .send()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_first_byte_reflects_the_server_delay() {
+    let timings = RequestTimings::new();
+    let client = delaying_mock(Duration::from_millis(200), &timings).await;
+
+    upload_part(&client, 1).await;
+    upload_part(&client, 2).await;
+
+    let attempts = timings.attempts();
+    assert_eq!(2, attempts.len());
+    for attempt in &attempts {
+        let first_byte = attempt.first_byte_ms.unwrap();
+        assert!(first_byte >= 200.0, "{:?}", attempt);
+        assert!(first_byte < 1000.0, "{:?}", attempt);
+        assert!(attempt.total_ms >= first_byte);
+        assert_eq!(Some(200), attempt.status);
+    }
+    assert_eq!(
+        vec![1, 2],
+        attempts.iter().map(|a| a.part_number).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_new_and_reused_connections() {
+    let timings = RequestTimings::new();
+    let client = delaying_mock(Duration::ZERO, &timings).await;
+
+    for part_number in 1..=3 {
+        upload_part(&client, part_number).await;
+    }
+
+    let attempts = timings.attempts();
+    assert_eq!(Some(true), attempts[0].new_connection);
+    assert!(attempts[0].connect_ms.is_some());
+    for attempt in &attempts[1..] {
+        assert_eq!(Some(false), attempt.new_connection);
+        assert_eq!(None, attempt.connect_ms);
+    }
+    let summary = timings.summary();
+    assert_eq!(3, summary.attempts);
+    assert_eq!(1, summary.new_connections);
+    assert_eq!(2, summary.reused_connections);
+}
+
+#[tokio::test]
+async fn test_only_part_requests_are_timed() {
+    let timings = RequestTimings::new();
+    let client = delaying_mock(Duration::ZERO, &timings).await;
+
+    client
+        .put_object()
+        .bucket("bucket")
+        .key("key")
+        .body(ByteStream::from(b"hello".to_vec()))
+        .send()
+        .await
+        .unwrap();
+
+    assert!(timings.attempts().is_empty());
+    assert!(timings.summary().first_byte_ms.is_none());
+}
+
+#[tokio::test]
+async fn test_a_failed_response_body_fails_the_request() {
+    let port = common::mock_s3(|req: Request<Body>| async move {
+        hyper::body::to_bytes(req.into_body()).await.unwrap();
+        // The connection is dropped part way through the body.
+        let body = futures::stream::iter(vec![
+            Ok(bytes::Bytes::from_static(b"<Error>")),
+            Err(std::io::Error::new(std::io::ErrorKind::Other, "reset")),
+        ]);
+        Response::builder()
+            .header("ETag", "\"part-etag\"")
+            .body(Body::wrap_stream(body))
+            .unwrap()
+    });
+    let conf = common::config_for(port).build();
+    let timings = RequestTimings::new();
+    let client = timed_client(conf, &timings);
+
+    let sent = client
+        .upload_part()
+        .bucket("bucket")
+        .key("key")
+        .upload_id("upload-1")
+        .part_number(1)
+        .body(ByteStream::from(vec![b'x'; 1000]))
+        .send()
+        .await;
+
+    assert!(sent.is_err());
+    let attempts = timings.attempts();
+    assert_eq!(1, attempts.len());
+    assert!(attempts[0]
+        .error
+        .as_deref()
+        .unwrap()
+        .starts_with("The body could not be read"));
+}
+
+#[test]
+fn test_percentiles_by_nearest_rank() {
+    let values: Vec<f64> = (1..=100).rev().map(f64::from).collect();
+
+    let percentiles = Percentiles::of(&values).unwrap();
+
+    assert_eq!(50.0, percentiles.p50);
+    assert_eq!(95.0, percentiles.p95);
+    assert_eq!(99.0, percentiles.p99);
+    let single = Percentiles::of(&[7.0]).unwrap();
+    assert_eq!((7.0, 7.0, 7.0), (single.p50, single.p95, single.p99));
+    assert!(Percentiles::of(&[]).is_none());
+}
+
+#[tokio::test]
+async fn test_json_has_the_raw_values() {
+    let timings = RequestTimings::new();
+    let client = delaying_mock(Duration::ZERO, &timings).await;
+    upload_part(&client, 1).await;
+
+    let json: serde_json::Value = serde_json::from_str(&timings.to_json()).unwrap();
+
+    assert_eq!(1, json["summary"]["attempts"]);
+    assert_eq!(1, json["attempts"][0]["part_number"]);
+    assert!(json["attempts"][0]["first_byte_ms"].is_number());
+    assert!(json["summary"]["first_byte_ms"]["p99"].is_number());
+}
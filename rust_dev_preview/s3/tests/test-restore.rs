@@ -0,0 +1,181 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::{Client, RetryConfig};
+use hyper::{Body, Method, Request, Response};
+use s3_service::restore::{
+    parse_restore_header, restore_from_deep_archive, wait_for_restore, GlacierRestoreTier,
+    RestoreStatus,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// What the mock server saw and answers.
+#[derive(Default)]
+struct Server {
+    /// The HEAD requests answered so far.
+    heads: AtomicUsize,
+    /// The restore is ongoing for this many HEAD requests.
+    ongoing_heads: usize,
+    /// The bodies of the restore requests.
+    restores: Mutex<Vec<String>>,
+    /// Answer restore requests with RestoreAlreadyInProgress.
+    already_in_progress: bool,
+}
+
+/// Starts a server that answers like S3 for a Deep Archive object.
+async fn mock_server(server: Arc<Server>) -> Client {
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let server = server.clone();
+        async move {
+            let method = req.method().clone();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let response = if method == Method::HEAD {
+                let n = server.heads.fetch_add(1, Ordering::SeqCst);
+                let restore = if n < server.ongoing_heads {
+                    "ongoing-request=\"true\"".to_string()
+                } else {
+                    "ongoing-request=\"false\", \
+                     expiry-date=\"Fri, 21 Dec 2012 00:00:00 GMT\""
+                        .to_string()
+                };
+                Response::builder()
+                    .header("x-amz-storage-class", "DEEP_ARCHIVE")
+                    .header("x-amz-restore", restore)
+                    .body(Body::empty())
+            } else {
+                server
+                    .restores
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(body.to_vec()).unwrap());
+                if server.already_in_progress {
+                    Response::builder().status(409).body(Body::from(
+                        "<Error><Code>RestoreAlreadyInProgress</Code>\
+                         <Message>Object restore is already in progress</Message></Error>",
+                    ))
+                } else {
+                    Response::builder().status(202).body(Body::empty())
+                }
+            };
+            response.unwrap()
+        }
+    });
+
+    let conf = common::config_for(port)
+        .retry_config(RetryConfig::new())
+        .build();
+    Client::from_conf(conf)
+}
+
+#[test]
+fn test_parse_restore_header() {
+    assert_eq!(
+        Some(RestoreStatus {
+            ongoing: true,
+            expiry_date: None
+        }),
+        parse_restore_header("ongoing-request=\"true\"")
+    );
+    assert_eq!(
+        Some(RestoreStatus {
+            ongoing: false,
+            expiry_date: Some("Fri, 21 Dec 2012 00:00:00 GMT".to_string())
+        }),
+        parse_restore_header(
+            "ongoing-request=\"false\", expiry-date=\"Fri, 21 Dec 2012 00:00:00 GMT\""
+        )
+    );
+    assert_eq!(None, parse_restore_header("ongoing-request=maybe"));
+}
+
+#[test]
+fn test_tiers() {
+    assert_eq!(Ok(GlacierRestoreTier::Bulk), "Bulk".parse());
+    assert_eq!(Ok(GlacierRestoreTier::Standard), "standard".parse());
+    assert!("fast".parse::<GlacierRestoreTier>().is_err());
+    assert!(
+        GlacierRestoreTier::Bulk.deep_archive_duration()
+            > GlacierRestoreTier::Standard.deep_archive_duration()
+    );
+}
+
+#[tokio::test]
+async fn test_expedited_is_rejected() {
+    let server = Arc::new(Server::default());
+    let client = mock_server(server.clone()).await;
+    let err = restore_from_deep_archive(&client, "bucket", "key", 1, GlacierRestoreTier::Expedited)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Expedited"));
+    assert!(server.restores.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_restore_request() {
+    let server = Arc::new(Server::default());
+    let client = mock_server(server.clone()).await;
+    restore_from_deep_archive(&client, "bucket", "key", 7, GlacierRestoreTier::Bulk)
+        .await
+        .unwrap();
+    let restores = server.restores.lock().unwrap();
+    assert_eq!(1, restores.len());
+    assert!(restores[0].contains("<Days>7</Days>"));
+    assert!(restores[0].contains("<Tier>Bulk</Tier>"));
+}
+
+#[tokio::test]
+async fn test_restore_already_in_progress() {
+    let server = Arc::new(Server {
+        already_in_progress: true,
+        ..Default::default()
+    });
+    let client = mock_server(server).await;
+    restore_from_deep_archive(&client, "bucket", "key", 1, GlacierRestoreTier::Standard)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_wait_for_restore() {
+    let server = Arc::new(Server {
+        ongoing_heads: 2,
+        ..Default::default()
+    });
+    let client = mock_server(server.clone()).await;
+    wait_for_restore(
+        &client,
+        "bucket",
+        "key",
+        Duration::from_millis(10),
+        Duration::from_secs(10),
+    )
+    .await
+    .unwrap();
+    assert_eq!(3, server.heads.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_wait_for_restore_times_out() {
+    let server = Arc::new(Server {
+        ongoing_heads: usize::MAX,
+        ..Default::default()
+    });
+    let client = mock_server(server.clone()).await;
+    let err = wait_for_restore(
+        &client,
+        "bucket",
+        "key",
+        Duration::from_millis(20),
+        Duration::from_millis(100),
+    )
+    .await
+    .unwrap_err();
+    assert!(err.to_string().contains("still in progress"));
+    assert!(server.heads.load(Ordering::SeqCst) <= 5);
+}
@@ -0,0 +1,355 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use md5::{Digest, Md5};
+use s3_service::progress::{ProgressEvent, TransferTotals};
+use s3_service::resume::{
+    plan_resume, resume_upload, resume_upload_with_events, ResumePlan, ResumeVerify,
+};
+use s3_service::upload_watch::ListedPart;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+const PART_SIZE: usize = 1000;
+/// Three parts, the last one taking the remainder: 1000, 1000, and 1500.
+const FILE_SIZE: usize = 3500;
+
+fn content() -> Vec<u8> {
+    (0..FILE_SIZE).map(|i| (i % 251) as u8).collect()
+}
+
+fn write_file(data: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("resume-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+fn md5_hex(data: &[u8]) -> String {
+    format!("{:x}", Md5::digest(data))
+}
+
+/// Parts 1 and 2 of `data`, as uploaded before the interruption.
+fn uploaded_parts(data: &[u8]) -> Vec<ListedPart> {
+    (0..2)
+        .map(|i| ListedPart {
+            part_number: i as i32 + 1,
+            size: PART_SIZE as u64,
+            e_tag: md5_hex(&data[i * PART_SIZE..(i + 1) * PART_SIZE]),
+        })
+        .collect()
+}
+
+fn modified(path: &Path) -> SystemTime {
+    std::fs::metadata(path).unwrap().modified().unwrap()
+}
+
+/// The upload was created before the file was last written.
+fn started_before(path: &Path) -> Option<SystemTime> {
+    Some(modified(path) - Duration::from_secs(60))
+}
+
+fn kept(plan: &ResumePlan) -> Vec<i32> {
+    plan.kept.iter().map(|part| part.part_number).collect()
+}
+
+fn to_upload(plan: &ResumePlan) -> Vec<i32> {
+    plan.to_upload.iter().map(|part| part.part_number).collect()
+}
+
+#[test]
+fn test_parse_resume_verify() {
+    assert_eq!(Ok(ResumeVerify::Mtime), "mtime".parse());
+    assert_eq!(Ok(ResumeVerify::Content), "content".parse());
+    assert!("size".parse::<ResumeVerify>().is_err());
+}
+
+#[tokio::test]
+async fn test_unchanged_file_keeps_parts_by_mtime() {
+    let data = content();
+    let path = write_file(&data);
+    let initiated = Some(modified(&path) + Duration::from_secs(60));
+
+    let plan = plan_resume(
+        "upload",
+        &uploaded_parts(&data),
+        &path,
+        initiated,
+        ResumeVerify::Mtime,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(vec![1, 2], kept(&plan));
+    assert_eq!(vec![3], to_upload(&plan));
+    assert_eq!(2000, plan.verified_bytes());
+    assert_eq!(1500, plan.bytes_to_upload());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_identical_regenerated_file() {
+    let data = content();
+    // The file is written again with the same content after the upload
+    // started.
+    let path = write_file(&data);
+    let parts = uploaded_parts(&data);
+
+    let by_mtime = plan_resume(
+        "upload",
+        &parts,
+        &path,
+        started_before(&path),
+        ResumeVerify::Mtime,
+    )
+    .await
+    .unwrap();
+    let by_content = plan_resume(
+        "upload",
+        &parts,
+        &path,
+        started_before(&path),
+        ResumeVerify::Content,
+    )
+    .await
+    .unwrap();
+
+    assert!(kept(&by_mtime).is_empty());
+    assert_eq!(vec![1, 2, 3], to_upload(&by_mtime));
+    assert_eq!(FILE_SIZE as u64, by_mtime.bytes_to_upload());
+
+    assert_eq!(ResumeVerify::Content, by_content.verify);
+    assert_eq!(None, by_content.fallback);
+    assert_eq!(vec![1, 2], kept(&by_content));
+    assert_eq!(vec![3], to_upload(&by_content));
+    assert_eq!(2000, by_content.verified_bytes());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_different_regenerated_file() {
+    let data = content();
+    let parts = uploaded_parts(&data);
+    let mut changed = data.clone();
+    changed[PART_SIZE + 10] ^= 0xFF;
+    let path = write_file(&changed);
+
+    let plan = plan_resume(
+        "upload",
+        &parts,
+        &path,
+        started_before(&path),
+        ResumeVerify::Content,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(vec![1], kept(&plan));
+    assert_eq!(vec![2, 3], to_upload(&plan));
+    assert_eq!(
+        (PART_SIZE as u64, PART_SIZE as u64),
+        (plan.to_upload[0].offset, plan.to_upload[0].size)
+    );
+    assert_eq!(
+        (2 * PART_SIZE as u64, 1500),
+        (plan.to_upload[1].offset, plan.to_upload[1].size)
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_kms_etags_fall_back_to_mtime() {
+    let data = content();
+    let path = write_file(&data);
+    let mut parts = uploaded_parts(&data);
+    parts[1].e_tag = "not-an-md5-kms-etag".to_string();
+
+    let plan = plan_resume(
+        "upload",
+        &parts,
+        &path,
+        started_before(&path),
+        ResumeVerify::Content,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(ResumeVerify::Mtime, plan.verify);
+    assert!(plan.fallback.unwrap().contains("part 2"));
+    assert!(kept(&plan).is_empty());
+    assert_eq!(vec![1, 2, 3], to_upload(&plan));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_no_parts_listed_plans_whole_file() {
+    let path = write_file(&content());
+
+    let plan = plan_resume("upload", &[], &path, None, ResumeVerify::Content)
+        .await
+        .unwrap();
+
+    assert!(plan.kept.is_empty());
+    assert_eq!(FILE_SIZE as u64, plan.bytes_to_upload());
+    assert_eq!(0, plan.to_upload[0].offset);
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Starts a server listing one upload of `key` with `parts`, accepting
+/// UploadPart and CompleteMultipartUpload, and recording the part numbers
+/// uploaded and the body of the completion.
+async fn mock_s3(parts: Vec<ListedPart>) -> (Client, Arc<Mutex<Vec<String>>>) {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let received = requests.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let parts = parts.clone();
+        let requests = received.clone();
+        async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let response = if method == Method::GET && path == "/bucket" {
+                Response::builder().body(Body::from(
+                    "<ListMultipartUploadsResult><Bucket>bucket</Bucket>\
+                     <IsTruncated>false</IsTruncated><Upload><Key>key</Key>\
+                     <UploadId>upload</UploadId>\
+                     <Initiated>2022-03-01T12:00:00.000Z</Initiated></Upload>\
+                     </ListMultipartUploadsResult>",
+                ))
+            } else if method == Method::GET {
+                let parts: String = parts
+                    .iter()
+                    .map(|p| {
+                        format!(
+                            "<Part><PartNumber>{}</PartNumber><ETag>\"{}\"</ETag>\
+                             <Size>{}</Size></Part>",
+                            p.part_number, p.e_tag, p.size
+                        )
+                    })
+                    .collect();
+                Response::builder().body(Body::from(format!(
+                    "<ListPartsResult><Bucket>bucket</Bucket><Key>key</Key>\
+                     <UploadId>upload</UploadId><IsTruncated>false</IsTruncated>{}\
+                     </ListPartsResult>",
+                    parts
+                )))
+            } else if method == Method::PUT {
+                let part = query
+                    .split('&')
+                    .find_map(|p| p.strip_prefix("partNumber="))
+                    .unwrap()
+                    .to_string();
+                requests
+                    .lock()
+                    .unwrap()
+                    .push(format!("part {} ({} bytes)", part, body.len()));
+                Response::builder()
+                    .header("ETag", format!("\"new-{}\"", part))
+                    .body(Body::empty())
+            } else {
+                requests
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(body.to_vec()).unwrap());
+                Response::builder().body(Body::from(
+                    "<CompleteMultipartUploadResult><ETag>\"done-3\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                ))
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), requests)
+}
+
+#[tokio::test]
+async fn test_resume_upload_sends_only_unverified_parts() {
+    let data = content();
+    // Written now, long after the upload was listed as created.
+    let path = write_file(&data);
+    let (client, requests) = mock_s3(uploaded_parts(&data)).await;
+
+    let result = resume_upload(
+        &client,
+        "bucket",
+        "key",
+        "upload",
+        &path,
+        ResumeVerify::Content,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!("done-3", result.e_tag);
+    assert_eq!(2000, result.verified_existing_bytes);
+    assert_eq!(1500, result.uploaded_bytes);
+    let requests = requests.lock().unwrap();
+    assert_eq!(2, requests.len());
+    assert_eq!("part 3 (1500 bytes)", requests[0]);
+    // The completion lists the kept parts with their ETags and the new one.
+    let completion = &requests[1];
+    assert!(completion.contains(&md5_hex(&data[..PART_SIZE])));
+    assert!(completion.contains(&md5_hex(&data[PART_SIZE..2 * PART_SIZE])));
+    assert!(completion.contains("new-3"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_resume_upload_events_start_from_the_kept_parts() {
+    let data = content();
+    let path = write_file(&data);
+    let (client, _) = mock_s3(uploaded_parts(&data)).await;
+    let events = Mutex::new(Vec::new());
+
+    let result = resume_upload_with_events(
+        &client,
+        "bucket",
+        "key",
+        "upload",
+        &path,
+        ResumeVerify::Content,
+        &|event| events.lock().unwrap().push(event.clone()),
+    )
+    .await
+    .unwrap();
+
+    let events = events.into_inner().unwrap();
+    assert_eq!(3, events.len(), "{:?}", events);
+    let kept = TransferTotals::resumed(3500, 2000);
+    assert_eq!(ProgressEvent::Started { totals: kept }, events[0]);
+    match &events[1] {
+        ProgressEvent::Part {
+            part_number,
+            bytes,
+            totals,
+            ..
+        } => {
+            assert_eq!(3, *part_number);
+            assert_eq!(1500, *bytes);
+            assert_eq!(3500, totals.completed_bytes());
+            assert_eq!(1500, totals.transferred_bytes);
+        }
+        other => panic!("{:?}", other),
+    }
+    match &events[2] {
+        ProgressEvent::Finished { totals, .. } => assert_eq!(result.totals, *totals),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(
+        TransferTotals {
+            total_bytes: 3500,
+            resumed_bytes: 2000,
+            transferred_bytes: 1500,
+        },
+        result.totals
+    );
+    std::fs::remove_file(&path).unwrap();
+}
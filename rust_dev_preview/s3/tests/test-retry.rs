@@ -0,0 +1,117 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use s3_service::retry::{
+    parse_retry_after, retry_after_header, RetryPolicy, SlowDownCoordinator, WaitSource,
+};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// 2022-01-31T00:00:00Z
+const NOW: u64 = 1_643_587_200;
+
+fn now() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(NOW)
+}
+
+fn headers(retry_after: &str) -> http::HeaderMap {
+    let mut headers = http::HeaderMap::new();
+    headers.insert(http::header::RETRY_AFTER, retry_after.parse().unwrap());
+    headers
+}
+
+#[test]
+fn test_retry_after_seconds() {
+    assert_eq!(
+        Some(Duration::from_secs(5)),
+        retry_after_header(&headers("5"), now())
+    );
+    assert_eq!(None, retry_after_header(&http::HeaderMap::new(), now()));
+    assert_eq!(None, parse_retry_after("soon", now()));
+}
+
+#[test]
+fn test_retry_after_http_date() {
+    assert_eq!(
+        Some(Duration::from_secs(90)),
+        retry_after_header(&headers("Mon, 31 Jan 2022 00:01:30 GMT"), now())
+    );
+    // A date in the past means "retry now".
+    assert_eq!(
+        Some(Duration::from_secs(0)),
+        parse_retry_after("Sun, 30 Jan 2022 23:00:00 GMT", now())
+    );
+}
+
+#[test]
+fn test_delay_sources_and_cap() {
+    let policy = RetryPolicy {
+        base_delay_ms: 100,
+        max_delay_ms: 1_000,
+        max_retry_after_ms: 30_000,
+        ..Default::default()
+    };
+    assert_eq!(
+        (Duration::from_millis(400), WaitSource::Backoff),
+        policy.delay(2, None)
+    );
+    assert_eq!(
+        (Duration::from_millis(1_000), WaitSource::Backoff),
+        policy.delay(20, None)
+    );
+    assert_eq!(
+        (Duration::from_secs(5), WaitSource::RetryAfter),
+        policy.delay(0, Some(Duration::from_secs(5)))
+    );
+    // A header asking for less than the backoff does not shorten it.
+    assert_eq!(
+        (Duration::from_millis(400), WaitSource::Backoff),
+        policy.delay(2, Some(Duration::from_millis(10)))
+    );
+    // An hour-long Retry-After is capped.
+    assert_eq!(
+        (Duration::from_secs(30), WaitSource::RetryAfterCapped),
+        policy.delay(0, Some(Duration::from_secs(3600)))
+    );
+}
+
+#[test]
+fn test_jitter_is_bounded() {
+    let policy = RetryPolicy {
+        base_delay_ms: 100,
+        max_delay_ms: 1_000,
+        jitter_ms: 50,
+        ..Default::default()
+    };
+    assert_eq!(
+        Duration::from_millis(250),
+        policy.backoff_with_jitter(1, 50)
+    );
+    // The jitter does not go over the cap.
+    assert_eq!(
+        Duration::from_millis(1_000),
+        policy.backoff_with_jitter(4, 50)
+    );
+    for _ in 0..100 {
+        let delay = policy.jittered_backoff(2);
+        assert!(delay >= Duration::from_millis(400) && delay <= Duration::from_millis(450));
+    }
+    // Plain backoff is never jittered.
+    assert_eq!(Duration::from_millis(400), policy.backoff(2));
+}
+
+#[tokio::test]
+async fn test_slow_down_pauses_waiters() {
+    let coordinator = SlowDownCoordinator::new();
+    let start = Instant::now();
+    coordinator.wait().await;
+    assert!(start.elapsed() < Duration::from_millis(50));
+
+    coordinator.slow_down(Duration::from_millis(200));
+    // A shorter request does not cut the pause short.
+    coordinator.slow_down(Duration::from_millis(10));
+    let other = coordinator.clone();
+    other.wait().await;
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
@@ -0,0 +1,159 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::{Client, Credentials, Endpoint, Region, RetryConfig};
+use hyper::{Body, Method, Request, Response};
+use s3_service::role_credentials::{
+    is_fresh, AssumeRoleProvider, CachingCredentialsProvider, REFRESH_BEFORE_EXPIRY,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+const ROLE_ARN: &str = "arn:aws:iam::123456789012:role/uploader";
+
+/// What the server saw: the number of AssumeRole calls, and the access key
+/// of each PutObject.
+#[derive(Default)]
+struct Captured {
+    assume_role: AtomicUsize,
+    put_keys: Mutex<Vec<String>>,
+}
+
+/// Starts a server answering AssumeRole with credentials expiring
+/// `expires_in` from now, `AKID` followed by the number of the call, and
+/// PutObject.
+fn mock_server(expires_in: Duration) -> (u16, Arc<Captured>) {
+    let captured = Arc::new(Captured::default());
+    let recorder = captured.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            if req.method() == Method::POST {
+                let call = recorder.assume_role.fetch_add(1, Ordering::SeqCst) + 1;
+                let expiration =
+                    chrono::Utc::now() + chrono::Duration::from_std(expires_in).unwrap();
+                let body = format!(
+                    "<AssumeRoleResponse><AssumeRoleResult><Credentials>\
+                     <AccessKeyId>AKID{}</AccessKeyId>\
+                     <SecretAccessKey>secret</SecretAccessKey>\
+                     <SessionToken>token</SessionToken>\
+                     <Expiration>{}</Expiration>\
+                     </Credentials></AssumeRoleResult></AssumeRoleResponse>",
+                    call,
+                    expiration.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                );
+                return Response::new(Body::from(body));
+            }
+            let authorization = req
+                .headers()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            let access_key = authorization
+                .split("Credential=")
+                .nth(1)
+                .and_then(|rest| rest.split('/').next())
+                .unwrap_or_default()
+                .to_string();
+            recorder.put_keys.lock().unwrap().push(access_key);
+            Response::builder()
+                .header("ETag", "\"put-etag\"")
+                .body(Body::empty())
+                .unwrap()
+        }
+    });
+    (port, captured)
+}
+
+fn role_provider(port: u16) -> AssumeRoleProvider {
+    use aws_sdk_sts::{Credentials, Endpoint, Region, RetryConfig};
+    let conf = aws_sdk_sts::Config::builder()
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("caller", "secret", None, None, "test"))
+        .endpoint_resolver(Endpoint::immutable(common::url_for(port).parse().unwrap()))
+        .retry_config(RetryConfig::disabled())
+        .build();
+    AssumeRoleProvider::new(aws_sdk_sts::Client::from_conf(conf), ROLE_ARN)
+}
+
+fn s3_client(port: u16, credentials: CachingCredentialsProvider) -> Client {
+    let conf = common::config_for(port)
+        .credentials_provider(credentials)
+        .build();
+    Client::from_conf(conf)
+}
+
+async fn put_objects(client: &Client, count: usize) {
+    for n in 0..count {
+        client
+            .put_object()
+            .bucket("bucket")
+            .key(format!("file-{}", n))
+            .body(format!("content {}", n).into_bytes().into())
+            .send()
+            .await
+            .unwrap();
+    }
+}
+
+#[test]
+fn test_is_fresh() {
+    let now = SystemTime::now();
+    let expiring =
+        |after: Duration| Credentials::new("access", "secret", None, Some(now + after), "test");
+    assert!(is_fresh(&expiring(Duration::from_secs(3600)), now));
+    assert!(!is_fresh(&expiring(REFRESH_BEFORE_EXPIRY), now));
+    assert!(!is_fresh(&expiring(Duration::from_secs(60)), now));
+    assert!(is_fresh(
+        &Credentials::new("access", "secret", None, None, "test"),
+        now
+    ));
+}
+
+#[tokio::test]
+async fn test_assumes_role_once_for_many_uploads() {
+    let (port, captured) = mock_server(Duration::from_secs(3600));
+    let client = s3_client(port, CachingCredentialsProvider::new(role_provider(port)));
+
+    put_objects(&client, 5).await;
+
+    assert_eq!(1, captured.assume_role.load(Ordering::SeqCst));
+    assert_eq!(vec!["AKID1"; 5], *captured.put_keys.lock().unwrap());
+}
+
+#[tokio::test]
+async fn test_concurrent_requests_share_one_refresh() {
+    let (port, captured) = mock_server(Duration::from_secs(3600));
+    let provider = Arc::new(CachingCredentialsProvider::new(role_provider(port)));
+
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            let provider = provider.clone();
+            tokio::spawn(async move { provider.credentials().await.unwrap() })
+        })
+        .collect();
+    for task in tasks {
+        assert_eq!("AKID1", task.await.unwrap().access_key_id());
+    }
+    assert_eq!(1, captured.assume_role.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_refreshes_credentials_about_to_expire() {
+    // Within REFRESH_BEFORE_EXPIRY of their expiry from the start.
+    let (port, captured) = mock_server(Duration::from_secs(120));
+    let client = s3_client(port, CachingCredentialsProvider::new(role_provider(port)));
+
+    put_objects(&client, 3).await;
+
+    assert_eq!(3, captured.assume_role.load(Ordering::SeqCst));
+    assert_eq!(
+        vec!["AKID1", "AKID2", "AKID3"],
+        *captured.put_keys.lock().unwrap()
+    );
+}
@@ -0,0 +1,345 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use common::MockS3;
+use s3_service::impl_s3_ops;
+use s3_service::ops::{OpError, S3Ops};
+use s3_service::run_summary::{
+    group_failures, listed_sha256, read_files_from, render_failures, retry_command, select_listed,
+    write_event_log, write_retry_file, ListedFile, MAX_LISTED_KEYS,
+};
+use s3_service::scheduler::{upload_files, PendingFile, ScheduledFile, SchedulerOptions};
+use s3_service::shutdown::Shutdown;
+use s3_service::upload::UploadPlanOptions;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// `MockS3` whose PutObject fails by key prefix: `denied/` with
+/// AccessDenied, and `quota/` with a code outside the catalogue.
+struct FailingByKey {
+    mock: MockS3,
+}
+
+impl_s3_ops! {
+    impl S3Ops for FailingByKey {
+        async fn head_bucket(&self, bucket: &str) -> Result<(), OpError> {
+            self.mock.head_bucket(bucket).await
+        }
+
+        async fn create_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> Result<String, OpError> {
+            self.mock.create_multipart_upload(bucket, key).await
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+        ) -> Result<(), OpError> {
+            self.mock
+                .abort_multipart_upload(bucket, key, upload_id)
+                .await
+        }
+
+        async fn upload_part(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            self.mock
+                .upload_part(bucket, key, upload_id, part_number, body)
+                .await
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String)>,
+        ) -> Result<String, OpError> {
+            self.mock
+                .complete_multipart_upload(bucket, key, upload_id, parts)
+                .await
+        }
+
+        async fn put_object(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            let (status, code) = if key.starts_with("denied/") {
+                (403, "AccessDenied")
+            } else if key.starts_with("quota/") {
+                (400, "QuotaExceeded")
+            } else {
+                return self.mock.put_object(bucket, key, body).await;
+            };
+            Err(OpError {
+                status: Some(status),
+                code: Some(code.to_string()),
+                message: "rejected".to_string(),
+            })
+        }
+
+        async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), OpError> {
+            self.mock.delete_object(bucket, key).await
+        }
+
+        async fn get_object_range(
+            &self,
+            bucket: &str,
+            key: &str,
+            offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>, OpError> {
+            self.mock
+                .get_object_range(bucket, key, offset, length)
+                .await
+        }
+    }
+}
+
+/// Writes a file for each of `names` under a new directory, keyed by its
+/// relative path.
+fn test_files(names: &[&str]) -> (PathBuf, Vec<ScheduledFile>) {
+    let dir = std::env::temp_dir().join(format!("run-summary-test-{}", uuid::Uuid::new_v4()));
+    let files = names
+        .iter()
+        .map(|name| {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, b"hello").unwrap();
+            ScheduledFile {
+                path,
+                key: name.to_string(),
+                size: 5,
+            }
+        })
+        .collect();
+    (dir, files)
+}
+
+fn options() -> SchedulerOptions {
+    SchedulerOptions {
+        concurrency: 4,
+        plan: UploadPlanOptions {
+            multipart_threshold: 1 << 30,
+            part_size: None,
+            num_parts: None,
+        },
+        ..Default::default()
+    }
+}
+
+fn keys(n: usize, prefix: &str) -> Vec<String> {
+    (0..n).map(|i| format!("{}{:02}", prefix, i)).collect()
+}
+
+#[tokio::test]
+async fn test_mixed_failures() {
+    let mut names = vec!["ok/a.txt", "ok/b.txt", "gone.txt", "quota/q.txt"];
+    let denied = keys(12, "denied/d");
+    names.extend(denied.iter().map(String::as_str));
+    let (dir, files) = test_files(&names);
+    // Listed, then deleted before it is read.
+    std::fs::remove_file(dir.join("gone.txt")).unwrap();
+
+    let ops = FailingByKey {
+        mock: MockS3::new(),
+    };
+    let shutdown = Shutdown::new(Duration::from_secs(60));
+    let summary = upload_files(&ops, "bucket", files, &options(), &shutdown).await;
+    assert_eq!(2, summary.completed.len());
+
+    let groups = group_failures(&summary.pending);
+    let classes: Vec<(&str, usize)> = groups
+        .iter()
+        .map(|group| (group.class.as_str(), group.keys.len()))
+        .collect();
+    assert_eq!(
+        vec![("AccessDenied", 12), ("NotFound", 1), ("QuotaExceeded", 1)],
+        classes
+    );
+    assert_eq!(denied, groups[0].keys);
+    assert!(groups[0].hint.is_some());
+    assert!(groups[2].hint.is_none());
+
+    // At most MAX_LISTED_KEYS keys per class, the rest in the event log.
+    let log = dir.join("events.jsonl");
+    let text = render_failures(&groups, names.len(), Some(&log));
+    assert!(text.starts_with("14 of 16 files failed:\n"), "{}", text);
+    assert!(text.contains(&denied[MAX_LISTED_KEYS - 1]));
+    assert!(!text.contains(&denied[MAX_LISTED_KEYS]));
+    assert!(text.contains(&format!("... and 2 more, listed in {}", log.display())));
+
+    // The retry file lists the failed files relative to the directory.
+    let retry = dir.join("retry.txt");
+    assert_eq!(
+        14,
+        write_retry_file(&retry, &dir, &summary.pending, &HashMap::new()).unwrap()
+    );
+    let mut listed = std::fs::read_to_string(&retry)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    listed.sort();
+    let mut expected = denied.clone();
+    expected.push("gone.txt".to_string());
+    expected.push("quota/q.txt".to_string());
+    expected.sort();
+    assert_eq!(expected, listed);
+
+    write_event_log(&log, &summary).unwrap();
+    let events: Vec<serde_json::Value> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(16, events.len());
+    let quota = events
+        .iter()
+        .find(|event| event["key"] == "quota/q.txt")
+        .unwrap();
+    assert_eq!("failed", quota["outcome"]);
+    assert_eq!("QuotaExceeded", quota["error_class"]);
+
+    // A run with --files-from the retry file uploads only those files.
+    let every: Vec<ScheduledFile> = names
+        .iter()
+        .map(|name| ScheduledFile {
+            path: dir.join(name),
+            key: name.to_string(),
+            size: 5,
+        })
+        .collect();
+    let selected = select_listed(every, &dir, &read_files_from(&retry).unwrap(), |file| {
+        file.path.as_path()
+    })
+    .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let mut selected: Vec<_> = selected.into_iter().map(|file| file.key).collect();
+    selected.sort();
+    assert_eq!(expected, selected);
+}
+
+#[test]
+fn test_read_files_from_and_select_listed() {
+    let dir = std::env::temp_dir().join(format!("run-summary-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let list = dir.join("list.txt");
+    let sha256 = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+    std::fs::write(
+        &list,
+        format!("# failed on Monday\na.txt\t{}\n\nsub/b.txt\n", sha256),
+    )
+    .unwrap();
+    let listed = read_files_from(&list).unwrap();
+    std::fs::write(&list, "a.txt\nsub/b.txt\tnot-a-digest\n").unwrap();
+    let err = read_files_from(&list).unwrap_err();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        vec![
+            ListedFile {
+                path: PathBuf::from("a.txt"),
+                sha256: Some(sha256.to_ascii_lowercase()),
+            },
+            ListedFile {
+                path: PathBuf::from("sub/b.txt"),
+                sha256: None,
+            },
+        ],
+        listed
+    );
+    assert!(err.to_string().contains("line 2"), "{}", err);
+
+    let root = Path::new("/data");
+    let mut expected_sha256 = HashMap::new();
+    expected_sha256.insert(PathBuf::from("/data/a.txt"), sha256.to_ascii_lowercase());
+    assert_eq!(expected_sha256, listed_sha256(root, &listed));
+
+    let files = vec![
+        PathBuf::from("/data/a.txt"),
+        PathBuf::from("/data/c.txt"),
+        PathBuf::from("/data/sub/b.txt"),
+    ];
+    let selected = select_listed(files.clone(), root, &listed, |path| path.as_path()).unwrap();
+    assert_eq!(vec![files[0].clone(), files[2].clone()], selected);
+
+    let missing: Vec<ListedFile> = ["a.txt", "z.txt"]
+        .iter()
+        .map(|name| ListedFile {
+            path: PathBuf::from(name),
+            sha256: None,
+        })
+        .collect();
+    let err = select_listed(files, root, &missing, |path| path.as_path()).unwrap_err();
+    assert!(err.to_string().contains("z.txt"), "{}", err);
+}
+
+#[test]
+fn test_retry_file_keeps_sha256() {
+    let dir = std::env::temp_dir().join(format!("run-summary-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let pending: Vec<PendingFile> = ["a.txt", "b.txt"]
+        .iter()
+        .map(|name| PendingFile {
+            path: dir.join(name),
+            key: name.to_string(),
+            size: 1,
+            bytes_transferred: 0,
+            error: Some("AccessDenied".to_string()),
+            error_class: Some("AccessDenied".to_string()),
+            expected_sha256: None,
+        })
+        .collect();
+    let mut expected_sha256 = HashMap::new();
+    expected_sha256.insert(dir.join("a.txt"), "ab".repeat(32));
+    let retry = dir.join("retry.txt");
+    assert_eq!(
+        2,
+        write_retry_file(&retry, &dir, &pending, &expected_sha256).unwrap()
+    );
+    let listed = read_files_from(&retry).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(expected_sha256, listed_sha256(&dir, &listed));
+    assert_eq!(2, listed.len());
+}
+
+#[test]
+fn test_retry_command() {
+    let args: Vec<String> = [
+        "upload-directory",
+        "-b",
+        "bucket",
+        "-d",
+        "my photos",
+        "--files-from",
+        "old.txt",
+        "--resume",
+        "--files-from=older.txt",
+        "-c",
+        "8",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    assert_eq!(
+        "upload-directory -b bucket -d 'my photos' -c 8 --files-from retry.txt",
+        retry_command(&args, Path::new("retry.txt"))
+    );
+}
@@ -0,0 +1,135 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::retry::RetryPolicy;
+use s3_service::runtime::{build_runtime, build_runtime_named, RuntimeFlavor};
+use s3_service::upload::upload_multipart_parallel;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn test_worker_threads_are_named_and_numbered() {
+    let runtime = build_runtime_named(2, "s3-upload");
+
+    let names: HashSet<String> = runtime.block_on(async {
+        let mut handles = Vec::new();
+        for _ in 0..16 {
+            handles.push(tokio::spawn(async {
+                std::thread::current().name().unwrap().to_string()
+            }));
+        }
+        let mut names = HashSet::new();
+        for handle in handles {
+            names.insert(handle.await.unwrap());
+        }
+        names
+    });
+
+    assert!(!names.is_empty());
+    for name in &names {
+        let number = name.strip_prefix("s3-upload-").unwrap();
+        assert!(number.parse::<usize>().is_ok(), "{}", name);
+    }
+}
+
+/// Starts a server answering multipart uploads, holding each part for
+/// `part_delay`, and returns a client for it and the number of parts.
+async fn mock_server(part_delay: Duration) -> (Client, Arc<AtomicUsize>) {
+    let parts = Arc::new(AtomicUsize::new(0));
+    let counter = parts.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let counter = counter.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let response = if method == Method::PUT {
+                tokio::time::sleep(part_delay).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                Response::builder()
+                    .header("ETag", "\"part-etag\"")
+                    .body(Body::empty())
+            } else if query.contains("uploads") {
+                Response::builder().body(Body::from(
+                    "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                ))
+            } else {
+                Response::builder().body(Body::from(
+                    "<CompleteMultipartUploadResult><ETag>\"complete-etag\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                ))
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), parts)
+}
+
+/// Uploads a file of 4 parts, each held 200ms by the server, and returns
+/// the ETag, the parts received, and how long it took.
+async fn upload_four_parts() -> (String, usize, Duration) {
+    let (client, parts) = mock_server(Duration::from_millis(200)).await;
+    let path = std::env::temp_dir().join(format!("runtime-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![b'x'; 4000]).unwrap();
+
+    let start = std::time::Instant::now();
+    let e_tag = upload_multipart_parallel(
+        &client,
+        "bucket",
+        "key",
+        path.to_str().unwrap(),
+        4,
+        None,
+        None,
+        &RetryPolicy::default(),
+    )
+    .await
+    .unwrap();
+    let elapsed = start.elapsed();
+
+    std::fs::remove_file(&path).unwrap();
+    (e_tag, parts.load(Ordering::SeqCst), elapsed)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_upload_in_an_existing_multi_thread_runtime() {
+    let (e_tag, parts, elapsed) = upload_four_parts().await;
+
+    assert_eq!("\"complete-etag\"", e_tag);
+    assert_eq!(4, parts);
+    assert!(elapsed < Duration::from_millis(700), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn test_upload_in_a_current_thread_runtime() {
+    let (e_tag, parts, elapsed) = upload_four_parts().await;
+
+    assert_eq!("\"complete-etag\"", e_tag);
+    assert_eq!(4, parts);
+    // The parts still overlap on a single thread.
+    assert!(elapsed < Duration::from_millis(700), "{:?}", elapsed);
+}
+
+#[test]
+fn test_built_runtimes_of_both_flavors() {
+    for flavor in [RuntimeFlavor::MultiThread, RuntimeFlavor::CurrentThread] {
+        let runtime = build_runtime(flavor, 2, "s3-upload");
+        let (_, parts, _) = runtime.block_on(upload_four_parts());
+        assert_eq!(4, parts, "{:?}", flavor);
+    }
+    assert_eq!(
+        Ok(RuntimeFlavor::CurrentThread),
+        "current".parse::<RuntimeFlavor>()
+    );
+    assert!("single".parse::<RuntimeFlavor>().is_err());
+}
@@ -0,0 +1,408 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use common::MockS3;
+use hyper::{Body, Request, Response};
+use s3_service::impl_s3_ops;
+use s3_service::memory_budget::MemoryBudget;
+use s3_service::ops::{OpError, S3Ops};
+use s3_service::scheduler::{
+    transfer_files, upload_files, ResumeManifest, ScheduledDownload, ScheduledFile,
+    SchedulerOptions, Transfer,
+};
+use s3_service::shutdown::Shutdown;
+use s3_service::upload::{UploadPlanOptions, MIN_PART_SIZE};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// `MockS3` with a scripted shutdown.
+struct Scripted {
+    mock: MockS3,
+    shutdown: Shutdown,
+    /// Requests a stop once this many files are uploaded.
+    stop_after_files: Option<usize>,
+    /// Requests a stop when this part is sent, and the part succeeds.
+    stop_on_part: Option<i32>,
+    /// This part never completes.
+    hang_on_part: Option<i32>,
+    files: AtomicUsize,
+    aborted: Mutex<Vec<String>>,
+}
+
+impl Scripted {
+    fn new(shutdown: &Shutdown) -> Self {
+        Self {
+            mock: MockS3::new(),
+            shutdown: shutdown.clone(),
+            stop_after_files: None,
+            stop_on_part: None,
+            hang_on_part: None,
+            files: AtomicUsize::new(0),
+            aborted: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn file_done(&self) {
+        let done = self.files.fetch_add(1, Ordering::SeqCst) + 1;
+        if Some(done) == self.stop_after_files {
+            self.shutdown.trigger();
+        }
+    }
+}
+
+impl_s3_ops! {
+    impl S3Ops for Scripted {
+        async fn head_bucket(&self, bucket: &str) -> Result<(), OpError> {
+            self.mock.head_bucket(bucket).await
+        }
+
+        async fn create_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+        ) -> Result<String, OpError> {
+            self.mock.create_multipart_upload(bucket, key).await
+        }
+
+        async fn abort_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+        ) -> Result<(), OpError> {
+            self.aborted.lock().unwrap().push(key.to_string());
+            self.mock
+                .abort_multipart_upload(bucket, key, upload_id)
+                .await
+        }
+
+        async fn upload_part(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            part_number: i32,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            if Some(part_number) == self.hang_on_part {
+                self.shutdown.trigger();
+                futures::future::pending::<()>().await;
+            }
+            if Some(part_number) == self.stop_on_part {
+                self.shutdown.trigger();
+            }
+            self.mock
+                .upload_part(bucket, key, upload_id, part_number, body)
+                .await
+        }
+
+        async fn complete_multipart_upload(
+            &self,
+            bucket: &str,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i32, String)>,
+        ) -> Result<String, OpError> {
+            let e_tag = self
+                .mock
+                .complete_multipart_upload(bucket, key, upload_id, parts)
+                .await?;
+            self.file_done();
+            Ok(e_tag)
+        }
+
+        async fn put_object(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: Vec<u8>,
+        ) -> Result<String, OpError> {
+            let e_tag = self.mock.put_object(bucket, key, body).await?;
+            self.file_done();
+            Ok(e_tag)
+        }
+
+        async fn delete_object(&self, bucket: &str, key: &str) -> Result<(), OpError> {
+            self.mock.delete_object(bucket, key).await
+        }
+
+        async fn get_object_range(
+            &self,
+            bucket: &str,
+            key: &str,
+            offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>, OpError> {
+            self.mock
+                .get_object_range(bucket, key, offset, length)
+                .await
+        }
+    }
+}
+
+/// Writes files of the given sizes, keyed `file-N`.
+fn test_files(sizes: &[usize]) -> (PathBuf, Vec<ScheduledFile>) {
+    let dir = std::env::temp_dir().join(format!("scheduler-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let files = sizes
+        .iter()
+        .enumerate()
+        .map(|(n, size)| {
+            let path = dir.join(format!("file-{}", n));
+            std::fs::write(&path, vec![b'x'; *size]).unwrap();
+            ScheduledFile {
+                path,
+                key: format!("file-{}", n),
+                size: *size as u64,
+            }
+        })
+        .collect();
+    (dir, files)
+}
+
+fn sequential(multipart_threshold: u64) -> SchedulerOptions {
+    SchedulerOptions {
+        concurrency: 1,
+        plan: UploadPlanOptions {
+            multipart_threshold,
+            part_size: None,
+            num_parts: Some(2),
+        },
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_stop_after_files_and_resume() {
+    let (dir, files) = test_files(&[10, 20, 30, 40, 50]);
+    let shutdown = Shutdown::new(Duration::from_secs(60));
+    let mut ops = Scripted::new(&shutdown);
+    ops.stop_after_files = Some(2);
+
+    let summary = upload_files(
+        &ops,
+        "bucket",
+        files.clone(),
+        &sequential(1 << 30),
+        &shutdown,
+    )
+    .await;
+    assert!(summary.interrupted);
+    assert_eq!(vec!["file-0", "file-1"], summary.completed);
+    assert!(summary.aborted_uploads.is_empty());
+    assert_eq!(vec!["PutObject", "PutObject"], ops.mock.calls());
+
+    let path = dir.join("resume.json");
+    summary.resume_manifest("bucket").save(&path).unwrap();
+    let manifest = ResumeManifest::load(&path).unwrap();
+    assert_eq!("bucket", manifest.bucket);
+    assert_eq!(files[2..].to_vec(), manifest.files());
+    assert!(manifest
+        .pending
+        .iter()
+        .all(|file| file.bytes_transferred == 0 && file.error.is_none()));
+
+    // The next run uploads exactly the pending files.
+    let shutdown = Shutdown::new(Duration::from_secs(60));
+    let ops = Scripted::new(&shutdown);
+    let summary = upload_files(
+        &ops,
+        "bucket",
+        manifest.files(),
+        &sequential(1 << 30),
+        &shutdown,
+    )
+    .await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(!summary.interrupted);
+    assert!(summary.pending.is_empty());
+    assert_eq!(vec!["file-2", "file-3", "file-4"], summary.completed);
+}
+
+#[tokio::test]
+async fn test_resume_manifest_keeps_expected_sha256() {
+    let (dir, files) = test_files(&[10, 20]);
+    let shutdown = Shutdown::new(Duration::from_secs(60));
+    let mut ops = Scripted::new(&shutdown);
+    ops.stop_after_files = Some(1);
+    let mut options = sequential(1 << 30);
+    options
+        .expected_sha256
+        .insert(files[1].path.clone(), "ab".repeat(32));
+
+    let summary = upload_files(&ops, "bucket", files.clone(), &options, &shutdown).await;
+
+    let path = dir.join("resume.json");
+    summary.resume_manifest("bucket").save(&path).unwrap();
+    let manifest = ResumeManifest::load(&path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(files[1..].to_vec(), manifest.files());
+    assert_eq!(options.expected_sha256, manifest.expected_sha256());
+}
+
+#[tokio::test]
+async fn test_graceful_stop_aborts_incomplete_upload() {
+    let size = 2 * MIN_PART_SIZE as usize;
+    let (dir, files) = test_files(&[size, size]);
+    let shutdown = Shutdown::new(Duration::from_secs(60));
+    let mut ops = Scripted::new(&shutdown);
+    ops.stop_on_part = Some(1);
+
+    let summary = upload_files(&ops, "bucket", files, &sequential(1), &shutdown).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // The part in flight finished, part 2 never started, and the second file
+    // was not started at all.
+    assert!(summary.interrupted);
+    assert!(summary.completed.is_empty());
+    assert_eq!(
+        vec![
+            "CreateMultipartUpload",
+            "UploadPart",
+            "AbortMultipartUpload"
+        ],
+        ops.mock.calls()
+    );
+    assert_eq!(vec!["file-0"], *ops.aborted.lock().unwrap());
+    assert_eq!(vec!["file-0"], summary.aborted_uploads);
+    let pending = summary
+        .pending
+        .iter()
+        .map(|file| (file.key.as_str(), file.bytes_transferred))
+        .collect::<Vec<_>>();
+    assert_eq!(vec![("file-0", MIN_PART_SIZE), ("file-1", 0)], pending);
+}
+
+#[tokio::test]
+async fn test_grace_period_expiry_drops_part_in_flight() {
+    let size = 2 * MIN_PART_SIZE as usize;
+    let (dir, files) = test_files(&[size]);
+    let shutdown = Shutdown::new(Duration::from_millis(50));
+    let mut ops = Scripted::new(&shutdown);
+    ops.hang_on_part = Some(2);
+
+    let summary = tokio::time::timeout(
+        Duration::from_secs(10),
+        upload_files(&ops, "bucket", files, &sequential(1), &shutdown),
+    )
+    .await
+    .expect("the hard stop did not end the upload");
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(shutdown.is_aborted());
+    assert_eq!(vec!["file-0"], *ops.aborted.lock().unwrap());
+    assert_eq!(1, summary.pending.len());
+    assert_eq!(MIN_PART_SIZE, summary.pending[0].bytes_transferred);
+    assert_eq!(
+        Some(&"AbortMultipartUpload".to_string()),
+        ops.mock.calls().last()
+    );
+}
+
+#[tokio::test]
+async fn test_parts_are_reserved_from_the_memory_budget() {
+    let size = 2 * MIN_PART_SIZE as usize;
+    let (dir, files) = test_files(&[size, size, size, size]);
+    let budget = MemoryBudget::new(2 * MIN_PART_SIZE);
+    let options = SchedulerOptions {
+        concurrency: 4,
+        memory_budget: Some(budget.clone()),
+        ..sequential(1)
+    };
+
+    let mock = MockS3::new();
+    let summary = upload_files(&mock, "bucket", files, &options, &Shutdown::default()).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(4, summary.completed.len());
+    // Four files in flight could hold four parts; the budget fits two.
+    let stats = budget.stats();
+    assert!(stats.peak >= MIN_PART_SIZE);
+    assert!(stats.peak <= 2 * MIN_PART_SIZE);
+    assert_eq!(0, budget.used());
+}
+
+/// Starts a server holding a 10-byte object with the ETag "v2", answering
+/// ranged GetObjects whose If-Match is another ETag with 412.
+async fn versioned_object() -> Client {
+    let port = common::mock_s3(|req: Request<Body>| async move {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+        if header("if-match").map_or(false, |e_tag| e_tag != "\"v2\"") {
+            return Response::builder()
+                .status(412)
+                .body(Body::from(
+                    "<Error><Code>PreconditionFailed</Code>\
+                         <Message>At least one of the preconditions you specified did not hold\
+                         </Message></Error>",
+                ))
+                .unwrap();
+        }
+        let range = header("range").unwrap();
+        let (start, end) = range
+            .strip_prefix("bytes=")
+            .and_then(|range| range.split_once('-'))
+            .unwrap();
+        let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+        Response::builder()
+            .status(206)
+            .header("ETag", "\"v2\"")
+            .body(Body::from(b"0123456789"[start..=end].to_vec()))
+            .unwrap()
+    });
+    common::client_for(port)
+}
+
+#[tokio::test]
+async fn test_downloads_read_the_listed_object_only() {
+    let client = versioned_object().await;
+    let dir = std::env::temp_dir().join(format!("scheduler-test-{}", uuid::Uuid::new_v4()));
+    let download = |name: &str, e_tag: &str| {
+        Transfer::Download(ScheduledDownload {
+            key: name.to_string(),
+            path: dir.join(name),
+            size: 10,
+            e_tag: Some(e_tag.to_string()),
+        })
+    };
+
+    let summary = transfer_files(
+        &client,
+        "bucket",
+        vec![download("current", "v2"), download("stale", "v1")],
+        &sequential(1 << 30),
+        &Shutdown::default(),
+    )
+    .await;
+    let current = std::fs::read(dir.join("current")).unwrap();
+    let stale = dir.join("stale").exists();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(vec!["current"], summary.downloaded);
+    assert_eq!(b"0123456789".to_vec(), current);
+    assert_eq!(1, summary.pending_downloads.len());
+    let (key, err) = &summary.pending_downloads[0];
+    assert_eq!("stale", key);
+    assert!(err.as_deref().unwrap().contains("PreconditionFailed"));
+    assert!(!stale);
+}
+
+#[tokio::test]
+async fn test_second_trigger_is_hard_stop() {
+    let shutdown = Shutdown::new(Duration::from_secs(3600));
+    shutdown.trigger();
+    assert!(shutdown.is_stopping());
+    assert!(!shutdown.is_aborted());
+    shutdown.trigger();
+    assert!(shutdown.is_aborted());
+}
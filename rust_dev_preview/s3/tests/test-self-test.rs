@@ -0,0 +1,286 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::self_test::{self_test, test_data, SelfTestOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Default)]
+struct Store {
+    objects: HashMap<String, Vec<u8>>,
+    /// The key and the parts of each upload in progress, by upload ID.
+    uploads: HashMap<String, (String, BTreeMap<u32, Vec<u8>>)>,
+    next_upload: u32,
+}
+
+/// How the mock endpoint misbehaves.
+#[derive(Clone, Copy, Default)]
+struct Faults {
+    /// Ranged GETs never answer.
+    hang_ranged_gets: bool,
+    /// Aborts succeed but leave the upload listed.
+    ignore_aborts: bool,
+    /// Deleting the object of the put-object stage is denied.
+    deny_put_object_delete: bool,
+}
+
+fn query_value(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let mut split = pair.splitn(2, '=');
+        (split.next() == Some(name)).then(|| split.next().unwrap_or("").to_string())
+    })
+}
+
+fn handle(store: &mut Store, faults: Faults, req: &Request<Body>, body: Vec<u8>) -> Response<Body> {
+    let key = req
+        .uri()
+        .path()
+        .trim_start_matches("/bucket")
+        .trim_start_matches('/')
+        .to_string();
+    let query = req.uri().query().unwrap_or("").to_string();
+    let upload_id = query_value(&query, "uploadId");
+    let response = match (req.method().clone(), upload_id) {
+        (Method::GET, _) if key.is_empty() => {
+            let uploads: String = store
+                .uploads
+                .iter()
+                .map(|(id, (key, _))| {
+                    format!(
+                        "<Upload><Key>{}</Key><UploadId>{}</UploadId></Upload>",
+                        key, id
+                    )
+                })
+                .collect();
+            Response::builder().body(Body::from(format!(
+                "<ListMultipartUploadsResult><Bucket>bucket</Bucket>\
+                 <IsTruncated>false</IsTruncated>{}</ListMultipartUploadsResult>",
+                uploads
+            )))
+        }
+        (Method::POST, None) => {
+            store.next_upload += 1;
+            let id = format!("upload-{}", store.next_upload);
+            store
+                .uploads
+                .insert(id.clone(), (key.clone(), BTreeMap::new()));
+            Response::builder().body(Body::from(format!(
+                "<InitiateMultipartUploadResult><Key>{}</Key><UploadId>{}</UploadId>\
+                 </InitiateMultipartUploadResult>",
+                key, id
+            )))
+        }
+        (Method::PUT, Some(id)) => {
+            let part: u32 = query_value(&query, "partNumber").unwrap().parse().unwrap();
+            store.uploads.get_mut(&id).unwrap().1.insert(part, body);
+            Response::builder()
+                .header("ETag", format!("\"part-{}\"", part))
+                .body(Body::empty())
+        }
+        (Method::POST, Some(id)) => {
+            let (key, parts) = store.uploads.remove(&id).unwrap();
+            let data = parts.into_iter().flat_map(|(_, part)| part).collect();
+            store.objects.insert(key, data);
+            Response::builder().body(Body::from(
+                "<CompleteMultipartUploadResult><ETag>\"multipart-3\"</ETag>\
+                 </CompleteMultipartUploadResult>",
+            ))
+        }
+        (Method::DELETE, Some(id)) => {
+            if !faults.ignore_aborts {
+                store.uploads.remove(&id);
+            }
+            Response::builder().status(204).body(Body::empty())
+        }
+        (Method::PUT, None) => {
+            store.objects.insert(key, body);
+            Response::builder()
+                .header("ETag", "\"etag\"")
+                .body(Body::empty())
+        }
+        (Method::DELETE, None) if faults.deny_put_object_delete && key.ends_with("put-object") => {
+            Response::builder()
+                .status(403)
+                .body(Body::from("<Error><Code>AccessDenied</Code></Error>"))
+        }
+        (Method::DELETE, None) => {
+            store.objects.remove(&key);
+            Response::builder().status(204).body(Body::empty())
+        }
+        (method, None) => match store.objects.get(&key) {
+            None => Response::builder()
+                .status(404)
+                .body(Body::from("<Error><Code>NoSuchKey</Code></Error>")),
+            Some(data) if method == Method::HEAD => Response::builder()
+                .header("Content-Length", data.len())
+                .header("ETag", "\"etag\"")
+                .body(Body::empty()),
+            Some(data) => match req.headers().get("range") {
+                Some(range) => {
+                    let bounds = range.to_str().unwrap().trim_start_matches("bytes=");
+                    let (first, last) = bounds.split_at(bounds.find('-').unwrap());
+                    let first: usize = first.parse().unwrap();
+                    let last: usize = last[1..].parse().unwrap();
+                    Response::builder()
+                        .status(206)
+                        .body(Body::from(data[first..=last].to_vec()))
+                }
+                None => Response::builder().body(Body::from(data.clone())),
+            },
+        },
+        _ => Response::builder().status(405).body(Body::empty()),
+    };
+    response.unwrap()
+}
+
+/// Starts a server keeping objects and multipart uploads in memory.
+async fn mock_s3(faults: Faults) -> (Client, Arc<Mutex<Store>>) {
+    let store = Arc::new(Mutex::new(Store::default()));
+    let shared = store.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let store = shared.clone();
+        async move {
+            if faults.hang_ranged_gets && req.headers().contains_key("range") {
+                futures::future::pending::<()>().await;
+            }
+            let (parts, body) = req.into_parts();
+            let body = hyper::body::to_bytes(body).await.unwrap().to_vec();
+            let req = Request::from_parts(parts, Body::empty());
+            handle(&mut store.lock().unwrap(), faults, &req, body)
+        }
+    });
+
+    (common::client_for(port), store)
+}
+
+fn options() -> SelfTestOptions {
+    SelfTestOptions {
+        file_size: 100_003,
+        stage_timeout: Duration::from_secs(10),
+    }
+}
+
+fn failed_stages(report: &s3_service::self_test::SelfTestReport) -> Vec<&str> {
+    report
+        .stages
+        .iter()
+        .filter(|stage| !stage.passed)
+        .map(|stage| stage.name.as_str())
+        .collect()
+}
+
+#[test]
+fn test_data_is_deterministic() {
+    let data = test_data(1000);
+    assert_eq!(data, test_data(1000));
+    assert_eq!(&data[..10], &test_data(10)[..]);
+    // Not a short repeating pattern.
+    assert_ne!(&data[..250], &data[250..500]);
+}
+
+#[tokio::test]
+async fn test_self_test_passes_and_cleans_up() {
+    let (client, store) = mock_s3(Faults::default()).await;
+
+    let report = self_test(&client, "bucket", "_selftest/", &options())
+        .await
+        .unwrap();
+
+    assert!(report.is_ok(), "{}", report.to_text());
+    let names: Vec<_> = report.stages.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(
+        vec![
+            "put-object",
+            "multipart-upload",
+            "get-object put-object",
+            "get-object multipart-upload",
+            "ranged-get put-object",
+            "ranged-get multipart-upload",
+            "abort-upload",
+            "cleanup",
+        ],
+        names
+    );
+    assert!(report.prefix.starts_with("_selftest/"));
+    assert_eq!(8, report.to_text().matches("PASS").count());
+    let store = store.lock().unwrap();
+    assert!(store.objects.is_empty());
+    assert!(store.uploads.is_empty());
+}
+
+#[tokio::test]
+async fn test_hung_stage_times_out() {
+    let (client, store) = mock_s3(Faults {
+        hang_ranged_gets: true,
+        ..Default::default()
+    })
+    .await;
+    let options = SelfTestOptions {
+        stage_timeout: Duration::from_millis(300),
+        ..options()
+    };
+
+    let report = self_test(&client, "bucket", "_selftest/", &options)
+        .await
+        .unwrap();
+
+    assert!(!report.is_ok());
+    assert_eq!(
+        vec!["ranged-get put-object", "ranged-get multipart-upload"],
+        failed_stages(&report)
+    );
+    assert!(report.to_text().contains("timed out"));
+    // The stages after the hung ones still ran.
+    assert!(store.lock().unwrap().objects.is_empty());
+}
+
+#[tokio::test]
+async fn test_upload_still_listed_after_abort_fails() {
+    let (client, _) = mock_s3(Faults {
+        ignore_aborts: true,
+        ..Default::default()
+    })
+    .await;
+
+    let report = self_test(&client, "bucket", "_selftest/", &options())
+        .await
+        .unwrap();
+
+    assert_eq!(vec!["abort-upload"], failed_stages(&report));
+    let abort = &report.stages[6];
+    assert!(
+        abort.error.as_deref().unwrap().contains("still listed"),
+        "{:?}",
+        abort.error
+    );
+}
+
+#[tokio::test]
+async fn test_cleanup_goes_on_after_a_failed_delete() {
+    let (client, store) = mock_s3(Faults {
+        deny_put_object_delete: true,
+        ..Default::default()
+    })
+    .await;
+
+    let report = self_test(&client, "bucket", "_selftest/", &options())
+        .await
+        .unwrap();
+
+    assert_eq!(vec!["cleanup"], failed_stages(&report));
+    let cleanup = report.stages.last().unwrap();
+    let error = cleanup.error.as_deref().unwrap();
+    assert!(error.contains("put-object"), "{}", error);
+    // The other object was still deleted.
+    let store = store.lock().unwrap();
+    let keys: Vec<_> = store.objects.keys().collect();
+    assert_eq!(1, keys.len());
+    assert!(keys[0].ends_with("put-object"), "{:?}", keys);
+}
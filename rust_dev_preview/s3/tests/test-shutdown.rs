@@ -0,0 +1,183 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::retry::RetryPolicy;
+use s3_service::shutdown::{Shutdown, StopSignal};
+use s3_service::upload::upload_multipart_parallel_with_shutdown;
+use s3_service::verbosity::VerbosityConfig;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The methods of the requests received, with `PUT` for each part.
+type Requests = Arc<Mutex<Vec<Method>>>;
+
+/// Starts a server that answers multipart uploads, each part after
+/// `part_delay`.
+async fn mock_server(part_delay: Duration) -> (Client, Requests) {
+    let requests = Requests::default();
+    let recorder = requests.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            hyper::body::to_bytes(req.into_body()).await.unwrap();
+            recorder.lock().unwrap().push(method.clone());
+            let response = if method == Method::PUT {
+                tokio::time::sleep(part_delay).await;
+                Response::builder()
+                    .header("ETag", "\"part-etag\"")
+                    .body(Body::empty())
+            } else if method == Method::DELETE {
+                Response::builder().status(204).body(Body::empty())
+            } else if query.contains("uploads") {
+                Response::builder().body(Body::from(
+                    "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                ))
+            } else {
+                Response::builder().body(Body::from(
+                    "<CompleteMultipartUploadResult><ETag>\"complete-etag\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                ))
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), requests)
+}
+
+fn test_file(size: usize) -> String {
+    let path = std::env::temp_dir().join(format!("shutdown-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![b'x'; size]).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+async fn upload(client: &Client, file: &str, shutdown: &Shutdown) -> Result<String, String> {
+    upload_multipart_parallel_with_shutdown(
+        client,
+        "bucket",
+        "key",
+        file,
+        4,
+        None,
+        None,
+        &RetryPolicy::default(),
+        VerbosityConfig::default(),
+        shutdown,
+    )
+    .await
+    .map_err(|err| err.to_string())
+}
+
+fn count(requests: &Requests, method: Method) -> usize {
+    requests
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|m| **m == method)
+        .count()
+}
+
+#[test]
+fn test_exit_codes() {
+    assert_eq!(130, StopSignal::Interrupt.exit_code());
+    assert_eq!(143, StopSignal::Terminate.exit_code());
+    assert_eq!("SIGTERM", StopSignal::Terminate.to_string());
+}
+
+#[tokio::test]
+async fn test_upload_completes_without_a_stop() {
+    let (client, requests) = mock_server(Duration::ZERO).await;
+    let file = test_file(4000);
+
+    let e_tag = upload(&client, &file, &Shutdown::default()).await.unwrap();
+
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!("\"complete-etag\"", e_tag);
+    assert_eq!(4, count(&requests, Method::PUT));
+    assert_eq!(0, count(&requests, Method::DELETE));
+}
+
+#[tokio::test]
+async fn test_stop_lets_parts_finish_then_aborts() {
+    let (client, requests) = mock_server(Duration::from_millis(300)).await;
+    let file = test_file(4000);
+    let shutdown = Shutdown::new(Duration::from_secs(10));
+    let stopper = shutdown.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stopper.trigger();
+    });
+
+    let start = Instant::now();
+    let err = upload(&client, &file, &shutdown).await.unwrap_err();
+
+    std::fs::remove_file(&file).unwrap();
+    assert!(err.contains("after 4 of 4 parts"), "{}", err);
+    // The parts in flight finished instead of being dropped.
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert!(!shutdown.is_aborted());
+    assert_eq!(1, count(&requests, Method::DELETE));
+}
+
+#[tokio::test]
+async fn test_parts_are_dropped_after_the_grace_period() {
+    let (client, requests) = mock_server(Duration::from_secs(30)).await;
+    let file = test_file(4000);
+    let shutdown = Shutdown::new(Duration::from_millis(200));
+    let stopper = shutdown.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stopper.trigger();
+    });
+
+    let start = Instant::now();
+    let err = upload(&client, &file, &shutdown).await.unwrap_err();
+
+    std::fs::remove_file(&file).unwrap();
+    assert!(err.contains("after 0 of 4 parts"), "{}", err);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(1, count(&requests, Method::DELETE));
+}
+
+#[tokio::test]
+async fn test_no_parts_start_once_stopping() {
+    let (client, requests) = mock_server(Duration::ZERO).await;
+    let file = test_file(4000);
+    let shutdown = Shutdown::new(Duration::from_secs(10));
+    shutdown.trigger();
+
+    let err = upload(&client, &file, &shutdown).await.unwrap_err();
+
+    std::fs::remove_file(&file).unwrap();
+    assert!(err.contains("after 0 of 4 parts"), "{}", err);
+    assert_eq!(0, count(&requests, Method::PUT));
+    assert_eq!(1, count(&requests, Method::DELETE));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_sigterm_requests_a_stop() {
+    let shutdown = Shutdown::new(Duration::from_secs(10));
+    shutdown.listen_for_signals();
+    // Let the listener install its handler before the signal is sent.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    unsafe {
+        libc::kill(libc::getpid(), libc::SIGTERM);
+    }
+    tokio::time::timeout(Duration::from_secs(5), shutdown.stopping())
+        .await
+        .unwrap();
+
+    assert_eq!(Some(StopSignal::Terminate), shutdown.signal());
+    assert!(!shutdown.is_aborted());
+}
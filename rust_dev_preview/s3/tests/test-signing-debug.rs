@@ -0,0 +1,187 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::{Credentials, RetryConfig};
+use hyper::{Body, Request, Response};
+use s3_service::signing_debug::{
+    debug_signing_client, redact_error_body, DebugSigningMode, SigningDebugger,
+};
+use std::sync::{Arc, Mutex};
+
+const ACCESS_KEY_ID: &str = "AKIDEXAMPLE";
+
+/// The Authorization headers received.
+type Received = Arc<Mutex<Vec<String>>>;
+
+/// Answers `/bucket/bad-signature` with SignatureDoesNotMatch, `/bucket/flaky`
+/// with a 500 that the SDK retries, and the rest with 200.
+async fn mock_endpoint() -> (u16, Received) {
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let recorder = received.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let authorization = req
+                .headers()
+                .get("authorization")
+                .map(|v| v.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            recorder.lock().unwrap().push(authorization);
+            let response = match req.uri().path() {
+                "/bucket/bad-signature" => {
+                    Response::builder().status(403).body(Body::from(format!(
+                        "<Error><Code>SignatureDoesNotMatch</Code>\
+                         <Message>The request signature we calculated does not match \
+                         the signature you provided.</Message>\
+                         <AWSAccessKeyId>{}</AWSAccessKeyId>\
+                         <StringToSign>AWS4-HMAC-SHA256</StringToSign>\
+                         <SignatureProvided>0123abcd</SignatureProvided></Error>",
+                        ACCESS_KEY_ID
+                    )))
+                }
+                "/bucket/flaky" => Response::builder()
+                    .status(500)
+                    .body(Body::from("<Error><Code>InternalError</Code></Error>")),
+                _ => Response::builder().body(Body::empty()),
+            };
+            response.unwrap()
+        }
+    });
+    (port, received)
+}
+
+fn config(port: u16, retry_config: RetryConfig) -> aws_sdk_s3::Config {
+    common::config_for(port)
+        .credentials_provider(Credentials::new(
+            ACCESS_KEY_ID,
+            "secret",
+            Some("session-token".to_string()),
+            None,
+            "test",
+        ))
+        .retry_config(retry_config)
+        .build()
+}
+
+/// The signature of an Authorization header.
+fn signature(authorization: &str) -> &str {
+    authorization.rsplit("Signature=").next().unwrap()
+}
+
+#[test]
+fn test_redact_error_body() {
+    assert_eq!(
+        "<Error><AWSAccessKeyId><redacted></AWSAccessKeyId><Code>X</Code>\
+         <SignatureProvided><redacted></SignatureProvided></Error>",
+        redact_error_body(
+            "<Error><AWSAccessKeyId>AKID</AWSAccessKeyId><Code>X</Code>\
+             <SignatureProvided>abcd</SignatureProvided></Error>"
+        )
+    );
+    assert_eq!("no elements", redact_error_body("no elements"));
+}
+
+#[tokio::test]
+async fn test_describes_rejected_signature_once() {
+    let (port, received) = mock_endpoint().await;
+    let debugger = SigningDebugger::capturing(DebugSigningMode::Failures);
+    let client = debug_signing_client(config(port, RetryConfig::disabled()), &debugger);
+
+    let err = client
+        .put_object()
+        .bucket("bucket")
+        .key("bad-signature")
+        .body(b"data".to_vec().into())
+        .send()
+        .await
+        .unwrap_err();
+    // The SDK still sees the error body.
+    assert!(
+        format!("{:?}", err).contains("SignatureDoesNotMatch"),
+        "{:?}",
+        err
+    );
+    client
+        .head_object()
+        .bucket("bucket")
+        .key("fine")
+        .send()
+        .await
+        .unwrap();
+
+    let blocks = debugger.blocks();
+    assert_eq!(1, blocks.len(), "{:?}", blocks);
+    let block = &blocks[0];
+    assert!(block.contains("method:           PUT"), "{}", block);
+    assert!(
+        block.contains("canonical URI:    /bucket/bad-signature"),
+        "{}",
+        block
+    );
+    assert!(block.contains("/us-east-1/s3/aws4_request"), "{}", block);
+    assert!(block.contains("x-amz-date"), "{}", block);
+    assert!(
+        block.contains("  x-amz-security-token: <redacted>"),
+        "{}",
+        block
+    );
+    assert!(block.contains("payload hash:"), "{}", block);
+    assert!(block.contains("SignatureDoesNotMatch"), "{}", block);
+    assert!(block.contains("<SignatureProvided><redacted></SignatureProvided>"));
+    assert!(!block.contains(ACCESS_KEY_ID), "{}", block);
+    assert!(!block.contains("session-token"), "{}", block);
+    let received = received.lock().unwrap();
+    assert!(!block.contains(signature(&received[0])), "{}", block);
+}
+
+#[tokio::test]
+async fn test_describes_each_failed_attempt() {
+    let (port, received) = mock_endpoint().await;
+    let debugger = SigningDebugger::capturing(DebugSigningMode::Failures);
+    let client = debug_signing_client(
+        config(port, RetryConfig::new().with_max_attempts(3)),
+        &debugger,
+    );
+
+    assert!(client
+        .get_object()
+        .bucket("bucket")
+        .key("flaky")
+        .send()
+        .await
+        .is_err());
+
+    let attempts = received.lock().unwrap().len();
+    assert_eq!(3, attempts);
+    let blocks = debugger.blocks();
+    assert_eq!(attempts, blocks.len(), "{:?}", blocks);
+    assert!(blocks.iter().all(|block| block.contains("InternalError")));
+}
+
+#[tokio::test]
+async fn test_all_describes_successes() {
+    let (port, _) = mock_endpoint().await;
+    let debugger = SigningDebugger::capturing(DebugSigningMode::All);
+    let client = debug_signing_client(config(port, RetryConfig::disabled()), &debugger);
+
+    client
+        .head_object()
+        .bucket("bucket")
+        .key("fine")
+        .send()
+        .await
+        .unwrap();
+
+    let blocks = debugger.blocks();
+    assert_eq!(1, blocks.len());
+    assert!(
+        blocks[0].contains("HEAD /bucket/fine -> 200"),
+        "{}",
+        blocks[0]
+    );
+    assert!(!blocks[0].contains("error body"));
+}
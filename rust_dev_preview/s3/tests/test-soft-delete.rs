@@ -0,0 +1,353 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::model::LifecycleRuleFilter;
+use aws_sdk_s3::Client;
+use chrono::{DateTime, Utc};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use s3_service::soft_delete::{
+    list_soft_deleted, purge_soft_deleted, put_soft_delete_lifecycle, soft_delete,
+    soft_delete_lifecycle_rule, soft_delete_restore, DELETED_AT_TAG, DELETED_TAG,
+    SOFT_DELETE_RULE_ID,
+};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const EXISTING_LIFECYCLE: &str = "<LifecycleConfiguration>\
+    <Rule><ID>other</ID><Filter><Prefix>logs/</Prefix></Filter><Status>Enabled</Status>\
+    <Expiration><Days>90</Days></Expiration></Rule>\
+    <Rule><ID>expire-soft-deleted</ID><Filter><Tag><Key>deleted</Key><Value>true</Value></Tag>\
+    </Filter><Status>Enabled</Status><Expiration><Days>1</Days></Expiration></Rule>\
+    </LifecycleConfiguration>";
+
+/// The objects of the mock bucket, by key, with their tags, and the
+/// requests changing the bucket.
+#[derive(Debug, Default)]
+struct State {
+    objects: BTreeMap<String, Vec<(String, String)>>,
+    lifecycle: Option<&'static str>,
+    put_lifecycle: Option<String>,
+    deleted: Vec<String>,
+}
+
+type Shared = Arc<Mutex<State>>;
+
+/// The text of each `<name>` element of `xml`, in order.
+fn elements(xml: &str, name: &str) -> Vec<String> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    xml.split(open.as_str())
+        .skip(1)
+        .filter_map(|rest| rest.split(close.as_str()).next())
+        .map(|text| text.to_string())
+        .collect()
+}
+
+fn error(status: StatusCode, code: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(format!(
+            "<Error><Code>{}</Code><Message>{}</Message></Error>",
+            code, code
+        )))
+        .unwrap()
+}
+
+fn handle(state: &Shared, method: &Method, path: &str, query: &str, body: &str) -> Response<Body> {
+    let mut state = state.lock().unwrap();
+    let key = path.trim_start_matches("/bucket").trim_start_matches('/');
+    let ok = |body: String| Response::builder().body(Body::from(body)).unwrap();
+    if key.is_empty() {
+        return if query.contains("list-type=2") {
+            let contents: String = state
+                .objects
+                .keys()
+                .map(|key| {
+                    format!(
+                        "<Contents><Key>{}</Key><LastModified>2022-03-01T12:00:00.000Z\
+                         </LastModified><ETag>\"etag\"</ETag><Size>{}</Size></Contents>",
+                        key,
+                        key.len()
+                    )
+                })
+                .collect();
+            ok(format!(
+                "<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                contents
+            ))
+        } else if query.contains("lifecycle") && *method == Method::GET {
+            match state.lifecycle {
+                Some(lifecycle) => ok(lifecycle.to_string()),
+                None => error(StatusCode::NOT_FOUND, "NoSuchLifecycleConfiguration"),
+            }
+        } else if query.contains("lifecycle") {
+            state.put_lifecycle = Some(body.to_string());
+            ok(String::new())
+        } else {
+            for key in elements(body, "Key") {
+                state.objects.remove(&key);
+                state.deleted.push(key);
+            }
+            ok("<DeleteResult></DeleteResult>".to_string())
+        };
+    }
+    let tags = match state.objects.get_mut(key) {
+        Some(tags) => tags,
+        None => return error(StatusCode::NOT_FOUND, "NoSuchKey"),
+    };
+    match *method {
+        Method::GET => ok(format!(
+            "<Tagging><TagSet>{}</TagSet></Tagging>",
+            tags.iter()
+                .map(|(key, value)| format!(
+                    "<Tag><Key>{}</Key><Value>{}</Value></Tag>",
+                    key, value
+                ))
+                .collect::<String>()
+        )),
+        Method::PUT => {
+            *tags = elements(body, "Key")
+                .into_iter()
+                .zip(elements(body, "Value"))
+                .collect();
+            ok(String::new())
+        }
+        _ => {
+            tags.clear();
+            Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .unwrap()
+        }
+    }
+}
+
+/// Starts a mock bucket holding `objects` and returns its client.
+fn mock_bucket(objects: &[(&str, &[(&str, &str)])]) -> (Client, Shared) {
+    let state = Shared::default();
+    state.lock().unwrap().objects = objects
+        .iter()
+        .map(|(key, tags)| {
+            (
+                key.to_string(),
+                tags.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            )
+        })
+        .collect();
+    let shared = state.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let shared = shared.clone();
+        async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let body = String::from_utf8_lossy(&body);
+            handle(&shared, &method, &path, &query, &body)
+        }
+    });
+
+    (common::client_for(port), state)
+}
+
+fn tags_of(state: &Shared, key: &str) -> BTreeMap<String, String> {
+    state.lock().unwrap().objects[key].iter().cloned().collect()
+}
+
+fn days_ago(days: i64) -> String {
+    (Utc::now() - chrono::Duration::days(days)).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+#[tokio::test]
+async fn soft_delete_keeps_other_tags() {
+    let (client, state) = mock_bucket(&[("report.csv", &[("project", "atlas")])]);
+
+    soft_delete(&client, "bucket", "report.csv").await.unwrap();
+
+    let tags = tags_of(&state, "report.csv");
+    assert_eq!(tags.len(), 3, "{:?}", tags);
+    assert_eq!(tags["project"], "atlas");
+    assert_eq!(tags[DELETED_TAG], "true");
+    let deleted_at: DateTime<Utc> = DateTime::parse_from_rfc3339(&tags[DELETED_AT_TAG])
+        .unwrap()
+        .into();
+    assert!((Utc::now() - deleted_at).num_seconds().abs() < 60);
+}
+
+#[tokio::test]
+async fn soft_delete_twice_keeps_the_first_time() {
+    let first = days_ago(3);
+    let (client, state) = mock_bucket(&[(
+        "report.csv",
+        &[(DELETED_TAG, "true"), (DELETED_AT_TAG, first.as_str())],
+    )]);
+
+    soft_delete(&client, "bucket", "report.csv").await.unwrap();
+
+    assert_eq!(tags_of(&state, "report.csv")[DELETED_AT_TAG], first);
+}
+
+#[tokio::test]
+async fn soft_delete_failures() {
+    let full: Vec<(String, String)> = (0..9)
+        .map(|i| (format!("tag{}", i), "x".to_string()))
+        .collect();
+    let full: Vec<(&str, &str)> = full.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let (client, state) = mock_bucket(&[("full", &full)]);
+
+    let err = soft_delete(&client, "bucket", "missing").await.unwrap_err();
+    assert!(err.to_string().contains("does not exist"), "{}", err);
+    let err = soft_delete(&client, "bucket", "full").await.unwrap_err();
+    assert!(err.to_string().contains("no room"), "{}", err);
+    assert_eq!(tags_of(&state, "full").len(), 9);
+}
+
+#[tokio::test]
+async fn restore_removes_the_deletion_tags() {
+    let deleted_at = days_ago(1);
+    let (client, state) = mock_bucket(&[
+        (
+            "tagged",
+            &[
+                ("project", "atlas"),
+                (DELETED_TAG, "true"),
+                (DELETED_AT_TAG, deleted_at.as_str()),
+            ],
+        ),
+        (
+            "bare",
+            &[(DELETED_TAG, "true"), (DELETED_AT_TAG, deleted_at.as_str())],
+        ),
+    ]);
+
+    soft_delete_restore(&client, "bucket", "tagged")
+        .await
+        .unwrap();
+    soft_delete_restore(&client, "bucket", "bare")
+        .await
+        .unwrap();
+
+    let tags = tags_of(&state, "tagged");
+    assert_eq!(tags.len(), 1, "{:?}", tags);
+    assert_eq!(tags["project"], "atlas");
+    assert!(tags_of(&state, "bare").is_empty());
+}
+
+#[tokio::test]
+async fn lists_the_soft_deleted_objects_under_the_prefix() {
+    let old = days_ago(10);
+    let (client, _) = mock_bucket(&[
+        (
+            "docs/b",
+            &[(DELETED_TAG, "true"), (DELETED_AT_TAG, old.as_str())],
+        ),
+        (
+            "docs/a",
+            &[(DELETED_TAG, "true"), (DELETED_AT_TAG, "yesterday")],
+        ),
+        ("docs/kept", &[(DELETED_TAG, "false")]),
+        ("docs/plain", &[]),
+        ("other/c", &[(DELETED_TAG, "true")]),
+    ]);
+
+    let deleted = list_soft_deleted(&client, "bucket", "docs/").await.unwrap();
+
+    let keys: Vec<&str> = deleted.iter().map(|object| object.key.as_str()).collect();
+    assert_eq!(keys, ["docs/a", "docs/b"]);
+    assert_eq!(deleted[0].deleted_at, None);
+    assert_eq!(
+        deleted[1]
+            .deleted_at
+            .unwrap()
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        old
+    );
+    assert_eq!(deleted[1].size, "docs/b".len() as u64);
+}
+
+#[tokio::test]
+async fn purge_deletes_objects_deleted_long_enough_ago() {
+    let (old, recent) = (days_ago(10), days_ago(1));
+    let (client, state) = mock_bucket(&[
+        (
+            "old",
+            &[(DELETED_TAG, "true"), (DELETED_AT_TAG, old.as_str())],
+        ),
+        (
+            "recent",
+            &[(DELETED_TAG, "true"), (DELETED_AT_TAG, recent.as_str())],
+        ),
+        ("undated", &[(DELETED_TAG, "true")]),
+        ("live", &[]),
+    ]);
+
+    let purged = purge_soft_deleted(&client, "bucket", "", Duration::from_secs(7 * 86400))
+        .await
+        .unwrap();
+
+    assert_eq!(purged, 1);
+    let state = state.lock().unwrap();
+    assert_eq!(state.deleted, ["old"]);
+    assert_eq!(state.objects.len(), 3);
+}
+
+#[test]
+fn lifecycle_rule_filters_on_the_tag() {
+    let rule = soft_delete_lifecycle_rule("", 30);
+    assert_eq!(rule.id(), Some(SOFT_DELETE_RULE_ID));
+    assert_eq!(rule.expiration().unwrap().days(), 30);
+    match rule.filter() {
+        Some(LifecycleRuleFilter::Tag(tag)) => {
+            assert_eq!(tag.key(), Some(DELETED_TAG));
+            assert_eq!(tag.value(), Some("true"));
+        }
+        filter => panic!("{:?}", filter),
+    }
+
+    let rule = soft_delete_lifecycle_rule("trash/", 30);
+    match rule.filter() {
+        Some(LifecycleRuleFilter::And(and)) => {
+            assert_eq!(and.prefix(), Some("trash/"));
+            assert_eq!(and.tags().unwrap().len(), 1);
+        }
+        filter => panic!("{:?}", filter),
+    }
+}
+
+#[tokio::test]
+async fn lifecycle_replaces_its_rule_and_keeps_the_others() {
+    let (client, state) = mock_bucket(&[]);
+    state.lock().unwrap().lifecycle = Some(EXISTING_LIFECYCLE);
+
+    put_soft_delete_lifecycle(&client, "bucket", "trash/", 30)
+        .await
+        .unwrap();
+
+    let body = state.lock().unwrap().put_lifecycle.clone().unwrap();
+    assert_eq!(elements(&body, "ID"), ["other", SOFT_DELETE_RULE_ID]);
+    assert_eq!(elements(&body, "Days"), ["90", "30"]);
+    assert!(body.contains("<Prefix>trash/</Prefix>"), "{}", body);
+    assert!(body.contains("<Key>deleted</Key>"), "{}", body);
+}
+
+#[tokio::test]
+async fn lifecycle_without_a_configuration() {
+    let (client, state) = mock_bucket(&[]);
+
+    put_soft_delete_lifecycle(&client, "bucket", "", 7)
+        .await
+        .unwrap();
+    let body = state.lock().unwrap().put_lifecycle.clone().unwrap();
+    assert_eq!(elements(&body, "ID"), [SOFT_DELETE_RULE_ID]);
+
+    assert!(put_soft_delete_lifecycle(&client, "bucket", "", 0)
+        .await
+        .is_err());
+}
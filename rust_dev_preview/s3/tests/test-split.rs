@@ -0,0 +1,114 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::split::{chunk_key, split_object};
+use std::sync::{Arc, Mutex};
+
+/// The path and body of each PutObject received by the mock.
+type Puts = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+/// Starts a server answering GetObject with `source`, sent in `frames`, and
+/// recording PutObject requests.
+async fn mock_s3(source: Vec<u8>, frames: Vec<usize>) -> (Client, Puts) {
+    let puts = Puts::default();
+    let log = puts.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let log = log.clone();
+        let source = source.clone();
+        let frames = frames.clone();
+        async move {
+            if req.method() == Method::GET {
+                let mut chunks = Vec::new();
+                let mut offset = 0;
+                for size in frames {
+                    chunks.push(source[offset..offset + size].to_vec());
+                    offset += size;
+                }
+                return Response::builder()
+                    .header("Content-Length", source.len())
+                    .body(Body::wrap_stream(futures::stream::iter(chunks)))
+                    .unwrap();
+            }
+            let path = req.uri().path().to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            log.lock().unwrap().push((path, body.to_vec()));
+            Ok(Response::builder()
+                .header("ETag", "\"chunk\"")
+                .body(Body::empty())
+                .unwrap())
+        }
+    });
+
+    (common::client_for(port), puts)
+}
+
+#[test]
+fn test_chunk_key() {
+    assert_eq!("shards/0", chunk_key("shards", 0));
+    assert_eq!("shards/12", chunk_key("shards/", 12));
+}
+
+#[tokio::test]
+async fn test_chunks_span_frames_and_last_is_smaller() {
+    let source: Vec<u8> = (0..25).collect();
+    let (client, puts) = mock_s3(source.clone(), vec![4, 9, 12]).await;
+
+    let keys = split_object(&client, "bucket", "big", "out", 10)
+        .await
+        .unwrap();
+
+    assert_eq!(vec!["out/0", "out/1", "out/2"], keys);
+    let puts = puts.lock().unwrap();
+    let paths: Vec<_> = puts.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(
+        vec!["/bucket/out/0", "/bucket/out/1", "/bucket/out/2"],
+        paths
+    );
+    assert_eq!(source[0..10], puts[0].1[..]);
+    assert_eq!(source[10..20], puts[1].1[..]);
+    assert_eq!(source[20..25], puts[2].1[..]);
+}
+
+#[tokio::test]
+async fn test_exact_multiple_has_no_empty_chunk() {
+    let (client, puts) = mock_s3(vec![7; 20], vec![20]).await;
+
+    let keys = split_object(&client, "bucket", "big", "out/", 10)
+        .await
+        .unwrap();
+
+    assert_eq!(vec!["out/0", "out/1"], keys);
+    assert!(puts
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|(_, body)| body.len() == 10));
+}
+
+#[tokio::test]
+async fn test_empty_source_has_no_chunks() {
+    let (client, puts) = mock_s3(Vec::new(), Vec::new()).await;
+
+    let keys = split_object(&client, "bucket", "empty", "out", 10)
+        .await
+        .unwrap();
+
+    assert!(keys.is_empty());
+    assert!(puts.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_zero_chunk_size_is_rejected() {
+    let (client, puts) = mock_s3(vec![1; 5], vec![5]).await;
+
+    assert!(split_object(&client, "bucket", "big", "out", 0)
+        .await
+        .is_err());
+    assert!(puts.lock().unwrap().is_empty());
+}
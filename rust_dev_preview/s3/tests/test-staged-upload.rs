@@ -0,0 +1,330 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::staged_upload::{
+    check_dense, check_stage_layout, complete_staged_upload, number_parts, start_staged_upload,
+    upload_parts_range, PartsRangeOptions, StageManifest,
+};
+use s3_service::upload::SourceWindow;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const PART_SIZE: u64 = 500;
+/// Each of the three stages uploads 1000 bytes, as two parts.
+const STAGE_SIZE: u64 = 1000;
+
+/// The parts uploaded, by part number, and the bodies of the completions.
+#[derive(Default)]
+struct Upload {
+    parts: BTreeMap<i32, usize>,
+    completions: Vec<String>,
+}
+
+/// Starts a server holding one multipart upload `upload` of `key`: it
+/// creates it, stores the size of each part uploaded, lists them, and
+/// records the completions.
+async fn mock_s3() -> (Client, Arc<Mutex<Upload>>) {
+    let upload = Arc::new(Mutex::new(Upload::default()));
+    let state = upload.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let state = state.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let mut upload = state.lock().unwrap();
+            let response = if method == Method::POST && query.starts_with("uploads") {
+                Response::builder().body(Body::from(
+                    "<InitiateMultipartUploadResult><Bucket>bucket</Bucket>\
+                     <Key>key</Key><UploadId>upload</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                ))
+            } else if method == Method::GET {
+                let parts: String = upload
+                    .parts
+                    .iter()
+                    .map(|(part_number, size)| {
+                        format!(
+                            "<Part><PartNumber>{}</PartNumber><ETag>\"etag-{}\"</ETag>\
+                             <Size>{}</Size></Part>",
+                            part_number, part_number, size
+                        )
+                    })
+                    .collect();
+                Response::builder().body(Body::from(format!(
+                    "<ListPartsResult><Bucket>bucket</Bucket><Key>key</Key>\
+                     <UploadId>upload</UploadId><IsTruncated>false</IsTruncated>{}\
+                     </ListPartsResult>",
+                    parts
+                )))
+            } else if method == Method::PUT {
+                let part_number: i32 = query
+                    .split('&')
+                    .find_map(|p| p.strip_prefix("partNumber="))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                upload.parts.insert(part_number, body.len());
+                Response::builder()
+                    .header("ETag", format!("\"etag-{}\"", part_number))
+                    .body(Body::empty())
+            } else {
+                upload
+                    .completions
+                    .push(String::from_utf8(body.to_vec()).unwrap());
+                Response::builder().body(Body::from(
+                    "<CompleteMultipartUploadResult><ETag>\"done-6\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                ))
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), upload)
+}
+
+fn write_file() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("staged-{}", uuid::Uuid::new_v4()));
+    let data: Vec<u8> = (0..3 * STAGE_SIZE).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// Uploads the bytes of stage `stage` (from 0) as parts `2 * stage + 1` and
+/// `2 * stage + 2`.
+async fn run_stage(
+    client: &Client,
+    path: &Path,
+    stage: u64,
+    collision_check: bool,
+) -> Result<StageManifest, aws_sdk_s3::Error> {
+    let options = PartsRangeOptions {
+        part_size: PART_SIZE,
+        part_number_offset: 2 * stage as i32,
+        collision_check,
+        min_part_size: PART_SIZE,
+    };
+    let window = SourceWindow {
+        offset: stage * STAGE_SIZE,
+        length: STAGE_SIZE,
+    };
+    upload_parts_range(
+        client,
+        "bucket",
+        "key",
+        "upload",
+        path.to_str().unwrap(),
+        window,
+        &options,
+    )
+    .await
+}
+
+fn part_numbers(manifest: &StageManifest) -> Vec<i32> {
+    manifest.parts.iter().map(|part| part.part_number).collect()
+}
+
+#[test]
+fn test_number_parts_from_offset() {
+    let window = SourceWindow {
+        offset: 100,
+        length: 1200,
+    };
+
+    let parts = number_parts(window, 500, 10).unwrap();
+
+    assert_eq!(vec![(11, 100, 500), (12, 600, 500), (13, 1100, 200)], parts);
+}
+
+#[test]
+fn test_number_parts_rejects_parts_past_cap() {
+    let window = SourceWindow {
+        offset: 0,
+        length: 1000,
+    };
+
+    assert_eq!(2, number_parts(window, 500, 9998).unwrap().len());
+    let err = number_parts(window, 500, 9999).unwrap_err().to_string();
+    assert!(err.contains("10000 to 10001"), "{}", err);
+    assert!(number_parts(window, 500, -1).is_err());
+}
+
+#[test]
+fn test_check_stage_layout() {
+    let window = |offset, length| SourceWindow { offset, length };
+
+    // Stages of whole parts, and a last stage with a short last part.
+    assert!(check_stage_layout(window(0, 1000), 2300, 500, 500).is_ok());
+    assert!(check_stage_layout(window(2000, 300), 2300, 500, 500).is_ok());
+    assert!(check_stage_layout(window(1000, 1300), 2300, 500, 500).is_ok());
+
+    // A stage before the end of the file would leave a short part behind.
+    let err = check_stage_layout(window(0, 1200), 2300, 500, 500)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("multiple of the part size 500"), "{}", err);
+
+    // Parts below the minimum, unless the stage is only the last part.
+    let err = check_stage_layout(window(0, 1000), 2300, 400, 500)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("The part size 400 is below"), "{}", err);
+    assert!(check_stage_layout(window(2000, 300), 2300, 400, 500).is_ok());
+}
+
+#[tokio::test]
+async fn test_short_stage_is_rejected_before_uploading() {
+    let (client, upload) = mock_s3().await;
+    let path = write_file();
+    let options = PartsRangeOptions {
+        part_size: PART_SIZE,
+        min_part_size: PART_SIZE,
+        ..Default::default()
+    };
+    let window = SourceWindow {
+        offset: 0,
+        length: STAGE_SIZE + 100,
+    };
+
+    let result = upload_parts_range(
+        &client,
+        "bucket",
+        "key",
+        "upload",
+        path.to_str().unwrap(),
+        window,
+        &options,
+    )
+    .await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(result.is_err());
+    assert!(upload.lock().unwrap().parts.is_empty());
+}
+
+#[test]
+fn test_check_dense() {
+    assert!(check_dense(&[1, 2, 3]).is_ok());
+    assert!(check_dense(&[]).is_ok());
+
+    let err = check_dense(&[2, 3, 6, 8]).unwrap_err().to_string();
+    assert!(err.contains("parts 1, 4 to 5, 7 are missing"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_three_stages_complete_with_all_parts() {
+    let (client, upload) = mock_s3().await;
+    let path = write_file();
+
+    assert_eq!(
+        "upload",
+        start_staged_upload(&client, "bucket", "key").await.unwrap()
+    );
+    let mut manifests = Vec::new();
+    for stage in 0..3 {
+        manifests.push(run_stage(&client, &path, stage, true).await.unwrap());
+    }
+    // The manifests of the stages may come in any order.
+    manifests.reverse();
+    let e_tag = complete_staged_upload(&client, &manifests).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(vec![5, 6], part_numbers(&manifests[0]));
+    assert_eq!(
+        (2000, 500),
+        (manifests[0].parts[0].offset, manifests[0].parts[0].size)
+    );
+    assert_eq!("done-6", e_tag);
+    let upload = upload.lock().unwrap();
+    assert_eq!(6, upload.parts.len());
+    assert!(upload
+        .parts
+        .values()
+        .all(|size| *size == PART_SIZE as usize));
+    assert_eq!(1, upload.completions.len());
+    let completion = &upload.completions[0];
+    let positions: Vec<usize> = (1..=6)
+        .map(|n| completion.find(&format!("etag-{}", n)).unwrap())
+        .collect();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", completion);
+}
+
+#[tokio::test]
+async fn test_gap_is_rejected_before_completing() {
+    let (client, upload) = mock_s3().await;
+    let path = write_file();
+
+    // The second stage did not run.
+    let first = run_stage(&client, &path, 0, true).await.unwrap();
+    let third = run_stage(&client, &path, 2, true).await.unwrap();
+    let err = complete_staged_upload(&client, &[first, third])
+        .await
+        .unwrap_err()
+        .to_string();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(err.contains("parts 3 to 4 are missing"), "{}", err);
+    assert!(upload.lock().unwrap().completions.is_empty());
+}
+
+#[tokio::test]
+async fn test_collision_with_listed_parts() {
+    let (client, upload) = mock_s3().await;
+    let path = write_file();
+    run_stage(&client, &path, 0, true).await.unwrap();
+
+    // The same stage again, over parts 1 and 2.
+    let err = run_stage(&client, &path, 0, true)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("Parts 1, 2 of upload upload"), "{}", err);
+
+    let again = run_stage(&client, &path, 0, false).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(vec![1, 2], part_numbers(&again));
+    assert_eq!(2, upload.lock().unwrap().parts.len());
+}
+
+#[tokio::test]
+async fn test_overlapping_manifests_are_rejected() {
+    let (client, upload) = mock_s3().await;
+    let path = write_file();
+    let first = run_stage(&client, &path, 0, true).await.unwrap();
+    let again = run_stage(&client, &path, 0, false).await.unwrap();
+
+    let err = complete_staged_upload(&client, &[first, again])
+        .await
+        .unwrap_err()
+        .to_string();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(err.contains("Part 1 is in more than one"), "{}", err);
+    assert!(upload.lock().unwrap().completions.is_empty());
+}
+
+#[tokio::test]
+async fn test_stage_manifest_round_trip() {
+    let path = std::env::temp_dir().join(format!("stage-{}.json", uuid::Uuid::new_v4()));
+    let manifest = StageManifest {
+        bucket: "bucket".to_string(),
+        key: "key".to_string(),
+        upload_id: "upload".to_string(),
+        parts: Vec::new(),
+    };
+
+    manifest.save(&path).await.unwrap();
+    let loaded = StageManifest::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(manifest, loaded);
+}
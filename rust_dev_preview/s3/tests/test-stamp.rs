@@ -0,0 +1,415 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use s3_service::batch::{ArchiveIndex, BatchOptions};
+use s3_service::stamp::{StampSampling, CONTENT_SHA256_METADATA};
+use s3_service::sync::{sync_directory, SyncOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// An object of the mock bucket: its body and its `x-amz-meta-` metadata.
+#[derive(Debug, Clone, Default)]
+struct Object {
+    body: Vec<u8>,
+    metadata: HashMap<String, String>,
+}
+
+/// The objects of the mock bucket, by key, and the keys put.
+#[derive(Debug, Default)]
+struct State {
+    objects: BTreeMap<String, Object>,
+    puts: Vec<String>,
+    heads: usize,
+}
+
+type Shared = Arc<Mutex<State>>;
+
+fn handle(state: &Shared, req: &Request<Body>, body: Vec<u8>) -> Response<Body> {
+    let mut state = state.lock().unwrap();
+    let key = req
+        .uri()
+        .path()
+        .trim_start_matches("/bucket")
+        .trim_start_matches('/')
+        .to_string();
+    if key.is_empty() {
+        // Listed in the future, so that no local file looks newer.
+        let contents: String = state
+            .objects
+            .iter()
+            .map(|(key, object)| {
+                format!(
+                    "<Contents><Key>{}</Key><LastModified>2100-01-01T00:00:00.000Z\
+                     </LastModified><ETag>\"etag\"</ETag><Size>{}</Size></Contents>",
+                    key,
+                    object.body.len()
+                )
+            })
+            .collect();
+        return Response::builder()
+            .body(Body::from(format!(
+                "<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                contents
+            )))
+            .unwrap();
+    }
+    if req.method() == Method::PUT {
+        let metadata = req
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = name.as_str().strip_prefix("x-amz-meta-")?;
+                Some((name.to_string(), value.to_str().unwrap().to_string()))
+            })
+            .collect();
+        state.objects.insert(key.clone(), Object { body, metadata });
+        state.puts.push(key);
+        return Response::builder()
+            .header("ETag", "\"etag\"")
+            .body(Body::empty())
+            .unwrap();
+    }
+    let object = match state.objects.get(&key) {
+        Some(object) => object.clone(),
+        None => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .unwrap()
+        }
+    };
+    let mut response = Response::builder()
+        .header("ETag", "\"etag\"")
+        .header("Content-Length", object.body.len());
+    for (name, value) in &object.metadata {
+        response = response.header(format!("x-amz-meta-{}", name).as_str(), value.as_str());
+    }
+    if req.method() == Method::HEAD {
+        state.heads += 1;
+        return response.body(Body::empty()).unwrap();
+    }
+    response.body(Body::from(object.body)).unwrap()
+}
+
+fn mock_bucket() -> (Client, Shared) {
+    let state = Shared::default();
+    let shared = state.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let shared = shared.clone();
+        async move {
+            let (parts, body) = req.into_parts();
+            let body = hyper::body::to_bytes(body).await.unwrap().to_vec();
+            let req = Request::from_parts(parts, Body::empty());
+            handle(&shared, &req, body)
+        }
+    });
+
+    (common::client_for(port), state)
+}
+
+/// A directory with three files of the same size.
+fn same_size_files() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("stamp-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, content) in [("a.txt", "alpha"), ("b.txt", "bravo"), ("c.txt", "charl")] {
+        std::fs::write(dir.join(name), content).unwrap();
+    }
+    dir
+}
+
+/// Swaps the objects at `a` and `b`, as a planner that mixed up their keys
+/// would have uploaded them.
+fn swap(state: &Shared, a: &str, b: &str) {
+    let mut state = state.lock().unwrap();
+    let object_a = state.objects[a].clone();
+    let object_b = state.objects.insert(b.to_string(), object_a).unwrap();
+    state.objects.insert(a.to_string(), object_b);
+}
+
+fn verifying(sampling: StampSampling) -> SyncOptions {
+    SyncOptions {
+        verify_stamps: Some(sampling),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn parses_the_sampling() {
+    assert_eq!(
+        StampSampling::parse("all").unwrap(),
+        StampSampling::default()
+    );
+    assert_eq!(
+        StampSampling::parse("sample=5%").unwrap(),
+        StampSampling {
+            fraction: 0.05,
+            seed: 0
+        }
+    );
+    assert_eq!(
+        StampSampling::parse("sample=12.5%,seed=7").unwrap(),
+        StampSampling {
+            fraction: 0.125,
+            seed: 7
+        }
+    );
+    for invalid in [
+        "some",
+        "sample=5",
+        "sample=0%",
+        "sample=101%",
+        "sample=x%",
+        "sample=5%,seed=x",
+        "sample=5%,step=2",
+    ] {
+        assert!(StampSampling::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn sampling_is_seeded() {
+    let keys: Vec<String> = (0..10_000).map(|i| format!("logs/{}.txt", i)).collect();
+    let sample = |sampling: StampSampling| -> Vec<&String> {
+        keys.iter().filter(|key| sampling.selects(key)).collect()
+    };
+    let five = StampSampling::parse("sample=5%").unwrap();
+    let first = sample(five);
+    assert!((400..600).contains(&first.len()), "{}", first.len());
+    assert_eq!(first, sample(five));
+    let other = sample(StampSampling::parse("sample=5%,seed=7").unwrap());
+    assert_ne!(first, other);
+    assert_eq!(sample(StampSampling::default()).len(), keys.len());
+}
+
+#[tokio::test]
+async fn stamps_each_uploaded_object() {
+    let dir = same_size_files();
+    let (client, state) = mock_bucket();
+    let options = SyncOptions {
+        stamp_content_hash: true,
+        ..Default::default()
+    };
+
+    sync_directory(&client, "bucket", &dir, "", &options, false)
+        .await
+        .unwrap();
+
+    let state = state.lock().unwrap();
+    assert_eq!(
+        state.objects["a.txt"].metadata[CONTENT_SHA256_METADATA],
+        // SHA-256 of "alpha".
+        "8ed3f6ad685b959ead7022518e1af76cd816f8e8ec7ccdda1ed4018e8f2223f8"
+    );
+    assert_eq!(state.objects.len(), 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn swapped_keys_are_reported_as_mixups() {
+    let dir = same_size_files();
+    let (client, state) = mock_bucket();
+    let stamping = SyncOptions {
+        stamp_content_hash: true,
+        ..Default::default()
+    };
+    sync_directory(&client, "bucket", &dir, "", &stamping, false)
+        .await
+        .unwrap();
+    swap(&state, "a.txt", "b.txt");
+    state
+        .lock()
+        .unwrap()
+        .objects
+        .get_mut("c.txt")
+        .unwrap()
+        .metadata
+        .insert(CONTENT_SHA256_METADATA.to_string(), "0".repeat(64));
+
+    let summary = sync_directory(
+        &client,
+        "bucket",
+        &dir,
+        "",
+        &verifying(StampSampling::default()),
+        true,
+    )
+    .await
+    .unwrap();
+
+    let stamps = summary.stamps.unwrap();
+    assert_eq!(stamps.checked, 3);
+    assert_eq!(stamps.matched, 0);
+    assert_eq!(
+        stamps.mixups,
+        [
+            ("a.txt".to_string(), "b.txt".to_string()),
+            ("b.txt".to_string(), "a.txt".to_string())
+        ]
+    );
+    assert_eq!(stamps.modified, ["c.txt"]);
+    let mut uploaded = summary.uploaded;
+    uploaded.sort();
+    assert_eq!(uploaded, ["a.txt", "b.txt", "c.txt"]);
+    assert!(summary.identical.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn mismatched_objects_are_uploaded_again() {
+    let dir = same_size_files();
+    let (client, state) = mock_bucket();
+    let stamping = SyncOptions {
+        stamp_content_hash: true,
+        ..Default::default()
+    };
+    sync_directory(&client, "bucket", &dir, "", &stamping, false)
+        .await
+        .unwrap();
+    swap(&state, "a.txt", "b.txt");
+    state.lock().unwrap().puts.clear();
+
+    let options = SyncOptions {
+        stamp_content_hash: true,
+        ..verifying(StampSampling::default())
+    };
+    let summary = sync_directory(&client, "bucket", &dir, "", &options, false)
+        .await
+        .unwrap();
+
+    assert_eq!(summary.stamps.unwrap().mixups.len(), 2);
+    assert_eq!(summary.identical, ["c.txt"]);
+    let state = state.lock().unwrap();
+    let mut puts = state.puts.clone();
+    puts.sort();
+    assert_eq!(puts, ["a.txt", "b.txt"]);
+    assert_eq!(state.objects["a.txt"].body, b"alpha");
+    assert_eq!(state.objects["b.txt"].body, b"bravo");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn unstamped_objects_are_left_alone() {
+    let dir = same_size_files();
+    let (client, _) = mock_bucket();
+    sync_directory(&client, "bucket", &dir, "", &SyncOptions::default(), false)
+        .await
+        .unwrap();
+
+    let summary = sync_directory(
+        &client,
+        "bucket",
+        &dir,
+        "",
+        &verifying(StampSampling::default()),
+        true,
+    )
+    .await
+    .unwrap();
+
+    let stamps = summary.stamps.unwrap();
+    assert_eq!(stamps.unstamped, ["a.txt", "b.txt", "c.txt"]);
+    assert!(summary.uploaded.is_empty());
+    assert_eq!(summary.identical.len(), 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn only_the_sample_is_checked() {
+    let dir = same_size_files();
+    let (client, state) = mock_bucket();
+    sync_directory(&client, "bucket", &dir, "", &SyncOptions::default(), false)
+        .await
+        .unwrap();
+    let sampling = StampSampling::parse("sample=50%,seed=3").unwrap();
+    let sampled = ["a.txt", "b.txt", "c.txt"]
+        .iter()
+        .filter(|key| sampling.selects(key))
+        .count();
+
+    let summary = sync_directory(&client, "bucket", &dir, "", &verifying(sampling), true)
+        .await
+        .unwrap();
+
+    let stamps = summary.stamps.unwrap();
+    assert_eq!(stamps.checked, sampled);
+    assert_eq!(stamps.sampled_out, 3 - sampled);
+    assert_eq!(state.lock().unwrap().heads, sampled);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn swapped_files_in_an_archive_are_reported_as_mixups() {
+    let dir = same_size_files();
+    let (client, state) = mock_bucket();
+    let batch = Some(BatchOptions {
+        threshold: 1024,
+        max_archive_size: 1024,
+        stamp_content_hash: false,
+    });
+    let stamping = SyncOptions {
+        batch,
+        stamp_content_hash: true,
+        ..Default::default()
+    };
+    let summary = sync_directory(&client, "bucket", &dir, "", &stamping, false)
+        .await
+        .unwrap();
+    assert_eq!(summary.archives, 1);
+
+    // An index recording each of a.txt and b.txt at the other's place.
+    {
+        let mut state = state.lock().unwrap();
+        let index_key = state
+            .objects
+            .keys()
+            .find(|key| key.ends_with(".index.json"))
+            .unwrap()
+            .clone();
+        let index = state.objects.get_mut(&index_key).unwrap();
+        let mut parsed: ArchiveIndex = serde_json::from_slice(&index.body).unwrap();
+        assert!(parsed.entries.iter().all(|entry| entry.sha256.is_some()));
+        let at = |key: &str| parsed.entries.iter().position(|e| e.key == key).unwrap();
+        let (a, b) = (at("a.txt"), at("b.txt"));
+        let (offset, sha256) = (parsed.entries[a].offset, parsed.entries[a].sha256.clone());
+        parsed.entries[a].offset = parsed.entries[b].offset;
+        parsed.entries[a].sha256 = parsed.entries[b].sha256.clone();
+        parsed.entries[b].offset = offset;
+        parsed.entries[b].sha256 = sha256;
+        index.body = serde_json::to_vec(&parsed).unwrap();
+        state.heads = 0;
+    }
+
+    let summary = sync_directory(
+        &client,
+        "bucket",
+        &dir,
+        "",
+        &SyncOptions {
+            batch,
+            ..verifying(StampSampling::default())
+        },
+        true,
+    )
+    .await
+    .unwrap();
+
+    let stamps = summary.stamps.unwrap();
+    assert_eq!(
+        stamps.mixups,
+        [
+            ("a.txt".to_string(), "b.txt".to_string()),
+            ("b.txt".to_string(), "a.txt".to_string())
+        ]
+    );
+    assert_eq!(stamps.matched, 1);
+    // The stamps of packed files are in the index.
+    assert_eq!(state.lock().unwrap().heads, 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,197 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::types::ByteStream;
+use hyper::{Body, Request, Response};
+use s3_service::sync::{
+    fetch_source_mtimes, format_mtime, parse_mtime, plan_sync, sync_directory, LocalFile,
+    RemoteObject, SyncOptions, SOURCE_MTIME_METADATA,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn local(key: &str, size: u64, mtime: u64) -> LocalFile {
+    LocalFile {
+        path: PathBuf::from(key),
+        key: key.to_string(),
+        size,
+        mtime: at(mtime),
+    }
+}
+
+fn stamped(key: &str, size: u64, source_mtime: u64) -> RemoteObject {
+    RemoteObject {
+        key: key.to_string(),
+        size,
+        // Uploaded well after the file was last modified.
+        last_modified: at(source_mtime + 60),
+        source_mtime: Some(at(source_mtime)),
+        e_tag: None,
+    }
+}
+
+#[test]
+fn test_plan_no_overwrite_newer() {
+    let remote = vec![
+        stamped("same", 10, 1000),
+        stamped("skewed", 10, 1001),
+        stamped("local-newer", 10, 1000),
+        stamped("remote-newer", 10, 2000),
+    ]
+    .into_iter()
+    .map(|o| (o.key.clone(), o))
+    .collect::<HashMap<_, _>>();
+    let files = || {
+        vec![
+            local("new", 10, 1000),
+            local("same", 10, 1000),
+            local("skewed", 10, 1000),
+            local("local-newer", 12, 1500),
+            local("remote-newer", 12, 1000),
+        ]
+    };
+
+    let options = SyncOptions {
+        no_overwrite_newer: true,
+        ..Default::default()
+    };
+    let plan = plan_sync(files(), &remote, &options);
+    let uploads = plan
+        .uploads
+        .iter()
+        .map(|(f, _)| f.key.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["new", "local-newer"], uploads);
+    assert_eq!(vec!["same", "skewed"], plan.identical);
+    assert_eq!(vec!["remote-newer"], plan.newer_remote);
+
+    // A window smaller than the skew treats the skewed object as newer.
+    let strict = SyncOptions {
+        mtime_window: Duration::from_millis(500),
+        ..options.clone()
+    };
+    let plan = plan_sync(files(), &remote, &strict);
+    assert_eq!(vec!["skewed", "remote-newer"], plan.newer_remote);
+
+    let forced = SyncOptions {
+        force: true,
+        ..options
+    };
+    let plan = plan_sync(files(), &remote, &forced);
+    assert!(plan.newer_remote.is_empty());
+    assert_eq!(3, plan.uploads.len());
+}
+
+#[test]
+fn test_parse_mtime() {
+    assert_eq!(Some(at(1000)), parse_mtime("1000"));
+    assert_eq!(Some(at(1000)), parse_mtime(&format_mtime(at(1000))));
+    for value in ["inf", "-inf", "NaN", "-1", "1e30", "", "yesterday"] {
+        assert_eq!(None, parse_mtime(value), "{}", value);
+    }
+}
+
+#[tokio::test]
+async fn test_fetch_source_mtimes_skips_deleted_objects() {
+    let port = common::mock_s3(|req: Request<Body>| async move {
+        let response = if req.uri().path().ends_with("/gone") {
+            Response::builder().status(404).body(Body::empty())
+        } else {
+            Response::builder()
+                .header(
+                    format!("x-amz-meta-{}", SOURCE_MTIME_METADATA),
+                    format_mtime(at(1000)),
+                )
+                .body(Body::empty())
+        };
+        response.unwrap()
+    });
+    let client = common::client_for(port);
+    let local: Vec<_> = (0..20)
+        .map(|n| local(&format!("file-{}", n), 10, 2000))
+        .chain(std::iter::once(local("gone", 10, 2000)))
+        .collect();
+    let mut remote: HashMap<String, RemoteObject> = local
+        .iter()
+        .map(|file| {
+            let mut object = stamped(&file.key, 10, 0);
+            object.source_mtime = None;
+            (file.key.clone(), object)
+        })
+        .collect();
+
+    fetch_source_mtimes(&client, "bucket", &local, &mut remote, 4)
+        .await
+        .unwrap();
+
+    for n in 0..20 {
+        assert_eq!(Some(at(1000)), remote[&format!("file-{}", n)].source_mtime);
+    }
+    assert_eq!(None, remote["gone"].source_mtime);
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_sync_skips_newer_remote() {
+    let client = common::minio_client().await;
+    let bucket = common::create_test_bucket(&client).await;
+    let dir = std::env::temp_dir().join(format!("sync-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"].iter() {
+        std::fs::write(dir.join(name), b"local content").unwrap();
+    }
+    let local_mtime = std::fs::metadata(dir.join("a.txt"))
+        .unwrap()
+        .modified()
+        .unwrap();
+
+    // b.txt was edited remotely after the local copy, c.txt before it.
+    let remote = [
+        ("sync/b.txt", local_mtime + Duration::from_secs(3600)),
+        ("sync/c.txt", local_mtime - Duration::from_secs(3600)),
+    ];
+    for (key, mtime) in remote.iter() {
+        client
+            .put_object()
+            .bucket(&bucket)
+            .key(*key)
+            .metadata(SOURCE_MTIME_METADATA, format_mtime(*mtime))
+            .body(ByteStream::from_static(b"remote"))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let options = SyncOptions {
+        no_overwrite_newer: true,
+        ..Default::default()
+    };
+    let summary = sync_directory(&client, &bucket, &dir, "sync/", &options, false)
+        .await
+        .unwrap();
+    let mut uploaded = summary.uploaded.clone();
+    uploaded.sort();
+    assert_eq!(vec!["sync/a.txt", "sync/c.txt"], uploaded);
+    assert_eq!(vec!["sync/b.txt"], summary.newer_remote);
+    assert!(summary.failed.is_empty());
+
+    // The uploads are stamped, so a second run finds them identical.
+    let summary = sync_directory(&client, &bucket, &dir, "sync/", &options, false)
+        .await
+        .unwrap();
+    assert!(summary.uploaded.is_empty());
+    assert_eq!(2, summary.identical.len());
+    assert_eq!(vec!["sync/b.txt"], summary.newer_remote);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    common::delete_test_bucket(&client, &bucket).await;
+}
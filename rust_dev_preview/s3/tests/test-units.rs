@@ -0,0 +1,88 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use s3_service::units::{
+    average_rate, format_duration, format_latency, format_live_rate, format_overall_rate,
+    format_rate, format_size, summary_line, KIB, MIB,
+};
+use std::time::Duration;
+
+#[test]
+fn test_format_size() {
+    assert_eq!("0 B", format_size(0));
+    assert_eq!("1023 B", format_size(1023));
+    assert_eq!("1.0 KiB", format_size(KIB));
+    assert_eq!("1.5 KiB", format_size(1536));
+    assert_eq!("8.0 MiB", format_size(8 * MIB));
+    assert_eq!("100.0 MiB", format_size(100 * MIB));
+    // Not 1024.0 KiB.
+    assert_eq!("1.0 MiB", format_size(MIB - 1));
+    assert_eq!("1.5 GiB", format_size(1536 * MIB));
+    assert_eq!("5.0 TiB", format_size(5 * 1024 * 1024 * MIB));
+    assert_eq!("16384.0 PiB", format_size(u64::MAX));
+}
+
+#[test]
+fn test_format_rate() {
+    assert_eq!("0.0 MiB/s", format_rate(0.0));
+    assert_eq!("0.0 MiB/s", format_rate(1000.0));
+    assert_eq!("0.5 MiB/s", format_rate(512.0 * KIB as f64));
+    assert_eq!("17.8 MiB/s", format_rate(17.8 * MIB as f64));
+    // Rates stay in MiB/s, however fast.
+    assert_eq!("2048.0 MiB/s", format_rate(2048.0 * MIB as f64));
+}
+
+#[test]
+fn test_rate_labels() {
+    assert_eq!(
+        "10.0 MiB/s (10s avg)",
+        format_live_rate(Some(10.0 * MIB as f64))
+    );
+    assert_eq!("-- MiB/s (10s avg)", format_live_rate(None));
+    assert_eq!(
+        "2.5 MiB/s (overall avg)",
+        format_overall_rate(Some(2.5 * MIB as f64))
+    );
+    assert_eq!("-- MiB/s (overall avg)", format_overall_rate(None));
+    assert_eq!(None, average_rate(MIB, Duration::ZERO));
+    assert_eq!(
+        Some(MIB as f64 / 2.0),
+        average_rate(MIB, Duration::from_secs(2))
+    );
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!("0.0s", format_duration(Duration::ZERO));
+    assert_eq!("0.4s", format_duration(Duration::from_millis(400)));
+    assert_eq!("23.4s", format_duration(Duration::from_millis(23_400)));
+    // Rounded up into the next minute, not 60.0s.
+    assert_eq!("1m 0.0s", format_duration(Duration::from_millis(59_960)));
+    assert_eq!("1m 23.4s", format_duration(Duration::from_millis(83_400)));
+    assert_eq!(
+        "59m 59.9s",
+        format_duration(Duration::from_millis(3_599_900))
+    );
+    assert_eq!("2h 5m 0.0s", format_duration(Duration::from_secs(7500)));
+}
+
+#[test]
+fn test_format_latency() {
+    assert_eq!("0.0 ms", format_latency(Duration::ZERO));
+    assert_eq!("12.3 ms", format_latency(Duration::from_micros(12_340)));
+    assert_eq!("1500.0 ms", format_latency(Duration::from_millis(1500)));
+}
+
+#[test]
+fn test_summary_line() {
+    assert_eq!(
+        "Uploaded 1.5 GiB in 1m 23.4s, 18.4 MiB/s (overall avg)",
+        summary_line("Uploaded", 1536 * MIB, Duration::from_millis(83_400))
+    );
+    assert_eq!(
+        "Downloaded 0 B in 0.0s, -- MiB/s (overall avg)",
+        summary_line("Downloaded", 0, Duration::ZERO)
+    );
+}
@@ -0,0 +1,155 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::upload::{upload_auto, AutoUploadConfig, AUTO_MULTIPART_THRESHOLD, MIN_PART_SIZE};
+use std::sync::{Arc, Mutex};
+
+/// The requests received: `put`, `create`, `part`, or `complete`, with the
+/// size of the body.
+type Captured = Arc<Mutex<Vec<(&'static str, usize)>>>;
+
+/// Starts a server answering PutObject and multipart uploads.
+fn mock_server() -> (Client, Captured) {
+    let captured = Captured::default();
+    let recorder = captured.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let (request, response) = if method == Method::PUT {
+                let request = if query.contains("partNumber=") {
+                    "part"
+                } else {
+                    "put"
+                };
+                (request, "")
+            } else if query.contains("uploads") {
+                (
+                    "create",
+                    "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                )
+            } else {
+                (
+                    "complete",
+                    "<CompleteMultipartUploadResult><ETag>\"complete-etag\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                )
+            };
+            recorder.lock().unwrap().push((request, body.len()));
+            Response::builder()
+                .header("ETag", "\"put-etag\"")
+                .body(Body::from(response))
+                .unwrap()
+        }
+    });
+
+    (common::client_for(port), captured)
+}
+
+fn test_file(size: u64) -> String {
+    let path = std::env::temp_dir().join(format!("auto-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![b'x'; size as usize]).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn requests(captured: &Captured) -> Vec<&'static str> {
+    captured.lock().unwrap().iter().map(|(r, _)| *r).collect()
+}
+
+#[test]
+fn test_default_config() {
+    let config = AutoUploadConfig::default();
+    assert_eq!(100 * 1024 * 1024, AUTO_MULTIPART_THRESHOLD);
+    assert_eq!(AUTO_MULTIPART_THRESHOLD, config.multipart_threshold);
+    assert!(config.part_size >= MIN_PART_SIZE);
+    assert!(config.concurrency > 0);
+}
+
+#[tokio::test]
+async fn test_small_file_is_put() {
+    let (client, captured) = mock_server();
+    let file = test_file(1000);
+
+    let e_tag = upload_auto(&client, "bucket", "key", &file, AutoUploadConfig::default())
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!("put-etag", e_tag);
+    assert_eq!(vec![("put", 1000)], *captured.lock().unwrap());
+}
+
+#[tokio::test]
+async fn test_file_at_threshold_is_uploaded_in_parts() {
+    let (client, captured) = mock_server();
+    let size = 2 * MIN_PART_SIZE + 1000;
+    let file = test_file(size);
+    let config = AutoUploadConfig {
+        multipart_threshold: size,
+        part_size: MIN_PART_SIZE,
+        concurrency: 2,
+    };
+
+    let e_tag = upload_auto(&client, "bucket", "key", &file, config)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!("complete-etag", e_tag);
+    assert_eq!(
+        vec!["create", "part", "part", "complete"],
+        requests(&captured)
+    );
+    let sent: usize = captured
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(request, _)| *request == "part")
+        .map(|(_, len)| len)
+        .sum();
+    assert_eq!(size as usize, sent);
+}
+
+#[tokio::test]
+async fn test_file_below_threshold_is_put() {
+    let (client, captured) = mock_server();
+    let size = 2 * MIN_PART_SIZE + 1000;
+    let file = test_file(size);
+    let config = AutoUploadConfig {
+        multipart_threshold: size + 1,
+        ..Default::default()
+    };
+
+    upload_auto(&client, "bucket", "key", &file, config)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!(vec!["put"], requests(&captured));
+}
+
+#[tokio::test]
+async fn test_missing_file_is_an_error() {
+    let (client, captured) = mock_server();
+    let missing = std::env::temp_dir().join(format!("auto-{}", uuid::Uuid::new_v4()));
+
+    assert!(upload_auto(
+        &client,
+        "bucket",
+        "key",
+        missing.to_str().unwrap(),
+        AutoUploadConfig::default()
+    )
+    .await
+    .is_err());
+    assert!(captured.lock().unwrap().is_empty());
+}
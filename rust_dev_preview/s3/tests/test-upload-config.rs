@@ -0,0 +1,145 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use s3_service::runtime::RuntimeFlavor;
+use s3_service::upload_config::{write_default_config, UploadConfig, DEFAULT_CONFIG_TEMPLATE};
+
+const CONFIG: &str = r#"
+profile = "default"
+url = "https://s3.us-east-1.amazonaws.com"
+bucket = "bucket"
+key = "backups/data.bin"
+file_name = "data.bin"
+num_parts = 8
+num_threads = 4
+runtime = "current"
+concurrency = 2
+no_warm_up = true
+sse = "aws:kms"
+sse_kms_key_id = "key-1"
+notify_sns_topic_arn = "arn:aws:sns:us-east-1:111122223333:uploads"
+
+[retry]
+max_attempts = 6
+base_delay_ms = 250
+
+[tags]
+team = "storage"
+project = "a b&c"
+"#;
+
+#[test]
+fn test_parses_every_section() {
+    let config = UploadConfig::from_toml(CONFIG).unwrap();
+
+    assert!(config.missing().is_empty());
+    assert_eq!(Some(8), config.num_parts);
+    assert_eq!(Some(RuntimeFlavor::CurrentThread), config.runtime);
+    assert_eq!(Some(2), config.concurrency);
+    assert_eq!(Some(true), config.no_warm_up);
+    let policy = config.retry_policy();
+    assert_eq!(6, policy.max_attempts);
+    assert_eq!(250, policy.base_delay_ms);
+    let headers = config.headers().unwrap();
+    assert_eq!(Some("aws:kms"), headers.server_side_encryption.as_deref());
+    assert_eq!(Some("key-1"), headers.ssekms_key_id.as_deref());
+    assert_eq!(
+        Some("project=a%20b%26c&team=storage"),
+        headers.tagging.as_deref()
+    );
+}
+
+#[test]
+fn test_command_line_wins_over_the_file() {
+    let file = UploadConfig::from_toml(CONFIG).unwrap();
+    let mut cli = UploadConfig {
+        num_parts: Some(16),
+        verbose: Some(true),
+        ..Default::default()
+    };
+    cli.retry.max_attempts = Some(2);
+    cli.tags.insert("team".into(), "ops".into());
+
+    let config = cli.or(file);
+
+    assert_eq!(Some(16), config.num_parts);
+    assert_eq!(Some(4), config.num_threads);
+    assert_eq!(Some(true), config.verbose);
+    assert_eq!(Some(2), config.retry.max_attempts);
+    assert_eq!(Some(250), config.retry.base_delay_ms);
+    assert_eq!(Some("ops"), config.tags.get("team").map(String::as_str));
+    assert_eq!(
+        Some("a b&c"),
+        config.tags.get("project").map(String::as_str)
+    );
+}
+
+#[test]
+fn test_invalid_settings_are_named() {
+    let cases = [
+        ("num_parts = 0", "num_parts"),
+        ("num_parts = 10001", "num_parts"),
+        ("url = \"not a url\"", "url"),
+        ("sse = \"DES\"", "AES256 or aws:kms"),
+        (
+            "sse = \"AES256\"\nsse_kms_key_id = \"key-1\"",
+            "sse_kms_key_id",
+        ),
+        ("[retry]\nmax_attempts = 0", "max_attempts"),
+        ("num_part = 8", "num_part"),
+    ];
+    for (content, expected) in cases.iter() {
+        let err = UploadConfig::from_toml(content).unwrap_err();
+        assert!(err.contains(expected), "{}: {}", content, err);
+    }
+}
+
+#[test]
+fn test_missing_settings_are_listed() {
+    let config = UploadConfig::from_toml("bucket = \"bucket\"\nnum_parts = 2").unwrap();
+
+    assert_eq!(
+        vec!["profile", "url", "key", "file_name", "num_threads"],
+        config.missing()
+    );
+    assert!(config.headers().is_none());
+}
+
+#[test]
+fn test_template_parses_commented_and_uncommented() {
+    let config = UploadConfig::from_toml(DEFAULT_CONFIG_TEMPLATE).unwrap();
+    assert_eq!(7, config.missing().len());
+
+    // Every setting line starts with `#` and no space; the descriptions
+    // start with `# `. Buffer capacity and auto buffer exclude each other.
+    let uncommented: String = DEFAULT_CONFIG_TEMPLATE
+        .lines()
+        .filter(|line| !line.starts_with("#auto_buffer"))
+        .map(|line| match line.strip_prefix('#') {
+            Some(setting) if !setting.starts_with(' ') && !setting.is_empty() => setting,
+            _ => line,
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let config = UploadConfig::from_toml(&uncommented).unwrap();
+    assert!(config.missing().is_empty());
+    assert_eq!(2, config.tags.len());
+    assert_eq!(Some(4), config.retry.max_attempts);
+}
+
+#[test]
+fn test_write_default_config_does_not_overwrite() {
+    let path = std::env::temp_dir().join(format!("upload-config-{}.toml", uuid::Uuid::new_v4()));
+
+    write_default_config(&path).unwrap();
+    assert_eq!(
+        DEFAULT_CONFIG_TEMPLATE,
+        std::fs::read_to_string(&path).unwrap()
+    );
+    std::fs::write(&path, "bucket = \"mine\"").unwrap();
+    assert!(write_default_config(&path).is_err());
+    assert_eq!("bucket = \"mine\"", std::fs::read_to_string(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
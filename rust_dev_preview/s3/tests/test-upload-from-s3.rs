@@ -0,0 +1,217 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::copy_prefix::CopyPrefixOptions;
+use s3_service::upload_from_s3::{upload_from_s3, upload_from_s3_with_path, CopyPath};
+use std::sync::{Arc, Mutex};
+
+const OBJECT: &[u8] = b"the bytes of the source object";
+
+/// The size the source reports to HeadObject, over the 5 GiB of a copy.
+const LARGE_SIZE: u64 = 6 * 1024 * 1024 * 1024;
+
+/// The message of S3 refusing to copy a source over 5 GiB.
+const TOO_LARGE: &str = "The specified copy source is larger than the maximum allowable size \
+                         for a copy source: 5368709120";
+
+/// The operation of each request received, and the bodies and headers of
+/// the PutObject and CreateMultipartUpload requests.
+#[derive(Default)]
+struct Received {
+    requests: Vec<String>,
+    puts: Vec<Vec<u8>>,
+    /// The Content-Type and `x-amz-meta-owner` of each new object.
+    headers: Vec<(Option<String>, Option<String>)>,
+}
+
+/// Serves `OBJECT` to GetObject, with a Content-Type and metadata, accepts
+/// PutObject and multipart copies, and answers CopyObject with
+/// `copy_error`, a code and a message, when given: as a 403 for
+/// AccessDenied and a 400 or 404 otherwise.
+async fn mock_s3(
+    copy_error: Option<(&'static str, &'static str)>,
+) -> (Client, Arc<Mutex<Received>>) {
+    let received = Arc::new(Mutex::new(Received::default()));
+    let state = received.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let state = state.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or_default().to_string();
+            let copy = req.headers().contains_key("x-amz-copy-source");
+            let header = |name: &str| {
+                req.headers()
+                    .get(name)
+                    .map(|value| value.to_str().unwrap().to_string())
+            };
+            let headers = (header("content-type"), header("x-amz-meta-owner"));
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let mut received = state.lock().unwrap();
+            let response = if copy && query.contains("partNumber") {
+                received.requests.push("UPLOAD_PART_COPY".to_string());
+                Response::builder().body(Body::from(
+                    "<CopyPartResult><ETag>\"part\"</ETag></CopyPartResult>",
+                ))
+            } else if copy {
+                received.requests.push("COPY".to_string());
+                match copy_error {
+                    Some((code, message)) => Response::builder()
+                        .status(match code {
+                            "AccessDenied" => 403,
+                            "InvalidRequest" => 400,
+                            _ => 404,
+                        })
+                        .body(Body::from(format!(
+                            "<Error><Code>{}</Code><Message>{}</Message></Error>",
+                            code, message
+                        ))),
+                    None => Response::builder().body(Body::from(
+                        "<CopyObjectResult><ETag>\"copied\"</ETag></CopyObjectResult>",
+                    )),
+                }
+            } else if method == Method::HEAD {
+                received.requests.push("HEAD".to_string());
+                Response::builder()
+                    .header("Content-Length", LARGE_SIZE)
+                    .header("Content-Type", "text/csv")
+                    .body(Body::empty())
+            } else if method == Method::GET && query.contains("tagging") {
+                received.requests.push("GET_TAGGING".to_string());
+                Response::builder().body(Body::from("<Tagging><TagSet></TagSet></Tagging>"))
+            } else if method == Method::GET {
+                received.requests.push("GET".to_string());
+                Response::builder()
+                    .header("Content-Length", OBJECT.len())
+                    .header("Content-Type", "text/csv")
+                    .header("x-amz-meta-owner", "ingest")
+                    .body(Body::from(OBJECT))
+            } else if method == Method::POST && query.contains("uploads") {
+                received.requests.push("CREATE".to_string());
+                received.headers.push(headers);
+                Response::builder().body(Body::from(
+                    "<InitiateMultipartUploadResult><UploadId>upload</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                ))
+            } else if method == Method::POST {
+                received.requests.push("COMPLETE".to_string());
+                Response::builder().body(Body::from(
+                    "<CompleteMultipartUploadResult><ETag>\"multipart\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                ))
+            } else {
+                received.requests.push(method.to_string());
+                received.puts.push(body.to_vec());
+                received.headers.push(headers);
+                Response::builder()
+                    .header("ETag", "\"streamed\"")
+                    .body(Body::empty())
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), received)
+}
+
+#[tokio::test]
+async fn test_copies_server_side_when_accepted() {
+    let (source, source_received) = mock_s3(None).await;
+    let (destination, destination_received) = mock_s3(None).await;
+
+    let e_tag = upload_from_s3(&source, "src", "key", &destination, "dst", "copy")
+        .await
+        .unwrap();
+
+    assert_eq!("copied", e_tag);
+    assert_eq!(vec!["COPY"], destination_received.lock().unwrap().requests);
+    assert!(source_received.lock().unwrap().requests.is_empty());
+}
+
+#[tokio::test]
+async fn test_streams_when_the_copy_is_denied() {
+    let (source, source_received) = mock_s3(None).await;
+    let (destination, destination_received) = mock_s3(Some(("AccessDenied", "refused"))).await;
+
+    let upload = upload_from_s3_with_path(&source, "src", "key", &destination, "dst", "copy")
+        .await
+        .unwrap();
+
+    assert_eq!("streamed", upload.e_tag);
+    assert_eq!(
+        CopyPath::Streamed {
+            code: "AccessDenied".to_string()
+        },
+        upload.path
+    );
+    assert_eq!(vec!["GET"], source_received.lock().unwrap().requests);
+    let destination_received = destination_received.lock().unwrap();
+    assert_eq!(vec!["COPY", "PUT"], destination_received.requests);
+    assert_eq!(vec![OBJECT.to_vec()], destination_received.puts);
+    assert_eq!(
+        vec![(Some("text/csv".to_string()), Some("ingest".to_string()))],
+        destination_received.headers
+    );
+}
+
+#[tokio::test]
+async fn test_copies_a_large_object_in_parts() {
+    let (source, source_received) = mock_s3(None).await;
+    let (destination, destination_received) = mock_s3(Some(("InvalidRequest", TOO_LARGE))).await;
+
+    let upload = upload_from_s3_with_path(&source, "src", "key", &destination, "dst", "copy")
+        .await
+        .unwrap();
+
+    assert_eq!("multipart", upload.e_tag);
+    assert_eq!(CopyPath::ServerSideMultipart, upload.path);
+    assert!(source_received.lock().unwrap().requests.is_empty());
+    let destination_received = destination_received.lock().unwrap();
+    let requests = &destination_received.requests;
+    assert_eq!("COPY", requests[0]);
+    assert_eq!(Some("COMPLETE"), requests.last().map(|r| r.as_str()));
+    let parts = LARGE_SIZE / CopyPrefixOptions::default().part_size;
+    assert_eq!(
+        parts as usize,
+        requests.iter().filter(|r| *r == "UPLOAD_PART_COPY").count()
+    );
+    assert!(!requests.contains(&"GET".to_string()));
+    assert!(!requests.contains(&"PUT".to_string()));
+    assert_eq!(
+        vec![(Some("text/csv".to_string()), None)],
+        destination_received.headers
+    );
+}
+
+#[tokio::test]
+async fn test_other_invalid_requests_are_returned() {
+    let (source, source_received) = mock_s3(None).await;
+    let (destination, _) = mock_s3(Some(("InvalidRequest", "The copy source is invalid"))).await;
+
+    let err = upload_from_s3(&source, "src", "key", &destination, "dst", "copy")
+        .await
+        .unwrap_err();
+    let err = format!("{:?}", err);
+
+    assert!(err.contains("InvalidRequest"), "{}", err);
+    assert!(source_received.lock().unwrap().requests.is_empty());
+}
+
+#[tokio::test]
+async fn test_other_copy_errors_are_returned() {
+    let (source, source_received) = mock_s3(None).await;
+    let (destination, _) = mock_s3(Some(("NoSuchKey", "refused"))).await;
+
+    let err = upload_from_s3(&source, "src", "key", &destination, "dst", "copy")
+        .await
+        .unwrap_err();
+    let err = format!("{:?}", err);
+
+    assert!(err.contains("NoSuchKey"), "{}", err);
+    assert!(source_received.lock().unwrap().requests.is_empty());
+}
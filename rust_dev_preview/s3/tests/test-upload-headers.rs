@@ -0,0 +1,387 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::{Client, RetryConfig};
+use hyper::{Body, HeaderMap, Method, Request, Response};
+use s3_service::failover::EndpointPool;
+use s3_service::upload::{
+    parse_expires, upload_chunk, upload_chunk_with_endpoints, upload_multipart,
+    upload_multipart_parallel_with_options, upload_multipart_window, ParallelUploadOptions,
+    SourceWindow, UploadHeaders, MAX_PUT_OBJECT_SIZE,
+};
+use std::sync::{Arc, Mutex};
+
+fn headers() -> UploadHeaders {
+    UploadHeaders {
+        cache_control: Some("max-age=3600".into()),
+        content_disposition: Some("attachment; filename=\"report.bin\"".into()),
+        ..Default::default()
+    }
+}
+
+fn all_headers() -> UploadHeaders {
+    UploadHeaders {
+        content_type: Some("text/css".into()),
+        cache_control: Some("public, max-age=31536000, immutable".into()),
+        content_encoding: Some("gzip".into()),
+        content_disposition: Some("inline".into()),
+        content_language: Some("en-US".into()),
+        expires: Some(parse_expires("2030-01-31T00:00:00Z").unwrap()),
+        ..Default::default()
+    }
+}
+
+const EXPECTED: [(&str, &str); 6] = [
+    ("content-type", "text/css"),
+    ("cache-control", "public, max-age=31536000, immutable"),
+    ("content-encoding", "gzip"),
+    ("content-disposition", "inline"),
+    ("content-language", "en-US"),
+    ("expires", "Thu, 31 Jan 2030 00:00:00 GMT"),
+];
+
+type Captured = Arc<Mutex<Vec<(Method, String, HeaderMap)>>>;
+
+/// Starts a server that answers like S3 to PutObject and multipart upload
+/// requests and records the method, query, and headers of each one.
+async fn capture_server() -> (Client, Captured) {
+    let captured = Captured::default();
+    let recorder = captured.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let recorder = recorder.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            recorder
+                .lock()
+                .unwrap()
+                .push((method.clone(), query.clone(), req.headers().clone()));
+            hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let body = if method == Method::POST && query.split('&').any(|p| p == "uploads") {
+                "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                 </InitiateMultipartUploadResult>"
+            } else if method == Method::POST {
+                "<CompleteMultipartUploadResult><ETag>\"etag\"</ETag>\
+                 </CompleteMultipartUploadResult>"
+            } else {
+                ""
+            };
+            Response::builder()
+                .header("ETag", "\"etag\"")
+                .body(Body::from(body))
+                .unwrap()
+        }
+    });
+
+    let conf = common::config_for(port)
+        .retry_config(RetryConfig::new())
+        .build();
+    (Client::from_conf(conf), captured)
+}
+
+fn test_file(size: usize) -> String {
+    let path = std::env::temp_dir().join(format!("headers-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![b'x'; size]).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+/// A file without an extension holding `prefix`, then `size` bytes.
+fn untyped_file(prefix: &[u8], size: usize) -> String {
+    let path = std::env::temp_dir().join(format!("untyped-{}", uuid::Uuid::new_v4()));
+    let mut content = prefix.to_vec();
+    content.resize(prefix.len() + size, 0);
+    std::fs::write(&path, content).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn content_type(headers: &HeaderMap) -> Option<&str> {
+    headers.get("content-type").and_then(|v| v.to_str().ok())
+}
+
+fn assert_has_headers(headers: &HeaderMap) {
+    for (name, value) in EXPECTED.iter() {
+        assert_eq!(
+            Some(*value),
+            headers.get(*name).and_then(|v| v.to_str().ok()),
+            "{}",
+            name
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_put_object_sends_headers() {
+    let (client, captured) = capture_server().await;
+    let file = test_file(1024);
+
+    upload_chunk(
+        &client,
+        "bucket",
+        "key",
+        &file,
+        0,
+        1024,
+        Some(all_headers()),
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    let captured = captured.lock().unwrap();
+    assert_eq!(1, captured.len());
+    assert_eq!(Method::PUT, captured[0].0);
+    assert_has_headers(&captured[0].2);
+}
+
+#[tokio::test]
+async fn test_multipart_sends_headers_on_create_only() {
+    let (client, captured) = capture_server().await;
+    let file = test_file(3000);
+
+    upload_multipart(
+        &client,
+        "bucket",
+        "key",
+        &file,
+        3,
+        None,
+        Some(all_headers()),
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    let captured = captured.lock().unwrap();
+    // Create, three parts, complete.
+    assert_eq!(5, captured.len());
+    let (method, query, create) = &captured[0];
+    assert_eq!(
+        (&Method::POST, true),
+        (method, query.split('&').any(|p| p == "uploads"))
+    );
+    assert_has_headers(create);
+    for (method, query, headers) in &captured[1..] {
+        if *method == Method::PUT {
+            assert!(query.contains("partNumber="));
+        }
+        // Part and completion requests carry none of the object headers;
+        // they would be ignored or rejected.
+        for (name, _) in EXPECTED.iter().skip(1) {
+            assert!(
+                headers.get(*name).is_none(),
+                "{} on {} {}",
+                name,
+                method,
+                query
+            );
+        }
+    }
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_parallel_upload_sends_encryption_and_tags_on_create() {
+    let (client, captured) = capture_server().await;
+    let file = test_file(3000);
+    let options = ParallelUploadOptions {
+        headers: Some(UploadHeaders {
+            server_side_encryption: Some("aws:kms".into()),
+            ssekms_key_id: Some("key-1".into()),
+            tagging: Some("team=storage&project=a%20b".into()),
+            ..Default::default()
+        }),
+        max_concurrent_parts: Some(1),
+        ..Default::default()
+    };
+
+    upload_multipart_parallel_with_options(&client, "bucket", "key", &file, 3, &options)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    let captured = captured.lock().unwrap();
+    assert_eq!(5, captured.len());
+    let create = &captured[0].2;
+    let header = |headers: &HeaderMap, name: &str| {
+        headers
+            .get(name)
+            .map(|value| value.to_str().unwrap().to_string())
+    };
+    assert_eq!(
+        Some("aws:kms".to_string()),
+        header(create, "x-amz-server-side-encryption")
+    );
+    assert_eq!(
+        Some("key-1".to_string()),
+        header(create, "x-amz-server-side-encryption-aws-kms-key-id")
+    );
+    assert_eq!(
+        Some("team=storage&project=a%20b".to_string()),
+        header(create, "x-amz-tagging")
+    );
+    for (_, _, headers) in &captured[1..] {
+        assert_eq!(None, header(headers, "x-amz-tagging"));
+    }
+}
+
+#[tokio::test]
+async fn test_upload_headers_are_stored() {
+    let client = common::minio_client().await;
+    let bucket = common::create_test_bucket(&client).await;
+    let path = std::env::temp_dir().join(format!("headers-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, vec![b'x'; 11 * 1024 * 1024]).unwrap();
+    let file_name = path.to_str().unwrap();
+
+    upload_chunk(
+        &client,
+        &bucket,
+        "chunk",
+        file_name,
+        0,
+        1024,
+        Some(headers()),
+    )
+    .await
+    .unwrap();
+    // Two parts: the headers must be applied when the upload is created.
+    upload_multipart(
+        &client,
+        &bucket,
+        "multipart",
+        file_name,
+        2,
+        None,
+        Some(headers()),
+    )
+    .await
+    .unwrap();
+
+    for key in ["chunk", "multipart"] {
+        let head = client
+            .head_object()
+            .bucket(&bucket)
+            .key(key)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(Some("max-age=3600"), head.cache_control());
+        assert_eq!(
+            Some("attachment; filename=\"report.bin\""),
+            head.content_disposition()
+        );
+    }
+
+    std::fs::remove_file(path).unwrap();
+    common::delete_test_bucket(&client, &bucket).await;
+}
+
+#[tokio::test]
+async fn test_oversized_chunk_is_rejected_before_sending() {
+    let (client, captured) = capture_server().await;
+    let file = test_file(1024);
+
+    let err = upload_chunk(
+        &client,
+        "bucket",
+        "key",
+        &file,
+        0,
+        MAX_PUT_OBJECT_SIZE + 1,
+        None,
+    )
+    .await
+    .unwrap_err();
+    assert!(err.to_string().contains("5 GiB"));
+    let endpoints = EndpointPool::single(client);
+    upload_chunk_with_endpoints(
+        &endpoints,
+        "bucket",
+        "key",
+        &file,
+        0,
+        20 * 1024 * 1024 * 1024,
+        None,
+    )
+    .await
+    .unwrap_err();
+    std::fs::remove_file(&file).unwrap();
+
+    assert!(captured.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_put_object_sniffs_content_type() {
+    let (client, captured) = capture_server().await;
+    let file = untyped_file(b"\x89PNG\r\n\x1a\n", 1024);
+
+    upload_chunk(&client, "bucket", "image", &file, 0, 1032, Some(headers()))
+        .await
+        .unwrap();
+    // An explicit Content-Type is kept.
+    upload_chunk(
+        &client,
+        "bucket",
+        "styles",
+        &file,
+        0,
+        1032,
+        Some(all_headers()),
+    )
+    .await
+    .unwrap();
+    // The bytes at the offset are the start of the object.
+    upload_chunk(&client, "bucket", "tail", &file, 8, 1024, None)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    let captured = captured.lock().unwrap();
+    assert_eq!(Some("image/png"), content_type(&captured[0].2));
+    assert_eq!(
+        Some("max-age=3600"),
+        captured[0]
+            .2
+            .get("cache-control")
+            .and_then(|v| v.to_str().ok())
+    );
+    assert_eq!(Some("text/css"), content_type(&captured[1].2));
+    assert_eq!(
+        Some("application/octet-stream"),
+        content_type(&captured[2].2)
+    );
+}
+
+#[tokio::test]
+async fn test_multipart_sniffs_content_type_at_window_offset() {
+    let (client, captured) = capture_server().await;
+    let file = untyped_file(b"garbage-%PDF-1.7", 3000);
+    let endpoints = EndpointPool::single(client);
+
+    let e_tag = upload_multipart_window(
+        &endpoints,
+        "bucket",
+        "document",
+        &file,
+        SourceWindow {
+            offset: 8,
+            length: 3008,
+        },
+        3,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!("etag", e_tag);
+    let captured = captured.lock().unwrap();
+    // Create, three parts, complete: reading the first bytes does not move
+    // the parts.
+    assert_eq!(5, captured.len());
+    assert_eq!(Some("application/pdf"), content_type(&captured[0].2));
+}
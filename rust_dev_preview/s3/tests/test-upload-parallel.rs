@@ -0,0 +1,49 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use s3_service::retry::RetryPolicy;
+use s3_service::upload::upload_multipart_parallel;
+
+/// `len` bytes derived from their offset, so a byte read at the wrong
+/// offset shows.
+fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_parallel_parts_are_read_at_their_offsets() {
+    let (client, uploads) = common::mock_uploads();
+    let content = data(2_000_000);
+    let path = std::env::temp_dir().join(format!("parallel-upload-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, &content).unwrap();
+
+    // Small reads, so the parts read from the file many times at once.
+    upload_multipart_parallel(
+        &client,
+        "bucket",
+        "key",
+        path.to_str().unwrap(),
+        8,
+        Some(4096),
+        None,
+        &RetryPolicy::default(),
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let uploads = uploads.lock().unwrap();
+    assert_eq!(
+        8,
+        uploads
+            .requests
+            .iter()
+            .filter(|op| *op == "UploadPart")
+            .count()
+    );
+    assert!(uploads.objects["key"] == content);
+}
@@ -0,0 +1,306 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use rand::{Rng, SeedableRng};
+use s3_service::upload::{
+    buffer_capacity_for, check_object_size, parse_meminfo, part_ranges, plan_upload,
+    put_object_content_length, SourceWindow, UploadPlan, UploadPlanOptions, UploadStrategy,
+    DEFAULT_MULTIPART_THRESHOLD, FALLBACK_BUFFER_CAPACITY, MAX_OBJECT_SIZE, MAX_PARTS,
+    MAX_PART_SIZE, MAX_PUT_OBJECT_SIZE, MIN_PART_SIZE,
+};
+
+const MIB: u64 = 1024 * 1024;
+const GIB: u64 = 1024 * MIB;
+const TIB: u64 = 1024 * GIB;
+
+fn options(num_parts: Option<usize>, part_size: Option<u64>) -> UploadPlanOptions {
+    UploadPlanOptions {
+        num_parts,
+        part_size,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_threshold_boundary() {
+    let below = plan_upload(DEFAULT_MULTIPART_THRESHOLD - 1, &options(None, None));
+    assert_eq!(UploadStrategy::PutObject, below.strategy);
+    assert_eq!(1, below.num_parts);
+    assert_eq!(DEFAULT_MULTIPART_THRESHOLD - 1, below.last_part_size);
+
+    for size in [DEFAULT_MULTIPART_THRESHOLD, DEFAULT_MULTIPART_THRESHOLD + 1].iter() {
+        let plan = plan_upload(*size, &options(None, None));
+        assert_eq!(UploadStrategy::Multipart, plan.strategy);
+        assert_eq!(1, plan.num_parts);
+        assert_eq!(*size, plan.last_part_size);
+    }
+}
+
+#[test]
+fn test_custom_threshold() {
+    let options = UploadPlanOptions {
+        multipart_threshold: 100 * MIB,
+        ..Default::default()
+    };
+    assert_eq!(
+        UploadStrategy::PutObject,
+        plan_upload(100 * MIB - 1, &options).strategy
+    );
+    let plan = plan_upload(100 * MIB + 1, &options);
+    assert_eq!(UploadStrategy::Multipart, plan.strategy);
+    assert_eq!(12, plan.num_parts);
+
+    // A threshold below the minimum part size yields a single-part upload.
+    let options = UploadPlanOptions {
+        multipart_threshold: MIB,
+        ..Default::default()
+    };
+    let plan = plan_upload(3 * MIB, &options);
+    assert_eq!(UploadStrategy::Multipart, plan.strategy);
+    assert_eq!(1, plan.num_parts);
+}
+
+#[test]
+fn test_auto_part_size() {
+    let plan = plan_upload(100 * MIB, &options(None, None));
+    assert_eq!(12, plan.num_parts);
+    assert_eq!(100 * MIB / 12, plan.part_size);
+    assert_eq!(
+        100 * MIB,
+        plan.part_size * (plan.num_parts as u64 - 1) + plan.last_part_size
+    );
+
+    // 8 MiB parts would need more than 10,000 parts for 100 GiB.
+    let plan = plan_upload(100 * GIB, &options(None, None));
+    assert!(plan.num_parts as u64 <= MAX_PARTS);
+    assert!(plan.part_size > 8 * MIB);
+}
+
+#[test]
+fn test_requested_layout_is_adjusted() {
+    // 20 parts of a 50 MiB file would be below the minimum part size.
+    let plan = plan_upload(50 * MIB, &options(Some(20), None));
+    assert_eq!(10, plan.num_parts);
+    assert!(plan.part_size >= MIN_PART_SIZE);
+
+    let plan = plan_upload(50 * MIB, &options(Some(3), None));
+    assert_eq!(3, plan.num_parts);
+
+    let plan = plan_upload(50 * MIB, &options(None, Some(16 * MIB)));
+    assert_eq!(3, plan.num_parts);
+    assert!(plan.part_size >= 16 * MIB);
+
+    // Parts cannot exceed 5 GiB.
+    let plan = plan_upload(12 * GIB, &options(Some(1), None));
+    assert_eq!(3, plan.num_parts);
+}
+
+/// Checks that the parts are contiguous and cover exactly `window`.
+fn assert_covers(window: SourceWindow, num_parts: usize, ranges: &[(u64, u64)]) {
+    let expected_parts = (num_parts as u64).min(window.length).max(1) as usize;
+    assert_eq!(expected_parts, ranges.len(), "{:?}", window);
+    let mut next = window.offset;
+    for (offset, size) in ranges {
+        assert_eq!(next, *offset, "{:?}", window);
+        assert!(*size > 0 || window.length == 0, "{:?}", window);
+        next += size;
+    }
+    assert_eq!(window.end(), next, "{:?}", window);
+    // Only the last part takes the remainder.
+    let first = ranges[0].1;
+    assert!(ranges[..ranges.len() - 1]
+        .iter()
+        .all(|(_, size)| *size == first));
+    assert!(ranges[ranges.len() - 1].1 < first + ranges.len() as u64);
+}
+
+#[test]
+fn test_part_ranges_cover_window() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(163);
+    for _ in 0..10_000 {
+        let file_len = rng.gen_range(0..=1 << 40);
+        let offset = rng.gen_range(0..=file_len);
+        let length = rng.gen_range(0..=file_len - offset);
+        let num_parts = rng.gen_range(1..=MAX_PARTS as usize);
+        let window = SourceWindow::resolve(file_len, Some(offset), Some(length)).unwrap();
+        assert_covers(window, num_parts, &part_ranges(window, num_parts));
+    }
+}
+
+#[test]
+fn test_part_ranges_of_a_small_window_are_not_empty() {
+    let window = SourceWindow::resolve(100, Some(10), Some(3)).unwrap();
+    assert_eq!(vec![(10, 1), (11, 1), (12, 1)], part_ranges(window, 5));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+    for _ in 0..1_000 {
+        let length = rng.gen_range(1..=100);
+        let num_parts = rng.gen_range(1..=200);
+        let window = SourceWindow::resolve(100, Some(0), Some(length)).unwrap();
+        assert_covers(window, num_parts, &part_ranges(window, num_parts));
+    }
+}
+
+#[test]
+fn test_window_at_eof() {
+    let file_len = 100 * MIB + 7;
+    let window = SourceWindow::resolve(file_len, Some(file_len - 10 * MIB), None).unwrap();
+    assert_eq!(10 * MIB, window.length);
+    assert_eq!(file_len, window.end());
+    let ranges = part_ranges(window, 2);
+    assert_eq!(
+        vec![
+            (file_len - 10 * MIB, 5 * MIB),
+            (file_len - 5 * MIB, 5 * MIB)
+        ],
+        ranges
+    );
+
+    let window = SourceWindow::resolve(file_len, Some(file_len - 1), Some(1)).unwrap();
+    assert_eq!(vec![(file_len - 1, 1)], part_ranges(window, 1));
+
+    // One byte too many, or an offset past the end, is rejected.
+    assert!(SourceWindow::resolve(file_len, Some(file_len - 1), Some(2)).is_err());
+    assert!(SourceWindow::resolve(file_len, Some(file_len + 1), None).is_err());
+    assert!(SourceWindow::resolve(file_len, Some(1), Some(u64::MAX)).is_err());
+}
+
+#[test]
+fn test_zero_length_window() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    for _ in 0..1_000 {
+        let file_len = rng.gen_range(0..=1 << 40);
+        let offset = rng.gen_range(0..=file_len);
+        let num_parts = rng.gen_range(1..=MAX_PARTS as usize);
+        let window = SourceWindow::resolve(file_len, Some(offset), Some(0)).unwrap();
+        assert_eq!(vec![(offset, 0)], part_ranges(window, num_parts));
+    }
+    // An offset at the end of the file without a length is an empty window.
+    let window = SourceWindow::resolve(42, Some(42), None).unwrap();
+    assert_eq!(0, window.length);
+    assert_eq!(
+        SourceWindow::whole(0),
+        SourceWindow::resolve(0, None, None).unwrap()
+    );
+}
+
+#[test]
+fn test_put_object_size_limit() {
+    assert_eq!(5 * GIB, MAX_PUT_OBJECT_SIZE);
+    assert_eq!(
+        Ok(5 * GIB as i64 - 1),
+        put_object_content_length(5 * GIB - 1).map_err(|err| err.to_string())
+    );
+    assert_eq!(
+        Ok(5 * GIB as i64),
+        put_object_content_length(5 * GIB).map_err(|err| err.to_string())
+    );
+    let err = put_object_content_length(5 * GIB + 1).unwrap_err();
+    assert!(err.to_string().contains("--auto-multipart"));
+    // Sizes that do not fit Content-Length are over the limit too.
+    assert!(put_object_content_length(u64::MAX).is_err());
+}
+
+/// Checks that a multipart plan for `size` bytes is within the limits of
+/// Amazon S3, the last part included, and adds up to `size`.
+fn assert_within_limits(size: u64, plan: &UploadPlan) {
+    let num_parts = plan.num_parts as u64;
+    assert_eq!(UploadStrategy::Multipart, plan.strategy);
+    assert!(num_parts <= MAX_PARTS, "{:?}", plan);
+    assert!(plan.part_size >= MIN_PART_SIZE, "{:?}", plan);
+    assert!(plan.part_size <= MAX_PART_SIZE, "{:?}", plan);
+    assert!(plan.last_part_size <= MAX_PART_SIZE, "{:?}", plan);
+    assert_eq!(size, plan.part_size * (num_parts - 1) + plan.last_part_size);
+    let ranges = part_ranges(SourceWindow::whole(size), plan.num_parts);
+    assert_covers(SourceWindow::whole(size), plan.num_parts, &ranges);
+    assert_eq!(plan.last_part_size, ranges[ranges.len() - 1].1);
+    assert!(ranges
+        .iter()
+        .all(|(_, size)| put_object_content_length(*size).is_ok()));
+}
+
+#[test]
+fn test_object_size_limit() {
+    assert_eq!(5 * TIB, MAX_OBJECT_SIZE);
+    for size in [
+        MAX_OBJECT_SIZE - MAX_PART_SIZE,
+        MAX_OBJECT_SIZE - 1,
+        MAX_OBJECT_SIZE,
+    ]
+    .iter()
+    {
+        assert!(check_object_size(*size).is_ok());
+        let layouts = [
+            options(None, None),
+            options(Some(1), None),
+            options(Some(MAX_PARTS as usize), None),
+            options(Some(usize::MAX), None),
+            options(None, Some(MIN_PART_SIZE)),
+            options(None, Some(MAX_PART_SIZE)),
+            options(None, Some(u64::MAX)),
+        ];
+        for layout in layouts.iter() {
+            assert_within_limits(*size, &plan_upload(*size, layout));
+        }
+    }
+
+    // The fewest parts of a file just under the limit are just under 5 GiB,
+    // and the remainder would push the last one over.
+    let plan = plan_upload(MAX_OBJECT_SIZE - 1, &options(Some(1), None));
+    assert_eq!(1025, plan.num_parts);
+    let plan = plan_upload(MAX_OBJECT_SIZE, &options(Some(1), None));
+    assert_eq!(1024, plan.num_parts);
+    assert_eq!(MAX_PART_SIZE, plan.last_part_size);
+
+    for size in [MAX_OBJECT_SIZE + 1, 6 * TIB, u64::MAX].iter() {
+        let err = check_object_size(*size).unwrap_err().to_string();
+        assert!(err.contains("5 TiB"), "{}", err);
+        assert!(err.contains("--source-offset"), "{}", err);
+    }
+}
+
+#[test]
+fn test_plans_near_the_object_size_limit() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(172);
+    for _ in 0..1_000 {
+        let size = rng.gen_range(MAX_OBJECT_SIZE - 100 * GIB..=MAX_OBJECT_SIZE);
+        let num_parts = rng.gen_range(1..=MAX_PARTS as usize);
+        assert_within_limits(size, &plan_upload(size, &options(Some(num_parts), None)));
+        let part_size = rng.gen_range(MIN_PART_SIZE..=MAX_PART_SIZE);
+        assert_within_limits(size, &plan_upload(size, &options(None, Some(part_size))));
+    }
+}
+
+#[test]
+fn test_buffer_capacity_fits_half_of_memory() {
+    // Plenty of memory: the buffer holds a whole part.
+    assert_eq!(
+        10 * MIB as usize,
+        buffer_capacity_for(100 * MIB, 10, Some(64 * GIB))
+    );
+    // 10 parts of 1 GiB with 8 GiB available: 8 GiB / 20 each.
+    let capacity = buffer_capacity_for(10 * GIB, 10, Some(8 * GIB));
+    assert_eq!((8 * GIB / 20) as usize, capacity);
+    assert!(capacity as u64 * 10 <= 4 * GIB);
+    // Unknown memory.
+    assert_eq!(
+        FALLBACK_BUFFER_CAPACITY,
+        buffer_capacity_for(10 * GIB, 10, None)
+    );
+    assert_eq!(MIB as usize, buffer_capacity_for(10 * MIB, 10, None));
+    // Empty files and absurd part counts still get a usable buffer.
+    assert!(buffer_capacity_for(0, 0, Some(GIB)) > 0);
+    assert!(buffer_capacity_for(GIB, 10_000, Some(MIB)) > 0);
+}
+
+#[test]
+fn test_parse_meminfo() {
+    let meminfo = "MemTotal:       16318164 kB\n\
+                   MemFree:          870044 kB\n\
+                   MemAvailable:    9520356 kB\n\
+                   Buffers:          512208 kB\n";
+
+    assert_eq!(Some(9_520_356 * 1024), parse_meminfo(meminfo));
+    assert_eq!(None, parse_meminfo("MemTotal: 16318164 kB\n"));
+}
@@ -0,0 +1,160 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::upload::{SharedUploadPool, MIN_PART_SIZE};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The parts being uploaded, and the most there were at once.
+#[derive(Default)]
+struct InFlight {
+    current: AtomicUsize,
+    max: AtomicUsize,
+    parts: AtomicUsize,
+    /// The body of each part, by key and part number.
+    bodies: Mutex<BTreeMap<(String, i32), Vec<u8>>>,
+}
+
+impl InFlight {
+    /// The parts of `key`, in order.
+    fn object(&self, key: &str) -> Vec<u8> {
+        self.bodies
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((part_key, _), _)| part_key == key)
+            .flat_map(|(_, body)| body.clone())
+            .collect()
+    }
+}
+
+/// Starts a server that answers multipart uploads, holding each part for a
+/// while so that concurrent parts overlap.
+async fn mock_server(in_flight: Arc<InFlight>) -> Client {
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let in_flight = in_flight.clone();
+        async move {
+            let method = req.method().clone();
+            let key = req.uri().path().trim_start_matches("/bucket/").to_string();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let response = if method == Method::PUT {
+                let part_number = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("partNumber="))
+                    .map(|n| n.parse::<i32>().unwrap())
+                    .unwrap();
+                in_flight
+                    .bodies
+                    .lock()
+                    .unwrap()
+                    .insert((key, part_number), body.to_vec());
+                let now = in_flight.current.fetch_add(1, Ordering::SeqCst) + 1;
+                in_flight.max.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                in_flight.current.fetch_sub(1, Ordering::SeqCst);
+                in_flight.parts.fetch_add(1, Ordering::SeqCst);
+                Response::builder()
+                    .header("ETag", "\"part-etag\"")
+                    .body(Body::empty())
+            } else if query.contains("uploads") {
+                Response::builder().body(Body::from(
+                    "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                ))
+            } else {
+                Response::builder().body(Body::from(
+                    "<CompleteMultipartUploadResult><ETag>\"complete-etag\"</ETag>\
+                     </CompleteMultipartUploadResult>",
+                ))
+            };
+            response.unwrap()
+        }
+    });
+
+    common::client_for(port)
+}
+
+/// `size` bytes derived from their offset and `seed`, so a byte read at
+/// the wrong offset, or from another file, shows.
+fn content(size: u64, seed: u8) -> Vec<u8> {
+    (0..size)
+        .map(|i| (i % 251) as u8 ^ seed.wrapping_mul(37))
+        .collect()
+}
+
+fn test_file(content: &[u8]) -> String {
+    let path = std::env::temp_dir().join(format!("pool-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, content).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_pool_bounds_parts_across_files() {
+    let in_flight = Arc::new(InFlight::default());
+    let client = mock_server(in_flight.clone()).await;
+    let pool = SharedUploadPool::new(2);
+    let contents: Vec<Vec<u8>> = (0..3).map(|i| content(2 * MIN_PART_SIZE, i)).collect();
+    let files: Vec<String> = contents.iter().map(|content| test_file(content)).collect();
+
+    let handles: Vec<_> = files
+        .iter()
+        .enumerate()
+        .map(|(i, file)| {
+            pool.upload_file(
+                &client,
+                "bucket",
+                &format!("key-{}", i),
+                file,
+                MIN_PART_SIZE,
+            )
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!("complete-etag", handle.await.unwrap().unwrap());
+    }
+    for file in &files {
+        std::fs::remove_file(file).unwrap();
+    }
+
+    assert_eq!(6, in_flight.parts.load(Ordering::SeqCst));
+    assert_eq!(2, in_flight.max.load(Ordering::SeqCst));
+    for (i, content) in contents.iter().enumerate() {
+        assert!(in_flight.object(&format!("key-{}", i)) == *content);
+    }
+    // All permits are back once the uploads are done.
+    assert_eq!(2, pool.semaphore.available_permits());
+}
+
+#[tokio::test]
+async fn test_small_part_size_is_raised_to_the_minimum() {
+    let in_flight = Arc::new(InFlight::default());
+    let client = mock_server(in_flight.clone()).await;
+    let pool = SharedUploadPool::new(4);
+    let content = content(2 * MIN_PART_SIZE, 0);
+    let file = test_file(&content);
+
+    let result = pool
+        .upload_file(&client, "bucket", "key", &file, 1000)
+        .await
+        .unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    assert_eq!("complete-etag", result.unwrap());
+    assert_eq!(2, in_flight.parts.load(Ordering::SeqCst));
+    assert!(in_flight.object("key") == content);
+}
+
+#[test]
+#[should_panic(expected = "at least one concurrent part")]
+fn test_empty_pool_is_refused() {
+    SharedUploadPool::new(0);
+}
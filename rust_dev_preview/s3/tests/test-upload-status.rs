@@ -0,0 +1,243 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use s3_service::upload_status::{
+    expected_part, match_parts, sample_indices, upload_status, verify_parts, StatusOptions,
+    UploadStatus,
+};
+use s3_service::upload_watch::ListedPart;
+use std::path::PathBuf;
+
+const CONTENT: &[u8] = b"abcdefghijklmnopqrstuvwxy";
+const MD5_PART_1: &str = "a925576942e94b2ef57a066101b48876";
+const MD5_PART_3: &str = "5185e23403b620323c7d8f87893fd9cf";
+
+fn part(part_number: i32, size: u64, e_tag: &str) -> ListedPart {
+    ListedPart {
+        part_number,
+        size,
+        e_tag: e_tag.to_string(),
+    }
+}
+
+fn test_file() -> PathBuf {
+    let path = std::env::temp_dir().join(format!("upload-status-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, CONTENT).unwrap();
+    path
+}
+
+/// Starts a server answering ListParts with `parts`, or NoSuchUpload when
+/// there are none.
+async fn mock_s3(parts: Vec<ListedPart>) -> Client {
+    let port = common::mock_s3(move |_req: Request<Body>| {
+        let parts = parts.clone();
+        async move {
+            let response = if parts.is_empty() {
+                Response::builder().status(404).body(Body::from(
+                    "<Error><Code>NoSuchUpload</Code>\
+                     <Message>The specified upload does not exist.</Message></Error>",
+                ))
+            } else {
+                let parts: String = parts
+                    .iter()
+                    .map(|p| {
+                        format!(
+                            "<Part><PartNumber>{}</PartNumber><ETag>\"{}\"</ETag>\
+                             <Size>{}</Size></Part>",
+                            p.part_number, p.e_tag, p.size
+                        )
+                    })
+                    .collect();
+                Response::builder().body(Body::from(format!(
+                    "<ListPartsResult><Bucket>bucket</Bucket><Key>key</Key>\
+                     <UploadId>upload</UploadId><IsTruncated>false</IsTruncated>{}\
+                     </ListPartsResult>",
+                    parts
+                )))
+            };
+            response.unwrap()
+        }
+    });
+
+    common::client_for(port)
+}
+
+#[test]
+fn test_expected_part() {
+    // The remainder as an extra part, or in the last part.
+    assert_eq!(Some((0, 10)), expected_part(1, 10, 3, 25));
+    assert_eq!(Some((20, 5)), expected_part(3, 10, 3, 25));
+    assert_eq!(Some((10, 15)), expected_part(2, 10, 2, 25));
+    assert_eq!(None, expected_part(3, 10, 2, 25));
+    assert_eq!(None, expected_part(0, 10, 3, 25));
+    assert_eq!(Some((10, 10)), expected_part(2, 10, 2, 20));
+    assert_eq!(None, expected_part(3, 10, 2, 20));
+}
+
+#[test]
+fn test_match_parts() {
+    // Listed out of order, part 2 missing, part 4 past the end of the file
+    // and part 5 beyond it.
+    let parts = vec![
+        part(3, 5, "c"),
+        part(1, 10, "a"),
+        part(4, 10, "d"),
+        part(5, 10, "e"),
+    ];
+    let result = match_parts(&parts, 25);
+    assert_eq!(Some(10), result.part_size);
+    assert_eq!(Some(3), result.total_parts);
+    let matched: Vec<_> = result
+        .matched
+        .iter()
+        .map(|p| (p.part_number, p.offset, p.size))
+        .collect();
+    assert_eq!(vec![(1, 0, 10), (3, 20, 5)], matched);
+    assert_eq!(vec![2], result.missing);
+    assert_eq!(vec![4, 5], result.mismatched);
+    assert_eq!(15, result.bytes_matched());
+
+    // The last part takes the remainder, as in the uploads of this crate.
+    let result = match_parts(&[part(1, 10, "a"), part(2, 15, "b")], 25);
+    assert_eq!(Some(2), result.total_parts);
+    assert!(result.mismatched.is_empty());
+    assert!(result.missing.is_empty());
+    assert_eq!(25, result.bytes_matched());
+
+    // A part of another size was uploaded with another layout.
+    let result = match_parts(&[part(1, 10, "a"), part(2, 8, "b")], 25);
+    assert_eq!(vec![2], result.mismatched);
+    assert_eq!(vec![2], result.missing);
+
+    // Without a hint, the remainder is expected in the last part.
+    let result = match_parts(&[part(1, 10, "a")], 25);
+    assert_eq!(Some(2), result.total_parts);
+    assert_eq!(vec![2], result.missing);
+
+    let result = match_parts(&[], 25);
+    assert_eq!(None, result.part_size);
+    assert!(result.missing.is_empty());
+}
+
+#[test]
+fn test_sample_indices() {
+    assert_eq!(vec![0, 1, 2], sample_indices(3, 8));
+    assert_eq!(vec![0, 4, 9], sample_indices(10, 3));
+    assert_eq!(vec![0], sample_indices(10, 1));
+    assert!(sample_indices(10, 0).is_empty());
+    assert!(sample_indices(0, 3).is_empty());
+}
+
+#[tokio::test]
+async fn test_verify_parts() {
+    let path = test_file();
+    let parts = vec![
+        part(1, 10, MD5_PART_1),
+        part(2, 10, "0123456789abcdef0123456789abcdef"),
+        part(3, 5, "not-an-md5-1"),
+    ];
+    let matched = match_parts(&parts, CONTENT.len() as u64).matched;
+
+    let checks = verify_parts(&path, &matched, 3).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let results: Vec<_> = checks.iter().map(|c| (c.part_number, c.matches)).collect();
+    assert_eq!(vec![(1, Some(true)), (2, Some(false)), (3, None)], results);
+    assert_eq!(MD5_PART_3, checks[2].local_md5);
+}
+
+#[tokio::test]
+async fn test_upload_status() {
+    let path = test_file();
+    let client = mock_s3(vec![part(1, 10, MD5_PART_1), part(3, 5, MD5_PART_3)]).await;
+    let options = StatusOptions {
+        bytes_per_second: Some(5),
+        verify_sample: Some(8),
+    };
+
+    let status = upload_status(&client, "bucket", "key", "upload", &path, &options)
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(25, status.file_size);
+    assert_eq!(15, status.bytes_uploaded);
+    assert_eq!(60.0, status.percent);
+    assert_eq!(10, status.remaining_bytes);
+    assert_eq!(Some(2000), status.eta_ms);
+    assert_eq!(vec![2], status.parts.missing);
+    assert_eq!(2, status.verified.as_ref().unwrap().len());
+    assert!(!status.source_changed());
+    let text = status.to_text();
+    assert!(text.contains("15 B of 25 B (60.0%)"), "{}", text);
+    assert!(text.contains("Missing parts: 2"), "{}", text);
+}
+
+#[tokio::test]
+async fn test_changed_source_is_detected() {
+    let path = test_file();
+    let client = mock_s3(vec![part(1, 10, MD5_PART_3)]).await;
+    let options = StatusOptions {
+        verify_sample: Some(8),
+        ..Default::default()
+    };
+
+    let status = upload_status(&client, "bucket", "key", "upload", &path, &options)
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(status.source_changed());
+    assert_eq!(None, status.eta_ms);
+}
+
+#[tokio::test]
+async fn test_upload_that_no_longer_exists() {
+    let path = test_file();
+    let client = mock_s3(Vec::new()).await;
+
+    let result = upload_status(
+        &client,
+        "bucket",
+        "key",
+        "upload",
+        &path,
+        &StatusOptions::default(),
+    )
+    .await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(result.unwrap_err().to_string().contains("no longer exists"));
+}
+
+#[test]
+fn test_status_at_the_object_size_limit() {
+    const TIB: u64 = 1 << 40;
+    let file_size = 5 * TIB;
+    let part_size = file_size / 10_000;
+    // Every part but the last, which takes the remainder.
+    let parts: Vec<_> = (1..10_000).map(|n| part(n, part_size, "etag")).collect();
+
+    let result = match_parts(&parts, file_size);
+    assert_eq!(Some(10_000), result.total_parts);
+    assert_eq!(vec![10_000], result.missing);
+    let status = UploadStatus::new(
+        "upload",
+        file_size,
+        result,
+        &StatusOptions {
+            bytes_per_second: Some(u64::MAX),
+            ..Default::default()
+        },
+    );
+    assert_eq!(9_999 * part_size, status.bytes_uploaded);
+    assert_eq!(file_size - 9_999 * part_size, status.remaining_bytes);
+    assert!(status.percent > 99.98 && status.percent < 100.0);
+    assert!(status.eta_ms.unwrap() < 1000);
+}
@@ -0,0 +1,286 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Method, Request, Response};
+use s3_service::upload_watch::{
+    estimate, find_upload, list_all_parts, watch_upload_with_hook, InProgressUpload, PartsProgress,
+    WatchEvent, WatchOptions,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The uploads of `key`, the newest second, and one of a longer key that
+/// the prefix also matches.
+const UPLOADS: &str = "<ListMultipartUploadsResult><Bucket>bucket</Bucket>\
+    <IsTruncated>false</IsTruncated>\
+    <Upload><Key>key</Key><UploadId>old</UploadId>\
+    <Initiated>2022-01-01T00:00:00.000Z</Initiated></Upload>\
+    <Upload><Key>key</Key><UploadId>new</UploadId>\
+    <Initiated>2022-01-02T00:00:00.000Z</Initiated></Upload>\
+    <Upload><Key>key2</Key><UploadId>other</UploadId>\
+    <Initiated>2022-01-03T00:00:00.000Z</Initiated></Upload>\
+    </ListMultipartUploadsResult>";
+
+/// What the mock server answers.
+struct Server {
+    /// ListParts returns parts for this many listings, then NoSuchUpload.
+    listings: usize,
+    /// Whether the object exists once the upload is gone.
+    completed: bool,
+    /// The first pages of ListParts answered so far.
+    first_pages: AtomicUsize,
+}
+
+fn parts_page(first: bool) -> String {
+    let (parts, truncated) = if first {
+        (
+            vec![1, 2],
+            "<IsTruncated>true</IsTruncated><NextPartNumberMarker>2</NextPartNumberMarker>",
+        )
+    } else {
+        (vec![3], "<IsTruncated>false</IsTruncated>")
+    };
+    let parts: String = parts
+        .iter()
+        .map(|n| {
+            format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>\"etag\"</ETag><Size>1000</Size></Part>",
+                n
+            )
+        })
+        .collect();
+    format!(
+        "<ListPartsResult><Bucket>bucket</Bucket><Key>key</Key><UploadId>new</UploadId>{}{}\
+         </ListPartsResult>",
+        truncated, parts
+    )
+}
+
+async fn mock_server(server: Arc<Server>) -> Client {
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let server = server.clone();
+        async move {
+            let method = req.method().clone();
+            let query = req.uri().query().unwrap_or("").to_string();
+            let response = if method == Method::HEAD {
+                if server.completed {
+                    Response::builder()
+                        .header("ETag", "\"complete-etag\"")
+                        .header("Last-Modified", "Sun, 02 Jan 2022 00:00:00 GMT")
+                        .body(Body::empty())
+                } else {
+                    Response::builder().status(404).body(Body::empty())
+                }
+            } else if query.contains("uploads") {
+                Response::builder().body(Body::from(UPLOADS))
+            } else if query.contains("part-number-marker=2") {
+                Response::builder().body(Body::from(parts_page(false)))
+            } else if server.first_pages.fetch_add(1, Ordering::SeqCst) < server.listings {
+                Response::builder().body(Body::from(parts_page(true)))
+            } else {
+                Response::builder().status(404).body(Body::from(
+                    "<Error><Code>NoSuchUpload</Code>\
+                     <Message>The specified upload does not exist.</Message></Error>",
+                ))
+            };
+            response.unwrap()
+        }
+    });
+
+    common::client_for(port)
+}
+
+fn server(listings: usize, completed: bool) -> Arc<Server> {
+    Arc::new(Server {
+        listings,
+        completed,
+        first_pages: AtomicUsize::new(0),
+    })
+}
+
+fn options() -> WatchOptions {
+    WatchOptions {
+        interval: Duration::from_millis(10),
+        timeout: Some(Duration::from_secs(10)),
+        total_size: None,
+    }
+}
+
+#[tokio::test]
+async fn test_find_newest_upload() {
+    let client = mock_server(server(1, false)).await;
+    let upload = find_upload(&client, "bucket", "key", None).await.unwrap();
+    assert_eq!("new", upload.upload_id);
+    let given = find_upload(&client, "bucket", "key", Some("gone"))
+        .await
+        .unwrap();
+    assert_eq!(
+        InProgressUpload {
+            upload_id: "gone".to_string(),
+            initiated: None
+        },
+        given
+    );
+}
+
+#[tokio::test]
+async fn test_list_all_parts_follows_pages() {
+    let client = mock_server(server(1, false)).await;
+    let progress = list_all_parts(&client, "bucket", "key", "new")
+        .await
+        .unwrap();
+    assert_eq!(
+        Some(PartsProgress {
+            parts: 3,
+            bytes: 3000
+        }),
+        progress
+    );
+    // The upload is gone on the next listing.
+    assert_eq!(
+        None,
+        list_all_parts(&client, "bucket", "key", "new")
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_watch_until_completed() {
+    let client = mock_server(server(2, true)).await;
+    let upload = find_upload(&client, "bucket", "key", None).await.unwrap();
+    let mut events = Vec::new();
+    let last = watch_upload_with_hook(&client, "bucket", "key", &upload, &options(), |event| {
+        events.push(event.clone())
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        WatchEvent::Completed {
+            e_tag: "complete-etag".to_string()
+        },
+        last
+    );
+    assert_eq!(3, events.len());
+    assert!(matches!(
+        events[0],
+        WatchEvent::InProgress {
+            bytes_per_second: None,
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_watch_until_aborted() {
+    let client = mock_server(server(1, false)).await;
+    let upload = find_upload(&client, "bucket", "key", None).await.unwrap();
+    let last = watch_upload_with_hook(&client, "bucket", "key", &upload, &options(), |_| {})
+        .await
+        .unwrap();
+    assert_eq!(WatchEvent::Aborted, last);
+}
+
+#[tokio::test]
+async fn test_older_object_means_aborted() {
+    let client = mock_server(server(0, true)).await;
+    // Created after the object's Last-Modified date.
+    let upload = InProgressUpload {
+        upload_id: "new".to_string(),
+        initiated: Some(1_700_000_000),
+    };
+    let last = watch_upload_with_hook(&client, "bucket", "key", &upload, &options(), |_| {})
+        .await
+        .unwrap();
+    assert_eq!(WatchEvent::Aborted, last);
+}
+
+#[tokio::test]
+async fn test_watch_times_out() {
+    let client = mock_server(server(usize::MAX, true)).await;
+    let upload = find_upload(&client, "bucket", "key", None).await.unwrap();
+    let options = WatchOptions {
+        interval: Duration::from_millis(20),
+        timeout: Some(Duration::from_millis(100)),
+        total_size: None,
+    };
+    let err = watch_upload_with_hook(&client, "bucket", "key", &upload, &options, |_| {})
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("still in progress"));
+}
+
+#[test]
+fn test_estimate() {
+    let before = PartsProgress {
+        parts: 1,
+        bytes: 1000,
+    };
+    let after = PartsProgress {
+        parts: 3,
+        bytes: 3000,
+    };
+    assert_eq!(
+        (None, None),
+        estimate(None, after, Duration::from_secs(1), Some(5000))
+    );
+    assert_eq!(
+        (Some(1000.0), Some(2000)),
+        estimate(
+            Some((before, Duration::from_secs(1))),
+            after,
+            Duration::from_secs(3),
+            Some(5000)
+        )
+    );
+    // No progress since the previous poll: no time left to estimate.
+    assert_eq!(
+        (Some(0.0), None),
+        estimate(
+            Some((after, Duration::from_secs(1))),
+            after,
+            Duration::from_secs(3),
+            Some(5000)
+        )
+    );
+}
+
+#[test]
+fn test_estimate_at_the_object_size_limit() {
+    const TIB: u64 = 1 << 40;
+    let before = PartsProgress {
+        parts: 5_000,
+        bytes: 5 * TIB / 2,
+    };
+    let after = PartsProgress {
+        parts: 9_999,
+        bytes: 5 * TIB - (1 << 29),
+    };
+    let (rate, eta) = estimate(
+        Some((before, Duration::from_secs(1))),
+        after,
+        Duration::from_secs(2),
+        Some(5 * TIB),
+    );
+    assert_eq!(Some((5 * TIB / 2 - (1 << 29)) as f64), rate);
+    let eta = eta.unwrap();
+    assert!(eta < 1000, "{}", eta);
+
+    // More bytes listed than expected leaves nothing to wait for.
+    let (_, eta) = estimate(
+        Some((before, Duration::from_secs(1))),
+        PartsProgress {
+            parts: 10_000,
+            bytes: 6 * TIB,
+        },
+        Duration::from_secs(2),
+        Some(5 * TIB),
+    );
+    assert_eq!(Some(0), eta);
+}
@@ -0,0 +1,109 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use hyper::{Body, Request, Response};
+use s3_service::verbosity::{request_id_client, transfer_line, with_request_id};
+use std::time::Duration;
+
+/// Answers every request with an ETag and a request ID naming its part.
+async fn mock_endpoint() -> u16 {
+    common::mock_s3(|req: Request<Body>| async move {
+        let part = req
+            .uri()
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .find_map(|p| p.strip_prefix("partNumber="))
+            .unwrap_or("none")
+            .to_string();
+        hyper::body::to_bytes(req.into_body()).await.unwrap();
+        Response::builder()
+            .header("ETag", "\"etag\"")
+            .header("x-amz-request-id", format!("REQUEST-{}", part))
+            .body(Body::empty())
+            .unwrap()
+    })
+}
+
+async fn upload_part(client: &Client, part_number: i32) {
+    client
+        .upload_part()
+        .bucket("bucket")
+        .key("key")
+        .upload_id("upload")
+        .part_number(part_number)
+        .body(b"part data".to_vec().into())
+        .send()
+        .await
+        .unwrap();
+}
+
+#[test]
+fn test_transfer_line() {
+    assert_eq!(
+        "[part 3/10] uploaded 8.0 MiB in 0.4s (20.0 MiB/s)",
+        transfer_line(
+            "part",
+            "uploaded",
+            3,
+            10,
+            8 * 1024 * 1024,
+            Duration::from_millis(400),
+            None
+        )
+    );
+    assert_eq!(
+        "[chunk 3/10] downloaded 8.0 MiB in 0.3s (26.7 MiB/s) request ID ABC",
+        transfer_line(
+            "chunk",
+            "downloaded",
+            3,
+            10,
+            8 * 1024 * 1024,
+            Duration::from_millis(300),
+            Some("ABC")
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_request_id_of_each_part() {
+    let port = mock_endpoint().await;
+    let client = request_id_client(common::config_for(port).build());
+
+    let (_, first) = with_request_id(upload_part(&client, 1)).await;
+    let ((), second) = with_request_id(upload_part(&client, 2)).await;
+
+    assert_eq!(Some("REQUEST-1".to_string()), first);
+    assert_eq!(Some("REQUEST-2".to_string()), second);
+}
+
+#[tokio::test]
+async fn test_concurrent_parts_get_their_own_request_ids() {
+    let port = mock_endpoint().await;
+    let client = request_id_client(common::config_for(port).build());
+
+    let results = futures::future::join_all(
+        (1..=4).map(|part_number| with_request_id(upload_part(&client, part_number))),
+    )
+    .await;
+
+    let request_ids: Vec<Option<String>> = results.into_iter().map(|(_, id)| id).collect();
+    let expected: Vec<Option<String>> = (1..=4).map(|n| Some(format!("REQUEST-{}", n))).collect();
+    assert_eq!(expected, request_ids);
+}
+
+#[tokio::test]
+async fn test_no_request_id_without_the_connector() {
+    let port = mock_endpoint().await;
+    let client = common::client_for(port);
+
+    let (_, request_id) = with_request_id(upload_part(&client, 1)).await;
+
+    assert_eq!(None, request_id);
+}
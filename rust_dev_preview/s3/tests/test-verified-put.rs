@@ -0,0 +1,98 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use aws_sdk_s3::Client;
+use bytes::Bytes;
+use hyper::{Body, Request, Response};
+use s3_service::verified_put::{put_object_verified, HashAlgorithm, MAX_DIGEST_RETRIES};
+use std::sync::{Arc, Mutex};
+
+/// The checksum headers received by the mock, one per request.
+type Received = Arc<Mutex<Vec<Option<String>>>>;
+
+/// Starts a server rejecting the first `rejections` PutObject requests with
+/// BadDigest and accepting the others.
+async fn mock_s3(header: &'static str, rejections: usize) -> (Client, Received) {
+    let received = Received::default();
+    let log = received.clone();
+    let port = common::mock_s3(move |req: Request<Body>| {
+        let log = log.clone();
+        async move {
+            let checksum = req
+                .headers()
+                .get(header)
+                .map(|v| v.to_str().unwrap().to_string());
+            let mut log = log.lock().unwrap();
+            log.push(checksum.clone());
+            let response = if log.len() <= rejections {
+                Response::builder().status(400).body(Body::from(
+                    "<Error><Code>BadDigest</Code>\
+                     <Message>The Content-MD5 or checksum value that you specified \
+                     did not match what the server received.</Message></Error>",
+                ))
+            } else {
+                let mut builder = Response::builder().header("ETag", "\"verified\"");
+                if let Some(checksum) = checksum {
+                    builder = builder.header(header, checksum);
+                }
+                builder.body(Body::empty())
+            };
+            response.unwrap()
+        }
+    });
+
+    (common::client_for(port), received)
+}
+
+#[test]
+fn checksums_are_base64_of_the_digest() {
+    // Known values for "hello world".
+    let data = b"hello world";
+    assert_eq!(
+        HashAlgorithm::Sha256.checksum(data),
+        "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek="
+    );
+    assert_eq!(
+        HashAlgorithm::Sha1.checksum(data),
+        "Kq5sNclPz7QV2+lfQIuc6R7oRu0="
+    );
+    assert_eq!(HashAlgorithm::Crc32.checksum(data), "DUoRhQ==");
+    assert_eq!(HashAlgorithm::Crc32c.checksum(data), "yZRlqg==");
+    assert_eq!("CRC32C".parse(), Ok(HashAlgorithm::Crc32c));
+    assert_eq!("sha-256".parse(), Ok(HashAlgorithm::Sha256));
+    assert!("md5".parse::<HashAlgorithm>().is_err());
+}
+
+#[tokio::test]
+async fn object_rejected_for_its_checksum_is_sent_again() {
+    let (client, received) = mock_s3("x-amz-checksum-sha256", 2).await;
+    let data = Bytes::from_static(b"hello world");
+
+    let e_tag = put_object_verified(&client, "bucket", "key", data, HashAlgorithm::Sha256)
+        .await
+        .unwrap();
+
+    assert_eq!(e_tag, "verified");
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 3);
+    let expected = HashAlgorithm::Sha256.checksum(b"hello world");
+    assert!(received.iter().all(|c| c.as_deref() == Some(&expected)));
+}
+
+#[tokio::test]
+async fn gives_up_after_the_last_retry() {
+    let (client, received) = mock_s3("x-amz-checksum-crc32c", usize::MAX).await;
+    let data = Bytes::from_static(b"hello world");
+
+    let result = put_object_verified(&client, "bucket", "key", data, HashAlgorithm::Crc32c).await;
+
+    assert!(result.is_err());
+    assert_eq!(
+        received.lock().unwrap().len(),
+        1 + MAX_DIGEST_RETRIES as usize
+    );
+}
@@ -0,0 +1,279 @@
+/*
+ * Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+mod common;
+
+use async_zip::base::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use aws_sdk_s3::types::ByteStream;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use rand::{RngCore, SeedableRng};
+use s3_service::zip_archive::{download_and_extract_zip, entry_path, upload_as_zip};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The objects of an in-memory S3, by path, and the requests it answered.
+#[derive(Debug, Default)]
+struct Store {
+    objects: HashMap<String, Vec<u8>>,
+    parts: BTreeMap<i32, Vec<u8>>,
+    /// The method and path and query of each request.
+    requests: Vec<(Method, String)>,
+    /// Whether UploadPart fails with a 500.
+    fail_parts: bool,
+}
+
+/// Starts a server answering PutObject, GetObject, and the multipart upload
+/// requests from `store`, and returns its port.
+fn object_store(store: Arc<Mutex<Store>>) -> u16 {
+    common::mock_s3(move |req: Request<Body>| {
+        let store = store.clone();
+        async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let query = req.uri().query().unwrap_or_default().to_string();
+            let body = hyper::body::to_bytes(req.into_body())
+                .await
+                .unwrap()
+                .to_vec();
+            let mut store = store.lock().unwrap();
+            store
+                .requests
+                .push((method.clone(), format!("{}?{}", path, query)));
+            let part_number = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("partNumber="))
+                .map(|n| n.parse::<i32>().unwrap());
+            let mut response = Response::builder().header("ETag", "\"etag\"");
+            let body = match (&method, part_number) {
+                (&Method::PUT, Some(_)) if store.fail_parts => {
+                    response = response.status(StatusCode::INTERNAL_SERVER_ERROR);
+                    String::new()
+                }
+                (&Method::PUT, Some(n)) => {
+                    store.parts.insert(n, body);
+                    String::new()
+                }
+                (&Method::PUT, None) => {
+                    store.objects.insert(path, body);
+                    String::new()
+                }
+                (&Method::POST, _) if query.starts_with("uploads") => {
+                    "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                     </InitiateMultipartUploadResult>"
+                        .to_string()
+                }
+                (&Method::POST, _) => {
+                    let object = std::mem::take(&mut store.parts)
+                        .into_values()
+                        .flatten()
+                        .collect::<Vec<u8>>();
+                    store.objects.insert(path, object);
+                    "<CompleteMultipartUploadResult><ETag>\"etag\"</ETag>\
+                     </CompleteMultipartUploadResult>"
+                        .to_string()
+                }
+                (&Method::DELETE, _) => {
+                    store.parts.clear();
+                    response = response.status(StatusCode::NO_CONTENT);
+                    String::new()
+                }
+                _ => match store.objects.get(&path) {
+                    Some(object) => {
+                        return response.body(Body::from(object.clone())).unwrap();
+                    }
+                    None => {
+                        response = response.status(StatusCode::NOT_FOUND);
+                        "<Error><Code>NoSuchKey</Code></Error>".to_string()
+                    }
+                },
+            };
+            response.body(Body::from(body)).unwrap()
+        }
+    })
+}
+
+/// A tree with a compressible file, a nested file, and `random` bytes that
+/// do not compress.
+fn source_tree(random: usize) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("zip-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(root.join("nested")).unwrap();
+    std::fs::write(root.join("top.txt"), "top\n".repeat(1000)).unwrap();
+    std::fs::write(root.join("nested").join("inner.txt"), "inner").unwrap();
+    if random > 0 {
+        let mut data = vec![0; random];
+        rand::rngs::StdRng::seed_from_u64(158).fill_bytes(&mut data);
+        std::fs::write(root.join("random.bin"), data).unwrap();
+    }
+    root
+}
+
+#[test]
+fn test_entry_path_rejects_traversal() {
+    let dest = Path::new("/tmp/extract");
+    assert_eq!(
+        Some(dest.join("a").join("b.txt")),
+        entry_path(dest, "a/./b.txt")
+    );
+    assert_eq!(
+        Some(dest.join("a").join("b.txt")),
+        entry_path(dest, "a\\b.txt")
+    );
+    assert_eq!(None, entry_path(dest, "../evil.txt"));
+    assert_eq!(None, entry_path(dest, "a/../../evil.txt"));
+    assert_eq!(None, entry_path(dest, "a\\..\\..\\evil.txt"));
+    assert_eq!(None, entry_path(dest, "/etc/passwd"));
+}
+
+#[ignore]
+#[tokio::test]
+async fn test_zip_round_trip() {
+    let client = common::minio_client().await;
+    let bucket = common::create_test_bucket(&client).await;
+    let root = std::env::temp_dir().join(format!("zip-test-{}", uuid::Uuid::new_v4()));
+    let source = root.join("source");
+    std::fs::create_dir_all(source.join("nested")).unwrap();
+    std::fs::write(source.join("top.txt"), "top\n".repeat(1000)).unwrap();
+    std::fs::write(source.join("nested").join("inner.txt"), "inner").unwrap();
+
+    upload_as_zip(&client, &bucket, "tree.zip", &source)
+        .await
+        .unwrap();
+    let dest = root.join("dest");
+    let report = download_and_extract_zip(&client, &bucket, "tree.zip", &dest)
+        .await
+        .unwrap();
+    assert_eq!(2, report.files);
+    assert_eq!(4005, report.bytes);
+    assert!(report.failed.is_empty());
+    assert_eq!(
+        "inner",
+        std::fs::read_to_string(dest.join("nested").join("inner.txt")).unwrap()
+    );
+
+    // An archive with a traversal entry: the entry is reported, the others
+    // are still extracted.
+    let mut zip = ZipFileWriter::with_tokio(Vec::new());
+    for (name, data) in [("../evil.txt", "evil"), ("good.txt", "good")] {
+        let builder = ZipEntryBuilder::new(name.to_string().into(), Compression::Deflate);
+        zip.write_entry_whole(builder, data.as_bytes())
+            .await
+            .unwrap();
+    }
+    let archive = zip.close().await.unwrap().into_inner();
+    client
+        .put_object()
+        .bucket(&bucket)
+        .key("evil.zip")
+        .body(ByteStream::from(archive))
+        .send()
+        .await
+        .unwrap();
+    let dest = root.join("evil");
+    let report = download_and_extract_zip(&client, &bucket, "evil.zip", &dest)
+        .await
+        .unwrap();
+    assert_eq!(1, report.files);
+    assert_eq!(1, report.failed.len());
+    assert_eq!("../evil.txt", report.failed[0].name);
+    assert!(!root.join("evil.txt").exists());
+    assert_eq!(
+        "good",
+        std::fs::read_to_string(dest.join("good.txt")).unwrap()
+    );
+
+    std::fs::remove_dir_all(&root).unwrap();
+    common::delete_test_bucket(&client, &bucket).await;
+}
+
+#[tokio::test]
+async fn test_upload_as_zip_round_trip() {
+    let store = Arc::new(Mutex::new(Store::default()));
+    let client = common::client_for(object_store(store.clone()));
+    let source = source_tree(0);
+
+    upload_as_zip(&client, "bucket", "tree.zip", &source)
+        .await
+        .unwrap();
+    // A small archive is sent whole.
+    let requests = store.lock().unwrap().requests.clone();
+    assert_eq!(requests.len(), 1, "{:?}", requests);
+    assert_eq!(requests[0].0, Method::PUT);
+
+    let dest = source.with_extension("dest");
+    let report = download_and_extract_zip(&client, "bucket", "tree.zip", &dest)
+        .await
+        .unwrap();
+    assert_eq!(2, report.files);
+    assert_eq!(4005, report.bytes);
+    assert!(report.failed.is_empty());
+    assert_eq!(
+        "top\n".repeat(1000),
+        std::fs::read_to_string(dest.join("top.txt")).unwrap()
+    );
+    assert_eq!(
+        "inner",
+        std::fs::read_to_string(dest.join("nested").join("inner.txt")).unwrap()
+    );
+    std::fs::remove_dir_all(&source).unwrap();
+    std::fs::remove_dir_all(&dest).unwrap();
+}
+
+#[tokio::test]
+async fn test_upload_as_zip_in_parts() {
+    let store = Arc::new(Mutex::new(Store::default()));
+    let client = common::client_for(object_store(store.clone()));
+    // More than one 8 MB part once compressed.
+    let source = source_tree(9 * 1024 * 1024);
+
+    upload_as_zip(&client, "bucket", "tree.zip", &source)
+        .await
+        .unwrap();
+    let requests = store.lock().unwrap().requests.clone();
+    let parts = requests
+        .iter()
+        .filter(|(method, path)| *method == Method::PUT && path.contains("partNumber="))
+        .count();
+    assert_eq!(parts, 2, "{:?}", requests);
+
+    let dest = source.with_extension("dest");
+    let report = download_and_extract_zip(&client, "bucket", "tree.zip", &dest)
+        .await
+        .unwrap();
+    assert_eq!(3, report.files);
+    assert!(report.failed.is_empty());
+    assert_eq!(
+        std::fs::read(source.join("random.bin")).unwrap(),
+        std::fs::read(dest.join("random.bin")).unwrap()
+    );
+    std::fs::remove_dir_all(&source).unwrap();
+    std::fs::remove_dir_all(&dest).unwrap();
+}
+
+#[tokio::test]
+async fn test_upload_as_zip_aborts_on_failure() {
+    let store = Arc::new(Mutex::new(Store {
+        fail_parts: true,
+        ..Store::default()
+    }));
+    let client = common::client_for(object_store(store.clone()));
+    let source = source_tree(9 * 1024 * 1024);
+
+    assert!(upload_as_zip(&client, "bucket", "tree.zip", &source)
+        .await
+        .is_err());
+    let store = store.lock().unwrap();
+    assert!(
+        store
+            .requests
+            .iter()
+            .any(|(method, path)| *method == Method::DELETE && path.contains("uploadId=upload-1")),
+        "{:?}",
+        store.requests
+    );
+    assert!(store.objects.is_empty());
+    std::fs::remove_dir_all(&source).unwrap();
+}